@@ -0,0 +1,10 @@
+INSERT INTO KillCounts (
+    PlayerID,
+    Kills
+)
+VALUES (
+    $1,
+    $2
+)
+ON CONFLICT (PlayerID) DO UPDATE
+SET Kills = KillCounts.Kills + EXCLUDED.Kills;
@@ -0,0 +1,2 @@
+DELETE FROM cratepity
+WHERE playerid = $1;
@@ -0,0 +1,3 @@
+UPDATE AuthTokens
+SET Used = 1
+WHERE TokenID = $1;
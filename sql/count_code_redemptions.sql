@@ -0,0 +1,8 @@
+SELECT COUNT(*) AS Redemptions
+FROM RedeemedCodes
+INNER JOIN Players ON RedeemedCodes.PlayerID = Players.PlayerID
+WHERE RedeemedCodes.Code = $1
+    AND (
+        RedeemedCodes.PlayerID = $2
+        OR ($3 AND Players.AccountID = (SELECT AccountID FROM Players WHERE PlayerID = $2))
+    );
@@ -0,0 +1,17 @@
+INSERT INTO RedeemCodes (
+    Code,
+    Taros,
+    FusionMatter,
+    MaxUses,
+    OncePerAccount,
+    Expires
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6
+)
+ON CONFLICT (Code) DO NOTHING;
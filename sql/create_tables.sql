@@ -31,7 +31,9 @@ CREATE TABLE IF NOT EXISTS Accounts (
     LastLogin    INTEGER DEFAULT extract(epoch from now()) NOT NULL,
     BannedUntil  INTEGER DEFAULT 0 NOT NULL,
     BannedSince  INTEGER DEFAULT 0 NOT NULL,
-    BanReason    TEXT    DEFAULT '' NOT NULL
+    BanReason    TEXT    DEFAULT '' NOT NULL,
+    SlotBonus    INTEGER DEFAULT 0 NOT NULL,
+    FreeChat     INTEGER DEFAULT 1 NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS Players (
@@ -60,11 +62,13 @@ CREATE TABLE IF NOT EXISTS Players (
     BatteryW           INTEGER DEFAULT 0 NOT NULL,
     BatteryN           INTEGER DEFAULT 0 NOT NULL,
     Mentor             INTEGER DEFAULT 5 NOT NULL,
+    MentorCount        INTEGER DEFAULT 0 NOT NULL,
     CurrentMissionID   INTEGER DEFAULT 0 NOT NULL,
     WarpLocationFlag   INTEGER DEFAULT 0 NOT NULL,
     SkywayLocationFlag BYTEA NOT NULL,
     FirstUseFlag       BYTEA NOT NULL,
     Quests             BYTEA NOT NULL,
+    BuddyWarpTime      INTEGER DEFAULT 0 NOT NULL,
     FOREIGN KEY(AccountID) REFERENCES Accounts(AccountID) ON DELETE CASCADE,
     UNIQUE (AccountID, Slot),
     UNIQUE (FirstName, LastName)
@@ -90,6 +94,7 @@ CREATE TABLE IF NOT EXISTS Inventory (
     Type        INTEGER NOT NULL,
     Opt         INTEGER NOT NULL,
     TimeLimit   INTEGER DEFAULT 0 NOT NULL,
+    Provenance  TEXT,
     FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
     UNIQUE (PlayerID, Slot)
 );
@@ -114,11 +119,13 @@ CREATE TABLE IF NOT EXISTS Nanos (
 
 CREATE TABLE IF NOT EXISTS RunningQuests (
     PlayerID              BIGINT NOT NULL,
+    Slot                  INTEGER NOT NULL,
     TaskID                INTEGER NOT NULL,
     RemainingNPCCount1    INTEGER NOT NULL,
     RemainingNPCCount2    INTEGER NOT NULL,
     RemainingNPCCount3    INTEGER NOT NULL,
-    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
+    UNIQUE (PlayerID, Slot)
 );
 
 CREATE TABLE IF NOT EXISTS Buddyships (
@@ -180,3 +187,105 @@ CREATE TABLE IF NOT EXISTS RedeemedCodes(
     FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
     UNIQUE (PlayerID, Code)
 );
+
+CREATE TABLE IF NOT EXISTS RedeemCodes(
+    Code            TEXT PRIMARY KEY NOT NULL,
+    Taros           INTEGER DEFAULT 0 NOT NULL,
+    FusionMatter    INTEGER DEFAULT 0 NOT NULL,
+    MaxUses         INTEGER DEFAULT 1 NOT NULL,
+    Uses            INTEGER DEFAULT 0 NOT NULL,
+    OncePerAccount  BOOLEAN DEFAULT FALSE NOT NULL,
+    Created         INTEGER DEFAULT extract(epoch from now()) NOT NULL,
+    Expires         INTEGER DEFAULT 0 NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS RedeemCodeItems(
+    Code        TEXT NOT NULL,
+    Slot        INTEGER NOT NULL,
+    ID          INTEGER NOT NULL,
+    Type        INTEGER NOT NULL,
+    Quantity    INTEGER NOT NULL,
+    FOREIGN KEY(Code) REFERENCES RedeemCodes(Code) ON DELETE CASCADE,
+    UNIQUE (Code, Slot)
+);
+
+CREATE TABLE IF NOT EXISTS CurrencyEvents(
+    PlayerID    BIGINT NOT NULL,
+    Currency    INTEGER NOT NULL,
+    Source      INTEGER NOT NULL,
+    Delta       BIGINT NOT NULL,
+    Balance     BIGINT NOT NULL,
+    Timestamp   INTEGER NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS RaceResultsByScore ON RaceResults(EPID, Score);
+
+CREATE TABLE IF NOT EXISTS KillCounts(
+    PlayerID    BIGINT PRIMARY KEY,
+    Kills       BIGINT NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS KillCountsByKills ON KillCounts(Kills);
+
+CREATE TABLE IF NOT EXISTS AuthTokens(
+    TokenID     TEXT PRIMARY KEY,
+    TokenHash   TEXT NOT NULL,
+    AccountID   BIGINT NOT NULL,
+    Expiry      INTEGER NOT NULL,
+    Used        INTEGER DEFAULT 0 NOT NULL,
+    FOREIGN KEY(AccountID) REFERENCES Accounts(AccountID) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS Transactions(
+    Kind            INTEGER NOT NULL,
+    PlayerID        BIGINT NOT NULL,
+    OtherPlayerID   BIGINT,
+    TarosGiven      BIGINT NOT NULL,
+    TarosReceived   BIGINT NOT NULL,
+    ItemsGiven      TEXT NOT NULL,
+    ItemsReceived   TEXT NOT NULL,
+    Timestamp       INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS TransactionsByPlayer ON Transactions(PlayerID, Timestamp);
+CREATE INDEX IF NOT EXISTS TransactionsByOtherPlayer ON Transactions(OtherPlayerID, Timestamp);
+
+CREATE TABLE IF NOT EXISTS CratePity(
+    PlayerID    BIGINT NOT NULL,
+    CrateID     INTEGER NOT NULL,
+    Pity        INTEGER NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
+    UNIQUE (PlayerID, CrateID)
+);
+
+CREATE TABLE IF NOT EXISTS Ignores(
+    AccountID           BIGINT NOT NULL,
+    IgnoredPlayerID     BIGINT NOT NULL,
+    FOREIGN KEY(AccountID)       REFERENCES Accounts(AccountID) ON DELETE CASCADE,
+    FOREIGN KEY(IgnoredPlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
+    UNIQUE (AccountID, IgnoredPlayerID)
+);
+
+CREATE TABLE IF NOT EXISTS Reports(
+    ReportID        BIGSERIAL PRIMARY KEY NOT NULL,
+    ReporterID      BIGINT NOT NULL,
+    ReportedID      BIGINT NOT NULL,
+    Category        INTEGER NOT NULL,
+    Reason          TEXT,
+    Timestamp       INTEGER NOT NULL,
+    ShardID         INTEGER NOT NULL,
+    ChannelNum      INTEGER NOT NULL,
+    ReporterX       INTEGER NOT NULL,
+    ReporterY       INTEGER NOT NULL,
+    ReporterZ       INTEGER NOT NULL,
+    ReportedX       INTEGER,
+    ReportedY       INTEGER,
+    ReportedZ       INTEGER,
+    Flagged         INTEGER NOT NULL,
+    HandledBy       BIGINT,
+    HandledAt       INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS OpenReports ON Reports(HandledBy, ReportID);
@@ -0,0 +1,6 @@
+DELETE FROM Ignores
+WHERE AccountID = (
+    SELECT AccountID
+    FROM Players
+    WHERE PlayerID = $1
+) AND IgnoredPlayerID = $2;
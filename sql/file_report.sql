@@ -0,0 +1,33 @@
+INSERT INTO Reports (
+    ReporterID,
+    ReportedID,
+    Category,
+    Reason,
+    Timestamp,
+    ShardID,
+    ChannelNum,
+    ReporterX,
+    ReporterY,
+    ReporterZ,
+    ReportedX,
+    ReportedY,
+    ReportedZ,
+    Flagged
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6,
+    $7,
+    $8,
+    $9,
+    $10,
+    $11,
+    $12,
+    $13,
+    $14
+)
+RETURNING ReportID;
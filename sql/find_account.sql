@@ -1,4 +1,4 @@
-SELECT AccountID, AccountLevel, Password, Selected, BannedUntil, BanReason
+SELECT AccountID, AccountLevel, Password, Selected, BannedUntil, BanReason, SlotBonus, FreeChat
 FROM Accounts
 WHERE Login iLIKE $1
 LIMIT 1;
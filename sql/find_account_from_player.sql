@@ -1,4 +1,4 @@
-SELECT a.AccountID, a.AccountLevel, a.Login, a.Password, a.Selected, a.BannedUntil, a.BanReason
+SELECT a.AccountID, a.AccountLevel, a.Login, a.Password, a.Selected, a.BannedUntil, a.BanReason, a.SlotBonus, a.FreeChat
 FROM Accounts as a
 INNER JOIN Players as p ON p.AccountID = a.AccountID
 WHERE p.PlayerID = $1;
@@ -0,0 +1,5 @@
+SELECT a.AccountID, a.AccountLevel, a.Login, a.Password, a.Selected, a.BannedUntil, a.BanReason, a.SlotBonus, a.FreeChat, t.TokenHash
+FROM AuthTokens as t
+INNER JOIN Accounts as a ON a.AccountID = t.AccountID
+WHERE t.TokenID = $1 AND t.Used = 0 AND t.Expiry > $2
+FOR UPDATE OF t;
@@ -0,0 +1,4 @@
+SELECT DISTINCT a.AccountID, a.AccountLevel, a.Login, a.Password, a.Selected, a.BannedUntil, a.BanReason, a.SlotBonus, a.FreeChat
+FROM Accounts as a
+INNER JOIN Players as p ON p.AccountID = a.AccountID
+WHERE p.PlayerID = ANY($1) AND a.BannedUntil > $2;
@@ -0,0 +1,4 @@
+SELECT Code, Taros, FusionMatter, MaxUses, Uses, OncePerAccount, Expires
+FROM RedeemCodes
+WHERE Code = $1
+LIMIT 1;
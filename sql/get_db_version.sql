@@ -0,0 +1,3 @@
+SELECT Value
+FROM Meta
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,12 @@
+INSERT INTO AuthTokens (
+    TokenID,
+    TokenHash,
+    AccountID,
+    Expiry
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4
+);
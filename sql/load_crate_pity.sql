@@ -0,0 +1,3 @@
+SELECT crateid, pity
+FROM cratepity
+WHERE playerid = $1;
@@ -0,0 +1,4 @@
+SELECT i.IgnoredPlayerID
+FROM Ignores as i
+INNER JOIN Players as p ON i.AccountID = p.AccountID
+WHERE p.PlayerID = $1;
@@ -1,3 +1,3 @@
-SELECT slot, id, "type", opt, timelimit
+SELECT slot, id, "type", opt, timelimit, provenance
 FROM inventory
 WHERE playerid = $1;
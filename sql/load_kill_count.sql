@@ -0,0 +1,3 @@
+SELECT Kills AS Score
+FROM KillCounts
+WHERE PlayerID = $1;
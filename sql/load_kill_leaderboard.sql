@@ -0,0 +1,6 @@
+SELECT
+    p.PlayerID, p.FirstName, p.LastName, k.Kills AS Score
+FROM KillCounts as k
+INNER JOIN Players as p ON k.PlayerID = p.PlayerID
+ORDER BY k.Kills DESC
+LIMIT $1::INTEGER;
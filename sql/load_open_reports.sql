@@ -0,0 +1,8 @@
+SELECT
+    ReportID, ReporterID, ReportedID, Category, Reason, Timestamp, ShardID, ChannelNum,
+    ReporterX, ReporterY, ReporterZ, ReportedX, ReportedY, ReportedZ, Flagged, HandledBy
+FROM Reports
+WHERE HandledBy IS NULL
+ORDER BY ReportID
+LIMIT $2::INTEGER
+OFFSET $1::INTEGER;
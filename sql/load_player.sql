@@ -1,13 +1,13 @@
 SELECT
     p.PlayerID, p.Slot, p.FirstName, p.LastName, p.NameCheck,
     p.Level, p.HP, p.FusionMatter, p.Taros, p.BatteryW, p.BatteryN,
-    p.AppearanceFlag, p.TutorialFlag, p.PayZoneFlag, p.FirstUseFlag, p.Mentor,
+    p.AppearanceFlag, p.TutorialFlag, p.PayZoneFlag, p.FirstUseFlag, p.Mentor, p.MentorCount,
     p.WarpLocationFlag, p.SkywayLocationFlag,
-    p.CurrentMissionID, p.Quests,
+    p.CurrentMissionID, p.Quests, p.BuddyWarpTime,
     p.XCoordinate, p.YCoordinate, p.ZCoordinate, p.Angle,
     p.Nano1, p.Nano2, p.Nano3,
     s.Body, s.EyeColor, s.FaceStyle, s.Gender, s.HairColor, s.HairStyle, s.Height, s.SkinColor,
-    a.AccountLevel
+    a.AccountLevel, a.FreeChat
 FROM Players as p
 INNER JOIN Appearances as s ON p.PlayerID = s.PlayerID
 INNER JOIN Accounts as a ON p.AccountID = a.AccountID
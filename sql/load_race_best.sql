@@ -0,0 +1,3 @@
+SELECT MAX(Score)::BIGINT AS Score
+FROM RaceResults
+WHERE EPID = $1 AND PlayerID = $2;
@@ -0,0 +1,8 @@
+SELECT
+    p.PlayerID, p.FirstName, p.LastName, MAX(r.Score)::BIGINT AS Score
+FROM RaceResults as r
+INNER JOIN Players as p ON r.PlayerID = p.PlayerID
+WHERE r.EPID = $1
+GROUP BY p.PlayerID
+ORDER BY Score DESC
+LIMIT $2::INTEGER;
@@ -0,0 +1,4 @@
+SELECT ID, Type, Quantity
+FROM RedeemCodeItems
+WHERE Code = $1
+ORDER BY Slot ASC;
@@ -1,3 +1,4 @@
-SELECT taskid, remainingnpccount1, remainingnpccount2, remainingnpccount3
+SELECT slot, taskid, remainingnpccount1, remainingnpccount2, remainingnpccount3
 FROM runningquests
-WHERE playerid = $1;
+WHERE playerid = $1
+ORDER BY slot ASC;
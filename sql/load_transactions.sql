@@ -0,0 +1,6 @@
+SELECT
+    Kind, PlayerID, OtherPlayerID, TarosGiven, TarosReceived, ItemsGiven, ItemsReceived, Timestamp
+FROM Transactions
+WHERE PlayerID = $1 OR OtherPlayerID = $1
+ORDER BY Timestamp DESC
+LIMIT $2::INTEGER;
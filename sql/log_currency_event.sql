@@ -0,0 +1,16 @@
+INSERT INTO CurrencyEvents (
+    PlayerID,
+    Currency,
+    Source,
+    Delta,
+    Balance,
+    Timestamp
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6
+);
@@ -0,0 +1,20 @@
+INSERT INTO Transactions (
+    Kind,
+    PlayerID,
+    OtherPlayerID,
+    TarosGiven,
+    TarosReceived,
+    ItemsGiven,
+    ItemsReceived,
+    Timestamp
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6,
+    $7,
+    $8
+);
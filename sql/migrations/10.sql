@@ -0,0 +1,6 @@
+ALTER TABLE Accounts
+ADD COLUMN FreeChat INTEGER DEFAULT 1 NOT NULL;
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,13 @@
+CREATE INDEX IF NOT EXISTS RaceResultsByScore ON RaceResults(EPID, Score);
+
+CREATE TABLE IF NOT EXISTS KillCounts(
+    PlayerID    BIGINT PRIMARY KEY,
+    Kills       BIGINT NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS KillCountsByKills ON KillCounts(Kills);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS AuthTokens(
+    TokenID     TEXT PRIMARY KEY,
+    TokenHash   TEXT NOT NULL,
+    AccountID   BIGINT NOT NULL,
+    Expiry      INTEGER NOT NULL,
+    Used        INTEGER DEFAULT 0 NOT NULL,
+    FOREIGN KEY(AccountID) REFERENCES Accounts(AccountID) ON DELETE CASCADE
+);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS Transactions(
+    Kind            INTEGER NOT NULL,
+    PlayerID        BIGINT NOT NULL,
+    OtherPlayerID   BIGINT,
+    TarosGiven      BIGINT NOT NULL,
+    TarosReceived   BIGINT NOT NULL,
+    ItemsGiven      TEXT NOT NULL,
+    ItemsReceived   TEXT NOT NULL,
+    Timestamp       INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS TransactionsByPlayer ON Transactions(PlayerID, Timestamp);
+CREATE INDEX IF NOT EXISTS TransactionsByOtherPlayer ON Transactions(OtherPlayerID, Timestamp);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
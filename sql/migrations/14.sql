@@ -0,0 +1,11 @@
+ALTER TABLE Players
+ADD COLUMN MentorCount INTEGER DEFAULT 0 NOT NULL;
+
+-- anyone off the starting guide has changed at least once
+UPDATE Players
+SET MentorCount = 1
+WHERE Mentor <> 5;
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS CratePity(
+    PlayerID    BIGINT NOT NULL,
+    CrateID     INTEGER NOT NULL,
+    Pity        INTEGER NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
+    UNIQUE (PlayerID, CrateID)
+);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS Ignores(
+    AccountID           BIGINT NOT NULL,
+    IgnoredPlayerID     BIGINT NOT NULL,
+    FOREIGN KEY(AccountID)       REFERENCES Accounts(AccountID) ON DELETE CASCADE,
+    FOREIGN KEY(IgnoredPlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE,
+    UNIQUE (AccountID, IgnoredPlayerID)
+);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,6 @@
+ALTER TABLE Inventory
+ADD COLUMN IF NOT EXISTS Provenance TEXT;
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS Reports(
+    ReportID        BIGSERIAL PRIMARY KEY NOT NULL,
+    ReporterID      BIGINT NOT NULL,
+    ReportedID      BIGINT NOT NULL,
+    Category        INTEGER NOT NULL,
+    Reason          TEXT,
+    Timestamp       INTEGER NOT NULL,
+    ShardID         INTEGER NOT NULL,
+    ChannelNum      INTEGER NOT NULL,
+    ReporterX       INTEGER NOT NULL,
+    ReporterY       INTEGER NOT NULL,
+    ReporterZ       INTEGER NOT NULL,
+    ReportedX       INTEGER,
+    ReportedY       INTEGER,
+    ReportedZ       INTEGER,
+    Flagged         INTEGER NOT NULL,
+    HandledBy       BIGINT,
+    HandledAt       INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS OpenReports ON Reports(HandledBy, ReportID);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,6 @@
+ALTER TABLE Accounts
+ADD COLUMN SlotBonus INTEGER DEFAULT 0 NOT NULL;
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
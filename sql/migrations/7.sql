@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS RedeemCodes(
+    Code            TEXT PRIMARY KEY NOT NULL,
+    Taros           INTEGER DEFAULT 0 NOT NULL,
+    FusionMatter    INTEGER DEFAULT 0 NOT NULL,
+    MaxUses         INTEGER DEFAULT 1 NOT NULL,
+    Uses            INTEGER DEFAULT 0 NOT NULL,
+    OncePerAccount  BOOLEAN DEFAULT FALSE NOT NULL,
+    Created         INTEGER DEFAULT extract(epoch from now()) NOT NULL,
+    Expires         INTEGER DEFAULT 0 NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS RedeemCodeItems(
+    Code        TEXT NOT NULL,
+    Slot        INTEGER NOT NULL,
+    ID          INTEGER NOT NULL,
+    Type        INTEGER NOT NULL,
+    Quantity    INTEGER NOT NULL,
+    FOREIGN KEY(Code) REFERENCES RedeemCodes(Code) ON DELETE CASCADE,
+    UNIQUE (Code, Slot)
+);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
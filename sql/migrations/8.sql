@@ -0,0 +1,20 @@
+ALTER TABLE RunningQuests
+ADD COLUMN Slot INTEGER;
+
+UPDATE RunningQuests AS r
+SET Slot = -n.RowNum
+FROM (
+    SELECT ctid, ROW_NUMBER() OVER (PARTITION BY PlayerID ORDER BY TaskID) AS RowNum
+    FROM RunningQuests
+) AS n
+WHERE r.ctid = n.ctid;
+
+ALTER TABLE RunningQuests
+ALTER COLUMN Slot SET NOT NULL;
+
+ALTER TABLE RunningQuests
+ADD UNIQUE (PlayerID, Slot);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
@@ -0,0 +1,16 @@
+ALTER TABLE Players
+ADD COLUMN BuddyWarpTime INTEGER DEFAULT 0 NOT NULL;
+
+CREATE TABLE IF NOT EXISTS CurrencyEvents(
+    PlayerID    BIGINT NOT NULL,
+    Currency    INTEGER NOT NULL,
+    Source      INTEGER NOT NULL,
+    Delta       BIGINT NOT NULL,
+    Balance     BIGINT NOT NULL,
+    Timestamp   INTEGER NOT NULL,
+    FOREIGN KEY(PlayerID) REFERENCES Players(PlayerID) ON DELETE CASCADE
+);
+
+UPDATE Meta
+SET Value = $1
+WHERE Key = 'DatabaseVersion';
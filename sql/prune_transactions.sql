@@ -0,0 +1,2 @@
+DELETE FROM Transactions
+WHERE Timestamp < $1;
@@ -0,0 +1,3 @@
+UPDATE Reports
+SET HandledBy = $2, HandledAt = $3
+WHERE ReportID = $1 AND HandledBy IS NULL;
@@ -0,0 +1,8 @@
+INSERT INTO RedeemedCodes (
+    PlayerID,
+    Code
+)
+VALUES (
+    $1,
+    $2
+);
@@ -0,0 +1,10 @@
+INSERT INTO cratepity (
+    playerid,
+    crateid,
+    pity
+)
+VALUES (
+    $1,
+    $2,
+    $3
+);
@@ -0,0 +1,8 @@
+INSERT INTO Ignores (
+    AccountID,
+    IgnoredPlayerID
+)
+SELECT AccountID, $2
+FROM Players
+WHERE PlayerID = $1
+ON CONFLICT DO NOTHING;
@@ -4,7 +4,8 @@ INSERT INTO inventory (
     id,
     "type",
     opt,
-    timelimit
+    timelimit,
+    provenance
 )
 VALUES (
     $1,
@@ -12,5 +13,6 @@ VALUES (
     $3,
     $4,
     $5,
-    $6
+    $6,
+    $7
 );
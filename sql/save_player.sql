@@ -20,5 +20,7 @@ SET
     warplocationflag = $19,
     skywaylocationflag = $20,
     firstuseflag = $21,
-    quests = $22
+    quests = $22,
+    buddywarptime = $23,
+    mentorcount = $24
 WHERE playerid = $1;
@@ -0,0 +1,16 @@
+INSERT INTO RaceResults (
+    EPID,
+    PlayerID,
+    Score,
+    RingCount,
+    Time,
+    Timestamp
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6
+);
@@ -0,0 +1,14 @@
+INSERT INTO RedeemCodeItems (
+    Code,
+    Slot,
+    ID,
+    Type,
+    Quantity
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5
+);
@@ -1,5 +1,6 @@
 INSERT INTO runningquests (
     playerid,
+    slot,
     taskid,
     remainingnpccount1,
     remainingnpccount2,
@@ -10,5 +11,6 @@ VALUES (
     $2,
     $3,
     $4,
-    $5
+    $5,
+    $6
 );
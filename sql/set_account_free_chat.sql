@@ -0,0 +1,3 @@
+UPDATE Accounts
+SET FreeChat = $2
+WHERE AccountID = $1;
@@ -0,0 +1,3 @@
+UPDATE Accounts
+SET SlotBonus = $2
+WHERE AccountID = $1;
@@ -0,0 +1,6 @@
+UPDATE players
+SET
+    firstname = $2,
+    lastname = $3,
+    namecheck = $4
+WHERE playerid = $1;
@@ -0,0 +1,6 @@
+UPDATE RedeemCodes
+SET Uses = Uses + 1
+WHERE Code = $1
+    AND Uses < MaxUses
+    AND (Expires = 0 OR Expires > $2)
+RETURNING OncePerAccount;
@@ -1,22 +1,27 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     time::{Duration, SystemTime},
 };
 
-use rand::{rngs::ThreadRng, thread_rng, Rng};
+use rand::Rng;
 
 use crate::{
     chunk::TickMode,
-    defines::{RANGE_GROUP_PARTICIPATE, SHARD_TICKS_PER_SECOND},
+    defines::{
+        BOSS_TOP_CONTRIBUTORS_SHOWN, NPC_REGEN_PERCENT, RANGE_GROUP_PARTICIPATE,
+        SHARD_TICKS_PER_SECOND,
+    },
     entity::{Combatant, Entity, EntityID, NPC},
     enums::CombatantTeam,
     error::*,
     helpers,
+    leaderboard::leaderboard_queue_kill,
     net::ClientMap,
     path::Path,
+    rng::GameRng,
     skills,
     state::ShardServerState,
-    tabledata::tdata_get,
+    tabledata::{tdata_get, AIParams, NPCStats},
     util::*,
     Position,
 };
@@ -29,7 +34,7 @@ trait AINode: std::fmt::Debug {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) -> NodeStatus;
 }
 impl Clone for Box<dyn AINode> {
@@ -56,6 +61,7 @@ impl AI {
             include_combatant_nodes && stats.team == CombatantTeam::Friendly;
         let include_mob_nodes = include_combatant_nodes && stats.team == CombatantTeam::Mob;
         let include_pack_follower_nodes = include_mob_nodes && npc.tight_follow.is_some();
+        let params = NodeParams::new(stats, tdata_get().get_ai_params(stats.ai_type));
 
         let root_sequence = SequenceNode::new_node({
             let mut root_behaviors = Vec::new();
@@ -83,13 +89,22 @@ impl AI {
                     // Retreat if needed
                     let retreat_threshold = stats.combat_range;
                     let retreat_to = npc.get_position();
-                    retreat_behaviors.push(CheckRetreat::new_node(retreat_to, retreat_threshold));
+                    retreat_behaviors.push(CheckRetreat::new_node(
+                        retreat_to,
+                        retreat_threshold,
+                        params.retreat_speed,
+                    ));
 
                     retreat_behaviors
                 });
                 root_behaviors.push(retreat_selector);
             }
 
+            // Combatants: heal up while out of combat
+            if include_combatant_nodes {
+                root_behaviors.push(RegenHP::new_node(params.regen_interval));
+            }
+
             // Pack followers: sync aggro with leader.
             // This has to happen before movement to avoid yo-yoing
             if include_pack_follower_nodes {
@@ -134,19 +149,19 @@ impl AI {
                     stats.run_speed,
                 ));
 
+                // Escorts released from a group: walk back to where they were invited from
+                movement_behaviors
+                    .push(ReturnToEscortHome::new_node(stats.radius, stats.walk_speed));
+
                 // Follow assigned path
                 movement_behaviors.push(FollowAssignedPath::new_node());
 
                 // Mobs with non-zero idle range: roam around spawn
                 if include_mob_nodes && stats.idle_range > 0 {
-                    let roam_radius_max = stats.idle_range / 2;
-                    let roam_radius_range = (roam_radius_max / 2, roam_radius_max);
-                    let roam_delay_max_ms = stats.delay_time * 1000;
-                    let roam_delay_range_ms = (roam_delay_max_ms / 2, roam_delay_max_ms);
                     movement_behaviors.push(PatrolPoint::new_node(
                         npc.get_position(),
-                        roam_radius_range,
-                        roam_delay_range_ms,
+                        params.roam_radius_range,
+                        params.roam_delay_range_ms,
                     ));
                 }
 
@@ -161,26 +176,20 @@ impl AI {
 
                     // Mobs: scan for non-mob targets
                     if include_mob_nodes {
-                        // tweak as needed
-                        let scan_radius = stats.sight_range;
-                        let distance_factor = 0.5;
-                        let level_factor = 0.1;
-                        let aggro_rates = (1.5, -1.0);
-                        let aggro_threshold = 1.0;
                         combat_behaviors.push(ScanForTargets::new_node(
                             Some(CombatantTeam::Friendly),
-                            scan_radius,
-                            distance_factor,
-                            level_factor,
-                            aggro_rates,
-                            aggro_threshold,
+                            params.scan_radius,
+                            params.distance_factor,
+                            params.level_factor,
+                            params.aggro_rates,
+                            params.aggro_threshold,
                         ));
                     }
 
                     // Attack target
                     let attack_range = stats.attack_range + stats.radius;
-                    let attack_cooldown = Duration::from_millis(stats.delay_time * 100);
-                    combat_behaviors.push(CheckAttack::new_node(attack_range, attack_cooldown));
+                    combat_behaviors
+                        .push(CheckAttack::new_node(attack_range, params.attack_cooldown));
 
                     combat_behaviors
                 });
@@ -209,12 +218,52 @@ impl AI {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) {
+        // stunned mobs don't move or attack
+        if !npc.can_act() {
+            return;
+        }
         self.root.tick(npc, state, clients, time, rng);
     }
 }
 
+// Node parameters derived from an NPC's stats, scaled by the AI params for its type
+#[derive(Debug, Clone, PartialEq)]
+struct NodeParams {
+    scan_radius: u32,
+    distance_factor: f32,
+    level_factor: f32,
+    aggro_rates: (f32, f32),
+    aggro_threshold: f32,
+    retreat_speed: i32,
+    roam_radius_range: (u32, u32),
+    roam_delay_range_ms: (u64, u64),
+    attack_cooldown: Duration,
+    regen_interval: Duration,
+}
+impl NodeParams {
+    fn new(stats: &NPCStats, params: &AIParams) -> Self {
+        let roam_radius_max = (stats.idle_range as f32 / 2.0 * params.roam_radius_mult) as u32;
+        let roam_delay_max_ms = (stats.delay_time as f32 * 1000.0 * params.roam_delay_mult) as u64;
+        Self {
+            scan_radius: (stats.sight_range as f32 * params.scan_radius_mult) as u32,
+            distance_factor: params.distance_factor,
+            level_factor: params.level_factor,
+            aggro_rates: (params.aggro_rate_up, -params.aggro_rate_down),
+            aggro_threshold: params.aggro_threshold,
+            retreat_speed: (stats.run_speed as f32 * params.retreat_speed_mult) as i32,
+            roam_radius_range: (roam_radius_max / 2, roam_radius_max),
+            roam_delay_range_ms: (roam_delay_max_ms / 2, roam_delay_max_ms),
+            attack_cooldown: Duration::from_millis(stats.delay_time * 100)
+                .mul_f32(params.attack_cooldown_mult),
+            // regen_time is the respawn time in tenths of a second
+            regen_interval: Duration::from_millis(stats.regen_time * NPC_REGEN_PERCENT as u64)
+                .max(Duration::from_secs(1)),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 enum NodeStatus {
@@ -247,7 +296,7 @@ impl AINode for SequenceNode {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) -> NodeStatus {
         while self.cursor < self.children.len() {
             let status = self.children[self.cursor].tick(npc, state, clients, time, rng);
@@ -293,7 +342,7 @@ impl AINode for SelectorNode {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) -> NodeStatus {
         while self.cursor < self.children.len() {
             let status = self.children[self.cursor].tick(npc, state, clients, time, rng);
@@ -333,7 +382,7 @@ impl AINode for FollowAssignedPath {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let path = npc.path.take();
         match path {
@@ -386,7 +435,7 @@ impl AINode for FollowEntityLoose {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let target_id = match self.target {
             FollowTarget::AssignedEntity => npc.loose_follow,
@@ -426,6 +475,50 @@ impl AINode for FollowEntityLoose {
     }
 }
 
+#[derive(Debug, Clone)]
+struct ReturnToEscortHome {
+    tolerance: u32,
+    speed: i32,
+}
+impl ReturnToEscortHome {
+    fn new_node(tolerance: u32, speed: i32) -> Box<dyn AINode> {
+        Box::new(Self { tolerance, speed })
+    }
+}
+impl AINode for ReturnToEscortHome {
+    fn clone_node(&self) -> Box<dyn AINode> {
+        Box::new(self.clone())
+    }
+
+    fn tick(
+        &mut self,
+        npc: &mut NPC,
+        state: &mut ShardServerState,
+        clients: &mut ClientMap,
+        _time: &SystemTime,
+        _rng: &mut GameRng,
+    ) -> NodeStatus {
+        if npc.group_id.is_some() {
+            return NodeStatus::Failure;
+        }
+
+        let home = match npc.escort_home {
+            Some(home) => home,
+            None => return NodeStatus::Failure,
+        };
+
+        if npc.get_position().distance_to(&home) <= self.tolerance {
+            npc.escort_home = None;
+            return NodeStatus::Failure;
+        }
+
+        let mut path = Path::new_single(home, self.speed);
+        path.start();
+        npc.tick_movement_along_path(&mut path, clients, state);
+        NodeStatus::Success
+    }
+}
+
 #[derive(Debug, Clone)]
 struct FollowEntityTight {
     tolerance: u32,
@@ -450,7 +543,7 @@ impl AINode for FollowEntityTight {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let (leader_id, offset) = match npc.tight_follow {
             Some(tight_follow) => tight_follow,
@@ -519,20 +612,22 @@ impl AINode for PatrolPoint {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        _rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) -> NodeStatus {
         match self.roam_state {
             RoamState::Idle => {
                 let (delay_min, delay_max) = self.roam_delay_range_ms;
-                let wait_time_ms = thread_rng().gen_range(delay_min..delay_max);
+                let wait_time_ms = rng.gen_range(delay_min..delay_max);
                 let wait_time = Duration::from_millis(wait_time_ms);
                 self.roam_state = RoamState::Waiting(*time + wait_time);
             }
             RoamState::Waiting(wait_time) => {
                 if *time > wait_time {
                     let (min_radius, max_radius) = self.roam_radius_range;
-                    let roam_radius = thread_rng().gen_range(min_radius..max_radius);
-                    let target_pos = self.home.get_random_around(roam_radius, roam_radius, 0);
+                    let roam_radius = rng.gen_range(min_radius..max_radius);
+                    let target_pos = self
+                        .home
+                        .get_random_around(rng, roam_radius, roam_radius, 0);
                     let speed = tdata_get().get_npc_stats(npc.ty).unwrap().walk_speed;
                     let mut path = Path::new_single(target_pos, speed);
                     path.start();
@@ -591,7 +686,7 @@ impl AINode for CheckDead {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) -> NodeStatus {
         match self.dead_state {
             DeadState::Alive => {
@@ -666,9 +761,9 @@ impl AINode for CheckLeaderRetreat {
         &mut self,
         npc: &mut NPC,
         state: &mut ShardServerState,
-        _clients: &mut ClientMap,
+        clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let leader_npc_id = match npc.tight_follow {
             Some((EntityID::NPC(leader_npc_id), _)) => leader_npc_id,
@@ -693,8 +788,7 @@ impl AINode for CheckLeaderRetreat {
             }
             LeaderRetreatState::LeaderRetreating => {
                 if !leader_npc.retreating {
-                    npc.reset();
-                    // TODO full heal effect
+                    npc.return_home_heal(state, clients);
                     self.leader_retreat_state = LeaderRetreatState::Idle;
                 }
             }
@@ -721,7 +815,7 @@ impl AINode for SyncPlayerTarget {
         state: &mut ShardServerState,
         _clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         if npc.target_id.is_some() {
             return NodeStatus::Success;
@@ -767,7 +861,7 @@ impl AINode for SyncPackLeaderTarget {
         state: &mut ShardServerState,
         _clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let leader_npc_id = match npc.tight_follow {
             Some((EntityID::NPC(leader_npc_id), _)) => leader_npc_id,
@@ -856,7 +950,7 @@ impl AINode for ScanForTargets {
         state: &mut ShardServerState,
         _clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         if npc.target_id.is_some() {
             return NodeStatus::Success;
@@ -937,13 +1031,19 @@ enum RetreatState {
 struct CheckRetreat {
     retreat_to: Position,
     retreat_threshold: u32,
+    retreat_speed: i32,
     retreat_state: RetreatState,
 }
 impl CheckRetreat {
-    fn new_node(retreat_to_initial: Position, retreat_threshold: u32) -> Box<dyn AINode> {
+    fn new_node(
+        retreat_to_initial: Position,
+        retreat_threshold: u32,
+        retreat_speed: i32,
+    ) -> Box<dyn AINode> {
         Box::new(Self {
             retreat_to: retreat_to_initial,
             retreat_threshold,
+            retreat_speed,
             retreat_state: RetreatState::Idle,
         })
     }
@@ -959,7 +1059,7 @@ impl AINode for CheckRetreat {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         _time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         match &mut self.retreat_state {
             RetreatState::Idle => {
@@ -989,8 +1089,7 @@ impl AINode for CheckRetreat {
                 };
 
                 if should_retreat {
-                    let speed = tdata_get().get_npc_stats(npc.ty).unwrap().run_speed * 2;
-                    let mut path = Path::new_single(self.retreat_to, speed);
+                    let mut path = Path::new_single(self.retreat_to, self.retreat_speed);
                     path.start();
                     self.retreat_state = RetreatState::Retreating(path);
                     npc.retreating = true;
@@ -1003,8 +1102,7 @@ impl AINode for CheckRetreat {
                 npc.tick_movement_along_path(path, clients, state);
                 if path.is_done() {
                     self.retreat_state = RetreatState::Idle;
-                    npc.reset();
-                    // TODO full heal effect
+                    npc.return_home_heal(state, clients);
                     NodeStatus::Success
                 } else {
                     NodeStatus::Running
@@ -1014,6 +1112,36 @@ impl AINode for CheckRetreat {
     }
 }
 
+#[derive(Debug, Clone)]
+struct RegenHP {
+    interval: Duration,
+}
+impl RegenHP {
+    fn new_node(interval: Duration) -> Box<dyn AINode> {
+        Box::new(Self { interval })
+    }
+}
+impl AINode for RegenHP {
+    fn clone_node(&self) -> Box<dyn AINode> {
+        Box::new(self.clone())
+    }
+
+    fn tick(
+        &mut self,
+        npc: &mut NPC,
+        state: &mut ShardServerState,
+        clients: &mut ClientMap,
+        time: &SystemTime,
+        _rng: &mut GameRng,
+    ) -> NodeStatus {
+        let healed = npc.tick_regen(*time, self.interval);
+        if healed > 0 {
+            npc.broadcast_heal(healed, state, clients);
+        }
+        NodeStatus::Success
+    }
+}
+
 #[derive(Debug, Clone)]
 enum AttackState {
     Attacking,
@@ -1047,7 +1175,7 @@ impl AINode for CheckAttack {
         state: &mut ShardServerState,
         clients: &mut ClientMap,
         time: &SystemTime,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) -> NodeStatus {
         let target_id = match npc.target_id {
             Some(target_id) => target_id,
@@ -1081,10 +1209,12 @@ impl AINode for CheckAttack {
                     NodeStatus::Running
                 } else {
                     let target_ids = &[target_id];
+                    // mob data has no projectile travel time, so these land right away
                     log_if_failed(skills::do_basic_attack(
                         npc.get_id(),
                         target_ids,
                         false,
+                        None,
                         state,
                         clients,
                     ));
@@ -1118,17 +1248,72 @@ impl AINode for CheckAttack {
     }
 }
 
+fn on_boss_defeated(
+    npc_id: i32,
+    event_id: Option<i32>,
+    state: &mut ShardServerState,
+    clients: &mut ClientMap,
+    rng: &mut GameRng,
+) {
+    let boss = state.get_npc(npc_id).unwrap();
+    let (boss_type, mob_drop_id) = (boss.ty, boss.mob_drop_id);
+    let loot_eligible = boss.get_boss_loot_eligible();
+    let top_damage = boss.get_boss_top_contributors(BOSS_TOP_CONTRIBUTORS_SHOWN);
+
+    // everyone who pulled their weight gets their own roll, killer or not
+    let mut credited = HashSet::new();
+    for pc_id in loot_eligible {
+        if let Ok(player) = state.get_player_mut(pc_id) {
+            helpers::give_defeat_rewards(player, boss_type, mob_drop_id, event_id, clients, rng);
+            credited.insert(pc_id);
+        }
+    }
+    helpers::give_contributor_kill_credit(npc_id, &credited, state, clients);
+
+    let top_contributors: Vec<String> = top_damage
+        .into_iter()
+        .filter_map(|(pc_id, damage)| {
+            let player = state.get_player(pc_id).ok()?;
+            Some(format!(
+                "{} {} ({})",
+                player.first_name, player.last_name, damage
+            ))
+        })
+        .collect();
+    let mut msg = "The world boss has been defeated!".to_string();
+    if !top_contributors.is_empty() {
+        msg.push_str(&format!("\nTop fighters: {}", top_contributors.join(", ")));
+    }
+    helpers::send_shard_announcement(clients, &msg);
+}
+
 fn on_mob_defeated(
     npc_id: i32,
     defeater_id: EntityID,
     state: &mut ShardServerState,
     clients: &mut ClientMap,
-    rng: &mut ThreadRng,
+    rng: &mut GameRng,
 ) -> FFResult<()> {
-    let defeated_type = state.get_npc(npc_id).unwrap().ty;
+    let defeated = state.get_npc(npc_id).unwrap();
+    // copied out so rewards can be rolled while players are borrowed mutably
+    let (defeated_type, mob_drop_id) = (defeated.ty, defeated.mob_drop_id);
+    let is_boss = defeated.is_boss;
+    let event_id = state.active_event_id;
+    // only the finishing blow counts towards the kill leaderboard
+    if let EntityID::Player(pc_id) = defeater_id {
+        leaderboard_queue_kill(state.get_player(pc_id)?.get_uid());
+    }
+
+    if is_boss {
+        on_boss_defeated(npc_id, event_id, state, clients, rng);
+        return Ok(());
+    }
+
+    let mut credited = HashSet::new();
     if let EntityID::Player(pc_id) = defeater_id {
         let player = state.get_player_mut(pc_id)?;
-        helpers::give_defeat_rewards(player, defeated_type, clients, rng);
+        helpers::give_defeat_rewards(player, defeated_type, mob_drop_id, event_id, clients, rng);
+        credited.insert(pc_id);
     }
 
     let defeater = state.get_combatant(defeater_id)?;
@@ -1143,10 +1328,91 @@ fn on_mob_defeated(
                 }
                 let player = state.get_player_mut(member_pc_id).unwrap();
                 if player.get_position().distance_to(&position) < RANGE_GROUP_PARTICIPATE {
-                    helpers::give_defeat_rewards(player, defeated_type, clients, rng);
+                    helpers::give_defeat_rewards(
+                        player,
+                        defeated_type,
+                        mob_drop_id,
+                        event_id,
+                        clients,
+                        rng,
+                    );
+                    credited.insert(member_pc_id);
                 }
             }
         }
     }
+
+    helpers::give_contributor_kill_credit(npc_id, &credited, state, clients);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    use crate::{enums::CombatStyle, tabledata::AIParamsData};
+
+    fn mob_stats(ai_type: u8) -> NPCStats {
+        NPCStats {
+            team: CombatantTeam::Mob,
+            style: CombatStyle::Adaptium,
+            level: 10,
+            max_hp: 1000,
+            power: 100,
+            defense: 100,
+            radius: 150,
+            walk_speed: 300,
+            run_speed: 600,
+            sight_range: 1500,
+            idle_range: 2000,
+            combat_range: 3000,
+            attack_range: 400,
+            regen_time: 100,
+            delay_time: 20,
+            ai_type,
+            bark_type: None,
+        }
+    }
+
+    #[test]
+    fn test_node_params_ai_type_override() {
+        let serde_json::Value::Object(root) = json!({
+            "AITypes": {
+                "3": {
+                    "scan_radius_mult": 2.0,
+                    "aggro_rate_up": 3.0,
+                    "aggro_rate_down": 0.25,
+                    "retreat_speed_mult": 1.0,
+                    "attack_cooldown_mult": 0.5,
+                }
+            }
+        }) else {
+            unreachable!();
+        };
+        let ai_params = AIParamsData::load(&root).unwrap();
+
+        // without an override, the old hardcoded tuning is kept
+        let default_params = NodeParams::new(&mob_stats(1), ai_params.get(1));
+        assert_eq!(default_params.scan_radius, 1500);
+        assert_eq!(default_params.aggro_rates, (1.5, -1.0));
+        assert_eq!(default_params.aggro_threshold, 1.0);
+        assert_eq!(default_params.retreat_speed, 1200);
+        assert_eq!(default_params.roam_radius_range, (500, 1000));
+        assert_eq!(default_params.roam_delay_range_ms, (10000, 20000));
+        assert_eq!(default_params.attack_cooldown, Duration::from_secs(2));
+        // a tenth of the 10 second respawn time
+        assert_eq!(default_params.regen_interval, Duration::from_secs(1));
+
+        let tuned_params = NodeParams::new(&mob_stats(3), ai_params.get(3));
+        assert_eq!(tuned_params.scan_radius, 3000);
+        assert_eq!(tuned_params.aggro_rates, (3.0, -0.25));
+        assert_eq!(tuned_params.retreat_speed, 600);
+        assert_eq!(tuned_params.attack_cooldown, Duration::from_secs(1));
+        assert_eq!(
+            tuned_params.roam_radius_range,
+            default_params.roam_radius_range
+        );
+    }
+}
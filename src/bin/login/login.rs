@@ -5,9 +5,9 @@ use rand::random;
 
 use rusty_fusion::{
     config::config_get,
-    database::db_run_sync,
+    database::{db_run_sync, UnavailablePlayer},
     defines::*,
-    entity::{Combatant, Entity, Player},
+    entity::{Combatant, Entity, Player, PlayerStyle},
     enums::{ItemLocation, ItemType, PlayerNameStatus},
     error::{catch_fail, log, log_if_failed, FFError, FFResult, Severity},
     item::Item,
@@ -19,6 +19,7 @@ use rusty_fusion::{
     },
     state::LoginServerState,
     unused, util,
+    wordfilter::wordfilter_get,
 };
 
 pub fn login(
@@ -46,44 +47,80 @@ pub fn login(
             .trim()
             .to_owned();
 
-            let lookup_username = username.clone();
-            let account =
-                match db_run_sync(move |db| db.find_account_from_username(&lookup_username))? {
-                    Some(account) => account,
-                    None => {
-                        if config_get().login.auto_create_accounts.get() {
-                            // automatically create the account with the supplied credentials
-                            let new_username = username.clone();
-                            let password_hashed = util::hash_password(&password)?;
-                            let new_acc = db_run_sync(move |db| {
-                                db.create_account(&new_username, &password_hashed)
-                            })?;
-                            log(
-                                Severity::Info,
-                                &format!(
-                                    "Created account {} with ID {} and level {}",
-                                    username, new_acc.id, new_acc.account_level
-                                ),
-                            );
-                            new_acc
-                        } else {
-                            error_code = 1; // "Sorry, the ID you have entered does not exist. Please try again."
+            let config = &config_get().login;
+            let auth_token = if config.auth_tokens_enabled.get() {
+                util::AuthToken::parse(&password, &config.auth_token_prefix.get())
+            } else {
+                None
+            };
+
+            let account = match auth_token {
+                Some(auth_token) => {
+                    let token_id = auth_token.id.clone();
+                    let secret = auth_token.secret.clone();
+                    // only a token whose secret checks out gets used up
+                    let consumed = db_run_sync(move |db| {
+                        db.consume_auth_token(&token_id, time, &|token_hash| {
+                            util::check_password(&secret, token_hash).unwrap_or(false)
+                        })
+                    })?;
+                    match consumed {
+                        Some(account) => account,
+                        None => {
+                            // same as a wrong password so nobody can probe for valid tokens
+                            error_code = 2; // "Sorry, the ID and Password you have entered do not match. Please try again."
                             return Err(FFError::build(
                                 Severity::Warning,
-                                format!("Couldn't find account {}", username),
+                                format!("Invalid auth token {} for {}", auth_token.id, username),
                             ));
                         }
                     }
-                };
-
-            // check password
-            if !util::check_password(&password, &account.password_hashed)? {
-                error_code = 2; // "Sorry, the ID and Password you have entered do not match. Please try again."
-                return Err(FFError::build(
-                    Severity::Warning,
-                    format!("Incorrect password for account {}", username),
-                ));
-            }
+                }
+                None => {
+                    let lookup_username = username.clone();
+                    let account = match db_run_sync(move |db| {
+                        db.find_account_from_username(&lookup_username)
+                    })? {
+                        Some(account) => account,
+                        None => {
+                            if config.auto_create_accounts.get() {
+                                // automatically create the account with the supplied credentials
+                                let new_username = username.clone();
+                                let password_hashed = util::hash_password(&password)?;
+                                let new_acc = db_run_sync(move |db| {
+                                    db.create_account(&new_username, &password_hashed)
+                                })?;
+                                log(
+                                    Severity::Info,
+                                    &format!(
+                                        "Created account {} with ID {} and level {}",
+                                        username, new_acc.id, new_acc.account_level
+                                    ),
+                                );
+                                new_acc
+                            } else {
+                                error_code = 1; // "Sorry, the ID you have entered does not exist. Please try again."
+                                return Err(FFError::build(
+                                    Severity::Warning,
+                                    format!("Couldn't find account {}", username),
+                                ));
+                            }
+                        }
+                    };
+
+                    // check password
+                    if !util::check_password(&password, &account.password_hashed)? {
+                        error_code = 2; // "Sorry, the ID and Password you have entered do not match. Please try again."
+                        return Err(FFError::build(
+                            Severity::Warning,
+                            format!("Incorrect password for account {}", username),
+                        ));
+                    }
+                    account
+                }
+            };
+            // token logins are for whichever account the token was issued to
+            let username = account.username.clone();
 
             // check if banned
             if account.banned_until > time {
@@ -110,7 +147,14 @@ pub fn login(
             }
 
             let last_player_slot = account.selected_slot;
-            let players = db_run_sync(move |db| db.load_players(account.id))?;
+            let mut players = Vec::new();
+            let mut unavailable_players = Vec::new();
+            for res in db_run_sync(move |db| db.load_players(account.id))? {
+                match res {
+                    Ok(player) => players.push(player),
+                    Err(unavailable) => unavailable_players.push(unavailable),
+                }
+            }
 
             /*
              * Check if this account is already logged in, meaning:
@@ -129,6 +173,8 @@ pub fn login(
                 .iter()
                 .find(|p| state.get_player_shard(p.get_uid()).is_some())
             {
+                // the shard may have lost track of them, so make sure they're really there
+                state.request_player_check(account.id, dup_player.get_uid());
                 client.client_type = ClientType::UnauthedClient {
                     username: username.clone(),
                     dup_pc_uid: Some(dup_player.get_uid()),
@@ -144,7 +190,7 @@ pub fn login(
             }
 
             let resp = sP_LS2CL_REP_LOGIN_SUCC {
-                iCharCount: players.len() as i8,
+                iCharCount: (players.len() + unavailable_players.len()) as i8,
                 iSlotNum: last_player_slot as i8,
                 iTempForPacking4: unused!(),
                 uiSvrTime: util::get_timestamp_ms(time),
@@ -170,7 +216,14 @@ pub fn login(
                 serial_key,
                 pc_id: None,
             };
-            state.start_session(account, players.clone().iter().cloned());
+            state.start_session(
+                account,
+                players.clone().iter().cloned(),
+                unavailable_players
+                    .iter()
+                    .map(|unavailable| unavailable.slot_num)
+                    .collect(),
+            );
 
             players.iter().try_for_each(|player| {
                 let pos = player.get_position();
@@ -185,7 +238,15 @@ pub fn login(
                     aEquip: player.get_equipped().map(Option::<Item>::into),
                 };
                 client.send_packet(P_LS2CL_REP_CHAR_INFO, &pkt)
-            })
+            })?;
+
+            // broken characters still take up their slot so nothing gets created over them
+            for unavailable in &unavailable_players {
+                let pkt = get_unavailable_char_info(unavailable);
+                client.send_packet(P_LS2CL_REP_CHAR_INFO, &pkt)?;
+            }
+
+            Ok(())
         })(),
         || {
             let resp = sP_LS2CL_REP_LOGIN_FAIL {
@@ -197,6 +258,39 @@ pub fn login(
     )
 }
 
+// Shows up in the character list as "Slot Unavailable" and can't be selected
+fn get_unavailable_char_info(unavailable: &UnavailablePlayer) -> sP_LS2CL_REP_CHAR_INFO {
+    let style = PlayerStyle::default();
+    sP_LS2CL_REP_CHAR_INFO {
+        iSlot: unavailable.slot_num as i8,
+        iLevel: 1,
+        sPC_Style: sPCStyle {
+            iPC_UID: unavailable.pc_uid,
+            iNameCheck: 1,
+            szFirstName: util::encode_utf16("Slot"),
+            szLastName: util::encode_utf16("Unavailable"),
+            iGender: style.gender,
+            iFaceStyle: style.face_style,
+            iHairStyle: style.hair_style,
+            iHairColor: style.hair_color,
+            iSkinColor: style.skin_color,
+            iEyeColor: style.eye_color,
+            iHeight: style.height,
+            iBody: style.body,
+            iClass: unused!(),
+        },
+        sPC_Style2: sPCStyle2 {
+            iAppearanceFlag: 1,
+            iTutorialFlag: 1,
+            iPayzoneFlag: 0,
+        },
+        iX: 0,
+        iY: 0,
+        iZ: 0,
+        aEquip: [None::<Item>; 9].map(Option::<Item>::into),
+    }
+}
+
 pub fn pc_exit_duplicate(
     new_key: usize,
     clients: &mut HashMap<usize, FFClient>,
@@ -253,45 +347,93 @@ pub fn pc_exit_duplicate(
     }
 }
 
-pub fn check_char_name(client: &mut FFClient) -> FFResult<()> {
-    // TODO failure
-    let pkt: &sP_CL2LS_REQ_CHECK_CHAR_NAME = client.get_packet(P_CL2LS_REQ_CHECK_CHAR_NAME)?;
-    let resp = sP_LS2CL_REP_CHECK_CHAR_NAME_SUCC {
-        szFirstName: pkt.szFirstName,
-        szLastName: pkt.szLastName,
+pub fn validate_char_name(first_name: &str, last_name: &str) -> FFResult<()> {
+    let Some(filter) = wordfilter_get() else {
+        return Ok(());
     };
-    client.send_packet(P_LS2CL_REP_CHECK_CHAR_NAME_SUCC, &resp)
+    if !filter.check_name(first_name) || !filter.check_name(last_name) {
+        return Err(FFError::build(
+            Severity::Info,
+            format!("Name rejected by filter: {} {}", first_name, last_name),
+        ));
+    }
+    Ok(())
+}
+
+// Custom names need approval unless the server is set to auto-approve them
+pub fn get_custom_name_status(pc_uid: i64, first_name: &str, last_name: &str) -> PlayerNameStatus {
+    if config_get().login.auto_approve_custom_names.get() {
+        return PlayerNameStatus::Approved;
+    }
+
+    monitor_queue(MonitorEvent::NameRequest(NameRequestEvent {
+        player_uid: pc_uid as u64,
+        requested_name: format!("{} {}", first_name, last_name),
+    }));
+    PlayerNameStatus::Pending
+}
+
+pub fn check_char_name(client: &mut FFClient) -> FFResult<()> {
+    let pkt: sP_CL2LS_REQ_CHECK_CHAR_NAME = *client.get_packet(P_CL2LS_REQ_CHECK_CHAR_NAME)?;
+    catch_fail(
+        (|| {
+            if pkt.iFNCode == 0 {
+                // custom name
+                let first_name = util::parse_utf16(&pkt.szFirstName)?;
+                let last_name = util::parse_utf16(&pkt.szLastName)?;
+                validate_char_name(&first_name, &last_name)?;
+            }
+
+            let resp = sP_LS2CL_REP_CHECK_CHAR_NAME_SUCC {
+                szFirstName: pkt.szFirstName,
+                szLastName: pkt.szLastName,
+            };
+            client.send_packet(P_LS2CL_REP_CHECK_CHAR_NAME_SUCC, &resp)
+        })(),
+        || {
+            let resp = sP_LS2CL_REP_CHECK_CHAR_NAME_FAIL {
+                iErrorCode: 1, // generic failure
+            };
+            client.send_packet(P_LS2CL_REP_CHECK_CHAR_NAME_FAIL, &resp)
+        },
+    )
 }
 
 pub fn save_char_name(client: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
     // TODO failure
     let acc_id = client.get_account_id()?;
-    let pkt: &sP_CL2LS_REQ_SAVE_CHAR_NAME = client.get_packet(P_CL2LS_REQ_SAVE_CHAR_NAME)?;
+    let pkt: sP_CL2LS_REQ_SAVE_CHAR_NAME = *client.get_packet(P_CL2LS_REQ_SAVE_CHAR_NAME)?;
 
     let pc_uid = util::get_uid();
-    let slot_num = pkt.iSlotNum as usize;
-    if !(1..=4).contains(&slot_num) {
-        return Err(FFError::build(
-            Severity::Warning,
-            format!("Bad slot number {}", slot_num),
-        ));
-    }
+    let slot_num = match state.get_free_player_slot(acc_id, pkt.iSlotNum as usize) {
+        Ok(slot_num) => slot_num,
+        Err(e) => {
+            let resp = sP_LS2CL_REP_SAVE_CHAR_NAME_FAIL {
+                iErrorCode: 1, // generic failure
+            };
+            log_if_failed(client.send_packet(P_LS2CL_REP_SAVE_CHAR_NAME_FAIL, &resp));
+            return Err(e);
+        }
+    };
 
     let mut player = Player::new(pc_uid, slot_num);
     let first_name = util::parse_utf16(&pkt.szFirstName)?;
     let last_name = util::parse_utf16(&pkt.szLastName)?;
+    if pkt.iFNCode == 0 {
+        if let Err(e) = validate_char_name(&first_name, &last_name) {
+            let resp = sP_LS2CL_REP_SAVE_CHAR_NAME_FAIL {
+                iErrorCode: 1, // generic failure
+            };
+            log_if_failed(client.send_packet(P_LS2CL_REP_SAVE_CHAR_NAME_FAIL, &resp));
+            return Err(e);
+        }
+    }
 
     let name_check = if pkt.iFNCode != 0 {
         // name wheel name; TODO validate
         PlayerNameStatus::Approved
-    } else if config_get().login.auto_approve_custom_names.get() {
-        PlayerNameStatus::Approved
     } else {
-        monitor_queue(MonitorEvent::NameRequest(NameRequestEvent {
-            player_uid: pc_uid as u64,
-            requested_name: format!("{} {}", first_name, last_name),
-        }));
-        PlayerNameStatus::Pending
+        get_custom_name_status(pc_uid, &first_name, &last_name)
     };
 
     player.first_name = first_name;
@@ -308,7 +450,7 @@ pub fn save_char_name(client: &mut FFClient, state: &mut LoginServerState) -> FF
     let style = &player.get_style();
     let resp = sP_LS2CL_REP_SAVE_CHAR_NAME_SUCC {
         iPC_UID: pc_uid,
-        iSlotNum: pkt.iSlotNum,
+        iSlotNum: slot_num as i8,
         iGender: style.iGender,
         szFirstName: style.szFirstName,
         szLastName: style.szLastName,
@@ -329,6 +471,8 @@ pub fn char_create(client: &mut FFClient, state: &mut LoginServerState) -> FFRes
         let player_saved = player.clone();
         db_run_sync(move |db| db.update_player_appearance(&player_saved))?;
 
+        // clothes picked on the creation screen go on first so any starting gear
+        // configured to be equipped in the same slots replaces them
         player
             .set_item(
                 ItemLocation::Equip,
@@ -350,6 +494,7 @@ pub fn char_create(client: &mut FFClient, state: &mut LoginServerState) -> FFRes
                 Some(Item::new(ItemType::Foot, pkt.sOn_Item.iEquipFootID)),
             )
             .unwrap();
+        player.init_new_character();
 
         let player_saved = player.clone();
         db_run_sync(move |db| db.save_player(&player_saved))?;
@@ -450,12 +595,7 @@ pub fn char_select(
 pub fn shard_list_info(client: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
     // client is hardcoded to shard 1 for this at the time of writing
     let shard_id = 1; // pkt.iShardNum some day?
-    let mut statuses = [0; MAX_NUM_CHANNELS + 1];
-    statuses[0] = unused!();
-    statuses[1..].copy_from_slice(&state.get_shard_channel_statuses(shard_id).map(|s| s as u8));
-    let resp = sP_LS2CL_REP_SHARD_LIST_INFO_SUCC {
-        aShardConnectFlag: statuses,
-    };
+    let resp = state.get_shard_list_info(shard_id);
     client.send_packet(P_LS2CL_REP_SHARD_LIST_INFO_SUCC, &resp)
 }
 
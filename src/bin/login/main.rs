@@ -14,9 +14,9 @@ use rusty_fusion::{
     database::{db_init, db_shutdown},
     error::{
         log, log_error, log_if_failed, logger_flush, logger_flush_scheduled, logger_init,
-        panic_log, FFError, FFResult, Severity,
+        panic_if_failed, panic_log, FFError, FFResult, Severity,
     },
-    monitor::{monitor_flush, monitor_init, monitor_queue, MonitorEvent},
+    monitor::{monitor_flush, monitor_init, monitor_queue, monitor_queue_stat, MonitorEvent},
     net::{
         packet::{
             PacketID::{self, *},
@@ -28,6 +28,8 @@ use rusty_fusion::{
     tabledata::tdata_init,
     timer::TimerMap,
     unused,
+    webhook::{webhook_flush, webhook_init, webhook_queue, webhook_shutdown, WebhookEventKind},
+    wordfilter::wordfilter_init,
 };
 
 fn main() -> Result<()> {
@@ -37,6 +39,13 @@ fn main() -> Result<()> {
     logger_init(config.login.log_path.get());
     cleanup.db_thread_handle = Some(db_init());
     tdata_init();
+    wordfilter_init(&config.general.word_filter_path.get());
+    cleanup.webhook_thread_handle = webhook_init(
+        config.general.webhook_url.get(),
+        config.general.webhook_events.get(),
+        "Login server".to_string(),
+    );
+    panic_if_failed(config.newcharacter.validate());
 
     let polling_interval = Duration::from_millis(50);
     let listen_addr = config.login.listen_addr.get();
@@ -63,16 +72,34 @@ fn main() -> Result<()> {
         Duration::from_secs(config.general.log_write_interval.get()),
         false,
     );
+    timers.register_timer(
+        Box::new(|_, _, _| webhook_flush()),
+        Duration::from_secs(config.general.webhook_interval.get()),
+        false,
+    );
     timers.register_timer(
         Box::new(|t, srv, st| {
             st.as_login()
                 .process_shard_connection_requests(srv.get_clients(), t);
+            st.as_login().process_player_checks(srv.get_clients());
             Ok(())
         }),
         Duration::from_millis(250),
         false,
     );
 
+    let handler_stats_interval = config.general.handler_stats_interval.get();
+    if handler_stats_interval > 0 {
+        timers.register_timer(
+            Box::new(|_, _, st| {
+                st.get_handler_stats_mut().log_top();
+                Ok(())
+            }),
+            Duration::from_secs(handler_stats_interval),
+            false,
+        );
+    }
+
     log(
         Severity::Info,
         &format!(
@@ -81,14 +108,16 @@ fn main() -> Result<()> {
             state.as_login().server_id
         ),
     );
+    webhook_queue(WebhookEventKind::ServerUp, "Login server is up");
 
     if config.login.monitor_enabled.get() {
         let monitor_addr = config.login.monitor_addr.get();
         monitor_init(monitor_addr);
 
         let monitor_interval = config.login.monitor_interval.get();
+        let monitor_world_state = config.login.monitor_world_state.get();
         timers.register_timer(
-            Box::new(move |_, _, st| send_monitor_update(st.as_login())),
+            Box::new(move |_, _, st| send_monitor_update(st.as_login(), monitor_world_state)),
             Duration::from_secs(monitor_interval),
             false,
         );
@@ -96,6 +125,10 @@ fn main() -> Result<()> {
 
     let live_check_time = Duration::from_secs(config.general.live_check_time.get());
     while running.load(Ordering::SeqCst) {
+        if server.should_shut_down() {
+            log(Severity::Fatal, "Too many packet handlers panicked");
+            break;
+        }
         server.poll(&mut state, live_check_time)?;
         timers
             .check_all(&mut server, &mut state)
@@ -109,12 +142,17 @@ fn main() -> Result<()> {
     }
 
     log(Severity::Info, "Login server shutting down...");
+    webhook_queue(
+        WebhookEventKind::ServerDown,
+        "Login server is shutting down",
+    );
     Ok(())
 }
 
 #[derive(Default)]
 struct Cleanup {
     db_thread_handle: Option<std::thread::JoinHandle<()>>,
+    webhook_thread_handle: Option<std::thread::JoinHandle<()>>,
 }
 impl Drop for Cleanup {
     fn drop(&mut self) {
@@ -123,6 +161,10 @@ impl Drop for Cleanup {
             db_shutdown();
             handle.join().unwrap();
         }
+        if let Some(handle) = self.webhook_thread_handle.take() {
+            let _ = webhook_shutdown();
+            handle.join().unwrap();
+        }
         if let Err(e) = logger_flush() {
             println!("Could not flush log: {}", e);
         }
@@ -144,6 +186,7 @@ fn handle_disconnect(key: usize, clients: &mut HashMap<usize, FFClient>, state:
                     client.get_addr()
                 ),
             );
+            client.log_compression_stats();
         }
         ClientType::GameClient { account_id, .. } => {
             log_if_failed(state.end_session(account_id));
@@ -185,9 +228,12 @@ fn handle_packet(
     match pkt_id {
         P_FE2LS_REQ_AUTH_CHALLENGE => shard::auth_challenge(client),
         P_FE2LS_REQ_CONNECT => shard::connect(client, state, time),
-        P_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC => shard::update_login_info_succ(key, clients),
+        P_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC => shard::update_login_info_succ(key, clients, state),
         P_FE2LS_REP_UPDATE_LOGIN_INFO_FAIL => shard::update_login_info_fail(key, clients),
-        P_FE2LS_REP_LIVE_CHECK => Ok(()),
+        P_FE2LS_REP_LIVE_CHECK => {
+            client.on_live_check_reply(time);
+            Ok(())
+        }
         P_FE2LS_UPDATE_PC_STATUSES => shard::update_pc_statuses(client, state),
         P_FE2LS_REQ_MOTD => shard::motd(client),
         P_FE2LS_MOTD_REGISTER => shard::motd_register(client),
@@ -197,6 +243,13 @@ fn handle_packet(
         P_FE2LS_REP_PC_LOCATION_FAIL => shard::pc_location_fail(key, clients, state),
         P_FE2LS_REQ_GET_BUDDY_STATE => shard::get_buddy_state(key, clients, state),
         P_FE2LS_DISCONNECTING => shard::handle_disconnecting(key, clients, state),
+        P_FE2LS_ACCOUNT_BANNED => shard::account_banned(key, clients),
+        P_FE2LS_REQ_CHANGE_NAME => shard::change_name(client),
+        P_FE2LS_REQ_CONFISCATE_ITEM => shard::confiscate_item(client, state),
+        P_FE2LS_LOGIN_DATA_EXPIRED => shard::login_data_expired(client, state),
+        P_FE2LS_REP_VERIFY_PLAYER => shard::verify_player(client, state),
+        P_FE2LS_BUDDY_PRESENCE => shard::buddy_presence(key, clients, state),
+        P_FE2LS_WORLD_SNAPSHOT => shard::world_snapshot(client, state),
         P_FE2LS_REQ_LIVE_CHECK => shard::shard_live_check(client),
         //
         P_CL2LS_REQ_LOGIN => login::login(client, state, time),
@@ -209,7 +262,10 @@ fn handle_packet(
         P_CL2LS_REQ_SAVE_CHAR_TUTOR => login::save_char_tutor(client, state),
         P_CL2LS_REQ_CHAR_SELECT => login::char_select(key, clients, state),
         P_CL2LS_REQ_SHARD_SELECT => login::shard_select(key, clients, state, time),
-        P_CL2LS_REP_LIVE_CHECK => Ok(()),
+        P_CL2LS_REP_LIVE_CHECK => {
+            client.on_live_check_reply(time);
+            Ok(())
+        }
         //
         _ => Err(FFError::build(
             Severity::Warning,
@@ -236,7 +292,7 @@ fn send_live_check(client: &mut FFClient) -> FFResult<()> {
     }
 }
 
-fn send_monitor_update(state: &LoginServerState) -> FFResult<()> {
+fn send_monitor_update(state: &LoginServerState, with_world_state: bool) -> FFResult<()> {
     for data in state.get_all_shard_player_data() {
         monitor_queue(MonitorEvent::Player(PlayerEvent {
             x_coord: data.x_coord,
@@ -244,5 +300,37 @@ fn send_monitor_update(state: &LoginServerState) -> FFResult<()> {
             name: format!("{} {}", data.first_name, data.last_name),
         }));
     }
+    if with_world_state {
+        queue_world_state(state);
+    }
     monitor_flush()
 }
+
+fn queue_world_state(state: &LoginServerState) {
+    for (shard_id, snapshot) in state.get_shard_world_snapshots() {
+        for (i, pop) in snapshot.channel_pops.iter().enumerate() {
+            monitor_queue_stat(
+                format!("shard.{}.channel.{}.population", shard_id, i + 1),
+                pop,
+            );
+        }
+        for status in &snapshot.tracked_npcs {
+            monitor_queue_stat(
+                format!("shard.{}.npc.{}", shard_id, status.npc_id),
+                format!(
+                    "{} {} {}",
+                    status.npc_type,
+                    status.channel_num,
+                    if status.alive { "alive" } else { "dead" }
+                ),
+            );
+        }
+        if snapshot.tracked_npcs_truncated {
+            monitor_queue_stat(format!("shard.{}.npcs_truncated", shard_id), true);
+        }
+        monitor_queue_stat(
+            format!("shard.{}.avg_tick_us", shard_id),
+            snapshot.avg_tick.as_micros(),
+        );
+    }
+}
@@ -1,21 +1,31 @@
 use std::{
     collections::{HashMap, HashSet},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use rusty_fusion::{
     config::config_get,
+    database::{db_run_sync, edit_offline_player},
     entity::PlayerMetadata,
-    error::{codes::PlayerSearchReqErr, log, log_if_failed, FFError, FFResult, Severity},
+    enums::ItemType,
+    error::{
+        catch_fail,
+        codes::{ConfiscateItemErr, PlayerSearchReqErr},
+        log, log_if_failed, FFError, FFResult, Severity,
+    },
     net::{
-        crypto,
+        compression, crypto,
         packet::{PacketID::*, *},
         ClientType, FFClient,
     },
-    state::{LoginServerState, PlayerSearchRequest},
+    state::{
+        relay_account_ban, LoginServerState, PlayerSearchRequest, TrackedNPCStatus, WorldSnapshot,
+    },
     unused, util,
 };
 
+use crate::login;
+
 pub fn auth_challenge(server: &mut FFClient) -> FFResult<()> {
     let key = config_get().general.server_key.get().clone();
     let mut challenge = crypto::gen_auth_challenge();
@@ -37,6 +47,8 @@ pub fn connect(
     let shard_id = pkt.iShardID;
     let num_channels = pkt.iNumChannels;
     let max_channel_pop = pkt.iMaxChannelPop;
+    let public_addr = util::parse_server_addr(&pkt.g_FE_ServerIP, pkt.g_FE_ServerPort);
+    let capabilities = pkt.iCapabilities;
 
     let challenge_solved = pkt.aChallengeSolved;
     let ClientType::UnauthedShardServer(challenge) = &server.client_type else {
@@ -61,28 +73,48 @@ pub fn connect(
         ));
     }
 
-    if let Err(e) = state.register_shard(shard_id, num_channels as u8, max_channel_pop as usize) {
+    if let Err(e) = public_addr.and_then(|public_addr| {
+        state.register_shard(
+            shard_id,
+            num_channels as u8,
+            max_channel_pop as usize,
+            public_addr,
+        )
+    }) {
         let resp = sP_LS2FE_REP_CONNECT_FAIL { iErrorCode: 2 };
         log_if_failed(server.send_packet(P_LS2FE_REP_CONNECT_FAIL, &resp));
         return Err(e);
     };
     server.client_type = ClientType::ShardServer(shard_id);
+
+    // only turn on what both sides support
+    let mut shared_capabilities = 0;
+    if config_get().general.inter_server_compression.get() {
+        shared_capabilities |= capabilities & compression::CAPABILITY_COMPRESSION;
+    }
+
     let resp = sP_LS2FE_REP_CONNECT_SUCC {
         uiSvrTime: util::get_timestamp_ms(time),
         aLS_UID: state.server_id.to_bytes_le(),
+        iCapabilities: shared_capabilities,
     };
     server.send_packet(P_LS2FE_REP_CONNECT_SUCC, &resp)?;
 
+    // the shard switches keys (and framing) as soon as it reads the reply
     let iv1: i32 = resp.aLS_UID.into_iter().reduce(|a, b| a ^ b).unwrap() as i32;
     let iv2: i32 = shard_id + 1;
     server.e_key = crypto::gen_key(resp.uiSvrTime, iv1, iv2);
+    if shared_capabilities & compression::CAPABILITY_COMPRESSION != 0 {
+        server.enable_compression(config_get().general.compression_threshold.get())?;
+    }
 
     log(
         Severity::Info,
         &format!(
-            "Connected to shard server #{} ({}) [{} channel(s), {} players per channel]",
+            "Connected to shard server #{} ({}, clients go to {}) [{} channel(s), {} players per channel]",
             shard_id,
             server.get_addr(),
+            state.get_shard_public_addr(shard_id)?,
             num_channels,
             max_channel_pop
         ),
@@ -102,14 +134,26 @@ pub fn shard_live_check(client: &mut FFClient) -> FFResult<()> {
 pub fn update_login_info_succ(
     shard_key: usize,
     clients: &mut HashMap<usize, FFClient>,
+    state: &LoginServerState,
 ) -> FFResult<()> {
     let server = clients.get_mut(&shard_key).unwrap();
-    let pkt: &sP_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC =
-        server.get_packet(P_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC)?;
+    let pkt: sP_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC =
+        *server.get_packet(P_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC)?;
+    let ClientType::ShardServer(shard_id) = server.client_type else {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "Unregistered shard server {} sent login info",
+                server.get_addr()
+            ),
+        ));
+    };
 
+    // clients go wherever the shard was advertised at, not necessarily where the shard says
+    let (ip_buf, port) = util::encode_server_addr(&state.get_shard_public_addr(shard_id)?)?;
     let resp = sP_LS2CL_REP_SHARD_SELECT_SUCC {
-        g_FE_ServerIP: pkt.g_FE_ServerIP,
-        g_FE_ServerPort: pkt.g_FE_ServerPort,
+        g_FE_ServerIP: ip_buf,
+        g_FE_ServerPort: port,
         iEnterSerialKey: pkt.iEnterSerialKey,
     };
 
@@ -158,8 +202,20 @@ pub fn update_login_info_fail(
 pub fn update_pc_statuses(client: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
     let pkt: &sP_FE2LS_UPDATE_PC_STATUSES = client.get_packet(P_FE2LS_UPDATE_PC_STATUSES)?;
     let count = pkt.iCnt;
+    let joinable = pkt.bJoinable != 0;
     let shard_id = client.get_shard_id().expect("Packet filter failed");
 
+    if state.set_shard_joinable(shard_id, joinable) {
+        log(
+            Severity::Info,
+            &format!(
+                "Shard {} is {} taking new players",
+                shard_id,
+                if joinable { "now" } else { "not" }
+            ),
+        );
+    }
+
     state.clear_shard_players(shard_id);
     for _ in 0..count {
         let data: &sPlayerMetadata = client.get_struct()?;
@@ -178,6 +234,36 @@ pub fn update_pc_statuses(client: &mut FFClient, state: &mut LoginServerState) -
     Ok(())
 }
 
+pub fn world_snapshot(client: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
+    let pkt: sP_FE2LS_WORLD_SNAPSHOT = *client.get_packet(P_FE2LS_WORLD_SNAPSHOT)?;
+    let shard_id = client.get_shard_id()?;
+
+    // clamp the counts; a bad shard shouldn't make us read past the arrays
+    let channel_pops = pkt.aChannelPop;
+    let channel_count = (pkt.iChannelCnt.max(0) as usize).min(channel_pops.len());
+    let tracked_npcs = pkt.aTrackedNPCs;
+    let tracked_npc_count = (pkt.iTrackedNPCCnt.max(0) as usize).min(tracked_npcs.len());
+    let snapshot = WorldSnapshot {
+        channel_pops: channel_pops[..channel_count]
+            .iter()
+            .map(|pop| *pop as usize)
+            .collect(),
+        tracked_npcs: tracked_npcs[..tracked_npc_count]
+            .iter()
+            .map(|status| TrackedNPCStatus {
+                npc_id: status.iNPC_ID,
+                npc_type: status.iNPCType,
+                channel_num: status.iChannelNum as u8,
+                alive: status.bAlive != 0,
+            })
+            .collect(),
+        tracked_npcs_truncated: pkt.bTrackedNPCsTruncated != 0,
+        avg_tick: Duration::from_micros(pkt.iAvgTickUs as u64),
+    };
+    state.set_shard_world_snapshot(shard_id, snapshot);
+    Ok(())
+}
+
 pub fn motd(client: &mut FFClient) -> FFResult<()> {
     let pkt: &sP_FE2LS_REQ_MOTD = client.get_packet(P_FE2LS_REQ_MOTD)?;
 
@@ -226,6 +312,118 @@ pub fn announce_msg(shard_key: usize, clients: &mut HashMap<usize, FFClient>) ->
     Ok(())
 }
 
+pub fn account_banned(shard_key: usize, clients: &mut HashMap<usize, FFClient>) -> FFResult<()> {
+    let server = clients.get_mut(&shard_key).unwrap();
+    let pkt: sP_FE2LS_ACCOUNT_BANNED = *server.get_packet(P_FE2LS_ACCOUNT_BANNED)?;
+    relay_account_ban(shard_key, &pkt, clients);
+    Ok(())
+}
+
+pub fn change_name(server: &mut FFClient) -> FFResult<()> {
+    let pkt: sP_FE2LS_REQ_CHANGE_NAME = *server.get_packet(P_FE2LS_REQ_CHANGE_NAME)?;
+    let pc_uid = pkt.iPC_UID;
+    catch_fail(
+        (|| {
+            // same checks as a new character's custom name
+            let first_name = util::parse_utf16(&pkt.szFirstName)?;
+            let last_name = util::parse_utf16(&pkt.szLastName)?;
+            if first_name.is_empty() || last_name.is_empty() {
+                return Err(FFError::build(
+                    Severity::Info,
+                    format!("Empty name requested for player {}", pc_uid),
+                ));
+            }
+            login::validate_char_name(&first_name, &last_name)?;
+
+            let name_check = login::get_custom_name_status(pc_uid, &first_name, &last_name);
+            let (first_saved, last_saved) = (first_name.clone(), last_name.clone());
+            db_run_sync(move |db| {
+                db.update_player_name(pc_uid, &first_saved, &last_saved, name_check as i32)
+            })?;
+            log(
+                Severity::Info,
+                &format!(
+                    "Player {} renamed to {} {} ({:?})",
+                    pc_uid, first_name, last_name, name_check
+                ),
+            );
+
+            let resp = sP_LS2FE_REP_CHANGE_NAME_SUCC {
+                iPC_UID: pc_uid,
+                iItemID: pkt.iItemID,
+                iNameCheck: name_check as i8,
+                szFirstName: pkt.szFirstName,
+                szLastName: pkt.szLastName,
+            };
+            server.send_packet(P_LS2FE_REP_CHANGE_NAME_SUCC, &resp)
+        })(),
+        || {
+            let resp = sP_LS2FE_REP_CHANGE_NAME_FAIL {
+                iPC_UID: pc_uid,
+                iErrorCode: 1, // generic failure
+            };
+            server.send_packet(P_LS2FE_REP_CHANGE_NAME_FAIL, &resp)
+        },
+    )
+}
+
+// Shards handle confiscations from online players themselves.
+// Offline ones come here, since only the login server knows whether the player is on anywhere.
+pub fn confiscate_item(server: &mut FFClient, state: &LoginServerState) -> FFResult<()> {
+    let pkt: sP_FE2LS_REQ_CONFISCATE_ITEM = *server.get_packet(P_FE2LS_REQ_CONFISCATE_ITEM)?;
+    let gm_uid = pkt.iGM_PC_UID;
+    let target_uid = pkt.iTargetPC_UID;
+    let send_fail = |server: &mut FFClient, err: ConfiscateItemErr| {
+        let resp = sP_LS2FE_REP_CONFISCATE_ITEM_FAIL {
+            iGM_PC_UID: gm_uid,
+            iTargetPC_UID: target_uid,
+            iErrorCode: err as i32,
+        };
+        server.send_packet(P_LS2FE_REP_CONFISCATE_ITEM_FAIL, &resp)
+    };
+
+    catch_fail(
+        (|| {
+            let item_type: ItemType = pkt.iItemType.try_into()?;
+            let item_id = pkt.iItemID;
+            let max_count = pkt.iCount.max(0) as usize;
+
+            let acc_id = db_run_sync(move |db| db.find_account_from_player(target_uid))?.id;
+            // whoever has the player loaded would overwrite the edit on their next save
+            if state.get_player_shard(target_uid).is_some() || state.is_session_active(acc_id) {
+                return send_fail(server, ConfiscateItemErr::TargetOnline);
+            }
+
+            let removed = db_run_sync(move |db| {
+                edit_offline_player(db, target_uid, |player| {
+                    let (removed, _) = player.remove_items_any(
+                        |item| item.ty == item_type && item.id == item_id,
+                        max_count,
+                    )?;
+                    Ok(removed)
+                })
+            })?;
+            log(
+                Severity::Info,
+                &format!(
+                    "Confiscated {} of item {:?} #{} from offline player {} (requested by player {})",
+                    removed, item_type, item_id, target_uid, gm_uid
+                ),
+            );
+
+            let resp = sP_LS2FE_REP_CONFISCATE_ITEM_SUCC {
+                iGM_PC_UID: gm_uid,
+                iTargetPC_UID: target_uid,
+                iItemType: pkt.iItemType,
+                iItemID: item_id,
+                iRemoved: removed as i32,
+            };
+            server.send_packet(P_LS2FE_REP_CONFISCATE_ITEM_SUCC, &resp)
+        })(),
+        || send_fail(server, ConfiscateItemErr::Generic),
+    )
+}
+
 pub fn pc_location(
     shard_key: usize,
     clients: &mut HashMap<usize, FFClient>,
@@ -316,6 +514,8 @@ pub fn pc_location_succ(
     let resp = sP_LS2FE_REP_PC_LOCATION_SUCC {
         iPC_ID: pkt.iPC_ID,
         sResp: pkt.sResp,
+        iAvgRTT: pkt.iAvgRTT,
+        iMaxRTT: pkt.iMaxRTT,
     };
     log_if_failed(client.send_packet(P_LS2FE_REP_PC_LOCATION_SUCC, &resp));
     Ok(())
@@ -385,7 +585,10 @@ pub fn get_buddy_state(
         if buddy_uid == 0 {
             continue;
         }
-        if state.get_player_shard(buddy_uid).is_some() {
+        // players who blocked them don't show up as online
+        let blocked = state.buddy_presence.is_online(buddy_uid)
+            && !state.buddy_presence.is_visible_to(buddy_uid, pkt.iPC_UID);
+        if state.get_player_shard(buddy_uid).is_some() && !blocked {
             resp.aBuddyState[i] = 1;
         }
     }
@@ -393,6 +596,52 @@ pub fn get_buddy_state(
     server.send_packet(P_LS2FE_REP_GET_BUDDY_STATE, &resp)
 }
 
+pub fn buddy_presence(
+    shard_key: usize,
+    clients: &mut HashMap<usize, FFClient>,
+    state: &mut LoginServerState,
+) -> FFResult<()> {
+    let server = clients.get_mut(&shard_key).unwrap();
+    let shard_id = server.get_shard_id()?;
+    let pkt: sP_FE2LS_BUDDY_PRESENCE = *server.get_packet(P_FE2LS_BUDDY_PRESENCE)?;
+    let pc_uid = pkt.iPC_UID;
+
+    let watchers = if pkt.iOnline != 0 {
+        let buddy_uids = pkt.aBuddyUID;
+        let blocked_flags = pkt.aBuddyBlocked;
+        let mut buddies = Vec::new();
+        let mut blocked = Vec::new();
+        for (&buddy_uid, &is_blocked) in buddy_uids.iter().zip(blocked_flags.iter()) {
+            if buddy_uid == 0 {
+                continue;
+            }
+            if is_blocked != 0 {
+                blocked.push(buddy_uid);
+            } else {
+                buddies.push(buddy_uid);
+            }
+        }
+        if !state
+            .buddy_presence
+            .set_online(pc_uid, shard_id, &buddies, &blocked)
+        {
+            // just a buddy list sync
+            return Ok(());
+        }
+        state.buddy_presence.get_watchers(pc_uid)
+    } else {
+        // who's watching has to be worked out while we still know who they blocked
+        let watchers = state.buddy_presence.get_watchers(pc_uid);
+        if !state.buddy_presence.set_offline(pc_uid) {
+            return Ok(());
+        }
+        watchers
+    };
+
+    state.push_buddy_states(watchers, clients);
+    Ok(())
+}
+
 pub fn handle_disconnecting(
     shard_key: usize,
     clients: &mut HashMap<usize, FFClient>,
@@ -406,3 +655,36 @@ pub fn handle_disconnecting(
     state.unregister_shard(shard_id);
     Ok(())
 }
+
+pub fn login_data_expired(server: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
+    let shard_id = server.get_shard_id()?;
+    let pkt: &sP_FE2LS_LOGIN_DATA_EXPIRED = server.get_packet(P_FE2LS_LOGIN_DATA_EXPIRED)?;
+    let pc_uid = pkt.iPC_UID;
+    if state.cancel_reservation(shard_id, pc_uid) {
+        log(
+            Severity::Debug,
+            &format!(
+                "Player with UID {} never reached shard {}; freed their spot",
+                pc_uid, shard_id
+            ),
+        );
+    }
+    Ok(())
+}
+
+pub fn verify_player(server: &mut FFClient, state: &mut LoginServerState) -> FFResult<()> {
+    let shard_id = server.get_shard_id()?;
+    let pkt: &sP_FE2LS_REP_VERIFY_PLAYER = server.get_packet(P_FE2LS_REP_VERIFY_PLAYER)?;
+    let acc_id = pkt.iAccountID;
+    let pc_uid = pkt.iPC_UID;
+    if state.resolve_player_check(shard_id, pc_uid, pkt.iPresent != 0) {
+        log(
+            Severity::Warning,
+            &format!(
+                "Shard {} no longer has player with UID {} (account {}); cleared stale entry",
+                shard_id, pc_uid, acc_id
+            ),
+        );
+    }
+    Ok(())
+}
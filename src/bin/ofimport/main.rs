@@ -0,0 +1,283 @@
+// Copies accounts and players out of an OpenFusion database into the configured one.
+// Usage: ofimport <path to OpenFusion database>
+
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OpenFlags, Row};
+use rusty_fusion::{
+    config::config_init,
+    database::{
+        convert_player, db_init, db_run_sync, db_shutdown, import_account, import_buddies,
+        ImportOutcome, ImportReport, OpenFusionAccount, OpenFusionPlayer,
+    },
+    entity::PlayerStyle,
+    error::{log, logger_flush, logger_init, FFError, FFResult, Severity},
+    net::packet::{sItemBase, sNano},
+    tabledata::tdata_init,
+    util, Position,
+};
+
+fn main() {
+    let Some(of_db_path) = std::env::args().nth(1) else {
+        println!("Usage: ofimport <path to OpenFusion database>");
+        std::process::exit(1);
+    };
+
+    let config = config_init();
+    // console only
+    logger_init(String::new());
+
+    // a running login server could be loading or saving the very players being imported
+    let login_addr = config.login.listen_addr.get();
+    if is_listening(&login_addr) {
+        log(
+            Severity::Fatal,
+            &format!(
+                "A login server is up at {}; shut it down before importing",
+                login_addr
+            ),
+        );
+        std::process::exit(1);
+    }
+
+    let accounts = match read_accounts(&of_db_path) {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            log(Severity::Fatal, e.get_msg());
+            std::process::exit(1);
+        }
+    };
+    log(
+        Severity::Info,
+        &format!("Read {} accounts from {}", accounts.len(), of_db_path),
+    );
+
+    let db_thread_handle = db_init();
+    tdata_init();
+
+    let mut reports: Vec<ImportReport> = accounts
+        .iter()
+        .map(|account| {
+            let acc = account.clone();
+            db_run_sync(move |db| Ok(import_account(db, &acc, convert_player))).unwrap_or_else(
+                |e| ImportReport {
+                    username: account.username.clone(),
+                    account_error: Some(e.get_msg().to_string()),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+
+    // buddies can be on any account, so these go in once every player is in
+    for (account, report) in accounts.iter().zip(reports.iter_mut()) {
+        let acc = account.clone();
+        let mut rep = report.clone();
+        match db_run_sync(move |db| {
+            import_buddies(db, &acc, &mut rep);
+            Ok(rep)
+        }) {
+            Ok(rep) => *report = rep,
+            Err(e) => report.dropped.push(format!("buddies: {}", e.get_msg())),
+        }
+    }
+
+    let mut num_imported = 0;
+    for report in &reports {
+        num_imported += report
+            .players
+            .iter()
+            .filter(|(_, outcome)| *outcome == ImportOutcome::Imported)
+            .count();
+        print!("{}", report);
+    }
+    let num_unclean = reports.iter().filter(|report| !report.is_clean()).count();
+    log(
+        Severity::Info,
+        &format!(
+            "Imported {} players; {} of {} accounts had problems (see above)",
+            num_imported,
+            num_unclean,
+            reports.len()
+        ),
+    );
+
+    db_shutdown();
+    db_thread_handle.join().unwrap();
+    if let Err(e) = logger_flush() {
+        println!("Could not flush log: {}", e);
+    }
+}
+
+fn is_listening(addr: &str) -> bool {
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    addr.to_socket_addrs()
+        .into_iter()
+        .flatten()
+        .any(|addr| TcpStream::connect_timeout(&addr, TIMEOUT).is_ok())
+}
+
+fn sqlite_err(e: rusqlite::Error) -> FFError {
+    FFError::build(Severity::Fatal, format!("OpenFusion database error: {}", e))
+}
+
+fn read_accounts(path: &str) -> FFResult<Vec<OpenFusionAccount>> {
+    let conn =
+        Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(sqlite_err)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT AccountID, Login, Password, AccountLevel, BannedUntil, BanReason
+            FROM Accounts
+            ORDER BY AccountID",
+        )
+        .map_err(sqlite_err)?;
+    let mut accounts = stmt
+        .query_map([], |row| {
+            Ok(OpenFusionAccount {
+                id: row.get("AccountID")?,
+                username: row.get("Login")?,
+                password_hashed: row.get("Password")?,
+                account_level: row.get("AccountLevel")?,
+                banned_until: util::get_systime_from_sec(
+                    row.get::<_, i64>("BannedUntil")?.max(0) as u64
+                ),
+                ban_reason: row.get("BanReason")?,
+                players: Vec::new(),
+            })
+        })
+        .map_err(sqlite_err)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sqlite_err)?;
+
+    for account in &mut accounts {
+        account.players = read_players(&conn, account.id)?;
+    }
+    Ok(accounts)
+}
+
+fn read_players(conn: &Connection, acc_id: i64) -> FFResult<Vec<OpenFusionPlayer>> {
+    let mut players = read_rows(
+        conn,
+        "SELECT p.*, a.Body, a.EyeColor, a.FaceStyle, a.Gender, a.HairColor, a.HairStyle,
+            a.Height, a.SkinColor
+        FROM Players AS p
+        LEFT JOIN Appearances AS a ON a.PlayerID = p.PlayerID
+        WHERE p.AccountID = ?1
+        ORDER BY p.Slot",
+        acc_id,
+        read_player,
+    )?;
+
+    for player in &mut players {
+        let pc_uid = player.uid;
+        player.items = read_rows(
+            conn,
+            "SELECT Slot, ID, Type, Opt, TimeLimit FROM Inventory WHERE PlayerID = ?1",
+            pc_uid,
+            |row| {
+                let item_raw = sItemBase {
+                    iType: row.get::<_, i32>("Type")? as i16,
+                    iID: row.get::<_, i32>("ID")? as i16,
+                    iOpt: row.get("Opt")?,
+                    iTimeLimit: row.get("TimeLimit")?,
+                };
+                Ok((row.get::<_, i32>("Slot")? as usize, item_raw))
+            },
+        )?;
+        player.quest_items = read_rows(
+            conn,
+            "SELECT ID, Opt FROM QuestItems WHERE PlayerID = ?1",
+            pc_uid,
+            |row| Ok((row.get("ID")?, row.get("Opt")?)),
+        )?;
+        player.nanos = read_rows(
+            conn,
+            "SELECT ID, Skill, Stamina FROM Nanos WHERE PlayerID = ?1",
+            pc_uid,
+            |row| {
+                Ok(sNano {
+                    iID: row.get::<_, i32>("ID")? as i16,
+                    iSkillID: row.get::<_, i32>("Skill")? as i16,
+                    iStamina: row.get::<_, i32>("Stamina")? as i16,
+                })
+            },
+        )?;
+        // OpenFusion keeps one row per buddyship, in either order
+        player.buddy_uids = read_rows(
+            conn,
+            "SELECT PlayerBID FROM Buddyships WHERE PlayerAID = ?1
+            UNION
+            SELECT PlayerAID FROM Buddyships WHERE PlayerBID = ?1",
+            pc_uid,
+            |row| row.get(0),
+        )?;
+        player.blocked_uids = read_rows(
+            conn,
+            "SELECT BlockedPlayerID FROM Blocks WHERE PlayerID = ?1",
+            pc_uid,
+            |row| row.get(0),
+        )?;
+    }
+    Ok(players)
+}
+
+fn read_player(row: &Row) -> rusqlite::Result<OpenFusionPlayer> {
+    let style = if row.get::<_, i32>("AppearanceFlag")? != 0 {
+        Some(PlayerStyle {
+            gender: row.get::<_, i32>("Gender")? as i8,
+            face_style: row.get::<_, i32>("FaceStyle")? as i8,
+            hair_style: row.get::<_, i32>("HairStyle")? as i8,
+            hair_color: row.get::<_, i32>("HairColor")? as i8,
+            skin_color: row.get::<_, i32>("SkinColor")? as i8,
+            eye_color: row.get::<_, i32>("EyeColor")? as i8,
+            height: row.get::<_, i32>("Height")? as i8,
+            body: row.get::<_, i32>("Body")? as i8,
+        })
+    } else {
+        None
+    };
+
+    Ok(OpenFusionPlayer {
+        uid: row.get("PlayerID")?,
+        slot_num: row.get::<_, i32>("Slot")? as usize,
+        first_name: row.get("FirstName")?,
+        last_name: row.get("LastName")?,
+        name_check: row.get("NameCheck")?,
+        level: row.get("Level")?,
+        equipped_nano_ids: [row.get("Nano1")?, row.get("Nano2")?, row.get("Nano3")?],
+        style,
+        tutorial_flag: row.get::<_, i32>("TutorialFlag")? != 0,
+        payzone_flag: row.get::<_, i32>("PayZoneFlag")? != 0,
+        position: Position {
+            x: row.get("XCoordinate")?,
+            y: row.get("YCoordinate")?,
+            z: row.get("ZCoordinate")?,
+        },
+        angle: row.get("Angle")?,
+        hp: row.get("HP")?,
+        fusion_matter: row.get("FusionMatter")?,
+        taros: row.get("Taros")?,
+        weapon_boosts: row.get("BatteryW")?,
+        nano_potions: row.get("BatteryN")?,
+        guide: row.get("Mentor")?,
+        scamper_flags: row.get("WarpLocationFlag")?,
+        skyway_flags: row.get("SkywayLocationFlag")?,
+        tip_flags: row.get("FirstUseFlag")?,
+        quest_flags: row.get("Quests")?,
+        ..Default::default()
+    })
+}
+
+fn read_rows<T>(
+    conn: &Connection,
+    sql: &str,
+    id: i64,
+    f: impl FnMut(&Row) -> rusqlite::Result<T>,
+) -> FFResult<Vec<T>> {
+    let mut stmt = conn.prepare(sql).map_err(sqlite_err)?;
+    let rows = stmt.query_map(params![id], f).map_err(sqlite_err)?;
+    rows.collect::<Result<_, _>>().map_err(sqlite_err)
+}
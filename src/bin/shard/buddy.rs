@@ -1,17 +1,22 @@
+use std::time::{Duration, SystemTime};
+
 use rusty_fusion::{
+    config::config_get,
+    database::db_run_async,
     defines::*,
     entity::{BuddyListEntry, Entity, EntityID, PlayerSearchQuery},
+    enums::{ItemLocation, ReportCategory},
     error::*,
+    helpers::{send_buddy_presence, warp_player},
     net::{
         packet::{PacketID::*, *},
         ClientMap,
     },
+    report::Report,
     state::ShardServerState,
-    util,
+    unused, util,
 };
 
-const ERROR_CODE_BUDDY_DENY: i32 = 6;
-
 pub fn get_buddy_state(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
     let pc_id = client.get_player_id()?;
@@ -101,7 +106,7 @@ pub fn request_make_buddy(clients: &mut ClientMap, state: &mut ShardServerState)
         .is_ok()
     {
         let player = state.get_player_mut(pc_id).unwrap();
-        player.buddy_offered_to = Some(buddy_uid);
+        player.buddy_offered_to.set(buddy_uid, SystemTime::now());
     }
 
     Ok(())
@@ -155,7 +160,7 @@ pub fn find_name_make_buddy(clients: &mut ClientMap, state: &mut ShardServerStat
 
     let buddy_client = buddy.get_client(clients).unwrap();
     let player = state.get_player_mut(pc_id).unwrap();
-    player.buddy_offered_to = Some(buddy_uid);
+    player.buddy_offered_to.set(buddy_uid, SystemTime::now());
     let req_pkt = sP_FE2CL_REP_PC_FIND_NAME_MAKE_BUDDY_SUCC {
         szFirstName: util::encode_utf16(&player.first_name),
         szLastName: util::encode_utf16(&player.last_name),
@@ -179,13 +184,12 @@ pub fn accept_make_buddy(clients: &mut ClientMap, state: &mut ShardServerState)
 
     let buddy = state.get_player_mut(buddy_id)?;
     let buddy_uid = buddy.get_uid();
-    if buddy.buddy_offered_to != Some(pc_uid) {
+    if !buddy.buddy_offered_to.take_for(pc_uid, SystemTime::now()) {
         return Err(FFError::build(
             Severity::Warning,
             format!("{} did not send buddy request to player {}", buddy, pc_id),
         ));
     }
-    buddy.buddy_offered_to = None;
 
     catch_fail(
         (|| {
@@ -223,6 +227,11 @@ pub fn accept_make_buddy(clients: &mut ClientMap, state: &mut ShardServerState)
                     .send_packet(P_FE2CL_REP_ACCEPT_MAKE_BUDDY_SUCC, &pkt_player),
             );
 
+            // so the login server knows they want to hear about each other
+            for id in [pc_id, buddy_id] {
+                send_buddy_presence(state.get_player(id).unwrap(), true, clients);
+            }
+
             Ok(())
         })(),
         || {
@@ -275,13 +284,12 @@ pub fn find_name_accept_buddy(
     let buddy_id = res.unwrap();
 
     let buddy = state.get_player_mut(buddy_id)?;
-    if buddy.buddy_offered_to != Some(pc_uid) {
+    if !buddy.buddy_offered_to.take_for(pc_uid, SystemTime::now()) {
         return Err(FFError::build(
             Severity::Warning,
             format!("{} did not send buddy request to player {}", buddy, pc_id),
         ));
     }
-    buddy.buddy_offered_to = None;
 
     catch_fail(
         (|| {
@@ -319,6 +327,11 @@ pub fn find_name_accept_buddy(
                     .send_packet(P_FE2CL_REP_ACCEPT_MAKE_BUDDY_SUCC, &pkt_player),
             );
 
+            // so the login server knows they want to hear about each other
+            for id in [pc_id, buddy_id] {
+                send_buddy_presence(state.get_player(id).unwrap(), true, clients);
+            }
+
             Ok(())
         })(),
         || {
@@ -340,3 +353,172 @@ pub fn find_name_accept_buddy(
         },
     )
 }
+
+pub fn buddy_warp(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let client = clients.get_self();
+    let pc_id = client.get_player_id()?;
+    let pkt: sP_CL2FE_REQ_PC_BUDDY_WARP = *client.get_packet(P_CL2FE_REQ_PC_BUDDY_WARP)?;
+    let buddy_uid = pkt.iBuddyPCUID;
+    catch_fail(
+        (|| {
+            let player = state.get_player(pc_id)?;
+            if !player.is_buddies_with(buddy_uid) {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} tried to warp to non-buddy {}", player, buddy_uid),
+                ));
+            }
+            // checked before looking for the buddy so a buddy on another shard can't be used to skip it
+            player.check_buddy_warp_cooldown(time)?;
+
+            // TODO warp to buddies on other shards through the login server
+            let buddy_id =
+                PlayerSearchQuery::ByUID(buddy_uid)
+                    .execute(state)
+                    .ok_or(FFError::build(
+                        Severity::Warning,
+                        format!(
+                            "{} tried to warp to buddy {} who isn't here",
+                            player, buddy_uid
+                        ),
+                    ))?;
+            let buddy = state.get_player(buddy_id)?;
+            if buddy.instance_id.instance_num.is_some()
+                || buddy.instance_id.channel_num != player.instance_id.channel_num
+                || buddy.is_riding_skyway()
+                || player.is_riding_skyway()
+            {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} can't warp to {} right now", player, buddy),
+                ));
+            }
+            let dest_pos = buddy.get_position();
+            let dest_map_num = buddy.instance_id.map_num;
+
+            warp_player(pc_id, dest_pos, dest_map_num, None, clients, state)?;
+            // only a warp that went through uses up the cooldown
+            let cooldown = Duration::from_secs(config_get().shard.buddy_warp_cooldown.get());
+            state
+                .get_player_mut(pc_id)?
+                .start_buddy_warp_cooldown(time, cooldown);
+
+            // the NPC warp packet resets the clientside instance state
+            let taros = state.get_player(pc_id)?.get_taros();
+            let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
+                iX: dest_pos.x,
+                iY: dest_pos.y,
+                iZ: dest_pos.z,
+                eIL: ItemLocation::end(),
+                iItemSlotNum: unused!(),
+                Item: unused!(),
+                iCandy: taros as i32,
+            };
+            clients
+                .get_self()
+                .send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp)
+        })(),
+        || {
+            let resp = sP_FE2CL_REP_PC_BUDDY_WARP_FAIL {
+                iBuddyPCUID: buddy_uid,
+                iErrorCode: unused!(),
+            };
+            clients
+                .get_self()
+                .send_packet(P_FE2CL_REP_PC_BUDDY_WARP_FAIL, &resp)
+        },
+    )
+}
+
+// The client's /block. Every block is filed as a report for moderators;
+// buddies are also blocked on the buddy list.
+pub fn set_pc_block(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let client = clients.get_self();
+    let pc_id = client.get_player_id()?;
+    let pkt: sP_CL2FE_REQ_SET_PC_BLOCK = *client.get_packet(P_CL2FE_REQ_SET_PC_BLOCK)?;
+    let target_uid = pkt.iBlock_PCUID;
+    catch_fail(
+        (|| {
+            let reporter_uid = state.get_player(pc_id)?.get_uid();
+            if target_uid == reporter_uid {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} tried to report themselves", state.get_player(pc_id)?),
+                ));
+            }
+            let per_hour = config_get().shard.reports_per_hour.get();
+            if !state
+                .report_limiter
+                .try_report(reporter_uid, time, per_hour)
+            {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} filed too many reports", state.get_player(pc_id)?),
+                ));
+            }
+
+            let player = state.get_player(pc_id)?;
+            // the target's position is only known if they're on this shard
+            let target = state
+                .get_player(pkt.iBlock_ID)
+                .ok()
+                .filter(|target| target.get_uid() == target_uid);
+            let report = Report {
+                id: unused!(),
+                reporter_uid,
+                reported_uid: target_uid,
+                category: ReportCategory::Block,
+                reason: None,
+                time,
+                shard_id: state.shard_id,
+                channel_num: player.instance_id.channel_num,
+                reporter_pos: player.get_position(),
+                reported_pos: target.map(|target| target.get_position()),
+                // lower account levels are more privileged
+                flagged: target.is_some_and(|target| target.perms < player.perms),
+                handled_by: None,
+            };
+            if report.flagged {
+                log(
+                    Severity::Info,
+                    &format!("{} reported uid:{}, who outranks them", player, target_uid),
+                );
+            }
+            let _ = db_run_async(move |db| db.file_report(&report));
+
+            let player = state.get_player_mut(pc_id)?;
+            // not on the buddy list, so there's no slot to block
+            let buddy_slot = if player.is_buddies_with(target_uid) {
+                player.block_player(target_uid)? as i8
+            } else {
+                -1
+            };
+            let resp = sP_FE2CL_REP_SET_PC_BLOCK_SUCC {
+                iBlock_ID: pkt.iBlock_ID,
+                iBlock_PCUID: target_uid,
+                iBuddySlot: buddy_slot,
+            };
+            clients
+                .get_self()
+                .send_packet(P_FE2CL_REP_SET_PC_BLOCK_SUCC, &resp)
+        })(),
+        || {
+            let resp = sP_FE2CL_REP_SET_PC_BLOCK_FAIL {
+                iBlock_ID: pkt.iBlock_ID,
+                iBlock_PCUID: target_uid,
+                iErrorCode: unused!(),
+            };
+            clients
+                .get_self()
+                .send_packet(P_FE2CL_REP_SET_PC_BLOCK_FAIL, &resp)
+        },
+    )
+}
@@ -1,8 +1,10 @@
+use std::time::SystemTime;
+
 use rusty_fusion::{
     defines::*,
     entity::{Entity, EntityID},
     error::*,
-    helpers::send_system_message,
+    helpers::{send_in_chat_range, send_in_group, send_system_message},
     net::{
         packet::{PacketID::*, *},
         ClientMap,
@@ -30,6 +32,19 @@ pub fn send_freechat_message(
                 }
             }
 
+            let mut tokens = msg.split_whitespace().collect::<Vec<_>>();
+            // the client has no packets for these, so we intercept the chat commands
+            if let Some(cmd_name) = tokens.first().and_then(|token| match *token {
+                "/redeem" => Some("redeem"),
+                "/accept" => Some("accept"),
+                "/decline" => Some("decline"),
+                "/sort" => Some("sort"),
+                _ => None,
+            }) {
+                tokens[0] = cmd_name;
+                return commands::handle_custom_command(tokens, clients, state);
+            }
+
             let client = clients.get_self();
             if msg.starts_with('/') {
                 return send_system_message(
@@ -44,7 +59,7 @@ pub fn send_freechat_message(
 
             let pc_id = client.get_player_id()?;
             let player = state.get_player(pc_id)?;
-            if player.freechat_muted {
+            if !player.can_freechat() {
                 return Err(FFError::build_dc(
                     Severity::Warning,
                     "Muted player sent freechat packet".to_string(),
@@ -63,11 +78,13 @@ pub fn send_freechat_message(
                 szFreeChat: util::encode_utf16(&msg),
                 iEmoteCode: pkt.iEmoteCode,
             };
-            state
-                .entity_map
-                .for_each_around(EntityID::Player(pc_id), clients, |client| {
-                    client.send_packet(P_FE2CL_REP_SEND_FREECHAT_MESSAGE_SUCC, &resp)
-                });
+            send_in_chat_range(
+                pc_id,
+                P_FE2CL_REP_SEND_FREECHAT_MESSAGE_SUCC,
+                &resp,
+                clients,
+                state,
+            );
             Ok(())
         })(),
         || {
@@ -111,11 +128,13 @@ pub fn send_menuchat_message(
                 szFreeChat: pkt.szFreeChat,
                 iEmoteCode: pkt.iEmoteCode,
             };
-            state
-                .entity_map
-                .for_each_around(EntityID::Player(pc_id), clients, |client| {
-                    client.send_packet(P_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC, &resp)
-                });
+            send_in_chat_range(
+                pc_id,
+                P_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC,
+                &resp,
+                clients,
+                state,
+            );
             Ok(())
         })(),
         || {
@@ -141,7 +160,7 @@ pub fn send_group_freechat_message(
     let player = state.get_player(pc_id)?;
 
     let msg = util::parse_utf16(&pkt.szFreeChat)?;
-    if player.freechat_muted {
+    if !player.can_freechat() {
         return Err(FFError::build_dc(
             Severity::Warning,
             "Muted player sent freechat packet".to_string(),
@@ -163,19 +182,13 @@ pub fn send_group_freechat_message(
         szFreeChat: util::encode_utf16(&msg),
         iEmoteCode: pkt.iEmoteCode,
     };
-    if let Some(group_id) = player.group_id {
-        let group = state.groups.get(&group_id).unwrap();
-        for eid in group.get_member_ids() {
-            let entity = state.entity_map.get_entity_raw(*eid).unwrap();
-            if let Some(client) = entity.get_client(clients) {
-                log_if_failed(
-                    client.send_packet(P_FE2CL_REP_SEND_ALL_GROUP_FREECHAT_MESSAGE_SUCC, &pkt),
-                );
-            }
-        }
-    }
-
-    Ok(())
+    send_in_group(
+        pc_id,
+        P_FE2CL_REP_SEND_ALL_GROUP_FREECHAT_MESSAGE_SUCC,
+        &pkt,
+        clients,
+        state,
+    )
 }
 
 pub fn send_group_menuchat_message(
@@ -203,19 +216,13 @@ pub fn send_group_menuchat_message(
         szFreeChat: pkt.szFreeChat,
         iEmoteCode: pkt.iEmoteCode,
     };
-    if let Some(group_id) = player.group_id {
-        let group = state.groups.get(&group_id).unwrap();
-        for eid in group.get_member_ids() {
-            let entity = state.entity_map.get_entity_raw(*eid).unwrap();
-            if let Some(client) = entity.get_client(clients) {
-                log_if_failed(
-                    client.send_packet(P_FE2CL_REP_SEND_ALL_GROUP_MENUCHAT_MESSAGE_SUCC, &pkt),
-                );
-            }
-        }
-    }
-
-    Ok(())
+    send_in_group(
+        pc_id,
+        P_FE2CL_REP_SEND_ALL_GROUP_MENUCHAT_MESSAGE_SUCC,
+        &pkt,
+        clients,
+        state,
+    )
 }
 
 pub fn pc_avatar_emotes_chat(
@@ -231,6 +238,9 @@ pub fn pc_avatar_emotes_chat(
         iID_From: pkt.iID_From,
         iEmoteCode: pkt.iEmoteCode,
     };
+    state
+        .get_player_mut(pc_id)?
+        .set_emote(pkt.iEmoteCode, SystemTime::now());
     state
         .entity_map
         .for_each_around(EntityID::Player(pc_id), clients, |client| {
@@ -240,21 +250,49 @@ pub fn pc_avatar_emotes_chat(
 }
 
 mod helpers {
+    use rusty_fusion::{config::config_get, wordfilter::wordfilter_get};
+
     pub fn validate_menuchat_message(_msg: &str) -> bool {
         // TODO validate
         true
     }
 
     pub fn process_freechat_message(msg: String) -> String {
-        // TODO process
-        msg
+        if !config_get().shard.chat_filter_enabled.get() {
+            return msg;
+        }
+        match wordfilter_get() {
+            Some(filter) => filter.censor(&msg),
+            None => msg,
+        }
     }
 }
 
 mod commands {
-    use std::{collections::HashMap, sync::OnceLock, time::SystemTime};
+    use std::{
+        collections::HashMap,
+        sync::OnceLock,
+        time::{Duration, SystemTime},
+    };
 
-    use rusty_fusion::{ai::AI, database::db_run_sync};
+    use rusty_fusion::{
+        ai::AI,
+        config::config_get,
+        database::db_run_sync,
+        entity::{EggSummon, Player, PlayerSearchQuery, PlayerStyle, ShinyReward, NPC},
+        enums::{ItemLocation, ItemType, RewardCategory, RewardType},
+        helpers::{
+            broadcast_appearance, change_reward_rates, consume_item,
+            kick_and_report_banned_account, redeem_code, send_announcement, start_spectating,
+            stop_spectating, RewardRateChange,
+        },
+        item::RedeemCode,
+        leaderboard::{format_leaderboard, Leaderboard},
+        path::PathRecorder,
+        tabledata::tdata_get,
+        transaction::{transaction_log_enabled, transaction_log_flush},
+        webhook::{webhook_queue, WebhookEventKind},
+    };
 
     use super::*;
 
@@ -268,7 +306,7 @@ mod commands {
 
     fn init_commands() -> HashMap<&'static str, Command> {
         #[rustfmt::skip]
-        let commands: [(&'static str, &'static str, CommandHandler); 9] = [
+        let commands: [(&'static str, &'static str, CommandHandler); 41] = [
             ("about", "Show information about the server", cmd_about),
             ("ban_a", "Ban an account", cmd_ban),
             ("ban_i", "Ban a player and their account", cmd_ban),
@@ -276,7 +314,39 @@ mod commands {
             ("followme", "Make the nearest NPC start following you", cmd_followme),
             ("unfollowme", "Stop the nearest NPC from following you", cmd_unfollowme),
             ("perms", "View or change a player's permissions level", cmd_perms),
+            ("freechat", "View or change whether a player's account can use free chat", cmd_freechat),
+            ("slots", "View or change how many extra character slots a player's account has", cmd_slots),
             ("refresh", "Reinsert the player into the current chunk", cmd_refresh),
+            ("pvp", "Toggle PvP in the current instance", cmd_pvp),
+            ("spectate", "Follow a player around without being seen", cmd_spectate),
+            ("unspectate", "Stop spectating and return to where you were", cmd_unspectate),
+            ("currency", "Show a player's recent taro and fusion matter changes", cmd_currency),
+            ("finditem", "List every slot where a player has an item", cmd_finditem),
+            ("provenance", "Show where one of a player's items came from", cmd_provenance),
+            ("transactions", "Show a player's recent trades and vendor deals", cmd_transactions),
+            ("confiscate", "Take an item away from a player", cmd_confiscate),
+            ("summondrop", "Set or clear the drop table for NPCs you summon", cmd_summondrop),
+            ("summonboss", "Toggle summoning NPCs as world bosses", cmd_summonboss),
+            ("shinyreward", "Set or clear the reward for shinies you summon", cmd_shinyreward),
+            ("shinylifetime", "Set or clear how long shinies you summon last", cmd_shinylifetime),
+            ("shinyscatter", "Summon shinies at random spots around you", cmd_shinyscatter),
+            ("pathrec", "Record, preview, and export an NPC path", cmd_pathrec),
+            ("rename", "Change a player's name", cmd_rename),
+            ("restyle", "Change a player's appearance", cmd_restyle),
+            ("rewardrate", "View, change, or reset a player's reward rates", cmd_rewardrate),
+            ("event", "Set or clear the event whose crates mobs drop", cmd_event),
+            ("disembark", "Get off the Skyway at the next station", cmd_disembark),
+            ("leaderboard", "Show the top players for kills or an infected zone race", cmd_leaderboard),
+            ("redeem", "Redeem a promotional code", cmd_redeem),
+            ("addcode", "Create a promotional code", cmd_addcode),
+            ("population", "Show the most crowded chunks in your channel", cmd_population),
+            ("summons", "Count or clear the NPCs summoned in your map", cmd_summons),
+            ("reports", "List open player reports or mark one handled", cmd_reports),
+            ("accept", "Come along on your group's pending warp", cmd_group_warp_answer),
+            ("decline", "Stay behind when your group warps", cmd_group_warp_answer),
+            ("sort", "Merge partial stacks and move items to the front of your inventory", cmd_sort),
+            ("ignore", "Hide a player's chat and invites from your account, or list who you ignore", cmd_ignore),
+            ("unignore", "Stop ignoring a player", cmd_ignore),
             ("help", "Show this help message", cmd_help),
         ];
 
@@ -301,6 +371,36 @@ mod commands {
         token.parse::<i32>().map_err(|_| ()).map(Some)
     }
 
+    // . for yourself, a pc_id or name:<first>:<last> for someone on this shard,
+    // or uid:<pc_uid> for anyone
+    fn parse_player_target(token: &str, own_pc_id: i32) -> Result<PlayerSearchQuery, ()> {
+        if let Some(pc_uid) = token.strip_prefix("uid:") {
+            return pc_uid
+                .parse::<i64>()
+                .map_err(|_| ())
+                .map(PlayerSearchQuery::ByUID);
+        }
+        if let Some(name) = token.strip_prefix("name:") {
+            let (first_name, last_name) = name.split_once(':').ok_or(())?;
+            return Ok(PlayerSearchQuery::ByName(
+                first_name.to_string(),
+                last_name.to_string(),
+            ));
+        }
+        Ok(PlayerSearchQuery::ByID(
+            parse_pc_id(token)?.unwrap_or(own_pc_id),
+        ))
+    }
+
+    fn parse_item_type_and_id(type_token: &str, id_token: &str) -> Option<(ItemType, i16)> {
+        let ty = type_token
+            .parse::<i16>()
+            .ok()
+            .and_then(|ty| ItemType::try_from(ty).ok())?;
+        let id = id_token.parse::<i16>().ok()?;
+        Some((ty, id))
+    }
+
     pub fn handle_custom_command(
         tokens: Vec<&str>,
         clients: &mut ClientMap,
@@ -421,30 +521,22 @@ mod commands {
                     ban_reason,
                 );
                 log_if_failed(send_system_message(client, &ban_msg));
-                log(
-                    Severity::Info,
-                    &format!("{}\nBanned by: {}", ban_msg, player),
-                );
+                let ban_log_msg = format!("{}\nBanned by: {}", ban_msg, player);
+                log(Severity::Info, &ban_log_msg);
+                webhook_queue(WebhookEventKind::Ban, &ban_log_msg);
             }
             Err(e) => {
                 return send_system_message(client, &format!("Failed to ban: {}", e.get_msg()));
             }
         }
 
-        if is_ban_i {
-            let banned_player = state.get_player(pc_id)?;
-            let banned_client = banned_player.get_client(clients).unwrap();
-            let pkt = sP_FE2CL_REP_PC_EXIT_SUCC {
-                iID: pc_id,
-                iExitCode: EXIT_CODE_REQ_BY_GM as i32,
-            };
-            log_if_failed(banned_client.send_packet(P_FE2CL_REP_PC_EXIT_SUCC, &pkt));
-            banned_client.disconnect();
+        let kicked = kick_and_report_banned_account(acc_id, banned_until, &ban_reason, clients);
 
+        if kicked > 0 {
             let client = clients.get_self();
             log_if_failed(send_system_message(
                 client,
-                &format!("{} kicked", banned_player),
+                &format!("{} player(s) kicked", kicked),
             ));
         }
 
@@ -673,6 +765,165 @@ mod commands {
         Ok(())
     }
 
+    fn cmd_freechat(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}freechat <pc_id> [on|off]\n\
+                Use . for pc_id to select yourself\n\
+                Turning free chat off limits the whole account to menu chat",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to change chat restrictions",
+            );
+        }
+
+        let target_pc_id = match parse_pc_id(tokens[1]) {
+            Ok(Some(pc_id)) => pc_id,
+            Ok(None) => pc_id,
+            Err(_) => return send_system_message(client, "Invalid player ID"),
+        };
+        let Ok(target_player) = state.get_player_mut(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+
+        let allowed = match tokens.get(2) {
+            None => {
+                let msg = if target_player.menuchat_only {
+                    format!("{} is restricted to menu chat", target_player)
+                } else {
+                    format!("{} can use free chat", target_player)
+                };
+                return send_system_message(client, &msg);
+            }
+            Some(&"on") => true,
+            Some(&"off") => false,
+            Some(_) => return send_system_message(client, "Expected on or off"),
+        };
+
+        let target_uid = target_player.get_uid();
+        let saved = db_run_sync(move |db| {
+            let acc = db.find_account_from_player(target_uid)?;
+            db.set_account_free_chat(acc.id, allowed)
+        });
+        if let Err(e) = saved {
+            return send_system_message(
+                client,
+                &format!("Failed to save chat restriction: {}", e.get_msg()),
+            );
+        }
+
+        target_player.menuchat_only = !allowed;
+        log_if_failed(send_system_message(
+            client,
+            &format!(
+                "Free chat turned {} for {}'s account",
+                if allowed { "on" } else { "off" },
+                target_player
+            ),
+        ));
+
+        // the client greys out free chat based on the mute flag
+        let pkt = sP_FE2CL_REP_PC_SPECIAL_STATE_SWITCH_SUCC {
+            iPC_ID: target_pc_id,
+            iReqSpecialStateFlag: CN_SPECIAL_STATE_FLAG__MUTE_FREECHAT as i8,
+            iSpecialState: target_player.get_special_state_bit_flag(),
+        };
+        state
+            .entity_map
+            .for_each_around(EntityID::Player(target_pc_id), clients, |c| {
+                c.send_packet(P_FE2CL_PC_SPECIAL_STATE_CHANGE, &pkt)
+            });
+        Ok(())
+    }
+
+    fn cmd_slots(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}slots <pc_id> [bonus]\n\
+                Use . for pc_id to select yourself\n\
+                Leave bonus empty to view the account's extra character slots",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to change character slots",
+            );
+        }
+
+        let target_pc_id = match parse_pc_id(tokens[1]) {
+            Ok(Some(pc_id)) => pc_id,
+            Ok(None) => pc_id,
+            Err(_) => return send_system_message(client, "Invalid player ID"),
+        };
+        let Ok(target_player) = state.get_player(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+        let target_uid = target_player.get_uid();
+
+        let Some(bonus_token) = tokens.get(2) else {
+            let account = db_run_sync(move |db| db.find_account_from_player(target_uid));
+            let msg = match account {
+                Ok(account) => format!(
+                    "{}'s account has {} extra character slot(s)",
+                    target_player, account.slot_bonus
+                ),
+                Err(e) => format!("Failed to look up account: {}", e.get_msg()),
+            };
+            return send_system_message(client, &msg);
+        };
+        let Ok(slot_bonus) = bonus_token.parse::<u8>() else {
+            return send_system_message(client, "Invalid slot bonus");
+        };
+
+        let saved = db_run_sync(move |db| {
+            let acc = db.find_account_from_player(target_uid)?;
+            db.set_account_slot_bonus(acc.id, slot_bonus as i32)
+        });
+        if let Err(e) = saved {
+            return send_system_message(
+                client,
+                &format!("Failed to save slot bonus: {}", e.get_msg()),
+            );
+        }
+
+        // the login server reads the bonus from the account when they next log in
+        send_system_message(
+            client,
+            &format!(
+                "{}'s account now has {} extra character slot(s), starting next login",
+                target_player, slot_bonus
+            ),
+        )
+    }
+
     fn cmd_refresh(
         _tokens: Vec<&str>,
         clients: &mut ClientMap,
@@ -690,6 +941,1688 @@ mod commands {
         Ok(())
     }
 
+    fn cmd_pvp(
+        _tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to toggle PvP");
+        }
+
+        let player_name = player.to_string();
+        let instance_id = player.instance_id;
+        let enabled = if state.pvp_instances.remove(&instance_id) {
+            false
+        } else {
+            state.pvp_instances.insert(instance_id);
+            true
+        };
+        log(
+            Severity::Info,
+            &format!(
+                "{} turned PvP {} in {}",
+                player_name,
+                if enabled { "on" } else { "off" },
+                instance_id
+            ),
+        );
+        send_system_message(
+            client,
+            &format!(
+                "PvP is now {} in {}",
+                if enabled { "on" } else { "off" },
+                instance_id
+            ),
+        )
+    }
+
+    fn cmd_spectate(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!("Usage: {}spectate <pc_id>", CUSTOM_COMMAND_PREFIX),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to spectate players");
+        }
+
+        let Ok(Some(target_pc_id)) = parse_pc_id(tokens[1]) else {
+            return send_system_message(client, "Invalid player ID");
+        };
+        let Ok(target) = state.get_player(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+        let target_name = target.to_string();
+        let player_name = player.to_string();
+
+        if let Err(e) = start_spectating(pc_id, target_pc_id, clients, state) {
+            return send_system_message(
+                clients.get_self(),
+                &format!("Couldn't spectate: {}", e.get_msg()),
+            );
+        }
+        log(
+            Severity::Info,
+            &format!("{} started spectating {}", player_name, target_name),
+        );
+        send_system_message(
+            clients.get_self(),
+            &format!(
+                "Now spectating {}\nUse {}unspectate to stop",
+                target_name, CUSTOM_COMMAND_PREFIX
+            ),
+        )
+    }
+
+    fn cmd_unspectate(
+        _tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let pc_id = clients.get_self().get_player_id()?;
+        match stop_spectating(pc_id, clients, state)? {
+            Some(target_pc_id) => {
+                let player = state.get_player(pc_id)?;
+                log(
+                    Severity::Info,
+                    &format!("{} stopped spectating player {}", player, target_pc_id),
+                );
+                send_system_message(clients.get_self(), "No longer spectating")
+            }
+            None => send_system_message(clients.get_self(), "You are not spectating anyone"),
+        }
+    }
+
+    fn cmd_currency(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!("Usage: {}currency <pc_id>", CUSTOM_COMMAND_PREFIX),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to view currency history",
+            );
+        }
+
+        let Ok(Some(target_pc_id)) = parse_pc_id(tokens[1]) else {
+            return send_system_message(client, "Invalid player ID");
+        };
+        let Ok(target) = state.get_player(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+
+        // system messages are capped at 512 characters, so only the newest few fit
+        const MAX_SHOWN: usize = 8;
+        let now = SystemTime::now();
+        let mut msg = format!("Recent currency changes for {}:", target);
+        let mut num_shown = 0;
+        for event in target.get_currency_log().get_events().rev().take(MAX_SHOWN) {
+            let currency = match event.currency {
+                RewardType::Taros => "taros",
+                RewardType::FusionMatter => "FM",
+            };
+            let secs_ago = now.duration_since(event.time).unwrap_or_default().as_secs();
+            msg.push_str(&format!(
+                "\n{:+} {} ({:?}) = {}, {}s ago",
+                event.delta, currency, event.source, event.balance, secs_ago
+            ));
+            num_shown += 1;
+        }
+        if num_shown == 0 {
+            msg.push_str("\nNone");
+        }
+        send_system_message(client, &msg)
+    }
+
+    fn cmd_transactions(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const DEFAULT_SHOWN: i32 = 5;
+        const MAX_SHOWN: i32 = 20;
+
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to view transaction history",
+            );
+        }
+        if !transaction_log_enabled() {
+            return send_system_message(client, "Transaction logging is disabled");
+        }
+
+        let usage = format!(
+            "Usage: {}transactions <pc_id | uid:pc_uid> [count]",
+            CUSTOM_COMMAND_PREFIX
+        );
+        if tokens.len() < 2 {
+            return send_system_message(client, &usage);
+        }
+        let target_uid = match parse_player_target(tokens[1], pc_id) {
+            Ok(PlayerSearchQuery::ByUID(pc_uid)) => pc_uid,
+            Ok(query) => match query.execute(state) {
+                Some(target_pc_id) => state.get_player(target_pc_id)?.get_uid(),
+                None => return send_system_message(client, "Player not found"),
+            },
+            Err(()) => return send_system_message(client, &usage),
+        };
+        let count = match tokens.get(2).map(|t| t.parse::<i32>()) {
+            None => DEFAULT_SHOWN,
+            Some(Ok(count)) if count > 0 && count <= MAX_SHOWN => count,
+            Some(_) => {
+                return send_system_message(
+                    client,
+                    &format!("Count must be between 1 and {}", MAX_SHOWN),
+                )
+            }
+        };
+
+        // get anything still queued into the DB first so it shows up
+        transaction_log_flush()?;
+        let transactions = match db_run_sync(move |db| db.load_transactions(target_uid, count)) {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                return send_system_message(
+                    client,
+                    &format!("Failed to load transactions: {}", e.get_msg()),
+                )
+            }
+        };
+        if transactions.is_empty() {
+            return send_system_message(
+                client,
+                &format!("No transactions found for uid:{}", target_uid),
+            );
+        }
+        for transaction in transactions.iter().rev() {
+            send_announcement(client, &transaction.describe(target_uid));
+        }
+        Ok(())
+    }
+
+    fn cmd_finditem(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 4 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}finditem <target> <item_type> <item_id>\n\
+                Target is . for yourself, a pc_id, or uid:<pc_uid>\n\
+                Offline players are searched as of their last save",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to search players");
+        }
+
+        let Ok(query) = parse_player_target(tokens[1], pc_id) else {
+            return send_system_message(client, "Invalid player");
+        };
+        let Some((item_type, item_id)) = parse_item_type_and_id(tokens[2], tokens[3]) else {
+            return send_system_message(client, "Invalid item type or ID");
+        };
+
+        let find_counts = |target: &Player| -> FFResult<Vec<(ItemLocation, usize, u16)>> {
+            target
+                .find_items_any(|item| item.ty == item_type && item.id == item_id)
+                .into_iter()
+                .map(|(location, slot_num)| {
+                    let quantity = target
+                        .get_item(location, slot_num)?
+                        .map_or(0, |item| item.quantity);
+                    Ok((location, slot_num, quantity))
+                })
+                .collect()
+        };
+        let (target_name, found) = match query.execute(state) {
+            Some(target_pc_id) => {
+                let target = state.get_player(target_pc_id)?;
+                (target.to_string(), find_counts(target)?)
+            }
+            None => {
+                let PlayerSearchQuery::ByUID(target_uid) = query else {
+                    return send_system_message(client, "Player not found");
+                };
+                let loaded = db_run_sync(move |db| {
+                    let account = db.find_account_from_player(target_uid)?;
+                    db.load_player(account.id, target_uid)
+                });
+                let Ok(target) = loaded else {
+                    return send_system_message(
+                        client,
+                        &format!("Player with UID {} not found", target_uid),
+                    );
+                };
+                (format!("{} (offline)", target), find_counts(&target)?)
+            }
+        };
+
+        let total: usize = found.iter().map(|(_, _, count)| *count as usize).sum();
+        send_announcement(
+            client,
+            &format!(
+                "{} has {} of item {:?} #{}",
+                target_name, total, item_type, item_id
+            ),
+        );
+        // announcements are capped at 512 characters, so long lists are split up
+        const SLOTS_PER_MESSAGE: usize = 20;
+        for location in [ItemLocation::Equip, ItemLocation::Inven, ItemLocation::Bank] {
+            let slots: Vec<String> = found
+                .iter()
+                .filter(|(loc, _, _)| *loc == location)
+                .map(|(_, slot_num, count)| format!("slot {} x{}", slot_num, count))
+                .collect();
+            for chunk in slots.chunks(SLOTS_PER_MESSAGE) {
+                send_announcement(client, &format!("{:?}: {}", location, chunk.join(", ")));
+            }
+        }
+        Ok(())
+    }
+
+    fn cmd_provenance(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 4 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}provenance <target> <equip|inven|bank> <slot>\n\
+                Target is . for yourself or a pc_id",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to inspect items");
+        }
+
+        let Ok(query) = parse_player_target(tokens[1], pc_id) else {
+            return send_system_message(client, "Invalid player");
+        };
+        let location = match tokens[2] {
+            "equip" => ItemLocation::Equip,
+            "inven" => ItemLocation::Inven,
+            "bank" => ItemLocation::Bank,
+            _ => return send_system_message(client, "Invalid location"),
+        };
+        let Ok(slot_num) = tokens[3].parse::<usize>() else {
+            return send_system_message(client, "Invalid slot");
+        };
+        let Some(target_pc_id) = query.execute(state) else {
+            return send_system_message(client, "Player not found");
+        };
+
+        let target = state.get_player(target_pc_id)?;
+        let Ok(item) = target.get_item(location, slot_num) else {
+            return send_system_message(client, "Invalid slot");
+        };
+        let Some(item) = item else {
+            return send_system_message(
+                client,
+                &format!("{} has nothing in {:?} slot {}", target, location, slot_num),
+            );
+        };
+        let msg = match item.get_provenance() {
+            Some(provenance) => format!("{:?} #{}: {}", item.ty, item.id, provenance),
+            None => format!("{:?} #{} has no recorded provenance", item.ty, item.id),
+        };
+        send_announcement(client, &msg);
+        Ok(())
+    }
+
+    fn cmd_confiscate(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 5 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}confiscate <target> <item_type> <item_id> <count>\n\
+                Target is . for yourself, a pc_id, name:<first>:<last>, or uid:<pc_uid>\n\
+                Offline players can only be edited while they're offline everywhere",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to confiscate items");
+        }
+        let gm_name = player.to_string();
+        let gm_uid = player.get_uid();
+
+        let Ok(query) = parse_player_target(tokens[1], pc_id) else {
+            return send_system_message(client, "Invalid player");
+        };
+        let Some((item_type, item_id)) = parse_item_type_and_id(tokens[2], tokens[3]) else {
+            return send_system_message(client, "Invalid item type or ID");
+        };
+        let Ok(count) = tokens[4].parse::<usize>() else {
+            return send_system_message(client, "Invalid count");
+        };
+        if count == 0 {
+            return send_system_message(client, "Count must be non-zero");
+        }
+
+        let Some(target_pc_id) = query.execute(state) else {
+            let PlayerSearchQuery::ByUID(target_uid) = query else {
+                return send_system_message(client, "Player not found");
+            };
+            // the login server makes sure they aren't on anywhere before touching the database
+            let pkt = sP_FE2LS_REQ_CONFISCATE_ITEM {
+                iGM_PC_UID: gm_uid,
+                iTargetPC_UID: target_uid,
+                iItemType: item_type as i16,
+                iItemID: item_id,
+                iCount: count.min(i32::MAX as usize) as i32,
+            };
+            let Some(login_server) = clients.get_login_server() else {
+                return send_system_message(
+                    clients.get_self(),
+                    "Offline players can't be edited right now. Try again later",
+                );
+            };
+            log_if_failed(login_server.send_packet(P_FE2LS_REQ_CONFISCATE_ITEM, &pkt));
+            return send_system_message(clients.get_self(), "Confiscation requested");
+        };
+
+        let target = state.get_player_mut(target_pc_id)?;
+        let (removed, changed) =
+            target.remove_items_any(|item| item.ty == item_type && item.id == item_id, count)?;
+        if let Some(target_client) = target.get_client(clients) {
+            for (location, slot_num, item) in &changed {
+                if item.is_some() {
+                    let pkt = sP_FE2CL_REP_PC_GIVE_ITEM_SUCC {
+                        eIL: *location as i32,
+                        iSlotNum: *slot_num as i32,
+                        Item: (*item).into(),
+                    };
+                    log_if_failed(target_client.send_packet(P_FE2CL_REP_PC_GIVE_ITEM_SUCC, &pkt));
+                } else {
+                    let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
+                        eIL: *location as i32,
+                        iSlotNum: *slot_num as i32,
+                    };
+                    log_if_failed(target_client.send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt));
+                }
+            }
+        }
+
+        let msg = format!(
+            "Confiscated {} of item {:?} #{} from {}",
+            removed, item_type, item_id, target
+        );
+        log(
+            Severity::Info,
+            &format!("{}\nConfiscated by: {}", msg, gm_name),
+        );
+        if removed > 0 {
+            state.save_players_now(&[target_pc_id], "confiscation save");
+            if changed
+                .iter()
+                .any(|(location, _, _)| *location == ItemLocation::Equip)
+            {
+                log_if_failed(broadcast_appearance(target_pc_id, clients, state));
+            }
+        }
+        send_system_message(clients.get_self(), &msg)
+    }
+
+    fn cmd_summondrop(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to override summon drops",
+            );
+        }
+
+        if tokens.len() < 2 {
+            player.summon_drop_id = None;
+            return send_system_message(client, "Summoned NPCs will use their default drops");
+        }
+
+        let Ok(mob_drop_id) = tokens[1].parse::<i32>() else {
+            return send_system_message(client, "Invalid mob drop ID");
+        };
+        if let Err(e) = tdata_get().validate_mob_drop_id(mob_drop_id) {
+            return send_system_message(client, e.get_msg());
+        }
+        player.summon_drop_id = Some(mob_drop_id);
+        send_system_message(
+            client,
+            &format!(
+                "Summoned NPCs will use mob drop {}\nUse {}summondrop to reset",
+                mob_drop_id, CUSTOM_COMMAND_PREFIX
+            ),
+        )
+    }
+
+    fn cmd_summonboss(
+        _tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to summon bosses");
+        }
+
+        player.summon_as_boss = !player.summon_as_boss;
+        let msg = if player.summon_as_boss {
+            "Summoned NPCs will be world bosses"
+        } else {
+            "Summoned NPCs will be regular NPCs"
+        };
+        send_system_message(client, msg)
+    }
+
+    fn cmd_shinyreward(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to override shiny rewards",
+            );
+        }
+
+        if tokens.len() < 3 {
+            player.summon_shiny_reward = None;
+            return send_system_message(
+                client,
+                &format!(
+                    "Summoned shinies will use their default C.R.A.T.E.s\nUsage: {}shinyreward <crate|reward> <id>",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let reward = match (tokens[1], tokens[2].parse::<i32>()) {
+            ("crate", Ok(crate_id)) => {
+                let crate_id = crate_id as i16;
+                if let Err(e) = tdata_get().get_item_stats(crate_id, ItemType::Chest) {
+                    return send_system_message(client, e.get_msg());
+                }
+                ShinyReward::Crate(crate_id)
+            }
+            ("reward", Ok(reward_id)) => {
+                if let Err(e) = tdata_get().get_mission_reward(reward_id) {
+                    return send_system_message(client, e.get_msg());
+                }
+                ShinyReward::Reward(reward_id)
+            }
+            _ => return send_system_message(client, "Invalid shiny reward"),
+        };
+        player.summon_shiny_reward = Some(reward);
+        send_system_message(
+            client,
+            &format!(
+                "Summoned shinies will give {:?}\nUse {}shinyreward to reset",
+                reward, CUSTOM_COMMAND_PREFIX
+            ),
+        )
+    }
+
+    fn cmd_shinylifetime(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to override shiny lifetimes",
+            );
+        }
+
+        if tokens.len() < 2 {
+            player.summon_shiny_lifetime = None;
+            return send_system_message(
+                client,
+                &format!(
+                    "Summoned shinies will last {} seconds",
+                    config_get().shard.shiny_lifetime.get()
+                ),
+            );
+        }
+
+        let Ok(lifetime) = tokens[1].parse::<u64>() else {
+            return send_system_message(client, "Invalid lifetime");
+        };
+        player.summon_shiny_lifetime = Some(lifetime);
+        send_system_message(
+            client,
+            &format!(
+                "Summoned shinies will last {} seconds\nUse {}shinylifetime to reset",
+                lifetime, CUSTOM_COMMAND_PREFIX
+            ),
+        )
+    }
+
+    fn cmd_shinyscatter(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const MAX_SCATTER_COUNT: usize = 50;
+
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to summon shinies");
+        }
+
+        if tokens.len() < 4 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}shinyscatter <shiny type> <count> <radius>",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let Ok(egg_type) = tokens[1].parse::<i32>() else {
+            return send_system_message(client, "Invalid shiny type");
+        };
+        if let Err(e) = tdata_get().get_egg_stats(egg_type) {
+            return send_system_message(client, e.get_msg());
+        }
+        let count = match tokens[2].parse::<usize>() {
+            Ok(count) if count > 0 && count <= MAX_SCATTER_COUNT => count,
+            _ => {
+                return send_system_message(
+                    client,
+                    &format!("Count must be between 1 and {}", MAX_SCATTER_COUNT),
+                )
+            }
+        };
+        let Ok(radius) = tokens[3].parse::<u32>() else {
+            return send_system_message(client, "Invalid radius");
+        };
+
+        let summon = EggSummon {
+            ty: egg_type,
+            instance_id: player.instance_id,
+            lifetime: Duration::from_secs(
+                player
+                    .summon_shiny_lifetime
+                    .unwrap_or(config_get().shard.shiny_lifetime.get()),
+            ),
+            reward: player.summon_shiny_reward,
+        };
+        let center = player.get_position();
+        let egg_ids = state.scatter_eggs(&summon, center, radius, count, clients);
+        send_system_message(
+            clients.get_self(),
+            &format!("Scattered {} shinies", egg_ids.len()),
+        )
+    }
+
+    fn cmd_pathrec(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const USAGE: &str = "start | stop | preview <npc type> [cycle] | export <name> [cycle]";
+
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to record paths");
+        }
+
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!("Usage: {}pathrec {}", CUSTOM_COMMAND_PREFIX, USAGE),
+            );
+        }
+
+        let cycle = tokens.get(3).is_some_and(|arg| *arg == "cycle");
+        match tokens[1] {
+            "start" => {
+                let mut recorder = PathRecorder::new();
+                recorder.sample(player.get_position(), SystemTime::now());
+                player.path_recorder = Some(recorder);
+                send_system_message(
+                    client,
+                    &format!(
+                        "Recording path (max {} points)\nUse {}pathrec stop when done",
+                        PATH_RECORD_MAX_POINTS, CUSTOM_COMMAND_PREFIX
+                    ),
+                )
+            }
+            "stop" => {
+                let Some(recorder) = player.path_recorder.as_mut() else {
+                    return send_system_message(client, "You aren't recording a path");
+                };
+                recorder.stop();
+                let msg = format!(
+                    "Recorded {} points at base speed {}",
+                    recorder.get_points().len(),
+                    recorder.get_base_speed()
+                );
+                send_system_message(client, &msg)
+            }
+            "preview" => {
+                let Some(recorder) = player.path_recorder.as_ref() else {
+                    return send_system_message(client, "You haven't recorded a path");
+                };
+                if recorder.get_points().len() < 2 {
+                    return send_system_message(client, "Path needs at least two points");
+                }
+                let Some(Ok(npc_type)) = tokens.get(2).map(|arg| arg.parse::<i32>()) else {
+                    return send_system_message(client, "Invalid NPC type");
+                };
+                if tdata_get().get_npc_stats(npc_type).is_err() {
+                    return send_system_message(client, "Invalid NPC type");
+                }
+
+                let mut path = recorder.to_path(cycle);
+                path.start();
+                let spawn_pos = path.get_points()[0].pos;
+                let spawn_angle = player.get_rotation();
+                let instance_id = player.instance_id;
+
+                let npc_id = state.entity_map.gen_next_npc_id();
+                let mut npc = NPC::new(npc_id, npc_type, spawn_pos, spawn_angle, instance_id)?;
+                npc.set_path(path);
+                if let Err(e) = state.summon_npc(npc, clients) {
+                    return send_system_message(clients.get_self(), e.get_msg());
+                }
+                send_system_message(
+                    clients.get_self(),
+                    &format!("Spawned NPC {} on the recorded path", npc_id),
+                )
+            }
+            "export" => {
+                let Some(recorder) = player.path_recorder.as_ref() else {
+                    return send_system_message(client, "You haven't recorded a path");
+                };
+                if recorder.get_points().len() < 2 {
+                    return send_system_message(client, "Path needs at least two points");
+                }
+                let Some(name) = tokens.get(2) else {
+                    return send_system_message(client, "Missing path name");
+                };
+                // this becomes a file name, so keep it tame
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    return send_system_message(
+                        client,
+                        "Path names can only have letters, numbers, '_' and '-'",
+                    );
+                }
+
+                let entry = recorder.to_json(&[], cycle);
+                let out = serde_json::json!({ "npc": { *name: entry } });
+                let dir = std::path::Path::new(&config_get().general.table_data_path.get())
+                    .join("recorded_paths");
+                let file_path = dir.join(format!("{}.json", name));
+                let res = std::fs::create_dir_all(&dir).and_then(|_| {
+                    std::fs::write(&file_path, serde_json::to_string_pretty(&out).unwrap())
+                });
+                if let Err(e) = res {
+                    log(
+                        Severity::Warning,
+                        &format!("Couldn't export path to {}: {}", file_path.display(), e),
+                    );
+                    return send_system_message(client, "Failed to export path");
+                }
+
+                log(
+                    Severity::Info,
+                    &format!("{} exported a path to {}", player, file_path.display()),
+                );
+                send_system_message(client, &format!("Exported path to {}", file_path.display()))
+            }
+            _ => send_system_message(
+                client,
+                &format!("Usage: {}pathrec {}", CUSTOM_COMMAND_PREFIX, USAGE),
+            ),
+        }
+    }
+
+    // Players can only change themselves, using up the configured item.
+    // GMs can change anyone for free. Returns the item to use up, if any
+    fn check_change_allowed(
+        pc_id: i32,
+        target_pc_id: i32,
+        item_id: i16,
+        state: &ShardServerState,
+    ) -> Result<Option<i16>, &'static str> {
+        let player = state.get_player(pc_id).map_err(|_| "Player not found")?;
+        if player.perms <= CN_ACCOUNT_LEVEL__GM as i16 {
+            return Ok(None);
+        }
+
+        if target_pc_id != pc_id {
+            return Err("You can only change yourself");
+        }
+        if item_id == 0 {
+            return Err("Only GMs can do that on this server");
+        }
+        let has_item = !player
+            .find_items(ItemLocation::Inven, |item| {
+                item.ty == ItemType::General && item.id == item_id
+            })
+            .is_empty();
+        if !has_item {
+            return Err("You don't have the item needed for that");
+        }
+        Ok(Some(item_id))
+    }
+
+    fn cmd_rename(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 4 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}rename <pc_id> <first_name> <last_name>\n\
+                Use . for pc_id to select yourself",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let target_pc_id = match parse_pc_id(tokens[1]) {
+            Ok(Some(pc_id)) => pc_id,
+            Ok(None) => pc_id,
+            Err(_) => return send_system_message(client, "Invalid player ID"),
+        };
+        let (first_name, last_name) = (tokens[2], tokens[3]);
+        if first_name.chars().count() > 8 || last_name.chars().count() > 16 {
+            return send_system_message(
+                client,
+                "Names are limited to 8 characters (first) and 16 characters (last)",
+            );
+        }
+
+        let item_id = config_get().shard.name_change_item_id.get();
+        let item_id = match check_change_allowed(pc_id, target_pc_id, item_id, state) {
+            Ok(item_id) => item_id,
+            Err(msg) => return send_system_message(client, msg),
+        };
+
+        let Ok(target_player) = state.get_player_mut(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+        if target_player.name_change_pending {
+            return send_system_message(
+                client,
+                &format!("{} already has a name change in progress", target_player),
+            );
+        }
+
+        // the login server checks the name the same way it does for new characters
+        let pkt = sP_FE2LS_REQ_CHANGE_NAME {
+            iPC_UID: target_player.get_uid(),
+            iItemID: item_id.unwrap_or(0),
+            szFirstName: util::encode_utf16(first_name),
+            szLastName: util::encode_utf16(last_name),
+        };
+        let Some(login_server) = clients.get_login_server() else {
+            return send_system_message(
+                clients.get_self(),
+                "Names can't be changed right now. Try again later",
+            );
+        };
+        log_if_failed(login_server.send_packet(P_FE2LS_REQ_CHANGE_NAME, &pkt));
+        target_player.name_change_pending = true;
+        send_system_message(clients.get_self(), "Name change requested")
+    }
+
+    fn cmd_restyle(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 10 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}restyle <pc_id> <gender> <face> <hair_style> <hair_color> <skin_color> <eye_color> <height> <body>\n\
+                Use . for pc_id to select yourself",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let target_pc_id = match parse_pc_id(tokens[1]) {
+            Ok(Some(pc_id)) => pc_id,
+            Ok(None) => pc_id,
+            Err(_) => return send_system_message(client, "Invalid player ID"),
+        };
+        let Ok(values) = tokens[2..10]
+            .iter()
+            .map(|token| token.parse::<i8>())
+            .collect::<Result<Vec<i8>, _>>()
+        else {
+            return send_system_message(client, "Invalid appearance values");
+        };
+        let style = PlayerStyle {
+            gender: values[0],
+            face_style: values[1],
+            hair_style: values[2],
+            hair_color: values[3],
+            skin_color: values[4],
+            eye_color: values[5],
+            height: values[6],
+            body: values[7],
+        };
+        if let Err(e) = style.validate() {
+            return send_system_message(client, e.get_msg());
+        }
+
+        let item_id = config_get().shard.style_change_item_id.get();
+        let item_id = match check_change_allowed(pc_id, target_pc_id, item_id, state) {
+            Ok(item_id) => item_id,
+            Err(msg) => return send_system_message(client, msg),
+        };
+
+        let Ok(target_player) = state.get_player_mut(target_pc_id) else {
+            return send_system_message(client, &format!("Player {} not found", target_pc_id));
+        };
+        let old_style = target_player.style.replace(style);
+        let player_saved = target_player.clone();
+        if let Err(e) = db_run_sync(move |db| db.update_player_appearance(&player_saved)) {
+            target_player.style = old_style;
+            return send_system_message(
+                client,
+                &format!("Failed to save appearance: {}", e.get_msg()),
+            );
+        }
+        log(
+            Severity::Info,
+            &format!("{} changed appearance: {:?}", target_player, style),
+        );
+
+        if let Some(item_id) = item_id {
+            log_if_failed(consume_item(target_player, item_id, clients));
+        }
+        broadcast_appearance(target_pc_id, clients, state)?;
+        send_system_message(clients.get_self(), "Appearance changed")
+    }
+
+    fn cmd_rewardrate(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}rewardrate <target> [<taros|fm> <category> <percent> | reset] [\"quiet\"]\n\
+                Target is . for yourself, a pc_id, name:<first>:<last>, or uid:<pc_uid>\n\
+                Category is combat, missions, eggs, racing, or all\n\
+                Rates last until the player logs out. Add \"quiet\" to not tell them",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let pc_id = client.get_player_id()?;
+        let Ok(query) = parse_player_target(tokens[1], pc_id) else {
+            return send_system_message(client, "Invalid player");
+        };
+        let Some(target_pc_id) = query.execute(state) else {
+            return send_system_message(client, "Player not found on this shard");
+        };
+
+        let mut args = &tokens[2..];
+        let quiet = args.last() == Some(&"quiet");
+        if quiet {
+            args = &args[..args.len() - 1];
+        }
+        let change = match args {
+            [] => None,
+            ["reset"] => Some(RewardRateChange::Reset),
+            [reward_type, category, percent] => {
+                let reward_type = match *reward_type {
+                    "taros" => RewardType::Taros,
+                    "fm" => RewardType::FusionMatter,
+                    _ => return send_system_message(client, "Reward type must be taros or fm"),
+                };
+                let category = match *category {
+                    "combat" => RewardCategory::Combat,
+                    "missions" => RewardCategory::Missions,
+                    "eggs" => RewardCategory::Eggs,
+                    "racing" => RewardCategory::Racing,
+                    "all" => RewardCategory::All,
+                    _ => return send_system_message(client, "Invalid reward category"),
+                };
+                let Some(percent) = percent
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|percent| percent.is_finite() && *percent >= 0.0)
+                else {
+                    return send_system_message(client, "Invalid percent");
+                };
+                Some(RewardRateChange::Set {
+                    reward_type,
+                    category,
+                    percent,
+                })
+            }
+            _ => {
+                return send_system_message(
+                    client,
+                    &format!(
+                        "Invalid arguments. Use {}rewardrate for usage",
+                        CUSTOM_COMMAND_PREFIX
+                    ),
+                )
+            }
+        };
+
+        let resp = match change_reward_rates(state, pc_id, target_pc_id, change) {
+            Ok(resp) => resp,
+            Err(e) => {
+                log_if_failed(send_system_message(
+                    client,
+                    &format!("Couldn't change reward rates: {}", e.get_msg()),
+                ));
+                return Err(e);
+            }
+        };
+
+        let target = state.get_player(target_pc_id)?;
+        let to_percents = |rates: [f32; 5]| {
+            rates[1..]
+                .iter()
+                .map(|rate| format!("{}%", rate * 100.0))
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        let (taro_rates, fm_rates) = (resp.afRewardRate_Taros, resp.afRewardRate_FusionMatter);
+        let msg = format!(
+            "Reward rates for {} (combat/missions/eggs/racing)\nTaros: {}\nFM: {}",
+            target,
+            to_percents(taro_rates),
+            to_percents(fm_rates),
+        );
+
+        if change.is_some() && target_pc_id != pc_id && !quiet {
+            if let Some(target_client) = target.get_client(clients) {
+                log_if_failed(target_client.send_packet(P_FE2CL_GM_REP_REWARD_RATE_SUCC, &resp));
+                log_if_failed(send_system_message(
+                    target_client,
+                    "A GM changed your reward rates",
+                ));
+            }
+        }
+        let client = clients.get_self();
+        client.send_packet(P_FE2CL_GM_REP_REWARD_RATE_SUCC, &resp)?;
+        send_system_message(client, &msg)
+    }
+
+    fn cmd_event(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to change the event");
+        }
+        let gm = player.to_string();
+
+        if tokens.len() < 2 {
+            if let Some(event_id) = state.active_event_id.take() {
+                log(Severity::Info, &format!("{} ended event {}", gm, event_id));
+            }
+            return send_system_message(client, "No event is active");
+        }
+
+        let Ok(event_id) = tokens[1].parse::<i32>() else {
+            return send_system_message(client, "Invalid event ID");
+        };
+        if let Err(e) = tdata_get().validate_event_id(event_id) {
+            return send_system_message(client, e.get_msg());
+        }
+        log(
+            Severity::Info,
+            &format!("{} started event {}", gm, event_id),
+        );
+        state.active_event_id = Some(event_id);
+        send_system_message(
+            client,
+            &format!(
+                "Mobs will drop crates for event {}\nUse {}event to end it",
+                event_id, CUSTOM_COMMAND_PREFIX
+            ),
+        )
+    }
+
+    fn cmd_disembark(
+        _tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        match player.request_skyway_disembark() {
+            Ok(location_id) => send_system_message(
+                client,
+                &format!("You'll get off at station {}", location_id),
+            ),
+            Err(_) => send_system_message(client, "There's no earlier station to get off at"),
+        }
+    }
+
+    fn cmd_leaderboard(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const DEFAULT_SHOWN: i32 = 5;
+        const MAX_SHOWN: i32 = 20;
+
+        let client = clients.get_self();
+        let usage = format!(
+            "Usage: {}leaderboard kills [count] | race <EP ID> [count]",
+            CUSTOM_COMMAND_PREFIX
+        );
+        let (board, count_idx) = match tokens.get(1).copied() {
+            Some("kills") => (Leaderboard::Kills, 2),
+            Some("race") => {
+                let Some(Ok(ep_id)) = tokens.get(2).map(|t| t.parse::<i32>()) else {
+                    return send_system_message(client, &usage);
+                };
+                (Leaderboard::Race { ep_id }, 3)
+            }
+            _ => return send_system_message(client, &usage),
+        };
+        let count = match tokens.get(count_idx).map(|t| t.parse::<i32>()) {
+            None => DEFAULT_SHOWN,
+            Some(Ok(count)) if count > 0 && count <= MAX_SHOWN => count,
+            Some(_) => {
+                return send_system_message(
+                    client,
+                    &format!("Count must be between 1 and {}", MAX_SHOWN),
+                )
+            }
+        };
+
+        let pc_id = client.get_player_id()?;
+        let pc_uid = state.get_player(pc_id)?.get_uid();
+        let loaded = db_run_sync(move |db| {
+            let entries = db.load_leaderboard(board, count)?;
+            let personal_best = db.load_personal_best(board, pc_uid)?;
+            Ok((entries, personal_best))
+        });
+        match loaded {
+            Ok((entries, personal_best)) => send_system_message(
+                client,
+                &format_leaderboard(board, &entries, personal_best).join("\n"),
+            ),
+            Err(e) => send_system_message(
+                client,
+                &format!("Failed to load leaderboard: {}", e.get_msg()),
+            ),
+        }
+    }
+
+    fn cmd_redeem(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 2 {
+            return send_system_message(
+                client,
+                &format!("Usage: {}redeem <code>", CUSTOM_COMMAND_PREFIX),
+            );
+        }
+
+        let code = tokens[1].to_uppercase();
+        let pc_id = client.get_player_id()?;
+        redeem_code(
+            pc_id,
+            &code,
+            |code| {
+                let code = code.to_string();
+                db_run_sync(move |db| db.find_redeem_code(&code))
+            },
+            |pc_uid, code| {
+                let code = code.to_string();
+                db_run_sync(move |db| db.use_redeem_code(pc_uid, &code))
+            },
+            clients,
+            state,
+        )
+    }
+
+    fn cmd_addcode(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        if tokens.len() < 7 {
+            return send_system_message(
+                client,
+                &format!(
+                    "Usage: {}addcode <code> <max_uses> <duration> <once_per> <taros> <fm> [items...]\n\
+                Duration example: 1d3h5m42s (no spaces!) or \"never\"\n\
+                once_per: \"player\" or \"account\"\n\
+                Item format: <type>:<id>[:<quantity>]",
+                    CUSTOM_COMMAND_PREFIX
+                ),
+            );
+        }
+
+        let player = state.get_player(client.get_player_id()?)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to create codes");
+        }
+
+        let redeem_code = match RedeemCode::parse(&tokens[1..], SystemTime::now(), |id, ty| {
+            tdata_get()
+                .get_item_stats(id, ty)
+                .map(|stats| stats.max_stack_size)
+        }) {
+            Ok(redeem_code) => redeem_code,
+            Err(msg) => return send_system_message(client, &msg),
+        };
+
+        let code = redeem_code.code.clone();
+        match db_run_sync(move |db| db.create_redeem_code(&redeem_code)) {
+            Ok(()) => {
+                log(
+                    Severity::Info,
+                    &format!("Redeem code {} created by {}", code, player),
+                );
+                send_system_message(client, &format!("Code {} created", code))
+            }
+            Err(e) => {
+                send_system_message(client, &format!("Failed to create code: {}", e.get_msg()))
+            }
+        }
+    }
+
+    fn cmd_summons(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to manage summons");
+        }
+        let instance_id = player.instance_id;
+
+        let npc_ids = state.get_summoned_npc_ids(instance_id);
+        match tokens.get(1).copied() {
+            None => send_system_message(
+                client,
+                &format!("{} summoned NPCs in {}", npc_ids.len(), instance_id),
+            ),
+            Some("clear") => {
+                let mut num_cleared = 0;
+                for npc_id in npc_ids {
+                    match state.unsummon_npc(npc_id, clients) {
+                        Ok(()) => num_cleared += 1,
+                        Err(e) => log_error(&e),
+                    }
+                }
+                send_system_message(
+                    clients.get_self(),
+                    &format!("Unsummoned {} NPCs in {}", num_cleared, instance_id),
+                )
+            }
+            Some(_) => send_system_message(
+                client,
+                &format!("Usage: {}summons [clear]", CUSTOM_COMMAND_PREFIX),
+            ),
+        }
+    }
+
+    fn cmd_reports(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const REPORTS_PER_PAGE: i32 = 5;
+
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(client, "You do not have permission to view reports");
+        }
+        let pc_uid = player.get_uid();
+
+        let usage = format!(
+            "Usage: {}reports [page] | {}reports handle <report_id>",
+            CUSTOM_COMMAND_PREFIX, CUSTOM_COMMAND_PREFIX
+        );
+        if tokens.get(1) == Some(&"handle") {
+            let Some(Ok(report_id)) = tokens.get(2).map(|t| t.parse::<i64>()) else {
+                return send_system_message(client, &usage);
+            };
+            let time = SystemTime::now();
+            return match db_run_sync(move |db| db.resolve_report(report_id, pc_uid, time)) {
+                Ok(true) => {
+                    send_system_message(client, &format!("Marked report #{} handled", report_id))
+                }
+                Ok(false) => send_system_message(client, &format!("No open report #{}", report_id)),
+                Err(e) => send_system_message(
+                    client,
+                    &format!("Failed to update report: {}", e.get_msg()),
+                ),
+            };
+        }
+
+        let page = match tokens.get(1).map(|t| t.parse::<i32>()) {
+            None => 1,
+            Some(Ok(page)) if page > 0 => page,
+            Some(_) => return send_system_message(client, &usage),
+        };
+        // one extra to see if there's another page
+        let offset = (page - 1).saturating_mul(REPORTS_PER_PAGE);
+        let mut reports =
+            match db_run_sync(move |db| db.load_open_reports(offset, REPORTS_PER_PAGE + 1)) {
+                Ok(reports) => reports,
+                Err(e) => {
+                    return send_system_message(
+                        client,
+                        &format!("Failed to load reports: {}", e.get_msg()),
+                    )
+                }
+            };
+        if reports.is_empty() {
+            let msg = if page == 1 {
+                "No open reports".to_string()
+            } else {
+                format!("No open reports on page {}", page)
+            };
+            return send_system_message(client, &msg);
+        }
+
+        let more = reports.len() > REPORTS_PER_PAGE as usize;
+        reports.truncate(REPORTS_PER_PAGE as usize);
+        send_announcement(client, &format!("Open reports, page {}", page));
+        for report in &reports {
+            send_announcement(client, &report.describe());
+        }
+        if more {
+            send_announcement(
+                client,
+                &format!("More on {}reports {}", CUSTOM_COMMAND_PREFIX, page + 1),
+            );
+        }
+        Ok(())
+    }
+
+    fn cmd_population(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        const DEFAULT_SHOWN: usize = 5;
+        const MAX_SHOWN: usize = 20;
+
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player(pc_id)?;
+        if player.perms > CN_ACCOUNT_LEVEL__GM as i16 {
+            return send_system_message(
+                client,
+                "You do not have permission to view chunk populations",
+            );
+        }
+        let channel_num = player.instance_id.channel_num;
+
+        let usage = format!("Usage: {}population [count] [csv]", CUSTOM_COMMAND_PREFIX);
+        let mut count = DEFAULT_SHOWN;
+        let mut write_csv = false;
+        for token in tokens.iter().skip(1) {
+            match (*token, token.parse::<usize>()) {
+                ("csv", _) => write_csv = true,
+                (_, Ok(n)) if n > 0 && n <= MAX_SHOWN => count = n,
+                (_, Ok(_)) => {
+                    return send_system_message(
+                        client,
+                        &format!("Count must be between 1 and {}", MAX_SHOWN),
+                    )
+                }
+                _ => return send_system_message(client, &usage),
+            }
+        }
+
+        let report = state.entity_map.get_population_report(channel_num, count);
+        if report.is_empty() {
+            return send_system_message(client, "No populated chunks in this channel");
+        }
+        for (rank, (coords, population)) in report.iter().enumerate() {
+            send_announcement(
+                client,
+                &format!(
+                    "#{} {}: {} ({} players, {} NPCs, {} eggs, {} sliders)",
+                    rank + 1,
+                    coords,
+                    population.total(),
+                    population.players,
+                    population.npcs,
+                    population.eggs,
+                    population.sliders
+                ),
+            );
+        }
+
+        if !write_csv {
+            return Ok(());
+        }
+        // every populated chunk goes in the file so it can be rendered as a full heatmap
+        let mut csv =
+            "map_num,instance_num,chunk_x,chunk_y,players,npcs,eggs,sliders\n".to_string();
+        for (coords, population) in state
+            .entity_map
+            .get_population_report(channel_num, usize::MAX)
+        {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                coords.i.map_num,
+                coords
+                    .i
+                    .instance_num
+                    .map(|n| n.to_string())
+                    .unwrap_or_default(),
+                coords.x,
+                coords.y,
+                population.players,
+                population.npcs,
+                population.eggs,
+                population.sliders
+            ));
+        }
+        let log_path = config_get().shard.log_path.get();
+        let dir = std::path::Path::new(&log_path)
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_default();
+        let file_path = dir.join(format!(
+            "population_ch{}_{}.csv",
+            channel_num,
+            util::get_timestamp_sec(SystemTime::now())
+        ));
+        if let Err(e) = std::fs::write(&file_path, csv) {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Couldn't write population report to {}: {}",
+                    file_path.display(),
+                    e
+                ),
+            );
+            return send_system_message(client, "Failed to write population report");
+        }
+        send_system_message(
+            client,
+            &format!("Wrote population report to {}", file_path.display()),
+        )
+    }
+
+    fn cmd_group_warp_answer(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let accept = tokens[0] == "accept";
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let group_id = state.get_player(pc_id)?.group_id;
+        let warp = group_id
+            .and_then(|group_id| state.pending_group_warps.get_mut(&group_id))
+            .filter(|warp| warp.is_waiting_on(pc_id));
+        let Some(warp) = warp else {
+            return send_system_message(client, "Your group has no warp waiting on you");
+        };
+
+        warp.respond(pc_id, accept)?;
+        let msg = if accept {
+            "You'll come along when the group warps"
+        } else {
+            "You'll stay behind when the group warps"
+        };
+        send_system_message(client, msg)
+    }
+
+    fn cmd_sort(
+        _tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let player = state.get_player_mut(pc_id)?;
+        if player.trade_id.is_some() {
+            return send_system_message(client, "You can't sort your inventory while trading");
+        }
+        let changed = player.consolidate_inventory(|item| Ok(item.get_stats()?.max_stack_size))?;
+        if changed.is_empty() {
+            return send_system_message(client, "Your inventory is already sorted");
+        }
+
+        for slot_num in changed {
+            match *player.get_item(ItemLocation::Inven, slot_num)? {
+                Some(item) => {
+                    let pkt = sP_FE2CL_REP_PC_GIVE_ITEM_SUCC {
+                        eIL: ItemLocation::Inven as i32,
+                        iSlotNum: slot_num as i32,
+                        Item: Some(item).into(),
+                    };
+                    log_if_failed(client.send_packet(P_FE2CL_REP_PC_GIVE_ITEM_SUCC, &pkt));
+                }
+                None => {
+                    let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
+                        eIL: ItemLocation::Inven as i32,
+                        iSlotNum: slot_num as i32,
+                    };
+                    log_if_failed(client.send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt));
+                }
+            }
+        }
+        send_system_message(client, "Inventory sorted")
+    }
+
+    fn cmd_ignore(
+        tokens: Vec<&str>,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) -> FFResult<()> {
+        let client = clients.get_self();
+        let pc_id = client.get_player_id()?;
+        let ignoring = tokens[0] == "ignore";
+        let Some(target) = tokens.get(1) else {
+            if !ignoring {
+                return send_system_message(
+                    client,
+                    &format!(
+                        "Usage: {}unignore <target>\n\
+                        Target is a pc_id, name:<first>:<last>, or uid:<pc_uid>",
+                        CUSTOM_COMMAND_PREFIX
+                    ),
+                );
+            }
+
+            let player = state.get_player(pc_id)?;
+            let mut ignored: Vec<String> = player
+                .get_ignored_uids()
+                .map(|uid| match PlayerSearchQuery::ByUID(uid).execute(state) {
+                    Some(ignored_pc_id) => {
+                        format!("{} (uid:{})", state.get_player(ignored_pc_id).unwrap(), uid)
+                    }
+                    None => format!("uid:{}", uid),
+                })
+                .collect();
+            if ignored.is_empty() {
+                return send_system_message(
+                    client,
+                    &format!(
+                        "You aren't ignoring anyone. Use {}ignore <target> to ignore a player",
+                        CUSTOM_COMMAND_PREFIX
+                    ),
+                );
+            }
+            ignored.sort();
+            return send_system_message(
+                client,
+                &format!("Ignoring {}:\n{}", ignored.len(), ignored.join("\n")),
+            );
+        };
+
+        // ignored players don't need to be around to be let back in
+        let target_uid = match parse_player_target(target, pc_id) {
+            Ok(PlayerSearchQuery::ByUID(uid)) if !ignoring => uid,
+            Ok(query) => match query.execute(state) {
+                Some(target_pc_id) => state.get_player(target_pc_id)?.get_uid(),
+                None => return send_system_message(client, "Player not found on this shard"),
+            },
+            Err(()) => return send_system_message(client, "Invalid player"),
+        };
+
+        let player = state.get_player_mut(pc_id)?;
+        let player_uid = player.get_uid();
+        let changed = if ignoring {
+            if target_uid == player_uid {
+                return send_system_message(client, "You can't ignore yourself");
+            }
+            if player.get_ignored_uids().count() >= MAX_IGNORED_PLAYERS {
+                return send_system_message(
+                    client,
+                    &format!("You can't ignore more than {} players", MAX_IGNORED_PLAYERS),
+                );
+            }
+            player.ignore_player(target_uid)?
+        } else {
+            player.unignore_player(target_uid)
+        };
+        if !changed {
+            let msg = if ignoring {
+                "You're already ignoring that player"
+            } else {
+                "You aren't ignoring that player"
+            };
+            return send_system_message(client, msg);
+        }
+
+        let saved = db_run_sync(move |db| {
+            if ignoring {
+                db.add_ignore(player_uid, target_uid)
+            } else {
+                db.remove_ignore(player_uid, target_uid)
+            }
+        });
+        if let Err(e) = saved {
+            // keep it in line with what's saved
+            if ignoring {
+                player.unignore_player(target_uid);
+            } else {
+                log_if_failed(player.ignore_player(target_uid).map(|_| ()));
+            }
+            return send_system_message(
+                client,
+                &format!("Failed to save your ignore list: {}", e.get_msg()),
+            );
+        }
+
+        let msg = if ignoring {
+            "You won't see that player's chat, trade offers, or group invites anymore"
+        } else {
+            "You're no longer ignoring that player"
+        };
+        send_system_message(client, msg)
+    }
+
     fn cmd_help(
         _tokens: Vec<&str>,
         clients: &mut ClientMap,
@@ -1,5 +1,8 @@
 use rusty_fusion::{
-    entity::{Combatant, Entity},
+    config::config_get,
+    defines::*,
+    entity::{Combatant, Entity, EntityID},
+    enums::{CharType, CurrencySource, ItemLocation},
     error::*,
     net::{
         packet::{PacketID::*, *},
@@ -7,8 +10,11 @@ use rusty_fusion::{
     },
     skills,
     state::ShardServerState,
+    tabledata::tdata_get,
 };
 
+const MAX_TARGETS: usize = 3;
+
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
 #[repr(packed(4))]
@@ -19,10 +25,18 @@ struct sTargetNpcId {
 }
 impl FFPacket for sTargetNpcId {}
 
-pub fn pc_attack_npcs(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
-    const MAX_TARGETS: usize = 3;
-    const BATTERY_BASE_COST: u32 = 6;
+#[allow(non_camel_case_types)]
+#[allow(non_snake_case)]
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct sTargetCharId {
+    pub iID: i32,
+    pub eCT: i32,
+}
+impl FFPacket for sTargetCharId {}
 
+pub fn pc_attack_npcs(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
     let pc_id = client.get_player_id()?;
     let pkt: sP_CL2FE_REQ_PC_ATTACK_NPCs = *client.get_packet(P_CL2FE_REQ_PC_ATTACK_NPCs)?;
@@ -30,31 +44,203 @@ pub fn pc_attack_npcs(clients: &mut ClientMap, state: &mut ShardServerState) ->
     if target_count == 0 {
         return Ok(());
     }
+    if target_count > MAX_TARGETS {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "Player {} tried to attack {} NPCs (max {})",
+                pc_id, pkt.iNPCCnt, MAX_TARGETS
+            ),
+        ));
+    }
+
+    let mut requested_ids = Vec::with_capacity(target_count);
+    for _ in 0..target_count {
+        let npc_id = client.get_struct::<sTargetNpcId>()?.iNPC_ID;
+        requested_ids.push(EntityID::NPC(npc_id));
+    }
+
+    do_pc_attack(pc_id, requested_ids, true, clients, state)?;
+    Ok(())
+}
+
+pub fn pc_attack_chars(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
+    let client = clients.get_self();
+    let pc_id = client.get_player_id()?;
+    let pkt: sP_CL2FE_REQ_PC_ATTACK_CHARs = *client.get_packet(P_CL2FE_REQ_PC_ATTACK_CHARs)?;
+    let target_count = pkt.iTargetCnt as usize;
+    if target_count == 0 {
+        return Ok(());
+    }
+    if target_count > MAX_TARGETS {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "Player {} tried to attack {} characters (max {})",
+                pc_id, pkt.iTargetCnt, MAX_TARGETS
+            ),
+        ));
+    }
+
+    let mut requested_ids = Vec::with_capacity(target_count);
+    for _ in 0..target_count {
+        let target = *client.get_struct::<sTargetCharId>()?;
+        let char_type: CharType = target.eCT.try_into()?;
+        let target_id = match char_type {
+            CharType::Player => EntityID::Player(target.iID),
+            CharType::NPC | CharType::Mob => EntityID::NPC(target.iID),
+            CharType::Unknown => {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Player {} tried to attack character {} of unknown type",
+                        pc_id, target.iID
+                    ),
+                );
+                continue;
+            }
+        };
+        requested_ids.push(target_id);
+    }
+
+    // no projectile delay here, so the defeat penalty below sees the outcome
+    let target_ids = do_pc_attack(pc_id, requested_ids, false, clients, state)?;
+    for target_id in target_ids {
+        if let EntityID::Player(target_pc_id) = target_id {
+            apply_pvp_defeat_penalty(pc_id, target_pc_id, clients, state);
+        }
+    }
+    Ok(())
+}
+
+// Validates the requested targets against the attacker's weapon and performs the attack.
+// With use_projectile, damage lands after the weapon's projectile travel time.
+// Returns the targets that were actually attacked.
+fn do_pc_attack(
+    pc_id: i32,
+    requested_ids: Vec<EntityID>,
+    use_projectile: bool,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<Vec<EntityID>> {
+    const BATTERY_BASE_COST: u32 = 6;
+    // extra distance allowed on top of weapon range to account for movement latency
+    const RANGE_LEEWAY: u32 = 200;
+
+    let target_count = requested_ids.len();
+    if state.get_player(pc_id)?.is_dead() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Player {} tried to attack while dead", pc_id),
+        ));
+    }
+    if !state.get_player(pc_id)?.can_act() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Player {} tried to attack while incapacitated", pc_id),
+        ));
+    }
+    state.get_player_mut(pc_id)?.clear_avatar_state();
+    let player = state.get_player(pc_id)?;
+    let player_pos = player.get_position();
+    let weapon_stats = match player.get_item(ItemLocation::Equip, EQUIP_SLOT_HAND as usize)? {
+        Some(weapon) => Some(weapon.get_stats()?),
+        None => None,
+    };
+    // unarmed attacks only ever hit one target
+    let max_targets = weapon_stats
+        .and_then(|stats| stats.target_number)
+        .unwrap_or(1);
+    let attack_range = weapon_stats.and_then(|stats| stats.attack_range);
+    let effect_area = weapon_stats.and_then(|stats| stats.effect_area);
+    let projectile_time = weapon_stats.and_then(|stats| stats.projectile_time);
 
-    let mut target_ids = Vec::with_capacity(MAX_TARGETS);
+    let mut target_ids = Vec::with_capacity(max_targets);
+    let mut primary_pos = None;
     let mut weapon_boosts_needed = 0;
-    for i in 0..target_count {
-        // TODO stricter anti-cheat.
-        // validate target count, range, attack cooldown, etc against weapon stats
-        if i >= MAX_TARGETS {
-            return Err(FFError::build(
+    let mut max_target_radius = 0;
+    for target_id in requested_ids {
+        if target_ids.len() >= max_targets {
+            log(
                 Severity::Warning,
-                format!(
-                    "Player {} tried to attack {} NPCs (max {})",
-                    pc_id, pkt.iNPCCnt, MAX_TARGETS
+                &format!(
+                    "Player {} tried to attack {} targets with a weapon that hits {}",
+                    pc_id, target_count, max_targets
                 ),
-            ));
+            );
+            break;
         }
-        let npc_id = client.get_struct::<sTargetNpcId>()?.iNPC_ID;
-        let npc = match state.get_npc(npc_id) {
-            Ok(npc) => npc,
-            Err(e) => {
-                log_error(&e);
-                continue;
+
+        let (target_instance_id, target_radius) = match target_id {
+            EntityID::NPC(npc_id) => match state.get_npc(npc_id) {
+                Ok(npc) => {
+                    let npc_radius = tdata_get()
+                        .get_npc_stats(npc.ty)
+                        .map(|stats| stats.radius)
+                        .unwrap_or(0);
+                    (npc.instance_id, npc_radius)
+                }
+                Err(e) => {
+                    log_error(&e);
+                    continue;
+                }
+            },
+            EntityID::Player(target_pc_id) => {
+                if let Err(e) = state.validate_pvp(pc_id, target_pc_id) {
+                    log_error(&e);
+                    continue;
+                }
+                let target = state.get_player(target_pc_id).unwrap();
+                (target.instance_id, 0)
             }
+            _ => unreachable!(),
         };
-        weapon_boosts_needed += BATTERY_BASE_COST + npc.get_level() as u32;
-        target_ids.push(npc.get_id());
+        if target_instance_id != player.instance_id {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Player {} tried to attack {:?} in another instance",
+                    pc_id, target_id
+                ),
+            );
+            continue;
+        }
+
+        let target = state.get_combatant(target_id).unwrap();
+        let target_pos = target.get_position();
+        if let Some(range) = attack_range {
+            let dist = player_pos.distance_to(&target_pos);
+            if dist > range + target_radius + RANGE_LEEWAY {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Player {} tried to attack {:?} out of range ({} > {})",
+                        pc_id, target_id, dist, range
+                    ),
+                );
+                continue;
+            }
+        }
+
+        // secondary targets have to be caught in the splash around the first one
+        if let (Some(area), Some(primary_pos)) = (effect_area, primary_pos) {
+            let dist = target_pos.distance_to(&primary_pos);
+            if dist > area + target_radius + RANGE_LEEWAY {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Player {} tried to hit {:?} outside the effect area ({} > {})",
+                        pc_id, target_id, dist, area
+                    ),
+                );
+                continue;
+            }
+        }
+
+        primary_pos.get_or_insert(target_pos);
+        max_target_radius = max_target_radius.max(target_radius);
+        weapon_boosts_needed += BATTERY_BASE_COST + target.get_level() as u32;
+        target_ids.push(target_id);
     }
 
     // consume weapon boosts
@@ -68,8 +254,60 @@ pub fn pc_attack_npcs(clients: &mut ClientMap, state: &mut ShardServerState) ->
         false
     };
 
+    let projectile = projectile_time
+        .filter(|_| use_projectile)
+        .map(|travel_time| skills::Projectile {
+            travel_time,
+            max_range: attack_range.map(|range| range + max_target_radius + RANGE_LEEWAY),
+        });
+
     // attack handler
-    skills::do_basic_attack(player.get_id(), &target_ids, charged, state, clients)?;
+    skills::do_basic_attack(
+        player.get_id(),
+        &target_ids,
+        charged,
+        projectile,
+        state,
+        clients,
+    )?;
 
-    Ok(())
+    Ok(target_ids)
+}
+
+fn apply_pvp_defeat_penalty(
+    pc_id: i32,
+    target_pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) {
+    let Ok(target) = state.get_player_mut(target_pc_id) else {
+        return;
+    };
+    if !target.is_dead() {
+        return;
+    }
+
+    let penalty = config_get().shard.pvp_defeat_taro_penalty.get();
+    let taros = target.get_taros();
+    let taros_lost = (taros as f32 * penalty.clamp(0.0, 1.0)) as u32;
+    log(
+        Severity::Info,
+        &format!(
+            "{} was defeated by player {} and lost {} taros",
+            target, pc_id, taros_lost
+        ),
+    );
+    if taros_lost == 0 {
+        return;
+    }
+
+    let new_taros = target.add_taros(-(taros_lost as i64), CurrencySource::PvP);
+    if let Some(client) = target.get_client(clients) {
+        let pkt = sP_FE2CL_GM_REP_PC_SET_VALUE {
+            iPC_ID: target_pc_id,
+            iSetValue: new_taros as i32,
+            iSetValueType: CN_GM_SET_VALUE_TYPE__CANDY as i32,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_GM_REP_PC_SET_VALUE, &pkt));
+    }
 }
@@ -4,27 +4,30 @@ use std::{
 };
 
 use rusty_fusion::{
-    chunk::{EntityMap, InstanceID, TickMode},
+    chunk::InstanceID,
+    config::config_get,
     defines::*,
-    entity::{Combatant, Egg, Entity, EntityID, PlayerSearchQuery, NPC},
+    entity::{Combatant, EggSummon, Entity, EntityID, PlayerSearchQuery, NPC},
     enums::*,
     error::*,
-    item::Item,
+    item::{Item, ItemOrigin},
     net::{
         packet::{PacketID::*, *},
         ClientMap, FFClient,
     },
+    perms::check_gm_perms,
     placeholder,
-    state::ShardServerState,
+    state::{ShardServerState, SpawnCaps},
     tabledata::tdata_get,
-    unused, util, Position,
+    unused, util,
+    webhook::{webhook_queue, WebhookEventKind},
+    Position,
 };
 
 pub fn gm_pc_set_value(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-
-    let pkt: sP_CL2FE_GM_REQ_PC_SET_VALUE = *client.get_packet(P_CL2FE_GM_REQ_PC_SET_VALUE)?;
+    let (_, pkt): (_, sP_CL2FE_GM_REQ_PC_SET_VALUE) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_PC_SET_VALUE)?;
     let pc_id = pkt.iPC_ID;
     let value = pkt.iSetValue;
     let value_type = pkt.iSetValueType;
@@ -35,9 +38,13 @@ pub fn gm_pc_set_value(clients: &mut ClientMap, state: &mut ShardServerState) ->
         CN_GM_SET_VALUE_TYPE__WEAPON_BATTERY => player.set_weapon_boosts(value as u32) as i32,
         CN_GM_SET_VALUE_TYPE__NANO_BATTERY => player.set_nano_potions(value as u32) as i32,
         CN_GM_SET_VALUE_TYPE__FUSION_MATTER => {
-            player.set_fusion_matter(value as u32, Some(clients)) as i32
+            let delta = value as i64 - player.get_fusion_matter() as i64;
+            player.add_fusion_matter(delta, CurrencySource::GM, Some(clients)) as i32
+        }
+        CN_GM_SET_VALUE_TYPE__CANDY => {
+            let delta = value as i64 - player.get_taros() as i64;
+            player.add_taros(delta, CurrencySource::GM) as i32
         }
-        CN_GM_SET_VALUE_TYPE__CANDY => player.set_taros(value as u32) as i32,
         CN_GM_SET_VALUE_TYPE__SPEED => placeholder!(value),
         CN_GM_SET_VALUE_TYPE__JUMP => placeholder!(value),
         _ => {
@@ -61,11 +68,15 @@ pub fn gm_pc_set_value(clients: &mut ClientMap, state: &mut ShardServerState) ->
 pub fn gm_pc_give_item(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
     catch_fail(
         (|| {
-            let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-            let pkt: &sP_CL2FE_REQ_PC_GIVE_ITEM = client.get_packet(P_CL2FE_REQ_PC_GIVE_ITEM)?;
+            let (pc_id, pkt): (_, sP_CL2FE_REQ_PC_GIVE_ITEM) =
+                helpers::require_perms(client, state, P_CL2FE_REQ_PC_GIVE_ITEM)?;
             let player = state.get_player_mut(pc_id)?;
 
-            let mut item: Option<Item> = pkt.Item.try_into()?;
+            let item: Option<Item> = pkt.Item.try_into()?;
+            let origin = ItemOrigin::GM {
+                actor: player.get_uid(),
+            };
+            let mut item = item.map(|item| item.with_origin(origin, SystemTime::now()));
             let time = pkt.iTimeLeft as u32;
             if time > 0 && item.is_some() {
                 let duration = Duration::from_secs(time as u64);
@@ -104,12 +115,11 @@ pub fn gm_pc_give_item(client: &mut FFClient, state: &mut ShardServerState) -> F
 }
 
 pub fn gm_pc_give_nano(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
-    let pkt: sP_CL2FE_REQ_PC_GIVE_NANO =
-        *clients.get_self().get_packet(P_CL2FE_REQ_PC_GIVE_NANO)?;
     catch_fail(
         (|| {
             let client = clients.get_self();
-            let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
+            let (pc_id, pkt): (_, sP_CL2FE_REQ_PC_GIVE_NANO) =
+                helpers::require_perms(client, state, P_CL2FE_REQ_PC_GIVE_NANO)?;
             let nano_id = pkt.iNanoID;
             let player = state.get_player_mut(pc_id)?;
             let new_level = max(player.get_level(), nano_id);
@@ -156,13 +166,14 @@ pub fn gm_pc_give_nano(clients: &mut ClientMap, state: &mut ShardServerState) ->
 
 pub fn gm_pc_goto(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-    let pkt: &sP_CL2FE_REQ_PC_GOTO = client.get_packet(P_CL2FE_REQ_PC_GOTO)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_PC_GOTO) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_PC_GOTO)?;
     let new_pos = Position {
         x: pkt.iToX,
         y: pkt.iToY,
         z: pkt.iToZ,
     };
+    rusty_fusion::helpers::force_dismount(pc_id, clients, state)?;
     let player = state.get_player_mut(pc_id)?;
     player.set_position(new_pos);
     player.instance_id = InstanceID::default();
@@ -193,9 +204,8 @@ pub fn gm_pc_special_state_switch(
     state: &mut ShardServerState,
 ) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__GM as i16)?;
-    let pkt: &sP_CL2FE_GM_REQ_PC_SPECIAL_STATE_SWITCH =
-        client.get_packet(P_CL2FE_GM_REQ_PC_SPECIAL_STATE_SWITCH)?;
+    let (pc_id, pkt): (_, sP_CL2FE_GM_REQ_PC_SPECIAL_STATE_SWITCH) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_PC_SPECIAL_STATE_SWITCH)?;
 
     let player = state.get_player_mut(pc_id)?;
 
@@ -239,9 +249,8 @@ pub fn gm_pc_special_state_switch(
 
 pub fn gm_pc_motd_register(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: &sP_CL2FE_GM_REQ_PC_MOTD_REGISTER =
-        client.get_packet(P_CL2FE_GM_REQ_PC_MOTD_REGISTER)?;
+    let (_, pkt): (_, sP_CL2FE_GM_REQ_PC_MOTD_REGISTER) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_PC_MOTD_REGISTER)?;
     let pkt = sP_FE2LS_MOTD_REGISTER {
         szMessage: pkt.szSystemMsg,
     };
@@ -254,15 +263,24 @@ pub fn gm_pc_motd_register(clients: &mut ClientMap, state: &mut ShardServerState
 
 pub fn gm_pc_announce(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: &sP_CL2FE_GM_REQ_PC_ANNOUNCE = client.get_packet(P_CL2FE_GM_REQ_PC_ANNOUNCE)?;
+    let (pc_id, pkt): (_, sP_CL2FE_GM_REQ_PC_ANNOUNCE) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_PC_ANNOUNCE)?;
     let area_type: AreaType = pkt.iAreaType.try_into()?;
+    webhook_queue(
+        WebhookEventKind::Announce,
+        &format!(
+            "{} ({:?}): {}",
+            state.get_player(pc_id)?,
+            area_type,
+            util::parse_utf16(&pkt.szAnnounceMsg)?
+        ),
+    );
     let pkt = sP_FE2CL_ANNOUNCE_MSG {
         iAnnounceType: pkt.iAnnounceType,
         iDuringTime: pkt.iDuringTime,
         szAnnounceMsg: pkt.szAnnounceMsg,
     };
-    let player = state.get_player(pc_id).unwrap();
+    let channel_num = state.get_player(pc_id).unwrap().instance_id.channel_num;
     match area_type {
         AreaType::Local => {
             state
@@ -271,24 +289,12 @@ pub fn gm_pc_announce(clients: &mut ClientMap, state: &mut ShardServerState) ->
                     c.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt)
                 });
         }
-        AreaType::Channel => state
-            .entity_map
-            .find_players(|p| p.instance_id.channel_num == player.instance_id.channel_num)
-            .iter()
-            .for_each(|pc_id| {
-                let player = state.get_player(*pc_id).unwrap();
-                let client = player.get_client(clients).unwrap();
-                log_if_failed(client.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt));
-            }),
-        AreaType::Shard => state
-            .entity_map
-            .find_players(|_| true)
-            .iter()
-            .for_each(|pc_id| {
-                let player = state.get_player(*pc_id).unwrap();
-                let client = player.get_client(clients).unwrap();
-                log_if_failed(client.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt));
-            }),
+        AreaType::Channel => clients
+            .get_gameclients_on_channel(channel_num, &state.entity_map)
+            .for_each(|c| log_if_failed(c.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt))),
+        AreaType::Shard => clients
+            .get_all_gameclient()
+            .for_each(|c| log_if_failed(c.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt))),
         AreaType::Global => {
             if let Some(login_server) = clients.get_login_server() {
                 log_if_failed(login_server.send_packet(P_FE2LS_ANNOUNCE_MSG, &pkt));
@@ -300,8 +306,8 @@ pub fn gm_pc_announce(clients: &mut ClientMap, state: &mut ShardServerState) ->
 
 pub fn gm_pc_location(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let gm_pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: sP_CL2FE_GM_REQ_PC_LOCATION = *client.get_packet(P_CL2FE_GM_REQ_PC_LOCATION)?;
+    let (gm_pc_id, pkt): (_, sP_CL2FE_GM_REQ_PC_LOCATION) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_PC_LOCATION)?;
     let search_mode: TargetSearchBy = pkt.eTargetSearchBy.try_into()?;
     let search_query = match search_mode {
         TargetSearchBy::PlayerID => PlayerSearchQuery::ByID(pkt.iTargetPC_ID),
@@ -331,9 +337,13 @@ pub fn gm_pc_location(clients: &mut ClientMap, state: &mut ShardServerState) ->
             szTargetPC_FirstName: util::encode_utf16(&player.first_name),
             szTargetPC_LastName: util::encode_utf16(&player.last_name),
         };
-        clients
-            .get_self()
-            .send_packet(P_FE2CL_GM_REP_PC_LOCATION, &resp)
+        let target_name = player.to_string();
+        let (avg_rtt, max_rtt) = player
+            .get_client(clients)
+            .map_or((-1, -1), |c| c.get_latency().get_millis());
+        let client = clients.get_self();
+        client.send_packet(P_FE2CL_GM_REP_PC_LOCATION, &resp)?;
+        rusty_fusion::helpers::send_latency_info(client, &target_name, avg_rtt, max_rtt)
     } else if search_mode != TargetSearchBy::PlayerID && clients.get_login_server().is_some() {
         // for name or UID search, we can ask the login server,
         // which will ask all the other shards
@@ -354,9 +364,8 @@ pub fn gm_target_pc_special_state_onoff(
     state: &mut ShardServerState,
 ) -> FFResult<()> {
     let client = clients.get_self();
-    helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: sP_CL2FE_GM_REQ_TARGET_PC_SPECIAL_STATE_ONOFF =
-        *client.get_packet(P_CL2FE_GM_REQ_TARGET_PC_SPECIAL_STATE_ONOFF)?;
+    let (_, pkt): (_, sP_CL2FE_GM_REQ_TARGET_PC_SPECIAL_STATE_ONOFF) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_TARGET_PC_SPECIAL_STATE_ONOFF)?;
 
     let search_mode: TargetSearchBy = pkt.eTargetSearchBy.try_into()?;
     let search_query = match search_mode {
@@ -411,9 +420,8 @@ pub fn gm_target_pc_teleport(
     state: &mut ShardServerState,
 ) -> FFResult<()> {
     let client = clients.get_self();
-    let gm_pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: sP_CL2FE_GM_REQ_TARGET_PC_TELEPORT =
-        *client.get_packet(P_CL2FE_GM_REQ_TARGET_PC_TELEPORT)?;
+    let (gm_pc_id, pkt): (_, sP_CL2FE_GM_REQ_TARGET_PC_TELEPORT) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_TARGET_PC_TELEPORT)?;
 
     // the "target PC" is the player being teleported
     let search_mode: TargetSearchBy = pkt.eTargetPCSearchBy.try_into()?;
@@ -482,6 +490,7 @@ pub fn gm_target_pc_teleport(
         ),
     };
 
+    rusty_fusion::helpers::force_dismount(target_pc_id, clients, state)?;
     let player = state.get_player_mut(target_pc_id).unwrap();
     player.set_pre_warp();
     player.set_position(dest_pos);
@@ -508,8 +517,8 @@ pub fn gm_target_pc_teleport(
 
 pub fn gm_kick_player(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__CS as i16)?;
-    let pkt: sP_CL2FE_GM_REQ_KICK_PLAYER = *client.get_packet(P_CL2FE_GM_REQ_KICK_PLAYER)?;
+    let (gm_pc_id, pkt): (_, sP_CL2FE_GM_REQ_KICK_PLAYER) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_KICK_PLAYER)?;
     let search_mode: TargetSearchBy = pkt.eTargetSearchBy.try_into()?;
     let search_query = match search_mode {
         TargetSearchBy::PlayerID => PlayerSearchQuery::ByID(pkt.iTargetPC_ID),
@@ -533,35 +542,38 @@ pub fn gm_kick_player(clients: &mut ClientMap, state: &mut ShardServerState) ->
     };
     log_if_failed(client.send_packet(P_FE2CL_REP_PC_EXIT_SUCC, &pkt));
     client.disconnect();
+
+    let kick_msg = format!(
+        "{} kicked by {}",
+        state.get_player(pc_id).unwrap(),
+        state.get_player(gm_pc_id).unwrap()
+    );
+    log(Severity::Info, &kick_msg);
+    webhook_queue(WebhookEventKind::Kick, &kick_msg);
     Ok(())
 }
 
 pub fn gm_reward_rate(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-    let pkt: &sP_CL2FE_GM_REQ_REWARD_RATE = client.get_packet(P_CL2FE_GM_REQ_REWARD_RATE)?;
-    let player = state.get_player_mut(pc_id)?;
-
-    if pkt.iGetSet != 0 {
-        let reward_type: RewardType = pkt.iRewardType.try_into()?;
-        let rate_percent = pkt.iSetRateValue as f32;
-        let category: RewardCategory = (pkt.iRewardRateIndex as usize).try_into()?;
-        player
-            .reward_data
-            .set_reward_rate(reward_type, category, rate_percent);
-    }
-
-    let resp = sP_FE2CL_GM_REP_REWARD_RATE_SUCC {
-        afRewardRate_Taros: player.reward_data.get_rates_as_array(RewardType::Taros),
-        afRewardRate_FusionMatter: player
-            .reward_data
-            .get_rates_as_array(RewardType::FusionMatter),
+    let (pc_id, pkt): (_, sP_CL2FE_GM_REQ_REWARD_RATE) =
+        helpers::require_perms(client, state, P_CL2FE_GM_REQ_REWARD_RATE)?;
+
+    // the client only ever asks about its own rates; see the rewardrate command for others
+    let change = if pkt.iGetSet != 0 {
+        Some(rusty_fusion::helpers::RewardRateChange::Set {
+            reward_type: pkt.iRewardType.try_into()?,
+            category: (pkt.iRewardRateIndex as usize).try_into()?,
+            percent: pkt.iSetRateValue as f32,
+        })
+    } else {
+        None
     };
+    let resp = rusty_fusion::helpers::change_reward_rates(state, pc_id, pc_id, change)?;
     client.send_packet(P_FE2CL_GM_REP_REWARD_RATE_SUCC, &resp)
 }
 
 pub fn gm_pc_task_complete(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-    let pkt: &sP_CL2FE_REQ_PC_TASK_COMPLETE = client.get_packet(P_CL2FE_REQ_PC_TASK_COMPLETE)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_PC_TASK_COMPLETE) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_PC_TASK_COMPLETE)?;
     let player = state.get_player_mut(pc_id)?;
     let task_id = pkt.iTaskNum;
     player.mission_journal.complete_task(task_id)?;
@@ -570,12 +582,11 @@ pub fn gm_pc_task_complete(client: &mut FFClient, state: &mut ShardServerState)
 }
 
 pub fn gm_pc_mission_complete(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__DEVELOPER as i16)?;
-    let pkt: &sP_CL2FE_REQ_PC_MISSION_COMPLETE =
-        client.get_packet(P_CL2FE_REQ_PC_MISSION_COMPLETE)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_PC_MISSION_COMPLETE) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_PC_MISSION_COMPLETE)?;
     let player = state.get_player_mut(pc_id)?;
     let mission_id = pkt.iMissionNum;
-    player.mission_journal.set_mission_completed(mission_id)?;
+    player.set_mission_completed(mission_id)?;
     let resp = sP_FE2CL_REP_PC_MISSION_COMPLETE_SUCC {
         iMissionNum: mission_id,
     };
@@ -584,8 +595,8 @@ pub fn gm_pc_mission_complete(client: &mut FFClient, state: &mut ShardServerStat
 
 pub fn gm_shiny_summon(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__GM as i16)?;
-    let pkt: sP_CL2FE_REQ_SHINY_SUMMON = *client.get_packet(P_CL2FE_REQ_SHINY_SUMMON)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_SHINY_SUMMON) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_SHINY_SUMMON)?;
     let player = state.get_player(pc_id)?;
 
     let egg_type = pkt.iShinyType;
@@ -595,21 +606,24 @@ pub fn gm_shiny_summon(clients: &mut ClientMap, state: &mut ShardServerState) ->
         y: pkt.iY,
         z: pkt.iZ,
     };
-    let egg_instance_id = player.instance_id;
-
-    let entity_map = &mut state.entity_map;
-    let egg_id = entity_map.gen_next_egg_id();
-    let egg = Egg::new(egg_id, egg_type, egg_pos, egg_instance_id, true);
-    let chunk_coords = egg.get_chunk_coords();
-    let eid = entity_map.track(Box::new(egg), TickMode::Always);
-    entity_map.update(eid, Some(chunk_coords), Some(clients));
+    let summon = EggSummon {
+        ty: egg_type,
+        instance_id: player.instance_id,
+        lifetime: Duration::from_secs(
+            player
+                .summon_shiny_lifetime
+                .unwrap_or(config_get().shard.shiny_lifetime.get()),
+        ),
+        reward: player.summon_shiny_reward,
+    };
+    state.summon_egg(&summon, egg_pos, clients);
     Ok(())
 }
 
 pub fn gm_npc_summon(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__GM as i16)?;
-    let pkt: sP_CL2FE_REQ_NPC_SUMMON = *client.get_packet(P_CL2FE_REQ_NPC_SUMMON)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_NPC_SUMMON) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_NPC_SUMMON)?;
     let player = state.get_player(pc_id)?;
 
     let npc_type = pkt.iNPCType;
@@ -618,13 +632,26 @@ pub fn gm_npc_summon(clients: &mut ClientMap, state: &mut ShardServerState) -> F
     let spawn_pos = player.get_position();
     let spawn_angle = player.get_rotation();
     let spawn_instance_id = player.instance_id;
+    let mob_drop_id = player.summon_drop_id;
+    let is_boss = player.summon_as_boss;
 
-    let entity_map = &mut state.entity_map;
     let count = pkt.iNPCCnt as usize;
+    if let Err(e) = state.check_npc_summon(spawn_instance_id, count, SpawnCaps::from_config()) {
+        return Err(helpers::send_summon_fail(clients.get_self(), e));
+    }
     for _ in 0..count {
-        let npc_id = entity_map.gen_next_npc_id();
-        let npc = NPC::new(npc_id, npc_type, spawn_pos, spawn_angle, spawn_instance_id).unwrap();
-        helpers::spawn_temp_npc(clients, entity_map, npc);
+        let npc_id = state.entity_map.gen_next_npc_id();
+        let mut npc =
+            NPC::new(npc_id, npc_type, spawn_pos, spawn_angle, spawn_instance_id).unwrap();
+        if mob_drop_id.is_some() {
+            npc.mob_drop_id = mob_drop_id;
+        }
+        npc.is_boss = is_boss;
+        state.summon_npc(npc, clients)?;
+    }
+
+    if is_boss && count > 0 {
+        rusty_fusion::helpers::send_shard_announcement(clients, "A world boss has appeared!");
     }
 
     Ok(())
@@ -632,22 +659,29 @@ pub fn gm_npc_summon(clients: &mut ClientMap, state: &mut ShardServerState) -> F
 
 pub fn gm_npc_group_summon(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    let pc_id = helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__GM as i16)?;
-    let pkt: sP_CL2FE_REQ_NPC_GROUP_SUMMON = *client.get_packet(P_CL2FE_REQ_NPC_GROUP_SUMMON)?;
+    let (pc_id, pkt): (_, sP_CL2FE_REQ_NPC_GROUP_SUMMON) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_NPC_GROUP_SUMMON)?;
     let player = state.get_player(pc_id)?;
 
     let spawn_pos = player.get_position();
     let spawn_angle = player.get_rotation();
     let spawn_instance_id = player.instance_id;
+    let mob_drop_id = player.summon_drop_id;
 
     let group_id = pkt.iNPCGroupType;
-    let entity_map = &mut state.entity_map;
-    let npcs = tdata_get().make_group_npcs(entity_map, unused!(), group_id);
+    let npcs = tdata_get().make_group_npcs(&mut state.entity_map, unused!(), group_id);
+    if let Err(e) = state.check_npc_summon(spawn_instance_id, npcs.len(), SpawnCaps::from_config())
+    {
+        return Err(helpers::send_summon_fail(clients.get_self(), e));
+    }
     for mut npc in npcs {
         npc.set_position(spawn_pos);
         npc.set_rotation(spawn_angle);
         npc.instance_id = spawn_instance_id;
-        helpers::spawn_temp_npc(clients, entity_map, npc);
+        if mob_drop_id.is_some() {
+            npc.mob_drop_id = mob_drop_id;
+        }
+        state.summon_npc(npc, clients)?;
     }
 
     Ok(())
@@ -655,40 +689,46 @@ pub fn gm_npc_group_summon(clients: &mut ClientMap, state: &mut ShardServerState
 
 pub fn gm_npc_unsummon(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     let client = clients.get_self();
-    helpers::validate_perms(client, state, CN_ACCOUNT_LEVEL__GM as i16)?;
-    let pkt: sP_CL2FE_REQ_NPC_UNSUMMON = *client.get_packet(P_CL2FE_REQ_NPC_UNSUMMON)?;
+    let (_, pkt): (_, sP_CL2FE_REQ_NPC_UNSUMMON) =
+        helpers::require_perms(client, state, P_CL2FE_REQ_NPC_UNSUMMON)?;
     let npc_id = pkt.iNPC_ID;
     let npc = state.get_npc(npc_id)?;
     if !npc.summoned {
         return Ok(());
     }
 
-    helpers::remove_temp_npc(clients, state, npc_id);
-    Ok(())
+    state.unsummon_npc(npc_id, clients)
 }
 
 mod helpers {
-    use rusty_fusion::ai::AI;
-
     use super::*;
 
-    pub fn validate_perms(
+    // Checks the sender against the GM permission table, then fetches the packet
+    // and leaves an audit trail of who used it and with what parameters
+    pub fn require_perms<T: FFPacket + Copy>(
         client: &mut FFClient,
         state: &ShardServerState,
-        req_perms: i16,
-    ) -> FFResult<i32> {
+        pkt_id: PacketID,
+    ) -> FFResult<(i32, T)> {
         let user_pc_id = client.get_player_id()?;
         let player = state.get_player(user_pc_id)?;
-        if player.perms > req_perms {
+        if let Err(e) = check_gm_perms(player.perms, pkt_id) {
             return Err(FFError::build(
                 Severity::Warning,
                 format!(
                     "{} tried to use cheats without sufficient perms: {}",
-                    player, player.perms
+                    player,
+                    e.get_msg()
                 ),
             ));
         }
-        Ok(user_pc_id)
+
+        let pkt: T = *client.get_packet(pkt_id)?;
+        log(
+            Severity::Info,
+            &format!("[GM] {} used {:?}: {:?}", player, pkt_id, pkt),
+        );
+        Ok((user_pc_id, pkt))
     }
 
     pub fn send_search_fail(client: &mut FFClient, query: PlayerSearchQuery) -> FFError {
@@ -702,20 +742,13 @@ mod helpers {
         FFError::build(Severity::Warning, err_msg)
     }
 
-    pub fn spawn_temp_npc(clients: &mut ClientMap, entity_map: &mut EntityMap, mut npc: NPC) {
-        npc.summoned = true;
-        let (ai, tick_mode) = AI::make_for_npc(&npc, true);
-        npc.ai = ai;
-        let chunk_coords = npc.get_chunk_coords();
-        let eid = entity_map.track(Box::new(npc), tick_mode);
-        entity_map.update(eid, Some(chunk_coords), Some(clients));
-    }
-
-    pub fn remove_temp_npc(clients: &mut ClientMap, state: &mut ShardServerState, npc_id: i32) {
-        let entity_map = &mut state.entity_map;
-        let eid = EntityID::NPC(npc_id);
-        entity_map.update(eid, None, Some(clients));
-        let mut npc = entity_map.untrack(eid);
-        npc.cleanup(clients, state)
+    pub fn send_summon_fail(client: &mut FFClient, e: FFError) -> FFError {
+        let pkt = sP_FE2CL_ANNOUNCE_MSG {
+            iAnnounceType: unused!(),
+            iDuringTime: MSG_BOX_DURATION_DEFAULT,
+            szAnnounceMsg: util::encode_utf16(e.get_msg()),
+        };
+        log_if_failed(client.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt));
+        e
     }
 }
@@ -1,6 +1,11 @@
+use std::time::SystemTime;
+
 use rusty_fusion::{
+    ai::AI,
+    chunk::TickMode,
     entity::{Entity, EntityID, Group},
     error::*,
+    helpers::refuse_group_invite_if_ignored,
     net::{
         packet::{PacketID::*, *},
         ClientMap,
@@ -17,8 +22,9 @@ pub fn pc_group_invite(clients: &mut ClientMap, state: &mut ShardServerState) ->
         (|| {
             let client = clients.get_self();
             let pc_id = client.get_player_id()?;
-            let player = state.get_player_mut(pc_id)?;
-            if player.group_offered_to.is_some() {
+            let now = SystemTime::now();
+            let player = state.get_player(pc_id)?;
+            if player.group_offered_to.is_pending(now) {
                 return Err(FFError::build(
                     Severity::Debug,
                     format!("{} is already offering a group invite", player),
@@ -26,7 +32,10 @@ pub fn pc_group_invite(clients: &mut ClientMap, state: &mut ShardServerState) ->
             }
 
             let target_pc_id = pkt.iID_To;
-            player.group_offered_to = Some(target_pc_id);
+            if refuse_group_invite_if_ignored(pc_id, target_pc_id, clients, state)? {
+                return Ok(());
+            }
+
             let target_player = state.get_player(target_pc_id)?;
             if target_player.group_id.is_some() {
                 return Err(FFError::build(
@@ -38,6 +47,9 @@ pub fn pc_group_invite(clients: &mut ClientMap, state: &mut ShardServerState) ->
             let target_client = target_player.get_client(clients).unwrap();
             let pkt = sP_FE2CL_PC_GROUP_INVITE { iHostID: pc_id };
             log_if_failed(target_client.send_packet(P_FE2CL_PC_GROUP_INVITE, &pkt));
+
+            let player = state.get_player_mut(pc_id)?;
+            player.group_offered_to.set(target_pc_id, now);
             Ok(())
         })(),
         || {
@@ -62,7 +74,10 @@ pub fn pc_group_invite_refuse(
 
     let host_pc_id = pkt.iID_From;
     let host_player = state.get_player_mut(host_pc_id)?;
-    if host_player.group_offered_to != Some(pc_id) {
+    if !host_player
+        .group_offered_to
+        .take_for(pc_id, SystemTime::now())
+    {
         return Err(FFError::build(
             Severity::Debug,
             format!("Group offer from {} expired", host_player),
@@ -72,7 +87,6 @@ pub fn pc_group_invite_refuse(
     let host_client = host_player.get_client(clients).unwrap();
     let pkt = sP_FE2CL_PC_GROUP_INVITE_REFUSE { iID_To: pc_id };
     log_if_failed(host_client.send_packet(P_FE2CL_PC_GROUP_INVITE_REFUSE, &pkt));
-    host_player.group_offered_to = None;
     Ok(())
 }
 
@@ -93,13 +107,15 @@ pub fn pc_group_join(clients: &mut ClientMap, state: &mut ShardServerState) -> F
 
             let host_pc_id = pkt.iID_From;
             let host_player = state.get_player_mut(host_pc_id)?;
-            if host_player.group_offered_to != Some(pc_id) {
+            if !host_player
+                .group_offered_to
+                .take_for(pc_id, SystemTime::now())
+            {
                 return Err(FFError::build(
                     Severity::Debug,
                     format!("Group offer from {} expired", host_player),
                 ));
             }
-            host_player.group_offered_to = None;
 
             let group_id = host_player.group_id.unwrap_or(Uuid::new_v4());
             let mut group = if host_player.group_id.is_some() {
@@ -232,8 +248,17 @@ pub fn npc_group_invite(clients: &mut ClientMap, state: &mut ShardServerState) -
 
     state.groups.insert(group_id, group);
     state.get_player_mut(pc_id).unwrap().group_id = Some(group_id);
-    state.get_npc_mut(target_npc_id).unwrap().group_id = Some(group_id);
-    Ok(())
+
+    // NPCs that normally stand still need an AI to follow the player around
+    let target_npc = state.get_npc_mut(target_npc_id).unwrap();
+    target_npc.join_group(group_id, pc_id);
+    if target_npc.ai.is_none() {
+        let (ai, _) = AI::make_for_npc(target_npc, true);
+        target_npc.ai = ai;
+    }
+    state
+        .entity_map
+        .set_tick(EntityID::NPC(target_npc_id), TickMode::Always)
 }
 
 pub fn npc_group_kick(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
@@ -250,7 +275,7 @@ pub fn npc_group_kick(clients: &mut ClientMap, state: &mut ShardServerState) ->
     })?;
 
     let target_npc_id = pkt.iNPC_ID;
-    let target_npc = state.get_npc_mut(target_npc_id)?;
+    let target_npc = state.get_npc(target_npc_id)?;
     if target_npc.group_id != Some(group_id) {
         return Err(FFError::build(
             Severity::Warning,
@@ -258,6 +283,5 @@ pub fn npc_group_kick(clients: &mut ClientMap, state: &mut ShardServerState) ->
         ));
     }
 
-    target_npc.group_id = None;
-    rusty_fusion::helpers::remove_group_member(target_npc.get_id(), group_id, state, clients)
+    rusty_fusion::helpers::kick_npc_from_group(target_npc_id, state, clients)
 }
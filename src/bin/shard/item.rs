@@ -3,21 +3,23 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use rand::random;
+use rand::Rng;
 
 use rusty_fusion::{
     config::config_get,
     defines::*,
-    entity::{Entity, EntityID},
+    entity::{Combatant, Entity, EntityID, ShinyReward},
     enums::*,
     error::*,
-    item::Item,
+    helpers::send_system_message,
+    item::{Item, ItemOrigin, ItemUseEffect, Reward},
     net::{
         packet::{PacketID::*, *},
-        ClientMap, FFClient,
+        ClientMap, FFClient, PacketBuffer,
     },
     state::ShardServerState,
     tabledata::tdata_get,
+    transaction::{is_loggable_vendor_deal, transaction_log_queue, Transaction},
     unused, util,
 };
 
@@ -29,14 +31,13 @@ pub fn item_move(clients: &mut ClientMap, state: &mut ShardServerState) -> FFRes
     let player = state.get_player_mut(pc_id)?;
 
     let location_from = pkt.eFrom.try_into()?;
-    let mut item_from = player.set_item(location_from, pkt.iFromSlotNum as usize, None)?;
-
     let location_to = pkt.eTo.try_into()?;
-    let mut item_to = player.set_item(location_to, pkt.iToSlotNum as usize, None)?;
-
-    Item::transfer_items(&mut item_from, &mut item_to)?;
-    player.set_item(location_from, pkt.iFromSlotNum as usize, item_from)?;
-    player.set_item(location_to, pkt.iToSlotNum as usize, item_to)?;
+    let (item_from, item_to) = player.move_item(
+        location_from,
+        pkt.iFromSlotNum as usize,
+        location_to,
+        pkt.iToSlotNum as usize,
+    )?;
 
     let resp = sP_FE2CL_PC_ITEM_MOVE_SUCC {
         eFrom: pkt.eFrom,
@@ -72,17 +73,10 @@ pub fn item_move(clients: &mut ClientMap, state: &mut ShardServerState) -> FFRes
     }
 
     // dismount vehicle
-    let player = state.get_player_mut(pc_id).unwrap();
-    if ((location_from == ItemLocation::Equip && pkt.iFromSlotNum == EQUIP_SLOT_VEHICLE as i32)
-        || (location_to == ItemLocation::Equip && pkt.iToSlotNum == EQUIP_SLOT_VEHICLE as i32))
-        && player.vehicle_speed.is_some()
+    if (location_from == ItemLocation::Equip && pkt.iFromSlotNum == EQUIP_SLOT_VEHICLE as i32)
+        || (location_to == ItemLocation::Equip && pkt.iToSlotNum == EQUIP_SLOT_VEHICLE as i32)
     {
-        player.vehicle_speed = None;
-        rusty_fusion::helpers::broadcast_state(pc_id, player.get_state_bit_flag(), clients, state);
-        let pkt = sP_FE2CL_PC_VEHICLE_OFF_SUCC { UNUSED: unused!() };
-        clients
-            .get_self()
-            .send_packet(P_FE2CL_PC_VEHICLE_OFF_SUCC, &pkt)?;
+        rusty_fusion::helpers::force_dismount(pc_id, clients, state)?;
     }
 
     Ok(())
@@ -114,9 +108,12 @@ pub fn item_delete(client: &mut FFClient, state: &mut ShardServerState) -> FFRes
 pub fn item_combination(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
     let pkt: sP_CL2FE_REQ_PC_ITEM_COMBINATION =
         *client.get_packet(P_CL2FE_REQ_PC_ITEM_COMBINATION)?;
-    catch_fail(
+    // the player borrows the state, so take the RNG out for the duration
+    let mut rng = std::mem::take(&mut state.rng);
+    let res = catch_fail(
         (|| {
-            let player = state.get_player_mut(client.get_player_id()?)?;
+            let pc_id = client.get_player_id()?;
+            let player = state.get_player_mut(pc_id)?;
 
             let looks_item = player
                 .get_item(ItemLocation::Inven, pkt.iCostumeItemSlot as usize)?
@@ -167,7 +164,7 @@ pub fn item_combination(client: &mut FFClient, state: &mut ShardServerState) ->
                     ),
                 ));
             }
-            let taros_left = player.set_taros(player.get_taros() - cost);
+            let taros_left = player.add_taros(-(cost as i64), CurrencySource::Combine);
 
             let looks_item = player
                 .set_item(ItemLocation::Inven, pkt.iCostumeItemSlot as usize, None)
@@ -180,7 +177,7 @@ pub fn item_combination(client: &mut FFClient, state: &mut ShardServerState) ->
 
             let success_chance = crocpot_data.base_chance
                 * crocpot_data.rarity_diff_multipliers[rarity_gap as usize];
-            let roll: f32 = random();
+            let roll: f32 = rng.gen();
             let succeeded = roll < success_chance;
             if succeeded {
                 // set the appearance of the stats item
@@ -221,6 +218,10 @@ pub fn item_combination(client: &mut FFClient, state: &mut ShardServerState) ->
                 iCandy: taros_left as i32,
                 iSuccessFlag: if succeeded { 1 } else { 0 },
             };
+            if succeeded {
+                // the looks item is gone for good
+                state.save_players_now(&[pc_id], "combination save");
+            }
             client.send_packet(P_FE2CL_REP_PC_ITEM_COMBINATION_SUCC, &resp)
         })(),
         || {
@@ -233,12 +234,15 @@ pub fn item_combination(client: &mut FFClient, state: &mut ShardServerState) ->
             };
             client.send_packet(P_FE2CL_REP_PC_ITEM_COMBINATION_FAIL, &resp)
         },
-    )
+    );
+    state.rng = rng;
+    res
 }
 
 pub fn item_chest_open(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
     let pkt: sP_CL2FE_REQ_ITEM_CHEST_OPEN = *client.get_packet(P_CL2FE_REQ_ITEM_CHEST_OPEN)?;
-    catch_fail(
+    let mut rng = std::mem::take(&mut state.rng);
+    let res = catch_fail(
         (|| {
             let player = state.get_player_mut(client.get_player_id()?)?;
             let location: ItemLocation = pkt.eIL.try_into()?;
@@ -263,15 +267,33 @@ pub fn item_chest_open(client: &mut FFClient, state: &mut ShardServerState) -> F
                 ));
             }
 
-            let reward_item = tdata_get()
-                .get_item_from_crate(chest.id, player.get_style().iGender as i32)
-                .unwrap_or_else(|e| {
-                    // If for some reason we can't find a valid drop for the crate,
-                    // give the player a random gumball instead.
-                    // This idea was taken from OpenFusion <3
-                    log_error(&e);
-                    util::get_random_gumball()
-                });
+            let gender = player.get_style().iGender as i32;
+            let config = &config_get().shard;
+            let reward_item = if config.crate_pity_enabled.get() {
+                tdata_get()
+                    .get_item_from_crate_with_pity(
+                        &mut rng,
+                        chest.id,
+                        gender,
+                        player.get_crate_pity(chest.id),
+                        config.crate_pity_rate.get(),
+                    )
+                    .map(|(item, rarest)| {
+                        player.record_crate_roll(chest.id, rarest);
+                        item
+                    })
+            } else {
+                tdata_get().get_item_from_crate(&mut rng, chest.id, gender)
+            };
+            let reward_item = reward_item.unwrap_or_else(|e| {
+                // If for some reason we can't find a valid drop for the crate,
+                // give the player a random gumball instead.
+                // This idea was taken from OpenFusion <3
+                log_error(&e);
+                util::get_random_gumball(&mut rng)
+            });
+            let reward_item = reward_item
+                .with_origin(ItemOrigin::Crate { crate_id: chest.id }, SystemTime::now());
 
             player.set_item(location, pkt.iSlotNum as usize, Some(reward_item))?;
 
@@ -307,6 +329,281 @@ pub fn item_chest_open(client: &mut FFClient, state: &mut ShardServerState) -> F
             };
             client.send_packet(P_FE2CL_REP_ITEM_CHEST_OPEN_FAIL, &resp)
         },
+    );
+    state.rng = rng;
+    res
+}
+
+pub fn item_use(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let client = clients.get_self();
+    let pkt: sP_CL2FE_REQ_ITEM_USE = *client.get_packet(P_CL2FE_REQ_ITEM_USE)?;
+    let pc_id = client.get_player_id()?;
+    let bcast = catch_fail(
+        (|| {
+            let player = state.get_player_mut(pc_id)?;
+            if player.is_dead() {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} tried to use an item while dead", player),
+                ));
+            }
+            if player.trade_id.is_some() {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} tried to use an item while trading", player),
+                ));
+            }
+
+            let location: ItemLocation = pkt.eIL.try_into()?;
+            if location != ItemLocation::Inven {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Used item not in main inventory: {:?}", location),
+                ));
+            }
+
+            let slot_num = pkt.iSlotNum as usize;
+            let item = player.get_item(location, slot_num)?.ok_or(FFError::build(
+                Severity::Warning,
+                format!("Tried to use what's in empty slot {}", pkt.iSlotNum),
+            ))?;
+            let effect = match item.ty {
+                ItemType::General => item.get_stats()?.use_effect,
+                _ => None,
+            }
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Item not usable: {:?}", item),
+            ))?;
+
+            let mut bcast = PacketBuffer::default();
+            let mut remaining_item = Some(item);
+            Item::split_items(&mut remaining_item, 1);
+            match effect {
+                ItemUseEffect::NanoStamina { style } => {
+                    let nano_id = player
+                        .get_equipped_nano_ids()
+                        .get(pkt.iNanoSlot as usize)
+                        .copied()
+                        .filter(|id| *id != 0)
+                        .ok_or(FFError::build(
+                            Severity::Warning,
+                            format!("No nano equipped in slot {}", pkt.iNanoSlot),
+                        ))?;
+                    let nano = player.get_nano_mut(nano_id as i16).unwrap();
+                    let nano_style = nano.get_stats()?.style;
+                    if nano_style != style {
+                        return Err(FFError::build(
+                            Severity::Warning,
+                            format!(
+                                "Gumball {:?} doesn't match nano {} ({:?})",
+                                item, nano_id, nano_style
+                            ),
+                        ));
+                    }
+
+                    let healed = NANO_STAMINA_MAX - nano.stamina;
+                    nano.stamina = NANO_STAMINA_MAX;
+                    let result = sSkillResult_Heal_Stamina {
+                        eCT: CharType::Player as i32,
+                        iID: pc_id,
+                        iHealNanoStamina: healed,
+                        Nano: Some(nano.clone()).into(),
+                    };
+                    player.set_item(location, slot_num, remaining_item)?;
+
+                    let resp = sP_FE2CL_REP_PC_ITEM_USE_SUCC {
+                        iPC_ID: pc_id,
+                        eIL: pkt.eIL,
+                        iSlotNum: pkt.iSlotNum,
+                        RemainItem: remaining_item.into(),
+                        iSkillID: unused!(),
+                        eST: SkillType::HealStamina as i32,
+                        iTargetCnt: 1,
+                    };
+                    client.queue_packet(P_FE2CL_REP_PC_ITEM_USE_SUCC, &resp);
+                    client.queue_struct(&result);
+                    client.flush()?;
+
+                    let pkt = sP_FE2CL_PC_ITEM_USE {
+                        iPC_ID: pc_id,
+                        iSkillID: unused!(),
+                        eST: SkillType::HealStamina as i32,
+                        iTargetCnt: 1,
+                    };
+                    bcast.queue_packet(P_FE2CL_PC_ITEM_USE, &pkt);
+                    bcast.queue_struct(&result);
+                }
+                ItemUseEffect::Buff {
+                    skill_type,
+                    condition_bit,
+                    duration,
+                } => {
+                    player.add_timed_buff(condition_bit, TimeBuffType::Item, duration, time);
+                    let result = sSkillResult_Buff {
+                        eCT: CharType::Player as i32,
+                        iID: pc_id,
+                        bProtected: 0,
+                        iConditionBitFlag: player.get_condition_bit_flag(),
+                    };
+                    player.set_item(location, slot_num, remaining_item)?;
+
+                    let resp = sP_FE2CL_REP_PC_ITEM_USE_SUCC {
+                        iPC_ID: pc_id,
+                        eIL: pkt.eIL,
+                        iSlotNum: pkt.iSlotNum,
+                        RemainItem: remaining_item.into(),
+                        iSkillID: unused!(),
+                        eST: skill_type as i32,
+                        iTargetCnt: 1,
+                    };
+                    client.queue_packet(P_FE2CL_REP_PC_ITEM_USE_SUCC, &resp);
+                    client.queue_struct(&result);
+                    client.flush()?;
+
+                    let buff_update = player.get_buff_update(
+                        condition_bit,
+                        TimeBuffType::Item,
+                        TimeBuffUpdate::Add,
+                    );
+                    client.send_packet(P_FE2CL_PC_BUFF_UPDATE, &buff_update)?;
+
+                    let pkt = sP_FE2CL_PC_ITEM_USE {
+                        iPC_ID: pc_id,
+                        iSkillID: unused!(),
+                        eST: skill_type as i32,
+                        iTargetCnt: 1,
+                    };
+                    bcast.queue_packet(P_FE2CL_PC_ITEM_USE, &pkt);
+                    bcast.queue_struct(&result);
+                }
+            }
+            Ok(bcast)
+        })(),
+        || {
+            let resp = sP_FE2CL_REP_PC_ITEM_USE_FAIL {
+                iErrorCode: unused!(),
+            };
+            client.send_packet(P_FE2CL_REP_PC_ITEM_USE_FAIL, &resp)
+        },
+    )?;
+
+    state
+        .entity_map
+        .for_each_around(EntityID::Player(pc_id), clients, |c| {
+            c.send_payload(bcast.clone())
+        });
+    Ok(())
+}
+
+pub fn shiny_pickup(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let pkt: sP_CL2FE_REQ_SHINY_PICKUP =
+        *clients.get_self().get_packet(P_CL2FE_REQ_SHINY_PICKUP)?;
+    catch_fail(
+        (|| {
+            let pc_id = clients.get_self().get_player_id()?;
+            let player = state.get_player(pc_id)?;
+            let egg = state.get_egg(pkt.iShinyID)?;
+            let distance = player.get_position().distance_to(&egg.get_position());
+            if distance > RANGE_INTERACT {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} too far from egg {} to pick it up ({} > {})",
+                        player, pkt.iShinyID, distance, RANGE_INTERACT
+                    ),
+                ));
+            }
+
+            let stats = tdata_get().get_egg_stats(egg.get_type())?;
+            let mut reward = Reward::new(RewardCategory::Eggs);
+            match egg.get_reward().or(stats.crate_id.map(ShinyReward::Crate)) {
+                Some(ShinyReward::Crate(crate_id)) => {
+                    reward.items.push(Item::new(ItemType::Chest, crate_id));
+                }
+                Some(ShinyReward::Reward(reward_id)) => {
+                    reward = tdata_get().get_mission_reward(reward_id)?;
+                }
+                None => {}
+            }
+            if player.get_free_slots(ItemLocation::Inven) < reward.items.len() {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} has no room for egg {} rewards", player, pkt.iShinyID),
+                ));
+            }
+
+            let player_uid = player.get_uid();
+            let respawn_after = stats.respawn_time;
+            let egg = state.get_egg_mut(pkt.iShinyID)?;
+            egg.pick_up(player_uid, time, respawn_after)?;
+            let egg_id = egg.get_id();
+            if egg.is_live() {
+                // summoned eggs stick around for other players
+                log_if_failed(egg.send_exit(clients.get_self()));
+            } else {
+                state.entity_map.update(egg_id, None, Some(clients));
+            }
+
+            let player = state.get_player_mut(pc_id)?;
+            let mut item_rewards = Vec::with_capacity(reward.items.len());
+            for item in reward.items {
+                let slot = player.find_free_slot(ItemLocation::Inven)?;
+                player.set_item(ItemLocation::Inven, slot, Some(item))?;
+                item_rewards.push(sItemReward {
+                    sItem: Some(item).into(),
+                    eIL: ItemLocation::Inven as i32,
+                    iSlotNum: slot as i32,
+                });
+            }
+            let reward_pkt = sP_FE2CL_REP_REWARD_ITEM {
+                m_iCandy: player.add_taros(reward.taros as i64, CurrencySource::Egg) as i32,
+                m_iFusionMatter: player.add_fusion_matter(
+                    reward.fusion_matter as i64,
+                    CurrencySource::Egg,
+                    Some(clients),
+                ) as i32,
+                m_iBatteryN: player
+                    .set_nano_potions(player.get_nano_potions() + reward.nano_potions)
+                    as i32,
+                m_iBatteryW: player
+                    .set_weapon_boosts(player.get_weapon_boosts() + reward.weapon_boosts)
+                    as i32,
+                iItemCnt: item_rewards.len() as i8,
+                iFatigue: 100,
+                iFatigue_Level: 1,
+                iNPC_TypeID: unused!(),
+                iTaskID: unused!(),
+            };
+
+            let client = clients.get_self();
+            client.queue_packet(P_FE2CL_REP_REWARD_ITEM, &reward_pkt);
+            for item in &item_rewards {
+                client.queue_struct(item);
+            }
+            client.flush()?;
+
+            // TODO egg effects (buffs)
+            let resp = sP_FE2CL_REP_SHINY_PICKUP_SUCC {
+                iSkillID: unused!(),
+                eCSTB: unused!(),
+            };
+            client.send_packet(P_FE2CL_REP_SHINY_PICKUP_SUCC, &resp)
+        })(),
+        || {
+            let resp = sP_FE2CL_REP_SHINY_PICKUP_FAIL { UNUSED: unused!() };
+            clients
+                .get_self()
+                .send_packet(P_FE2CL_REP_SHINY_PICKUP_FAIL, &resp)
+        },
     )
 }
 
@@ -363,10 +660,12 @@ pub fn vendor_item_buy(
 
             // sanitize the item
             let item: Option<Item> = pkt.Item.try_into()?;
-            let mut item = item.ok_or(FFError::build(
-                Severity::Warning,
-                "Tried to buy nothing".to_string(),
-            ))?;
+            let mut item = item
+                .ok_or(FFError::build(
+                    Severity::Warning,
+                    "Tried to buy nothing".to_string(),
+                ))?
+                .with_origin(ItemOrigin::Vendor, time);
             if item.ty == ItemType::Vehicle {
                 // set expiration date
                 let duration_min = config_get().shard.vehicle_duration.get();
@@ -387,28 +686,72 @@ pub fn vendor_item_buy(
             }
 
             let stats = item.get_stats()?;
-            let price = stats.buy_price * item.quantity as u32;
-            let player = state.get_player_mut(client.get_player_id()?)?;
-            if player.get_taros() < price {
-                Err(FFError::build(
+            let price = stats.buy_price as u64 * item.quantity as u64;
+            let pc_id = client.get_player_id()?;
+            let player = state.get_player_mut(pc_id)?;
+            if (player.get_taros() as u64) < price {
+                return Err(FFError::build(
                     Severity::Warning,
                     format!(
                         "Not enough taros to buy item ({} < {})",
                         player.get_taros(),
                         price
                     ),
-                ))
-            } else {
-                player.set_item(ItemLocation::Inven, pkt.iInvenSlotNum as usize, Some(item))?;
-                player.set_taros(player.get_taros() - price);
+                ));
+            }
 
-                let resp = sP_FE2CL_REP_PC_VENDOR_ITEM_BUY_SUCC {
-                    iCandy: player.get_taros() as i32,
-                    iInvenSlotNum: pkt.iInvenSlotNum,
-                    Item: Some(item).into(),
+            // figure out where everything goes before changing anything
+            let plan = player.plan_item_placement(
+                &item,
+                stats.max_stack_size,
+                pkt.iInvenSlotNum as usize,
+            )?;
+
+            player.add_taros(-(price as i64), CurrencySource::VendorBuy);
+            let mut placed = Vec::with_capacity(plan.len());
+            for (slot_num, count) in plan {
+                let mut new_stack = (*player.get_item(ItemLocation::Inven, slot_num)?)
+                    .unwrap_or_else(|| {
+                        let mut stack = item;
+                        stack.quantity = 0;
+                        stack
+                    });
+                new_stack.quantity += count;
+                player.set_item(ItemLocation::Inven, slot_num, Some(new_stack))?;
+                placed.push((slot_num, new_stack));
+            }
+
+            // the last slot rides on the buy response, the rest are sent as item updates
+            let (last_slot, last_stack) = placed.pop().unwrap();
+            for (slot_num, stack) in placed {
+                let update = sP_FE2CL_REP_PC_GIVE_ITEM_SUCC {
+                    eIL: ItemLocation::Inven as i32,
+                    iSlotNum: slot_num as i32,
+                    Item: Some(stack).into(),
                 };
-                client.send_packet(P_FE2CL_REP_PC_VENDOR_ITEM_BUY_SUCC, &resp)
+                log_if_failed(client.send_packet(P_FE2CL_REP_PC_GIVE_ITEM_SUCC, &update));
+            }
+            let resp = sP_FE2CL_REP_PC_VENDOR_ITEM_BUY_SUCC {
+                iCandy: player.get_taros() as i32,
+                iInvenSlotNum: last_slot as i32,
+                Item: Some(last_stack).into(),
+            };
+            if is_loggable_vendor_deal(price) {
+                transaction_log_queue(Transaction {
+                    kind: TransactionKind::VendorBuy,
+                    pc_uid: player.get_uid(),
+                    other_pc_uid: None,
+                    taros_given: price as u32,
+                    items_given: Vec::new(),
+                    taros_received: 0,
+                    items_received: vec![item],
+                    time,
+                });
             }
+            if price >= IMMEDIATE_SAVE_MIN_PRICE {
+                state.save_players_now(&[pc_id], "vendor buy save");
+            }
+            client.send_packet(P_FE2CL_REP_PC_VENDOR_ITEM_BUY_SUCC, &resp)
         })(),
         || {
             let resp = sP_FE2CL_REP_PC_VENDOR_ITEM_BUY_FAIL {
@@ -426,6 +769,7 @@ pub fn vendor_item_sell(client: &mut FFClient, state: &mut ShardServerState) ->
                 *client.get_packet(P_CL2FE_REQ_PC_VENDOR_ITEM_SELL)?;
             let pc_id = client.get_player_id()?;
             let player = state.get_player_mut(pc_id)?;
+            let time = SystemTime::now();
 
             let item = player
                 .get_item(ItemLocation::Inven, pkt.iInvenSlotNum as usize)?
@@ -442,6 +786,35 @@ pub fn vendor_item_sell(client: &mut FFClient, state: &mut ShardServerState) ->
                 ));
             }
 
+            let config = &config_get().shard;
+            if config.is_sell_blacklisted(&item) {
+                send_system_message(client, "That item can't be sold to vendors")?;
+                return Err(FFError::build(
+                    Severity::Debug,
+                    format!("{} tried to sell blacklisted item {:?}", player, item),
+                ));
+            }
+
+            let confirm_rarity = config.sell_confirm_rarity.get();
+            let is_rare = stats.rarity.is_some_and(|rarity| rarity >= confirm_rarity);
+            if confirm_rarity > 0 && is_rare {
+                let window = config.sell_confirm_window.get();
+                let slot_num = pkt.iInvenSlotNum as usize;
+                if !player.confirm_rare_sell(slot_num, item, time, Duration::from_secs(window)) {
+                    send_system_message(
+                        client,
+                        &format!(
+                            "That item is rare. Sell it again within {} seconds to confirm",
+                            window
+                        ),
+                    )?;
+                    return Err(FFError::build(
+                        Severity::Debug,
+                        format!("{} needs to confirm selling {:?}", player, item),
+                    ));
+                }
+            }
+
             let mut remaining_item =
                 player.set_item(ItemLocation::Inven, pkt.iInvenSlotNum as usize, None)?;
             let quantity = pkt.iItemCnt as u16;
@@ -455,7 +828,19 @@ pub fn vendor_item_sell(client: &mut FFClient, state: &mut ShardServerState) ->
                 .unwrap();
 
             let sell_price = stats.sell_price * quantity as u32;
-            let new_taros = player.set_taros(player.get_taros() + sell_price);
+            let new_taros = player.add_taros(sell_price as i64, CurrencySource::VendorSell);
+            if is_loggable_vendor_deal(sell_price as u64) {
+                transaction_log_queue(Transaction {
+                    kind: TransactionKind::VendorSell,
+                    pc_uid: player.get_uid(),
+                    other_pc_uid: None,
+                    taros_given: 0,
+                    items_given: item.into_iter().collect(),
+                    taros_received: sell_price,
+                    items_received: Vec::new(),
+                    time,
+                });
+            }
             let buyback_list = state.buyback_lists.entry(pc_id).or_default();
             buyback_list.push(item.unwrap());
 
@@ -527,7 +912,7 @@ pub fn vendor_item_restore_buy(
                 ))
             } else {
                 player.set_item(ItemLocation::Inven, pkt.iInvenSlotNum as usize, Some(item))?;
-                let new_taros = player.set_taros(player.get_taros() - cost);
+                let new_taros = player.add_taros(-(cost as i64), CurrencySource::VendorBuy);
 
                 let resp = sP_FE2CL_REP_PC_VENDOR_ITEM_RESTORE_BUY_SUCC {
                     iCandy: new_taros as i32,
@@ -603,7 +988,7 @@ pub fn vendor_battery_buy(client: &mut FFClient, state: &mut ShardServerState) -
                     ));
                 }
             }
-            let taros_new = player.set_taros(player.get_taros() - cost);
+            let taros_new = player.add_taros(-(cost as i64), CurrencySource::VendorBuy);
 
             let resp = sP_FE2CL_REP_PC_VENDOR_BATTERY_BUY_SUCC {
                 iCandy: taros_new as i32,
@@ -1,15 +1,19 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{collections::HashMap, net::SocketAddr, time::SystemTime};
 
 use uuid::Uuid;
 
 use rusty_fusion::{
     config::config_get,
     defines::*,
-    entity::{Entity, PlayerSearchQuery},
+    entity::{BuddyListEntry, Entity, PlayerSearchQuery},
     enums::*,
-    error::{codes::PlayerSearchReqErr, *},
+    error::{
+        codes::{ConfiscateItemErr, PlayerSearchReqErr},
+        *,
+    },
+    helpers,
     net::{
-        crypto,
+        compression, crypto,
         packet::{PacketID::*, *},
         ClientMap, FFClient, LoginData,
     },
@@ -29,11 +33,15 @@ pub fn login_connect_challenge(server: &mut FFClient, state: &ShardServerState)
     let key = config_get().general.server_key.get().clone();
     let mut challenge = pkt.aChallenge;
     crypto::decrypt_payload(&mut challenge[..], key.as_bytes());
+    let (ip_buf, port) = util::encode_server_addr(&get_public_addr())?;
     let pkt = sP_FE2LS_REQ_CONNECT {
         aChallengeSolved: challenge,
         iShardID: state.shard_id,
         iNumChannels: config_get().shard.num_channels.get() as i8,
         iMaxChannelPop: config_get().shard.max_channel_pop.get() as i32,
+        g_FE_ServerIP: ip_buf,
+        g_FE_ServerPort: port,
+        iCapabilities: get_capabilities(),
     };
     server.send_packet(P_FE2LS_REQ_CONNECT, &pkt)
 }
@@ -42,12 +50,32 @@ pub fn login_connect_succ(server: &mut FFClient, state: &mut ShardServerState) -
     let pkt: &sP_LS2FE_REP_CONNECT_SUCC = server.get_packet(P_LS2FE_REP_CONNECT_SUCC)?;
     let login_server_id = Uuid::from_bytes_le(pkt.aLS_UID);
     let conn_time: u64 = pkt.uiSvrTime;
+    let capabilities = pkt.iCapabilities;
 
     let iv1: i32 = pkt.aLS_UID.into_iter().reduce(|a, b| a ^ b).unwrap() as i32;
     let iv2: i32 = state.shard_id + 1;
     server.e_key = crypto::gen_key(conn_time, iv1, iv2);
     state.login_server_conn_id = Some(login_server_id);
 
+    // the login server only echoes capabilities we both have
+    if capabilities & compression::CAPABILITY_COMPRESSION != 0 {
+        server.enable_compression(config_get().general.compression_threshold.get())?;
+        log(
+            Severity::Debug,
+            "Compression enabled for the login server connection",
+        );
+    }
+
+    // a freshly started shard has nobody on it, so tell the login server right away
+    // in case it still thinks players are here from before a crash
+    if state.entity_map.get_player_ids().next().is_none() {
+        let pkt = sP_FE2LS_UPDATE_PC_STATUSES {
+            iCnt: 0,
+            bJoinable: state.is_ready() as i32,
+        };
+        log_if_failed(server.send_packet(P_FE2LS_UPDATE_PC_STATUSES, &pkt));
+    }
+
     log(
         Severity::Info,
         &format!(
@@ -69,28 +97,40 @@ pub fn login_connect_fail(server: &mut FFClient) -> FFResult<()> {
     ))
 }
 
-pub fn login_update_info(server: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
-    let public_addr: SocketAddr = config_get()
+fn get_capabilities() -> i32 {
+    let mut capabilities = 0;
+    if config_get().general.inter_server_compression.get() {
+        capabilities |= compression::CAPABILITY_COMPRESSION;
+    }
+    capabilities
+}
+
+fn get_public_addr() -> SocketAddr {
+    config_get()
         .shard
-        .external_addr
-        .get()
+        .get_external_addr()
         .parse()
-        .expect("Bad public address");
-    let mut ip_buf: [u8; 16] = [0; 16];
-    let ip_str: &str = &public_addr.ip().to_string();
-    let ip_bytes: &[u8] = ip_str.as_bytes();
-    ip_buf[..ip_bytes.len()].copy_from_slice(ip_bytes);
+        .expect("Bad public address")
+}
+
+pub fn login_update_info(server: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
+    let (ip_buf, port) = util::encode_server_addr(&get_public_addr())?;
 
     let pkt: &sP_LS2FE_REQ_UPDATE_LOGIN_INFO = server.get_packet(P_LS2FE_REQ_UPDATE_LOGIN_INFO)?;
     let resp = sP_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC {
         iEnterSerialKey: pkt.iEnterSerialKey,
         g_FE_ServerIP: ip_buf,
-        g_FE_ServerPort: public_addr.port() as i32,
+        g_FE_ServerPort: port,
     };
 
     let serial_key = resp.iEnterSerialKey;
-    let ld = &mut state.login_data;
-    if ld.contains_key(&serial_key) {
+    let login_data = LoginData {
+        iAccountID: pkt.iAccountID,
+        iPC_UID: pkt.iPC_UID,
+        uiFEKey: pkt.uiFEKey,
+        uiSvrTime: pkt.uiSvrTime,
+    };
+    if !state.add_login_data(serial_key, login_data, SystemTime::now()) {
         // this serial key was already registered...
         // extremely unlikely?
         let resp = sP_FE2LS_REP_UPDATE_LOGIN_INFO_FAIL {
@@ -100,15 +140,6 @@ pub fn login_update_info(server: &mut FFClient, state: &mut ShardServerState) ->
         server.send_packet(P_FE2LS_REP_UPDATE_LOGIN_INFO_FAIL, &resp)?;
         return Ok(());
     }
-    ld.insert(
-        serial_key,
-        LoginData {
-            iAccountID: pkt.iAccountID,
-            iPC_UID: pkt.iPC_UID,
-            uiFEKey: pkt.uiFEKey,
-            uiSvrTime: pkt.uiSvrTime,
-        },
-    );
 
     server.send_packet(P_FE2LS_REP_UPDATE_LOGIN_INFO_SUCC, &resp)
 }
@@ -175,11 +206,16 @@ pub fn login_pc_location(clients: &mut ClientMap, state: &mut ShardServerState)
             szTargetPC_FirstName: util::encode_utf16(&player.first_name),
             szTargetPC_LastName: util::encode_utf16(&player.last_name),
         };
+        let (avg_rtt, max_rtt) = player
+            .get_client(clients)
+            .map_or((-1, -1), |c| c.get_latency().get_millis());
         if let Some(login_server) = clients.get_login_server() {
             let resp = sP_FE2LS_REP_PC_LOCATION_SUCC {
                 iReqShard_ID: pkt.iReqShard_ID,
                 iPC_ID: pkt.iPC_ID,
                 sResp: resp,
+                iAvgRTT: avg_rtt,
+                iMaxRTT: max_rtt,
             };
             log_if_failed(login_server.send_packet(P_FE2LS_REP_PC_LOCATION_SUCC, &resp));
         }
@@ -203,10 +239,16 @@ pub fn login_pc_location_succ(
         .get_self()
         .get_packet(P_LS2FE_REP_PC_LOCATION_SUCC)?;
     let resp = pkt.sResp;
+    let (avg_rtt, max_rtt) = (pkt.iAvgRTT, pkt.iMaxRTT);
     let player = state.get_player(pkt.iPC_ID)?;
     let client = player.get_client(clients).unwrap();
     log_if_failed(client.send_packet(P_FE2CL_GM_REP_PC_LOCATION, &resp));
-    Ok(())
+    let target_name = format!(
+        "{} {}",
+        util::parse_utf16(&resp.szTargetPC_FirstName)?,
+        util::parse_utf16(&resp.szTargetPC_LastName)?
+    );
+    helpers::send_latency_info(client, &target_name, avg_rtt, max_rtt)
 }
 
 pub fn login_pc_location_fail(
@@ -256,10 +298,8 @@ pub fn login_pc_exit_duplicate(
         .get_self()
         .get_packet(P_LS2FE_REQ_PC_EXIT_DUPLICATE)?;
     let pc_uid = pkt.iPC_UID;
-    let pc_id = *state
-        .entity_map
-        .find_players(|p| p.get_uid() == pc_uid)
-        .first()
+    let pc_id = PlayerSearchQuery::ByUID(pc_uid)
+        .execute(state)
         .ok_or(FFError::build(
             Severity::Warning,
             format!("Couldn't find player with UID {}", pc_uid),
@@ -274,6 +314,24 @@ pub fn login_pc_exit_duplicate(
     Ok(())
 }
 
+pub fn login_verify_player(server: &mut FFClient, state: &ShardServerState) -> FFResult<()> {
+    let pkt: &sP_LS2FE_REQ_VERIFY_PLAYER = server.get_packet(P_LS2FE_REQ_VERIFY_PLAYER)?;
+    let pc_uid = pkt.iPC_UID;
+    let present = PlayerSearchQuery::ByUID(pc_uid).execute(state).is_some();
+    let resp = sP_FE2LS_REP_VERIFY_PLAYER {
+        iAccountID: pkt.iAccountID,
+        iPC_UID: pc_uid,
+        iPresent: present as i32,
+    };
+    server.send_packet(P_FE2LS_REP_VERIFY_PLAYER, &resp)
+}
+
+pub fn login_kick_account(clients: &mut ClientMap) -> FFResult<()> {
+    let pkt: sP_LS2FE_REQ_KICK_ACCOUNT =
+        *clients.get_self().get_packet(P_LS2FE_REQ_KICK_ACCOUNT)?;
+    helpers::kick_account_for_login_server(&pkt, clients)
+}
+
 pub fn login_get_buddy_state(
     clients: &mut ClientMap,
     state: &mut ShardServerState,
@@ -299,15 +357,27 @@ pub fn login_get_buddy_state(
         )
         .collect();
 
-    let pc_id = *state
-        .entity_map
-        .find_players(|p| p.get_uid() == pc_uid)
-        .first()
+    let pc_id = PlayerSearchQuery::ByUID(pc_uid)
+        .execute(state)
         .ok_or(FFError::build(
             Severity::Warning,
             format!("Couldn't find player with UID {}", pc_uid),
         ))?;
-    let player = state.get_player(pc_id).unwrap();
+    // buddies here might have been renamed or restyled since the list was loaded
+    let latest_buddy_info: Vec<BuddyListEntry> = state
+        .get_player(pc_id)
+        .unwrap()
+        .get_all_buddy_info()
+        .iter()
+        .filter_map(|info| {
+            let buddy_id = PlayerSearchQuery::ByUID(info.pc_uid).execute(state)?;
+            state.get_player(buddy_id).ok().map(BuddyListEntry::new)
+        })
+        .collect();
+    let player = state.get_player_mut(pc_id).unwrap();
+    for latest in latest_buddy_info {
+        player.refresh_buddy_info(latest);
+    }
     let buddy_info = player.get_all_buddy_info();
 
     let mut resp = sP_FE2CL_REP_GET_BUDDY_STATE_SUCC {
@@ -319,16 +389,147 @@ pub fn login_get_buddy_state(
         resp.aBuddyState[i] = if online { 1 } else { 0 };
         if online {
             // lookup shard-local ID
-            let buddy_id = *state
-                .entity_map
-                .find_players(|p| p.get_uid() == buddy_uid)
-                .first()
-                .unwrap_or(&0);
+            let buddy_id = PlayerSearchQuery::ByUID(buddy_uid)
+                .execute(state)
+                .unwrap_or(0);
             resp.aBuddyID[i] = buddy_id;
         }
     }
 
+    let player = state.get_player(pc_id).unwrap();
     let client = player.get_client(clients).unwrap();
     log_if_failed(client.send_packet(P_FE2CL_REP_GET_BUDDY_STATE_SUCC, &resp));
     Ok(())
 }
+
+pub fn login_change_name_succ(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let pkt: sP_LS2FE_REP_CHANGE_NAME_SUCC = *clients
+        .get_self()
+        .get_packet(P_LS2FE_REP_CHANGE_NAME_SUCC)?;
+    let pc_uid = pkt.iPC_UID;
+    let pc_id = PlayerSearchQuery::ByUID(pc_uid)
+        .execute(state)
+        .ok_or(FFError::build(
+            Severity::Warning,
+            format!("Couldn't find renamed player with UID {}", pc_uid),
+        ))?;
+
+    // the login server already saved the new name
+    state.entity_map.rename_player(
+        pc_id,
+        util::parse_utf16(&pkt.szFirstName)?,
+        util::parse_utf16(&pkt.szLastName)?,
+    )?;
+    let player = state.get_player_mut(pc_id).unwrap();
+    player.name_change_pending = false;
+    player.flags.name_check = pkt.iNameCheck.try_into()?;
+    if pkt.iItemID != 0 {
+        log_if_failed(helpers::consume_item(player, pkt.iItemID, clients));
+    }
+
+    let mut msg = format!(
+        "Your name is now {} {}",
+        player.first_name, player.last_name
+    );
+    if player.flags.name_check == PlayerNameStatus::Pending {
+        msg.push_str("\nIt will be shown to others once it's approved");
+    }
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(helpers::send_system_message(client, &msg));
+    }
+    helpers::broadcast_appearance(pc_id, clients, state)
+}
+
+pub fn login_change_name_fail(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let pkt: sP_LS2FE_REP_CHANGE_NAME_FAIL = *clients
+        .get_self()
+        .get_packet(P_LS2FE_REP_CHANGE_NAME_FAIL)?;
+    let pc_uid = pkt.iPC_UID;
+    let pc_id = PlayerSearchQuery::ByUID(pc_uid)
+        .execute(state)
+        .ok_or(FFError::build(
+            Severity::Warning,
+            format!("Couldn't find player with UID {}", pc_uid),
+        ))?;
+
+    let player = state.get_player_mut(pc_id).unwrap();
+    player.name_change_pending = false;
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(helpers::send_system_message(
+            client,
+            "That name isn't allowed. Your name wasn't changed",
+        ));
+    }
+    Ok(())
+}
+
+pub fn login_confiscate_item_succ(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let pkt: sP_LS2FE_REP_CONFISCATE_ITEM_SUCC = *clients
+        .get_self()
+        .get_packet(P_LS2FE_REP_CONFISCATE_ITEM_SUCC)?;
+    let gm_uid = pkt.iGM_PC_UID;
+    let target_uid = pkt.iTargetPC_UID;
+    let (removed, item_id) = (pkt.iRemoved, pkt.iItemID);
+    let item_type: ItemType = pkt.iItemType.try_into()?;
+    let msg = format!(
+        "Confiscated {} of item {:?} #{} from offline player with UID {}",
+        removed, item_type, item_id, target_uid
+    );
+
+    // the GM may have logged off in the meantime
+    let Some(gm_pc_id) = PlayerSearchQuery::ByUID(gm_uid).execute(state) else {
+        log(
+            Severity::Info,
+            &format!("{}\nConfiscated by: player with UID {}", msg, gm_uid),
+        );
+        return Ok(());
+    };
+    let gm = state.get_player(gm_pc_id).unwrap();
+    log(Severity::Info, &format!("{}\nConfiscated by: {}", msg, gm));
+    if let Some(client) = gm.get_client(clients) {
+        log_if_failed(helpers::send_system_message(client, &msg));
+    }
+    Ok(())
+}
+
+pub fn login_confiscate_item_fail(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let pkt: sP_LS2FE_REP_CONFISCATE_ITEM_FAIL = *clients
+        .get_self()
+        .get_packet(P_LS2FE_REP_CONFISCATE_ITEM_FAIL)?;
+    let gm_uid = pkt.iGM_PC_UID;
+    let target_uid = pkt.iTargetPC_UID;
+    let gm_pc_id = PlayerSearchQuery::ByUID(gm_uid)
+        .execute(state)
+        .ok_or(FFError::build(
+            Severity::Warning,
+            format!("Couldn't find player with UID {}", gm_uid),
+        ))?;
+
+    let err: ConfiscateItemErr = pkt.iErrorCode.try_into()?;
+    let msg = match err {
+        ConfiscateItemErr::TargetOnline => format!(
+            "Player with UID {} is online. Confiscate from the shard they're on instead",
+            target_uid
+        ),
+        ConfiscateItemErr::Generic => {
+            format!("Couldn't confiscate from player with UID {}", target_uid)
+        }
+    };
+    let gm = state.get_player(gm_pc_id).unwrap();
+    if let Some(client) = gm.get_client(clients) {
+        log_if_failed(helpers::send_system_message(client, &msg));
+    }
+    Ok(())
+}
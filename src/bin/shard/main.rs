@@ -10,13 +10,17 @@ use std::{
 
 use rusty_fusion::{
     config::{config_get, config_init},
-    database::{db_init, db_run_async, db_shutdown},
+    currency::currency_log_flush,
+    database::{db_init, db_run_async, db_run_sync, db_shutdown},
     defines::*,
     entity::{Entity, Player},
     error::{
         log, log_error, log_if_failed, logger_flush, logger_flush_scheduled, logger_init,
         panic_log, FFError, FFResult, Severity,
     },
+    helpers,
+    journal::{journal_commit, journal_flush, journal_init, journal_mark, journal_replay},
+    leaderboard::leaderboard_flush,
     net::{
         packet::{
             PacketID::{self, *},
@@ -27,7 +31,10 @@ use rusty_fusion::{
     state::{ServerState, ShardServerState},
     tabledata::tdata_init,
     timer::TimerMap,
+    transaction::{transaction_log_flush, transaction_log_prune},
     unused, util,
+    webhook::{webhook_flush, webhook_init, webhook_queue, webhook_shutdown, WebhookEventKind},
+    wordfilter::wordfilter_init,
 };
 
 fn main() -> Result<()> {
@@ -40,8 +47,24 @@ fn main() -> Result<()> {
         Severity::Info,
         &format!("Shard server #{} starting up...", shard_id),
     );
+    if std::env::args().any(|arg| arg == "--validate-tdata") {
+        let exit_code = validate_tdata();
+        let _ = logger_flush();
+        std::process::exit(exit_code);
+    }
     cleanup.db_thread_handle = Some(db_init());
     tdata_init();
+    wordfilter_init(&config.general.word_filter_path.get());
+    if config.shard.journal_enabled.get() {
+        let journal_path = config.shard.journal_path.get();
+        log_if_failed(journal_replay(&journal_path));
+        log_if_failed(journal_init(&journal_path));
+    }
+    cleanup.webhook_thread_handle = webhook_init(
+        config.general.webhook_url.get(),
+        config.general.webhook_events.get(),
+        format!("Shard {}", shard_id),
+    );
 
     let polling_interval = Duration::from_millis(50);
     let listen_addr = config_get().shard.listen_addr.get();
@@ -63,6 +86,36 @@ fn main() -> Result<()> {
         Duration::from_secs(config.general.log_write_interval.get()),
         false,
     );
+    timers.register_timer(
+        Box::new(|_, _, _| webhook_flush()),
+        Duration::from_secs(config.general.webhook_interval.get()),
+        false,
+    );
+    timers.register_timer(
+        Box::new(|_, _, _| journal_flush()),
+        Duration::from_secs(1),
+        false,
+    );
+    timers.register_timer(
+        Box::new(|_, _, _| currency_log_flush()),
+        Duration::from_secs(10),
+        false,
+    );
+    timers.register_timer(
+        Box::new(|_, _, _| leaderboard_flush()),
+        Duration::from_secs(10),
+        false,
+    );
+    timers.register_timer(
+        Box::new(|_, _, _| transaction_log_flush()),
+        Duration::from_secs(10),
+        false,
+    );
+    timers.register_timer(
+        Box::new(|t, _, _| transaction_log_prune(t)),
+        Duration::from_secs(60 * 60),
+        true,
+    );
     timers.register_timer(
         Box::new(|_, srv, st| connect_to_login_server(srv, st.as_shard())),
         Duration::from_secs(config.shard.login_server_conn_interval.get()),
@@ -79,12 +132,25 @@ fn main() -> Result<()> {
         false,
     );
 
+    let handler_stats_interval = config.general.handler_stats_interval.get();
+    if handler_stats_interval > 0 {
+        timers.register_timer(
+            Box::new(|_, _, st| {
+                st.get_handler_stats_mut().log_top();
+                Ok(())
+            }),
+            Duration::from_secs(handler_stats_interval),
+            false,
+        );
+    }
+
     // Per-minute timer
     timers.register_timer(
         Box::new(|t, srv, st| {
             st.as_shard()
                 .check_for_expired_vehicles(t, &mut srv.get_client_map());
-            Ok(())
+            st.as_shard().report_limiter.prune(t);
+            send_world_snapshot_to_login_server(srv, st.as_shard())
         }),
         Duration::from_secs(60),
         false,
@@ -93,6 +159,8 @@ fn main() -> Result<()> {
     // Per-tick "fast" timer
     timers.register_timer(
         Box::new(|t, srv, st| {
+            // land hits first so anything they kill gets handled this tick
+            st.as_shard().tick_pending_hits(t);
             st.as_shard().tick_entities(t, &mut srv.get_client_map());
             Ok(())
         }),
@@ -102,11 +170,19 @@ fn main() -> Result<()> {
 
     // Per-second "slow" timer
     timers.register_timer(
-        Box::new(|_, srv, st| {
+        Box::new(|t, srv, st| {
             let state = st.as_shard();
             state.tick_garbage_collection(&mut srv.get_client_map());
-            state.tick_groups(&mut srv.get_client_map());
-            state.check_receivers();
+            state.tick_groups(t, &mut srv.get_client_map());
+            state.tick_group_warps(t, &mut srv.get_client_map());
+            state.tick_login_data(t, &mut srv.get_client_map());
+            state.tick_offers(t, &mut srv.get_client_map());
+            for pc_id in state.tick_exits(t, &mut srv.get_client_map()) {
+                helpers::finish_exit(pc_id, &mut srv.get_client_map(), state);
+            }
+            state.tick_trades(&mut srv.get_client_map());
+            state.tick_spectators(&mut srv.get_client_map());
+            state.check_receivers(&mut srv.get_client_map());
             Ok(())
         }),
         Duration::from_secs(1),
@@ -124,8 +200,16 @@ fn main() -> Result<()> {
         Severity::Info,
         &format!("Shard server listening on {}", server.get_endpoint()),
     );
+    webhook_queue(
+        WebhookEventKind::ServerUp,
+        &format!("Shard server #{} is up", shard_id),
+    );
     let live_check_time = Duration::from_secs(config.general.live_check_time.get());
     while running.load(Ordering::SeqCst) {
+        if server.should_shut_down() {
+            log(Severity::Fatal, "Too many packet handlers panicked");
+            break;
+        }
         server.poll(&mut state, live_check_time)?;
         timers
             .check_all(&mut server, &mut state)
@@ -139,34 +223,87 @@ fn main() -> Result<()> {
     }
 
     log(Severity::Info, "Shard server shutting down...");
-    log_if_failed(do_save(SystemTime::now(), state.as_shard()));
+    webhook_queue(
+        WebhookEventKind::ServerDown,
+        &format!("Shard server #{} is shutting down", shard_id),
+    );
+    // so the login server knows to hold onto reconnecting players while we save
+    let reconnect = shutdown_notify_login_server(&mut server);
+    let save_timeout = Duration::from_secs(config.shard.shutdown_save_timeout.get());
+    state.as_shard().save_for_shutdown(
+        &mut server.get_client_map(),
+        save_timeout,
+        |player| {
+            let pc_uid = player.get_uid();
+            let journal_mark = journal_mark();
+            db_run_async(move |db| {
+                db.save_player(&player)?;
+                journal_commit(journal_mark, Some(&[pc_uid][..]))
+            })
+        },
+        |player| {
+            let player = player.clone();
+            let pc_uid = player.get_uid();
+            let journal_mark = journal_mark();
+            db_run_sync(move |db| {
+                db.save_player(&player)?;
+                journal_commit(journal_mark, Some(&[pc_uid][..]))
+            })
+        },
+    );
 
-    let mut attempts = 5;
-    while state.as_shard().check_receivers() {
-        // Wait for all receivers to finish
-        if attempts == 0 {
-            log(Severity::Warning, "Some receivers hanging!");
-            break;
-        }
-        std::thread::sleep(Duration::from_secs(1));
-        attempts -= 1;
+    shutdown_notify_clients(&mut server, state.as_shard(), reconnect);
+    Ok(())
+}
+
+// Loads the table data, reports every dangling cross-reference, and returns the exit code
+fn validate_tdata() -> i32 {
+    let dangling_refs = tdata_init().find_dangling_refs();
+    if dangling_refs.is_empty() {
+        log(Severity::Info, "Table data validation passed");
+        return 0;
     }
 
-    shutdown_notify_clients(&mut server, state.as_shard());
-    Ok(())
+    let mut total = 0;
+    for (kind, refs) in &dangling_refs {
+        log(
+            Severity::Warning,
+            &format!("Dangling {} ({}):", kind, refs.len()),
+        );
+        for dangling_ref in refs {
+            log(Severity::Warning, &format!("    {}", dangling_ref));
+        }
+        total += refs.len();
+    }
+    log(
+        Severity::Warning,
+        &format!(
+            "Table data validation failed with {} dangling references",
+            total
+        ),
+    );
+    1
 }
 
 #[derive(Default)]
 struct Cleanup {
     db_thread_handle: Option<std::thread::JoinHandle<()>>,
+    webhook_thread_handle: Option<std::thread::JoinHandle<()>>,
 }
 impl Drop for Cleanup {
     fn drop(&mut self) {
         print!("Cleaning up...");
         if let Some(handle) = self.db_thread_handle.take() {
+            let _ = currency_log_flush();
+            let _ = leaderboard_flush();
+            let _ = transaction_log_flush();
             db_shutdown();
             handle.join().unwrap();
         }
+        if let Some(handle) = self.webhook_thread_handle.take() {
+            let _ = webhook_shutdown();
+            handle.join().unwrap();
+        }
         if let Err(e) = logger_flush() {
             println!("Could not flush log: {}", e);
         }
@@ -184,12 +321,17 @@ fn handle_disconnect(key: usize, clients: &mut HashMap<usize, FFClient>, state:
                 Severity::Warning,
                 &format!("Login server ({}) disconnected", client.get_addr()),
             );
+            client.log_compression_stats();
             state.login_server_conn_id = None;
+            log(
+                Severity::Info,
+                "Turning away new players until the login server is back",
+            );
         }
         ClientType::GameClient {
             pc_id: Some(pc_id), ..
         } => {
-            // dirty exit; clean exit happens in P_CL2FE_REQ_PC_EXIT handler
+            // dirty exit, even mid-countdown; clean exits go through the P_CL2FE_REQ_PC_EXIT handler
             Player::disconnect(pc_id, state, &mut clients);
         }
         ClientType::Unknown => {
@@ -233,6 +375,9 @@ fn handle_packet(
 ) -> FFResult<()> {
     let state = state.as_shard();
     let mut clients = ClientMap::new(key, clients);
+    if let Ok(pc_id) = clients.get_self().get_player_id() {
+        helpers::cancel_exit_on_packet(pc_id, pkt_id, state);
+    }
     match pkt_id {
         P_LS2FE_REP_AUTH_CHALLENGE => login::login_connect_challenge(clients.get_self(), state),
         P_LS2FE_REP_CONNECT_SUCC => login::login_connect_succ(clients.get_self(), state),
@@ -246,7 +391,16 @@ fn handle_packet(
         P_LS2FE_REP_PC_LOCATION_FAIL => login::login_pc_location_fail(&mut clients, state),
         P_LS2FE_REQ_PC_EXIT_DUPLICATE => login::login_pc_exit_duplicate(&mut clients, state),
         P_LS2FE_REP_GET_BUDDY_STATE => login::login_get_buddy_state(&mut clients, state),
-        P_LS2FE_REP_LIVE_CHECK => Ok(()),
+        P_LS2FE_REQ_KICK_ACCOUNT => login::login_kick_account(&mut clients),
+        P_LS2FE_REQ_VERIFY_PLAYER => login::login_verify_player(clients.get_self(), state),
+        P_LS2FE_REP_CHANGE_NAME_SUCC => login::login_change_name_succ(&mut clients, state),
+        P_LS2FE_REP_CHANGE_NAME_FAIL => login::login_change_name_fail(&mut clients, state),
+        P_LS2FE_REP_CONFISCATE_ITEM_SUCC => login::login_confiscate_item_succ(&mut clients, state),
+        P_LS2FE_REP_CONFISCATE_ITEM_FAIL => login::login_confiscate_item_fail(&mut clients, state),
+        P_LS2FE_REP_LIVE_CHECK => {
+            clients.get_self().on_live_check_reply(time);
+            Ok(())
+        }
         //
         P_CL2LS_REQ_LOGIN => wrong_server(clients.get_self()),
         //
@@ -268,7 +422,7 @@ fn handle_packet(
         P_CL2FE_REQ_PC_REGEN => pc::pc_regen(&mut clients, state),
         P_CL2FE_REQ_PC_FIRST_USE_FLAG_SET => pc::pc_first_use_flag_set(clients.get_self(), state),
         P_CL2FE_REQ_PC_CHANGE_MENTOR => pc::pc_change_mentor(clients.get_self(), state),
-        P_CL2FE_REQ_PC_EXIT => pc::pc_exit(&mut clients, state),
+        P_CL2FE_REQ_PC_EXIT => pc::pc_exit(&mut clients, state, time),
         //
         P_CL2FE_REQ_PC_GIVE_ITEM => gm::gm_pc_give_item(clients.get_self(), state),
         P_CL2FE_GM_REQ_PC_SET_VALUE => gm::gm_pc_set_value(&mut clients, state),
@@ -307,11 +461,14 @@ fn handle_packet(
         P_CL2FE_REQ_PC_AVATAR_EMOTES_CHAT => chat::pc_avatar_emotes_chat(&mut clients, state),
         //
         P_CL2FE_REQ_PC_ATTACK_NPCs => combat::pc_attack_npcs(&mut clients, state),
+        P_CL2FE_REQ_PC_ATTACK_CHARs => combat::pc_attack_chars(&mut clients, state),
         //
         P_CL2FE_REQ_ITEM_MOVE => item::item_move(&mut clients, state),
         P_CL2FE_REQ_PC_ITEM_DELETE => item::item_delete(clients.get_self(), state),
         P_CL2FE_REQ_PC_ITEM_COMBINATION => item::item_combination(clients.get_self(), state),
         P_CL2FE_REQ_ITEM_CHEST_OPEN => item::item_chest_open(clients.get_self(), state),
+        P_CL2FE_REQ_ITEM_USE => item::item_use(&mut clients, state, time),
+        P_CL2FE_REQ_SHINY_PICKUP => item::shiny_pickup(&mut clients, state, time),
         P_CL2FE_REQ_PC_VENDOR_START => item::vendor_start(clients.get_self(), state),
         P_CL2FE_REQ_PC_VENDOR_TABLE_UPDATE => item::vendor_table_update(clients.get_self()),
         P_CL2FE_REQ_PC_VENDOR_ITEM_BUY => item::vendor_item_buy(clients.get_self(), state, time),
@@ -325,6 +482,7 @@ fn handle_packet(
         P_CL2FE_REQ_NANO_EQUIP => nano::nano_equip(&mut clients, state),
         P_CL2FE_REQ_NANO_UNEQUIP => nano::nano_unequip(&mut clients, state),
         P_CL2FE_REQ_NANO_ACTIVE => nano::nano_active(&mut clients, state),
+        P_CL2FE_REQ_NANO_SKILL_USE => nano::nano_skill_use(&mut clients, state, time),
         P_CL2FE_REQ_NANO_TUNE => nano::nano_tune(clients.get_self(), state),
         //
         P_CL2FE_REQ_REQUEST_MAKE_BUDDY => buddy::request_make_buddy(&mut clients, state),
@@ -332,6 +490,8 @@ fn handle_packet(
         P_CL2FE_REQ_PC_FIND_NAME_MAKE_BUDDY => buddy::find_name_make_buddy(&mut clients, state),
         P_CL2FE_REQ_PC_FIND_NAME_ACCEPT_BUDDY => buddy::find_name_accept_buddy(&mut clients, state),
         P_CL2FE_REQ_GET_BUDDY_STATE => buddy::get_buddy_state(&mut clients, state),
+        P_CL2FE_REQ_PC_BUDDY_WARP => buddy::buddy_warp(&mut clients, state, time),
+        P_CL2FE_REQ_SET_PC_BLOCK => buddy::set_pc_block(&mut clients, state, time),
         //
         P_CL2FE_REQ_PC_TRADE_OFFER => trade::trade_offer(&mut clients, state),
         P_CL2FE_REQ_PC_TRADE_OFFER_REFUSAL => trade::trade_offer_refusal(&mut clients, state),
@@ -367,7 +527,10 @@ fn handle_packet(
         P_CL2FE_REQ_NPC_GROUP_INVITE => group::npc_group_invite(&mut clients, state),
         P_CL2FE_REQ_NPC_GROUP_KICK => group::npc_group_kick(&mut clients, state),
         //
-        P_CL2FE_REP_LIVE_CHECK => Ok(()),
+        P_CL2FE_REP_LIVE_CHECK => {
+            clients.get_self().on_live_check_reply(time);
+            Ok(())
+        }
         //
         _ => Err(FFError::build(
             Severity::Warning,
@@ -428,6 +591,7 @@ fn send_status_to_login_server(
     let pc_ids: Vec<i32> = state.entity_map.get_player_ids().collect();
     let pkt = sP_FE2LS_UPDATE_PC_STATUSES {
         iCnt: pc_ids.len() as u32,
+        bJoinable: state.is_ready() as i32,
     };
     client.queue_packet(P_FE2LS_UPDATE_PC_STATUSES, &pkt);
     for pc_id in pc_ids {
@@ -447,6 +611,48 @@ fn send_status_to_login_server(
     client.flush()
 }
 
+fn send_world_snapshot_to_login_server(
+    shard_server: &mut FFServer,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    // taken even while disconnected, so the next average only covers the next minute
+    let snapshot = state.get_world_snapshot(&config_get().shard.tracked_npc_types.get());
+    if !is_login_server_connected(state) {
+        return Ok(());
+    }
+
+    let clients = &mut shard_server.get_client_map();
+    let Some(client) = clients.get_login_server() else {
+        return Ok(());
+    };
+
+    let mut pkt = sP_FE2LS_WORLD_SNAPSHOT {
+        iAvgTickUs: snapshot.avg_tick.as_micros().try_into().unwrap_or(u32::MAX),
+        iChannelCnt: snapshot.channel_pops.len() as i32,
+        aChannelPop: [0; MAX_NUM_CHANNELS],
+        iTrackedNPCCnt: snapshot.tracked_npcs.len() as i32,
+        bTrackedNPCsTruncated: snapshot.tracked_npcs_truncated as i32,
+        aTrackedNPCs: [sTrackedNPCStatus {
+            iNPC_ID: 0,
+            iNPCType: 0,
+            iChannelNum: 0,
+            bAlive: 0,
+        }; MAX_TRACKED_NPCS],
+    };
+    for (i, pop) in snapshot.channel_pops.iter().enumerate() {
+        pkt.aChannelPop[i] = *pop as u32;
+    }
+    for (i, status) in snapshot.tracked_npcs.iter().enumerate() {
+        pkt.aTrackedNPCs[i] = sTrackedNPCStatus {
+            iNPC_ID: status.npc_id,
+            iNPCType: status.npc_type,
+            iChannelNum: status.channel_num as i8,
+            bAlive: status.alive as i8,
+        };
+    }
+    client.send_packet(P_FE2LS_WORLD_SNAPSHOT, &pkt)
+}
+
 fn send_live_check(client: &mut FFClient) -> FFResult<()> {
     match client.client_type {
         ClientType::GameClient { .. } => {
@@ -466,6 +672,8 @@ fn send_live_check(client: &mut FFClient) -> FFResult<()> {
 }
 
 fn do_save(_time: SystemTime, state: &mut ShardServerState) -> FFResult<()> {
+    const SAVE_TIMEOUT: Duration = Duration::from_secs(60);
+
     let pc_ids: Vec<i32> = state.entity_map.get_player_ids().collect();
     if pc_ids.is_empty() {
         return Ok(());
@@ -479,27 +687,44 @@ fn do_save(_time: SystemTime, state: &mut ShardServerState) -> FFResult<()> {
         .iter()
         .map(|pc_id| state.get_player(*pc_id).unwrap().clone())
         .collect();
+    // catch bans that didn't reach this shard through the login server
+    let pc_uids: Vec<i64> = players.iter().map(|player| player.get_uid()).collect();
+    let journal_uids = pc_uids.clone();
+    let journal_mark = journal_mark();
     let rx = db_run_async(move |db| {
         let player_refs: Vec<&Player> = players.iter().collect();
-        db.save_players(&player_refs)
+        db.save_players(&player_refs)?;
+        log_if_failed(journal_commit(journal_mark, Some(&journal_uids)));
+        Ok(())
     });
-
-    state.save_rx = Some(rx);
+    let ban_check_rx = db_run_async(move |db| db.find_banned_accounts(&pc_uids));
+
+    state.register_receiver("save", rx, SAVE_TIMEOUT, ShardServerState::on_save_complete);
+    state.register_receiver(
+        "ban check",
+        ban_check_rx,
+        SAVE_TIMEOUT,
+        ShardServerState::on_ban_check_complete,
+    );
     Ok(())
 }
 
-fn shutdown_notify_clients(server: &mut FFServer, state: &mut ShardServerState) {
+// Returns whether the login server was told, so players can be sent back to reconnect
+fn shutdown_notify_login_server(server: &mut FFServer) -> bool {
     let clients = &mut server.get_client_map();
-    let reconnect = if let Some(login_server) = clients.get_login_server() {
-        let pkt = sP_FE2LS_DISCONNECTING {
-            iTempValue: unused!(),
-        };
-        login_server
-            .send_packet(P_FE2LS_DISCONNECTING, &pkt)
-            .is_ok()
-    } else {
-        false
+    let Some(login_server) = clients.get_login_server() else {
+        return false;
     };
+    let pkt = sP_FE2LS_DISCONNECTING {
+        iTempValue: unused!(),
+    };
+    login_server
+        .send_packet(P_FE2LS_DISCONNECTING, &pkt)
+        .is_ok()
+}
+
+fn shutdown_notify_clients(server: &mut FFServer, state: &mut ShardServerState, reconnect: bool) {
+    let clients = &mut server.get_client_map();
 
     for client in clients.get_all_gameclient() {
         let Ok(pc_id) = client.get_player_id() else {
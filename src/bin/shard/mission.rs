@@ -1,10 +1,15 @@
+use std::time::SystemTime;
+
 use rusty_fusion::{
     chunk::TickMode,
+    config::config_get,
     defines::{RANGE_INTERACT, RANGE_TRIGGER},
-    entity::{Combatant, EntityID},
-    enums::{ItemLocation, ItemType, MissionType, TaskType},
+    entity::{Combatant, Entity, EntityID},
+    enums::{CurrencySource, ItemLocation, ItemType, MissionType, TaskType},
     error::*,
-    mission::Task,
+    helpers,
+    item::ItemOrigin,
+    mission::{Task, TaskDefinition},
     net::{
         packet::{PacketID::*, *},
         ClientMap, FFClient,
@@ -179,6 +184,22 @@ pub fn task_start(client: &mut FFClient, state: &mut ShardServerState) -> FFResu
                 }
             }
 
+            // check for room in the journal
+            let mission_def = tdata_get().get_mission_definition(task_def.mission_id)?;
+            if !player.mission_journal.has_mission(mission_def.mission_id)
+                && !player
+                    .mission_journal
+                    .has_free_slot(mission_def.mission_type)
+            {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "Tried to start task {} with no free {:?} mission slots",
+                        pkt.iTaskNum, mission_def.mission_type
+                    ),
+                ));
+            }
+
             // check for free qitem slots. this is stricter than it needs to be but
             // we can keep it as long as we don't run into problems
             if task_def.given_qitems.len() > player.get_free_slots(ItemLocation::QInven) {
@@ -195,7 +216,6 @@ pub fn task_start(client: &mut FFClient, state: &mut ShardServerState) -> FFResu
 
             // all clear, start the task
             let mut task: Task = task_def.into();
-            let mission_def = task.get_mission_def();
 
             // start escort path (non-destructive if start_task fails)
             if task_def.obj_escort_npc_type.is_some() {
@@ -458,21 +478,39 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
             let player = state.get_player_mut(pc_id).unwrap();
             player.mission_journal.complete_task(pkt.iTaskNum)?;
 
-            // if escort following, stop it
+            // if escort following, stop it. grouped escorts leave the group too
             if let Some(escort_npc_id) = task.escort_npc_id {
                 let escort_npc = state.get_npc_mut(escort_npc_id).unwrap();
-                escort_npc.loose_follow = None;
+                if escort_npc.group_id.is_some() {
+                    log_if_failed(rusty_fusion::helpers::kick_npc_from_group(
+                        escort_npc_id,
+                        state,
+                        clients,
+                    ));
+                } else {
+                    escort_npc.loose_follow = None;
+                }
             }
             let player = state.get_player_mut(pc_id).unwrap();
 
-            // success qitem changes
-            if !task_def.succ_qitems.is_empty() {
+            // success qitem changes and deletions. other running tasks keep what they need
+            let other_task_defs: Vec<&TaskDefinition> = player
+                .mission_journal
+                .get_current_tasks()
+                .iter()
+                .filter(|t| t.get_task_id() != pkt.iTaskNum && !t.completed && !t.failed)
+                .map(|t| t.get_task_def())
+                .collect();
+            let qitem_changes = player.plan_qitem_turn_in(task_def, &other_task_defs);
+            let (qitem_updates, qitem_deletes): (Vec<_>, Vec<_>) =
+                qitem_changes.into_iter().partition(|(_, count)| *count > 0);
+            if !qitem_updates.is_empty() {
                 let qitem_pkt = sP_FE2CL_REP_REWARD_ITEM {
                     m_iCandy: player.get_taros() as i32,
                     m_iFusionMatter: player.get_fusion_matter() as i32,
                     m_iBatteryN: player.get_nano_potions() as i32,
                     m_iBatteryW: player.get_weapon_boosts() as i32,
-                    iItemCnt: task_def.succ_qitems.len() as i8,
+                    iItemCnt: qitem_updates.len() as i8,
                     iFatigue: 100,
                     iFatigue_Level: 1,
                     iNPC_TypeID: 0,
@@ -481,14 +519,12 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
                 clients
                     .get_self()
                     .queue_packet(P_FE2CL_REP_REWARD_ITEM, &qitem_pkt);
-                for (qitem_id, qitem_count_mod) in &task_def.succ_qitems {
-                    let curr_count = player.get_quest_item_count(*qitem_id) as isize;
-                    let new_count = (curr_count + *qitem_count_mod) as usize;
-                    let qitem_slot = player.set_quest_item_count(*qitem_id, new_count).unwrap();
+                for (qitem_id, new_count) in qitem_updates {
+                    let qitem_slot = player.set_quest_item_count(qitem_id, new_count).unwrap();
                     let qitem_reward = sItemReward {
                         sItem: sItemBase {
                             iType: ItemType::Quest as i16,
-                            iID: *qitem_id,
+                            iID: qitem_id,
                             iOpt: new_count as i32,
                             iTimeLimit: unused!(),
                         },
@@ -499,19 +535,40 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
                 }
                 log_if_failed(clients.get_self().flush());
             }
+            for (qitem_id, _) in qitem_deletes {
+                let qitem_slot = player.set_quest_item_count(qitem_id, 0).unwrap();
+                // client doesn't automatically delete qitems clientside
+                let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
+                    eIL: ItemLocation::QInven as i32,
+                    iSlotNum: qitem_slot as i32,
+                };
+                log_if_failed(
+                    clients
+                        .get_self()
+                        .send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt),
+                );
+            }
 
             if let Some(reward_id) = task_def.succ_reward {
-                match tdata_get()
-                    .get_mission_reward(reward_id)
-                    .map(|r| r.with_rates(&player.reward_data))
-                {
+                match tdata_get().get_mission_reward(reward_id).map(|r| {
+                    r.with_rates(&player.reward_data).with_origin(
+                        ItemOrigin::Mission {
+                            mission_id: task_def.mission_id,
+                        },
+                        SystemTime::now(),
+                    )
+                }) {
                     Err(e) => log_error(&e),
                     Ok(reward) => {
-                        let taros_new = player.get_taros() + reward.taros;
-                        let fm_new = player.get_fusion_matter() + reward.fusion_matter;
                         let reward_pkt = sP_FE2CL_REP_REWARD_ITEM {
-                            m_iCandy: player.set_taros(taros_new) as i32,
-                            m_iFusionMatter: player.set_fusion_matter(fm_new, Some(clients)) as i32,
+                            m_iCandy: player
+                                .add_taros(reward.taros as i64, CurrencySource::MissionReward)
+                                as i32,
+                            m_iFusionMatter: player.add_fusion_matter(
+                                reward.fusion_matter as i64,
+                                CurrencySource::MissionReward,
+                                Some(clients),
+                            ) as i32,
                             m_iBatteryN: player.get_nano_potions() as i32,
                             m_iBatteryW: player.get_weapon_boosts() as i32,
                             iItemCnt: reward.items.len() as i8,
@@ -550,10 +607,7 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
                     .mission_journal
                     .remove_task(task_def.task_id)
                     .unwrap();
-                player
-                    .mission_journal
-                    .set_mission_completed(task_def.mission_id)
-                    .unwrap();
+                player.set_mission_completed(task_def.mission_id).unwrap();
                 log(
                     Severity::Info,
                     &format!(
@@ -565,8 +619,9 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
                     let player_stats = tdata_get().get_player_stats(player.get_level()).unwrap();
                     match player.unlock_nano(nano_id).cloned() {
                         Ok(nano) => {
-                            player.set_fusion_matter(
-                                player.get_fusion_matter() - player_stats.req_fm_nano_create,
+                            player.add_fusion_matter(
+                                -(player_stats.req_fm_nano_create as i64),
+                                CurrencySource::Nano,
                                 None,
                             );
                             let new_level = std::cmp::max(player.get_level(), nano_id);
@@ -609,7 +664,13 @@ pub fn task_end(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
             };
             clients
                 .get_self()
-                .send_packet(P_FE2CL_REP_PC_TASK_END_SUCC, &resp)
+                .send_packet(P_FE2CL_REP_PC_TASK_END_SUCC, &resp)?;
+
+            let past_transition_task_id = config_get().shard.past_transition_task_id.get();
+            if past_transition_task_id != 0 && pkt.iTaskNum == past_transition_task_id {
+                log_if_failed(send_to_past(pc_id, clients, state));
+            }
+            Ok(())
         })(),
         || {
             let resp = sP_FE2CL_REP_PC_TASK_END_FAIL {
@@ -656,3 +717,35 @@ pub fn set_current_mission_id(client: &mut FFClient, state: &mut ShardServerStat
         },
     )
 }
+
+fn send_to_past(pc_id: i32, clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
+    let player = state.get_player_mut(pc_id)?;
+    let client = player.get_client(clients).unwrap();
+    if !helpers::do_past_transition(player, client) {
+        return Ok(());
+    }
+
+    let warp_data = tdata_get().get_warp_data(config_get().shard.past_arrival_warp_id.get())?;
+    helpers::warp_player(
+        pc_id,
+        warp_data.pos,
+        warp_data.map_num,
+        None,
+        clients,
+        state,
+    )?;
+
+    let player = state.get_player(pc_id)?;
+    let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
+        iX: warp_data.pos.x,
+        iY: warp_data.pos.y,
+        iZ: warp_data.pos.z,
+        eIL: ItemLocation::end(),
+        iItemSlotNum: unused!(),
+        Item: None.into(),
+        iCandy: player.get_taros() as i32,
+    };
+    clients
+        .get_self()
+        .send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp)
+}
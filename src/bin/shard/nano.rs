@@ -1,14 +1,17 @@
+use std::time::SystemTime;
+
 use rusty_fusion::{
     defines::*,
-    entity::{Combatant, Entity, EntityID},
+    entity::Entity,
     enums::*,
     error::*,
+    helpers,
     item::Item,
     net::{
         packet::{PacketID::*, *},
         ClientMap, FFClient,
     },
-    placeholder,
+    placeholder, skills,
     state::ShardServerState,
     tabledata::tdata_get,
     unused,
@@ -47,17 +50,7 @@ pub fn nano_equip(clients: &mut ClientMap, state: &mut ShardServerState) -> FFRe
 
     if deactivate {
         player.set_active_nano_slot(None).unwrap();
-        let bcast = sP_FE2CL_NANO_ACTIVE {
-            iPC_ID: pc_id,
-            Nano: None.into(),
-            iConditionBitFlag: player.get_condition_bit_flag(),
-            eCSTB___Add: 0,
-        };
-        state
-            .entity_map
-            .for_each_around(EntityID::Player(pc_id), clients, |c| {
-                c.send_packet(P_FE2CL_NANO_ACTIVE, &bcast)
-            });
+        helpers::broadcast_nano_active(pc_id, clients, state);
     }
 
     clients
@@ -81,17 +74,7 @@ pub fn nano_unequip(clients: &mut ClientMap, state: &mut ShardServerState) -> FF
 
     if deactivate {
         player.set_active_nano_slot(None).unwrap();
-        let bcast = sP_FE2CL_NANO_ACTIVE {
-            iPC_ID: pc_id,
-            Nano: None.into(),
-            iConditionBitFlag: player.get_condition_bit_flag(),
-            eCSTB___Add: 0,
-        };
-        state
-            .entity_map
-            .for_each_around(EntityID::Player(pc_id), clients, |c| {
-                c.send_packet(P_FE2CL_NANO_ACTIVE, &bcast)
-            });
+        helpers::broadcast_nano_active(pc_id, clients, state);
     }
 
     clients
@@ -116,24 +99,24 @@ pub fn nano_active(clients: &mut ClientMap, state: &mut ShardServerState) -> FFR
         eCSTB___Add: placeholder!(0),
     };
 
-    let bcast = sP_FE2CL_NANO_ACTIVE {
-        iPC_ID: pc_id,
-        Nano: player.get_active_nano().cloned().into(),
-        iConditionBitFlag: player.get_condition_bit_flag(),
-        eCSTB___Add: placeholder!(0),
-    };
-
-    state
-        .entity_map
-        .for_each_around(EntityID::Player(pc_id), clients, |c| {
-            c.send_packet(P_FE2CL_NANO_ACTIVE, &bcast)
-        });
+    helpers::broadcast_nano_active(pc_id, clients, state);
 
     clients
         .get_self()
         .send_packet(P_FE2CL_REP_NANO_ACTIVE_SUCC, &resp)
 }
 
+pub fn nano_skill_use(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let client = clients.get_self();
+    let pc_id = client.get_player_id()?;
+    let pkt: sP_CL2FE_REQ_NANO_SKILL_USE = *client.get_packet(P_CL2FE_REQ_NANO_SKILL_USE)?;
+    skills::do_support_skill(pc_id, &pkt, state, clients, time)
+}
+
 pub fn nano_tune(client: &mut FFClient, state: &mut ShardServerState) -> FFResult<()> {
     let pkt: sP_CL2FE_REQ_NANO_TUNE = *client.get_packet(P_CL2FE_REQ_NANO_TUNE)?;
     catch_fail(
@@ -198,8 +181,9 @@ pub fn nano_tune(client: &mut FFClient, state: &mut ShardServerState) -> FFResul
                         ),
                     ));
                 }
-                player_working.set_fusion_matter(
-                    player_working.get_fusion_matter() - tuning.fusion_matter_cost,
+                player_working.add_fusion_matter(
+                    -(tuning.fusion_matter_cost as i64),
+                    CurrencySource::Nano,
                     None, // no broadcast since there's no chance of leveling up
                 );
             }
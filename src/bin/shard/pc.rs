@@ -5,9 +5,10 @@ use rusty_fusion::{
     config::config_get,
     database::db_run_sync,
     defines::*,
-    entity::{Combatant, Entity, EntityID, Player},
+    entity::{Combatant, Entity, EntityID, Player, PlayerSearchQuery},
     enums::*,
     error::*,
+    helpers,
     net::{
         crypto::{self, EncryptionMode},
         packet::{PacketID::*, *},
@@ -27,16 +28,38 @@ pub fn pc_enter(
     let client = clients.get_self();
     let pkt: sP_CL2FE_REQ_PC_ENTER = *client.get_packet(P_CL2FE_REQ_PC_ENTER)?;
     let serial_key: i64 = pkt.iEnterSerialKey;
-    let login_data = state.login_data.remove(&serial_key).unwrap();
+    if !state.is_ready() {
+        // leave the login info alone so the player can get in once we're ready
+        let resp = sP_FE2CL_REP_PC_ENTER_FAIL {
+            iErrorCode: 1, // generic failure; the client backs out and can try again
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_ENTER_FAIL, &resp));
+        return Err(FFError::build_dc(
+            Severity::Info,
+            format!(
+                "Turned away player with serial key {} since the shard isn't ready",
+                serial_key
+            ),
+        ));
+    }
+    let login_data = match state.take_login_data(serial_key) {
+        Ok(login_data) => login_data,
+        Err(e) => {
+            // the login info expired or never existed
+            let resp = sP_FE2CL_REP_PC_ENTER_FAIL {
+                iErrorCode: unused!(),
+            };
+            log_if_failed(client.send_packet(P_FE2CL_REP_PC_ENTER_FAIL, &resp));
+            return Err(FFError::build_dc(
+                Severity::Warning,
+                e.get_msg().to_string(),
+            ));
+        }
+    };
 
     // check if this player is already in the shard and kick if so.
     // important that we save the current player to DB first to avoid state desync
-    if let Some(existing_pc_id) = state
-        .entity_map
-        .find_players(|p| p.get_uid() == login_data.iPC_UID)
-        .first()
-        .copied()
-    {
+    if let Some(existing_pc_id) = PlayerSearchQuery::ByUID(login_data.iPC_UID).execute(state) {
         log(
             Severity::Warning,
             &format!(
@@ -59,7 +82,7 @@ pub fn pc_enter(
     player.set_player_id(pc_id);
     player.set_client_id(key);
 
-    let channel_num = state.entity_map.get_min_pop_channel_num();
+    let channel_num = state.entity_map.get_channel_for_new_player();
     player.instance_id.channel_num = channel_num;
 
     let resp = sP_FE2CL_REP_PC_ENTER_SUCC {
@@ -80,6 +103,7 @@ pub fn pc_enter(
     client.e_key = crypto::gen_key(resp.uiSvrTime, iv1, iv2);
     client.fe_key = login_data.uiFEKey.to_le_bytes();
     client.enc_mode = EncryptionMode::FEKey;
+    client.set_strike_exempt(player.perms <= CN_ACCOUNT_LEVEL__GM as i16);
 
     let pkt_motd = sP_FE2LS_REQ_MOTD { iPC_ID: pc_id };
     match clients.get_login_server() {
@@ -108,25 +132,23 @@ pub fn pc_enter(
         .send_packet(P_FE2CL_REP_PC_ENTER_SUCC, &resp)
 }
 
-pub fn pc_exit(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
-    let client = clients.get_self();
-    let pc_id = client.clear_player_id()?;
-    Player::disconnect(pc_id, state, clients);
-
-    let exit_code = if clients.get_login_server().is_some() {
-        EXIT_CODE_REQ_BY_PC
-    } else {
-        EXIT_CODE_SERVER_ERROR
-    };
-
-    let resp = sP_FE2CL_REP_PC_EXIT_SUCC {
-        iID: pc_id,
-        iExitCode: exit_code as i32,
-    };
+pub fn pc_exit(
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    time: SystemTime,
+) -> FFResult<()> {
+    let pc_id = clients.get_self().get_player_id()?;
+    let player = state.get_player_mut(pc_id)?;
+    let countdown = player.get_exit_countdown(time, &config_get().shard.safe_exit_zones.get());
+    let deadline = player.start_exit(time + countdown);
+    if deadline <= time {
+        helpers::finish_exit(pc_id, clients, state);
+        return Ok(());
+    }
 
-    clients
-        .get_self()
-        .send_packet(P_FE2CL_REP_PC_EXIT_SUCC, &resp)
+    // the save and disconnect happen once the countdown is up
+    let remaining = deadline.duration_since(time).unwrap_or_default();
+    helpers::send_exit_countdown(clients.get_self(), remaining)
 }
 
 pub fn pc_loading_complete(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
@@ -193,6 +215,7 @@ pub fn pc_loading_complete(clients: &mut ClientMap, state: &mut ShardServerState
                 }
                 client.flush()?;
                 player.buddy_list_synced = true;
+                helpers::send_buddy_presence(player, true, clients);
             }
 
             Ok(())
@@ -213,6 +236,25 @@ pub fn pc_move(
 ) -> FFResult<()> {
     let client = clients.get_self();
     let pc_id = client.get_player_id()?;
+    if state.get_player(pc_id)?.is_riding_skyway() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Player {} tried to move while riding the Skyway", pc_id),
+        ));
+    }
+    let player = state.get_player(pc_id)?;
+    if !player.can_act() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Player {} tried to move while incapacitated", pc_id),
+        ));
+    }
+    // snared players can't go faster than their slowed speed
+    let max_speed = if player.has_condition(CSB_BIT_DN_MOVE_SPEED) {
+        Some(player.get_speed())
+    } else {
+        None
+    };
     let pkt: &sP_CL2FE_REQ_PC_MOVE = client.get_packet(P_CL2FE_REQ_PC_MOVE)?;
     let pos = Position {
         x: pkt.iX,
@@ -233,7 +275,7 @@ pub fn pc_move(
         fVZ: pkt.fVZ,
         iAngle: pkt.iAngle,
         cKeyValue: pkt.cKeyValue,
-        iSpeed: pkt.iSpeed,
+        iSpeed: max_speed.map_or(pkt.iSpeed, |max| pkt.iSpeed.min(max)),
         iID: pc_id,
         iSvrTime: util::get_timestamp_ms(time),
     };
@@ -248,10 +290,13 @@ pub fn pc_move(
     let entity_id = player.get_id();
     player.set_position(pos);
     player.set_rotation(angle);
-    let chunk = player.get_chunk_coords();
+    let instance_id = player.instance_id;
     state
         .entity_map
-        .update(entity_id, Some(chunk), Some(clients));
+        .update_moved(entity_id, pos, instance_id, Some(clients));
+    log_if_failed(rusty_fusion::helpers::auto_unlock_transport_locations(
+        pc_id, clients, state,
+    ));
     Ok(())
 }
 
@@ -297,10 +342,10 @@ pub fn pc_jump(
     let entity_id = player.get_id();
     player.set_position(pos);
     player.set_rotation(angle);
-    let chunk = player.get_chunk_coords();
+    let instance_id = player.instance_id;
     state
         .entity_map
-        .update(entity_id, Some(chunk), Some(clients));
+        .update_moved(entity_id, pos, instance_id, Some(clients));
     Ok(())
 }
 
@@ -338,10 +383,10 @@ pub fn pc_stop(
     let player = state.get_player_mut(pc_id)?;
     let entity_id = player.get_id();
     player.set_position(pos);
-    let chunk = player.get_chunk_coords();
+    let instance_id = player.instance_id;
     state
         .entity_map
-        .update(entity_id, Some(chunk), Some(clients));
+        .update_moved(entity_id, pos, instance_id, Some(clients));
     Ok(())
 }
 
@@ -396,10 +441,10 @@ pub fn pc_movetransportation(
     let entity_id = player.get_id();
     player.set_position(pos);
     player.set_rotation(angle);
-    let chunk = player.get_chunk_coords();
+    let instance_id = player.instance_id;
     state
         .entity_map
-        .update(entity_id, Some(chunk), Some(clients));
+        .update_moved(entity_id, pos, instance_id, Some(clients));
     Ok(())
 }
 
@@ -447,12 +492,36 @@ pub fn pc_vehicle_on(clients: &mut ClientMap, state: &mut ShardServerState) -> F
                 ));
             }
             let vehicle = vehicle.as_ref().unwrap();
+            if vehicle.ty != ItemType::Vehicle {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "Player {} tried to mount a non-vehicle item: {:?}",
+                        pc_id, vehicle
+                    ),
+                ));
+            }
 
-            if let Some(vehicle_speed) = vehicle.get_stats()?.speed {
-                player.vehicle_speed = Some(vehicle_speed);
-            } else {
-                panic_log(&format!("Vehicle has no speed: {:?}", vehicle));
+            if vehicle
+                .get_expiry_time()
+                .is_some_and(|expiry_time| expiry_time <= SystemTime::now())
+            {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "Player {} tried to mount an expired vehicle: {:?}",
+                        pc_id, vehicle
+                    ),
+                ));
             }
+
+            let Some(vehicle_speed) = vehicle.get_stats()?.speed else {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Vehicle has no speed: {:?}", vehicle),
+                ));
+            };
+            player.vehicle_speed = Some(vehicle_speed);
             rusty_fusion::helpers::broadcast_state(
                 pc_id,
                 player.get_state_bit_flag(),
@@ -484,6 +553,7 @@ pub fn pc_vehicle_off(clients: &mut ClientMap, state: &mut ShardServerState) ->
             let player = state.get_player_mut(pc_id)?;
 
             player.vehicle_speed = None;
+            player.stop_vehicle_boost();
             rusty_fusion::helpers::broadcast_state(
                 pc_id,
                 player.get_state_bit_flag(),
@@ -590,6 +660,32 @@ fn get_respawn_point(pos: Position, map_num: u32) -> Position {
     })
 }
 
+// Called before the player is moved to the respawn point so the map they died in is checked
+fn take_death_fm_penalty(player: &mut Player) {
+    let config = &config_get().shard;
+    let map_num = player.instance_id.map_num;
+    let in_race = config.death_fm_exempt_races.get()
+        && tdata_get()
+            .get_map_data(map_num)
+            .is_ok_and(|map_data| map_data.ep_id.is_some());
+    if player.is_death_fm_exempt(in_race, &config.death_fm_exempt_maps.get()) {
+        return;
+    }
+
+    let Ok(player_stats) = tdata_get().get_player_stats(player.get_level()) else {
+        return;
+    };
+    let multiplier = config.death_fm_multiplier.get().max(0.0);
+    let death_fm = (player_stats.death_fm as f32 * multiplier) as u32;
+    let fm_lost = player.take_death_fm_penalty(death_fm, player_stats.req_fm_nano_create);
+    if fm_lost > 0 {
+        log(
+            Severity::Debug,
+            &format!("{} lost {} FM for dying", player, fm_lost),
+        );
+    }
+}
+
 pub fn pc_regen(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResult<()> {
     const WARP_AWAY_COOLDOWN: Duration = Duration::from_secs(60);
 
@@ -607,6 +703,7 @@ pub fn pc_regen(clients: &mut ClientMap, state: &mut ShardServerState) -> FFResu
                     format!("{} tried to revive while not dead", player),
                 ));
             }
+            take_death_fm_penalty(player);
             player.set_position(get_respawn_point(
                 player.get_position(),
                 player.instance_id.map_num,
@@ -695,7 +792,33 @@ pub fn pc_change_mentor(client: &mut FFClient, state: &mut ShardServerState) ->
                 ));
             }
 
-            let guide_count = player.update_guide(pkt.iMentor.try_into()?);
+            let guide: PlayerGuide = pkt.iMentor.try_into()?;
+            let fm_cost = config_get().shard.guide_change_fm_cost.get();
+            let guide_count = player.change_guide(guide, fm_cost)?;
+
+            // missions meant for the old guide can't be finished anymore
+            for task in player.mission_journal.get_current_tasks() {
+                let task_def = task.get_task_def();
+                if task_def.prereq_guide.map_or(true, |g| g == guide)
+                    || task.get_mission_def().mission_type == MissionType::Nano
+                {
+                    continue;
+                }
+
+                player.mission_journal.remove_task(task_def.task_id)?;
+                for item_id in &task_def.delete_qitems {
+                    let qitem_slot = player.set_quest_item_count(*item_id, 0).unwrap();
+                    let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
+                        eIL: ItemLocation::QInven as i32,
+                        iSlotNum: qitem_slot as i32,
+                    };
+                    log_if_failed(client.send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt));
+                }
+                let pkt = sP_FE2CL_REP_PC_TASK_STOP_SUCC {
+                    iTaskNum: task_def.task_id,
+                };
+                log_if_failed(client.send_packet(P_FE2CL_REP_PC_TASK_STOP_SUCC, &pkt));
+            }
 
             let resp = sP_FE2CL_REP_PC_CHANGE_MENTOR_SUCC {
                 iMentor: pkt.iMentor,
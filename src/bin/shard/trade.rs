@@ -1,17 +1,20 @@
+use std::time::SystemTime;
+
 use uuid::Uuid;
 
 use rusty_fusion::{
-    database::db_run_async,
-    defines::*,
-    entity::{Entity, EntityID},
+    config::config_get,
+    entity::Entity,
     enums::*,
     error::*,
+    helpers::refuse_trade_offer_if_ignored,
     net::{
         packet::{PacketID::*, *},
         ClientMap,
     },
     state::ShardServerState,
-    trade::TradeContext,
+    trade::{validate_trade_parties, TradeContext},
+    transaction::{transaction_log_queue, Transaction},
     unused,
 };
 
@@ -22,9 +25,10 @@ pub fn trade_offer(clients: &mut ClientMap, state: &mut ShardServerState) -> FFR
         (|| {
             let client = clients.get_self();
             let pc_id = client.get_player_id()?;
-            state.entity_map.validate_proximity(
-                &[EntityID::Player(pc_id), EntityID::Player(pkt.iID_To)],
-                RANGE_INTERACT,
+            validate_trade_parties(
+                state.get_player(pc_id)?,
+                state.get_player(pkt.iID_To)?,
+                config_get().shard.trade_range.get(),
             )?;
 
             let player = state.get_player_mut(pc_id)?;
@@ -36,6 +40,10 @@ pub fn trade_offer(clients: &mut ClientMap, state: &mut ShardServerState) -> FFR
             }
 
             let other_pc_id = pkt.iID_To;
+            if refuse_trade_offer_if_ignored(pc_id, other_pc_id, clients, state)? {
+                return Ok(());
+            }
+
             let other_player = state.get_player(other_pc_id)?;
             if other_player.trade_id.is_some() {
                 return Err(FFError::build(
@@ -53,7 +61,7 @@ pub fn trade_offer(clients: &mut ClientMap, state: &mut ShardServerState) -> FFR
 
             // to avoid other clients making offers on our behalf
             let player = state.get_player_mut(pc_id)?;
-            player.trade_offered_to = Some(other_pc_id);
+            player.trade_offered_to.set(other_pc_id, SystemTime::now());
             Ok(())
         })(),
         || {
@@ -79,14 +87,22 @@ pub fn trade_offer_accept(clients: &mut ClientMap, state: &mut ShardServerState)
             let pc_id = clients.get_self().get_player_id()?;
             let pc_id_other = pkt.iID_From;
 
+            // either side might have moved since the offer was made
+            validate_trade_parties(
+                state.get_player(pc_id_other)?,
+                state.get_player(pc_id)?,
+                config_get().shard.trade_range.get(),
+            )?;
+
             let player_from = state.get_player_mut(pc_id_other)?;
-            if player_from.trade_offered_to != Some(pc_id) {
+            if !player_from
+                .trade_offered_to
+                .take_for(pc_id, SystemTime::now())
+            {
                 return Err(FFError::build(
                     Severity::Info,
                     format!("Trade offer from {} to {} expired", pkt.iID_From, pc_id),
                 ));
-            } else {
-                player_from.trade_offered_to = None;
             }
             player_from.trade_id = Some(trade_id);
 
@@ -136,13 +152,14 @@ pub fn trade_offer_refusal(clients: &mut ClientMap, state: &mut ShardServerState
     let pc_id_other = pkt.iID_From;
 
     let player_from = state.get_player_mut(pc_id_other)?;
-    if player_from.trade_offered_to != Some(pc_id) {
+    if !player_from
+        .trade_offered_to
+        .take_for(pc_id, SystemTime::now())
+    {
         return Err(FFError::build(
             Severity::Info,
             format!("Trade offer from {} to {} expired", pkt.iID_From, pc_id),
         ));
-    } else {
-        player_from.trade_offered_to = None;
     }
 
     let resp = sP_FE2CL_REP_PC_TRADE_OFFER_REFUSAL {
@@ -246,10 +263,12 @@ pub fn trade_item_register(clients: &mut ClientMap, state: &mut ShardServerState
             let client = clients.get_self();
             let pc_id = client.get_player_id()?;
             let player = state.get_player(pc_id)?;
-            let trade_id = player.trade_id.ok_or(FFError::build(
-                Severity::Warning,
-                format!("Player {} is not trading", player.get_player_id()),
-            ))?;
+            let Some(trade_id) = player.trade_id else {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Player {} is not trading", player.get_player_id()),
+                ));
+            };
 
             // client sends an iOpt of 0 for unstackables
             let quantity = if pkt.Item.iOpt > 0 {
@@ -448,16 +467,34 @@ pub fn trade_confirm(clients: &mut ClientMap, state: &mut ShardServerState) -> F
     let trade = state.ongoing_trades.remove(&trade_id).unwrap();
     let id_from = trade.get_id_from();
     let id_to = trade.get_id_to();
-    if let Ok((items, items_other)) = trade.resolve((&mut player, &mut player_other)) {
+    let res = validate_trade_parties(&player, &player_other, config_get().shard.trade_range.get())
+        .and_then(|_| {
+            let goods = trade.get_offered_goods(&player)?;
+            let goods_other = trade.get_offered_goods(&player_other)?;
+            let items = trade.resolve((&mut player, &mut player_other))?;
+            Ok((items, goods, goods_other))
+        });
+    if let Ok(((items, items_other), goods, goods_other)) = res {
+        transaction_log_queue(Transaction {
+            kind: TransactionKind::Trade,
+            pc_uid: player.get_uid(),
+            other_pc_uid: Some(player_other.get_uid()),
+            taros_given: goods.0,
+            items_given: goods.1,
+            taros_received: goods_other.0,
+            items_received: goods_other.1,
+            time: SystemTime::now(),
+        });
+
         let player_taros = player.get_taros();
         let player_other_taros = player_other.get_taros();
 
         // save traded state
-        *state.get_player_mut(pc_id).unwrap() = player.clone();
-        *state.get_player_mut(pc_id_other).unwrap() = player_other.clone();
+        *state.get_player_mut(pc_id).unwrap() = player;
+        *state.get_player_mut(pc_id_other).unwrap() = player_other;
 
         // update the players in the DB
-        db_run_async(move |db| db.save_players(&[&player, &player_other]));
+        state.save_players_now(&[pc_id, pc_id_other], "trade save");
 
         let resp = sP_FE2CL_REP_PC_TRADE_CONFIRM_SUCC {
             iID_Request: pc_id,
@@ -1,6 +1,6 @@
 use rusty_fusion::{
     defines::*,
-    entity::{Combatant, Entity, EntityID},
+    entity::{Entity, EntityID},
     enums::*,
     error::*,
     net::{
@@ -29,48 +29,33 @@ pub fn regist_transportation_location(
 
             let player = state.get_player_mut(pc_id)?;
             let transport_type: TransportationType = pkt.eTT.try_into()?;
-            match transport_type {
-                TransportationType::Warp => {
-                    let location_data = tdata_get().get_scamper_data(pkt.iLocationID)?;
-                    if location_data.npc_type != npc_type {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to register a S.C.A.M.P.E.R. location with the wrong NPC type",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-                    player.unlock_scamper_location(pkt.iLocationID)?;
-                }
-                TransportationType::Wyvern => {
-                    let location_data = tdata_get().get_skyway_data(pkt.iLocationID)?;
-                    if location_data.npc_type != npc_type {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to register a Skyway location with the wrong NPC type",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-                    player.unlock_skyway_location(pkt.iLocationID)?;
-                }
+            let location_data = match transport_type {
+                TransportationType::Warp => tdata_get().get_scamper_data(pkt.iLocationID)?,
+                TransportationType::Wyvern => tdata_get().get_skyway_data(pkt.iLocationID)?,
                 other => {
                     return Err(FFError::build(
                         Severity::Warning,
                         format!("Can't register transportation type {:?}", other),
                     ));
                 }
+            };
+            if location_data.npc_type != npc_type {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} tried to register {:?} location {} with the wrong NPC type",
+                        player, transport_type, pkt.iLocationID
+                    ),
+                ));
             }
 
-            let resp = sP_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC {
-                eTT: pkt.eTT,
-                iLocationID: pkt.iLocationID,
-                iWarpLocationFlag: player.flags.scamper_flags.get_chunk(0).unwrap(),
-                aWyvernLocationFlag: player.flags.skyway_flags.to_array().unwrap(),
-            };
-            client.send_packet(P_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC, &resp)
+            player.unlock_transport_location(transport_type, pkt.iLocationID)?;
+            rusty_fusion::helpers::send_transport_location_unlocked(
+                client,
+                player,
+                transport_type,
+                pkt.iLocationID,
+            )
         })(),
         || {
             let resp = sP_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_FAIL {
@@ -104,106 +89,41 @@ pub fn warp_use_transportation(
             let player = state.get_player_mut(pc_id)?;
             let trip_id = pkt.iTransporationID;
             let trip = tdata_get().get_trip_data(trip_id)?;
-            if player.get_taros() < trip.cost {
-                return Err(FFError::build(
-                    Severity::Warning,
-                    format!(
-                        "Player {} doesn't have enough taros to warp",
-                        player.get_player_id()
-                    ),
-                ));
-            }
-
-            let new_taros = player.get_taros() - trip.cost;
-            match trip.transportation_type {
-                TransportationType::Warp => {
-                    let src_data = tdata_get().get_scamper_data(trip.start_location)?;
-                    if !player
-                        .is_scamper_location_unlocked(trip.start_location)
-                        .unwrap()
-                    {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp from an unregistered S.C.A.M.P.E.R. location",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    let dest_data = tdata_get().get_scamper_data(trip.end_location)?;
-                    if !player
-                        .is_scamper_location_unlocked(trip.end_location)
-                        .unwrap()
-                    {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp to an unregistered S.C.A.M.P.E.R. location",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    if src_data.npc_type != npc_type {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp with the wrong NPC type",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    player.set_taros(new_taros);
-                    player.set_position(dest_data.pos);
-                }
-                TransportationType::Wyvern => {
-                    let src_data = tdata_get().get_skyway_data(trip.start_location)?;
-                    if !player
-                        .is_skyway_location_unlocked(trip.start_location)
-                        .unwrap()
-                    {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp from an unregistered Skyway location",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    if !player.is_skyway_location_unlocked(trip.end_location)? {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp to an unregistered Skyway location",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    if src_data.npc_type != npc_type {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            format!(
-                                "Player {} tried to warp with the wrong NPC type",
-                                player.get_player_id()
-                            ),
-                        ));
-                    }
-
-                    let path = tdata_get().get_skyway_path(trip.route_number)?;
-                    player.set_active_nano_slot(None).unwrap();
-                    player.start_skyway_ride(trip, path);
-                    // we don't charge the player until the ride is done
-                }
+            let (src_data, dest_data) = match trip.transportation_type {
+                TransportationType::Warp => (
+                    tdata_get().get_scamper_data(trip.start_location)?,
+                    tdata_get().get_scamper_data(trip.end_location)?,
+                ),
+                TransportationType::Wyvern => (
+                    tdata_get().get_skyway_data(trip.start_location)?,
+                    tdata_get().get_skyway_data(trip.end_location)?,
+                ),
                 other => {
                     return Err(FFError::build(
                         Severity::Warning,
                         format!("Can't warp with transportation type {:?}", other),
                     ));
                 }
+            };
+
+            if src_data.npc_type != npc_type {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("{} tried to warp with the wrong NPC type", player),
+                ));
+            }
+
+            player.check_trip_requirements(trip).map_err(|(_, e)| e)?;
+
+            let new_taros = player.get_taros() - trip.cost;
+            if trip.transportation_type == TransportationType::Wyvern {
+                let legs = tdata_get().get_skyway_route(trip)?;
+                player.set_active_nano_slot(None).unwrap();
+                player.start_skyway_ride(trip, legs)?;
+                // we don't charge the player until the ride is done
+            } else {
+                player.add_taros(-(trip.cost as i64), CurrencySource::Transport);
+                player.set_position(dest_data.pos);
             }
 
             let new_pos = player.get_position();
@@ -218,6 +138,7 @@ pub fn warp_use_transportation(
                 .get_self()
                 .send_packet(P_FE2CL_REP_PC_WARP_USE_TRANSPORTATION_SUCC, &resp)?;
 
+            rusty_fusion::helpers::force_dismount(pc_id, clients, state)?;
             if trip.transportation_type == TransportationType::Wyvern {
                 rusty_fusion::helpers::broadcast_monkey(pc_id, RideType::Wyvern, clients, state);
             }
@@ -331,7 +252,18 @@ pub fn time_to_go_warp(clients: &mut ClientMap, state: &mut ShardServerState) ->
 mod helpers {
     #![allow(clippy::too_many_arguments)]
 
-    use rusty_fusion::{chunk::InstanceID, defines::TYPE_TIME_MACHINE, item::Item, util};
+    use std::time::{Duration, SystemTime};
+
+    use rusty_fusion::{
+        config::config_get,
+        defines::TYPE_TIME_MACHINE,
+        group_warp::{
+            gather_group_warp_members, notify_skipped_members, warp_group_member, GroupWarpDest,
+            PendingGroupWarp,
+        },
+        item::Item,
+        util,
+    };
 
     use super::*;
 
@@ -371,184 +303,107 @@ mod helpers {
             }
         }
 
+        // check every requirement before anything is taken from the player
         let player = state.get_player(pc_id)?;
-
-        if player.get_taros() < warp_data.cost {
+        player
+            .check_warp_requirements(
+                warp_data,
+                (req_item_location_ord, req_item_slot),
+                (req_item_consume_location_ord, req_item_consume_slot),
+                config_get().shard.gm_bypass_warp_requirements.get(),
+            )
+            .map_err(|(_, e)| e)?;
+
+        let group_id = player.group_id.filter(|_| warp_data.is_group_warp);
+        if group_id.is_some_and(|group_id| state.pending_group_warps.contains_key(&group_id)) {
             return Err(FFError::build(
                 Severity::Warning,
                 format!(
-                    "Player {} doesn't have enough taros to warp",
-                    player.get_player_id()
+                    "Player {} tried to group warp while another is pending",
+                    pc_id
                 ),
             ));
         }
 
-        if player.get_level() < warp_data.min_level {
-            return Err(FFError::build(
-                Severity::Warning,
-                format!(
-                    "Player {} isn't a high enough level to warp ({} < {})",
-                    player.get_player_id(),
-                    player.get_level(),
-                    warp_data.min_level
-                ),
-            ));
-        }
-
-        if let Some((item_type, item_id)) = warp_data.req_item {
-            let item = player
-                .get_item(req_item_location_ord.try_into()?, req_item_slot)?
-                .as_ref();
-            if !item.is_some_and(|item| item.ty == item_type && item.id == item_id) {
-                return Err(FFError::build(
-                    Severity::Warning,
-                    format!(
-                        "Player {} doesn't have the required item ({:?}) to warp",
-                        player.get_player_id(),
-                        warp_data.req_item
-                    ),
-                ));
-            }
-        }
-
-        // group proximity check
-        let position = player.get_position();
-        if warp_data.is_group_warp {
-            if let Some(group_id) = player.group_id {
-                let group = state.groups.get(&group_id).unwrap();
-                for member_id in group.get_member_ids() {
-                    if let EntityID::Player(member_pc_id) = *member_id {
-                        if member_pc_id == pc_id {
-                            continue;
-                        }
-
-                        let member = state.get_player(member_pc_id).unwrap();
-                        if member.get_position().distance_to(&position) > RANGE_GROUP_WARP {
-                            return Err(FFError::build(
-                                Severity::Warning,
-                                format!(
-                                    "Player {} tried to group warp with a group member too far away",
-                                    pc_id
-                                ),
-                            ));
-                        }
-                    }
-                }
-            }
-        }
+        // gather group members to bring along
+        let (member_pc_ids, skipped_members) = match group_id {
+            Some(group_id) => gather_group_warp_members(
+                pc_id,
+                group_id,
+                warp_data,
+                config_get().shard.group_warp_check_all_members.get(),
+                state,
+            )?,
+            None => (Vec::new(), Vec::new()),
+        };
 
         // good to warp
         let player = state.get_player_mut(pc_id)?;
-
-        let mut item_consumed = None;
-        if let Some((item_type, item_id)) = warp_data.req_item_consumed {
-            let item = player.get_item_mut(
-                req_item_consume_location_ord.try_into()?,
-                req_item_consume_slot,
-            )?;
-            if !item
-                .as_mut()
-                .is_some_and(|item| item.ty == item_type && item.id == item_id)
-            {
-                return Err(FFError::build(
-                    Severity::Warning,
-                    format!(
-                        "Player {} doesn't have the required consumed item ({:?}) to warp",
-                        player.get_player_id(),
-                        warp_data.req_item_consumed
-                    ),
-                ));
-            }
-            Item::split_items(item, 1); // consume item
-            item_consumed = *item;
-        }
-        player.set_taros(player.get_taros() - warp_data.cost);
-
-        let mut pc_ids_to_warp = vec![pc_id];
-        if warp_data.is_group_warp {
-            if let Some(group_id) = player.group_id {
-                let group = state.groups.get(&group_id).unwrap();
-                for member_id in group.get_member_ids() {
-                    if let EntityID::Player(member_pc_id) = *member_id {
-                        if member_pc_id == pc_id {
-                            continue;
-                        }
-                        pc_ids_to_warp.push(member_pc_id);
-                    }
-                }
-            }
-        }
+        let (payment, item_remaining) = player.pay_for_warp(
+            warp_data,
+            (req_item_consume_location_ord, req_item_consume_slot),
+        )?;
 
         let instance_num = if warp_data.is_instance {
             Some(util::rand_range_inclusive(1, u32::MAX))
         } else {
             None
         };
-        for warping_pc_id in pc_ids_to_warp {
-            let player = state.get_player_mut(warping_pc_id)?;
+
+        // the initiator goes first so a failed warp can be refunded before anyone moves
+        let warp_result = rusty_fusion::helpers::warp_player(
+            pc_id,
+            warp_data.pos,
+            warp_data.map_num,
+            instance_num,
+            clients,
+            state,
+        );
+        if let Err(e) = warp_result {
+            let player = state.get_player_mut(pc_id)?;
+            player.refund_warp(payment);
+            return Err(e);
+        }
+        if to_past {
+            let player = state.get_player_mut(pc_id)?;
             let client = player.get_client(clients).unwrap();
-            player.set_pre_warp();
-            player.set_position(warp_data.pos);
-            let instance_id = InstanceID {
-                channel_num: player.instance_id.channel_num,
-                map_num: warp_data.map_num,
-                instance_num,
-            };
-            player.instance_id = instance_id;
-
-            // force vehicle dismount
-            player.vehicle_speed = None;
-
-            if to_past {
-                player.set_future_done();
-
-                // remove all active tasks
-                for task_id in player.mission_journal.get_current_task_ids() {
-                    let task = player.mission_journal.remove_task(task_id).unwrap();
-                    for item_id in &task.get_task_def().delete_qitems {
-                        let qitem_slot = player.set_quest_item_count(*item_id, 0).unwrap();
-                        // client doesn't automatically delete qitems clientside
-                        let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
-                            eIL: ItemLocation::QInven as i32,
-                            iSlotNum: qitem_slot as i32,
-                        };
-                        log_if_failed(client.send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt));
-                    }
+            rusty_fusion::helpers::do_past_transition(player, client);
+        }
+        notify_skipped_members(pc_id, &skipped_members, clients, state);
 
-                    let resp = sP_FE2CL_REP_PC_TASK_STOP_SUCC { iTaskNum: task_id };
-                    log_if_failed(client.send_packet(P_FE2CL_REP_PC_TASK_STOP_SUCC, &resp));
+        let dest = GroupWarpDest {
+            pos: warp_data.pos,
+            map_num: warp_data.map_num,
+            instance_num,
+            to_past,
+        };
+        let consent_time = config_get().shard.group_warp_consent_time.get();
+        match group_id {
+            // members have to agree to come along
+            Some(group_id) if consent_time > 0 && !member_pc_ids.is_empty() => {
+                let initiator_name = state.get_player(pc_id)?.to_string();
+                let msg = format!(
+                    "{} used a group warp.\n\
+                    Type /accept within {} seconds to come along, or /decline to stay",
+                    initiator_name, consent_time
+                );
+                for member_pc_id in &member_pc_ids {
+                    let member = state.get_player(*member_pc_id)?;
+                    if let Some(client) = member.get_client(clients) {
+                        log_if_failed(rusty_fusion::helpers::send_system_message(client, &msg));
+                    }
                 }
+                let deadline = SystemTime::now() + Duration::from_secs(consent_time);
+                let warp = PendingGroupWarp::new(group_id, pc_id, &member_pc_ids, dest, deadline);
+                state.pending_group_warps.insert(group_id, warp);
             }
-
-            // generic NPC warp packet for players that are warping along.
-            // caller should reply with the correct packet for the initiator
-            if warping_pc_id != pc_id {
-                let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
-                    iX: player.get_position().x,
-                    iY: player.get_position().y,
-                    iZ: player.get_position().z,
-                    eIL: ItemLocation::end(),
-                    iItemSlotNum: unused!(),
-                    Item: None.into(),
-                    iCandy: player.get_taros() as i32,
-                };
-                log_if_failed(client.send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp));
+            _ => {
+                for member_pc_id in member_pc_ids {
+                    log_if_failed(warp_group_member(member_pc_id, &dest, clients, state));
+                }
             }
-
-            rusty_fusion::helpers::broadcast_state(
-                pc_id,
-                player.get_state_bit_flag(),
-                clients,
-                state,
-            );
-
-            // we remove the player from the chunk here and wait for PC_LOADING_COMPLETE to put them back.
-            // it needs to be done this way or the client will miss the PC/NPC_ENTER packets.
-            state
-                .entity_map
-                .update(EntityID::Player(pc_id), None, Some(clients));
         }
 
-        Ok(item_consumed)
+        Ok(item_remaining)
     }
 }
@@ -4,10 +4,13 @@ use std::{
     alloc::{self, Layout},
     collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
+    time::Duration,
 };
 
+use rand::seq::SliceRandom;
+
 use crate::{
-    config::config_get,
+    config::{config_get, ChannelPolicy, ChatRangeMode},
     defines::ID_OVERWORLD,
     entity::{Entity, EntityID, Player, NPC},
     error::{log, log_if_failed, panic_log, FFError, FFResult, Severity},
@@ -58,6 +61,27 @@ impl ChunkCoords {
             i: instance_id,
         }
     }
+
+    // Like from_pos_inst, but sticks with the current chunk
+    // until pos is more than margin past its edge
+    pub fn from_pos_inst_sticky(
+        pos: Position,
+        instance_id: InstanceID,
+        current: Option<ChunkCoords>,
+        margin: i32,
+    ) -> Self {
+        match current {
+            Some(current) if current.i == instance_id && current.contains(pos, margin) => current,
+            _ => Self::from_pos_inst(pos, instance_id),
+        }
+    }
+
+    fn contains(&self, pos: Position, margin: i32) -> bool {
+        let min_x = self.x * CHUNK_SIZE;
+        let min_y = self.y * CHUNK_SIZE;
+        (min_x - margin..min_x + CHUNK_SIZE + margin).contains(&pos.x)
+            && (min_y - margin..min_y + CHUNK_SIZE + margin).contains(&pos.y)
+    }
 }
 impl Display for ChunkCoords {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -69,6 +93,7 @@ pub const MAP_SQUARE_COUNT: i32 = 16; // how many map squares there are in each
 pub const NCHUNKS: usize = MAP_SQUARE_COUNT as usize * 8; // 16 map squares with side lengths of 8 chunks
 pub const MAP_SQUARE_SIZE: i32 = 51200;
 pub const MAP_BOUNDS: i32 = MAP_SQUARE_SIZE * MAP_SQUARE_COUNT; // top corner of (16, 16)
+pub const CHUNK_SIZE: i32 = MAP_BOUNDS / NCHUNKS as i32;
 
 fn get_visibility_range() -> usize {
     config_get().shard.visibility_range.get()
@@ -81,6 +106,93 @@ pub enum TickMode {
     Never,
 }
 
+// Spreads deferrable ticks across invocations when a tick runs over budget.
+// Entities are ticked in ID order from a rotating cursor so that
+// everything still gets ticked, just less often.
+#[derive(Debug, Default)]
+pub struct TickSlicer {
+    cursor: Option<i32>,
+    throttled: bool,
+}
+impl TickSlicer {
+    // Ticks as many of the deferrable IDs as fit in the budget (checked against `elapsed`,
+    // the time the whole tick has taken so far), always making progress by at least one.
+    // Returns how many were deferred.
+    pub fn run(
+        &mut self,
+        mut deferrable: Vec<i32>,
+        budget: Option<Duration>,
+        elapsed: impl Fn() -> Duration,
+        mut tick: impl FnMut(i32),
+    ) -> usize {
+        if deferrable.is_empty() {
+            return 0;
+        }
+        deferrable.sort_unstable();
+
+        // resume right after the last ID ticked
+        let start_idx = match self.cursor {
+            Some(cursor) => deferrable.partition_point(|id| *id <= cursor),
+            None => 0,
+        };
+        let total = deferrable.len();
+        let mut ticked = 0;
+        for i in 0..total {
+            if ticked > 0 && budget.is_some_and(|budget| elapsed() > budget) {
+                break;
+            }
+            let id = deferrable[(start_idx + i) % total];
+            tick(id);
+            self.cursor = Some(id);
+            ticked += 1;
+        }
+
+        let deferred = total - ticked;
+        if deferred > 0 && !self.throttled {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Tick over budget ({}ms); deferring {} of {} NPC ticks",
+                    elapsed().as_millis(),
+                    deferred,
+                    total
+                ),
+            );
+        } else if deferred == 0 && self.throttled {
+            log(Severity::Info, "Tick back within budget");
+        }
+        self.throttled = deferred > 0;
+        deferred
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+}
+
+// How many of each kind of entity are in one chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkPopulation {
+    pub players: usize,
+    pub npcs: usize,
+    pub eggs: usize,
+    pub sliders: usize,
+}
+impl ChunkPopulation {
+    pub fn total(&self) -> usize {
+        self.players + self.npcs + self.eggs + self.sliders
+    }
+
+    fn get_count_mut(&mut self, id: EntityID) -> &mut usize {
+        match id {
+            EntityID::Player(_) => &mut self.players,
+            EntityID::NPC(_) => &mut self.npcs,
+            EntityID::Egg(_) => &mut self.eggs,
+            EntityID::Slider(_) => &mut self.sliders,
+        }
+    }
+}
+
 struct RegistryEntry {
     entity: Box<dyn Entity>,
     chunk: Option<ChunkCoords>,
@@ -118,6 +230,14 @@ pub struct EntityMap {
     next_npc_id: u32,
     next_slider_id: u32,
     next_egg_id: u32,
+    // secondary indexes for player lookups. names only change through rename_player
+    player_ids_by_uid: HashMap<i64, i32>,
+    player_ids_by_name: HashMap<(String, String), i32>,
+    // entities (spectating GMs) that one other entity must never be told about
+    hidden_from: HashMap<EntityID, EntityID>,
+    // kept up to date as entities come and go so population reports don't need a full scan.
+    // only chunks with something in them have an entry
+    chunk_populations: HashMap<ChunkCoords, ChunkPopulation>,
 }
 
 impl EntityMap {
@@ -151,6 +271,10 @@ impl EntityMap {
         self.registry.keys().cloned()
     }
 
+    pub fn count(&self) -> usize {
+        self.registry.len()
+    }
+
     pub fn get_tickable_ids(&self) -> impl Iterator<Item = EntityID> + '_ {
         self.registry
             .iter()
@@ -176,6 +300,14 @@ impl EntityMap {
             })
     }
 
+    pub fn get_tick_mode(&self, id: EntityID) -> Option<TickMode> {
+        self.registry.get(&id).map(|entry| entry.tick_mode)
+    }
+
+    pub fn get_entity_chunk(&self, id: EntityID) -> Option<ChunkCoords> {
+        self.registry.get(&id).and_then(|entry| entry.chunk)
+    }
+
     pub fn get_around_entity(&mut self, id: EntityID) -> HashSet<EntityID> {
         let mut entities = HashSet::new();
         if let Some(coords) = self.registry.get(&id).and_then(|entry| entry.chunk) {
@@ -185,9 +317,73 @@ impl EntityMap {
                 }
             }
         }
+        if let Some(viewer) = self.hidden_from.get(&id) {
+            entities.remove(viewer);
+        }
         entities
     }
 
+    // Players who should hear chat from the given player.
+    // Instance mode only applies in private instances and is like chunk mode elsewhere
+    pub fn get_chat_recipients(
+        &mut self,
+        pc_id: i32,
+        mode: ChatRangeMode,
+        radius: u32,
+    ) -> Vec<i32> {
+        let speaker_id = EntityID::Player(pc_id);
+        let Some(speaker) = self.registry.get(&speaker_id) else {
+            return Vec::new();
+        };
+        let Some(speaker_chunk) = speaker.chunk else {
+            return Vec::new();
+        };
+        let speaker_pos = speaker.entity.get_position();
+
+        let max_distance = match mode {
+            ChatRangeMode::Radius => Some(radius),
+            ChatRangeMode::Instance if speaker_chunk.i.instance_num.is_some() => None,
+            _ => {
+                return self
+                    .get_around_entity(speaker_id)
+                    .into_iter()
+                    .filter_map(|id| match id {
+                        EntityID::Player(pc_id) => Some(pc_id),
+                        _ => None,
+                    })
+                    .collect();
+            }
+        };
+
+        self.registry
+            .iter()
+            .filter_map(|(id, entry)| {
+                let EntityID::Player(recipient_pc_id) = *id else {
+                    return None;
+                };
+                let in_instance = entry.chunk.is_some_and(|c| c.i == speaker_chunk.i);
+                let in_range = max_distance.map_or(true, |max| {
+                    entry.entity.get_position().distance_to(&speaker_pos) <= max
+                });
+                if !in_instance || !in_range || self.is_hidden_from(speaker_id, *id) {
+                    return None;
+                }
+                Some(recipient_pc_id)
+            })
+            .collect()
+    }
+
+    pub fn set_hidden_from(&mut self, id: EntityID, viewer: Option<EntityID>) {
+        match viewer {
+            Some(viewer) => self.hidden_from.insert(id, viewer),
+            None => self.hidden_from.remove(&id),
+        };
+    }
+
+    fn is_hidden_from(&self, id: EntityID, viewer: EntityID) -> bool {
+        self.hidden_from.get(&id) == Some(&viewer)
+    }
+
     pub fn get_player_ids(&self) -> impl Iterator<Item = i32> + '_ {
         self.registry.keys().filter_map(|id| {
             if let EntityID::Player(pc_id) = id {
@@ -198,6 +394,39 @@ impl EntityMap {
         })
     }
 
+    pub fn get_player_id_by_uid(&self, pc_uid: i64) -> Option<i32> {
+        self.player_ids_by_uid.get(&pc_uid).copied()
+    }
+
+    pub fn get_player_id_by_name(&self, first_name: &str, last_name: &str) -> Option<i32> {
+        self.player_ids_by_name
+            .get(&Self::get_name_key(first_name, last_name))
+            .copied()
+    }
+
+    fn get_name_key(first_name: &str, last_name: &str) -> (String, String) {
+        (
+            first_name.to_ascii_lowercase(),
+            last_name.to_ascii_lowercase(),
+        )
+    }
+
+    fn index_player(&mut self, pc_id: i32, player: &Player) {
+        self.player_ids_by_uid.insert(player.get_uid(), pc_id);
+        let name_key = Self::get_name_key(&player.first_name, &player.last_name);
+        self.player_ids_by_name.insert(name_key, pc_id);
+    }
+
+    fn unindex_player(&mut self, pc_id: i32, player: &Player) {
+        if self.player_ids_by_uid.get(&player.get_uid()) == Some(&pc_id) {
+            self.player_ids_by_uid.remove(&player.get_uid());
+        }
+        let name_key = Self::get_name_key(&player.first_name, &player.last_name);
+        if self.player_ids_by_name.get(&name_key) == Some(&pc_id) {
+            self.player_ids_by_name.remove(&name_key);
+        }
+    }
+
     pub fn find_players(&self, f: impl Fn(&Player) -> bool) -> Vec<i32> {
         self.registry
             .values()
@@ -328,6 +557,11 @@ impl EntityMap {
         if self.registry.contains_key(&id) {
             panic_log(&format!("Already tracking entity with id {:?}", id));
         }
+        if let (EntityID::Player(pc_id), Some(player)) =
+            (id, entity.as_any().downcast_ref::<Player>())
+        {
+            self.index_player(pc_id, player);
+        }
         let entry = RegistryEntry {
             entity,
             chunk: None,
@@ -338,18 +572,43 @@ impl EntityMap {
     }
 
     pub fn untrack(&mut self, id: EntityID) -> Box<dyn Entity> {
-        self.registry
-            .remove(&id)
-            .unwrap_or_else(|| {
-                panic_log(&format!("Entity with id {:?} already untracked", id));
-            })
-            .entity
+        let entry = self.registry.remove(&id).unwrap_or_else(|| {
+            panic_log(&format!("Entity with id {:?} already untracked", id));
+        });
+        if let Some(coords) = entry.chunk {
+            self.count_leaving(id, coords);
+        }
+        let entity = entry.entity;
+        if let (EntityID::Player(pc_id), Some(player)) =
+            (id, entity.as_any().downcast_ref::<Player>())
+        {
+            self.unindex_player(pc_id, player);
+        }
+        self.hidden_from
+            .retain(|hidden_id, viewer| *hidden_id != id && *viewer != id);
+        entity
     }
 
     pub fn mark_for_cleanup(&mut self, id: EntityID) {
         self.entities_to_cleanup.insert(id);
     }
 
+    // For entities moving along on their own. Going a little way past a chunk's edge
+    // doesn't change chunks yet, so hovering around the edge doesn't make everything
+    // nearby pop in and out
+    pub fn update_moved(
+        &mut self,
+        id: EntityID,
+        pos: Position,
+        instance_id: InstanceID,
+        client_map: Option<&mut ClientMap>,
+    ) {
+        let margin = config_get().shard.chunk_margin.get() as i32;
+        let to_chunk =
+            ChunkCoords::from_pos_inst_sticky(pos, instance_id, self.get_entity_chunk(id), margin);
+        self.update(id, Some(to_chunk), client_map);
+    }
+
     pub fn update(
         &mut self,
         id: EntityID,
@@ -385,7 +644,9 @@ impl EntityMap {
             if let Some(from_client) = from.get_client(client_map) {
                 // possible for the ID to be unregistered if the instance was cleaned up
                 if let Some(to) = self.get_entity_raw(*e) {
-                    log_if_failed(to.send_exit(from_client));
+                    if !self.is_hidden_from(*e, id) {
+                        log_if_failed(to.send_exit(from_client));
+                    }
                 }
             }
 
@@ -393,8 +654,10 @@ impl EntityMap {
             // possible for the ID to be unregistered if the instance was cleaned up
             if let Some(from) = self.get_entity_raw(*e) {
                 if let Some(from_client) = from.get_client(client_map) {
-                    let to = self.get_entity_raw(id).unwrap();
-                    log_if_failed(to.send_exit(from_client));
+                    if !self.is_hidden_from(id, *e) {
+                        let to = self.get_entity_raw(id).unwrap();
+                        log_if_failed(to.send_exit(from_client));
+                    }
                 }
             }
         }
@@ -404,15 +667,19 @@ impl EntityMap {
             // us to them
             let from = self.get_entity_raw(id).unwrap();
             if let Some(from_client) = from.get_client(client_map) {
-                let to = self.get_entity_raw(*e).unwrap();
-                log_if_failed(to.send_enter(from_client));
+                if !self.is_hidden_from(*e, id) {
+                    let to = self.get_entity_raw(*e).unwrap();
+                    log_if_failed(to.send_enter(from_client));
+                }
             }
 
             // them to us
             let from = self.get_entity_raw(*e).unwrap();
             if let Some(from_client) = from.get_client(client_map) {
-                let to = self.get_entity_raw(id).unwrap();
-                log_if_failed(to.send_enter(from_client));
+                if !self.is_hidden_from(id, *e) {
+                    let to = self.get_entity_raw(id).unwrap();
+                    log_if_failed(to.send_enter(from_client));
+                }
             }
         }
 
@@ -436,6 +703,31 @@ impl EntityMap {
         Ok(())
     }
 
+    // Changes a tracked player's name, keeping the name index in step
+    pub fn rename_player(
+        &mut self,
+        pc_id: i32,
+        first_name: String,
+        last_name: String,
+    ) -> FFResult<()> {
+        let player: &mut Player =
+            self.get_entity_mut(EntityID::Player(pc_id))
+                .ok_or(FFError::build(
+                    Severity::Warning,
+                    format!("Player with ID {} doesn't exist", pc_id),
+                ))?;
+        let old_key = Self::get_name_key(&player.first_name, &player.last_name);
+        let new_key = Self::get_name_key(&first_name, &last_name);
+        player.first_name = first_name;
+        player.last_name = last_name;
+
+        if self.player_ids_by_name.get(&old_key) == Some(&pc_id) {
+            self.player_ids_by_name.remove(&old_key);
+        }
+        self.player_ids_by_name.insert(new_key, pc_id);
+        Ok(())
+    }
+
     pub fn for_each_around(
         &mut self,
         id: EntityID,
@@ -451,18 +743,22 @@ impl EntityMap {
     }
 
     pub fn get_channel_population(&self, channel_num: u8) -> usize {
-        self.chunk_maps
-            .iter()
-            .filter(|(instance_id, _)| instance_id.channel_num == channel_num)
-            .map(|(_, chunk_map)| chunk_map.get_player_count())
-            .sum()
+        // count by tracked players rather than chunk contents so that
+        // players who are loading in or mid-warp are still counted
+        self.find_players(|player| player.instance_id.channel_num == channel_num)
+            .len()
     }
 
-    pub fn get_min_pop_channel_num(&self) -> u8 {
-        let num_channels = config_get().shard.num_channels.get();
-        (1..=num_channels)
-            .min_by_key(|channel_num| self.get_channel_population(*channel_num))
-            .unwrap()
+    pub fn get_channel_for_new_player(&self) -> u8 {
+        let config = &config_get().shard;
+        let populations: Vec<usize> = (1..=config.num_channels.get())
+            .map(|channel_num| self.get_channel_population(channel_num))
+            .collect();
+        select_channel(
+            &populations,
+            config.max_channel_pop.get(),
+            config.default_channel_policy.get(),
+        )
     }
 
     fn remove_from_chunk(&mut self, id: EntityID) -> HashSet<EntityID> {
@@ -477,6 +773,7 @@ impl EntityMap {
                     coords, id
                 ));
             }
+            self.count_leaving(id, coords);
             let coords_around = Self::get_coords_around(coords, get_visibility_range());
             for coords in coords_around {
                 if let Some(chunk) = self.get_chunk_mut(coords) {
@@ -506,6 +803,11 @@ impl EntityMap {
                 }
                 let entry = self.registry.get_mut(&id).unwrap();
                 entry.chunk = to_chunk;
+                *self
+                    .chunk_populations
+                    .entry(coords)
+                    .or_default()
+                    .get_count_mut(id) += 1;
                 let coords_around = Self::get_coords_around(coords, get_visibility_range());
                 for coords in coords_around {
                     if let Some(chunk) = self.get_chunk_mut(coords) {
@@ -521,6 +823,50 @@ impl EntityMap {
         affected
     }
 
+    fn count_leaving(&mut self, id: EntityID, coords: ChunkCoords) {
+        let Some(population) = self.chunk_populations.get_mut(&coords) else {
+            return;
+        };
+        let count = population.get_count_mut(id);
+        *count = count.saturating_sub(1);
+        if population.total() == 0 {
+            self.chunk_populations.remove(&coords);
+        }
+    }
+
+    pub fn get_chunk_population(&self, coords: ChunkCoords) -> ChunkPopulation {
+        self.chunk_populations
+            .get(&coords)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // The most crowded chunks in a channel, most crowded first
+    pub fn get_population_report(
+        &self,
+        channel_num: u8,
+        count: usize,
+    ) -> Vec<(ChunkCoords, ChunkPopulation)> {
+        let mut report: Vec<(ChunkCoords, ChunkPopulation)> = self
+            .chunk_populations
+            .iter()
+            .filter(|(coords, _)| coords.i.channel_num == channel_num)
+            .map(|(coords, population)| (*coords, *population))
+            .collect();
+        // ties broken by location so reports are stable
+        report.sort_by_key(|(coords, population)| {
+            (
+                std::cmp::Reverse(population.total()),
+                coords.i.map_num,
+                coords.i.instance_num,
+                coords.x,
+                coords.y,
+            )
+        });
+        report.truncate(count);
+        report
+    }
+
     fn get_chunk(&self, coords: ChunkCoords) -> Option<&Chunk> {
         if (0..NCHUNKS as i32).contains(&coords.x) && (0..NCHUNKS as i32).contains(&coords.y) {
             let chunk_map = self.chunk_maps.get(&coords.i)?;
@@ -681,6 +1027,10 @@ impl Default for EntityMap {
             next_npc_id: 1,
             next_slider_id: 1,
             next_egg_id: 1,
+            player_ids_by_uid: HashMap::new(),
+            player_ids_by_name: HashMap::new(),
+            hidden_from: HashMap::new(),
+            chunk_populations: HashMap::new(),
         }
     }
 }
@@ -715,3 +1065,590 @@ impl Chunk {
         self.tracked.remove(&id)
     }
 }
+
+// populations are indexed by channel number - 1
+fn select_channel(populations: &[usize], max_pop: usize, policy: ChannelPolicy) -> u8 {
+    let open_channels: Vec<usize> = (0..populations.len())
+        .filter(|idx| populations[*idx] < max_pop)
+        .collect();
+    let least_populated = |candidates: &[usize]| {
+        candidates
+            .iter()
+            .copied()
+            .min_by_key(|idx| populations[*idx])
+    };
+
+    let idx = match policy {
+        ChannelPolicy::First => open_channels.first().copied(),
+        ChannelPolicy::LeastPopulated => least_populated(&open_channels),
+        ChannelPolicy::Random => open_channels.choose(&mut rand::thread_rng()).copied(),
+    };
+    // if every channel is full, fall back to the least populated one
+    let idx = idx
+        .or_else(|| least_populated(&(0..populations.len()).collect::<Vec<_>>()))
+        .unwrap_or(0);
+    (idx + 1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::Any, cell::Cell, time::SystemTime};
+
+    use super::*;
+    use crate::{
+        entity::Combatant,
+        net::packet::PacketID,
+        rng::GameRng,
+        state::ShardServerState,
+        test_utils::{FakeClients, PlayerBuilder},
+    };
+
+    struct MockEntity {
+        id: EntityID,
+        pos: Position,
+    }
+    impl Entity for MockEntity {
+        fn get_id(&self) -> EntityID {
+            self.id
+        }
+        fn get_client<'a>(&self, _client_map: &'a mut ClientMap) -> Option<&'a mut FFClient> {
+            None
+        }
+        fn get_position(&self) -> Position {
+            self.pos
+        }
+        fn get_rotation(&self) -> i32 {
+            0
+        }
+        fn get_speed(&self) -> i32 {
+            0
+        }
+        fn get_chunk_coords(&self) -> ChunkCoords {
+            ChunkCoords::from_pos_inst(self.pos, InstanceID::default())
+        }
+        fn set_position(&mut self, pos: Position) {
+            self.pos = pos;
+        }
+        fn set_rotation(&mut self, _rotation: i32) {}
+        fn send_enter(&self, _client: &mut FFClient) -> FFResult<()> {
+            Ok(())
+        }
+        fn send_exit(&self, _client: &mut FFClient) -> FFResult<()> {
+            Ok(())
+        }
+        fn tick(
+            &mut self,
+            _time: &SystemTime,
+            _clients: &mut ClientMap,
+            _state: &mut ShardServerState,
+            _rng: &mut GameRng,
+        ) {
+        }
+        fn cleanup(&mut self, _clients: &mut ClientMap, _state: &mut ShardServerState) {}
+        fn as_combatant(&self) -> Option<&dyn Combatant> {
+            None
+        }
+        fn as_combatant_mut(&mut self) -> Option<&mut dyn Combatant> {
+            None
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    fn place(entity_map: &mut EntityMap, id: EntityID, pos: Position) {
+        let entity = entity_map.get_entity_raw_mut(id).unwrap();
+        entity.set_position(pos);
+        let chunk = entity.get_chunk_coords();
+        entity_map.update(id, Some(chunk), None);
+    }
+
+    #[test]
+    fn test_chunk_transition_visibility() {
+        let chunk_size = MAP_BOUNDS / NCHUNKS as i32;
+        let visibility_range = get_visibility_range() as i32;
+        let origin = Position {
+            x: chunk_size * 10 + chunk_size / 2,
+            y: chunk_size * 10 + chunk_size / 2,
+            z: 0,
+        };
+        let far = Position {
+            x: origin.x + chunk_size * (visibility_range + 1),
+            ..origin
+        };
+
+        let mut entity_map = EntityMap::default();
+        let summoner = EntityID::Player(1);
+        let watcher = EntityID::Player(2);
+        for id in [summoner, watcher] {
+            entity_map.track(Box::new(MockEntity { id, pos: origin }), TickMode::Never);
+        }
+
+        // watcher starts out of range, then moves to an adjacent chunk
+        place(&mut entity_map, summoner, origin);
+        place(&mut entity_map, watcher, far);
+        assert!(!entity_map.get_around_entity(summoner).contains(&watcher));
+
+        let adjacent = Position {
+            x: origin.x + chunk_size,
+            ..origin
+        };
+        place(&mut entity_map, watcher, adjacent);
+        assert_eq!(
+            entity_map.get_entity_chunk(watcher),
+            Some(ChunkCoords::from_pos_inst(adjacent, InstanceID::default()))
+        );
+        assert!(entity_map.get_around_entity(summoner).contains(&watcher));
+
+        // summoner transitions out mid-summon; once the map is updated
+        // for their new position, the broadcast set no longer includes the watcher
+        place(
+            &mut entity_map,
+            summoner,
+            Position {
+                x: far.x + chunk_size,
+                ..far
+            },
+        );
+        assert!(!entity_map.get_around_entity(summoner).contains(&watcher));
+
+        // and comes back
+        place(&mut entity_map, summoner, origin);
+        assert!(entity_map.get_around_entity(summoner).contains(&watcher));
+        assert!(entity_map.get_around_entity(watcher).contains(&summoner));
+
+        // entities in transit (no chunk) have nobody around them
+        entity_map.update(summoner, None, None);
+        assert!(entity_map.get_entity_chunk(summoner).is_none());
+        assert!(entity_map.get_around_entity(summoner).is_empty());
+    }
+
+    // A mover sitting just left of a chunk boundary, and a watcher who can only see
+    // the mover while the mover is in the chunk left of the boundary
+    fn setup_boundary(
+        fake_clients: &mut FakeClients,
+        watcher_key: usize,
+    ) -> (EntityMap, EntityID, Position) {
+        let boundary_x = CHUNK_SIZE * 20;
+        let y = CHUNK_SIZE * 10 + CHUNK_SIZE / 2;
+        let visibility_range = get_visibility_range() as i32;
+        let mover_pos = Position {
+            x: boundary_x - CHUNK_SIZE / 2,
+            y,
+            z: 0,
+        };
+        let watcher_pos = Position {
+            x: mover_pos.x - CHUNK_SIZE * visibility_range,
+            y,
+            z: 0,
+        };
+
+        let mut entity_map = EntityMap::default();
+        let mover = PlayerBuilder::new(1).pc_id(1).position(mover_pos).build();
+        let watcher = PlayerBuilder::new(2)
+            .pc_id(2)
+            .client_id(watcher_key)
+            .position(watcher_pos)
+            .build();
+        for player in [mover, watcher] {
+            let chunk = player.get_chunk_coords();
+            let eid = entity_map.track(Box::new(player), TickMode::Never);
+            entity_map.update(
+                eid,
+                Some(chunk),
+                Some(&mut fake_clients.get_map(watcher_key)),
+            );
+        }
+        assert!(entity_map
+            .get_around_entity(EntityID::Player(2))
+            .contains(&EntityID::Player(1)));
+        fake_clients.take_sent(watcher_key);
+
+        let boundary = Position {
+            x: boundary_x,
+            ..mover_pos
+        };
+        (entity_map, EntityID::Player(1), boundary)
+    }
+
+    #[test]
+    fn test_chunk_margin_no_churn() {
+        let mut fake_clients = FakeClients::default();
+        let watcher_key = fake_clients.add_game_client(Some(2));
+        let (mut entity_map, mover, boundary) = setup_boundary(&mut fake_clients, watcher_key);
+        let start_chunk = entity_map.get_entity_chunk(mover);
+        let margin = config_get().shard.chunk_margin.get() as i32;
+
+        // strafing back and forth over the boundary, never more than the margin past it
+        for i in 0..20 {
+            let offset = if i % 2 == 0 { margin - 1 } else { -margin };
+            let pos = Position {
+                x: boundary.x + offset,
+                ..boundary
+            };
+            entity_map.update_moved(
+                mover,
+                pos,
+                InstanceID::default(),
+                Some(&mut fake_clients.get_map(watcher_key)),
+            );
+        }
+        assert_eq!(entity_map.get_entity_chunk(mover), start_chunk);
+        assert!(fake_clients.take_sent(watcher_key).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_margin_crossing() {
+        let mut fake_clients = FakeClients::default();
+        let watcher_key = fake_clients.add_game_client(Some(2));
+        let (mut entity_map, mover, boundary) = setup_boundary(&mut fake_clients, watcher_key);
+        let margin = config_get().shard.chunk_margin.get() as i32;
+
+        let past = Position {
+            x: boundary.x + margin + 100,
+            ..boundary
+        };
+        entity_map.update_moved(
+            mover,
+            past,
+            InstanceID::default(),
+            Some(&mut fake_clients.get_map(watcher_key)),
+        );
+        assert_eq!(
+            entity_map.get_entity_chunk(mover),
+            Some(ChunkCoords::from_pos_inst(past, InstanceID::default()))
+        );
+        assert_eq!(
+            fake_clients.take_sent(watcher_key),
+            vec![PacketID::P_FE2CL_PC_EXIT]
+        );
+
+        // drifting back toward the boundary doesn't undo it
+        for offset in [0, -margin / 2, margin, -margin] {
+            let pos = Position {
+                x: boundary.x + offset,
+                ..boundary
+            };
+            entity_map.update_moved(
+                mover,
+                pos,
+                InstanceID::default(),
+                Some(&mut fake_clients.get_map(watcher_key)),
+            );
+        }
+        assert!(fake_clients.take_sent(watcher_key).is_empty());
+    }
+
+    #[test]
+    fn test_hidden_entity_visibility() {
+        let pos = Position {
+            x: MAP_BOUNDS / 2,
+            y: MAP_BOUNDS / 2,
+            z: 0,
+        };
+        let mut entity_map = EntityMap::default();
+        let spectator = EntityID::Player(1);
+        let target = EntityID::Player(2);
+        let bystander = EntityID::Player(3);
+        for id in [spectator, target, bystander] {
+            entity_map.track(Box::new(MockEntity { id, pos }), TickMode::Never);
+            place(&mut entity_map, id, pos);
+        }
+
+        // the spectator still sees the target, but broadcasts from them skip it
+        entity_map.set_hidden_from(spectator, Some(target));
+        let around_spectator = entity_map.get_around_entity(spectator);
+        assert!(!around_spectator.contains(&target));
+        assert!(around_spectator.contains(&bystander));
+        assert!(entity_map.get_around_entity(target).contains(&spectator));
+
+        entity_map.set_hidden_from(spectator, None);
+        assert!(entity_map.get_around_entity(spectator).contains(&target));
+
+        // untracking either side drops the pairing
+        entity_map.set_hidden_from(spectator, Some(target));
+        entity_map.update(target, None, None);
+        entity_map.untrack(target);
+        assert!(!entity_map.is_hidden_from(spectator, target));
+    }
+
+    #[test]
+    fn test_tick_slicer_rotation() {
+        const NUM_NPCS: i32 = 20;
+        let budget = Some(Duration::from_millis(5));
+        // each tick takes 2ms of simulated time
+        let clock = Cell::new(Duration::ZERO);
+        let elapsed = || clock.get();
+        let slow_tick = |ticked: &mut Vec<i32>, id: i32| {
+            clock.set(clock.get() + Duration::from_millis(2));
+            ticked.push(id);
+        };
+
+        let mut slicer = TickSlicer::default();
+        let mut ticked = Vec::new();
+        let mut invocations = 0;
+        while ticked.len() < NUM_NPCS as usize {
+            clock.set(Duration::ZERO);
+            // IDs come in unordered like they would from the registry
+            let ids: Vec<i32> = (1..=NUM_NPCS).rev().collect();
+            let deferred = slicer.run(ids, budget, elapsed, |id| slow_tick(&mut ticked, id));
+            // over budget after the third tick
+            assert_eq!(deferred, NUM_NPCS as usize - 3);
+            invocations += 1;
+            assert!(invocations <= NUM_NPCS);
+        }
+        assert_eq!(invocations, 7);
+        assert!(slicer.is_throttled());
+
+        // every NPC ticked once before anyone ticked twice, in rotating ID order
+        let expected: Vec<i32> = (1..=NUM_NPCS).collect();
+        assert_eq!(ticked[..NUM_NPCS as usize], expected);
+
+        // the next invocation picks up where the rotation left off
+        let last = *ticked.last().unwrap();
+        let mut next = Vec::new();
+        clock.set(Duration::ZERO);
+        slicer.run((1..=NUM_NPCS).collect(), budget, elapsed, |id| {
+            slow_tick(&mut next, id)
+        });
+        assert_eq!(next[0], last % NUM_NPCS + 1);
+
+        // a tick that's already over budget still makes progress
+        let mut next = Vec::new();
+        clock.set(Duration::from_secs(1));
+        let deferred = slicer.run((1..=NUM_NPCS).collect(), budget, elapsed, |id| {
+            slow_tick(&mut next, id)
+        });
+        assert_eq!(next.len(), 1);
+        assert_eq!(deferred, NUM_NPCS as usize - 1);
+    }
+
+    #[test]
+    fn test_tick_slicer_unthrottled() {
+        let elapsed = || Duration::from_secs(1);
+        let mut slicer = TickSlicer::default();
+        let mut ticked = Vec::new();
+        let deferred = slicer.run(vec![3, 1, 2], None, elapsed, |id| ticked.push(id));
+        assert_eq!(deferred, 0);
+        assert_eq!(ticked, vec![1, 2, 3]);
+        assert!(!slicer.is_throttled());
+
+        // IDs that disappeared since the last invocation don't break the rotation
+        let mut ticked = Vec::new();
+        slicer.run(vec![4, 1], Some(Duration::ZERO), elapsed, |id| {
+            ticked.push(id)
+        });
+        assert_eq!(ticked, vec![4]);
+        assert!(slicer.is_throttled());
+        assert_eq!(slicer.run(Vec::new(), None, elapsed, |_| ()), 0);
+    }
+
+    #[test]
+    fn test_select_channel() {
+        use ChannelPolicy::*;
+        const MAX_POP: usize = 10;
+
+        // single channel
+        assert_eq!(select_channel(&[0], MAX_POP, First), 1);
+        assert_eq!(select_channel(&[0], MAX_POP, LeastPopulated), 1);
+        assert_eq!(select_channel(&[0], MAX_POP, Random), 1);
+
+        // even populations; ties go to the lowest channel
+        let pops = [3, 3, 3];
+        assert_eq!(select_channel(&pops, MAX_POP, First), 1);
+        assert_eq!(select_channel(&pops, MAX_POP, LeastPopulated), 1);
+
+        // uneven populations
+        let pops = [8, 2, 5, 2];
+        assert_eq!(select_channel(&pops, MAX_POP, First), 1);
+        assert_eq!(select_channel(&pops, MAX_POP, LeastPopulated), 2);
+
+        // full channels are skipped
+        let pops = [10, 12, 9, 0];
+        assert_eq!(select_channel(&pops, MAX_POP, First), 3);
+        assert_eq!(select_channel(&pops, MAX_POP, LeastPopulated), 4);
+        for _ in 0..50 {
+            let channel = select_channel(&pops, MAX_POP, Random);
+            assert!(channel == 3 || channel == 4);
+        }
+
+        // all full; least populated is used regardless of policy
+        let pops = [12, 10, 11];
+        assert_eq!(select_channel(&pops, MAX_POP, First), 2);
+        assert_eq!(select_channel(&pops, MAX_POP, LeastPopulated), 2);
+        assert_eq!(select_channel(&pops, MAX_POP, Random), 2);
+    }
+
+    #[test]
+    fn test_chat_recipients() {
+        let visibility_range = get_visibility_range() as i32;
+        let origin = Position {
+            x: CHUNK_SIZE * 10 + CHUNK_SIZE / 2,
+            y: CHUNK_SIZE * 10 + CHUNK_SIZE / 2,
+            z: 0,
+        };
+        let near = Position {
+            x: origin.x + 1000,
+            ..origin
+        };
+        let out_of_view = Position {
+            x: origin.x + CHUNK_SIZE * (visibility_range + 1),
+            ..origin
+        };
+        let lair = InstanceID {
+            instance_num: Some(1),
+            ..Default::default()
+        };
+
+        let mut entity_map = EntityMap::default();
+        let overworld_players = [(1, origin), (2, near), (3, out_of_view)];
+        let lair_players = [(4, origin), (5, out_of_view)];
+        for (pc_id, pos) in overworld_players.iter().chain(lair_players.iter()) {
+            let id = EntityID::Player(*pc_id);
+            entity_map.track(Box::new(MockEntity { id, pos: *pos }), TickMode::Never);
+        }
+        for (pc_id, pos) in overworld_players {
+            place(&mut entity_map, EntityID::Player(pc_id), pos);
+        }
+        for (pc_id, pos) in lair_players {
+            let chunk = ChunkCoords::from_pos_inst(pos, lair);
+            entity_map.update(EntityID::Player(pc_id), Some(chunk), None);
+        }
+        // NPCs never receive chat
+        let npc_id = EntityID::NPC(1);
+        entity_map.track(
+            Box::new(MockEntity {
+                id: npc_id,
+                pos: near,
+            }),
+            TickMode::Never,
+        );
+        place(&mut entity_map, npc_id, near);
+
+        let recipients = |entity_map: &mut EntityMap, pc_id, mode, radius| {
+            let mut pc_ids = entity_map.get_chat_recipients(pc_id, mode, radius);
+            pc_ids.sort();
+            pc_ids
+        };
+        let past_view = (CHUNK_SIZE * (visibility_range + 2)) as u32;
+        let map = &mut entity_map;
+        assert_eq!(recipients(map, 1, ChatRangeMode::Chunk, 0), vec![1, 2]);
+        assert_eq!(recipients(map, 1, ChatRangeMode::Radius, 500), vec![1]);
+        assert_eq!(
+            recipients(map, 1, ChatRangeMode::Radius, past_view),
+            vec![1, 2, 3]
+        );
+        // the overworld isn't a private instance, so instance mode acts like chunk mode
+        assert_eq!(recipients(map, 1, ChatRangeMode::Instance, 0), vec![1, 2]);
+        assert_eq!(recipients(map, 4, ChatRangeMode::Instance, 0), vec![4, 5]);
+        assert_eq!(recipients(map, 4, ChatRangeMode::Chunk, 0), vec![4]);
+
+        // spectating GMs aren't heard by the player they're hidden from
+        map.set_hidden_from(EntityID::Player(1), Some(EntityID::Player(2)));
+        assert_eq!(
+            recipients(map, 1, ChatRangeMode::Radius, past_view),
+            vec![1, 3]
+        );
+        assert_eq!(recipients(map, 1, ChatRangeMode::Chunk, 0), vec![1]);
+    }
+
+    #[test]
+    fn test_chunk_population_counts() {
+        let pos_a = Position {
+            x: CHUNK_SIZE * 3 + CHUNK_SIZE / 2,
+            y: CHUNK_SIZE * 3 + CHUNK_SIZE / 2,
+            z: 0,
+        };
+        let pos_b = Position {
+            x: CHUNK_SIZE * 20 + CHUNK_SIZE / 2,
+            ..pos_a
+        };
+        let chunk_a = ChunkCoords::from_pos_inst(pos_a, InstanceID::default());
+        let chunk_b = ChunkCoords::from_pos_inst(pos_b, InstanceID::default());
+        let other_channel = InstanceID {
+            channel_num: 2,
+            ..Default::default()
+        };
+
+        let mut entity_map = EntityMap::default();
+        let ids = [
+            EntityID::Player(1),
+            EntityID::Player(2),
+            EntityID::NPC(1),
+            EntityID::NPC(2),
+            EntityID::Egg(1),
+            EntityID::Slider(1),
+        ];
+        for id in ids {
+            entity_map.track(Box::new(MockEntity { id, pos: pos_a }), TickMode::Never);
+        }
+        // tracked but not placed anywhere yet
+        assert_eq!(entity_map.get_chunk_population(chunk_a).total(), 0);
+
+        for id in ids {
+            place(&mut entity_map, id, pos_a);
+        }
+        assert_eq!(
+            entity_map.get_chunk_population(chunk_a),
+            ChunkPopulation {
+                players: 2,
+                npcs: 2,
+                eggs: 1,
+                sliders: 1,
+            }
+        );
+
+        // moving between chunks
+        place(&mut entity_map, EntityID::Player(2), pos_b);
+        place(&mut entity_map, EntityID::NPC(2), pos_b);
+        assert_eq!(entity_map.get_chunk_population(chunk_a).total(), 4);
+        assert_eq!(
+            entity_map.get_chunk_population(chunk_b),
+            ChunkPopulation {
+                players: 1,
+                npcs: 1,
+                ..Default::default()
+            }
+        );
+        // moving within a chunk changes nothing
+        place(&mut entity_map, EntityID::Player(2), pos_b);
+        assert_eq!(entity_map.get_chunk_population(chunk_b).players, 1);
+
+        // a different channel is its own report
+        let elsewhere = ChunkCoords::from_pos_inst(pos_a, other_channel);
+        entity_map.update(EntityID::Egg(1), Some(elsewhere), None);
+        let report = entity_map.get_population_report(1, 10);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].0, chunk_a);
+        assert_eq!(report[0].1.total(), 3);
+        assert_eq!(report[1].0, chunk_b);
+        assert_eq!(entity_map.get_population_report(1, 1).len(), 1);
+        assert_eq!(entity_map.get_population_report(2, 10).len(), 1);
+
+        // removing from the map, and untracking while still in a chunk like instance cleanup does
+        entity_map.update(EntityID::Slider(1), None, None);
+        entity_map.update(EntityID::Player(2), None, None);
+        entity_map.untrack(EntityID::Player(2));
+        entity_map.untrack(EntityID::Egg(1));
+        assert_eq!(
+            entity_map.get_chunk_population(chunk_a),
+            ChunkPopulation {
+                players: 1,
+                npcs: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(entity_map.get_chunk_population(chunk_b).players, 0);
+        assert!(entity_map.get_population_report(2, 10).is_empty());
+
+        // counts always match what the chunks actually hold
+        for (coords, population) in entity_map.get_population_report(1, usize::MAX) {
+            let chunk = entity_map.get_chunk(coords).unwrap();
+            assert_eq!(chunk.get_all().len(), population.total());
+            assert_eq!(chunk.get_player_count(), population.players);
+        }
+    }
+}
@@ -1,14 +1,35 @@
 use super::*;
 
+use crate::webhook::WebhookEventKind;
+
 define_setting!(LoggingLevelSetting, usize, 2_usize);
 define_setting!(LogWriteIntervalSetting, u64, 60_u64);
 define_setting!(LiveCheckTimeSetting, u64, 60_u64);
+define_setting!(LatencyWarnThresholdSetting, u64, 500_u64);
+define_setting!(SlowHandlerThresholdSetting, u64, 100_u64);
+define_setting!(HandlerStatsIntervalSetting, u64, 0_u64);
+define_setting!(HandlerPanicLimitSetting, usize, 10_usize);
+define_setting!(MaxOutboundBytesSetting, usize, 262144_usize);
+define_setting!(StrikeLimitSetting, usize, 10_usize);
+define_setting!(StrikeWindowSetting, u64, 60_u64);
 define_setting!(ServerKeySetting, String, "myserverkey".to_string());
+define_setting!(InterServerCompressionSetting, bool, false);
+define_setting!(CompressionThresholdSetting, usize, 128_usize);
 define_setting!(DbUsernameSetting, String, "admin".to_string());
 define_setting!(DbPasswordSetting, String, "mypassword".to_string());
 define_setting!(DbHostSetting, String, "localhost".to_string());
 define_setting!(DbPortSetting, u16, 5432_u16);
+define_setting!(DbAutoMigrateSetting, bool, true);
 define_setting!(TableDataPathSetting, String, "tabledata/".to_string());
+define_setting!(TableDataPatchesSetting, Vec<String>, Vec::<String>::new());
+define_setting!(WebhookUrlSetting, String, "".to_string());
+define_setting!(
+    WebhookEventsSetting,
+    Vec<WebhookEventKind>,
+    WebhookEventKind::all()
+);
+define_setting!(WebhookIntervalSetting, u64, 5_u64);
+define_setting!(WordFilterPathSetting, String, "wordfilter.toml".to_string());
 
 #[derive(Deserialize, Default)]
 pub struct GeneralConfig {
@@ -16,12 +37,27 @@ pub struct GeneralConfig {
     pub logging_level_file: LoggingLevelSetting,
     pub log_write_interval: LogWriteIntervalSetting,
     pub live_check_time: LiveCheckTimeSetting,
+    pub latency_warn_threshold: LatencyWarnThresholdSetting,
+    pub slow_handler_threshold: SlowHandlerThresholdSetting,
+    pub handler_stats_interval: HandlerStatsIntervalSetting,
+    pub handler_panic_limit: HandlerPanicLimitSetting,
+    pub max_outbound_bytes: MaxOutboundBytesSetting,
+    pub strike_limit: StrikeLimitSetting,
+    pub strike_window: StrikeWindowSetting,
     pub server_key: ServerKeySetting,
+    pub inter_server_compression: InterServerCompressionSetting,
+    pub compression_threshold: CompressionThresholdSetting,
     pub db_username: DbUsernameSetting,
     pub db_password: DbPasswordSetting,
     pub db_host: DbHostSetting,
     pub db_port: DbPortSetting,
+    pub db_auto_migrate: DbAutoMigrateSetting,
     pub table_data_path: TableDataPathSetting,
+    pub table_data_patches: TableDataPatchesSetting,
+    pub webhook_url: WebhookUrlSetting,
+    pub webhook_events: WebhookEventsSetting,
+    pub webhook_interval: WebhookIntervalSetting,
+    pub word_filter_path: WordFilterPathSetting,
 }
 
 #[cfg(test)]
@@ -37,11 +73,26 @@ mod tests {
         assert!(general.logging_level_file.is_set_to_default());
         assert!(general.log_write_interval.is_set_to_default());
         assert!(general.live_check_time.is_set_to_default());
+        assert!(general.latency_warn_threshold.is_set_to_default());
+        assert!(general.slow_handler_threshold.is_set_to_default());
+        assert!(general.handler_stats_interval.is_set_to_default());
+        assert!(general.handler_panic_limit.is_set_to_default());
+        assert!(general.max_outbound_bytes.is_set_to_default());
+        assert!(general.strike_limit.is_set_to_default());
+        assert!(general.strike_window.is_set_to_default());
         assert!(general.server_key.is_set_to_default());
+        assert!(general.inter_server_compression.is_set_to_default());
+        assert!(general.compression_threshold.is_set_to_default());
         assert!(general.db_username.is_set_to_default());
         assert!(general.db_password.is_set_to_default());
         assert!(general.db_host.is_set_to_default());
         assert!(general.db_port.is_set_to_default());
+        assert!(general.db_auto_migrate.is_set_to_default());
         assert!(general.table_data_path.is_set_to_default());
+        assert!(general.table_data_patches.is_set_to_default());
+        assert!(general.webhook_url.is_set_to_default());
+        assert!(general.webhook_events.is_set_to_default());
+        assert!(general.webhook_interval.is_set_to_default());
+        assert!(general.word_filter_path.is_set_to_default());
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use super::*;
 
 use crate::defines::CN_ACCOUNT_LEVEL__USER;
@@ -11,6 +13,16 @@ define_setting!(MotdPathSetting, String, "motd.txt");
 define_setting!(MonitorEnabledSetting, bool, false);
 define_setting!(MonitorAddrSetting, String, "127.0.0.1:8003");
 define_setting!(MonitorIntervalSetting, u64, 5_u64);
+define_setting!(MonitorWorldStateSetting, bool, false);
+define_setting!(CharacterSlotsSetting, usize, 4_usize);
+define_setting!(QueueBypassGmSetting, bool, true);
+define_setting!(
+    ShardAddrOverridesSetting,
+    HashMap<String, String>,
+    HashMap::<String, String>::new()
+);
+define_setting!(AuthTokensEnabledSetting, bool, false);
+define_setting!(AuthTokenPrefixSetting, String, "tok:");
 
 #[derive(Deserialize, Default)]
 pub struct LoginConfig {
@@ -23,6 +35,12 @@ pub struct LoginConfig {
     pub monitor_enabled: MonitorEnabledSetting,
     pub monitor_addr: MonitorAddrSetting,
     pub monitor_interval: MonitorIntervalSetting,
+    pub monitor_world_state: MonitorWorldStateSetting,
+    pub character_slots: CharacterSlotsSetting,
+    pub queue_bypass_gm: QueueBypassGmSetting,
+    pub shard_addr_overrides: ShardAddrOverridesSetting,
+    pub auth_tokens_enabled: AuthTokensEnabledSetting,
+    pub auth_token_prefix: AuthTokenPrefixSetting,
 }
 
 #[cfg(test)]
@@ -43,5 +61,11 @@ mod tests {
         assert!(login.monitor_enabled.is_set_to_default());
         assert!(login.monitor_addr.is_set_to_default());
         assert!(login.monitor_interval.is_set_to_default());
+        assert!(login.monitor_world_state.is_set_to_default());
+        assert!(login.character_slots.is_set_to_default());
+        assert!(login.queue_bypass_gm.is_set_to_default());
+        assert!(login.shard_addr_overrides.is_set_to_default());
+        assert!(login.auth_tokens_enabled.is_set_to_default());
+        assert!(login.auth_token_prefix.is_set_to_default());
     }
 }
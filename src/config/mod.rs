@@ -6,10 +6,16 @@ use crate::error::*;
 
 mod general_settings;
 mod login_settings;
+mod newcharacter_settings;
 mod shard_settings;
 
 pub use self::{
-    general_settings::GeneralConfig, login_settings::LoginConfig, shard_settings::ShardConfig,
+    general_settings::GeneralConfig,
+    login_settings::LoginConfig,
+    newcharacter_settings::{NewCharacterConfig, StartingItem, StartingNano},
+    shard_settings::{
+        ChannelPolicy, ChannelTemplate, ChatRangeMode, ItemRef, MapZone, ShardConfig,
+    },
 };
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
@@ -20,6 +26,7 @@ pub struct Config {
     pub general: GeneralConfig,
     pub login: LoginConfig,
     pub shard: ShardConfig,
+    pub newcharacter: NewCharacterConfig,
 }
 impl Config {
     fn load(path: &str) -> Option<Self> {
@@ -28,6 +35,7 @@ impl Config {
             general: Option<GeneralConfig>,
             login: Option<LoginConfig>,
             shard: Option<ShardConfig>,
+            newcharacter: Option<NewCharacterConfig>,
         }
         let file_read = std::fs::read_to_string(path);
         if let Err(e) = file_read {
@@ -51,6 +59,7 @@ impl Config {
             general: parsed.general.unwrap_or_default(),
             login: parsed.login.unwrap_or_default(),
             shard: parsed.shard.unwrap_or_default(),
+            newcharacter: parsed.newcharacter.unwrap_or_default(),
         })
     }
 }
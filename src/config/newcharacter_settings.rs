@@ -0,0 +1,182 @@
+use super::*;
+
+use std::collections::HashSet;
+
+use crate::{defines::*, enums::ItemType, tabledata::tdata_get};
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub struct StartingItem {
+    #[serde(rename = "type")]
+    pub ty: ItemType,
+    pub id: i16,
+    #[serde(default = "StartingItem::default_quantity")]
+    pub quantity: u16,
+    #[serde(default)]
+    pub equip: bool,
+}
+impl StartingItem {
+    fn default_quantity() -> u16 {
+        1
+    }
+
+    pub fn get_equip_slot(&self) -> Option<usize> {
+        match self.ty {
+            ItemType::Hand => Some(EQUIP_SLOT_HAND as usize),
+            ItemType::UpperBody => Some(EQUIP_SLOT_UPPERBODY as usize),
+            ItemType::LowerBody => Some(EQUIP_SLOT_LOWERBODY as usize),
+            ItemType::Foot => Some(EQUIP_SLOT_FOOT as usize),
+            ItemType::Head => Some(EQUIP_SLOT_HEAD as usize),
+            ItemType::Face => Some(EQUIP_SLOT_FACE as usize),
+            ItemType::Back => Some(EQUIP_SLOT_BACK as usize),
+            ItemType::Vehicle => Some(EQUIP_SLOT_VEHICLE as usize),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub struct StartingNano {
+    pub id: i16,
+    pub skill: Option<usize>,
+}
+
+define_setting!(LevelSetting, i16, 1_i16);
+define_setting!(TarosSetting, u32, 0_u32);
+define_setting!(FusionMatterSetting, u32, 0_u32);
+define_setting!(
+    ItemsSetting,
+    Vec<StartingItem>,
+    vec![StartingItem {
+        ty: ItemType::Hand,
+        id: ID_LIGHTNING_GUN,
+        quantity: 1,
+        equip: true,
+    }]
+);
+define_setting!(
+    NanosSetting,
+    Vec<StartingNano>,
+    vec![StartingNano {
+        id: ID_BUTTERCUP,
+        skill: Some(0),
+    }]
+);
+define_setting!(PositionSetting, [i32; 3], [632032, 187177, -5500]);
+define_setting!(SkipTutorialSetting, bool, false);
+
+#[derive(Deserialize, Default)]
+pub struct NewCharacterConfig {
+    pub level: LevelSetting,
+    pub taros: TarosSetting,
+    pub fusion_matter: FusionMatterSetting,
+    pub items: ItemsSetting,
+    pub nanos: NanosSetting,
+    pub position: PositionSetting,
+    pub skip_tutorial: SkipTutorialSetting,
+}
+impl NewCharacterConfig {
+    pub fn validate(&self) -> FFResult<()> {
+        let tdata = tdata_get();
+        let invalid = |msg: String| {
+            FFError::build(
+                Severity::Fatal,
+                format!("Invalid [newcharacter] config: {}", msg),
+            )
+        };
+
+        let level = self.level.get();
+        if !(1..=PC_LEVEL_MAX as i16).contains(&level) {
+            return Err(invalid(format!("level {} out of range", level)));
+        }
+        let stats = tdata.get_player_stats(level)?;
+
+        if self.taros.get() > PC_CANDY_MAX {
+            return Err(invalid(format!("taros {} over max", self.taros.get())));
+        }
+
+        if self.fusion_matter.get() > stats.fm_limit {
+            return Err(invalid(format!(
+                "fusion matter {} over limit for level {} ({})",
+                self.fusion_matter.get(),
+                level,
+                stats.fm_limit
+            )));
+        }
+
+        let mut equip_slots_used = HashSet::new();
+        let mut inven_slots_used = 0;
+        for item in self.items.get() {
+            tdata.get_item_stats(item.id, item.ty).map_err(|e| {
+                invalid(format!("item {:?} #{}: {}", item.ty, item.id, e.get_msg()))
+            })?;
+            if item.quantity == 0 {
+                return Err(invalid(format!(
+                    "item {:?} #{} has zero quantity",
+                    item.ty, item.id
+                )));
+            }
+
+            if item.equip {
+                let Some(slot_num) = item.get_equip_slot() else {
+                    return Err(invalid(format!(
+                        "item {:?} #{} can't be equipped",
+                        item.ty, item.id
+                    )));
+                };
+                if !equip_slots_used.insert(slot_num) {
+                    return Err(invalid(format!(
+                        "multiple items for equip slot {}",
+                        slot_num
+                    )));
+                }
+            } else {
+                inven_slots_used += 1;
+            }
+        }
+        if inven_slots_used > SIZEOF_INVEN_SLOT as usize {
+            return Err(invalid(format!(
+                "too many inventory items ({} > {})",
+                inven_slots_used, SIZEOF_INVEN_SLOT
+            )));
+        }
+
+        let mut nano_ids = HashSet::new();
+        for nano in self.nanos.get() {
+            let nano_stats = tdata
+                .get_nano_stats(nano.id)
+                .map_err(|e| invalid(format!("nano {}: {}", nano.id, e.get_msg())))?;
+            if let Some(skill) = nano.skill {
+                if skill >= nano_stats.skills.len() {
+                    return Err(invalid(format!(
+                        "nano {} has no skill at index {}",
+                        nano.id, skill
+                    )));
+                }
+            }
+            if !nano_ids.insert(nano.id) {
+                return Err(invalid(format!("nano {} listed more than once", nano.id)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Validate that every setting is defined with default value in config.toml.default
+    fn test_defaults() {
+        let config = Config::load("config.toml.default").unwrap();
+        let newcharacter = config.newcharacter;
+        assert!(newcharacter.level.is_set_to_default());
+        assert!(newcharacter.taros.is_set_to_default());
+        assert!(newcharacter.fusion_matter.is_set_to_default());
+        assert!(newcharacter.items.is_set_to_default());
+        assert!(newcharacter.nanos.is_set_to_default());
+        assert!(newcharacter.position.is_set_to_default());
+        assert!(newcharacter.skip_tutorial.is_set_to_default());
+    }
+}
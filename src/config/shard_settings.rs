@@ -1,18 +1,161 @@
+use std::collections::HashMap;
+
 use super::*;
 
+use crate::{defines::RANGE_INTERACT, enums::ItemType, item::Item, Position};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelPolicy {
+    First,
+    LeastPopulated,
+    Random,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRangeMode {
+    // everyone in the chunks around the speaker
+    Chunk,
+    // everyone within chat_radius of the speaker
+    Radius,
+    // everyone in the same private instance; like chunk outside of one
+    Instance,
+}
+
+// An area of a map, e.g. where players can fight each other
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub struct MapZone {
+    pub map_num: u32,
+    // x_min, y_min, x_max, y_max. the whole map if absent
+    pub bounds: Option<[i32; 4]>,
+}
+impl MapZone {
+    pub fn contains(&self, map_num: u32, pos: Position) -> bool {
+        if map_num != self.map_num {
+            return false;
+        }
+        match self.bounds {
+            Some([x_min, y_min, x_max, y_max]) => {
+                (x_min..=x_max).contains(&pos.x) && (y_min..=y_max).contains(&pos.y)
+            }
+            None => true,
+        }
+    }
+}
+
+// An item by type and ID, as in the item tables
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ItemRef {
+    #[serde(rename = "type")]
+    pub ty: ItemType,
+    pub id: i16,
+}
+impl ItemRef {
+    pub fn matches(&self, item: &Item) -> bool {
+        self.ty == item.ty && self.id == item.id
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub struct ChannelTemplate {
+    pub channel_num: u8,
+    #[serde(default)]
+    pub no_mobs: bool,
+    // only these NPC groups spawn. ungrouped NPCs like vendors always spawn
+    pub npc_group_whitelist: Option<Vec<i32>>,
+    // scales how many mob spawns there are; other NPCs are unaffected
+    #[serde(default = "ChannelTemplate::default_spawn_multiplier")]
+    pub spawn_multiplier: f32,
+}
+impl ChannelTemplate {
+    fn default_spawn_multiplier() -> f32 {
+        1.0
+    }
+
+    pub fn allows_spawn(&self, group_id: Option<i32>, is_mob: bool) -> bool {
+        if self.no_mobs && is_mob {
+            return false;
+        }
+        match (&self.npc_group_whitelist, group_id) {
+            (Some(whitelist), Some(group_id)) => whitelist.contains(&group_id),
+            _ => true,
+        }
+    }
+}
+
 define_setting!(LogPathSetting, String, "shard.log");
 define_setting!(ShardIDSetting, i32, 1_i32);
 define_setting!(ListenAddrSetting, String, "127.0.0.1:23001");
-define_setting!(ExternalAddrSetting, String, "127.0.0.1:23001");
+define_setting!(ExternalAddrSetting, String, "".to_string());
 define_setting!(LoginServerAddrSetting, String, "127.0.0.1:23000");
 define_setting!(LoginServerConnIntervalSetting, u64, 10_u64);
 define_setting!(LoginServerUpdateIntervalSetting, u64, 5_u64);
 define_setting!(NumChannelsSetting, u8, 1_u8);
 define_setting!(MaxChannelPopSetting, usize, 100_usize);
+define_setting!(
+    DefaultChannelPolicySetting,
+    ChannelPolicy,
+    ChannelPolicy::LeastPopulated
+);
 define_setting!(VisibilityRangeSetting, usize, 1_usize);
+define_setting!(ChunkMarginSetting, u32, 400_u32);
 define_setting!(AutosaveIntervalSetting, u64, 5_u64);
+define_setting!(ShutdownSaveTimeoutSetting, u64, 10_u64);
 define_setting!(NumSlidersSetting, usize, 20_usize);
 define_setting!(VehicleDurationSetting, u64, 10_080_u64);
+define_setting!(StyleAdvantageBonusSetting, f32, 0.25_f32);
+define_setting!(GroupWarpCheckAllMembersSetting, bool, false);
+define_setting!(GroupWarpConsentTimeSetting, u64, 15_u64);
+define_setting!(ChatFilterEnabledSetting, bool, false);
+define_setting!(TickBudgetSetting, u64, 100_u64);
+define_setting!(PastTransitionTaskIDSetting, i32, 0_i32);
+define_setting!(PastArrivalWarpIDSetting, i32, 28_i32);
+define_setting!(PastScamperUnlocksSetting, Vec<i32>, Vec::<i32>::new());
+define_setting!(PastSkywayUnlocksSetting, Vec<i32>, Vec::<i32>::new());
+define_setting!(OfferTimeoutSetting, u64, 60_u64);
+define_setting!(LoginDataTimeoutSetting, u64, 30_u64);
+define_setting!(PvpZonesSetting, Vec<MapZone>, Vec::<MapZone>::new());
+define_setting!(PvpDefeatTaroPenaltySetting, f32, 0.0_f32);
+define_setting!(DeathFmMultiplierSetting, f32, 1.0_f32);
+define_setting!(DeathFmExemptRacesSetting, bool, true);
+define_setting!(DeathFmExemptMapsSetting, Vec<i32>, Vec::<i32>::new());
+define_setting!(GuideChangeFmCostSetting, u32, 500_u32);
+define_setting!(CratePityEnabledSetting, bool, false);
+define_setting!(CratePityRateSetting, f32, 0.05_f32);
+define_setting!(JournalEnabledSetting, bool, false);
+define_setting!(JournalPathSetting, String, "shard.journal");
+define_setting!(SpectateDistanceSetting, u32, 1000_u32);
+define_setting!(GmPermsSetting, HashMap<String, i16>, HashMap::<String, i16>::new());
+define_setting!(CurrencyLogDbSetting, bool, false);
+define_setting!(TransactionLogEnabledSetting, bool, false);
+define_setting!(TransactionLogMinPriceSetting, u64, 10000_u64);
+define_setting!(TransactionLogRetentionSetting, u64, 30_u64);
+define_setting!(GmBypassWarpRequirementsSetting, bool, false);
+define_setting!(TransportUnlockRangeSetting, u32, 400_u32);
+define_setting!(TradeRangeSetting, u32, RANGE_INTERACT);
+define_setting!(BuddyWarpCooldownSetting, u64, 60_u64);
+define_setting!(NameChangeItemIDSetting, i16, 0_i16);
+define_setting!(StyleChangeItemIDSetting, i16, 0_i16);
+define_setting!(ShinyLifetimeSetting, u64, 300_u64);
+define_setting!(ChatRangeModeSetting, ChatRangeMode, ChatRangeMode::Chunk);
+define_setting!(ChatRadiusSetting, u32, 5000_u32);
+define_setting!(RngSeedSetting, u64, 0_u64);
+define_setting!(TrackedNpcTypesSetting, Vec<i32>, Vec::<i32>::new());
+define_setting!(ExitCountdownSetting, u64, 60_u64);
+define_setting!(SafeExitZonesSetting, Vec<MapZone>, Vec::<MapZone>::new());
+define_setting!(SummonCapPerChannelSetting, usize, 2000_usize);
+define_setting!(SummonCapPerMapSetting, usize, 500_usize);
+define_setting!(EntityHardCapSetting, usize, 100_000_usize);
+define_setting!(ReportsPerHourSetting, usize, 5_usize);
+define_setting!(SellConfirmRaritySetting, i8, 0_i8);
+define_setting!(SellConfirmWindowSetting, u64, 10_u64);
+define_setting!(SellBlacklistSetting, Vec<ItemRef>, Vec::<ItemRef>::new());
+define_setting!(
+    ChannelTemplatesSetting,
+    Vec<ChannelTemplate>,
+    Vec::<ChannelTemplate>::new()
+);
 
 #[derive(Deserialize, Default)]
 pub struct ShardConfig {
@@ -25,10 +168,83 @@ pub struct ShardConfig {
     pub login_server_update_interval: LoginServerUpdateIntervalSetting,
     pub num_channels: NumChannelsSetting,
     pub max_channel_pop: MaxChannelPopSetting,
+    pub default_channel_policy: DefaultChannelPolicySetting,
     pub visibility_range: VisibilityRangeSetting,
+    pub chunk_margin: ChunkMarginSetting,
     pub autosave_interval: AutosaveIntervalSetting,
+    pub shutdown_save_timeout: ShutdownSaveTimeoutSetting,
     pub num_sliders: NumSlidersSetting,
     pub vehicle_duration: VehicleDurationSetting,
+    pub style_advantage_bonus: StyleAdvantageBonusSetting,
+    pub group_warp_check_all_members: GroupWarpCheckAllMembersSetting,
+    pub group_warp_consent_time: GroupWarpConsentTimeSetting,
+    pub chat_filter_enabled: ChatFilterEnabledSetting,
+    pub tick_budget: TickBudgetSetting,
+    pub past_transition_task_id: PastTransitionTaskIDSetting,
+    pub past_arrival_warp_id: PastArrivalWarpIDSetting,
+    pub past_scamper_unlocks: PastScamperUnlocksSetting,
+    pub past_skyway_unlocks: PastSkywayUnlocksSetting,
+    pub offer_timeout: OfferTimeoutSetting,
+    pub login_data_timeout: LoginDataTimeoutSetting,
+    pub pvp_zones: PvpZonesSetting,
+    pub pvp_defeat_taro_penalty: PvpDefeatTaroPenaltySetting,
+    pub death_fm_multiplier: DeathFmMultiplierSetting,
+    pub death_fm_exempt_races: DeathFmExemptRacesSetting,
+    pub death_fm_exempt_maps: DeathFmExemptMapsSetting,
+    pub guide_change_fm_cost: GuideChangeFmCostSetting,
+    pub crate_pity_enabled: CratePityEnabledSetting,
+    pub crate_pity_rate: CratePityRateSetting,
+    pub journal_enabled: JournalEnabledSetting,
+    pub journal_path: JournalPathSetting,
+    pub spectate_distance: SpectateDistanceSetting,
+    pub gm_perms: GmPermsSetting,
+    pub currency_log_db: CurrencyLogDbSetting,
+    pub transaction_log_enabled: TransactionLogEnabledSetting,
+    pub transaction_log_min_price: TransactionLogMinPriceSetting,
+    pub transaction_log_retention: TransactionLogRetentionSetting,
+    pub gm_bypass_warp_requirements: GmBypassWarpRequirementsSetting,
+    pub transport_unlock_range: TransportUnlockRangeSetting,
+    pub trade_range: TradeRangeSetting,
+    pub buddy_warp_cooldown: BuddyWarpCooldownSetting,
+    pub name_change_item_id: NameChangeItemIDSetting,
+    pub style_change_item_id: StyleChangeItemIDSetting,
+    pub shiny_lifetime: ShinyLifetimeSetting,
+    pub channel_templates: ChannelTemplatesSetting,
+    pub chat_range_mode: ChatRangeModeSetting,
+    pub chat_radius: ChatRadiusSetting,
+    pub rng_seed: RngSeedSetting,
+    pub tracked_npc_types: TrackedNpcTypesSetting,
+    pub exit_countdown: ExitCountdownSetting,
+    pub safe_exit_zones: SafeExitZonesSetting,
+    pub summon_cap_per_channel: SummonCapPerChannelSetting,
+    pub summon_cap_per_map: SummonCapPerMapSetting,
+    pub entity_hard_cap: EntityHardCapSetting,
+    pub reports_per_hour: ReportsPerHourSetting,
+    pub sell_confirm_rarity: SellConfirmRaritySetting,
+    pub sell_confirm_window: SellConfirmWindowSetting,
+    pub sell_blacklist: SellBlacklistSetting,
+}
+impl ShardConfig {
+    // The address clients should connect to, which is just the listen address unless overridden
+    pub fn get_external_addr(&self) -> String {
+        let external_addr = self.external_addr.get();
+        if external_addr.is_empty() {
+            self.listen_addr.get()
+        } else {
+            external_addr
+        }
+    }
+
+    pub fn get_channel_template(&self, channel_num: u8) -> Option<ChannelTemplate> {
+        self.channel_templates
+            .get()
+            .into_iter()
+            .find(|t| t.channel_num == channel_num)
+    }
+
+    pub fn is_sell_blacklisted(&self, item: &Item) -> bool {
+        self.sell_blacklist.get().iter().any(|r| r.matches(item))
+    }
 }
 
 #[cfg(test)]
@@ -49,9 +265,95 @@ mod tests {
         assert!(shard.login_server_update_interval.is_set_to_default());
         assert!(shard.num_channels.is_set_to_default());
         assert!(shard.max_channel_pop.is_set_to_default());
+        assert!(shard.default_channel_policy.is_set_to_default());
         assert!(shard.visibility_range.is_set_to_default());
+        assert!(shard.chunk_margin.is_set_to_default());
         assert!(shard.autosave_interval.is_set_to_default());
+        assert!(shard.shutdown_save_timeout.is_set_to_default());
         assert!(shard.num_sliders.is_set_to_default());
         assert!(shard.vehicle_duration.is_set_to_default());
+        assert!(shard.style_advantage_bonus.is_set_to_default());
+        assert!(shard.group_warp_check_all_members.is_set_to_default());
+        assert!(shard.group_warp_consent_time.is_set_to_default());
+        assert!(shard.chat_filter_enabled.is_set_to_default());
+        assert!(shard.tick_budget.is_set_to_default());
+        assert!(shard.past_transition_task_id.is_set_to_default());
+        assert!(shard.past_arrival_warp_id.is_set_to_default());
+        assert!(shard.past_scamper_unlocks.is_set_to_default());
+        assert!(shard.past_skyway_unlocks.is_set_to_default());
+        assert!(shard.offer_timeout.is_set_to_default());
+        assert!(shard.login_data_timeout.is_set_to_default());
+        assert!(shard.pvp_zones.is_set_to_default());
+        assert!(shard.pvp_defeat_taro_penalty.is_set_to_default());
+        assert!(shard.death_fm_multiplier.is_set_to_default());
+        assert!(shard.death_fm_exempt_races.is_set_to_default());
+        assert!(shard.death_fm_exempt_maps.is_set_to_default());
+        assert!(shard.guide_change_fm_cost.is_set_to_default());
+        assert!(shard.crate_pity_enabled.is_set_to_default());
+        assert!(shard.crate_pity_rate.is_set_to_default());
+        assert!(shard.journal_enabled.is_set_to_default());
+        assert!(shard.journal_path.is_set_to_default());
+        assert!(shard.spectate_distance.is_set_to_default());
+        assert!(shard.gm_perms.is_set_to_default());
+        assert!(shard.currency_log_db.is_set_to_default());
+        assert!(shard.transaction_log_enabled.is_set_to_default());
+        assert!(shard.transaction_log_min_price.is_set_to_default());
+        assert!(shard.transaction_log_retention.is_set_to_default());
+        assert!(shard.gm_bypass_warp_requirements.is_set_to_default());
+        assert!(shard.transport_unlock_range.is_set_to_default());
+        assert!(shard.trade_range.is_set_to_default());
+        assert!(shard.buddy_warp_cooldown.is_set_to_default());
+        assert!(shard.name_change_item_id.is_set_to_default());
+        assert!(shard.style_change_item_id.is_set_to_default());
+        assert!(shard.shiny_lifetime.is_set_to_default());
+        assert!(shard.channel_templates.is_set_to_default());
+        assert!(shard.chat_range_mode.is_set_to_default());
+        assert!(shard.chat_radius.is_set_to_default());
+        assert!(shard.rng_seed.is_set_to_default());
+        assert!(shard.tracked_npc_types.is_set_to_default());
+        assert!(shard.exit_countdown.is_set_to_default());
+        assert!(shard.safe_exit_zones.is_set_to_default());
+        assert!(shard.summon_cap_per_channel.is_set_to_default());
+        assert!(shard.summon_cap_per_map.is_set_to_default());
+        assert!(shard.entity_hard_cap.is_set_to_default());
+        assert!(shard.reports_per_hour.is_set_to_default());
+        assert!(shard.sell_confirm_rarity.is_set_to_default());
+        assert!(shard.sell_confirm_window.is_set_to_default());
+        assert!(shard.sell_blacklist.is_set_to_default());
+    }
+
+    #[test]
+    fn test_sell_blacklist() {
+        let shard: ShardConfig =
+            toml::from_str("sell_blacklist = [{ type = 7, id = 5 }, { type = 0, id = 328 }]")
+                .unwrap();
+        assert!(shard.is_sell_blacklisted(&Item::new(ItemType::General, 5)));
+        assert!(shard.is_sell_blacklisted(&Item::new(ItemType::Hand, 328)));
+        // same ID, different type
+        assert!(!shard.is_sell_blacklisted(&Item::new(ItemType::Hand, 5)));
+        assert!(!shard.is_sell_blacklisted(&Item::new(ItemType::General, 6)));
+
+        assert!(!ShardConfig::default().is_sell_blacklisted(&Item::new(ItemType::General, 5)));
+    }
+
+    #[test]
+    fn test_map_zone_contains() {
+        let zone = MapZone {
+            map_num: 0,
+            bounds: Some([-100, 0, 100, 200]),
+        };
+        let pos = |x, y| Position { x, y, z: 0 };
+        assert!(zone.contains(0, pos(0, 0)));
+        assert!(zone.contains(0, pos(100, 200)));
+        assert!(!zone.contains(0, pos(101, 100)));
+        assert!(!zone.contains(0, pos(0, -1)));
+        assert!(!zone.contains(1, pos(0, 0)));
+
+        let whole_map = MapZone {
+            map_num: 7,
+            bounds: None,
+        };
+        assert!(whole_map.contains(7, pos(i32::MIN, i32::MAX)));
+        assert!(!whole_map.contains(0, pos(0, 0)));
     }
 }
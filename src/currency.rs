@@ -0,0 +1,123 @@
+use std::{collections::VecDeque, sync::Mutex, time::SystemTime};
+
+use crate::{
+    config::config_get,
+    database::db_run_async,
+    enums::{CurrencySource, RewardType},
+    error::{log, FFResult, Severity},
+};
+
+// how many recent mutations each player keeps in memory
+const CURRENCY_LOG_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrencyEvent {
+    pub currency: RewardType,
+    pub source: CurrencySource,
+    pub delta: i64,
+    pub balance: u32,
+    pub time: SystemTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CurrencyLog {
+    events: VecDeque<CurrencyEvent>,
+}
+impl CurrencyLog {
+    pub fn push(&mut self, event: CurrencyEvent) {
+        if self.events.len() == CURRENCY_LOG_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn get_events(&self) -> impl DoubleEndedIterator<Item = &CurrencyEvent> {
+        self.events.iter()
+    }
+}
+
+// events waiting to be written out to the DB, tagged with the player's UID
+static PENDING_EVENTS: Mutex<Vec<(i64, CurrencyEvent)>> = Mutex::new(Vec::new());
+
+pub fn currency_log_queue(pc_uid: i64, event: CurrencyEvent) {
+    if !config_get().shard.currency_log_db.get() {
+        return;
+    }
+    PENDING_EVENTS.lock().unwrap().push((pc_uid, event));
+}
+
+fn take_pending_events() -> Vec<(i64, CurrencyEvent)> {
+    std::mem::take(&mut *PENDING_EVENTS.lock().unwrap())
+}
+
+pub fn currency_log_flush() -> FFResult<()> {
+    let events = take_pending_events();
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let num_events = events.len();
+    let _ = db_run_async(move |db| db.log_currency_events(&events));
+    log(
+        Severity::Debug,
+        &format!("Queued {} currency events for the DB", num_events),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{Database, MockDatabase};
+
+    #[test]
+    fn test_currency_log_ring_buffer() {
+        let mut currency_log = CurrencyLog::default();
+        for i in 0..(CURRENCY_LOG_SIZE + 5) {
+            currency_log.push(CurrencyEvent {
+                currency: RewardType::Taros,
+                source: CurrencySource::MobKill,
+                delta: i as i64,
+                balance: 0,
+                time: SystemTime::now(),
+            });
+        }
+
+        let deltas: Vec<i64> = currency_log.get_events().map(|e| e.delta).collect();
+        assert_eq!(deltas.len(), CURRENCY_LOG_SIZE);
+        assert_eq!(deltas[0], 5);
+        assert_eq!(*deltas.last().unwrap(), (CURRENCY_LOG_SIZE + 4) as i64);
+    }
+
+    #[test]
+    fn test_currency_log_db_sink() {
+        let event = |source, delta| CurrencyEvent {
+            currency: RewardType::Taros,
+            source,
+            delta,
+            balance: 1000,
+            time: SystemTime::UNIX_EPOCH,
+        };
+
+        // the sink is off by default
+        currency_log_queue(1, event(CurrencySource::MobKill, 10));
+        assert!(take_pending_events().is_empty());
+
+        // queued events go out to the DB in one batch
+        PENDING_EVENTS.lock().unwrap().extend([
+            (1, event(CurrencySource::Trade, -50)),
+            (2, event(CurrencySource::Trade, 50)),
+        ]);
+        let events = take_pending_events();
+        assert_eq!(events.len(), 2);
+        assert!(take_pending_events().is_empty());
+
+        let mut db = MockDatabase::default();
+        db.log_currency_events(&events).unwrap();
+        assert_eq!(db.get_currency_events(), events.as_slice());
+        db.fail_next(1);
+        assert!(db.log_currency_events(&events).is_err());
+        assert_eq!(db.get_currency_events().len(), 2);
+    }
+}
@@ -0,0 +1,544 @@
+use std::fmt::Display;
+
+use crate::{
+    database::*,
+    defines::*,
+    entity::{BuddyListEntry, Entity, PlayerStyle},
+    enums::{ItemType, PlayerGuide},
+    item::Item,
+    nano::Nano,
+    net::packet::{sItemBase, sNano},
+    tabledata::tdata_get,
+    util::{self, Bitfield},
+    Position,
+};
+
+// An account as stored in an OpenFusion database
+#[derive(Debug, Clone)]
+pub struct OpenFusionAccount {
+    pub id: BigInt,
+    pub username: Text,
+    // OpenFusion uses bcrypt too, so these carry over as-is
+    pub password_hashed: Text,
+    pub account_level: Int,
+    pub banned_until: SystemTime,
+    pub ban_reason: Text,
+    pub players: Vec<OpenFusionPlayer>,
+}
+
+// A player as stored in an OpenFusion database. UIDs carry over unchanged
+#[derive(Debug, Clone, Default)]
+pub struct OpenFusionPlayer {
+    pub uid: BigInt,
+    pub slot_num: usize,
+    pub first_name: Text,
+    pub last_name: Text,
+    pub name_check: Int,
+    pub level: Int,
+    pub equipped_nano_ids: [Int; SIZEOF_NANO_CARRY_SLOT as usize],
+    // absent if they never finished making their character
+    pub style: Option<PlayerStyle>,
+    pub tutorial_flag: bool,
+    pub payzone_flag: bool,
+    pub position: Position,
+    pub angle: Int,
+    pub hp: Int,
+    pub fusion_matter: Int,
+    pub taros: Int,
+    pub weapon_boosts: Int,
+    pub nano_potions: Int,
+    pub guide: Int,
+    pub scamper_flags: Int,
+    pub skyway_flags: Bytes,
+    pub tip_flags: Bytes,
+    pub quest_flags: Bytes,
+    // (slot, item) with OpenFusion's equip, inventory, bank slot numbering
+    pub items: Vec<(usize, sItemBase)>,
+    // (item ID, count)
+    pub quest_items: Vec<(Int, Int)>,
+    pub nanos: Vec<sNano>,
+    pub buddy_uids: Vec<BigInt>,
+    pub blocked_uids: Vec<BigInt>,
+}
+impl OpenFusionPlayer {
+    fn get_name(&self) -> String {
+        format!("{} {} (UID {})", self.first_name, self.last_name, self.uid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Imported,
+    AlreadyImported,
+    Failed(String),
+}
+impl Display for ImportOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Imported => write!(f, "imported"),
+            Self::AlreadyImported => write!(f, "already imported, skipped"),
+            Self::Failed(msg) => write!(f, "failed: {}", msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub username: Text,
+    // set if the account itself couldn't be brought over
+    pub account_error: Option<String>,
+    // (player name, outcome)
+    pub players: Vec<(String, ImportOutcome)>,
+    // anything left behind, e.g. items the loaded tabledata doesn't have
+    pub dropped: Vec<String>,
+}
+impl ImportReport {
+    pub fn is_clean(&self) -> bool {
+        self.account_error.is_none()
+            && self.dropped.is_empty()
+            && self
+                .players
+                .iter()
+                .all(|(_, outcome)| !matches!(outcome, ImportOutcome::Failed(_)))
+    }
+}
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Account {}:", self.username)?;
+        if let Some(err) = &self.account_error {
+            writeln!(f, "  not imported: {}", err)?;
+        }
+        for (name, outcome) in &self.players {
+            writeln!(f, "  {}: {}", name, outcome)?;
+        }
+        for dropped in &self.dropped {
+            writeln!(f, "  dropped {}", dropped)?;
+        }
+        Ok(())
+    }
+}
+
+// Builds a RustyFusion player out of an OpenFusion one, checking everything against the
+// loaded tabledata. Whatever doesn't check out is left behind and noted in `dropped`
+pub fn convert_player(row: &OpenFusionPlayer, dropped: &mut Vec<String>) -> FFResult<Player> {
+    let tdata = tdata_get();
+    let mut player = Player::new(row.uid, row.slot_num);
+    player.style = row.style;
+    player.first_name = row.first_name.clone();
+    player.last_name = row.last_name.clone();
+    player.set_position(row.position);
+    player.set_rotation(row.angle);
+
+    player.set_taros(row.taros.max(0) as u32);
+    player.set_level(row.level as i16)?;
+    // fusion matter must be set after level
+    player.set_fusion_matter(row.fusion_matter.max(0) as u32, None);
+    player.set_hp(row.hp);
+    player.set_weapon_boosts(row.weapon_boosts.max(0) as u32);
+    player.set_nano_potions(row.nano_potions.max(0) as u32);
+
+    for nano_raw in &row.nanos {
+        let Some(mut nano) = Option::<Nano>::from(*nano_raw) else {
+            continue;
+        };
+        let Ok(stats) = tdata.get_nano_stats(nano.get_id()) else {
+            dropped.push(format!("unknown nano {}", nano.get_id()));
+            continue;
+        };
+        if let Some(skill_id) = nano.selected_skill {
+            if !stats.skills.contains(&skill_id) {
+                dropped.push(format!(
+                    "invalid tuning {} for nano {}",
+                    skill_id,
+                    nano.get_id()
+                ));
+                nano.tune(None);
+            }
+        }
+        player.set_nano(nano);
+    }
+    for (slot, nano_id) in row.equipped_nano_ids.iter().enumerate() {
+        let nano_id = *nano_id as i16;
+        if nano_id != 0 && player.get_nano(nano_id).is_some() {
+            player.change_nano(slot, Some(nano_id))?;
+        }
+    }
+
+    player.flags.tip_flags = Bitfield::from_bytes(&row.tip_flags);
+    player.flags.tutorial_flag = row.tutorial_flag;
+    player.flags.payzone_flag = row.payzone_flag;
+    player.flags.name_check = (row.name_check as i8).try_into()?;
+
+    let guide: PlayerGuide = (row.guide as i16).try_into()?;
+    player.set_guide_data(guide, player.get_total_guides());
+
+    player.flags.skyway_flags = Bitfield::from_bytes(&row.skyway_flags);
+    player.flags.scamper_flags = Bitfield::from_array([row.scamper_flags]);
+    player.mission_journal.completed_mission_flags = Bitfield::from_bytes(&row.quest_flags);
+
+    for (slot_num, item_raw) in &row.items {
+        let item: Option<Item> = match (*item_raw).try_into() {
+            Ok(item) => item,
+            Err(e) => {
+                dropped.push(format!(
+                    "malformed item {:?} in slot {}: {}",
+                    item_raw,
+                    slot_num,
+                    e.get_msg()
+                ));
+                continue;
+            }
+        };
+        let Some(item) = item else {
+            continue;
+        };
+        if tdata.get_item_stats(item.id, item.ty).is_err() {
+            dropped.push(format!(
+                "unknown item {} ({:?}) in slot {}",
+                item.id, item.ty, slot_num
+            ));
+            continue;
+        }
+        let placed = util::slot_num_to_loc_and_slot_num(*slot_num)
+            .and_then(|(loc, slot_num)| player.set_item(loc, slot_num, Some(item)));
+        if let Err(e) = placed {
+            dropped.push(format!(
+                "item {} ({:?}) in slot {}: {}",
+                item.id,
+                item.ty,
+                slot_num,
+                e.get_msg()
+            ));
+        }
+    }
+
+    for (item_id, count) in &row.quest_items {
+        let item_id = *item_id as i16;
+        if tdata.get_item_stats(item_id, ItemType::Quest).is_err() {
+            dropped.push(format!("unknown quest item {}", item_id));
+            continue;
+        }
+        if let Err(e) = player.set_quest_item_count(item_id, (*count).max(0) as usize) {
+            dropped.push(format!("quest item {}: {}", item_id, e.get_msg()));
+        }
+    }
+
+    Ok(player)
+}
+
+// Brings an OpenFusion account and its players over. Safe to run again; players that are
+// already in this account are skipped. Buddies come later in `import_buddies`
+pub fn import_account(
+    db: &mut dyn Database,
+    account: &OpenFusionAccount,
+    convert: impl Fn(&OpenFusionPlayer, &mut Vec<String>) -> FFResult<Player>,
+) -> ImportReport {
+    let mut report = ImportReport {
+        username: account.username.clone(),
+        ..Default::default()
+    };
+
+    let acc_id = match find_or_create_account(db, account) {
+        Ok(acc_id) => acc_id,
+        Err(e) => {
+            report.account_error = Some(e.get_msg().to_string());
+            return report;
+        }
+    };
+
+    for row in &account.players {
+        let name = row.get_name();
+        let outcome = match db.find_account_from_player(row.uid) {
+            Ok(owner) if owner.id == acc_id => ImportOutcome::AlreadyImported,
+            Ok(owner) => {
+                ImportOutcome::Failed(format!("UID already belongs to account {}", owner.username))
+            }
+            Err(_) => {
+                let mut dropped = Vec::new();
+                let outcome = match import_player(db, acc_id, row, &mut dropped, &convert) {
+                    Ok(()) => ImportOutcome::Imported,
+                    Err(e) => ImportOutcome::Failed(e.get_msg().to_string()),
+                };
+                report
+                    .dropped
+                    .extend(dropped.into_iter().map(|msg| format!("{}: {}", name, msg)));
+                outcome
+            }
+        };
+        report.players.push((name, outcome));
+    }
+    report
+}
+
+// Adds buddies and blocks for the account's players. Run once every account is in,
+// since buddies can be on other accounts
+pub fn import_buddies(
+    db: &mut dyn Database,
+    account: &OpenFusionAccount,
+    report: &mut ImportReport,
+) {
+    if report.account_error.is_some() {
+        return;
+    }
+
+    for row in &account.players {
+        let name = row.get_name();
+        let buddy_uids = row
+            .buddy_uids
+            .iter()
+            .filter(|uid| !row.blocked_uids.contains(uid))
+            .map(|uid| (*uid, false));
+        let blocked_uids = row.blocked_uids.iter().map(|uid| (*uid, true));
+
+        let mut entries = Vec::new();
+        for (buddy_uid, blocked) in buddy_uids.chain(blocked_uids) {
+            match load_buddy_entry(db, buddy_uid) {
+                Ok(mut entry) => {
+                    entry.blocked = blocked;
+                    entries.push(entry);
+                }
+                Err(e) => {
+                    report
+                        .dropped
+                        .push(format!("{}: buddy {}: {}", name, buddy_uid, e.get_msg()))
+                }
+            }
+        }
+        if entries.is_empty() {
+            continue;
+        }
+
+        let dropped = &mut report.dropped;
+        let res = edit_offline_player(db, row.uid, |player| {
+            for entry in entries {
+                let buddy_uid = entry.pc_uid;
+                if player.is_buddies_with(buddy_uid) {
+                    continue;
+                }
+                if let Err(e) = player.add_buddy(entry) {
+                    dropped.push(format!("{}: buddy {}: {}", name, buddy_uid, e.get_msg()));
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = res {
+            dropped.push(format!("{}: buddies: {}", name, e.get_msg()));
+        }
+    }
+}
+
+fn find_or_create_account(db: &mut dyn Database, account: &OpenFusionAccount) -> FFResult<BigInt> {
+    if let Some(existing) = db.find_account_from_username(&account.username)? {
+        // same hash means a previous run made it
+        if existing.password_hashed != account.password_hashed {
+            return Err(FFError::build(
+                Severity::Warning,
+                "Username is taken by a different account".to_string(),
+            ));
+        }
+        return Ok(existing.id);
+    }
+
+    let new_acc = db.create_account(&account.username, &account.password_hashed)?;
+    db.change_account_level(new_acc.id, account.account_level)?;
+    if account.banned_until > SystemTime::now() {
+        db.ban_account(new_acc.id, account.banned_until, account.ban_reason.clone())?;
+    }
+    Ok(new_acc.id)
+}
+
+fn import_player(
+    db: &mut dyn Database,
+    acc_id: BigInt,
+    row: &OpenFusionPlayer,
+    dropped: &mut Vec<String>,
+    convert: &impl Fn(&OpenFusionPlayer, &mut Vec<String>) -> FFResult<Player>,
+) -> FFResult<()> {
+    let player = convert(row, dropped)?;
+    db.init_player(acc_id, &player)?;
+    if let Err(e) = db.save_player(&player) {
+        // don't leave a half-imported player for the next run to skip
+        log_if_failed(db.delete_player(player.get_uid()));
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn load_buddy_entry(db: &mut dyn Database, buddy_uid: BigInt) -> FFResult<BuddyListEntry> {
+    let account = db.find_account_from_player(buddy_uid)?;
+    let buddy = db.load_player(account.id, buddy_uid)?;
+    if buddy.style.is_none() {
+        return Err(FFError::build(
+            Severity::Warning,
+            "Buddy never finished making their character".to_string(),
+        ));
+    }
+    Ok(BuddyListEntry::new(&buddy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::PlayerBuilder;
+
+    const STYLE: PlayerStyle = PlayerStyle {
+        gender: 1,
+        face_style: 1,
+        hair_style: 1,
+        hair_color: 1,
+        skin_color: 1,
+        eye_color: 1,
+        height: 1,
+        body: 1,
+    };
+
+    // stands in for `convert_player`, which needs the real tabledata
+    fn mock_convert(row: &OpenFusionPlayer, dropped: &mut Vec<String>) -> FFResult<Player> {
+        if row.level == 0 {
+            return Err(FFError::build(Severity::Warning, "Bad level".to_string()));
+        }
+        if !row.items.is_empty() {
+            dropped.push("an item".to_string());
+        }
+        let mut player = PlayerBuilder::new(row.uid).build();
+        player.first_name = row.first_name.clone();
+        player.last_name = row.last_name.clone();
+        player.style = row.style;
+        Ok(player)
+    }
+
+    fn account(username: &str, players: Vec<OpenFusionPlayer>) -> OpenFusionAccount {
+        OpenFusionAccount {
+            id: 1,
+            username: username.to_string(),
+            password_hashed: format!("{}-hash", username),
+            account_level: 30,
+            banned_until: SystemTime::UNIX_EPOCH,
+            ban_reason: String::new(),
+            players,
+        }
+    }
+
+    fn player_row(uid: BigInt, first_name: &str) -> OpenFusionPlayer {
+        OpenFusionPlayer {
+            uid,
+            slot_num: 1,
+            first_name: first_name.to_string(),
+            last_name: "Imported".to_string(),
+            level: 1,
+            style: Some(STYLE),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_import_account() {
+        let mut db = MockDatabase::default();
+        let mut acc = account("alice", vec![player_row(10, "Alice")]);
+        acc.players[0].items.push((
+            0,
+            sItemBase {
+                iType: 0,
+                iID: 1,
+                iOpt: 1,
+                iTimeLimit: 0,
+            },
+        ));
+        acc.banned_until = SystemTime::now() + Duration::from_secs(3600);
+        acc.ban_reason = "Cheating".to_string();
+
+        let report = import_account(&mut db, &acc, mock_convert);
+        assert_eq!(report.account_error, None);
+        assert_eq!(report.players[0].1, ImportOutcome::Imported);
+        assert_eq!(report.dropped, vec!["Alice Imported (UID 10): an item"]);
+        assert!(!report.is_clean());
+
+        let new_acc = db
+            .find_account_from_username(&"alice".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(new_acc.password_hashed, "alice-hash");
+        assert_eq!(new_acc.account_level, 30);
+        assert_eq!(new_acc.ban_reason, "Cheating");
+        let player = db.load_player(new_acc.id, 10).unwrap();
+        assert_eq!(player.first_name, "Alice");
+
+        // running again changes nothing
+        let report = import_account(&mut db, &acc, mock_convert);
+        assert_eq!(report.players[0].1, ImportOutcome::AlreadyImported);
+        assert!(report.dropped.is_empty());
+
+        // a different account already using the name is left alone
+        let mut other = account("alice", vec![player_row(11, "Mallory")]);
+        other.password_hashed = "other-hash".to_string();
+        let report = import_account(&mut db, &other, mock_convert);
+        assert!(report.account_error.is_some());
+        assert!(report.players.is_empty());
+        assert!(db.find_account_from_player(11).is_err());
+    }
+
+    #[test]
+    fn test_import_failures() {
+        let mut db = MockDatabase::default();
+        import_account(
+            &mut db,
+            &account("bob", vec![player_row(20, "Bob")]),
+            mock_convert,
+        );
+
+        let mut broken = player_row(31, "Broken");
+        broken.level = 0;
+        let acc = account("carol", vec![player_row(20, "Carol"), broken]);
+        let report = import_account(&mut db, &acc, mock_convert);
+        assert!(matches!(report.players[0].1, ImportOutcome::Failed(_)));
+        assert!(matches!(report.players[1].1, ImportOutcome::Failed(_)));
+        assert!(!report.is_clean());
+        assert!(db.find_account_from_player(31).is_err());
+    }
+
+    #[test]
+    fn test_import_buddies() {
+        let mut db = MockDatabase::default();
+        let mut alice = player_row(10, "Alice");
+        alice.buddy_uids = vec![20, 99];
+        alice.blocked_uids = vec![30, 40];
+        let mut bob = player_row(20, "Bob");
+        bob.buddy_uids = vec![10];
+        let mut accounts = [
+            account("alice", vec![alice]),
+            account("bob", vec![bob]),
+            account("carol", vec![player_row(30, "Carol")]),
+            account("dave", vec![player_row(40, "Dave")]),
+        ];
+        accounts[2].players[0].style = None;
+
+        let mut reports: Vec<ImportReport> = accounts
+            .iter()
+            .map(|acc| import_account(&mut db, acc, mock_convert))
+            .collect();
+        for (acc, report) in accounts.iter().zip(reports.iter_mut()) {
+            import_buddies(&mut db, acc, report);
+        }
+        // no such player, and one who never finished their character
+        assert_eq!(reports[0].dropped.len(), 2);
+        assert!(reports[1].is_clean());
+
+        let alice_acc = db.find_account_from_player(10).unwrap();
+        let alice = db.load_player(alice_acc.id, 10).unwrap();
+        assert_eq!(alice.get_buddy_uids(), vec![20]);
+        assert_eq!(alice.get_blocked_uids(), vec![40]);
+        let bob_acc = db.find_account_from_player(20).unwrap();
+        assert_eq!(
+            db.load_player(bob_acc.id, 20).unwrap().get_buddy_uids(),
+            vec![10]
+        );
+
+        // running again doesn't double anything up
+        for (acc, report) in accounts.iter().zip(reports.iter_mut()) {
+            import_buddies(&mut db, acc, report);
+        }
+        let alice = db.load_player(alice_acc.id, 10).unwrap();
+        assert_eq!(alice.get_num_buddies(), 2);
+    }
+}
@@ -0,0 +1,1043 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{database::*, defines::*, util};
+
+// Keeps everything in memory, for tests and for running without a database server.
+// Nothing survives a restart
+#[derive(Debug, Default)]
+pub struct MockDatabase {
+    accounts: HashMap<BigInt, Account>,
+    next_acc_id: BigInt,
+    // player UID -> (account ID, player)
+    players: HashMap<BigInt, (BigInt, Player)>,
+    // account ID -> ignored player UIDs
+    ignores: HashMap<BigInt, HashSet<BigInt>>,
+    redeem_codes: HashMap<Text, RedeemCode>,
+    // (player UID, code)
+    redemptions: Vec<(BigInt, Text)>,
+    currency_events: Vec<(BigInt, CurrencyEvent)>,
+    race_results: Vec<(BigInt, RaceResult)>,
+    // player UID -> kills
+    kill_counts: HashMap<BigInt, BigInt>,
+    // token ID -> (account ID, token hash, expiry, used)
+    auth_tokens: HashMap<Text, (BigInt, Text, SystemTime, bool)>,
+    transactions: Vec<Transaction>,
+    reports: Vec<Report>,
+    fail_count: usize,
+    latency: Option<Duration>,
+}
+impl MockDatabase {
+    pub fn connect(_config: &GeneralConfig) -> FFResult<Box<dyn Database>> {
+        log(
+            Severity::Warning,
+            "Using the in-memory mock database; nothing will be saved",
+        );
+        Ok(Box::new(Self::default()))
+    }
+
+    // Makes the next `count` calls fail
+    pub fn fail_next(&mut self, count: usize) {
+        self.fail_count = count;
+    }
+
+    // Makes every call take at least this long
+    pub fn set_latency(&mut self, latency: Option<Duration>) {
+        self.latency = latency;
+    }
+
+    pub fn get_currency_events(&self) -> &[(BigInt, CurrencyEvent)] {
+        &self.currency_events
+    }
+
+    fn check_faults(&mut self, op: &str) -> FFResult<()> {
+        if let Some(latency) = self.latency {
+            std::thread::sleep(latency);
+        }
+
+        if self.fail_count > 0 {
+            self.fail_count -= 1;
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Database error: injected failure in {}", op),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_account_mut(&mut self, acc_id: BigInt) -> FFResult<&mut Account> {
+        self.accounts.get_mut(&acc_id).ok_or(FFError::build(
+            Severity::Warning,
+            format!("Account with ID {} not found", acc_id),
+        ))
+    }
+
+    fn get_player_mut(&mut self, pc_uid: BigInt) -> FFResult<&mut Player> {
+        self.players
+            .get_mut(&pc_uid)
+            .map(|(_, player)| player)
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Player with UID {} not found", pc_uid),
+            ))
+    }
+
+    fn get_player_account_id(&self, pc_uid: BigInt) -> FFResult<BigInt> {
+        self.players
+            .get(&pc_uid)
+            .map(|(acc_id, _)| *acc_id)
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Player with UID {} not found", pc_uid),
+            ))
+    }
+
+    fn load_player_internal(&self, acc_id: BigInt, player: &Player) -> FFResult<Player> {
+        let account = self.accounts.get(&acc_id).ok_or(FFError::build(
+            Severity::Warning,
+            format!("Account with ID {} not found", acc_id),
+        ))?;
+        let mut player = player.clone();
+        player.perms = account.account_level;
+        player.menuchat_only = !account.free_chat_allowed;
+        player.clear_ignores();
+        // cooldowns only last the session
+        player.clear_skill_cooldowns();
+        for ignored_uid in self.ignores.get(&acc_id).into_iter().flatten() {
+            log_if_failed(player.ignore_player(*ignored_uid).map(|_| ()));
+        }
+        Ok(player)
+    }
+}
+
+impl Migrate for MockDatabase {
+    fn get_schema_version(&mut self) -> FFResult<Int> {
+        Ok(DB_VERSION)
+    }
+
+    fn migrate_to(&mut self, version: Int) -> FFResult<()> {
+        Err(FFError::build(
+            Severity::Fatal,
+            format!("No migration to database version {}", version),
+        ))
+    }
+}
+
+impl Database for MockDatabase {
+    fn find_account_from_username(&mut self, username: &Text) -> FFResult<Option<Account>> {
+        self.check_faults("find_account_from_username")?;
+        Ok(self
+            .accounts
+            .values()
+            .find(|acc| acc.username == *username)
+            .cloned())
+    }
+
+    fn find_account_from_player(&mut self, pc_uid: BigInt) -> FFResult<Account> {
+        self.check_faults("find_account_from_player")?;
+        self.players
+            .get(&pc_uid)
+            .and_then(|(acc_id, _)| self.accounts.get(acc_id))
+            .cloned()
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Account not found for player with UID {}", pc_uid),
+            ))
+    }
+
+    fn find_banned_accounts(&mut self, pc_uids: &[BigInt]) -> FFResult<Vec<Account>> {
+        self.check_faults("find_banned_accounts")?;
+        let now = SystemTime::now();
+        let mut acc_ids: Vec<BigInt> = pc_uids
+            .iter()
+            .filter_map(|pc_uid| self.players.get(pc_uid).map(|(acc_id, _)| *acc_id))
+            .collect();
+        acc_ids.sort();
+        acc_ids.dedup();
+        Ok(acc_ids
+            .iter()
+            .filter_map(|acc_id| self.accounts.get(acc_id))
+            .filter(|acc| acc.banned_until > now)
+            .cloned()
+            .collect())
+    }
+
+    fn create_account(&mut self, username: &Text, password_hashed: &Text) -> FFResult<Account> {
+        self.check_faults("create_account")?;
+        if self.accounts.values().any(|acc| acc.username == *username) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Account with username {} already exists", username),
+            ));
+        }
+
+        let acc_level = if self.accounts.is_empty() {
+            CN_ACCOUNT_LEVEL__MASTER
+        } else {
+            config_get().login.default_account_level.get()
+        } as i16;
+
+        self.next_acc_id += 1;
+        let account = Account {
+            id: self.next_acc_id,
+            username: username.clone(),
+            password_hashed: password_hashed.clone(),
+            selected_slot: 1,
+            account_level: acc_level,
+            banned_until: SystemTime::UNIX_EPOCH,
+            ban_reason: String::new(),
+            slot_bonus: 0,
+            free_chat_allowed: true,
+        };
+        self.accounts.insert(account.id, account.clone());
+        Ok(account)
+    }
+
+    fn change_account_level(&mut self, acc_id: BigInt, new_level: Int) -> FFResult<()> {
+        self.check_faults("change_account_level")?;
+        self.get_account_mut(acc_id)?.account_level = new_level as i16;
+        Ok(())
+    }
+
+    fn ban_account(
+        &mut self,
+        acc_id: BigInt,
+        banned_until: SystemTime,
+        ban_reason: Text,
+    ) -> FFResult<()> {
+        self.check_faults("ban_account")?;
+        let account = self.get_account_mut(acc_id)?;
+        // stored at second precision, same as the real thing
+        account.banned_until =
+            util::get_systime_from_sec(util::get_timestamp_sec(banned_until) as u64);
+        account.ban_reason = ban_reason;
+        Ok(())
+    }
+
+    fn unban_account(&mut self, acc_id: BigInt) -> FFResult<()> {
+        self.check_faults("unban_account")?;
+        let account = self.get_account_mut(acc_id)?;
+        account.banned_until = SystemTime::UNIX_EPOCH;
+        account.ban_reason = String::new();
+        Ok(())
+    }
+
+    fn set_account_slot_bonus(&mut self, acc_id: BigInt, slot_bonus: Int) -> FFResult<()> {
+        self.check_faults("set_account_slot_bonus")?;
+        let slot_bonus = check_slot_bonus(slot_bonus)?;
+        self.get_account_mut(acc_id)?.slot_bonus = slot_bonus;
+        Ok(())
+    }
+
+    fn set_account_free_chat(&mut self, acc_id: BigInt, allowed: bool) -> FFResult<()> {
+        self.check_faults("set_account_free_chat")?;
+        self.get_account_mut(acc_id)?.free_chat_allowed = allowed;
+        Ok(())
+    }
+
+    fn add_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()> {
+        self.check_faults("add_ignore")?;
+        let acc_id = self.get_player_account_id(pc_uid)?;
+        self.ignores.entry(acc_id).or_default().insert(ignored_uid);
+        Ok(())
+    }
+
+    fn remove_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()> {
+        self.check_faults("remove_ignore")?;
+        let acc_id = self.get_player_account_id(pc_uid)?;
+        if let Some(ignores) = self.ignores.get_mut(&acc_id) {
+            ignores.remove(&ignored_uid);
+        }
+        Ok(())
+    }
+
+    fn init_player(&mut self, acc_id: BigInt, player: &Player) -> FFResult<()> {
+        self.check_faults("init_player")?;
+        self.get_account_mut(acc_id)?;
+        let pc_uid = player.get_uid();
+        let slot_taken = self.players.values().any(|(owner_id, existing)| {
+            *owner_id == acc_id && existing.get_slot_num() == player.get_slot_num()
+        });
+        if self.players.contains_key(&pc_uid) || slot_taken {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Database error: player with UID {} or slot {} already exists",
+                    pc_uid,
+                    player.get_slot_num()
+                ),
+            ));
+        }
+        self.players.insert(pc_uid, (acc_id, player.clone()));
+        Ok(())
+    }
+
+    fn update_player_appearance(&mut self, player: &Player) -> FFResult<()> {
+        self.check_faults("update_player_appearance")?;
+        self.get_player_mut(player.get_uid())?.style = player.style;
+        Ok(())
+    }
+
+    fn update_player_name(
+        &mut self,
+        pc_uid: BigInt,
+        first_name: &Text,
+        last_name: &Text,
+        name_check: Int,
+    ) -> FFResult<()> {
+        self.check_faults("update_player_name")?;
+        let name_check = (name_check as i8).try_into()?;
+        let player = self.get_player_mut(pc_uid).map_err(|_| {
+            FFError::build(
+                Severity::Warning,
+                format!("Failed to change name for player with UID {}", pc_uid),
+            )
+        })?;
+        player.first_name = first_name.clone();
+        player.last_name = last_name.clone();
+        player.flags.name_check = name_check;
+        Ok(())
+    }
+
+    fn update_selected_player(&mut self, acc_id: BigInt, slot_num: Int) -> FFResult<()> {
+        self.check_faults("update_selected_player")?;
+        self.get_account_mut(acc_id)?.selected_slot = slot_num as u8;
+        Ok(())
+    }
+
+    fn save_player(&mut self, player: &Player) -> FFResult<()> {
+        self.check_faults("save_player")?;
+        *self.get_player_mut(player.get_uid())? = player.clone();
+        Ok(())
+    }
+
+    fn save_players(&mut self, players: &[&Player]) -> FFResult<()> {
+        self.check_faults("save_players")?;
+        // all or nothing, like the transaction it stands in for
+        if let Some(missing) = players
+            .iter()
+            .find(|player| !self.players.contains_key(&player.get_uid()))
+        {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Player with UID {} not found", missing.get_uid()),
+            ));
+        }
+        for player in players {
+            *self.get_player_mut(player.get_uid())? = (*player).clone();
+        }
+        Ok(())
+    }
+
+    fn load_player(&mut self, acc_id: BigInt, pc_uid: BigInt) -> FFResult<Player> {
+        self.check_faults("load_player")?;
+        match self.players.get(&pc_uid) {
+            Some((owner_id, player)) if *owner_id == acc_id => {
+                self.load_player_internal(acc_id, player)
+            }
+            _ => Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Player with UID {} not found for account with ID {}",
+                    pc_uid, acc_id
+                ),
+            )),
+        }
+    }
+
+    fn load_players(&mut self, acc_id: BigInt) -> FFResult<Vec<Result<Player, UnavailablePlayer>>> {
+        self.check_faults("load_players")?;
+        let mut players: Vec<&Player> = self
+            .players
+            .values()
+            .filter(|(owner_id, _)| *owner_id == acc_id)
+            .map(|(_, player)| player)
+            .collect();
+        players.sort_by_key(|player| player.get_slot_num());
+        players
+            .into_iter()
+            .map(|player| {
+                Ok(self
+                    .load_player_internal(acc_id, player)
+                    .map_err(|e| UnavailablePlayer {
+                        pc_uid: player.get_uid(),
+                        slot_num: player.get_slot_num(),
+                        err: e,
+                    }))
+            })
+            .collect()
+    }
+
+    fn delete_player(&mut self, pc_uid: BigInt) -> FFResult<()> {
+        self.check_faults("delete_player")?;
+        self.players.remove(&pc_uid).ok_or(FFError::build(
+            Severity::Warning,
+            format!("Player with UID {} not found", pc_uid),
+        ))?;
+        Ok(())
+    }
+
+    fn create_redeem_code(&mut self, code: &RedeemCode) -> FFResult<()> {
+        self.check_faults("create_redeem_code")?;
+        if self.redeem_codes.contains_key(&code.code) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Redeem code {} already exists", code.code),
+            ));
+        }
+        self.redeem_codes.insert(code.code.clone(), code.clone());
+        Ok(())
+    }
+
+    fn find_redeem_code(&mut self, code: &Text) -> FFResult<Option<RedeemCode>> {
+        self.check_faults("find_redeem_code")?;
+        Ok(self.redeem_codes.get(code).cloned())
+    }
+
+    fn use_redeem_code(&mut self, pc_uid: BigInt, code: &Text) -> FFResult<bool> {
+        self.check_faults("use_redeem_code")?;
+        let Some(redeem_code) = self.redeem_codes.get(code) else {
+            return Ok(false);
+        };
+        if redeem_code.is_expired() || redeem_code.get_uses_left() == 0 {
+            return Ok(false);
+        }
+
+        let acc_id = self.players.get(&pc_uid).map(|(acc_id, _)| *acc_id);
+        let already_redeemed = self.redemptions.iter().any(|(redeemer, redeemed)| {
+            redeemed == code
+                && (*redeemer == pc_uid
+                    || (redeem_code.once_per_account
+                        && self.players.get(redeemer).map(|(acc_id, _)| *acc_id) == acc_id))
+        });
+        if already_redeemed {
+            return Ok(false);
+        }
+
+        self.redeem_codes.get_mut(code).unwrap().uses += 1;
+        self.redemptions.push((pc_uid, code.clone()));
+        Ok(true)
+    }
+
+    fn log_currency_events(&mut self, events: &[(BigInt, CurrencyEvent)]) -> FFResult<()> {
+        self.check_faults("log_currency_events")?;
+        self.currency_events.extend_from_slice(events);
+        Ok(())
+    }
+
+    fn save_race_results(&mut self, results: &[(BigInt, RaceResult)]) -> FFResult<()> {
+        self.check_faults("save_race_results")?;
+        self.race_results.extend_from_slice(results);
+        Ok(())
+    }
+
+    fn add_kill_counts(&mut self, kills: &[(BigInt, Int)]) -> FFResult<()> {
+        self.check_faults("add_kill_counts")?;
+        for (pc_uid, count) in kills {
+            *self.kill_counts.entry(*pc_uid).or_default() += *count as BigInt;
+        }
+        Ok(())
+    }
+
+    fn load_leaderboard(
+        &mut self,
+        board: Leaderboard,
+        count: Int,
+    ) -> FFResult<Vec<LeaderboardEntry>> {
+        self.check_faults("load_leaderboard")?;
+        let mut scores = HashMap::new();
+        match board {
+            Leaderboard::Race { ep_id } => {
+                for (pc_uid, result) in &self.race_results {
+                    if result.ep_id == ep_id {
+                        let best: &mut BigInt = scores.entry(*pc_uid).or_insert(BigInt::MIN);
+                        *best = (*best).max(result.score as BigInt);
+                    }
+                }
+            }
+            Leaderboard::Kills => scores.clone_from(&self.kill_counts),
+        }
+
+        let mut entries: Vec<LeaderboardEntry> = scores
+            .into_iter()
+            .filter_map(|(pc_uid, score)| {
+                let (_, player) = self.players.get(&pc_uid)?;
+                Some(LeaderboardEntry {
+                    pc_uid,
+                    name: format!("{} {}", player.first_name, player.last_name),
+                    score,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| b.score.cmp(&a.score).then(a.pc_uid.cmp(&b.pc_uid)));
+        entries.truncate(count.max(0) as usize);
+        Ok(entries)
+    }
+
+    fn load_personal_best(
+        &mut self,
+        board: Leaderboard,
+        pc_uid: BigInt,
+    ) -> FFResult<Option<BigInt>> {
+        self.check_faults("load_personal_best")?;
+        let best = match board {
+            Leaderboard::Race { ep_id } => self
+                .race_results
+                .iter()
+                .filter(|(uid, result)| *uid == pc_uid && result.ep_id == ep_id)
+                .map(|(_, result)| result.score as BigInt)
+                .max(),
+            Leaderboard::Kills => self.kill_counts.get(&pc_uid).copied(),
+        };
+        Ok(best)
+    }
+
+    fn issue_auth_token(
+        &mut self,
+        acc_id: BigInt,
+        token_id: &Text,
+        token_hash: &Text,
+        expires: SystemTime,
+    ) -> FFResult<()> {
+        self.check_faults("issue_auth_token")?;
+        self.get_account_mut(acc_id)?;
+        if self.auth_tokens.contains_key(token_id) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Auth token {} already exists", token_id),
+            ));
+        }
+        self.auth_tokens.insert(
+            token_id.clone(),
+            (acc_id, token_hash.clone(), expires, false),
+        );
+        Ok(())
+    }
+
+    fn consume_auth_token(
+        &mut self,
+        token_id: &Text,
+        time: SystemTime,
+        check_secret: &dyn Fn(&Text) -> bool,
+    ) -> FFResult<Option<Account>> {
+        self.check_faults("consume_auth_token")?;
+        let Some((acc_id, token_hash, expires, used)) = self.auth_tokens.get_mut(token_id) else {
+            return Ok(None);
+        };
+        if *used || *expires <= time || !check_secret(token_hash) {
+            return Ok(None);
+        }
+        *used = true;
+        let acc_id = *acc_id;
+        Ok(self.accounts.get(&acc_id).cloned())
+    }
+
+    fn log_trade(&mut self, transactions: &[Transaction]) -> FFResult<()> {
+        self.check_faults("log_trade")?;
+        self.transactions.extend_from_slice(transactions);
+        Ok(())
+    }
+
+    fn load_transactions(&mut self, pc_uid: BigInt, count: Int) -> FFResult<Vec<Transaction>> {
+        self.check_faults("load_transactions")?;
+        let mut found: Vec<Transaction> = self
+            .transactions
+            .iter()
+            .filter(|t| t.pc_uid == pc_uid || t.other_pc_uid == Some(pc_uid))
+            .cloned()
+            .collect();
+        // stable, so same-time transactions stay newest first too
+        found.reverse();
+        found.sort_by_key(|t| std::cmp::Reverse(t.time));
+        found.truncate(count.max(0) as usize);
+        Ok(found)
+    }
+
+    fn prune_transactions(&mut self, before: SystemTime) -> FFResult<u64> {
+        self.check_faults("prune_transactions")?;
+        let num_before = self.transactions.len();
+        self.transactions.retain(|t| t.time >= before);
+        Ok((num_before - self.transactions.len()) as u64)
+    }
+
+    fn file_report(&mut self, report: &Report) -> FFResult<BigInt> {
+        self.check_faults("file_report")?;
+        let report_id = self.reports.len() as BigInt + 1;
+        self.reports.push(Report {
+            id: report_id,
+            handled_by: None,
+            ..report.clone()
+        });
+        Ok(report_id)
+    }
+
+    fn load_open_reports(&mut self, offset: Int, count: Int) -> FFResult<Vec<Report>> {
+        self.check_faults("load_open_reports")?;
+        Ok(self
+            .reports
+            .iter()
+            .filter(|r| r.handled_by.is_none())
+            .skip(offset.max(0) as usize)
+            .take(count.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn resolve_report(
+        &mut self,
+        report_id: BigInt,
+        handled_by: BigInt,
+        _time: SystemTime,
+    ) -> FFResult<bool> {
+        self.check_faults("resolve_report")?;
+        match self
+            .reports
+            .iter_mut()
+            .find(|r| r.id == report_id && r.handled_by.is_none())
+        {
+            Some(report) => {
+                report.handled_by = Some(handled_by);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        entity::PlayerStyle,
+        enums::{
+            ItemLocation, ItemType, PlayerGuide, PlayerNameStatus, ReportCategory, TransactionKind,
+        },
+        item::Item,
+        test_utils::{PlayerBuilder, PlayerSnapshot},
+        Position,
+    };
+
+    #[test]
+    fn test_char_create() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        assert_eq!(acc.account_level, CN_ACCOUNT_LEVEL__MASTER as i16);
+        assert!(db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .is_err());
+
+        let mut player = PlayerBuilder::new(100).build();
+        player.first_name = "Test".to_string();
+        player.last_name = "Player".to_string();
+        db.init_player(acc.id, &player).unwrap();
+        // same slot on the same account
+        assert!(db
+            .init_player(acc.id, &PlayerBuilder::new(101).build())
+            .is_err());
+
+        player.style = Some(PlayerStyle {
+            gender: 1,
+            face_style: 1,
+            hair_style: 1,
+            hair_color: 1,
+            skin_color: 1,
+            eye_color: 1,
+            height: 1,
+            body: 1,
+        });
+        db.update_player_appearance(&player).unwrap();
+        db.update_player_name(
+            100,
+            &"New".to_string(),
+            &"Name".to_string(),
+            PlayerNameStatus::Approved as Int,
+        )
+        .unwrap();
+        db.update_selected_player(acc.id, 1).unwrap();
+
+        let players = db.load_players(acc.id).unwrap();
+        assert_eq!(players.len(), 1);
+        let loaded = players[0].as_ref().unwrap();
+        assert!(loaded.style.is_some());
+        assert_eq!(loaded.first_name, "New");
+        assert_eq!(loaded.flags.name_check, PlayerNameStatus::Approved);
+        assert_eq!(loaded.perms, CN_ACCOUNT_LEVEL__MASTER as i16);
+        assert_eq!(db.find_account_from_player(100).unwrap().id, acc.id);
+
+        db.delete_player(100).unwrap();
+        assert!(db.load_players(acc.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        let mut player = PlayerBuilder::new(100)
+            .level(10)
+            .taros(5000)
+            .item(ItemLocation::Inven, 3, Item::new(ItemType::General, 7))
+            .build();
+        player.perms = acc.account_level;
+        db.init_player(acc.id, &player).unwrap();
+
+        player.set_taros(1234);
+        player.set_item(ItemLocation::Inven, 3, None).unwrap();
+        player.change_guide(PlayerGuide::Dexter, 0).unwrap();
+        player.record_crate_roll(7, false);
+        player.update_first_use_flag(70).unwrap();
+        player.unlock_skyway_location(3).unwrap();
+        player.mission_journal.set_mission_completed(5).unwrap();
+        db.save_player(&player).unwrap();
+        let loaded = db.load_player(acc.id, 100).unwrap();
+        let changes = PlayerSnapshot::take(&player).diff(&PlayerSnapshot::take(&loaded));
+        assert!(changes.is_empty());
+        assert_eq!(loaded.get_guide(), PlayerGuide::Dexter);
+        assert_eq!(loaded.get_total_guides(), 1);
+        assert_eq!(loaded.get_crate_pity(7), 1);
+
+        // nano skill cooldowns start fresh every session
+        let now = SystemTime::now();
+        player.start_skill_cooldown(1, Duration::from_secs(30), now);
+        db.save_player(&player).unwrap();
+        let loaded = db.load_player(acc.id, 100).unwrap();
+        assert!(loaded.is_skill_ready(1, now));
+
+        // someone else's account can't load it
+        assert!(db.load_player(acc.id + 1, 100).is_err());
+
+        // batch saves are all or nothing
+        let stranger = PlayerBuilder::new(200).build();
+        player.set_taros(1);
+        assert!(db.save_players(&[&player, &stranger]).is_err());
+        assert_eq!(db.load_player(acc.id, 100).unwrap().get_taros(), 1234);
+    }
+
+    #[test]
+    fn test_past_transition_round_trip() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        let mut player = PlayerBuilder::new(100).build();
+        db.init_player(acc.id, &player).unwrap();
+
+        assert!(player.set_future_done());
+        player.unlock_scamper_location(4).unwrap();
+        player.unlock_skyway_location(9).unwrap();
+        db.save_player(&player).unwrap();
+
+        let mut loaded = db.load_player(acc.id, 100).unwrap();
+        let changes = PlayerSnapshot::take(&player).diff(&PlayerSnapshot::take(&loaded));
+        assert!(changes.is_empty());
+        assert!(loaded.is_future_done());
+        assert!(loaded.is_scamper_location_unlocked(4).unwrap());
+        assert!(loaded.is_skyway_location_unlocked(9).unwrap());
+        // a loaded character that's already in the Past doesn't go again
+        assert!(!loaded.set_future_done());
+    }
+
+    #[test]
+    fn test_ban() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        db.init_player(acc.id, &PlayerBuilder::new(100).build())
+            .unwrap();
+        assert!(db.find_banned_accounts(&[100]).unwrap().is_empty());
+
+        let banned_until = SystemTime::now() + Duration::from_secs(3600);
+        db.ban_account(acc.id, banned_until, "cheating".to_string())
+            .unwrap();
+        let banned = db.find_banned_accounts(&[100, 200]).unwrap();
+        assert_eq!(banned.len(), 1);
+        assert_eq!(banned[0].ban_reason, "cheating");
+
+        db.unban_account(acc.id).unwrap();
+        assert!(db.find_banned_accounts(&[100]).unwrap().is_empty());
+        assert!(db
+            .ban_account(acc.id + 1, banned_until, String::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_free_chat() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        assert!(acc.free_chat_allowed);
+        db.init_player(acc.id, &PlayerBuilder::new(100).build())
+            .unwrap();
+        assert!(!db.load_player(acc.id, 100).unwrap().menuchat_only);
+
+        db.set_account_free_chat(acc.id, false).unwrap();
+        assert!(!db.find_account_from_player(100).unwrap().free_chat_allowed);
+        assert!(db.load_player(acc.id, 100).unwrap().menuchat_only);
+        assert!(db.set_account_free_chat(acc.id + 1, false).is_err());
+    }
+
+    #[test]
+    fn test_slot_bonus() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        db.set_account_slot_bonus(acc.id, 255).unwrap();
+        assert!(db.set_account_slot_bonus(acc.id, 256).is_err());
+        assert!(db.set_account_slot_bonus(acc.id, -1).is_err());
+        let found = db.find_account_from_username(&acc.username).unwrap();
+        assert_eq!(found.unwrap().slot_bonus, 255);
+    }
+
+    #[test]
+    fn test_leaderboards() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        for (slot_num, pc_uid) in [(1, 100), (2, 101), (3, 102)] {
+            let mut player = PlayerBuilder::new(pc_uid).slot_num(slot_num).build();
+            player.first_name = format!("Player{}", pc_uid);
+            player.last_name = "Test".to_string();
+            db.init_player(acc.id, &player).unwrap();
+        }
+
+        // kill counts accumulate across flushes
+        db.add_kill_counts(&[(100, 3), (101, 5)]).unwrap();
+        db.add_kill_counts(&[(100, 4)]).unwrap();
+        let board = db.load_leaderboard(Leaderboard::Kills, 10).unwrap();
+        let scores: Vec<(i64, i64)> = board.iter().map(|e| (e.pc_uid, e.score)).collect();
+        assert_eq!(scores, vec![(100, 7), (101, 5)]);
+        assert_eq!(board[0].name, "Player100 Test");
+        assert_eq!(
+            db.load_personal_best(Leaderboard::Kills, 101).unwrap(),
+            Some(5)
+        );
+        assert_eq!(
+            db.load_personal_best(Leaderboard::Kills, 102).unwrap(),
+            None
+        );
+
+        // only the best run per player counts, and only on the requested EP
+        let result = |ep_id, score| RaceResult {
+            ep_id,
+            score,
+            ring_count: 0,
+            time_sec: 60,
+            timestamp: SystemTime::UNIX_EPOCH,
+        };
+        db.save_race_results(&[
+            (100, result(1, 200)),
+            (100, result(1, 500)),
+            (101, result(1, 300)),
+            (102, result(1, 100)),
+            (102, result(2, 900)),
+        ])
+        .unwrap();
+        let board = db
+            .load_leaderboard(Leaderboard::Race { ep_id: 1 }, 2)
+            .unwrap();
+        let scores: Vec<(i64, i64)> = board.iter().map(|e| (e.pc_uid, e.score)).collect();
+        assert_eq!(scores, vec![(100, 500), (101, 300)]);
+        assert_eq!(
+            db.load_personal_best(Leaderboard::Race { ep_id: 2 }, 102)
+                .unwrap(),
+            Some(900)
+        );
+        assert_eq!(
+            db.load_personal_best(Leaderboard::Race { ep_id: 2 }, 100)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_auth_tokens() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let token_id = "abc".to_string();
+        let expires = now + Duration::from_secs(60);
+        db.issue_auth_token(acc.id, &token_id, &"secret".to_string(), expires)
+            .unwrap();
+        assert!(db
+            .issue_auth_token(acc.id, &token_id, &"other".to_string(), expires)
+            .is_err());
+        assert!(db
+            .issue_auth_token(
+                acc.id + 1,
+                &"def".to_string(),
+                &"secret".to_string(),
+                expires
+            )
+            .is_err());
+
+        let right = |hash: &Text| hash == "secret";
+        let wrong = |hash: &Text| hash == "guess";
+
+        // a wrong secret doesn't use the token up
+        assert!(db
+            .consume_auth_token(&token_id, now, &wrong)
+            .unwrap()
+            .is_none());
+
+        // single use
+        let account = db
+            .consume_auth_token(&token_id, now, &right)
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.id, acc.id);
+        assert!(db
+            .consume_auth_token(&token_id, now, &right)
+            .unwrap()
+            .is_none());
+        assert!(db
+            .consume_auth_token(&"missing".to_string(), now, &right)
+            .unwrap()
+            .is_none());
+
+        // and expiring
+        let token_id = "ghi".to_string();
+        db.issue_auth_token(acc.id, &token_id, &"secret".to_string(), expires)
+            .unwrap();
+        assert!(db
+            .consume_auth_token(&token_id, expires, &right)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_transaction_history() {
+        let mut db = MockDatabase::default();
+        let now = SystemTime::now();
+        let transaction = |pc_uid, other_pc_uid: Option<BigInt>, secs_ago| Transaction {
+            kind: if other_pc_uid.is_some() {
+                TransactionKind::Trade
+            } else {
+                TransactionKind::VendorBuy
+            },
+            pc_uid,
+            other_pc_uid,
+            taros_given: 100,
+            items_given: vec![],
+            taros_received: 0,
+            items_received: vec![Item::new(ItemType::General, 5)],
+            time: now - Duration::from_secs(secs_ago),
+        };
+        db.log_trade(&[
+            transaction(1, Some(2), 300),
+            transaction(3, None, 200),
+            transaction(2, None, 100),
+            transaction(3, Some(1), 0),
+        ])
+        .unwrap();
+
+        // both sides of a trade can find it, newest first
+        let found = db.load_transactions(1, 10).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].pc_uid, 3);
+        assert_eq!(found[1].other_pc_uid, Some(2));
+        assert_eq!(db.load_transactions(2, 10).unwrap().len(), 2);
+        assert_eq!(db.load_transactions(3, 1).unwrap()[0].other_pc_uid, Some(1));
+
+        assert_eq!(
+            db.prune_transactions(now - Duration::from_secs(150))
+                .unwrap(),
+            2
+        );
+        assert_eq!(db.load_transactions(1, 10).unwrap().len(), 1);
+        assert_eq!(db.load_transactions(2, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_account_ignores() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        let mut player = PlayerBuilder::new(100).build();
+        db.init_player(acc.id, &player).unwrap();
+
+        db.add_ignore(100, 300).unwrap();
+        db.add_ignore(100, 300).unwrap();
+        assert!(db.load_player(acc.id, 100).unwrap().is_ignoring(300));
+
+        // kept on the account, so saving the player doesn't touch them
+        player.ignore_player(400).unwrap();
+        db.save_player(&player).unwrap();
+        let loaded = db.load_player(acc.id, 100).unwrap();
+        assert!(loaded.is_ignoring(300));
+        assert!(!loaded.is_ignoring(400));
+
+        db.remove_ignore(100, 300).unwrap();
+        assert!(!db.load_player(acc.id, 100).unwrap().is_ignoring(300));
+        assert!(db.add_ignore(200, 300).is_err());
+    }
+
+    #[test]
+    fn test_report_queue() {
+        let mut db = MockDatabase::default();
+        let report = |reporter_uid, reported_uid| Report {
+            id: 0,
+            reporter_uid,
+            reported_uid,
+            category: ReportCategory::Block,
+            reason: None,
+            time: SystemTime::UNIX_EPOCH,
+            shard_id: 1,
+            channel_num: 1,
+            reporter_pos: Position::default(),
+            reported_pos: None,
+            flagged: false,
+            handled_by: None,
+        };
+        let ids: Vec<BigInt> = [(1, 2), (1, 3), (2, 3)]
+            .into_iter()
+            .map(|(from, to)| db.file_report(&report(from, to)).unwrap())
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // oldest first, in pages
+        let page = db.load_open_reports(0, 2).unwrap();
+        assert_eq!(page.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(page[1].reported_uid, 3);
+        assert_eq!(db.load_open_reports(2, 2).unwrap().len(), 1);
+
+        // handled reports drop out of the queue, and can't be handled twice
+        assert!(db.resolve_report(2, 10, SystemTime::UNIX_EPOCH).unwrap());
+        assert!(!db.resolve_report(2, 10, SystemTime::UNIX_EPOCH).unwrap());
+        assert!(!db.resolve_report(4, 10, SystemTime::UNIX_EPOCH).unwrap());
+        let open = db.load_open_reports(0, 10).unwrap();
+        assert_eq!(open.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_fault_injection() {
+        let mut db = MockDatabase::default();
+        db.fail_next(2);
+        assert!(db
+            .find_account_from_username(&"player".to_string())
+            .is_err());
+        assert!(db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .is_err());
+        // failed calls don't leave anything behind
+        assert!(db
+            .find_account_from_username(&"player".to_string())
+            .unwrap()
+            .is_none());
+
+        db.set_latency(Some(Duration::from_millis(20)));
+        let start = SystemTime::now();
+        db.find_account_from_username(&"player".to_string())
+            .unwrap();
+        assert!(start.elapsed().unwrap() >= Duration::from_millis(20));
+    }
+}
@@ -7,23 +7,54 @@ use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime};
 
 use crate::config::*;
+use crate::currency::CurrencyEvent;
+use crate::defines::DB_VERSION;
 use crate::entity::Player;
 use crate::error::*;
+use crate::item::RedeemCode;
+use crate::leaderboard::{Leaderboard, LeaderboardEntry, RaceResult};
+use crate::report::Report;
 use crate::state::{Account, FFReceiver, FFSender};
+use crate::transaction::Transaction;
 
 #[cfg(feature = "postgres")]
 mod postgresql;
 
+mod import;
+pub use import::*;
+
+#[cfg(any(test, feature = "mock-db"))]
+mod mock;
+#[cfg(any(test, feature = "mock-db"))]
+pub use mock::MockDatabase;
+
 type Int = i32;
 type BigInt = i64;
 type Text = String;
 type Bytes = Vec<u8>;
 
+// Slot bonuses are stored as a plain integer but only ever handed out as a u8
+fn check_slot_bonus(slot_bonus: Int) -> FFResult<u8> {
+    u8::try_from(slot_bonus).map_err(|_| {
+        FFError::build(
+            Severity::Warning,
+            format!("Slot bonus {} is out of range", slot_bonus),
+        )
+    })
+}
+
 pub struct DbResult {
     result: FFResult<Box<dyn Any>>,
     pub completed: SystemTime,
 }
 impl DbResult {
+    pub fn new<T: Any>(result: FFResult<T>) -> Self {
+        Self {
+            result: result.map(|v| Box::new(v) as Box<dyn Any>),
+            completed: SystemTime::now(),
+        }
+    }
+
     pub fn get<T: 'static>(self) -> FFResult<T> {
         self.result.map(|v| {
             *v.downcast::<T>()
@@ -33,6 +64,14 @@ impl DbResult {
 }
 type DbOperation = dyn FnOnce(&mut dyn Database);
 
+// A player row that couldn't be loaded into a playable character
+#[derive(Debug, Clone)]
+pub struct UnavailablePlayer {
+    pub pc_uid: BigInt,
+    pub slot_num: usize,
+    pub err: FFError,
+}
+
 struct DbManager {
     db_impl: Box<dyn Database>,
     op_queue: VecDeque<Box<DbOperation>>,
@@ -47,9 +86,58 @@ impl DbManager {
     }
 }
 
-pub trait Database: Send + std::fmt::Debug {
+pub trait Migrate {
+    fn get_schema_version(&mut self) -> FFResult<Int>;
+    // Upgrades the schema from the version right before this one
+    fn migrate_to(&mut self, version: Int) -> FFResult<()>;
+}
+
+// Brings the schema up to target_version one step at a time. Each step commits on its own,
+// so a failed migration leaves the schema at the last version that succeeded
+fn migrate_schema<D: Migrate + ?Sized>(
+    db: &mut D,
+    target_version: Int,
+    auto_migrate: bool,
+) -> FFResult<()> {
+    let version = db.get_schema_version()?;
+    if version > target_version {
+        return Err(FFError::build(
+            Severity::Fatal,
+            format!(
+                "Database schema version {} is newer than this server supports ({}); please update the server",
+                version, target_version
+            ),
+        ));
+    }
+
+    if version == target_version {
+        return Ok(());
+    }
+
+    if !auto_migrate {
+        return Err(FFError::build(
+            Severity::Fatal,
+            format!(
+                "Database schema version {} is out of date (need {}); back up the database and enable db_auto_migrate to upgrade it",
+                version, target_version
+            ),
+        ));
+    }
+
+    for next_version in (version + 1)..=target_version {
+        log(
+            Severity::Info,
+            &format!("Migrating database to version {}...", next_version),
+        );
+        db.migrate_to(next_version)?;
+    }
+    Ok(())
+}
+
+pub trait Database: Migrate + Send + std::fmt::Debug {
     fn find_account_from_username(&mut self, username: &Text) -> FFResult<Option<Account>>;
     fn find_account_from_player(&mut self, pc_uid: BigInt) -> FFResult<Account>;
+    fn find_banned_accounts(&mut self, pc_uids: &[BigInt]) -> FFResult<Vec<Account>>;
     fn create_account(&mut self, username: &Text, password_hashed: &Text) -> FFResult<Account>;
     fn change_account_level(&mut self, acc_id: BigInt, new_level: Int) -> FFResult<()>;
     fn ban_account(
@@ -59,14 +147,73 @@ pub trait Database: Send + std::fmt::Debug {
         ban_reason: Text,
     ) -> FFResult<()>;
     fn unban_account(&mut self, acc_id: BigInt) -> FFResult<()>;
+    fn set_account_slot_bonus(&mut self, acc_id: BigInt, slot_bonus: Int) -> FFResult<()>;
+    fn set_account_free_chat(&mut self, acc_id: BigInt, allowed: bool) -> FFResult<()>;
+    // Ignores belong to the account of the given player
+    fn add_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()>;
+    fn remove_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()>;
     fn init_player(&mut self, acc_id: BigInt, player: &Player) -> FFResult<()>;
     fn update_player_appearance(&mut self, player: &Player) -> FFResult<()>;
+    fn update_player_name(
+        &mut self,
+        pc_uid: BigInt,
+        first_name: &Text,
+        last_name: &Text,
+        name_check: Int,
+    ) -> FFResult<()>;
     fn update_selected_player(&mut self, acc_id: BigInt, slot_num: Int) -> FFResult<()>;
     fn save_player(&mut self, player: &Player) -> FFResult<()>;
     fn save_players(&mut self, players: &[&Player]) -> FFResult<()>;
     fn load_player(&mut self, acc_id: BigInt, pc_uid: BigInt) -> FFResult<Player>;
-    fn load_players(&mut self, acc_id: BigInt) -> FFResult<Vec<Player>>;
+    fn load_players(&mut self, acc_id: BigInt) -> FFResult<Vec<Result<Player, UnavailablePlayer>>>;
     fn delete_player(&mut self, pc_uid: BigInt) -> FFResult<()>;
+    fn create_redeem_code(&mut self, code: &RedeemCode) -> FFResult<()>;
+    fn find_redeem_code(&mut self, code: &Text) -> FFResult<Option<RedeemCode>>;
+    fn use_redeem_code(&mut self, pc_uid: BigInt, code: &Text) -> FFResult<bool>;
+    fn log_currency_events(&mut self, events: &[(BigInt, CurrencyEvent)]) -> FFResult<()>;
+    fn save_race_results(&mut self, results: &[(BigInt, RaceResult)]) -> FFResult<()>;
+    fn add_kill_counts(&mut self, kills: &[(BigInt, Int)]) -> FFResult<()>;
+    fn load_leaderboard(
+        &mut self,
+        board: Leaderboard,
+        count: Int,
+    ) -> FFResult<Vec<LeaderboardEntry>>;
+    fn load_personal_best(
+        &mut self,
+        board: Leaderboard,
+        pc_uid: BigInt,
+    ) -> FFResult<Option<BigInt>>;
+    fn issue_auth_token(
+        &mut self,
+        acc_id: BigInt,
+        token_id: &Text,
+        token_hash: &Text,
+        expires: SystemTime,
+    ) -> FFResult<()>;
+    // Marks the token as used if it's still valid and `check_secret` accepts its secret hash,
+    // returning its account. A token that fails the check stays usable.
+    fn consume_auth_token(
+        &mut self,
+        token_id: &Text,
+        time: SystemTime,
+        check_secret: &dyn Fn(&Text) -> bool,
+    ) -> FFResult<Option<Account>>;
+    fn log_trade(&mut self, transactions: &[Transaction]) -> FFResult<()>;
+    // Newest first, including ones where the player was the other party
+    fn load_transactions(&mut self, pc_uid: BigInt, count: Int) -> FFResult<Vec<Transaction>>;
+    // Returns how many were removed
+    fn prune_transactions(&mut self, before: SystemTime) -> FFResult<u64>;
+    // Returns the new report's ID
+    fn file_report(&mut self, report: &Report) -> FFResult<BigInt>;
+    // Reports nobody has handled yet, oldest first
+    fn load_open_reports(&mut self, offset: Int, count: Int) -> FFResult<Vec<Report>>;
+    // Returns false if there's no open report with that ID
+    fn resolve_report(
+        &mut self,
+        report_id: BigInt,
+        handled_by: BigInt,
+        time: SystemTime,
+    ) -> FFResult<bool>;
 }
 
 const DB_NAME: &str = "rustyfusion";
@@ -75,9 +222,13 @@ static DB_MANAGER: OnceLock<Mutex<DbManager>> = OnceLock::new();
 fn db_connect(config: &GeneralConfig) -> FFResult<Box<dyn Database>> {
     let _db_impl: Option<FFResult<Box<dyn Database>>> = None;
 
-    #[cfg(feature = "postgres")]
+    #[cfg(all(feature = "postgres", not(feature = "mock-db")))]
     let _db_impl = Some(postgresql::PostgresDatabase::connect(config));
 
+    // takes priority so the servers can run without a database server
+    #[cfg(feature = "mock-db")]
+    let _db_impl = Some(mock::MockDatabase::connect(config));
+
     match _db_impl {
         Some(Ok(db)) => Ok(db),
         Some(Err(e)) => Err(FFError::build(
@@ -97,7 +248,12 @@ pub fn db_init() -> JoinHandle<()> {
         None => {
             log(Severity::Info, "Connecting to database...");
             let config = &config_get().general;
-            let db_impl = panic_if_failed(db_connect(config));
+            let mut db_impl = panic_if_failed(db_connect(config));
+            panic_if_failed(migrate_schema(
+                &mut *db_impl,
+                DB_VERSION,
+                config.db_auto_migrate.get(),
+            ));
             let _ = DB_MANAGER.set(Mutex::new(DbManager {
                 db_impl,
                 op_queue: VecDeque::new(),
@@ -131,6 +287,20 @@ pub fn db_shutdown() {
 }
 
 // TODO migrate most DB operations to async
+// Loads a player who isn't online, lets f change them, and saves them back.
+// Nothing is saved if f fails.
+pub fn edit_offline_player<T>(
+    db: &mut dyn Database,
+    pc_uid: BigInt,
+    f: impl FnOnce(&mut Player) -> FFResult<T>,
+) -> FFResult<T> {
+    let account = db.find_account_from_player(pc_uid)?;
+    let mut player = db.load_player(account.id, pc_uid)?;
+    let result = f(&mut player)?;
+    db.save_player(&player)?;
+    Ok(result)
+}
+
 pub fn db_run_sync<T, F>(f: F) -> FFResult<T>
 where
     T: Send + 'static,
@@ -152,12 +322,7 @@ where
             let (tx, rx) = std::sync::mpsc::channel();
             let start_time = SystemTime::now();
             let f = move |db: &mut dyn Database| {
-                let result = f(db).map(|v| Box::new(v) as Box<dyn Any>);
-                let db_result = DbResult {
-                    result,
-                    completed: SystemTime::now(),
-                };
-                let _ = FFSender::new(tx).send(db_result);
+                let _ = FFSender::new(tx).send(DbResult::new(f(db)));
             };
             db_mgr.op_queue.push_back(Box::new(f));
             FFReceiver::new(start_time, rx)
@@ -165,3 +330,174 @@ where
         None => panic_log("Database not initialized"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        enums::{ItemLocation, ItemType},
+        item::Item,
+        test_utils::PlayerBuilder,
+    };
+
+    #[derive(Default)]
+    struct MockMigrator {
+        version: Int,
+        applied: Vec<Int>,
+        fail_at: Option<Int>,
+    }
+    impl Migrate for MockMigrator {
+        fn get_schema_version(&mut self) -> FFResult<Int> {
+            Ok(self.version)
+        }
+
+        fn migrate_to(&mut self, version: Int) -> FFResult<()> {
+            assert_eq!(version, self.version + 1);
+            if self.fail_at == Some(version) {
+                return Err(FFError::build(
+                    Severity::Fatal,
+                    "Migration failed".to_string(),
+                ));
+            }
+            self.version = version;
+            self.applied.push(version);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_migrate_schema() {
+        let mut db = MockMigrator {
+            version: 5,
+            ..Default::default()
+        };
+        migrate_schema(&mut db, 9, true).unwrap();
+        assert_eq!(db.applied, vec![6, 7, 8, 9]);
+
+        // already up to date
+        migrate_schema(&mut db, 9, true).unwrap();
+        assert_eq!(db.applied, vec![6, 7, 8, 9]);
+
+        // schema from the future
+        assert!(migrate_schema(&mut db, 8, true).is_err());
+        assert_eq!(db.version, 9);
+    }
+
+    #[test]
+    fn test_migrate_schema_manual() {
+        let mut db = MockMigrator {
+            version: 7,
+            ..Default::default()
+        };
+        assert!(migrate_schema(&mut db, 9, false).is_err());
+        assert!(db.applied.is_empty());
+
+        // nothing to do, so nothing to confirm
+        assert!(migrate_schema(&mut db, 7, false).is_ok());
+    }
+
+    #[test]
+    fn test_migrate_schema_failure() {
+        let mut db = MockMigrator {
+            version: 5,
+            fail_at: Some(8),
+            ..Default::default()
+        };
+        assert!(migrate_schema(&mut db, 9, true).is_err());
+        assert_eq!(db.version, 7);
+
+        // picks up where it left off once the problem is fixed
+        db.fail_at = None;
+        migrate_schema(&mut db, 9, true).unwrap();
+        assert_eq!(db.applied, vec![6, 7, 8, 9]);
+    }
+
+    fn queue_save(db_manager: &mut DbManager, players: Vec<Player>) {
+        db_manager
+            .op_queue
+            .push_back(Box::new(move |db: &mut dyn Database| {
+                let player_refs: Vec<&Player> = players.iter().collect();
+                db.save_players(&player_refs).unwrap();
+            }));
+    }
+
+    #[test]
+    fn test_edit_offline_player() {
+        let mut db = MockDatabase::default();
+        let acc_id = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap()
+            .id;
+        let player = PlayerBuilder::new(100).taros(500).build();
+        db.init_player(acc_id, &player).unwrap();
+
+        let taros = edit_offline_player(&mut db, 100, |player| {
+            player.set_taros(200);
+            Ok(player.get_taros())
+        })
+        .unwrap();
+        assert_eq!(taros, 200);
+        assert_eq!(db.load_player(acc_id, 100).unwrap().get_taros(), 200);
+
+        // a failed edit isn't saved
+        assert!(edit_offline_player(&mut db, 100, |player| {
+            player.set_taros(1);
+            Err::<(), _>(FFError::build(Severity::Warning, "nope".to_string()))
+        })
+        .is_err());
+        assert_eq!(db.load_player(acc_id, 100).unwrap().get_taros(), 200);
+
+        assert!(edit_offline_player(&mut db, 999, |_| Ok(())).is_err());
+    }
+
+    // An autosave snapshot taken before a trade is queued ahead of the trade's own save,
+    // so wherever a crash lands, the traded item ends up with exactly one of the players
+    #[test]
+    fn test_save_order_no_dupe() {
+        let item = Item::new(ItemType::General, 7);
+        for ops_before_crash in 0..=2 {
+            let mut db = MockDatabase::default();
+            let acc_id = db
+                .create_account(&"player".to_string(), &"hash".to_string())
+                .unwrap()
+                .id;
+            let acc_id_other = db
+                .create_account(&"other".to_string(), &"hash".to_string())
+                .unwrap()
+                .id;
+            let mut player = PlayerBuilder::new(100)
+                .item(ItemLocation::Inven, 0, item)
+                .build();
+            let mut player_other = PlayerBuilder::new(200).build();
+            db.init_player(acc_id, &player).unwrap();
+            db.init_player(acc_id_other, &player_other).unwrap();
+            let mut db_manager = DbManager {
+                db_impl: Box::new(db),
+                op_queue: VecDeque::new(),
+                shutdown: false,
+            };
+
+            queue_save(&mut db_manager, vec![player.clone(), player_other.clone()]);
+            let traded = player.set_item(ItemLocation::Inven, 0, None).unwrap();
+            player_other
+                .set_item(ItemLocation::Inven, 0, traded)
+                .unwrap();
+            queue_save(&mut db_manager, vec![player, player_other]);
+
+            for op in db_manager.op_queue.drain(..).take(ops_before_crash) {
+                op(&mut *db_manager.db_impl);
+            }
+
+            let db = &mut *db_manager.db_impl;
+            let copies = [(acc_id, 100), (acc_id_other, 200)]
+                .into_iter()
+                .filter(|(acc_id, pc_uid)| {
+                    let loaded = db.load_player(*acc_id, *pc_uid).unwrap();
+                    loaded.get_item(ItemLocation::Inven, 0).unwrap().is_some()
+                })
+                .count();
+            assert_eq!(copies, 1, "crashed after {} op(s)", ops_before_crash);
+        }
+    }
+}
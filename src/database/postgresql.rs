@@ -7,12 +7,14 @@ use crate::{
     database::*,
     defines::*,
     entity::{BuddyListEntry, Combatant, Entity, PlayerFlags, PlayerStyle},
-    enums::PlayerGuide,
-    item::Item,
+    enums::{PlayerGuide, ReportCategory, TransactionKind},
+    item::{Item, ItemProvenance, RedeemCode},
     mission::Task,
     nano::Nano,
     net::packet::*,
+    report::Report,
     tabledata::tdata_get,
+    transaction::Transaction,
     util::{self, Bitfield},
     Position,
 };
@@ -171,7 +173,7 @@ impl PostgresDatabase {
                 &(player.get_nano_potions() as Int),
                 &((player.get_guide() as i16) as Int),
                 &player.mission_journal.get_active_mission_id().unwrap_or(0),
-                &player.flags.scamper_flags.get_chunk(0).unwrap(),
+                &player.flags.scamper_flags.to_array()[0],
                 &player.flags.skyway_flags.to_bytes().as_slice(),
                 &player.flags.tip_flags.to_bytes().as_slice(),
                 &player
@@ -179,6 +181,10 @@ impl PostgresDatabase {
                     .completed_mission_flags
                     .to_bytes()
                     .as_slice(),
+                &player
+                    .get_buddy_warp_time()
+                    .map_or(0, |time| util::get_timestamp_sec(time) as Int),
+                &(player.get_total_guides() as Int),
             ],
         )?;
 
@@ -201,6 +207,10 @@ impl PostgresDatabase {
         Self::exec(client, "clear_items", &[&pc_uid])?;
         for (slot_num, item) in player.get_item_iter() {
             let item_raw: sItemBase = Some(*item).into();
+            let provenance: Option<Text> = item
+                .get_provenance()
+                .map(|provenance| provenance.encode())
+                .transpose()?;
             client
                 .execute(
                     &save_item,
@@ -211,6 +221,7 @@ impl PostgresDatabase {
                         &(item_raw.iType as Int),
                         &item_raw.iOpt,
                         &item_raw.iTimeLimit,
+                        &provenance,
                     ],
                 )
                 .map_err(FFError::from_db_err)?;
@@ -232,7 +243,12 @@ impl PostgresDatabase {
         }
 
         Self::exec(client, "clear_running_quests", &[&pc_uid])?;
-        for task in player.mission_journal.get_running_quests() {
+        for (slot_num, task) in player
+            .mission_journal
+            .get_running_quests()
+            .iter()
+            .enumerate()
+        {
             if task.m_aCurrTaskID == 0 {
                 continue;
             }
@@ -242,6 +258,7 @@ impl PostgresDatabase {
                     &save_running_quest,
                     &[
                         &pc_uid,
+                        &(slot_num as Int),
                         &(task.m_aCurrTaskID as Int),
                         &(task.m_aKillNPCCount[0] as Int),
                         &(task.m_aKillNPCCount[1] as Int),
@@ -261,6 +278,15 @@ impl PostgresDatabase {
             Self::exec(client, "save_block", &[&pc_uid, &blocked_uid])?;
         }
 
+        Self::exec(client, "clear_crate_pity", &[&pc_uid])?;
+        for (crate_id, pity) in player.get_crate_pity_iter() {
+            Self::exec(
+                client,
+                "save_crate_pity",
+                &[&pc_uid, &(crate_id as Int), &(pity as Int)],
+            )?;
+        }
+
         tsct.commit().map_err(FFError::from_db_err)?;
         Ok(())
     }
@@ -329,33 +355,31 @@ impl PostgresDatabase {
 
         let mut player_flags = PlayerFlags::default();
         let first_use_bytes: &[u8] = row.get("FirstUseFlag");
-        player_flags.tip_flags = Bitfield::from_bytes(first_use_bytes, SIZEOF_TIP_FLAGS)?;
+        player_flags.tip_flags = Bitfield::from_bytes(first_use_bytes);
         player_flags.tutorial_flag = row.get::<_, Int>("TutorialFlag") != 0;
+        player_flags.payzone_flag = row.get::<_, Int>("PayZoneFlag") != 0;
         player_flags.name_check = (row.get::<_, Int>("NameCheck") as i8).try_into()?;
         player.flags = player_flags;
 
         let guide: PlayerGuide = (row.get::<_, Int>("Mentor") as i16).try_into()?;
-        // TODO get total number of guides from DB (currently not stored)
-        if guide != PlayerGuide::Computress {
-            player.update_guide(guide);
-        }
+        let total_guides: Int = row.get("MentorCount");
+        player.set_guide_data(guide, total_guides as usize);
 
         let skyway_bytes: &[u8] = row.get("SkywayLocationFlag");
-        player.flags.skyway_flags =
-            Bitfield::from_bytes(skyway_bytes, WYVERN_LOCATION_FLAG_SIZE as usize)?;
-
-        player
-            .flags
-            .scamper_flags
-            .set_chunk(0, row.get("WarpLocationFlag"))
-            .unwrap();
+        player.flags.skyway_flags = Bitfield::from_bytes(skyway_bytes);
+        player.flags.scamper_flags = Bitfield::from_array([row.get("WarpLocationFlag")]);
 
         let quest_bytes: &[u8] = row.get("Quests");
-        player.mission_journal.completed_mission_flags =
-            Bitfield::from_bytes(quest_bytes, SIZEOF_QUESTFLAG_NUMBER as usize)?;
+        player.mission_journal.completed_mission_flags = Bitfield::from_bytes(quest_bytes);
+
+        let buddy_warp_time: Int = row.get("BuddyWarpTime");
+        if buddy_warp_time > 0 {
+            player.set_buddy_warp_time(Some(util::get_systime_from_sec(buddy_warp_time as u64)));
+        }
 
         let running_quests = Self::query(client, "load_running_quests", &[&pc_uid])?;
         for quest in running_quests {
+            let slot_num: Int = quest.get("Slot");
             let task_id: Int = quest.get("TaskID");
             let task_def = tdata_get().get_task_definition(task_id)?;
             let npc_count_1: Int = quest.get("RemainingNPCCount1");
@@ -368,7 +392,14 @@ impl PostgresDatabase {
                 npc_count_2 as usize,
                 npc_count_3 as usize,
             ]);
-            player.mission_journal.start_task(task)?;
+            if slot_num < 0 {
+                // saved before journal slots existed; migrated rows have negative slots
+                player.mission_journal.start_task(task)?;
+            } else {
+                player
+                    .mission_journal
+                    .restore_task(task, slot_num as usize)?;
+            }
         }
 
         let active_mission_id: Int = row.get("CurrentMissionID");
@@ -389,8 +420,23 @@ impl PostgresDatabase {
                 iOpt: item.get::<_, Int>("Opt"),
                 iTimeLimit: item.get::<_, Int>("TimeLimit"),
             };
+            let row_provenance: Option<Text> = item.get("Provenance");
 
-            let item: Option<Item> = item_raw.try_into()?;
+            let mut item: Option<Item> = match item_raw.try_into() {
+                Ok(item) => item,
+                Err(e) => {
+                    log(
+                        Severity::Warning,
+                        &format!(
+                            "Dropped malformed item {:?} from player with UID {}: {}",
+                            item_raw,
+                            pc_uid,
+                            e.get_msg()
+                        ),
+                    );
+                    continue;
+                }
+            };
             if item.is_some_and(|item| {
                 item.get_expiry_time()
                     .is_some_and(|et| et < SystemTime::now())
@@ -398,6 +444,21 @@ impl PostgresDatabase {
                 // item is expired; skip it
                 continue;
             }
+            // rows from before provenance was tracked have none
+            if let (Some(item), Some(text)) = (item.as_mut(), row_provenance) {
+                match ItemProvenance::decode(&text) {
+                    Ok(provenance) => item.set_provenance(Some(provenance)),
+                    Err(e) => log(
+                        Severity::Warning,
+                        &format!(
+                            "Dropped provenance of item {:?} from player with UID {}: {}",
+                            item_raw,
+                            pc_uid,
+                            e.get_msg()
+                        ),
+                    ),
+                }
+            }
 
             let (loc, slot_num) = util::slot_num_to_loc_and_slot_num(slot_num)?;
             player.set_item(loc, slot_num, item)?;
@@ -410,14 +471,25 @@ impl PostgresDatabase {
             player.set_quest_item_count(item_id as i16, count as usize)?;
         }
 
+        let crate_pity = Self::query(client, "load_crate_pity", &[&pc_uid])?;
+        for row in crate_pity {
+            let crate_id: Int = row.get("CrateID");
+            let pity: Int = row.get("Pity");
+            player.set_crate_pity(crate_id as i16, pity as u32);
+        }
+
         if load_buddies {
             log_if_failed(Self::load_buddies(client, &mut player));
             log_if_failed(Self::load_blocks(client, &mut player));
+            log_if_failed(Self::load_ignores(client, &mut player));
         }
 
         let perms: Int = row.get("AccountLevel");
         player.perms = perms as i16;
+        let free_chat: Int = row.get("FreeChat");
+        player.menuchat_only = free_chat == 0;
 
+        player.validate()?;
         Ok(player)
     }
 
@@ -462,7 +534,56 @@ impl PostgresDatabase {
         }
         Ok(())
     }
+
+    fn load_ignores(client: &mut impl GenericClient, player: &mut Player) -> FFResult<()> {
+        let rows = Self::query(client, "load_ignored_ids", &[&player.get_uid()])?;
+        for row in rows {
+            let ignored_uid: BigInt = row.get("IgnoredPlayerId");
+            log_if_failed(player.ignore_player(ignored_uid).map(|_| ()));
+        }
+        Ok(())
+    }
+
+    // A hand-edited row can hold anything, so clamp it rather than letting it wrap
+    fn get_slot_bonus(row: &Row) -> u8 {
+        let slot_bonus: Int = row.get("SlotBonus");
+        check_slot_bonus(slot_bonus).unwrap_or_else(|e| {
+            log_error(&e);
+            slot_bonus.clamp(0, u8::MAX as Int) as u8
+        })
+    }
+}
+// the oldest schema version there are migration scripts from
+const OLDEST_MIGRATABLE_VERSION: Int = 5;
+
+impl Migrate for PostgresDatabase {
+    fn get_schema_version(&mut self) -> FFResult<Int> {
+        let rows = Self::query(&mut self.client, "get_db_version", &[])?;
+        let row = rows.first().ok_or(FFError::build(
+            Severity::Fatal,
+            "Database version missing from meta table".to_string(),
+        ))?;
+        Ok(row.get("Value"))
+    }
+
+    fn migrate_to(&mut self, version: Int) -> FFResult<()> {
+        if version <= OLDEST_MIGRATABLE_VERSION || version > DB_VERSION {
+            return Err(FFError::build(
+                Severity::Fatal,
+                format!("No migration to database version {}", version),
+            ));
+        }
+
+        // the script bumps the version itself, in the same transaction
+        Self::exec(
+            &mut self.client,
+            &format!("migrations/{}", version),
+            &[&version],
+        )?;
+        Ok(())
+    }
 }
+
 impl Database for PostgresDatabase {
     fn init_player(&mut self, acc_id: BigInt, player: &Player) -> FFResult<()> {
         let client = &mut self.client;
@@ -522,6 +643,28 @@ impl Database for PostgresDatabase {
         Ok(())
     }
 
+    fn update_player_name(
+        &mut self,
+        pc_uid: BigInt,
+        first_name: &Text,
+        last_name: &Text,
+        name_check: Int,
+    ) -> FFResult<()> {
+        let client = &mut self.client;
+        let updated = Self::exec(
+            client,
+            "update_name",
+            &[&pc_uid, first_name, last_name, &name_check],
+        )?;
+        if updated == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Failed to change name for player with UID {}", pc_uid),
+            ));
+        }
+        Ok(())
+    }
+
     fn find_account_from_username(&mut self, username: &Text) -> FFResult<Option<Account>> {
         let client = &mut self.client;
         let rows = Self::query(client, "find_account", &[username])?;
@@ -534,6 +677,8 @@ impl Database for PostgresDatabase {
             account_level: row.get::<_, Int>("AccountLevel") as i16,
             banned_until: util::get_systime_from_sec(row.get::<_, Int>("BannedUntil") as u64),
             ban_reason: row.get("BanReason"),
+            slot_bonus: Self::get_slot_bonus(row),
+            free_chat_allowed: row.get::<_, Int>("FreeChat") != 0,
         }))
     }
 
@@ -555,9 +700,31 @@ impl Database for PostgresDatabase {
             account_level: row.get::<_, Int>("AccountLevel") as i16,
             banned_until: util::get_systime_from_sec(row.get::<_, Int>("BannedUntil") as u64),
             ban_reason: row.get("BanReason"),
+            slot_bonus: Self::get_slot_bonus(row),
+            free_chat_allowed: row.get::<_, Int>("FreeChat") != 0,
         })
     }
 
+    fn find_banned_accounts(&mut self, pc_uids: &[BigInt]) -> FFResult<Vec<Account>> {
+        let client = &mut self.client;
+        let timestamp_now = util::get_timestamp_sec(SystemTime::now()) as Int;
+        let rows = Self::query(client, "find_banned_accounts", &[&pc_uids, &timestamp_now])?;
+        Ok(rows
+            .iter()
+            .map(|row| Account {
+                id: row.get("AccountId"),
+                username: row.get("Login"),
+                password_hashed: row.get("Password"),
+                selected_slot: row.get::<_, Int>("Selected") as u8,
+                account_level: row.get::<_, Int>("AccountLevel") as i16,
+                banned_until: util::get_systime_from_sec(row.get::<_, Int>("BannedUntil") as u64),
+                ban_reason: row.get("BanReason"),
+                slot_bonus: Self::get_slot_bonus(row),
+                free_chat_allowed: row.get::<_, Int>("FreeChat") != 0,
+            })
+            .collect())
+    }
+
     fn create_account(&mut self, username: &Text, password_hashed: &Text) -> FFResult<Account> {
         let client = &mut self.client;
 
@@ -628,6 +795,44 @@ impl Database for PostgresDatabase {
         Ok(())
     }
 
+    fn set_account_slot_bonus(&mut self, acc_id: BigInt, slot_bonus: Int) -> FFResult<()> {
+        check_slot_bonus(slot_bonus)?;
+        let client = &mut self.client;
+        let updated = Self::exec(client, "set_account_slot_bonus", &[&acc_id, &slot_bonus])?;
+        if updated == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Failed to set slot bonus for account with ID {}", acc_id),
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_account_free_chat(&mut self, acc_id: BigInt, allowed: bool) -> FFResult<()> {
+        let client = &mut self.client;
+        let free_chat = allowed as Int;
+        let updated = Self::exec(client, "set_account_free_chat", &[&acc_id, &free_chat])?;
+        if updated == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Failed to set free chat for account with ID {}", acc_id),
+            ));
+        }
+        Ok(())
+    }
+
+    fn add_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()> {
+        let client = &mut self.client;
+        Self::exec(client, "save_ignore", &[&pc_uid, &ignored_uid])?;
+        Ok(())
+    }
+
+    fn remove_ignore(&mut self, pc_uid: BigInt, ignored_uid: BigInt) -> FFResult<()> {
+        let client = &mut self.client;
+        Self::exec(client, "delete_ignore", &[&pc_uid, &ignored_uid])?;
+        Ok(())
+    }
+
     fn update_selected_player(&mut self, acc_id: BigInt, slot_num: Int) -> FFResult<()> {
         let client = &mut self.client;
         let timestamp_now = util::get_timestamp_sec(SystemTime::now()) as Int;
@@ -657,21 +862,24 @@ impl Database for PostgresDatabase {
         ))
     }
 
-    fn load_players(&mut self, acc_id: BigInt) -> FFResult<Vec<Player>> {
+    fn load_players(&mut self, acc_id: BigInt) -> FFResult<Vec<Result<Player, UnavailablePlayer>>> {
         let client = &mut self.client;
         let chars = Self::query(client, "load_players", &[&acc_id])?;
         let mut players = Vec::with_capacity(chars.len());
         for row in chars {
-            match Self::load_player_internal(client, &row, true) {
-                Ok(p) => players.push(p),
-                Err(e) => {
-                    let pc_uid: BigInt = row.get("PlayerId");
-                    log(
-                        Severity::Warning,
-                        &format!("Failed to load player {}: {}", pc_uid, e.get_msg()),
-                    );
+            let player = Self::load_player_internal(client, &row, true).map_err(|e| {
+                let pc_uid: BigInt = row.get("PlayerId");
+                log(
+                    Severity::Warning,
+                    &format!("Failed to load player {}: {}", pc_uid, e.get_msg()),
+                );
+                UnavailablePlayer {
+                    pc_uid,
+                    slot_num: row.get::<_, Int>("Slot") as usize,
+                    err: e,
                 }
-            }
+            });
+            players.push(player);
         }
         Ok(players)
     }
@@ -695,4 +903,429 @@ impl Database for PostgresDatabase {
         assert_eq!(updated, 1);
         Ok(())
     }
+
+    fn create_redeem_code(&mut self, code: &RedeemCode) -> FFResult<()> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let create_redeem_code = Self::prep(&mut tsct, "create_redeem_code")?;
+        let save_redeem_code_item = Self::prep(&mut tsct, "save_redeem_code_item")?;
+
+        let expires = code
+            .expiry_time
+            .map(|et| util::get_timestamp_sec(et) as Int)
+            .unwrap_or(0);
+        let created = tsct
+            .execute(
+                &create_redeem_code,
+                &[
+                    &code.code,
+                    &(code.taros as Int),
+                    &(code.fusion_matter as Int),
+                    &(code.max_uses as Int),
+                    &code.once_per_account,
+                    &expires,
+                ],
+            )
+            .map_err(FFError::from_db_err)?;
+        if created == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Redeem code {} already exists", code.code),
+            ));
+        }
+
+        for (slot_num, item) in code.items.iter().enumerate() {
+            let item_raw: sItemBase = Some(*item).into();
+            tsct.execute(
+                &save_redeem_code_item,
+                &[
+                    &code.code,
+                    &(slot_num as Int),
+                    &(item_raw.iID as Int),
+                    &(item_raw.iType as Int),
+                    &item_raw.iOpt,
+                ],
+            )
+            .map_err(FFError::from_db_err)?;
+        }
+
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(())
+    }
+
+    fn find_redeem_code(&mut self, code: &Text) -> FFResult<Option<RedeemCode>> {
+        let client = &mut self.client;
+        let rows = Self::query(client, "find_redeem_code", &[code])?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+
+        let expires = row.get::<_, Int>("Expires") as u64;
+        let mut redeem_code = RedeemCode {
+            code: row.get("Code"),
+            taros: row.get::<_, Int>("Taros") as u32,
+            fusion_matter: row.get::<_, Int>("FusionMatter") as u32,
+            items: Vec::new(),
+            max_uses: row.get::<_, Int>("MaxUses") as u32,
+            uses: row.get::<_, Int>("Uses") as u32,
+            once_per_account: row.get("OncePerAccount"),
+            expiry_time: if expires == 0 {
+                None
+            } else {
+                Some(util::get_systime_from_sec(expires))
+            },
+        };
+
+        let items = Self::query(client, "load_redeem_code_items", &[code])?;
+        for item in items {
+            let item_raw = sItemBase {
+                iType: item.get::<_, Int>("Type") as i16,
+                iID: item.get::<_, Int>("ID") as i16,
+                iOpt: item.get::<_, Int>("Quantity"),
+                iTimeLimit: 0,
+            };
+            let item: Option<Item> = item_raw.try_into()?;
+            if let Some(item) = item {
+                redeem_code.items.push(item);
+            }
+        }
+        Ok(Some(redeem_code))
+    }
+
+    fn use_redeem_code(&mut self, pc_uid: BigInt, code: &Text) -> FFResult<bool> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let timestamp_now = util::get_timestamp_sec(SystemTime::now()) as Int;
+
+        // the row lock taken here serializes concurrent redemptions of the same code
+        let rows = Self::query(&mut tsct, "use_redeem_code", &[code, &timestamp_now])?;
+        let Some(row) = rows.first() else {
+            // expired, out of uses, or nonexistent
+            return Ok(false);
+        };
+        let once_per_account: bool = row.get("OncePerAccount");
+
+        let redemptions: BigInt = Self::query(
+            &mut tsct,
+            "count_code_redemptions",
+            &[code, &pc_uid, &once_per_account],
+        )?[0]
+            .get("Redemptions");
+        if redemptions > 0 {
+            // already redeemed; dropping the transaction rolls back the use
+            return Ok(false);
+        }
+
+        Self::exec(&mut tsct, "save_code_redemption", &[&pc_uid, code])?;
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(true)
+    }
+
+    fn log_currency_events(&mut self, events: &[(BigInt, CurrencyEvent)]) -> FFResult<()> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let log_currency_event = Self::prep(&mut tsct, "log_currency_event")?;
+        for (pc_uid, event) in events {
+            tsct.execute(
+                &log_currency_event,
+                &[
+                    pc_uid,
+                    &(event.currency as Int),
+                    &(event.source as Int),
+                    &event.delta,
+                    &(event.balance as BigInt),
+                    &(util::get_timestamp_sec(event.time) as Int),
+                ],
+            )
+            .map_err(FFError::from_db_err)?;
+        }
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(())
+    }
+
+    fn save_race_results(&mut self, results: &[(BigInt, RaceResult)]) -> FFResult<()> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let save_race_result = Self::prep(&mut tsct, "save_race_result")?;
+        for (pc_uid, result) in results {
+            tsct.execute(
+                &save_race_result,
+                &[
+                    &(result.ep_id as BigInt),
+                    pc_uid,
+                    &result.score,
+                    &result.ring_count,
+                    &result.time_sec,
+                    &(util::get_timestamp_sec(result.timestamp) as Int),
+                ],
+            )
+            .map_err(FFError::from_db_err)?;
+        }
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(())
+    }
+
+    fn add_kill_counts(&mut self, kills: &[(BigInt, Int)]) -> FFResult<()> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let add_kill_count = Self::prep(&mut tsct, "add_kill_count")?;
+        for (pc_uid, count) in kills {
+            tsct.execute(&add_kill_count, &[pc_uid, &(*count as BigInt)])
+                .map_err(FFError::from_db_err)?;
+        }
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(())
+    }
+
+    fn load_leaderboard(
+        &mut self,
+        board: Leaderboard,
+        count: Int,
+    ) -> FFResult<Vec<LeaderboardEntry>> {
+        let client = &mut self.client;
+        let rows = match board {
+            Leaderboard::Race { ep_id } => Self::query(
+                client,
+                "load_race_leaderboard",
+                &[&(ep_id as BigInt), &count],
+            )?,
+            Leaderboard::Kills => Self::query(client, "load_kill_leaderboard", &[&count])?,
+        };
+        let entries = rows
+            .iter()
+            .map(|row| {
+                let first_name: Text = row.get("FirstName");
+                let last_name: Text = row.get("LastName");
+                LeaderboardEntry {
+                    pc_uid: row.get("PlayerID"),
+                    name: format!("{} {}", first_name, last_name),
+                    score: row.get("Score"),
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn load_personal_best(
+        &mut self,
+        board: Leaderboard,
+        pc_uid: BigInt,
+    ) -> FFResult<Option<BigInt>> {
+        let client = &mut self.client;
+        let rows = match board {
+            Leaderboard::Race { ep_id } => {
+                Self::query(client, "load_race_best", &[&(ep_id as BigInt), &pc_uid])?
+            }
+            Leaderboard::Kills => Self::query(client, "load_kill_count", &[&pc_uid])?,
+        };
+        // MAX() yields a NULL row when there are no results
+        Ok(rows.first().and_then(|row| row.get("Score")))
+    }
+
+    fn issue_auth_token(
+        &mut self,
+        acc_id: BigInt,
+        token_id: &Text,
+        token_hash: &Text,
+        expires: SystemTime,
+    ) -> FFResult<()> {
+        let client = &mut self.client;
+        Self::exec(
+            client,
+            "issue_auth_token",
+            &[
+                token_id,
+                token_hash,
+                &acc_id,
+                &(util::get_timestamp_sec(expires) as Int),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn consume_auth_token(
+        &mut self,
+        token_id: &Text,
+        time: SystemTime,
+        check_secret: &dyn Fn(&Text) -> bool,
+    ) -> FFResult<Option<Account>> {
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        // the row lock taken here keeps the token from being used twice at once
+        let rows = Self::query(
+            &mut tsct,
+            "find_auth_token",
+            &[token_id, &(util::get_timestamp_sec(time) as Int)],
+        )?;
+        assert!(rows.len() <= 1);
+        let Some(row) = rows.first() else {
+            // used, expired, or nonexistent
+            return Ok(None);
+        };
+        if !check_secret(&row.get("TokenHash")) {
+            // dropping the transaction releases the token untouched
+            return Ok(None);
+        }
+        let account = Account {
+            id: row.get("AccountId"),
+            username: row.get("Login"),
+            password_hashed: row.get("Password"),
+            selected_slot: row.get::<_, Int>("Selected") as u8,
+            account_level: row.get::<_, Int>("AccountLevel") as i16,
+            banned_until: util::get_systime_from_sec(row.get::<_, Int>("BannedUntil") as u64),
+            ban_reason: row.get("BanReason"),
+            slot_bonus: Self::get_slot_bonus(row),
+            free_chat_allowed: row.get::<_, Int>("FreeChat") != 0,
+        };
+        Self::exec(&mut tsct, "consume_auth_token", &[token_id])?;
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(Some(account))
+    }
+
+    fn log_trade(&mut self, transactions: &[Transaction]) -> FFResult<()> {
+        let encode_items = |items: &[Item]| {
+            serde_json::to_string(items).map_err(|e| {
+                FFError::build(
+                    Severity::Warning,
+                    format!("Couldn't encode transaction items: {}", e),
+                )
+            })
+        };
+
+        let mut tsct = self.client.transaction().map_err(FFError::from_db_err)?;
+        let log_transaction = Self::prep(&mut tsct, "log_transaction")?;
+        for transaction in transactions {
+            tsct.execute(
+                &log_transaction,
+                &[
+                    &(transaction.kind as Int),
+                    &transaction.pc_uid,
+                    &transaction.other_pc_uid,
+                    &(transaction.taros_given as BigInt),
+                    &(transaction.taros_received as BigInt),
+                    &encode_items(&transaction.items_given)?,
+                    &encode_items(&transaction.items_received)?,
+                    &(util::get_timestamp_sec(transaction.time) as Int),
+                ],
+            )
+            .map_err(FFError::from_db_err)?;
+        }
+        tsct.commit().map_err(FFError::from_db_err)?;
+        Ok(())
+    }
+
+    fn load_transactions(&mut self, pc_uid: BigInt, count: Int) -> FFResult<Vec<Transaction>> {
+        let decode_items = |json: Text| -> FFResult<Vec<Item>> {
+            serde_json::from_str(&json).map_err(|e| {
+                FFError::build(
+                    Severity::Warning,
+                    format!("Couldn't decode transaction items: {}", e),
+                )
+            })
+        };
+
+        let client = &mut self.client;
+        let rows = Self::query(client, "load_transactions", &[&pc_uid, &count])?;
+        rows.iter()
+            .map(|row| {
+                Ok(Transaction {
+                    kind: TransactionKind::try_from(row.get::<_, Int>("Kind"))?,
+                    pc_uid: row.get("PlayerID"),
+                    other_pc_uid: row.get("OtherPlayerID"),
+                    taros_given: row.get::<_, BigInt>("TarosGiven") as u32,
+                    items_given: decode_items(row.get("ItemsGiven"))?,
+                    taros_received: row.get::<_, BigInt>("TarosReceived") as u32,
+                    items_received: decode_items(row.get("ItemsReceived"))?,
+                    time: util::get_systime_from_sec(row.get::<_, Int>("Timestamp") as u64),
+                })
+            })
+            .collect()
+    }
+
+    fn prune_transactions(&mut self, before: SystemTime) -> FFResult<u64> {
+        let client = &mut self.client;
+        Self::exec(
+            client,
+            "prune_transactions",
+            &[&(util::get_timestamp_sec(before) as Int)],
+        )
+    }
+
+    fn file_report(&mut self, report: &Report) -> FFResult<BigInt> {
+        let client = &mut self.client;
+        let reported_pos = report.reported_pos;
+        let rows = Self::query(
+            client,
+            "file_report",
+            &[
+                &report.reporter_uid,
+                &report.reported_uid,
+                &(report.category as Int),
+                &report.reason,
+                &(util::get_timestamp_sec(report.time) as Int),
+                &report.shard_id,
+                &(report.channel_num as Int),
+                &report.reporter_pos.x,
+                &report.reporter_pos.y,
+                &report.reporter_pos.z,
+                &reported_pos.map(|pos| pos.x),
+                &reported_pos.map(|pos| pos.y),
+                &reported_pos.map(|pos| pos.z),
+                &(report.flagged as Int),
+            ],
+        )?;
+        let row = rows.first().ok_or(FFError::build(
+            Severity::Warning,
+            "Filed report didn't get an ID".to_string(),
+        ))?;
+        Ok(row.get("ReportID"))
+    }
+
+    fn load_open_reports(&mut self, offset: Int, count: Int) -> FFResult<Vec<Report>> {
+        let client = &mut self.client;
+        let rows = Self::query(client, "load_open_reports", &[&offset, &count])?;
+        rows.iter()
+            .map(|row| {
+                let reported_pos = match (
+                    row.get::<_, Option<Int>>("ReportedX"),
+                    row.get::<_, Option<Int>>("ReportedY"),
+                    row.get::<_, Option<Int>>("ReportedZ"),
+                ) {
+                    (Some(x), Some(y), Some(z)) => Some(Position { x, y, z }),
+                    _ => None,
+                };
+                Ok(Report {
+                    id: row.get("ReportID"),
+                    reporter_uid: row.get("ReporterID"),
+                    reported_uid: row.get("ReportedID"),
+                    category: ReportCategory::try_from(row.get::<_, Int>("Category"))?,
+                    reason: row.get("Reason"),
+                    time: util::get_systime_from_sec(row.get::<_, Int>("Timestamp") as u64),
+                    shard_id: row.get("ShardID"),
+                    channel_num: row.get::<_, Int>("ChannelNum") as u8,
+                    reporter_pos: Position {
+                        x: row.get("ReporterX"),
+                        y: row.get("ReporterY"),
+                        z: row.get("ReporterZ"),
+                    },
+                    reported_pos,
+                    flagged: row.get::<_, Int>("Flagged") != 0,
+                    handled_by: row.get("HandledBy"),
+                })
+            })
+            .collect()
+    }
+
+    fn resolve_report(
+        &mut self,
+        report_id: BigInt,
+        handled_by: BigInt,
+        time: SystemTime,
+    ) -> FFResult<bool> {
+        let client = &mut self.client;
+        let updated = Self::exec(
+            client,
+            "resolve_report",
+            &[
+                &report_id,
+                &handled_by,
+                &(util::get_timestamp_sec(time) as Int),
+            ],
+        )?;
+        Ok(updated > 0)
+    }
 }
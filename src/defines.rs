@@ -2,7 +2,7 @@
 
 pub const LIB_VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 pub const PROTOCOL_VERSION: i32 = 104;
-pub const DB_VERSION: i32 = 5;
+pub const DB_VERSION: i32 = 18;
 
 pub const MAX_NUM_SHARDS: usize = 9;
 pub const MAX_NUM_CHANNELS: usize = 25;
@@ -32,11 +32,60 @@ pub const RANGE_GROUP_WARP: u32 = 500;
 pub const RANGE_INTERACT: u32 = 800;
 pub const RANGE_TRIGGER: u32 = 1600;
 pub const RANGE_GROUP_PARTICIPATE: u32 = 5000;
+pub const RANGE_ESCORT_LEASH: u32 = RANGE_GROUP_PARTICIPATE;
+
+pub const ESCORT_LEASH_SECS: u64 = 30;
+
+// each player past the first to damage a world boss adds this much of its base HP
+pub const BOSS_HP_SCALE_PER_PLAYER: f32 = 0.5;
+
+// snared combatants move at this fraction of their usual speed
+pub const SNARE_SPEED_FACTOR: f32 = 0.5;
+// boosting vehicles go this much faster, burning a weapon battery every interval
+pub const VEHICLE_BOOST_SPEED_FACTOR: f32 = 1.5;
+pub const VEHICLE_BOOST_DRAIN_INTERVAL_SECS: u64 = 1;
+// out of combat, NPCs heal this much of their max HP at a time,
+// getting back to full in about the time they'd take to respawn
+pub const NPC_REGEN_PERCENT: i32 = 10;
+// share of a world boss's total damage taken a player needs to deal to get loot
+pub const BOSS_LOOT_MIN_CONTRIBUTION: f32 = 0.05;
+pub const BOSS_HP_ANNOUNCE_THRESHOLDS: [i32; 3] = [75, 50, 25];
+pub const BOSS_TOP_CONTRIBUTORS_SHOWN: usize = 3;
+
+// per account, across all of its characters
+pub const MAX_IGNORED_PLAYERS: usize = 100;
+
+// fighting this recently keeps a safe zone exit from being instant
+pub const SAFE_EXIT_COMBAT_COOLDOWN_SECS: u64 = 10;
+
+// per shard world snapshot; the rest are left out
+pub const MAX_TRACKED_NPCS: usize = 32;
+
+// per item; older trades fall off the end
+pub const MAX_ITEM_TRADE_HOPS: usize = 4;
 
 pub const FLAG_PC_STATE_VEHICLE: i8 = 8;
 
 pub const MSG_BOX_DURATION_DEFAULT: i32 = 3;
 
+pub const LATENCY_SAMPLE_COUNT: usize = 10;
+
+// packets that race each other can make a skill look used a little early
+pub const SKILL_COOLDOWN_GRACE_MS: u64 = 300;
+
+pub const PATH_RECORD_INTERVAL_MS: u64 = 500;
+// movement under this between samples counts as standing still
+pub const PATH_RECORD_MIN_DISTANCE: u32 = 50;
+pub const PATH_RECORD_MAX_POINTS: usize = 256;
+
+// vendor buys at or above this price are saved right away
+pub const IMMEDIATE_SAVE_MIN_PRICE: u64 = 10000;
+
+pub const EMOTE_CODE_SIT: i32 = 7;
+pub const EMOTE_DURATION_SECS: u64 = 5;
+
+pub const ERROR_CODE_BUDDY_DENY: i32 = 6;
+
 pub const SIZEOF_TIP_FLAGS: usize = 2;
 pub const SIZEOF_SCAMPER_FLAGS: usize = 1;
 
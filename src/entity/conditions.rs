@@ -0,0 +1,204 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{defines::*, entity::EntityID};
+
+// Conditions that keep a combatant from attacking or moving
+pub const CSB_BITS_INCAPACITATED: u32 = CSB_BIT_STUN | CSB_BIT_MEZ | CSB_BIT_KNOCKDOWN;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ConditionEntry {
+    bit: u32,
+    expire_time: SystemTime,
+    source: EntityID,
+}
+
+// Timed statuses put on a combatant by something else, like stuns from a skill.
+// Each source gets its own entry, so a bit stays on until the last one runs out.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    entries: Vec<ConditionEntry>,
+}
+impl Conditions {
+    // Reapplying from the same source refreshes the duration.
+    // Returns whether the bit just turned on.
+    pub fn apply(
+        &mut self,
+        bit: u32,
+        duration: Duration,
+        source: EntityID,
+        time: SystemTime,
+    ) -> bool {
+        let turned_on = !self.has(bit);
+        let expire_time = time + duration;
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.bit == bit && entry.source == source)
+        {
+            Some(entry) => entry.expire_time = expire_time,
+            None => self.entries.push(ConditionEntry {
+                bit,
+                expire_time,
+                source,
+            }),
+        }
+        turned_on
+    }
+
+    // Clears the bit from one source, or from all of them.
+    // Returns whether the bit turned off.
+    pub fn clear(&mut self, bit: u32, source: Option<EntityID>) -> bool {
+        if !self.has(bit) {
+            return false;
+        }
+        self.entries
+            .retain(|entry| entry.bit != bit || source.is_some_and(|src| entry.source != src));
+        !self.has(bit)
+    }
+
+    pub fn clear_all(&mut self) -> bool {
+        let had_any = !self.entries.is_empty();
+        self.entries.clear();
+        had_any
+    }
+
+    pub fn has(&self, bit: u32) -> bool {
+        self.entries.iter().any(|entry| entry.bit & bit != 0)
+    }
+
+    pub fn get_bit_flag(&self) -> u32 {
+        self.entries
+            .iter()
+            .fold(0, |flags, entry| flags | entry.bit)
+    }
+
+    // When the bit will turn off if nothing else happens
+    pub fn get_expire_time(&self, bit: u32) -> Option<SystemTime> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.bit == bit)
+            .map(|entry| entry.expire_time)
+            .max()
+    }
+
+    // Drops entries that ran out.
+    // Returns the bits that turned off as a result, in the order they did.
+    pub fn take_expired(&mut self, time: SystemTime) -> Vec<u32> {
+        let mut expired: Vec<(SystemTime, u32)> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.expire_time <= time)
+            .map(|entry| (entry.expire_time, entry.bit))
+            .collect();
+        if expired.is_empty() {
+            return Vec::new();
+        }
+        self.entries.retain(|entry| entry.expire_time > time);
+
+        // a bit turns off when its last source runs out
+        expired.sort();
+        let mut turned_off: Vec<(SystemTime, u32)> = Vec::new();
+        for (expire_time, bit) in expired.into_iter().rev() {
+            if !self.has(bit) && !turned_off.iter().any(|(_, b)| *b == bit) {
+                turned_off.push((expire_time, bit));
+            }
+        }
+        turned_off.sort();
+        turned_off.into_iter().map(|(_, bit)| bit).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC_A: EntityID = EntityID::Player(1);
+    const SRC_B: EntityID = EntityID::NPC(2);
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_condition_expiry_order() {
+        let now = SystemTime::now();
+        let mut conditions = Conditions::default();
+        assert!(conditions.apply(CSB_BIT_DN_MOVE_SPEED, secs(20), SRC_A, now));
+        assert!(conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now));
+        assert!(conditions.apply(CSB_BIT_INVULNERABLE, secs(10), SRC_B, now));
+
+        assert!(conditions.take_expired(now + secs(4)).is_empty());
+        assert_eq!(
+            conditions.take_expired(now + secs(15)),
+            vec![CSB_BIT_STUN, CSB_BIT_INVULNERABLE]
+        );
+        assert_eq!(conditions.get_bit_flag(), CSB_BIT_DN_MOVE_SPEED);
+
+        // reapplying from the same source refreshes instead of stacking
+        assert!(!conditions.apply(CSB_BIT_DN_MOVE_SPEED, secs(30), SRC_A, now));
+        assert!(conditions.take_expired(now + secs(25)).is_empty());
+        assert_eq!(
+            conditions.take_expired(now + secs(30)),
+            vec![CSB_BIT_DN_MOVE_SPEED]
+        );
+        assert_eq!(conditions.get_bit_flag(), 0);
+    }
+
+    #[test]
+    fn test_condition_overlapping_sources() {
+        let now = SystemTime::now();
+        let mut conditions = Conditions::default();
+        assert!(conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now));
+        assert!(!conditions.apply(CSB_BIT_STUN, secs(10), SRC_B, now));
+        assert_eq!(
+            conditions.get_expire_time(CSB_BIT_STUN),
+            Some(now + secs(10))
+        );
+
+        // the first source running out doesn't end the stun
+        assert!(conditions.take_expired(now + secs(5)).is_empty());
+        assert!(conditions.has(CSB_BIT_STUN));
+        assert_eq!(conditions.take_expired(now + secs(10)), vec![CSB_BIT_STUN]);
+
+        // both running out in the same tick only reports it once
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now);
+        conditions.apply(CSB_BIT_STUN, secs(6), SRC_B, now);
+        assert_eq!(conditions.take_expired(now + secs(6)), vec![CSB_BIT_STUN]);
+
+        // clearing one source leaves the other
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now);
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_B, now);
+        assert!(!conditions.clear(CSB_BIT_STUN, Some(SRC_A)));
+        assert!(conditions.has(CSB_BIT_STUN));
+        assert!(conditions.clear(CSB_BIT_STUN, Some(SRC_B)));
+        assert!(!conditions.clear(CSB_BIT_STUN, None));
+
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now);
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_B, now);
+        assert!(conditions.clear(CSB_BIT_STUN, None));
+        assert_eq!(conditions.get_bit_flag(), 0);
+    }
+
+    #[test]
+    fn test_condition_bit_flag() {
+        let now = SystemTime::now();
+        let mut conditions = Conditions::default();
+        assert_eq!(conditions.get_bit_flag(), CSB_BIT_NONE);
+
+        conditions.apply(CSB_BIT_STUN, secs(5), SRC_A, now);
+        conditions.apply(CSB_BIT_DN_MOVE_SPEED, secs(5), SRC_A, now);
+        conditions.apply(CSB_BIT_DN_MOVE_SPEED, secs(5), SRC_B, now);
+        assert_eq!(
+            conditions.get_bit_flag(),
+            CSB_BIT_STUN | CSB_BIT_DN_MOVE_SPEED
+        );
+        assert!(conditions.has(CSB_BITS_INCAPACITATED));
+        assert!(!conditions.has(CSB_BIT_INVULNERABLE));
+
+        conditions.clear(CSB_BIT_STUN, None);
+        assert_eq!(conditions.get_bit_flag(), CSB_BIT_DN_MOVE_SPEED);
+        assert!(!conditions.has(CSB_BITS_INCAPACITATED));
+        assert!(conditions.clear_all());
+        assert_eq!(conditions.get_bit_flag(), CSB_BIT_NONE);
+    }
+}
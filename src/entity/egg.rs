@@ -1,21 +1,39 @@
-use std::{any::Any, time::SystemTime};
-
-use rand::rngs::ThreadRng;
+use std::{
+    any::Any,
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     chunk::{ChunkCoords, InstanceID},
     entity::{Entity, EntityID},
-    error::FFResult,
+    error::{FFError, FFResult, Severity},
     net::{
         packet::{sP_FE2CL_SHINY_ENTER, sP_FE2CL_SHINY_EXIT, sShinyAppearanceData, PacketID::*},
         ClientMap, FFClient,
     },
+    rng::GameRng,
     state::ShardServerState,
     Position,
 };
 
 use super::Combatant;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShinyReward {
+    Crate(i16),
+    Reward(i32),
+}
+
+// How a summoned egg should be set up, whether a GM or an event asked for it
+#[derive(Debug, Clone, Copy)]
+pub struct EggSummon {
+    pub ty: i32,
+    pub instance_id: InstanceID,
+    pub lifetime: Duration,
+    pub reward: Option<ShinyReward>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Egg {
     id: i32,
@@ -24,6 +42,9 @@ pub struct Egg {
     instance_id: InstanceID,
     respawn_time: Option<SystemTime>,
     summoned: bool,
+    despawn_time: Option<SystemTime>,
+    reward: Option<ShinyReward>,
+    picked_up_by: HashSet<i64>,
 }
 impl Egg {
     pub fn new(
@@ -40,9 +61,16 @@ impl Egg {
             instance_id,
             respawn_time: None,
             summoned,
+            despawn_time: None,
+            reward: None,
+            picked_up_by: HashSet::new(),
         }
     }
 
+    pub fn get_type(&self) -> i32 {
+        self.ty
+    }
+
     pub fn is_live(&self) -> bool {
         self.respawn_time.is_none()
     }
@@ -50,6 +78,52 @@ impl Egg {
     pub fn is_summoned(&self) -> bool {
         self.summoned
     }
+
+    pub fn set_despawn_time(&mut self, time: SystemTime) {
+        self.despawn_time = Some(time);
+    }
+
+    pub fn is_expired(&self, time: SystemTime) -> bool {
+        self.despawn_time
+            .is_some_and(|despawn_time| time >= despawn_time)
+    }
+
+    // Overrides the egg type's own C.R.A.T.E.
+    pub fn set_reward(&mut self, reward: ShinyReward) {
+        self.reward = Some(reward);
+    }
+
+    pub fn get_reward(&self) -> Option<ShinyReward> {
+        self.reward
+    }
+
+    // Summoned eggs stay up for everyone until they despawn, but each player only gets one go.
+    // Regular eggs go away for everyone until `respawn_after` has passed.
+    pub fn pick_up(
+        &mut self,
+        player_uid: i64,
+        time: SystemTime,
+        respawn_after: Duration,
+    ) -> FFResult<()> {
+        if !self.is_live() || self.is_expired(time) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Egg {} isn't available to pick up", self.id),
+            ));
+        }
+
+        if self.summoned {
+            if !self.picked_up_by.insert(player_uid) {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Player {} already picked up egg {}", player_uid, self.id),
+                ));
+            }
+        } else {
+            self.respawn_time = Some(time + respawn_after);
+        }
+        Ok(())
+    }
 }
 impl Entity for Egg {
     fn get_id(&self) -> EntityID {
@@ -106,8 +180,14 @@ impl Entity for Egg {
         time: &SystemTime,
         clients: &mut ClientMap,
         state: &mut ShardServerState,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) {
+        if self.is_expired(*time) {
+            state.entity_map.update(self.get_id(), None, Some(clients));
+            state.entity_map.mark_for_cleanup(self.get_id());
+            return;
+        }
+
         if let Some(respawn_time) = self.respawn_time {
             if time >= &respawn_time {
                 self.respawn_time = None;
@@ -138,3 +218,58 @@ impl Entity for Egg {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::defines::ID_OVERWORLD;
+
+    fn get_test_egg(summoned: bool) -> Egg {
+        let instance_id = InstanceID {
+            channel_num: 1,
+            map_num: ID_OVERWORLD,
+            instance_num: None,
+        };
+        Egg::new(1, 1, Position::default(), instance_id, summoned)
+    }
+
+    #[test]
+    fn test_egg_pick_up() {
+        let now = SystemTime::now();
+        let respawn_after = Duration::from_secs(30);
+
+        // summoned eggs can be picked up once per player
+        let mut egg = get_test_egg(true);
+        assert!(egg.pick_up(1, now, respawn_after).is_ok());
+        assert!(egg.pick_up(1, now, respawn_after).is_err());
+        assert!(egg.pick_up(2, now, respawn_after).is_ok());
+        assert!(egg.is_live());
+
+        // regular eggs go away for everyone until they respawn
+        let mut egg = get_test_egg(false);
+        assert!(egg.pick_up(1, now, respawn_after).is_ok());
+        assert!(!egg.is_live());
+        assert!(egg.pick_up(2, now, respawn_after).is_err());
+    }
+
+    #[test]
+    fn test_egg_despawn() {
+        let now = SystemTime::now();
+        let respawn_after = Duration::from_secs(30);
+
+        let mut egg = get_test_egg(true);
+        assert!(!egg.is_expired(now + Duration::from_secs(3600)));
+
+        egg.set_despawn_time(now + Duration::from_secs(60));
+        assert!(!egg.is_expired(now));
+        assert!(!egg.is_expired(now + Duration::from_secs(59)));
+        assert!(egg.is_expired(now + Duration::from_secs(60)));
+
+        // nobody can grab it once it's expired
+        assert!(egg
+            .pick_up(1, now + Duration::from_secs(61), respawn_after)
+            .is_err());
+        assert!(egg.pick_up(1, now, respawn_after).is_ok());
+    }
+}
@@ -1,4 +1,8 @@
-use std::{any::Any, collections::HashSet, time::SystemTime};
+use std::{
+    any::Any,
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     chunk::ChunkCoords,
@@ -9,10 +13,14 @@ use crate::{
         packet::{sNPCGroupMemberInfo, sPCGroupMemberInfo},
         ClientMap, FFClient,
     },
+    rng::GameRng,
     state::ShardServerState,
     Position,
 };
 
+mod conditions;
+pub use conditions::*;
+
 mod egg;
 pub use egg::*;
 
@@ -23,7 +31,6 @@ mod player;
 pub use player::*;
 
 mod slider;
-use rand::rngs::ThreadRng;
 pub use slider::*;
 use uuid::Uuid;
 
@@ -52,7 +59,7 @@ pub trait Entity {
         time: &SystemTime,
         clients: &mut ClientMap,
         state: &mut ShardServerState,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     );
     fn cleanup(&mut self, clients: &mut ClientMap, state: &mut ShardServerState);
 
@@ -65,11 +72,14 @@ pub trait Entity {
 
 pub trait Combatant: Entity {
     fn get_condition_bit_flag(&self) -> i32;
+    fn get_conditions(&self) -> &Conditions;
+    fn get_conditions_mut(&mut self) -> &mut Conditions;
     fn get_group_id(&self) -> Option<Uuid>;
     fn get_level(&self) -> i16;
     fn get_hp(&self) -> i32;
     fn get_max_hp(&self) -> i32;
     fn get_style(&self) -> Option<CombatStyle>;
+    fn get_style_modifiers(&self) -> (Option<f32>, Option<f32>);
     fn get_team(&self) -> CombatantTeam;
     fn get_char_type(&self) -> CharType;
     fn get_aggro_factor(&self) -> f32;
@@ -81,6 +91,31 @@ pub trait Combatant: Entity {
 
     fn take_damage(&mut self, damage: i32, source: EntityID) -> i32;
     fn reset(&mut self);
+
+    // Returns whether the bit just turned on; see helpers::apply_condition to tell clients
+    fn apply_condition(
+        &mut self,
+        bit: u32,
+        duration: Duration,
+        source: EntityID,
+        time: SystemTime,
+    ) -> bool {
+        self.get_conditions_mut().apply(bit, duration, source, time)
+    }
+
+    // Returns whether the bit turned off
+    fn clear_condition(&mut self, bit: u32, source: Option<EntityID>) -> bool {
+        self.get_conditions_mut().clear(bit, source)
+    }
+
+    fn has_condition(&self, bit: u32) -> bool {
+        self.get_condition_bit_flag() as u32 & bit != 0
+    }
+
+    // Stunned, mezzed, or knocked down combatants can't attack or move
+    fn can_act(&self) -> bool {
+        !self.has_condition(CSB_BITS_INCAPACITATED)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -169,15 +204,18 @@ impl Group {
     ) -> (Vec<sPCGroupMemberInfo>, Vec<sNPCGroupMemberInfo>) {
         let mut pc_group_data = Vec::with_capacity(GROUP_MAX_PLAYER_COUNT);
         let mut npc_group_data = Vec::with_capacity(GROUP_MAX_NPC_COUNT);
+        // members that aren't in the entity map right now are left out
         for eid in &self.members {
             match eid {
                 EntityID::Player(pc_id) => {
-                    let player = state.get_player(*pc_id).unwrap();
-                    pc_group_data.push(player.get_group_member_info());
+                    if let Ok(player) = state.get_player(*pc_id) {
+                        pc_group_data.push(player.get_group_member_info());
+                    }
                 }
                 EntityID::NPC(npc_id) => {
-                    let npc = state.get_npc(*npc_id).unwrap();
-                    npc_group_data.push(npc.get_group_member_info());
+                    if let Ok(npc) = state.get_npc(*npc_id) {
+                        npc_group_data.push(npc.get_group_member_info());
+                    }
                 }
                 _ => unreachable!(),
             }
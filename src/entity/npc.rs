@@ -1,27 +1,31 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-use rand::rngs::ThreadRng;
 use uuid::Uuid;
 
 use crate::{
     ai::AI,
     chunk::{ChunkCoords, InstanceID},
-    defines::RANGE_INTERACT,
-    entity::{Combatant, Entity, EntityID},
-    enums::{CharType, CombatStyle, CombatantTeam},
+    defines::{
+        BOSS_HP_ANNOUNCE_THRESHOLDS, BOSS_HP_SCALE_PER_PLAYER, BOSS_LOOT_MIN_CONTRIBUTION,
+        CSB_BIT_DN_MOVE_SPEED, CSB_BIT_INVULNERABLE, ESCORT_LEASH_SECS, NPC_REGEN_PERCENT,
+        RANGE_ESCORT_LEASH, RANGE_INTERACT, SNARE_SPEED_FACTOR,
+    },
+    entity::{Combatant, Conditions, Entity, EntityID},
+    enums::{CharType, CombatStyle, CombatantTeam, SkillType},
     error::FFResult,
     net::{
         packet::{
             sNPCAppearanceData, sNPCGroupMemberInfo, sP_FE2CL_NPC_ENTER, sP_FE2CL_NPC_EXIT,
-            sP_FE2CL_NPC_MOVE, PacketID,
+            sP_FE2CL_NPC_MOVE, sP_FE2CL_NPC_SKILL_HIT, sSkillResult_Heal_HP, PacketID,
         },
-        ClientMap, FFClient,
+        ClientMap, FFClient, PacketBuffer,
     },
     path::Path,
+    rng::GameRng,
     state::ShardServerState,
     tabledata::tdata_get,
     util::{self, clamp_min},
@@ -35,6 +39,7 @@ pub struct NPC {
     position: Position,
     rotation: i32,
     hp: i32,
+    base_max_hp: i32,
     pub target_id: Option<EntityID>,
     pub last_attacked_by: Option<EntityID>,
     pub invulnerable: bool,
@@ -44,9 +49,24 @@ pub struct NPC {
     pub path: Option<Path>,
     pub group_id: Option<Uuid>,
     pub loose_follow: Option<EntityID>,
+    // where a grouped NPC was invited from; it walks back here once released
+    pub escort_home: Option<Position>,
+    escort_strayed_since: Option<SystemTime>,
     pub interacting_pcs: HashSet<i32>,
     pub summoned: bool,
+    pub mob_drop_id: Option<i32>,
     pub ai: Option<AI>,
+    pub is_boss: bool,
+    // damage dealt by each player, by player ID. only tracked for bosses
+    boss_damage_taken: HashMap<i32, i32>,
+    // the lowest HP percentage threshold that's been announced so far
+    boss_hp_announced: Option<i32>,
+    // players who've damaged this NPC since it last reset, for kill credit
+    contributors: HashSet<i32>,
+    // only NPCs that tick (i.e. have AI) ever have these run out
+    conditions: Conditions,
+    // when out-of-combat regen last healed, or started waiting to
+    regen_since: Option<SystemTime>,
 }
 impl NPC {
     pub fn new(
@@ -57,12 +77,43 @@ impl NPC {
         instance_id: InstanceID,
     ) -> FFResult<Self> {
         let stats = tdata_get().get_npc_stats(ty)?;
-        Ok(Self {
+        Ok(Self::new_with_hp(
+            id,
+            ty,
+            position,
+            angle,
+            instance_id,
+            stats.max_hp as i32,
+        ))
+    }
+
+    // Skips the NPC stats lookup, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_mock(
+        id: i32,
+        ty: i32,
+        position: Position,
+        instance_id: InstanceID,
+        hp: i32,
+    ) -> Self {
+        Self::new_with_hp(id, ty, position, 0, instance_id, hp)
+    }
+
+    fn new_with_hp(
+        id: i32,
+        ty: i32,
+        position: Position,
+        angle: i32,
+        instance_id: InstanceID,
+        hp: i32,
+    ) -> Self {
+        Self {
             id,
             ty,
             position,
             rotation: angle % 360,
-            hp: stats.max_hp as i32,
+            hp,
+            base_max_hp: hp,
             target_id: None,
             last_attacked_by: None,
             invulnerable: false,
@@ -72,10 +123,84 @@ impl NPC {
             path: None,
             group_id: None,
             loose_follow: None,
+            escort_home: None,
+            escort_strayed_since: None,
             interacting_pcs: HashSet::new(),
             summoned: false,
+            mob_drop_id: None,
             ai: None,
-        })
+            is_boss: false,
+            boss_damage_taken: HashMap::new(),
+            boss_hp_announced: None,
+            contributors: HashSet::new(),
+            conditions: Conditions::default(),
+            regen_since: None,
+        }
+    }
+
+    // Heals NPC_REGEN_PERCENT of max HP every `interval` while out of combat.
+    // Getting into a fight starts the wait over. Returns how much was healed.
+    pub fn tick_regen(&mut self, time: SystemTime, interval: Duration) -> i32 {
+        let max_hp = self.get_max_hp();
+        if self.is_dead() || self.target_id.is_some() || self.retreating || self.hp >= max_hp {
+            self.regen_since = None;
+            return 0;
+        }
+
+        let since = *self.regen_since.get_or_insert(time);
+        if time.duration_since(since).unwrap_or_default() < interval {
+            return 0;
+        }
+        let old_hp = self.hp;
+        self.hp = (self.hp + max_hp * NPC_REGEN_PERCENT / 100).clamp(old_hp + 1, max_hp);
+        self.regen_since = Some(time);
+        self.hp - old_hp
+    }
+
+    // Shows everyone around the NPC's HP going up by `amount`
+    pub fn broadcast_heal(
+        &self,
+        amount: i32,
+        state: &mut ShardServerState,
+        clients: &mut ClientMap,
+    ) {
+        // only built if someone's around to see it
+        let mut payload = None;
+        state
+            .entity_map
+            .for_each_around(self.get_id(), clients, |c| {
+                let payload = payload.get_or_insert_with(|| {
+                    let pkt = sP_FE2CL_NPC_SKILL_HIT {
+                        iNPC_ID: self.id,
+                        iSkillID: unused!(),
+                        iValue1: unused!(),
+                        iValue2: unused!(),
+                        iValue3: unused!(),
+                        eST: SkillType::ReturnHomeHeal as i32,
+                        iTargetCnt: 1,
+                    };
+                    let result = sSkillResult_Heal_HP {
+                        eCT: self.get_char_type() as i32,
+                        iID: self.id,
+                        iHealHP: amount,
+                        iHP: self.hp,
+                    };
+                    let mut payload = PacketBuffer::default();
+                    payload.queue_packet(PacketID::P_FE2CL_NPC_SKILL_HIT, &pkt);
+                    payload.queue_struct(&result);
+                    payload
+                });
+                c.send_payload(payload.clone())
+            });
+    }
+
+    // Back to full health with nothing held against anyone, once it's made it home
+    pub fn return_home_heal(&mut self, state: &mut ShardServerState, clients: &mut ClientMap) {
+        let old_hp = self.hp;
+        self.reset();
+        if self.hp > old_hp {
+            self.broadcast_heal(self.hp - old_hp, state, clients);
+        }
     }
 
     pub fn set_path(&mut self, path: Path) {
@@ -86,6 +211,90 @@ impl NPC {
         self.loose_follow = Some(entity_id);
     }
 
+    pub fn join_group(&mut self, group_id: Uuid, pc_id: i32) {
+        self.group_id = Some(group_id);
+        self.loose_follow = Some(EntityID::Player(pc_id));
+        // if it's still walking back from a previous group, home stays the same
+        self.escort_home.get_or_insert(self.position);
+        self.escort_strayed_since = None;
+    }
+
+    pub fn leave_group(&mut self) {
+        self.group_id = None;
+        self.loose_follow = None;
+        self.target_id = None;
+        self.escort_strayed_since = None;
+    }
+
+    // Returns true once the escorted player has been out of range for too long.
+    // A player that can't be found at all counts as out of range
+    pub fn check_escort_leash(&mut self, player_pos: Option<Position>, time: SystemTime) -> bool {
+        let in_range =
+            player_pos.is_some_and(|pos| pos.distance_to(&self.position) <= RANGE_ESCORT_LEASH);
+        if in_range {
+            self.escort_strayed_since = None;
+            return false;
+        }
+
+        let strayed_since = *self.escort_strayed_since.get_or_insert(time);
+        time.duration_since(strayed_since).unwrap_or_default()
+            >= Duration::from_secs(ESCORT_LEASH_SECS)
+    }
+
+    pub fn get_contributors(&self) -> &HashSet<i32> {
+        &self.contributors
+    }
+
+    pub fn get_boss_contributor_count(&self) -> usize {
+        self.boss_damage_taken.len()
+    }
+
+    // Players who dealt enough of the damage to earn loot, in no particular order
+    pub fn get_boss_loot_eligible(&self) -> Vec<i32> {
+        let total: i32 = self.boss_damage_taken.values().sum();
+        let min_damage = total as f32 * BOSS_LOOT_MIN_CONTRIBUTION;
+        self.boss_damage_taken
+            .iter()
+            .filter(|(_, damage)| **damage > 0 && **damage as f32 >= min_damage)
+            .map(|(pc_id, _)| *pc_id)
+            .collect()
+    }
+
+    // Player IDs and damage dealt, highest first
+    pub fn get_boss_top_contributors(&self, count: usize) -> Vec<(i32, i32)> {
+        let mut contributors: Vec<(i32, i32)> = self
+            .boss_damage_taken
+            .iter()
+            .map(|(pc_id, damage)| (*pc_id, *damage))
+            .collect();
+        contributors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        contributors.truncate(count);
+        contributors
+    }
+
+    // Returns the HP percentage threshold the boss just fell past, if any.
+    // If it fell past several at once, only the lowest one is returned
+    pub fn check_boss_hp_threshold(&mut self) -> Option<i32> {
+        if !self.is_boss || self.is_dead() {
+            return None;
+        }
+
+        let hp_percent = self.hp * 100 / self.get_max_hp();
+        let crossed = BOSS_HP_ANNOUNCE_THRESHOLDS
+            .iter()
+            .copied()
+            .filter(|threshold| hp_percent <= *threshold)
+            .filter(|threshold| {
+                self.boss_hp_announced
+                    .map_or(true, |last| *threshold < last)
+            })
+            .min();
+        if crossed.is_some() {
+            self.boss_hp_announced = crossed;
+        }
+        crossed
+    }
+
     pub fn get_group_member_info(&self) -> sNPCGroupMemberInfo {
         sNPCGroupMemberInfo {
             iNPC_ID: self.id,
@@ -124,10 +333,12 @@ impl NPC {
         if path.tick(&mut self.position) {
             let new_angle = old_pos.angle_to(&self.position) as i32;
             self.set_rotation(util::angle_to_rotation(new_angle));
-            let chunk_pos = self.get_chunk_coords();
-            state
-                .entity_map
-                .update(self.get_id(), Some(chunk_pos), Some(clients));
+            state.entity_map.update_moved(
+                self.get_id(),
+                self.position,
+                self.instance_id,
+                Some(clients),
+            );
 
             let run_speed = tdata_get().get_npc_stats(self.ty).unwrap().run_speed;
             let pkt = sP_FE2CL_NPC_MOVE {
@@ -185,11 +396,16 @@ impl Entity for NPC {
     }
 
     fn get_speed(&self) -> i32 {
-        if let Some(path) = &self.path {
+        let speed = if let Some(path) = &self.path {
             path.get_speed()
         } else {
             let stats = tdata_get().get_npc_stats(self.ty).unwrap();
             stats.walk_speed
+        };
+        if self.conditions.has(CSB_BIT_DN_MOVE_SPEED) {
+            (speed as f32 * SNARE_SPEED_FACTOR) as i32
+        } else {
+            speed
         }
     }
 
@@ -222,8 +438,14 @@ impl Entity for NPC {
         time: &SystemTime,
         clients: &mut ClientMap,
         state: &mut ShardServerState,
-        rng: &mut ThreadRng,
+        rng: &mut GameRng,
     ) {
+        crate::helpers::tick_conditions(self, time, clients, state);
+        if let Some(threshold) = self.check_boss_hp_threshold() {
+            let msg = format!("The world boss is down to {}% HP!", threshold);
+            crate::helpers::send_shard_announcement(clients, &msg);
+        }
+
         let pc_ids: Vec<i32> = self.interacting_pcs.iter().copied().collect();
         for pc_id in pc_ids {
             let pc_eid = EntityID::Player(pc_id);
@@ -275,7 +497,15 @@ impl Entity for NPC {
 }
 impl Combatant for NPC {
     fn get_condition_bit_flag(&self) -> i32 {
-        placeholder!(0)
+        self.conditions.get_bit_flag() as i32
+    }
+
+    fn get_conditions(&self) -> &Conditions {
+        &self.conditions
+    }
+
+    fn get_conditions_mut(&mut self) -> &mut Conditions {
+        &mut self.conditions
     }
 
     fn get_group_id(&self) -> Option<Uuid> {
@@ -292,8 +522,13 @@ impl Combatant for NPC {
     }
 
     fn get_max_hp(&self) -> i32 {
-        let stats = tdata_get().get_npc_stats(self.ty).unwrap();
-        stats.max_hp as i32
+        if !self.is_boss {
+            return self.base_max_hp;
+        }
+
+        let extra_players = self.boss_damage_taken.len().saturating_sub(1);
+        let scale = 1.0 + BOSS_HP_SCALE_PER_PLAYER * extra_players as f32;
+        (self.base_max_hp as f32 * scale) as i32
     }
 
     fn get_style(&self) -> Option<CombatStyle> {
@@ -301,6 +536,10 @@ impl Combatant for NPC {
         Some(stats.style)
     }
 
+    fn get_style_modifiers(&self) -> (Option<f32>, Option<f32>) {
+        (None, None)
+    }
+
     fn get_team(&self) -> CombatantTeam {
         let stats = tdata_get().get_npc_stats(self.ty).unwrap();
         stats.team
@@ -348,7 +587,7 @@ impl Combatant for NPC {
     }
 
     fn take_damage(&mut self, damage: i32, source: EntityID) -> i32 {
-        if self.invulnerable || self.retreating {
+        if self.invulnerable || self.retreating || self.conditions.has(CSB_BIT_INVULNERABLE) {
             return 0;
         }
 
@@ -357,15 +596,195 @@ impl Combatant for NPC {
             self.target_id = Some(source);
         }
 
+        let boss_contributor = match source {
+            EntityID::Player(pc_id) if self.is_boss => Some(pc_id),
+            _ => None,
+        };
+        if let Some(pc_id) = boss_contributor {
+            if !self.boss_damage_taken.contains_key(&pc_id) && !self.is_dead() {
+                // a new challenger raises the boss's max HP; heal by the difference
+                let old_max_hp = self.get_max_hp();
+                self.boss_damage_taken.insert(pc_id, 0);
+                self.hp += self.get_max_hp() - old_max_hp;
+            }
+        }
+
         let init_hp = self.hp;
         self.hp = clamp_min(self.hp - damage, 0);
-        init_hp - self.hp
+        let dealt = init_hp - self.hp;
+        if let Some(pc_id) = boss_contributor {
+            *self.boss_damage_taken.entry(pc_id).or_default() += dealt;
+        }
+        if let (EntityID::Player(pc_id), true) = (source, dealt > 0) {
+            self.contributors.insert(pc_id);
+        }
+        dealt
     }
 
     fn reset(&mut self) {
         self.last_attacked_by = None;
         self.target_id = None;
         self.retreating = false;
+        self.boss_damage_taken.clear();
+        self.boss_hp_announced = None;
+        self.contributors.clear();
+        self.conditions.clear_all();
+        self.regen_since = None;
         self.hp = self.get_max_hp();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::FakeClients;
+
+    #[test]
+    fn test_escort_group() {
+        let home = Position {
+            x: 100,
+            y: 100,
+            z: 0,
+        };
+        let mut npc = NPC::new_mock(1, 1, home, InstanceID::default(), 100);
+        let group_id = Uuid::new_v4();
+
+        // joining makes it follow the inviter
+        npc.join_group(group_id, 7);
+        assert_eq!(npc.group_id, Some(group_id));
+        assert_eq!(npc.loose_follow, Some(EntityID::Player(7)));
+        assert_eq!(npc.escort_home, Some(home));
+
+        // rejoining before it made it back home keeps the original home
+        let away = Position { x: 5000, ..home };
+        npc.set_position(away);
+        npc.leave_group();
+        npc.join_group(group_id, 7);
+        assert_eq!(npc.escort_home, Some(home));
+
+        // straying too far for too long breaks the leash
+        let start = SystemTime::now();
+        let leash = Duration::from_secs(ESCORT_LEASH_SECS);
+        let far = Position {
+            x: away.x + RANGE_ESCORT_LEASH as i32 + 1,
+            ..away
+        };
+        assert!(!npc.check_escort_leash(Some(away), start));
+        assert!(!npc.check_escort_leash(Some(far), start));
+        assert!(!npc.check_escort_leash(Some(far), start + leash / 2));
+        // coming back resets the timer
+        assert!(!npc.check_escort_leash(Some(away), start + leash / 2));
+        assert!(!npc.check_escort_leash(None, start + leash));
+        assert!(npc.check_escort_leash(None, start + leash * 2));
+
+        // leaving drops the follow and combat target, but remembers home
+        npc.target_id = Some(EntityID::NPC(2));
+        npc.leave_group();
+        assert_eq!(npc.group_id, None);
+        assert_eq!(npc.loose_follow, None);
+        assert_eq!(npc.target_id, None);
+        assert_eq!(npc.escort_home, Some(home));
+    }
+
+    #[test]
+    fn test_boss_contributions() {
+        let mut npc = NPC::new_mock(1, 1, Position::default(), InstanceID::default(), 1000);
+        npc.is_boss = true;
+
+        // a second player scales up max HP and heals by the difference
+        assert_eq!(npc.take_damage(100, EntityID::Player(1)), 100);
+        assert_eq!(npc.get_hp(), 900);
+        npc.take_damage(10, EntityID::Player(2));
+        assert_eq!(npc.get_max_hp(), 1500);
+        assert_eq!(npc.get_hp(), 1390);
+        assert_eq!(npc.get_boss_contributor_count(), 2);
+
+        // NPC damage doesn't count, and retargeting doesn't lose contributions
+        npc.take_damage(100, EntityID::NPC(2));
+        npc.target_id = Some(EntityID::Player(2));
+        assert_eq!(npc.get_boss_contributor_count(), 2);
+
+        // only the lowest threshold passed gets announced, and only once
+        npc.take_damage(1090, EntityID::Player(1));
+        assert_eq!(npc.get_hp(), 200);
+        assert_eq!(npc.check_boss_hp_threshold(), Some(25));
+        assert_eq!(npc.check_boss_hp_threshold(), None);
+
+        // player 2 dealt under the minimum share of damage
+        assert_eq!(npc.get_boss_loot_eligible(), vec![1]);
+        assert_eq!(npc.get_boss_top_contributors(5), vec![(1, 1190), (2, 10)]);
+
+        npc.reset();
+        assert_eq!(npc.get_boss_contributor_count(), 0);
+        assert_eq!(npc.get_hp(), 1000);
+        npc.take_damage(300, EntityID::Player(3));
+        assert_eq!(npc.check_boss_hp_threshold(), Some(75));
+    }
+
+    #[test]
+    fn test_regen() {
+        let mut npc = NPC::new_mock(1, 1, Position::default(), InstanceID::default(), 1000);
+        let interval = Duration::from_secs(5);
+        let start = SystemTime::UNIX_EPOCH;
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // no regen at full HP or while in combat
+        assert_eq!(npc.tick_regen(start, interval), 0);
+        npc.take_damage(950, EntityID::Player(1));
+        assert_eq!(npc.tick_regen(at(10), interval), 0);
+
+        // out of combat, the first tick starts the wait
+        npc.target_id = None;
+        assert_eq!(npc.tick_regen(at(10), interval), 0);
+        assert_eq!(npc.tick_regen(at(14), interval), 0);
+        assert_eq!(npc.tick_regen(at(15), interval), 100);
+        assert_eq!(npc.get_hp(), 150);
+
+        // re-aggro restarts the wait
+        npc.target_id = Some(EntityID::Player(1));
+        assert_eq!(npc.tick_regen(at(20), interval), 0);
+        npc.target_id = None;
+        assert_eq!(npc.tick_regen(at(21), interval), 0);
+        assert_eq!(npc.tick_regen(at(25), interval), 0);
+        assert_eq!(npc.tick_regen(at(26), interval), 100);
+
+        // none while retreating
+        npc.retreating = true;
+        assert_eq!(npc.tick_regen(at(60), interval), 0);
+        npc.retreating = false;
+
+        // stops at max HP
+        let mut time = 60;
+        while npc.get_hp() < npc.get_max_hp() {
+            npc.tick_regen(at(time), interval);
+            time += 5;
+        }
+        assert_eq!(npc.get_hp(), 1000);
+        assert_eq!(npc.tick_regen(at(time + 5), interval), 0);
+
+        // and never brings back the dead
+        npc.take_damage(1000, EntityID::Player(1));
+        npc.target_id = None;
+        assert!(npc.is_dead());
+        assert_eq!(npc.tick_regen(at(time + 10), interval), 0);
+        assert_eq!(npc.tick_regen(at(time + 20), interval), 0);
+    }
+
+    #[test]
+    fn test_return_home_heal() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(None);
+        let mut state = ShardServerState::new_mock(1);
+        let mut npc = NPC::new_mock(1, 1, Position::default(), InstanceID::default(), 1000);
+        npc.is_boss = true;
+
+        npc.take_damage(400, EntityID::Player(1));
+        npc.tick_regen(SystemTime::UNIX_EPOCH, Duration::from_secs(1));
+        npc.return_home_heal(&mut state, &mut fake_clients.get_map(key));
+        assert_eq!(npc.get_hp(), 1000);
+        assert_eq!(npc.get_boss_contributor_count(), 0);
+        assert!(npc.target_id.is_none());
+        assert!(npc.last_attacked_by.is_none());
+        assert!(npc.regen_since.is_none());
+    }
+}
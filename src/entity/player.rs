@@ -1,21 +1,26 @@
 use std::{
     any::Any,
-    collections::HashMap,
+    cmp::min,
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
     time::{Duration, SystemTime},
 };
 
 use crate::{
     chunk::{ChunkCoords, InstanceID},
+    config::{config_get, MapZone},
+    currency::{currency_log_queue, CurrencyEvent, CurrencyLog},
     database::db_run_sync,
     defines::*,
-    entity::{Combatant, Entity, EntityID},
+    entity::{Combatant, Conditions, Entity, EntityID, ShinyReward},
     enums::{
-        CharType, CombatStyle, CombatantTeam, ItemLocation, ItemType, PlayerGuide,
-        PlayerNameStatus, RewardCategory, RewardType, RideType, TaskType,
+        CharType, CombatStyle, CombatantTeam, CurrencySource, ItemLocation, ItemType, PlayerGuide,
+        PlayerNameStatus, RewardCategory, RewardType, RideType, TaskType, TimeBuffType,
+        TimeBuffUpdate, TransportationType,
     },
     error::{codes, log, log_if_failed, panic_log, FFError, FFResult, Severity},
     item::Item,
+    journal::{journal_commit, journal_mark, journal_record, JournalOp},
     mission::{MissionJournal, Task, TaskDefinition},
     nano::Nano,
     net::{
@@ -25,16 +30,20 @@ use crate::{
         },
         ClientMap, ClientType, FFClient,
     },
-    path::Path,
+    path::{Path, PathRecorder},
+    rng::GameRng,
     state::ShardServerState,
-    tabledata::{tdata_get, TripData},
+    tabledata::{tdata_get, SkywayLeg, TripData, WarpData},
     util::{self, clamp, clamp_max, clamp_min, Bitfield},
     Position,
 };
 
-use rand::{rngs::ThreadRng, Rng};
+use rand::Rng;
 use uuid::Uuid;
 
+// an inventory slot and what's in it now
+pub type ChangedSlot = (ItemLocation, usize, Option<Item>);
+
 #[derive(Debug, Clone)]
 pub struct PlayerMetadata {
     pub first_name: String,
@@ -60,8 +69,7 @@ impl TryFrom<sPCStyle> for PlayerStyle {
     type Error = FFError;
 
     fn try_from(style: sPCStyle) -> FFResult<Self> {
-        // TODO style validation
-        Ok(Self {
+        let style = Self {
             gender: style.iGender,
             face_style: style.iFaceStyle,
             hair_style: style.iHairStyle,
@@ -70,7 +78,39 @@ impl TryFrom<sPCStyle> for PlayerStyle {
             eye_color: style.iEyeColor,
             height: style.iHeight,
             body: style.iBody,
-        })
+        };
+        style.validate()?;
+        Ok(style)
+    }
+}
+impl PlayerStyle {
+    pub fn validate(&self) -> FFResult<()> {
+        // face and hair styles depend on gender
+        let (face_styles, hair_styles) = match self.gender {
+            1 => (1..=5, 1..=23),
+            2 => (6..=10, 25..=45),
+            _ => {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Invalid gender: {}", self.gender),
+                ))
+            }
+        };
+
+        let valid = face_styles.contains(&self.face_style)
+            && hair_styles.contains(&self.hair_style)
+            && (1..=18).contains(&self.hair_color)
+            && (1..=12).contains(&self.skin_color)
+            && (1..=5).contains(&self.eye_color)
+            && (0..=4).contains(&self.height)
+            && (0..=2).contains(&self.body);
+        if !valid {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Invalid player style: {:?}", self),
+            ));
+        }
+        Ok(())
     }
 }
 impl Default for PlayerStyle {
@@ -93,9 +133,9 @@ pub struct PlayerFlags {
     pub name_check: PlayerNameStatus,
     pub tutorial_flag: bool,
     pub payzone_flag: bool,
-    pub tip_flags: Bitfield<i64>,
-    pub scamper_flags: Bitfield<i32>,
-    pub skyway_flags: Bitfield<i64>,
+    pub tip_flags: Bitfield<i64, SIZEOF_TIP_FLAGS>,
+    pub scamper_flags: Bitfield<i32, SIZEOF_SCAMPER_FLAGS>,
+    pub skyway_flags: Bitfield<i64, { WYVERN_LOCATION_FLAG_SIZE as usize }>,
 }
 impl Default for PlayerFlags {
     fn default() -> Self {
@@ -103,9 +143,9 @@ impl Default for PlayerFlags {
             name_check: PlayerNameStatus::Pending,
             tutorial_flag: false,
             payzone_flag: false,
-            tip_flags: Bitfield::new(SIZEOF_TIP_FLAGS),
-            scamper_flags: Bitfield::new(SIZEOF_SCAMPER_FLAGS),
-            skyway_flags: Bitfield::new(WYVERN_LOCATION_FLAG_SIZE as usize),
+            tip_flags: Bitfield::new(),
+            scamper_flags: Bitfield::new(),
+            skyway_flags: Bitfield::new(),
         }
     }
 }
@@ -128,8 +168,76 @@ impl Default for GuideData {
 struct SkywayRideState {
     trip_data: &'static TripData,
     path: Path,
+    station: i32, // where the current leg ends
+    next_legs: VecDeque<SkywayLeg>,
     monkey_pos: Position,
     resume_time: SystemTime,
+    total_distance: u32,
+    distance_flown: u32, // by finished legs
+    disembark_requested: bool,
+}
+impl SkywayRideState {
+    fn new(trip_data: &'static TripData, legs: Vec<SkywayLeg>, monkey_pos: Position) -> Self {
+        let total_distance = legs.iter().map(|leg| leg.path.get_total_length()).sum();
+        let mut next_legs = VecDeque::from(legs);
+        let first_leg = next_legs.pop_front().unwrap();
+        let mut path = first_leg.path;
+        path.start();
+        Self {
+            trip_data,
+            path,
+            station: first_leg.end_location,
+            next_legs,
+            monkey_pos,
+            resume_time: SystemTime::now(),
+            total_distance,
+            distance_flown: 0,
+            disembark_requested: false,
+        }
+    }
+
+    // Moves on to the next leg once the current one has been flown.
+    // If the ride is over instead, returns what the rider owes for it.
+    fn finish_leg(&mut self) -> Option<u32> {
+        self.distance_flown += self.path.get_total_length();
+        if self.disembark_requested || self.next_legs.is_empty() {
+            return Some(self.get_fare());
+        }
+
+        let leg = self.next_legs.pop_front().unwrap();
+        self.station = leg.end_location;
+        self.path = leg.path;
+        self.path.start();
+        None
+    }
+
+    // Riders getting off early pay for the share of the distance they flew
+    fn get_fare(&self) -> u32 {
+        if self.next_legs.is_empty() || self.total_distance == 0 {
+            return self.trip_data.cost;
+        }
+        (self.trip_data.cost as u64 * self.distance_flown as u64 / self.total_distance as u64)
+            as u32
+    }
+}
+
+// Poses other players need to be told about when they first see this one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AvatarState {
+    #[default]
+    Idle,
+    Sitting,
+    Emote {
+        emote_code: i32,
+        expire_time: SystemTime,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TimedBuff {
+    buff_type: TimeBuffType,
+    expire_time: SystemTime,
+    duration: Duration,
 }
 
 #[derive(Debug, Clone)]
@@ -225,6 +333,11 @@ pub struct RewardData {
     fusion_matter: RewardRates,
 }
 impl RewardData {
+    // Back to 100% for everything
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
     pub fn set_reward_rate(&mut self, reward_type: RewardType, category: RewardCategory, val: f32) {
         let reward_rates = match reward_type {
             RewardType::Taros => &mut self.taros,
@@ -284,6 +397,124 @@ pub struct PreWarpData {
     pub position: Position,
 }
 
+// A GM attached to another player to watch them without being seen
+#[derive(Debug, Clone)]
+pub struct SpectateState {
+    pub target_pc_id: i32,
+    pub return_to: PreWarpData,
+    was_invisible: bool,
+    was_invulnerable: bool,
+}
+
+// What a player paid to use a warp, kept so it can be refunded if the warp falls through
+#[derive(Debug)]
+pub struct WarpPayment {
+    taros: u32,
+    // the consumed item's slot and its stack from before the warp
+    consumed_item: Option<(ItemLocation, usize, Item)>,
+}
+
+// An invite (group, trade, buddy) sent to another player that hasn't been answered yet.
+// Offers older than the configured timeout count as gone.
+#[derive(Debug, Clone, Copy)]
+pub struct PendingOffer<T> {
+    offer: Option<(T, SystemTime)>,
+}
+impl<T> Default for PendingOffer<T> {
+    fn default() -> Self {
+        Self { offer: None }
+    }
+}
+impl<T: Copy + PartialEq> PendingOffer<T> {
+    fn get_timeout() -> Duration {
+        Duration::from_secs(config_get().shard.offer_timeout.get())
+    }
+
+    fn is_expired(sent_time: SystemTime, time: SystemTime) -> bool {
+        time.duration_since(sent_time).unwrap_or_default() >= Self::get_timeout()
+    }
+
+    pub fn set(&mut self, target: T, time: SystemTime) {
+        self.offer = Some((target, time));
+    }
+
+    pub fn get_target(&self, time: SystemTime) -> Option<T> {
+        match self.offer {
+            Some((target, sent_time)) if !Self::is_expired(sent_time, time) => Some(target),
+            _ => None,
+        }
+    }
+
+    pub fn is_pending(&self, time: SystemTime) -> bool {
+        self.get_target(time).is_some()
+    }
+
+    // Consumes the offer if it's still live and was made to `target`.
+    // A live offer to someone else is left alone.
+    pub fn take_for(&mut self, target: T, time: SystemTime) -> bool {
+        if self.get_target(time) == Some(target) {
+            self.offer = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn take_expired(&mut self, time: SystemTime) -> Option<T> {
+        match self.offer {
+            Some((target, sent_time)) if Self::is_expired(sent_time, time) => {
+                self.offer = None;
+                Some(target)
+            }
+            _ => None,
+        }
+    }
+
+    // Drops the offer if it was made to `target`, live or not
+    pub fn cancel_for(&mut self, target: T) -> bool {
+        if self
+            .offer
+            .is_some_and(|(offer_target, _)| offer_target == target)
+        {
+            self.offer = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Which warp requirement a player didn't meet.
+// The client's codes for these are unknown, so it only ever gets the generic failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarpDenial {
+    LevelTooLow,
+    MissingTask,
+    MissingItem,
+    NotEnoughTaros,
+}
+
+// Why a player can't take a trip, with the same caveat as WarpDenial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripDenial {
+    Generic,
+    NotEnoughTaros,
+    LocationLocked,
+}
+
+// Offers a player made that were dropped without an answer, by target
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CancelledOffers {
+    pub group: Option<i32>,
+    pub trade: Option<i32>,
+    pub buddy: Option<i64>,
+}
+impl CancelledOffers {
+    pub fn is_empty(&self) -> bool {
+        self.group.is_none() && self.trade.is_none() && self.buddy.is_none()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BuddyListEntry {
     pub pc_uid: i64,
@@ -302,7 +533,7 @@ impl BuddyListEntry {
             last_name: player.last_name.clone(),
             style: player.style.unwrap(),
             name_check: player.flags.name_check,
-            free_chat: true,
+            free_chat: !player.menuchat_only,
             blocked: false,
         }
     }
@@ -388,6 +619,23 @@ impl BuddyList {
         self.slots.iter().filter(|entry| entry.is_some()).count()
     }
 
+    // Returns true if anything the client shows for the buddy changed
+    fn refresh_buddy(&mut self, latest: BuddyListEntry) -> bool {
+        let Some(idx) = self.get_buddy_slot_number(latest.pc_uid) else {
+            return false;
+        };
+        let entry = self.slots[idx].as_mut().unwrap();
+        let changed = entry.first_name != latest.first_name
+            || entry.last_name != latest.last_name
+            || entry.name_check != latest.name_check
+            || entry.style.gender != latest.style.gender;
+        entry.first_name = latest.first_name;
+        entry.last_name = latest.last_name;
+        entry.name_check = latest.name_check;
+        entry.style = latest.style;
+        changed
+    }
+
     fn get_all_entries(&self) -> Vec<BuddyListEntry> {
         self.slots
             .iter()
@@ -412,6 +660,8 @@ pub struct Player {
     pub in_combat: bool,
     pub last_attacked_by: Option<EntityID>,
     pub freechat_muted: bool,
+    // account-level restriction, unlike the GM mute
+    pub menuchat_only: bool,
     pub reward_data: RewardData,
     position: Position,
     rotation: i32,
@@ -430,17 +680,45 @@ pub struct Player {
     weapon_boosts: u32,
     pub buddy_list_synced: bool,
     buddy_list: BuddyList,
-    pub buddy_offered_to: Option<i64>,
-    buddy_warp_time: i32,
+    pub buddy_offered_to: PendingOffer<i64>,
+    // when the player can buddy warp again
+    buddy_warp_time: Option<SystemTime>,
     last_heal_time: Option<SystemTime>,
     pub last_warp_away_time: Option<SystemTime>,
     skyway_ride: Option<SkywayRideState>,
     pub trade_id: Option<Uuid>,
-    pub trade_offered_to: Option<i32>,
+    pub trade_offered_to: PendingOffer<i32>,
     pub group_id: Option<Uuid>,
-    pub group_offered_to: Option<i32>,
+    pub group_offered_to: PendingOffer<i32>,
     pub vehicle_speed: Option<i32>,
+    // when the vehicle boost burns its next weapon battery
+    vehicle_boost_drain_time: Option<SystemTime>,
+    pub summon_drop_id: Option<i32>,
+    pub summon_as_boss: bool,
+    pub summon_shiny_reward: Option<ShinyReward>,
+    pub summon_shiny_lifetime: Option<u64>,
+    pub path_recorder: Option<PathRecorder>,
+    // waiting on the login server to approve a new name
+    pub name_change_pending: bool,
+    avatar_state: AvatarState,
+    timed_buffs: HashMap<u32, TimedBuff>,
+    conditions: Conditions,
     pre_warp_data: PreWarpData,
+    spectating: Option<SpectateState>,
+    currency_log: CurrencyLog,
+    // C.R.A.T.E. rolls in a row without the crate's rarest rarity, by crate ID
+    crate_pity: HashMap<i16, u32>,
+    // players whose chat and invites get dropped; shared by the whole account
+    ignored_uids: HashSet<i64>,
+    // when the clean exit the player asked for goes through
+    exit_deadline: Option<SystemTime>,
+    // the exit was called off and the player hasn't been told yet
+    exit_cancelled: bool,
+    last_combat_time: Option<SystemTime>,
+    // when each nano skill can be used again, by skill ID. not saved
+    skill_cooldowns: HashMap<i16, SystemTime>,
+    // a rare item sale waiting on the player to ask again: inventory slot, stack, when asked
+    sell_confirm: Option<(usize, Item, SystemTime)>,
 }
 impl Player {
     pub fn new(uid: i64, slot_num: usize) -> Self {
@@ -456,6 +734,25 @@ impl Player {
         }
     }
 
+    // Skips the table data lookups the real setters do, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_mock(uid: i64, level: i16, hp: i32, fusion_matter: u32) -> Self {
+        Self {
+            uid,
+            slot_num: 1,
+            level,
+            hp,
+            fusion_matter,
+            perms: CN_ACCOUNT_LEVEL__USER as i16,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn set_slot_num_mock(&mut self, slot_num: usize) {
+        self.slot_num = slot_num;
+    }
+
     pub fn get_uid(&self) -> i64 {
         self.uid
     }
@@ -473,6 +770,13 @@ impl Player {
         self.id = Some(pc_id);
     }
 
+    // only players in the shard have progress worth journaling
+    fn journal(&self, op: JournalOp) {
+        if self.id.is_some() {
+            journal_record(self.uid, op);
+        }
+    }
+
     pub fn set_client_id(&mut self, client_id: usize) {
         self.client_id = Some(client_id);
     }
@@ -652,22 +956,20 @@ impl Player {
                 iValue: placeholder!(0),
                 iConfirmNum: placeholder!(0),
             },
-            aQuestFlag: self
-                .mission_journal
-                .completed_mission_flags
-                .to_array()
-                .unwrap(),
+            aQuestFlag: self.mission_journal.completed_mission_flags.to_array(),
             aRepeatQuestFlag: unused!(),
             aRunningQuest: self.mission_journal.get_running_quests(),
             iCurrentMissionID: self.mission_journal.get_active_mission_id().unwrap_or(0),
-            iWarpLocationFlag: self.flags.scamper_flags.get_chunk(0).unwrap(),
-            aWyvernLocationFlag: self.flags.skyway_flags.to_array().unwrap(),
-            iBuddyWarpTime: self.buddy_warp_time,
+            iWarpLocationFlag: self.flags.scamper_flags.to_array()[0],
+            aWyvernLocationFlag: self.flags.skyway_flags.to_array(),
+            iBuddyWarpTime: self
+                .buddy_warp_time
+                .map_or(0, |time| util::get_timestamp_sec(time) as i32),
             iFatigue: unused!(),
             iFatigue_Level: unused!(),
             iFatigueRate: unused!(),
-            iFirstUseFlag1: self.flags.tip_flags.get_chunk(0).unwrap(),
-            iFirstUseFlag2: self.flags.tip_flags.get_chunk(1).unwrap(),
+            iFirstUseFlag1: self.flags.tip_flags.to_array()[0],
+            iFirstUseFlag2: self.flags.tip_flags.to_array()[1],
             aiPCSkill: [unused!(); 33],
         }
     }
@@ -732,6 +1034,75 @@ impl Player {
         flags
     }
 
+    pub fn is_vehicle_boosting(&self) -> bool {
+        self.vehicle_speed.is_some() && self.vehicle_boost_drain_time.is_some()
+    }
+
+    // TODO which client request turns the boost on is still unknown, so nothing calls this yet
+    pub fn start_vehicle_boost(&mut self, time: SystemTime) -> FFResult<()> {
+        if self.vehicle_speed.is_none() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Player {} tried to boost without a vehicle", self),
+            ));
+        }
+        if self.weapon_boosts == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Player {} tried to boost without any weapon batteries",
+                    self
+                ),
+            ));
+        }
+
+        if self.vehicle_boost_drain_time.is_none() {
+            self.vehicle_boost_drain_time =
+                Some(time + Duration::from_secs(VEHICLE_BOOST_DRAIN_INTERVAL_SECS));
+        }
+        Ok(())
+    }
+
+    // Returns whether the player was boosting
+    pub fn stop_vehicle_boost(&mut self) -> bool {
+        self.vehicle_boost_drain_time.take().is_some()
+    }
+
+    pub fn get_avatar_state(&self) -> AvatarState {
+        self.avatar_state
+    }
+
+    pub fn set_emote(&mut self, emote_code: i32, time: SystemTime) {
+        self.avatar_state = if emote_code == EMOTE_CODE_SIT {
+            AvatarState::Sitting
+        } else {
+            AvatarState::Emote {
+                emote_code,
+                expire_time: time + Duration::from_secs(EMOTE_DURATION_SECS),
+            }
+        };
+    }
+
+    pub fn clear_avatar_state(&mut self) {
+        self.avatar_state = AvatarState::Idle;
+    }
+
+    // The emote someone seeing this player for the first time should be shown, if any
+    pub fn get_visible_emote(&self, time: SystemTime) -> Option<i32> {
+        match self.avatar_state {
+            AvatarState::Idle => None,
+            AvatarState::Sitting => Some(EMOTE_CODE_SIT),
+            AvatarState::Emote {
+                emote_code,
+                expire_time,
+            } => (time < expire_time).then_some(emote_code),
+        }
+    }
+
+    pub fn can_freechat(&self) -> bool {
+        !self.freechat_muted && !self.menuchat_only
+    }
+
     pub fn get_special_state_bit_flag(&self) -> i8 {
         let mut flags = 0;
         if self.show_gm_marker {
@@ -749,7 +1120,7 @@ impl Player {
         if self.in_combat {
             flags |= CN_SPECIAL_STATE_FLAG__COMBAT;
         }
-        if self.freechat_muted {
+        if !self.can_freechat() {
             flags |= CN_SPECIAL_STATE_FLAG__MUTE_FREECHAT;
         }
         flags as i8
@@ -864,9 +1235,39 @@ impl Player {
         let slot_from = self.get_item_mut(location, slot_num)?;
         let old_item = slot_from.take();
         *slot_from = item;
+        self.journal(JournalOp::Item {
+            location,
+            slot_num,
+            item,
+        });
         Ok(old_item)
     }
 
+    // Moves, stacks, or swaps the items in two slots.
+    // Returns what ends up in the (from, to) slots.
+    pub fn move_item(
+        &mut self,
+        location_from: ItemLocation,
+        slot_from: usize,
+        location_to: ItemLocation,
+        slot_to: usize,
+    ) -> FFResult<(Option<Item>, Option<Item>)> {
+        let mut item_from = self.set_item(location_from, slot_from, None)?;
+        let mut item_to = match self.set_item(location_to, slot_to, None) {
+            Ok(item_to) => item_to,
+            Err(e) => {
+                self.set_item(location_from, slot_from, item_from)?;
+                return Err(e);
+            }
+        };
+
+        let transfer_result = Item::transfer_items(&mut item_from, &mut item_to);
+        self.set_item(location_from, slot_from, item_from)?;
+        self.set_item(location_to, slot_to, item_to)?;
+        transfer_result?;
+        Ok((item_from, item_to))
+    }
+
     pub fn get_quest_item_count(&self, item_id: i16) -> usize {
         self.inventory
             .quest
@@ -883,16 +1284,16 @@ impl Player {
         } else {
             Some((item_id, count))
         };
-        for (idx, slot) in self.inventory.quest.iter_mut().enumerate() {
-            if let Some((qitem_id, _)) = slot {
-                if *qitem_id == item_id {
-                    *slot = new_qitem;
-                    return Ok(idx);
-                }
-            } else {
-                *slot = new_qitem;
-                return Ok(idx);
-            }
+        // the item might be sitting past a slot that was freed up earlier
+        let existing_idx = self
+            .inventory
+            .quest
+            .iter()
+            .position(|slot| matches!(slot, Some((qitem_id, _)) if *qitem_id == item_id));
+        let idx = existing_idx.or(self.inventory.quest.iter().position(|slot| slot.is_none()));
+        if let Some(idx) = idx {
+            self.inventory.quest[idx] = new_qitem;
+            return Ok(idx);
         }
         Err(FFError::build(
             Severity::Warning,
@@ -903,6 +1304,61 @@ impl Player {
         ))
     }
 
+    // Works out the quest item counts a task turn-in leaves behind, for the counts that change.
+    // What the task uses up (its deleted qitems and negative success changes) only comes
+    // out of what the player's other running tasks don't still need
+    pub fn plan_qitem_turn_in(
+        &self,
+        task_def: &TaskDefinition,
+        other_task_defs: &[&TaskDefinition],
+    ) -> Vec<(i16, usize)> {
+        let reserved = |qitem_id: i16| {
+            other_task_defs
+                .iter()
+                .filter_map(|other_def| other_def.obj_qitems.get(&qitem_id))
+                .max()
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let mut new_counts: HashMap<i16, usize> = HashMap::new();
+        for (qitem_id, qitem_count_mod) in &task_def.succ_qitems {
+            let held = self.get_quest_item_count(*qitem_id);
+            let new_count = if *qitem_count_mod >= 0 {
+                held + *qitem_count_mod as usize
+            } else {
+                let used = qitem_count_mod.unsigned_abs();
+                if used > held {
+                    log(
+                        Severity::Warning,
+                        &format!(
+                            "Task {} uses {} of qitem {} but {} only has {}",
+                            task_def.task_id, used, qitem_id, self, held
+                        ),
+                    );
+                }
+                let spare = held.saturating_sub(reserved(*qitem_id));
+                held - min(used, spare)
+            };
+            new_counts.insert(*qitem_id, new_count);
+        }
+
+        for qitem_id in &task_def.delete_qitems {
+            let held = new_counts
+                .get(qitem_id)
+                .copied()
+                .unwrap_or_else(|| self.get_quest_item_count(*qitem_id));
+            new_counts.insert(*qitem_id, min(held, reserved(*qitem_id)));
+        }
+
+        let mut changes: Vec<(i16, usize)> = new_counts
+            .into_iter()
+            .filter(|(qitem_id, count)| *count != self.get_quest_item_count(*qitem_id))
+            .collect();
+        changes.sort_unstable();
+        changes
+    }
+
     pub fn get_free_slots(&self, location: ItemLocation) -> usize {
         match location {
             ItemLocation::Equip => self
@@ -955,6 +1411,127 @@ impl Player {
         ))
     }
 
+    // Works out where `item` would go in the main inventory without touching anything.
+    // Existing stacks of the same item are topped up to `max_stack_size` first, then
+    // free slots are used, starting with `preferred_slot`. Returns (slot, count) pairs.
+    pub fn plan_item_placement(
+        &self,
+        item: &Item,
+        max_stack_size: u16,
+        preferred_slot: usize,
+    ) -> FFResult<Vec<(usize, u16)>> {
+        if self.trade_id.is_some() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Can't place items for {}; player is trading", self),
+            ));
+        }
+
+        let max_stack_size = max_stack_size.max(1);
+        let mut remaining = item.quantity;
+        let mut plan = Vec::new();
+        for (slot_num, slot) in self.inventory.main.iter().enumerate() {
+            if remaining == 0 {
+                break;
+            }
+            if let Some(existing) = slot {
+                if existing.ty == item.ty
+                    && existing.id == item.id
+                    && existing.get_expiry_time() == item.get_expiry_time()
+                    && existing.quantity < max_stack_size
+                {
+                    let count = min(max_stack_size - existing.quantity, remaining);
+                    plan.push((slot_num, count));
+                    remaining -= count;
+                }
+            }
+        }
+
+        let mut free_slots: Vec<usize> = self
+            .inventory
+            .main
+            .iter()
+            .enumerate()
+            .filter_map(|(slot_num, slot)| slot.is_none().then_some(slot_num))
+            .collect();
+        if let Some(idx) = free_slots.iter().position(|slot| *slot == preferred_slot) {
+            free_slots.remove(idx);
+            free_slots.insert(0, preferred_slot);
+        }
+        for slot_num in free_slots {
+            if remaining == 0 {
+                break;
+            }
+            let count = min(max_stack_size, remaining);
+            plan.push((slot_num, count));
+            remaining -= count;
+        }
+
+        if remaining > 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Not enough inventory space for {} to receive {} of item ({}, {:?})",
+                    self, item.quantity, item.id, item.ty
+                ),
+            ));
+        }
+        Ok(plan)
+    }
+
+    // Merges partial stacks of the same item in the main inventory and packs everything
+    // toward the lowest slots, keeping items in the order they were first found.
+    // Nothing changes unless the whole thing works out. Returns the slots that changed.
+    pub fn consolidate_inventory(
+        &mut self,
+        get_max_stack_size: impl Fn(&Item) -> FFResult<u16>,
+    ) -> FFResult<Vec<usize>> {
+        if self.trade_id.is_some() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Can't sort inventory for {}; player is trading", self),
+            ));
+        }
+
+        let mut stacks: Vec<Item> = Vec::new();
+        for item in self.inventory.main.iter().flatten() {
+            let max_stack_size = get_max_stack_size(item)?.max(1);
+            let mut remaining = item.quantity;
+            for stack in stacks.iter_mut() {
+                if remaining == 0 {
+                    break;
+                }
+                if stack.stacks_with(item) && stack.quantity < max_stack_size {
+                    let count = min(max_stack_size - stack.quantity, remaining);
+                    stack.quantity += count;
+                    remaining -= count;
+                }
+            }
+            while remaining > 0 {
+                let mut stack = *item;
+                stack.quantity = min(max_stack_size, remaining);
+                remaining -= stack.quantity;
+                stacks.push(stack);
+            }
+        }
+        if stacks.len() > self.inventory.main.len() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Not enough inventory space to sort {}'s inventory", self),
+            ));
+        }
+
+        let mut new_main = [None; SIZEOF_INVEN_SLOT as usize];
+        for (slot, stack) in new_main.iter_mut().zip(stacks) {
+            *slot = Some(stack);
+        }
+        let changed: Vec<usize> = (0..new_main.len())
+            .filter(|slot_num| new_main[*slot_num] != self.inventory.main[*slot_num])
+            .collect();
+        self.inventory.main = new_main;
+        Ok(changed)
+    }
+
     pub fn find_items_any(&self, f: impl Fn(&Item) -> bool) -> Vec<(ItemLocation, usize)> {
         let mut found = Vec::new();
         found.extend(
@@ -1000,6 +1577,30 @@ impl Player {
             .collect()
     }
 
+    // Takes away up to max_count matching items, checking equipped, main, then bank slots.
+    // Returns how many were taken and the new contents of each slot that changed.
+    pub fn remove_items_any(
+        &mut self,
+        f: impl Fn(&Item) -> bool,
+        max_count: usize,
+    ) -> FFResult<(usize, Vec<ChangedSlot>)> {
+        let mut removed = 0;
+        let mut changed = Vec::new();
+        for (location, slot_num) in self.find_items_any(f) {
+            if removed >= max_count {
+                break;
+            }
+            let mut slot = *self.get_item(location, slot_num)?;
+            let to_take = (max_count - removed).min(u16::MAX as usize) as u16;
+            if let Some(taken) = Item::split_items(&mut slot, to_take) {
+                removed += taken.quantity as usize;
+            }
+            self.set_item(location, slot_num, slot)?;
+            changed.push((location, slot_num, slot));
+        }
+        Ok((removed, changed))
+    }
+
     pub fn get_item_iter(&self) -> impl Iterator<Item = (usize, &Item)> {
         let inv_slot_max =
             (SIZEOF_EQUIP_SLOT + SIZEOF_INVEN_SLOT + SIZEOF_BANK_SLOT + SIZEOF_QINVEN_SLOT)
@@ -1048,7 +1649,10 @@ impl Player {
     }
 
     pub fn is_scamper_location_unlocked(&self, location_id: i32) -> FFResult<bool> {
-        self.flags.scamper_flags.get((location_id - 1) as usize)
+        self.flags
+            .scamper_flags
+            .get((location_id - 1) as usize)
+            .map_err(FFError::from)
     }
 
     pub fn unlock_skyway_location(&mut self, location_id: i32) -> FFResult<()> {
@@ -1059,31 +1663,139 @@ impl Player {
     }
 
     pub fn is_skyway_location_unlocked(&self, location_id: i32) -> FFResult<bool> {
-        self.flags.skyway_flags.get((location_id - 1) as usize)
+        self.flags
+            .skyway_flags
+            .get((location_id - 1) as usize)
+            .map_err(FFError::from)
+    }
+
+    // Returns whether the location was newly unlocked
+    pub fn unlock_transport_location(
+        &mut self,
+        transport_type: TransportationType,
+        location_id: i32,
+    ) -> FFResult<bool> {
+        if self.is_transport_location_unlocked(transport_type, location_id)? {
+            return Ok(false);
+        }
+        match transport_type {
+            TransportationType::Warp => self.unlock_scamper_location(location_id)?,
+            TransportationType::Wyvern => self.unlock_skyway_location(location_id)?,
+            other => {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!("Can't unlock transportation type {:?}", other),
+                ))
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn is_transport_location_unlocked(
+        &self,
+        transport_type: TransportationType,
+        location_id: i32,
+    ) -> FFResult<bool> {
+        match transport_type {
+            TransportationType::Warp => self.is_scamper_location_unlocked(location_id),
+            TransportationType::Wyvern => self.is_skyway_location_unlocked(location_id),
+            other => Err(FFError::build(
+                Severity::Warning,
+                format!("Transportation type {:?} has no locations", other),
+            )),
+        }
+    }
+
+    // Checks that both ends of a trip are registered and the player can pay for it.
+    // Nothing is charged here; Skyway rides are only paid for once they finish.
+    pub fn check_trip_requirements(&self, trip: &TripData) -> Result<(), (TripDenial, FFError)> {
+        for (location_id, end) in [(trip.start_location, "from"), (trip.end_location, "to")] {
+            let unlocked = self
+                .is_transport_location_unlocked(trip.transportation_type, location_id)
+                .map_err(|e| (TripDenial::Generic, e))?;
+            if !unlocked {
+                return Err((
+                    TripDenial::LocationLocked,
+                    FFError::build(
+                        Severity::Warning,
+                        format!(
+                            "{} tried to travel {} unregistered {:?} location {}",
+                            self, end, trip.transportation_type, location_id
+                        ),
+                    ),
+                ));
+            }
+        }
+
+        if self.taros < trip.cost {
+            return Err((
+                TripDenial::NotEnoughTaros,
+                FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} doesn't have enough taros to travel ({} < {})",
+                        self, self.taros, trip.cost
+                    ),
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn init_new_character(&mut self) {
+        let config = &config_get().newcharacter;
+        self.set_level(config.level.get()).unwrap();
+        self.set_hp(self.get_max_hp());
+        self.set_taros(config.taros.get());
+        self.set_fusion_matter(config.fusion_matter.get(), None);
+        if config.skip_tutorial.get() {
+            self.set_tutorial_done();
+        }
     }
 
     pub fn set_tutorial_done(&mut self) {
+        // config is validated at startup, so these shouldn't fail
+        let config = &config_get().newcharacter;
         self.flags.tutorial_flag = true;
-        // unlock buttercup
-        let buttercup_stats = tdata_get().get_nano_stats(ID_BUTTERCUP).unwrap();
-        self.unlock_nano(ID_BUTTERCUP).unwrap();
-        self.tune_nano(ID_BUTTERCUP, Some(buttercup_stats.skills[0]))
-            .unwrap();
-        self.change_nano(0, Some(ID_BUTTERCUP)).unwrap();
-        // equip lightning gun
-        self.set_item(
-            ItemLocation::Equip,
-            EQUIP_SLOT_HAND as usize,
-            Some(Item::new(ItemType::Hand, ID_LIGHTNING_GUN)),
-        )
-        .unwrap();
-        // place in Sector V future
+        // unlock starting nanos
+        for nano in config.nanos.get() {
+            let nano_stats = tdata_get().get_nano_stats(nano.id).unwrap();
+            if self.unlock_nano(nano.id).is_err() {
+                // already unlocked
+                continue;
+            }
+            let skill_id = nano.skill.map(|idx| nano_stats.skills[idx]);
+            self.tune_nano(nano.id, skill_id).unwrap();
+            if let Some(slot) = self
+                .nano_data
+                .equipped_ids
+                .iter()
+                .position(|id| id.is_none())
+            {
+                self.change_nano(slot, Some(nano.id)).unwrap();
+            }
+        }
+        // grant starting items
+        for starting_item in config.items.get() {
+            let mut item = Item::new(starting_item.ty, starting_item.id);
+            item.quantity = starting_item.quantity;
+            let (location, slot_num) = match starting_item.get_equip_slot() {
+                Some(slot_num) if starting_item.equip => (ItemLocation::Equip, slot_num),
+                _ => match self.inventory.main.iter().position(|slot| slot.is_none()) {
+                    Some(slot_num) => (ItemLocation::Inven, slot_num),
+                    None => continue,
+                },
+            };
+            self.set_item(location, slot_num, Some(item)).unwrap();
+        }
+        // place at starting position
+        let [x, y, z] = config.position.get();
         let mut rand = rand::thread_rng();
         let range = 0; //PC_START_LOCATION_RANDOM_RANGE as i32 / 2;
         self.position = Position {
-            x: 632032 + rand.gen_range(-range..=range),
-            y: 187177 + rand.gen_range(-range..=range),
-            z: -5500,
+            x: x + rand.gen_range(-range..=range),
+            y: y + rand.gen_range(-range..=range),
+            z,
         }
     }
 
@@ -1091,26 +1803,166 @@ impl Player {
         self.guide_data.current_guide
     }
 
-    pub fn update_guide(&mut self, guide: PlayerGuide) -> usize {
-        self.guide_data.current_guide = guide;
-        self.guide_data.total_guides =
-            clamp_max(self.guide_data.total_guides + 1, i16::MAX as usize);
-        self.guide_data.total_guides
+    pub fn get_crate_pity(&self, crate_id: i16) -> u32 {
+        self.crate_pity.get(&crate_id).copied().unwrap_or(0)
     }
 
-    pub fn set_future_done(&mut self) {
-        self.flags.payzone_flag = true;
+    pub fn set_crate_pity(&mut self, crate_id: i16, pity: u32) {
+        if pity == 0 {
+            self.crate_pity.remove(&crate_id);
+        } else {
+            self.crate_pity.insert(crate_id, pity);
+        }
     }
 
-    pub fn is_future_done(&self) -> bool {
-        self.flags.payzone_flag
+    pub fn get_crate_pity_iter(&self) -> impl Iterator<Item = (i16, u32)> + '_ {
+        self.crate_pity.iter().map(|(id, pity)| (*id, *pity))
     }
 
-    pub fn set_taros(&mut self, taros: u32) -> u32 {
+    // Pity builds up until the crate gives its rarest rarity
+    pub fn record_crate_roll(&mut self, crate_id: i16, rarest: bool) {
+        let pity = if rarest {
+            0
+        } else {
+            self.get_crate_pity(crate_id).saturating_add(1)
+        };
+        self.set_crate_pity(crate_id, pity);
+    }
+
+    pub fn get_total_guides(&self) -> usize {
+        self.guide_data.total_guides
+    }
+
+    pub fn set_guide_data(&mut self, guide: PlayerGuide, total_guides: usize) {
+        self.guide_data = GuideData {
+            current_guide: guide,
+            total_guides: clamp_max(total_guides, i16::MAX as usize),
+        };
+    }
+
+    // Switches to another guide, returning how many times the player has switched.
+    // The first switch is free; every one after that costs `fm_cost`.
+    pub fn change_guide(&mut self, guide: PlayerGuide, fm_cost: u32) -> FFResult<usize> {
+        if guide == self.guide_data.current_guide {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} tried to change to the guide they already have", self),
+            ));
+        }
+
+        let fm_cost = if self.guide_data.total_guides == 0 {
+            0
+        } else {
+            fm_cost
+        };
+        if self.fusion_matter < fm_cost {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "{} can't afford to change guides ({} < {} FM)",
+                    self, self.fusion_matter, fm_cost
+                ),
+            ));
+        }
+
+        if fm_cost > 0 {
+            self.fusion_matter -= fm_cost;
+            self.journal(JournalOp::FusionMatter {
+                fusion_matter: self.fusion_matter,
+            });
+            self.log_currency(
+                RewardType::FusionMatter,
+                CurrencySource::Guide,
+                -(fm_cost as i64),
+            );
+        }
+        self.set_guide_data(guide, self.guide_data.total_guides + 1);
+        Ok(self.guide_data.total_guides)
+    }
+
+    // Returns false if the player was already in the Past
+    pub fn set_future_done(&mut self) -> bool {
+        if self.flags.payzone_flag {
+            return false;
+        }
+        self.flags.payzone_flag = true;
+
+        let config = &config_get().shard;
+        for location_id in config.past_scamper_unlocks.get() {
+            log_if_failed(self.unlock_scamper_location(location_id));
+        }
+        for location_id in config.past_skyway_unlocks.get() {
+            log_if_failed(self.unlock_skyway_location(location_id));
+        }
+        true
+    }
+
+    pub fn is_future_done(&self) -> bool {
+        self.flags.payzone_flag
+    }
+
+    pub fn set_taros(&mut self, taros: u32) -> u32 {
         self.taros = clamp(taros, 0, PC_CANDY_MAX);
+        self.journal(JournalOp::Taros { taros: self.taros });
+        self.taros
+    }
+
+    pub fn add_taros(&mut self, delta: i64, source: CurrencySource) -> u32 {
+        let old_taros = self.taros;
+        let new_taros = clamp(old_taros as i64 + delta, 0, u32::MAX as i64) as u32;
+        self.set_taros(new_taros);
+        self.log_currency(
+            RewardType::Taros,
+            source,
+            self.taros as i64 - old_taros as i64,
+        );
         self.taros
     }
 
+    pub fn add_fusion_matter(
+        &mut self,
+        delta: i64,
+        source: CurrencySource,
+        clients: Option<&mut ClientMap>,
+    ) -> u32 {
+        let old_fusion_matter = self.fusion_matter;
+        let new_fusion_matter = clamp(old_fusion_matter as i64 + delta, 0, u32::MAX as i64) as u32;
+        self.set_fusion_matter(new_fusion_matter, clients);
+        self.log_currency(
+            RewardType::FusionMatter,
+            source,
+            self.fusion_matter as i64 - old_fusion_matter as i64,
+        );
+        self.fusion_matter
+    }
+
+    fn log_currency(&mut self, currency: RewardType, source: CurrencySource, delta: i64) {
+        if delta == 0 {
+            return;
+        }
+
+        let balance = match currency {
+            RewardType::Taros => self.taros,
+            RewardType::FusionMatter => self.fusion_matter,
+        };
+        let event = CurrencyEvent {
+            currency,
+            source,
+            delta,
+            balance,
+            time: SystemTime::now(),
+        };
+        self.currency_log.push(event);
+        // same as the journal, only players in the shard are worth recording
+        if self.id.is_some() {
+            currency_log_queue(self.uid, event);
+        }
+    }
+
+    pub fn get_currency_log(&self) -> &CurrencyLog {
+        &self.currency_log
+    }
+
     pub fn set_hp(&mut self, hp: i32) -> i32 {
         let hp_max = if self.perms <= CN_ACCOUNT_LEVEL__DEVELOPER as i16 {
             i32::MAX // allow overflow for high perms
@@ -1127,9 +1979,16 @@ impl Player {
         tdata_get().get_player_stats(new_level)?; // validate
 
         self.level = new_level;
+        self.journal(JournalOp::Level { level: self.level });
         Ok(self.level)
     }
 
+    pub fn set_mission_completed(&mut self, mission_id: i32) -> FFResult<()> {
+        self.mission_journal.set_mission_completed(mission_id)?;
+        self.journal(JournalOp::MissionCompleted { mission_id });
+        Ok(())
+    }
+
     pub fn set_fusion_matter(
         &mut self,
         fusion_matter: u32,
@@ -1142,6 +2001,9 @@ impl Player {
             player_stats.fm_limit
         };
         self.fusion_matter = clamp(fusion_matter, 0, fm_max);
+        self.journal(JournalOp::FusionMatter {
+            fusion_matter: self.fusion_matter,
+        });
 
         if let Some(clients) = clients {
             let level_up_fusion_matter = player_stats.req_fm_nano_create;
@@ -1211,6 +2073,245 @@ impl Player {
         self.nano_potions
     }
 
+    pub fn take_expired_offers(&mut self, time: SystemTime) -> CancelledOffers {
+        CancelledOffers {
+            group: self.group_offered_to.take_expired(time),
+            trade: self.trade_offered_to.take_expired(time),
+            buddy: self.buddy_offered_to.take_expired(time),
+        }
+    }
+
+    pub fn cancel_offers_to(&mut self, pc_id: i32, pc_uid: i64) -> CancelledOffers {
+        CancelledOffers {
+            group: self.group_offered_to.cancel_for(pc_id).then_some(pc_id),
+            trade: self.trade_offered_to.cancel_for(pc_id).then_some(pc_id),
+            buddy: self.buddy_offered_to.cancel_for(pc_uid).then_some(pc_uid),
+        }
+    }
+
+    // Fixes up out-of-range values in a freshly loaded player.
+    // Fails if the player is too broken to be played at all.
+    pub fn validate(&mut self) -> FFResult<()> {
+        let tdata = tdata_get();
+        self.validate_with(
+            |level| {
+                tdata
+                    .get_player_stats(level)
+                    .map(|stats| stats.max_hp as i32)
+            },
+            |item| item.get_stats().is_ok(),
+        )
+    }
+
+    fn validate_with(
+        &mut self,
+        get_max_hp: impl Fn(i16) -> FFResult<i32>,
+        is_item_valid: impl Fn(&Item) -> bool,
+    ) -> FFResult<()> {
+        // slot numbers are 1-indexed
+        if self.slot_num == 0 {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Player with UID {} is in slot 0", self.uid),
+            ));
+        }
+
+        let level = clamp(self.level, 1, PC_LEVEL_MAX as i16);
+        if level != self.level {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Player with UID {} had level {}; clamped to {}",
+                    self.uid, self.level, level
+                ),
+            );
+            self.level = level;
+        }
+
+        let hp_max = get_max_hp(self.level).map_err(|e| {
+            FFError::build(
+                Severity::Warning,
+                format!("Player with UID {} has no stats for their level", self.uid),
+            )
+            .chain(e)
+        })?;
+        let hp_max = if self.perms <= CN_ACCOUNT_LEVEL__DEVELOPER as i16 {
+            i32::MAX // allow overflow for high perms
+        } else {
+            hp_max
+        };
+        let hp = clamp(self.hp, 0, hp_max);
+        if hp != self.hp {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Player with UID {} had {} HP; clamped to {}",
+                    self.uid, self.hp, hp
+                ),
+            );
+            self.hp = hp;
+        }
+
+        let inventory = &mut self.inventory;
+        let slots = [
+            (ItemLocation::Equip, &mut inventory.equipped[..]),
+            (ItemLocation::Inven, &mut inventory.main[..]),
+            (ItemLocation::Bank, &mut inventory.bank[..]),
+        ];
+        for (location, items) in slots {
+            for (slot_num, slot) in items.iter_mut().enumerate() {
+                if slot.is_some_and(|item| !is_item_valid(&item)) {
+                    let item = slot.take().unwrap();
+                    log(
+                        Severity::Warning,
+                        &format!(
+                            "Dropped invalid item {:?} from player with UID {} ({:?} slot {})",
+                            item, self.uid, location, slot_num
+                        ),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks everything a warp asks of the player without taking anything.
+    // Item slots are (location, slot number) as sent by the client.
+    // If allowed, GMs skip the level, task, and item gates but still have to pay.
+    pub fn check_warp_requirements(
+        &self,
+        warp_data: &WarpData,
+        req_item_slot: (i32, usize),
+        consumed_item_slot: (i32, usize),
+        allow_gm_bypass: bool,
+    ) -> Result<(), (WarpDenial, FFError)> {
+        let bypass_gates = allow_gm_bypass && self.perms <= CN_ACCOUNT_LEVEL__GM as i16;
+
+        if !bypass_gates && self.level < warp_data.min_level {
+            return Err((
+                WarpDenial::LevelTooLow,
+                FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} isn't a high enough level to warp ({} < {})",
+                        self, self.level, warp_data.min_level
+                    ),
+                ),
+            ));
+        }
+
+        if let Some((mission_id, task_id)) = warp_data.req_task {
+            // having finished the mission counts too, so lairs stay open for replays
+            let has_task = self
+                .mission_journal
+                .get_current_task_ids()
+                .contains(&task_id)
+                || self
+                    .mission_journal
+                    .is_mission_completed(mission_id)
+                    .unwrap_or(false);
+            if !bypass_gates && !has_task {
+                return Err((
+                    WarpDenial::MissingTask,
+                    FFError::build(
+                        Severity::Warning,
+                        format!(
+                            "{} doesn't have the required task {} (mission {}) to warp",
+                            self, task_id, mission_id
+                        ),
+                    ),
+                ));
+            }
+        }
+
+        if let Some(req_item) = warp_data.req_item {
+            if !bypass_gates {
+                self.get_warp_item(req_item, req_item_slot)
+                    .map_err(|e| (WarpDenial::MissingItem, e))?;
+            }
+        }
+
+        if let Some(req_item) = warp_data.req_item_consumed {
+            self.get_warp_item(req_item, consumed_item_slot)
+                .map_err(|e| (WarpDenial::MissingItem, e))?;
+        }
+
+        if self.taros < warp_data.cost {
+            return Err((
+                WarpDenial::NotEnoughTaros,
+                FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} doesn't have enough taros to warp ({} < {})",
+                        self, self.taros, warp_data.cost
+                    ),
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_warp_item(
+        &self,
+        (item_type, item_id): (ItemType, i16),
+        (location_ord, slot_num): (i32, usize),
+    ) -> FFResult<(ItemLocation, Item)> {
+        let location: ItemLocation = location_ord.try_into()?;
+        match self.get_item(location, slot_num)? {
+            Some(item) if item.ty == item_type && item.id == item_id => Ok((location, *item)),
+            _ => Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "{} doesn't have the item ({:?}, {}) needed to warp in {:?} slot {}",
+                    self, item_type, item_id, location, slot_num
+                ),
+            )),
+        }
+    }
+
+    // Takes the warp's cost and consumes one of the consumed item.
+    // Takes nothing if either can't be paid. Returns what's left in the consumed item's slot.
+    pub fn pay_for_warp(
+        &mut self,
+        warp_data: &WarpData,
+        consumed_item_slot: (i32, usize),
+    ) -> FFResult<(WarpPayment, Option<Item>)> {
+        if self.taros < warp_data.cost {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} can't pay {} taros to warp", self, warp_data.cost),
+            ));
+        }
+
+        let mut remaining_item = None;
+        let consumed_item = match warp_data.req_item_consumed {
+            Some(req_item) => {
+                let (location, item) = self.get_warp_item(req_item, consumed_item_slot)?;
+                let slot_num = consumed_item_slot.1;
+                remaining_item = Some(item);
+                Item::split_items(&mut remaining_item, 1);
+                self.set_item(location, slot_num, remaining_item)?;
+                Some((location, slot_num, item))
+            }
+            None => None,
+        };
+        self.add_taros(-(warp_data.cost as i64), CurrencySource::Warp);
+
+        let payment = WarpPayment {
+            taros: warp_data.cost,
+            consumed_item,
+        };
+        Ok((payment, remaining_item))
+    }
+
+    pub fn refund_warp(&mut self, payment: WarpPayment) {
+        self.add_taros(payment.taros as i64, CurrencySource::Warp);
+        if let Some((location, slot_num, item)) = payment.consumed_item {
+            log_if_failed(self.set_item(location, slot_num, Some(item)));
+        }
+    }
+
     pub fn set_pre_warp(&mut self) {
         // we only save pre-warp when we're not in an instance
         if self.instance_id.instance_num.is_none() {
@@ -1225,14 +2326,67 @@ impl Player {
         &self.pre_warp_data
     }
 
-    pub fn start_skyway_ride(&mut self, trip_data: &'static TripData, mut path: Path) {
-        path.tick(&mut self.position); // advance to Moving state
-        self.skyway_ride = Some(SkywayRideState {
-            trip_data,
-            path,
-            monkey_pos: self.position,
-            resume_time: SystemTime::now(),
+    pub fn start_spectating(&mut self, target_pc_id: i32) {
+        self.spectating = Some(SpectateState {
+            target_pc_id,
+            return_to: PreWarpData {
+                instance_id: self.instance_id,
+                position: self.position,
+            },
+            was_invisible: self.invisible,
+            was_invulnerable: self.invulnerable,
         });
+        self.invisible = true;
+        self.invulnerable = true;
+    }
+
+    pub fn stop_spectating(&mut self) -> Option<SpectateState> {
+        let spectate_state = self.spectating.take()?;
+        self.invisible = spectate_state.was_invisible;
+        self.invulnerable = spectate_state.was_invulnerable;
+        Some(spectate_state)
+    }
+
+    pub fn get_spectate_target(&self) -> Option<i32> {
+        self.spectating.as_ref().map(|s| s.target_pc_id)
+    }
+
+    pub fn start_skyway_ride(
+        &mut self,
+        trip_data: &'static TripData,
+        legs: Vec<SkywayLeg>,
+    ) -> FFResult<()> {
+        if legs.is_empty() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} tried to ride a Skyway route with no legs", self),
+            ));
+        }
+        self.skyway_ride = Some(SkywayRideState::new(trip_data, legs, self.position));
+        Ok(())
+    }
+
+    // Asks to get off at the end of the current leg.
+    // Returns the location of the station the player will get off at.
+    pub fn request_skyway_disembark(&mut self) -> FFResult<i32> {
+        let Some(ride) = self.skyway_ride.as_mut() else {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} isn't riding the Skyway", self),
+            ));
+        };
+        if ride.next_legs.is_empty() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} is already headed to the last stop", self),
+            ));
+        }
+        ride.disembark_requested = true;
+        Ok(ride.station)
+    }
+
+    pub fn is_riding_skyway(&self) -> bool {
+        self.skyway_ride.is_some()
     }
 
     pub fn do_revive(&mut self) {
@@ -1243,10 +2397,119 @@ impl Player {
         self.reset();
     }
 
+    // Dying costs nothing for GMs that can't be hurt, during races, or in penalty-free maps
+    pub fn is_death_fm_exempt(&self, in_race: bool, exempt_maps: &[i32]) -> bool {
+        self.invulnerable || in_race || exempt_maps.contains(&(self.instance_id.map_num as i32))
+    }
+
+    // Takes away FM for dying, returning how much was lost.
+    // Players that already have enough FM to level up are never knocked back under it.
+    pub fn take_death_fm_penalty(&mut self, death_fm: u32, level_up_fm: u32) -> u32 {
+        let floor = if self.fusion_matter >= level_up_fm {
+            level_up_fm
+        } else {
+            0
+        };
+        let new_fusion_matter = self.fusion_matter.saturating_sub(death_fm).max(floor);
+        let lost = self.fusion_matter - new_fusion_matter;
+        if lost == 0 {
+            return 0;
+        }
+
+        self.fusion_matter = new_fusion_matter;
+        self.journal(JournalOp::FusionMatter {
+            fusion_matter: self.fusion_matter,
+        });
+        self.log_currency(
+            RewardType::FusionMatter,
+            CurrencySource::Death,
+            -(lost as i64),
+        );
+        lost
+    }
+
+    pub fn add_timed_buff(
+        &mut self,
+        condition_bit: u32,
+        buff_type: TimeBuffType,
+        duration: Duration,
+        time: SystemTime,
+    ) {
+        let buff = TimedBuff {
+            buff_type,
+            expire_time: time + duration,
+            duration,
+        };
+        self.timed_buffs.insert(condition_bit, buff);
+    }
+
+    pub fn get_buff_update(
+        &self,
+        condition_bit: u32,
+        buff_type: TimeBuffType,
+        update: TimeBuffUpdate,
+    ) -> sP_FE2CL_PC_BUFF_UPDATE {
+        // buff IDs count up from 1 in the same order as the condition bits
+        let buff_id = condition_bit.trailing_zeros() as i32 + 1;
+        let buff = self.timed_buffs.get(&condition_bit);
+        sP_FE2CL_PC_BUFF_UPDATE {
+            eCSTB: buff_id,
+            eTBU: update as i32,
+            eTBT: buff_type as i32,
+            TimeBuff: sTimeBuff {
+                iTimeLimit: buff.map_or(0, |b| util::get_timestamp_ms(b.expire_time)),
+                iTimeDuration: buff.map_or(0, |b| b.duration.as_millis() as u64),
+                iTimeRepeat: unused!(),
+                iValue: unused!(),
+                iConfirmNum: unused!(),
+            },
+            iConditionBitFlag: self.get_condition_bit_flag(),
+        }
+    }
+
+    fn take_expired_buffs(&mut self, time: &SystemTime) -> Vec<(u32, TimeBuffType)> {
+        let expired: Vec<(u32, TimeBuffType)> = self
+            .timed_buffs
+            .iter()
+            .filter(|(_, buff)| buff.expire_time <= *time)
+            .map(|(bit, buff)| (*bit, buff.buff_type))
+            .collect();
+        for (bit, _) in &expired {
+            self.timed_buffs.remove(bit);
+        }
+        expired
+    }
+
     pub fn is_buddies_with(&self, pc_uid: i64) -> bool {
         self.buddy_list.is_buddies_with(pc_uid)
     }
 
+    pub fn get_buddy_warp_time(&self) -> Option<SystemTime> {
+        self.buddy_warp_time
+    }
+
+    pub fn set_buddy_warp_time(&mut self, buddy_warp_time: Option<SystemTime>) {
+        self.buddy_warp_time = buddy_warp_time;
+    }
+
+    pub fn check_buddy_warp_cooldown(&self, time: SystemTime) -> FFResult<()> {
+        match self.buddy_warp_time {
+            Some(ready_time) if time < ready_time => Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "{} tried to buddy warp {}s before their cooldown ended",
+                    self,
+                    ready_time.duration_since(time).unwrap().as_secs()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn start_buddy_warp_cooldown(&mut self, time: SystemTime, cooldown: Duration) {
+        self.buddy_warp_time = Some(time + cooldown);
+    }
+
     pub fn add_buddy(&mut self, buddy_info: BuddyListEntry) -> FFResult<usize> {
         self.buddy_list.insert_buddy(buddy_info)
     }
@@ -1267,6 +2530,11 @@ impl Player {
         self.buddy_list.get_all_entries()
     }
 
+    // Picks up a buddy's name or appearance changes, if they're on the buddy list
+    pub fn refresh_buddy_info(&mut self, latest: BuddyListEntry) -> bool {
+        self.buddy_list.refresh_buddy(latest)
+    }
+
     pub fn get_buddy_uids(&self) -> Vec<i64> {
         self.get_all_buddy_info()
             .iter()
@@ -1281,10 +2549,174 @@ impl Player {
             .collect()
     }
 
+    // Returns whether they weren't ignored already
+    pub fn ignore_player(&mut self, pc_uid: i64) -> FFResult<bool> {
+        if pc_uid == self.uid {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} tried to ignore themselves", self),
+            ));
+        }
+        if self.ignored_uids.contains(&pc_uid) {
+            return Ok(false);
+        }
+        if self.ignored_uids.len() >= MAX_IGNORED_PLAYERS {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "{} can't ignore more than {} players",
+                    self, MAX_IGNORED_PLAYERS
+                ),
+            ));
+        }
+        self.ignored_uids.insert(pc_uid);
+        Ok(true)
+    }
+
+    // Returns whether they were ignored
+    pub fn unignore_player(&mut self, pc_uid: i64) -> bool {
+        self.ignored_uids.remove(&pc_uid)
+    }
+
+    pub fn clear_ignores(&mut self) {
+        self.ignored_uids.clear();
+    }
+
+    // Checked for every recipient of a chat message, so it needs to stay cheap
+    pub fn is_ignoring(&self, pc_uid: i64) -> bool {
+        self.ignored_uids.contains(&pc_uid)
+    }
+
+    pub fn get_ignored_uids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.ignored_uids.iter().copied()
+    }
+
+    pub fn mark_combat(&mut self, time: SystemTime) {
+        self.last_combat_time = Some(time);
+    }
+
+    // How long a clean exit takes from now.
+    // Instant for players out of combat in one of `safe_zones`.
+    pub fn get_exit_countdown(&self, time: SystemTime, safe_zones: &[MapZone]) -> Duration {
+        let cooldown = Duration::from_secs(SAFE_EXIT_COMBAT_COOLDOWN_SECS);
+        let fought_recently = self
+            .last_combat_time
+            .is_some_and(|combat_time| time < combat_time + cooldown);
+        let in_safe_zone = safe_zones
+            .iter()
+            .any(|zone| zone.contains(self.instance_id.map_num, self.position));
+        if !self.in_combat && !fought_recently && in_safe_zone {
+            return Duration::ZERO;
+        }
+        Duration::from_secs(config_get().shard.exit_countdown.get())
+    }
+
+    // Returns when the exit goes through; asking again doesn't restart the countdown
+    pub fn start_exit(&mut self, deadline: SystemTime) -> SystemTime {
+        self.exit_cancelled = false;
+        *self.exit_deadline.get_or_insert(deadline)
+    }
+
+    pub fn get_exit_deadline(&self) -> Option<SystemTime> {
+        self.exit_deadline
+    }
+
+    // Moving, fighting, or trading calls off a pending clean exit.
+    // Returns whether there was one.
+    pub fn cancel_exit(&mut self) -> bool {
+        if self.exit_deadline.take().is_none() {
+            return false;
+        }
+        self.exit_cancelled = true;
+        true
+    }
+
+    pub fn take_exit_cancelled(&mut self) -> bool {
+        std::mem::take(&mut self.exit_cancelled)
+    }
+
+    // Ready again a little before the cooldown runs out, since the packet that
+    // started it may have taken longer to arrive than this one
+    pub fn is_skill_ready(&self, skill_id: i16, time: SystemTime) -> bool {
+        let grace = Duration::from_millis(SKILL_COOLDOWN_GRACE_MS);
+        self.skill_cooldowns
+            .get(&skill_id)
+            .map_or(true, |ready_time| time + grace >= *ready_time)
+    }
+
+    pub fn get_skill_ready_time(&self, skill_id: i16) -> Option<SystemTime> {
+        self.skill_cooldowns.get(&skill_id).copied()
+    }
+
+    pub fn start_skill_cooldown(&mut self, skill_id: i16, cooldown: Duration, time: SystemTime) {
+        if cooldown.is_zero() {
+            self.skill_cooldowns.remove(&skill_id);
+            return;
+        }
+        // a use within the grace window counts from when the skill was ready,
+        // so the grace can't be gained again on every use
+        let start = match self.skill_cooldowns.get(&skill_id) {
+            Some(ready_time) => time.max(*ready_time),
+            None => time,
+        };
+        self.skill_cooldowns.insert(skill_id, start + cooldown);
+    }
+
+    pub fn clear_skill_cooldowns(&mut self) {
+        self.skill_cooldowns.clear();
+    }
+
+    // Rare items only sell if the same stack is sold twice within `window`.
+    // Returns whether this request confirms an earlier one; if not, it becomes the one to confirm.
+    pub fn confirm_rare_sell(
+        &mut self,
+        slot_num: usize,
+        item: Item,
+        time: SystemTime,
+        window: Duration,
+    ) -> bool {
+        match self.sell_confirm.take() {
+            Some((pending_slot_num, pending_item, asked_time))
+                if pending_slot_num == slot_num
+                    && pending_item == item
+                    && time.duration_since(asked_time).unwrap_or_default() <= window =>
+            {
+                true
+            }
+            _ => {
+                self.sell_confirm = Some((slot_num, item, time));
+                false
+            }
+        }
+    }
+
     pub fn disconnect(pc_id: i32, state: &mut ShardServerState, clients: &mut ClientMap) {
+        let player = state.get_player_mut(pc_id).unwrap();
+        // a spectating GM shouldn't be saved wherever their target was
+        if let Some(spectate_state) = player.stop_spectating() {
+            player.set_position(spectate_state.return_to.position);
+            player.instance_id = spectate_state.return_to.instance_id;
+        }
         let player = state.get_player(pc_id).unwrap();
-        let player_saved = player.clone();
-        log_if_failed(db_run_sync(move |db| db.save_player(&player_saved)));
+        let handler_panicked = player
+            .get_client(clients)
+            .is_some_and(|client| client.has_handler_panicked());
+        if handler_panicked {
+            // whatever the handler left half-done shouldn't reach the database,
+            // even through the journal
+            log(
+                Severity::Warning,
+                &format!("Not saving {} after their packet handler panicked", player),
+            );
+            log_if_failed(journal_commit(journal_mark(), Some(&[player.get_uid()])));
+        } else {
+            let player_saved = player.clone();
+            let journal_mark = journal_mark();
+            log_if_failed(db_run_sync(move |db| {
+                db.save_player(&player_saved)?;
+                journal_commit(journal_mark, Some(&[player_saved.get_uid()]))
+            }));
+        }
         log(
             Severity::Info,
             &format!(
@@ -1293,6 +2725,8 @@ impl Player {
             ),
         );
 
+        crate::helpers::send_buddy_presence(player, false, clients);
+
         let id = EntityID::Player(pc_id);
         let entity_map = &mut state.entity_map;
         entity_map.update(id, None, Some(clients));
@@ -1303,6 +2737,60 @@ impl Player {
         client.disconnect();
     }
 
+    fn tick_path_recording(&mut self, time: &SystemTime, clients: &mut ClientMap) {
+        let Some(recorder) = self.path_recorder.as_mut() else {
+            return;
+        };
+        if !recorder.is_recording() {
+            return;
+        }
+
+        if !recorder.sample(self.position, *time) {
+            let msg = format!(
+                "Path recording stopped at the {} point limit",
+                PATH_RECORD_MAX_POINTS
+            );
+            if let Some(client) = self.get_client(clients) {
+                log_if_failed(crate::helpers::send_system_message(client, &msg));
+            }
+        }
+    }
+
+    fn tick_vehicle_boost(&mut self, time: &SystemTime, clients: &mut ClientMap) {
+        let Some(drain_time) = self.vehicle_boost_drain_time else {
+            return;
+        };
+        if self.vehicle_speed.is_none() {
+            // got off the vehicle some other way
+            self.vehicle_boost_drain_time = None;
+            return;
+        }
+        if *time < drain_time {
+            return;
+        }
+
+        let interval = Duration::from_secs(VEHICLE_BOOST_DRAIN_INTERVAL_SECS);
+        let drained = 1
+            + (time.duration_since(drain_time).unwrap().as_secs()
+                / VEHICLE_BOOST_DRAIN_INTERVAL_SECS) as u32;
+        self.weapon_boosts = self.weapon_boosts.saturating_sub(drained);
+        self.vehicle_boost_drain_time = Some(drain_time + interval * drained);
+
+        if let Some(client) = self.get_client(clients) {
+            // the battery buy reply just sets the counts, so it doubles as an update
+            let pkt = sP_FE2CL_REP_PC_VENDOR_BATTERY_BUY_SUCC {
+                iCandy: self.taros as i32,
+                iBatteryW: self.weapon_boosts as i32,
+                iBatteryN: self.nano_potions as i32,
+            };
+            log_if_failed(client.send_packet(P_FE2CL_REP_PC_VENDOR_BATTERY_BUY_SUCC, &pkt));
+        }
+
+        if self.weapon_boosts == 0 {
+            self.stop_vehicle_boost();
+        }
+    }
+
     fn tick_skyway_ride(
         &mut self,
         time: &SystemTime,
@@ -1317,14 +2805,16 @@ impl Player {
             }
 
             if ride.path.is_done() {
-                // we're done!
-                let final_pos = ride.monkey_pos;
-                let cost = ride.trip_data.cost;
-                self.set_taros(self.taros - cost);
-                self.set_position(final_pos);
-                self.skyway_ride = None;
-                crate::helpers::broadcast_monkey(pc_id, RideType::None, clients, state);
-                return;
+                if let Some(fare) = ride.finish_leg() {
+                    // we're done!
+                    let final_pos = ride.monkey_pos;
+                    self.add_taros(-(fare as i64), CurrencySource::Transport);
+                    self.set_position(final_pos);
+                    self.skyway_ride = None;
+                    crate::helpers::broadcast_monkey(pc_id, RideType::None, clients, state);
+                    return;
+                }
+                // otherwise, keep flying along the next leg
             }
 
             // N.B. the client doesn't treat monkey movement like every other movement.
@@ -1349,10 +2839,12 @@ impl Player {
 
             // update the player's chunk.
             // We don't actually update their position until they land
-            let chunk_coords = ChunkCoords::from_pos_inst(ride.monkey_pos, self.instance_id);
-            state
-                .entity_map
-                .update(EntityID::Player(pc_id), Some(chunk_coords), Some(clients));
+            state.entity_map.update_moved(
+                EntityID::Player(pc_id),
+                ride.monkey_pos,
+                self.instance_id,
+                Some(clients),
+            );
 
             // send the move packet
             let pkt = sP_FE2CL_PC_BROOMSTICK_MOVE {
@@ -1530,6 +3022,32 @@ impl Player {
         }
     }
 
+    fn tick_timed_buffs(
+        &mut self,
+        time: &SystemTime,
+        clients: &mut ClientMap,
+        state: &mut ShardServerState,
+    ) {
+        let pc_id = self.get_player_id();
+        for (condition_bit, buff_type) in self.take_expired_buffs(time) {
+            let pkt = self.get_buff_update(condition_bit, buff_type, TimeBuffUpdate::Del);
+            if let Some(client) = self.get_client(clients) {
+                log_if_failed(client.send_packet(P_FE2CL_PC_BUFF_UPDATE, &pkt));
+            }
+
+            let bcast = sP_FE2CL_CHAR_TIME_BUFF_TIME_OUT {
+                eCT: CharType::Player as i32,
+                iID: pc_id,
+                iConditionBitFlag: self.get_condition_bit_flag(),
+            };
+            state
+                .entity_map
+                .for_each_around(EntityID::Player(pc_id), clients, |c| {
+                    c.send_packet(P_FE2CL_CHAR_TIME_BUFF_TIME_OUT, &bcast)
+                });
+        }
+    }
+
     fn tick_regen(&mut self, time: &SystemTime) -> bool {
         const REGEN_INTERVAL: Duration = Duration::from_secs(4);
 
@@ -1557,7 +3075,16 @@ impl Player {
 }
 impl Combatant for Player {
     fn get_condition_bit_flag(&self) -> i32 {
-        placeholder!(0)
+        let buff_flags = self.timed_buffs.keys().fold(0, |flags, bit| flags | *bit);
+        (buff_flags | self.conditions.get_bit_flag()) as i32
+    }
+
+    fn get_conditions(&self) -> &Conditions {
+        &self.conditions
+    }
+
+    fn get_conditions_mut(&mut self) -> &mut Conditions {
+        &mut self.conditions
     }
 
     fn get_group_id(&self) -> Option<Uuid> {
@@ -1580,6 +3107,19 @@ impl Combatant for Player {
         self.get_active_nano().map(|n| n.get_stats().unwrap().style)
     }
 
+    fn get_style_modifiers(&self) -> (Option<f32>, Option<f32>) {
+        let weapon = self
+            .get_item(ItemLocation::Equip, EQUIP_SLOT_HAND as usize)
+            .unwrap();
+        match weapon {
+            Some(weapon) => {
+                let stats = weapon.get_stats().unwrap();
+                (stats.style_mod_trump, stats.style_mod_trumped)
+            }
+            None => (None, None),
+        }
+    }
+
     fn get_team(&self) -> CombatantTeam {
         CombatantTeam::Friendly
     }
@@ -1635,11 +3175,20 @@ impl Combatant for Player {
     }
 
     fn take_damage(&mut self, damage: i32, source: EntityID) -> i32 {
-        if self.invulnerable {
+        if self.invulnerable || self.has_condition(CSB_BIT_INVULNERABLE) {
             return 0;
         }
 
-        self.last_attacked_by = Some(source);
+        // PvP hits shouldn't draw friendly NPCs into the fight
+        if matches!(source, EntityID::NPC(_)) {
+            self.last_attacked_by = Some(source);
+        }
+        self.mark_combat(SystemTime::now());
+        self.cancel_exit();
+
+        if damage > 0 {
+            self.avatar_state = AvatarState::Idle;
+        }
 
         let init_hp = self.hp;
         self.hp = clamp_min(self.hp - damage, 0);
@@ -1669,10 +3218,17 @@ impl Entity for Player {
     }
 
     fn get_speed(&self) -> i32 {
-        if let Some(vehicle_speed) = self.vehicle_speed {
-            vehicle_speed
+        let speed = match self.vehicle_speed {
+            Some(vehicle_speed) if self.is_vehicle_boosting() => {
+                (vehicle_speed as f32 * VEHICLE_BOOST_SPEED_FACTOR) as i32
+            }
+            Some(vehicle_speed) => vehicle_speed,
+            None => PLAYER_RUN_SPEED,
+        };
+        if self.has_condition(CSB_BIT_DN_MOVE_SPEED) {
+            (speed as f32 * SNARE_SPEED_FACTOR) as i32
         } else {
-            PLAYER_RUN_SPEED
+            speed
         }
     }
 
@@ -1681,6 +3237,9 @@ impl Entity for Player {
     }
 
     fn set_position(&mut self, pos: Position) {
+        if pos != self.position {
+            self.avatar_state = AvatarState::Idle;
+        }
         self.position = pos;
     }
 
@@ -1692,7 +3251,17 @@ impl Entity for Player {
         let pkt = sP_FE2CL_PC_NEW {
             PCAppearanceData: self.get_appearance_data(),
         };
-        client.send_packet(PacketID::P_FE2CL_PC_NEW, &pkt)
+        client.send_packet(PacketID::P_FE2CL_PC_NEW, &pkt)?;
+
+        // the appearance data has no room for poses
+        if let Some(emote_code) = self.get_visible_emote(SystemTime::now()) {
+            let pkt = sP_FE2CL_REP_PC_AVATAR_EMOTES_CHAT {
+                iID_From: self.get_player_id(),
+                iEmoteCode: emote_code,
+            };
+            client.send_packet(PacketID::P_FE2CL_REP_PC_AVATAR_EMOTES_CHAT, &pkt)?;
+        }
+        Ok(())
     }
 
     fn send_exit(&self, client: &mut FFClient) -> FFResult<()> {
@@ -1733,6 +3302,19 @@ impl Entity for Player {
             crate::helpers::remove_group_member(EntityID::Player(pc_id), group_id, state, clients)
                 .unwrap();
         }
+
+        // cleanup offers made to this player
+        let pc_uid = self.get_uid();
+        let other_pc_ids: Vec<i32> = state
+            .entity_map
+            .get_player_ids()
+            .filter(|other_pc_id| *other_pc_id != pc_id)
+            .collect();
+        for other_pc_id in other_pc_ids {
+            let player_other = state.get_player_mut(other_pc_id).unwrap();
+            let cancelled = player_other.cancel_offers_to(pc_id, pc_uid);
+            crate::helpers::send_cancelled_offers(other_pc_id, &cancelled, clients, state);
+        }
     }
 
     fn tick(
@@ -1740,14 +3322,28 @@ impl Entity for Player {
         time: &SystemTime,
         clients: &mut ClientMap,
         state: &mut ShardServerState,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) {
+        self.tick_timed_buffs(time, clients, state);
+        crate::helpers::tick_conditions(self, time, clients, state);
         if self.is_dead() {
+            // dying knocks the player off their vehicle. we're ticking a copy,
+            // so the helper works on the state's version and we match it here
+            self.stop_vehicle_boost();
+            if self.vehicle_speed.take().is_some() {
+                log_if_failed(crate::helpers::force_dismount(
+                    self.get_player_id(),
+                    clients,
+                    state,
+                ));
+            }
             return;
         }
 
         self.tick_skyway_ride(time, clients, state);
+        self.tick_vehicle_boost(time, clients);
         self.tick_missions(time, clients, state);
+        self.tick_path_recording(time, clients);
 
         let transmit = self.tick_regen(time);
         if !transmit {
@@ -1830,19 +3426,1345 @@ impl PlayerSearchQuery {
                     None
                 }
             }
-            PlayerSearchQuery::ByUID(pc_uid) => state
-                .entity_map
-                .find_players(|player| player.get_uid() == *pc_uid)
-                .first()
-                .copied(),
-            PlayerSearchQuery::ByName(first_name, last_name) => state
-                .entity_map
-                .find_players(|player| {
+            PlayerSearchQuery::ByUID(pc_uid) => {
+                let pc_id = state.entity_map.get_player_id_by_uid(*pc_uid);
+                debug_assert!(Self::check_scan(pc_id, state, |player| {
+                    player.get_uid() == *pc_uid
+                }));
+                pc_id
+            }
+            PlayerSearchQuery::ByName(first_name, last_name) => {
+                let pc_id = state
+                    .entity_map
+                    .get_player_id_by_name(first_name, last_name);
+                debug_assert!(Self::check_scan(pc_id, state, |player| {
                     player.first_name.eq_ignore_ascii_case(first_name)
                         && player.last_name.eq_ignore_ascii_case(last_name)
-                })
-                .first()
-                .copied(),
+                }));
+                pc_id
+            }
+        }
+    }
+
+    // cross-checks an indexed lookup against a full scan
+    fn check_scan(
+        pc_id: Option<i32>,
+        state: &ShardServerState,
+        f: impl Fn(&Player) -> bool,
+    ) -> bool {
+        let found = state.entity_map.find_players(f);
+        match pc_id {
+            Some(pc_id) => found.contains(&pc_id),
+            None => found.is_empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        chunk::TickMode,
+        enums::MissionType,
+        path::PathPoint,
+        test_utils::{changed_fields, FakeClients, PlayerBuilder, PlayerSnapshot},
+    };
+
+    const TEST_MAX_HP: i32 = 1000;
+    const TEST_BAD_ITEM_ID: i16 = 999;
+
+    fn get_test_max_hp(level: i16) -> FFResult<i32> {
+        Ok(TEST_MAX_HP + level as i32)
+    }
+
+    fn is_test_item_valid(item: &Item) -> bool {
+        item.id != TEST_BAD_ITEM_ID
+    }
+
+    fn get_test_player() -> Player {
+        Player {
+            uid: 1,
+            slot_num: 1,
+            level: 10,
+            hp: 500,
+            perms: CN_ACCOUNT_LEVEL__USER as i16,
+            ..Default::default()
+        }
+    }
+
+    fn get_timeout() -> Duration {
+        Duration::from_secs(config_get().shard.offer_timeout.get())
+    }
+
+    #[test]
+    fn test_offer_expiry() {
+        let sent = SystemTime::now();
+        let mut offer = PendingOffer::default();
+        offer.set(2, sent);
+        assert!(offer.is_pending(sent));
+        assert_eq!(offer.get_target(sent + get_timeout() / 2), Some(2));
+        assert!(!offer.is_pending(sent + get_timeout()));
+
+        // still live, so nothing to reap
+        assert_eq!(offer.take_expired(sent), None);
+        assert_eq!(offer.take_expired(sent + get_timeout()), Some(2));
+        assert_eq!(offer.take_expired(sent + get_timeout()), None);
+    }
+
+    #[test]
+    fn test_offer_stale_accept() {
+        let sent = SystemTime::now();
+        let mut offer = PendingOffer::default();
+        offer.set(2, sent);
+
+        // wrong target leaves the offer alone
+        assert!(!offer.take_for(3, sent));
+        assert!(offer.is_pending(sent));
+
+        // the right target is too late
+        assert!(!offer.take_for(2, sent + get_timeout()));
+
+        offer.set(2, sent);
+        assert!(offer.take_for(2, sent));
+        assert!(!offer.take_for(2, sent));
+    }
+
+    #[test]
+    fn test_offer_reinvite() {
+        let sent = SystemTime::now();
+        let later = sent + get_timeout();
+        let mut player = Player::default();
+        player.group_offered_to.set(2, sent);
+        assert!(!player.group_offered_to.is_pending(later));
+
+        player.group_offered_to.set(3, later);
+        assert!(player.group_offered_to.is_pending(later));
+        assert!(!player.group_offered_to.take_for(2, later));
+        assert!(player.group_offered_to.take_for(3, later));
+    }
+
+    #[test]
+    fn test_offer_cancellation() {
+        let sent = SystemTime::now();
+        let mut player = Player::default();
+        player.group_offered_to.set(2, sent);
+        player.trade_offered_to.set(3, sent);
+        player.buddy_offered_to.set(20, sent);
+
+        let expired = player.take_expired_offers(sent);
+        assert!(expired.is_empty());
+
+        // cancelled even though the offers are still live
+        let cancelled = player.cancel_offers_to(2, 20);
+        assert_eq!(
+            cancelled,
+            CancelledOffers {
+                group: Some(2),
+                trade: None,
+                buddy: Some(20),
+            }
+        );
+        assert!(player.trade_offered_to.is_pending(sent));
+
+        let expired = player.take_expired_offers(sent + get_timeout());
+        assert_eq!(
+            expired,
+            CancelledOffers {
+                group: None,
+                trade: Some(3),
+                buddy: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_clean_player() {
+        let mut player = get_test_player();
+        player.inventory.main[0] = Some(Item::new(ItemType::Hand, 1));
+        player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .unwrap();
+        assert_eq!(player.level, 10);
+        assert_eq!(player.hp, 500);
+        assert!(player.inventory.main[0].is_some());
+    }
+
+    #[test]
+    fn test_validate_clamps_stats() {
+        let mut player = get_test_player();
+        player.level = 0;
+        player.hp = -50;
+        player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .unwrap();
+        assert_eq!(player.level, 1);
+        assert_eq!(player.hp, 0);
+
+        player.level = PC_LEVEL_MAX as i16 + 5;
+        player.hp = i32::MAX;
+        player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .unwrap();
+        assert_eq!(player.level, PC_LEVEL_MAX as i16);
+        assert_eq!(player.hp, TEST_MAX_HP + PC_LEVEL_MAX as i32);
+
+        // high perms are allowed to overflow
+        player.perms = CN_ACCOUNT_LEVEL__DEVELOPER as i16;
+        player.hp = i32::MAX;
+        player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .unwrap();
+        assert_eq!(player.hp, i32::MAX);
+    }
+
+    #[test]
+    fn test_validate_drops_bad_items() {
+        let mut player = get_test_player();
+        player.inventory.equipped[0] = Some(Item::new(ItemType::Hand, TEST_BAD_ITEM_ID));
+        player.inventory.main[3] = Some(Item::new(ItemType::General, 1));
+        player.inventory.main[4] = Some(Item::new(ItemType::General, TEST_BAD_ITEM_ID));
+        player.inventory.bank[7] = Some(Item::new(ItemType::Chest, TEST_BAD_ITEM_ID));
+        player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .unwrap();
+        assert!(player.inventory.equipped[0].is_none());
+        assert!(player.inventory.main[3].is_some());
+        assert!(player.inventory.main[4].is_none());
+        assert!(player.inventory.bank[7].is_none());
+    }
+
+    #[test]
+    fn test_validate_unrecoverable() {
+        let mut player = get_test_player();
+        player.slot_num = 0;
+        assert!(player
+            .validate_with(get_test_max_hp, is_test_item_valid)
+            .is_err());
+
+        let mut player = get_test_player();
+        let no_stats = |_: i16| -> FFResult<i32> {
+            Err(FFError::build(Severity::Warning, "No stats".to_string()))
+        };
+        assert!(player.validate_with(no_stats, is_test_item_valid).is_err());
+    }
+
+    #[test]
+    fn test_timed_buff_expiry() {
+        let now = SystemTime::now();
+        let mut player = Player::default();
+        player.add_timed_buff(
+            CSB_BIT_UP_MOVE_SPEED,
+            TimeBuffType::Item,
+            Duration::from_secs(10),
+            now,
+        );
+        player.add_timed_buff(
+            CSB_BIT_REWARD_BLOB,
+            TimeBuffType::Nano,
+            Duration::from_secs(30),
+            now,
+        );
+        assert_eq!(
+            player.get_condition_bit_flag(),
+            (CSB_BIT_UP_MOVE_SPEED | CSB_BIT_REWARD_BLOB) as i32
+        );
+
+        assert!(player.take_expired_buffs(&now).is_empty());
+        assert_eq!(
+            player.take_expired_buffs(&(now + Duration::from_secs(10))),
+            vec![(CSB_BIT_UP_MOVE_SPEED, TimeBuffType::Item)]
+        );
+        assert_eq!(player.get_condition_bit_flag(), CSB_BIT_REWARD_BLOB as i32);
+
+        // reapplying a buff refreshes it instead of stacking
+        player.add_timed_buff(
+            CSB_BIT_REWARD_BLOB,
+            TimeBuffType::Nano,
+            Duration::from_secs(30),
+            now + Duration::from_secs(20),
+        );
+        assert!(player
+            .take_expired_buffs(&(now + Duration::from_secs(30)))
+            .is_empty());
+        assert_eq!(
+            player.take_expired_buffs(&(now + Duration::from_secs(50))),
+            vec![(CSB_BIT_REWARD_BLOB, TimeBuffType::Nano)]
+        );
+        assert_eq!(player.get_condition_bit_flag(), 0);
+    }
+
+    #[test]
+    fn test_player_index_consistency() {
+        use crate::chunk::{EntityMap, TickMode};
+
+        const NUM_PLAYERS: i32 = 5000;
+        let make_player = |pc_id: i32| {
+            let mut player = Player {
+                uid: pc_id as i64 * 1000,
+                first_name: format!("First{}", pc_id),
+                last_name: format!("Last{}", pc_id),
+                ..Default::default()
+            };
+            player.set_player_id(pc_id);
+            player
+        };
+        let check = |entity_map: &EntityMap, pc_id: i32, tracked: bool| {
+            let expected = if tracked { Some(pc_id) } else { None };
+            let uid = pc_id as i64 * 1000;
+            let first_name = format!("FIRST{}", pc_id);
+            let last_name = format!("last{}", pc_id);
+            assert_eq!(entity_map.get_player_id_by_uid(uid), expected);
+            assert_eq!(
+                entity_map.get_player_id_by_name(&first_name, &last_name),
+                expected
+            );
+            let scanned = entity_map.find_players(|p| p.get_uid() == uid);
+            assert_eq!(scanned.first().copied(), expected);
+        };
+
+        let mut entity_map = EntityMap::default();
+        for pc_id in 1..=NUM_PLAYERS {
+            entity_map.track(Box::new(make_player(pc_id)), TickMode::Always);
+        }
+
+        // drop every third player, then bring half of those back
+        for pc_id in (1..=NUM_PLAYERS).step_by(3) {
+            entity_map.untrack(EntityID::Player(pc_id));
+        }
+        for pc_id in (1..=NUM_PLAYERS).step_by(6) {
+            entity_map.track(Box::new(make_player(pc_id)), TickMode::Always);
+        }
+
+        for pc_id in 1..=NUM_PLAYERS {
+            let tracked = (pc_id - 1) % 3 != 0 || (pc_id - 1) % 6 == 0;
+            check(&entity_map, pc_id, tracked);
         }
+
+        // renamed players are found by their new name only, even after leaving
+        entity_map
+            .rename_player(2, "New".to_string(), "Name".to_string())
+            .unwrap();
+        assert_eq!(entity_map.get_player_id_by_name("new", "name"), Some(2));
+        assert_eq!(entity_map.get_player_id_by_name("First2", "Last2"), None);
+        assert_eq!(entity_map.find_players(|p| p.first_name == "New"), vec![2]);
+        entity_map.untrack(EntityID::Player(2));
+        assert_eq!(entity_map.get_player_id_by_name("New", "Name"), None);
+        assert!(entity_map
+            .rename_player(2, "Gone".to_string(), "Player".to_string())
+            .is_err());
+    }
+
+    const WARP_ITEM_ID: i16 = 50;
+    const WARP_CONSUMED_ITEM_ID: i16 = 51;
+    const WARP_REQ_ITEM_SLOT: (i32, usize) = (ItemLocation::Inven as i32, 0);
+    const WARP_CONSUMED_ITEM_SLOT: (i32, usize) = (ItemLocation::Inven as i32, 1);
+
+    fn get_test_warp() -> WarpData {
+        WarpData {
+            pos: Position::default(),
+            npc_type: 1,
+            is_instance: false,
+            is_group_warp: false,
+            map_num: 0,
+            min_level: 5,
+            req_task: None,
+            req_item: Some((ItemType::General, WARP_ITEM_ID)),
+            req_item_consumed: Some((ItemType::General, WARP_CONSUMED_ITEM_ID)),
+            cost: 100,
+        }
+    }
+
+    fn get_test_warp_player(consumed_quantity: u16) -> Player {
+        let mut player = get_test_player();
+        player.taros = 150;
+        let mut consumed_item = Item::new(ItemType::General, WARP_CONSUMED_ITEM_ID);
+        consumed_item.quantity = consumed_quantity;
+        player.inventory.main[0] = Some(Item::new(ItemType::General, WARP_ITEM_ID));
+        player.inventory.main[1] = Some(consumed_item);
+        player
+    }
+
+    fn check_test_warp(player: &Player, warp: &WarpData) -> Result<(), WarpDenial> {
+        player
+            .check_warp_requirements(warp, WARP_REQ_ITEM_SLOT, WARP_CONSUMED_ITEM_SLOT, false)
+            .map_err(|(code, _)| code)
+    }
+
+    #[test]
+    fn test_warp_requirements() {
+        let warp = get_test_warp();
+        assert!(check_test_warp(&get_test_warp_player(1), &warp).is_ok());
+
+        let mut player = get_test_warp_player(1);
+        player.level = 4;
+        assert_eq!(
+            check_test_warp(&player, &warp),
+            Err(WarpDenial::LevelTooLow)
+        );
+
+        let warp_with_task = WarpData {
+            req_task: Some((1, 10)),
+            ..get_test_warp()
+        };
+        assert_eq!(
+            check_test_warp(&get_test_warp_player(1), &warp_with_task),
+            Err(WarpDenial::MissingTask)
+        );
+
+        let mut player = get_test_warp_player(1);
+        player.inventory.main[0] = None;
+        assert_eq!(
+            check_test_warp(&player, &warp),
+            Err(WarpDenial::MissingItem)
+        );
+
+        // right item, wrong slot
+        let mut player = get_test_warp_player(1);
+        player.inventory.main.swap(0, 1);
+        assert_eq!(
+            check_test_warp(&player, &warp),
+            Err(WarpDenial::MissingItem)
+        );
+
+        let mut player = get_test_warp_player(1);
+        player.inventory.main[1] = None;
+        assert_eq!(
+            check_test_warp(&player, &warp),
+            Err(WarpDenial::MissingItem)
+        );
+
+        let mut player = get_test_warp_player(1);
+        player.taros = 99;
+        assert_eq!(
+            check_test_warp(&player, &warp),
+            Err(WarpDenial::NotEnoughTaros)
+        );
+    }
+
+    #[test]
+    fn test_warp_req_task_completed() {
+        let warp_with_task = WarpData {
+            req_task: Some((1, 10)),
+            ..get_test_warp()
+        };
+        let mut player = get_test_warp_player(1);
+        player.mission_journal.set_mission_completed(1).unwrap();
+        assert!(check_test_warp(&player, &warp_with_task).is_ok());
+    }
+
+    #[test]
+    fn test_warp_gm_bypass() {
+        let warp = WarpData {
+            req_task: Some((1, 10)),
+            ..get_test_warp()
+        };
+        let make_gm = || {
+            let mut player = get_test_warp_player(1);
+            player.perms = CN_ACCOUNT_LEVEL__GM as i16;
+            player.level = 1;
+            player.inventory.main[0] = None;
+            player
+        };
+        let check = |player: &Player, allow_gm_bypass| {
+            player
+                .check_warp_requirements(
+                    &warp,
+                    WARP_REQ_ITEM_SLOT,
+                    WARP_CONSUMED_ITEM_SLOT,
+                    allow_gm_bypass,
+                )
+                .map_err(|(code, _)| code)
+        };
+
+        let gm = make_gm();
+        assert_eq!(check(&gm, false), Err(WarpDenial::LevelTooLow));
+        assert!(check(&gm, true).is_ok());
+
+        // regular players never skip the gates
+        let mut player = make_gm();
+        player.perms = CN_ACCOUNT_LEVEL__USER as i16;
+        assert_eq!(check(&player, true), Err(WarpDenial::LevelTooLow));
+
+        // the warp still has to be paid for
+        let mut gm = make_gm();
+        gm.taros = 99;
+        assert_eq!(check(&gm, true), Err(WarpDenial::NotEnoughTaros));
+        let mut gm = make_gm();
+        gm.inventory.main[1] = None;
+        assert_eq!(check(&gm, true), Err(WarpDenial::MissingItem));
+    }
+
+    fn make_skyway_leg(end_location: i32, from: Position, to: Position) -> SkywayLeg {
+        let point = |pos| PathPoint {
+            pos,
+            speed: 100,
+            stop_ticks: 0,
+        };
+        SkywayLeg {
+            end_location,
+            path: Path::new(vec![point(from), point(to)], false),
+        }
+    }
+
+    fn fly_leg(ride: &mut SkywayRideState) -> Option<u32> {
+        while !ride.path.is_done() {
+            ride.path.tick(&mut ride.monkey_pos);
+        }
+        ride.finish_leg()
+    }
+
+    fn get_skyway_test_legs() -> Vec<SkywayLeg> {
+        let a = Position::default();
+        let b = Position { x: 300, y: 0, z: 0 };
+        let c = Position { x: 400, y: 0, z: 0 };
+        vec![make_skyway_leg(2, a, b), make_skyway_leg(3, b, c)]
+    }
+
+    #[test]
+    fn test_skyway_multi_leg() {
+        let trip = Box::leak(Box::new(TripData {
+            npc_id: 0,
+            transportation_type: TransportationType::Wyvern,
+            start_location: 1,
+            end_location: 3,
+            cost: 100,
+            speed: 0,
+            route_number: 0,
+        }));
+        let mut player = get_test_player();
+        assert!(player.start_skyway_ride(trip, Vec::new()).is_err());
+
+        // the full ride flies every leg and costs the full fare
+        player
+            .start_skyway_ride(trip, get_skyway_test_legs())
+            .unwrap();
+        let ride = player.skyway_ride.as_mut().unwrap();
+        assert_eq!(ride.station, 2);
+        assert_eq!(fly_leg(ride), None);
+        assert_eq!(ride.station, 3);
+        assert_eq!(fly_leg(ride), Some(100));
+        assert_eq!(ride.monkey_pos, Position { x: 400, y: 0, z: 0 });
+
+        // getting off early costs the share of the distance flown
+        player
+            .start_skyway_ride(trip, get_skyway_test_legs())
+            .unwrap();
+        assert_eq!(player.request_skyway_disembark().unwrap(), 2);
+        let ride = player.skyway_ride.as_mut().unwrap();
+        assert_eq!(fly_leg(ride), Some(75));
+        assert_eq!(ride.monkey_pos, Position { x: 300, y: 0, z: 0 });
+
+        // there's no earlier stop on the last leg
+        player
+            .start_skyway_ride(trip, get_skyway_test_legs())
+            .unwrap();
+        let ride = player.skyway_ride.as_mut().unwrap();
+        fly_leg(ride);
+        assert!(player.request_skyway_disembark().is_err());
+    }
+
+    #[test]
+    fn test_trip_requirements() {
+        let trip = TripData {
+            npc_id: 0,
+            transportation_type: TransportationType::Warp,
+            start_location: 1,
+            end_location: 2,
+            cost: 100,
+            speed: 0,
+            route_number: 0,
+        };
+        let check = |player: &Player, trip: &TripData| {
+            player
+                .check_trip_requirements(trip)
+                .map_err(|(code, _)| code)
+        };
+
+        let mut player = get_test_player();
+        player.taros = 100;
+        player
+            .unlock_transport_location(TransportationType::Warp, 1)
+            .unwrap();
+        assert_eq!(check(&player, &trip), Err(TripDenial::LocationLocked));
+
+        // unlocking is idempotent
+        assert!(player
+            .unlock_transport_location(TransportationType::Warp, 2)
+            .unwrap());
+        assert!(!player
+            .unlock_transport_location(TransportationType::Warp, 2)
+            .unwrap());
+        assert!(check(&player, &trip).is_ok());
+
+        // Skyway locations are tracked separately
+        let skyway_trip = TripData {
+            transportation_type: TransportationType::Wyvern,
+            ..trip
+        };
+        assert_eq!(
+            check(&player, &skyway_trip),
+            Err(TripDenial::LocationLocked)
+        );
+
+        player.taros = 99;
+        assert_eq!(check(&player, &trip), Err(TripDenial::NotEnoughTaros));
+    }
+
+    #[test]
+    fn test_warp_payment() {
+        let warp = get_test_warp();
+
+        // last item in the stack empties the slot
+        let mut player = get_test_warp_player(1);
+        let (payment, remaining) = player.pay_for_warp(&warp, WARP_CONSUMED_ITEM_SLOT).unwrap();
+        assert_eq!(remaining, None);
+        assert_eq!(player.inventory.main[1], None);
+        assert_eq!(player.taros, 50);
+
+        player.refund_warp(payment);
+        assert_eq!(player.taros, 150);
+        assert_eq!(player.inventory.main[1].map(|item| item.quantity), Some(1));
+
+        let mut player = get_test_warp_player(3);
+        let (_, remaining) = player.pay_for_warp(&warp, WARP_CONSUMED_ITEM_SLOT).unwrap();
+        assert_eq!(remaining.map(|item| item.quantity), Some(2));
+
+        // nothing is taken if the payment can't be made in full
+        let mut player = get_test_warp_player(1);
+        player.taros = 99;
+        assert!(player.pay_for_warp(&warp, WARP_CONSUMED_ITEM_SLOT).is_err());
+        assert_eq!(player.inventory.main[1].map(|item| item.quantity), Some(1));
+
+        let mut player = get_test_warp_player(1);
+        player.inventory.main.swap(0, 1);
+        assert!(player.pay_for_warp(&warp, WARP_CONSUMED_ITEM_SLOT).is_err());
+        assert_eq!(player.taros, 150);
+    }
+
+    #[test]
+    fn test_move_item() {
+        let mut player = PlayerBuilder::new(1)
+            .item(ItemLocation::Inven, 0, Item::new(ItemType::Hand, 10))
+            .item(ItemLocation::Inven, 1, Item::new(ItemType::Hand, 11))
+            .build();
+
+        // into an empty slot
+        let before = PlayerSnapshot::take(&player);
+        let (item_from, item_to) = player
+            .move_item(ItemLocation::Inven, 0, ItemLocation::Equip, 0)
+            .unwrap();
+        assert_eq!(item_from, None);
+        assert_eq!(item_to.map(|item| item.id), Some(10));
+        let changes = before.diff(&PlayerSnapshot::take(&player));
+        assert_eq!(
+            changed_fields(&changes),
+            vec!["items[Equip 0]", "items[Inven 0]"]
+        );
+
+        // different items swap
+        let before = PlayerSnapshot::take(&player);
+        player
+            .move_item(ItemLocation::Inven, 1, ItemLocation::Equip, 0)
+            .unwrap();
+        let changes = before.diff(&PlayerSnapshot::take(&player));
+        assert_eq!(
+            changed_fields(&changes),
+            vec!["items[Equip 0]", "items[Inven 1]"]
+        );
+        let equipped = player.get_item(ItemLocation::Equip, 0).unwrap();
+        assert_eq!(equipped.map(|item| item.id), Some(11));
+
+        // a bad slot leaves everything where it was
+        let before = PlayerSnapshot::take(&player);
+        assert!(player
+            .move_item(ItemLocation::Inven, 1, ItemLocation::Bank, 999)
+            .is_err());
+        assert!(before.diff(&PlayerSnapshot::take(&player)).is_empty());
+    }
+
+    #[test]
+    fn test_remove_items_any() {
+        let stack = |id, quantity| {
+            let mut item = Item::new(ItemType::General, id);
+            item.quantity = quantity;
+            item
+        };
+        let mut player = PlayerBuilder::new(1)
+            .item(ItemLocation::Inven, 2, stack(5, 4))
+            .item(ItemLocation::Inven, 3, stack(6, 9))
+            .item(ItemLocation::Bank, 0, stack(5, 10))
+            .build();
+        let is_target = |item: &Item| item.ty == ItemType::General && item.id == 5;
+
+        // main inventory goes before the bank
+        let (removed, changed) = player.remove_items_any(is_target, 6).unwrap();
+        assert_eq!(removed, 6);
+        assert_eq!(
+            changed,
+            vec![
+                (ItemLocation::Inven, 2, None),
+                (ItemLocation::Bank, 0, Some(stack(5, 8))),
+            ]
+        );
+        assert_eq!(
+            player.find_items_any(is_target),
+            vec![(ItemLocation::Bank, 0)]
+        );
+
+        // asking for more than there is takes everything
+        let (removed, changed) = player.remove_items_any(is_target, 100).unwrap();
+        assert_eq!(removed, 8);
+        assert_eq!(changed, vec![(ItemLocation::Bank, 0, None)]);
+        assert!(player.find_items_any(is_target).is_empty());
+        assert!(player.get_item(ItemLocation::Inven, 3).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_plan_item_placement() {
+        let stack = |id, quantity| {
+            let mut item = Item::new(ItemType::General, id);
+            item.quantity = quantity;
+            item
+        };
+        let mut player = PlayerBuilder::new(1)
+            .item(ItemLocation::Inven, 0, stack(5, 90))
+            .item(ItemLocation::Inven, 1, stack(6, 10))
+            .item(ItemLocation::Inven, 3, stack(5, 100))
+            .item(ItemLocation::Inven, 4, stack(5, 50))
+            .build();
+
+        // partial stacks fill up first, in slot order
+        let plan = player.plan_item_placement(&stack(5, 40), 100, 2).unwrap();
+        assert_eq!(plan, vec![(0, 10), (4, 30)]);
+
+        // overflow goes to the preferred slot, then other free slots
+        let plan = player.plan_item_placement(&stack(5, 260), 100, 2).unwrap();
+        assert_eq!(plan, vec![(0, 10), (4, 50), (2, 100), (5, 100)]);
+
+        // a taken preferred slot is skipped
+        let plan = player.plan_item_placement(&stack(7, 1), 100, 1).unwrap();
+        assert_eq!(plan, vec![(2, 1)]);
+
+        // planning never touches the inventory
+        let before = PlayerSnapshot::take(&player);
+        player.plan_item_placement(&stack(5, 260), 100, 2).unwrap();
+        assert!(before.diff(&PlayerSnapshot::take(&player)).is_empty());
+
+        // full inventory
+        for slot_num in 0..SIZEOF_INVEN_SLOT as usize {
+            player.inventory.main[slot_num] = Some(stack(6, 100));
+        }
+        assert!(player.plan_item_placement(&stack(5, 1), 100, 2).is_err());
+        player.inventory.main[7] = Some(stack(5, 95));
+        let plan = player.plan_item_placement(&stack(5, 5), 100, 2).unwrap();
+        assert_eq!(plan, vec![(7, 5)]);
+        assert!(player.plan_item_placement(&stack(5, 6), 100, 2).is_err());
+    }
+
+    #[test]
+    fn test_consolidate_inventory() {
+        let stack = |id, quantity| {
+            let mut item = Item::new(ItemType::General, id);
+            item.quantity = quantity;
+            item
+        };
+        let mut expiring = stack(5, 30);
+        expiring.set_expiry_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        let mut player = PlayerBuilder::new(1)
+            .item(ItemLocation::Inven, 1, stack(6, 10))
+            .item(ItemLocation::Inven, 2, stack(5, 60))
+            .item(ItemLocation::Inven, 4, expiring)
+            .item(ItemLocation::Inven, 5, stack(5, 70))
+            .item(ItemLocation::Inven, 7, stack(6, 5))
+            .item(ItemLocation::Inven, 8, stack(5, 60))
+            .build();
+
+        let changed = player.consolidate_inventory(|_| Ok(100)).unwrap();
+        assert_eq!(changed, vec![0, 1, 2, 3, 4, 5, 7, 8]);
+        // items keep the order they were first found in, the overflow from a merge
+        // goes to a new stack, and items that expire at different times stay apart
+        let main: Vec<Option<Item>> = (0..6)
+            .map(|slot_num| *player.get_item(ItemLocation::Inven, slot_num).unwrap())
+            .collect();
+        assert_eq!(
+            main,
+            vec![
+                Some(stack(6, 15)),
+                Some(stack(5, 100)),
+                Some(expiring),
+                Some(stack(5, 90)),
+                None,
+                None,
+            ]
+        );
+
+        // already tidy, so nothing to do
+        assert!(player
+            .consolidate_inventory(|_| Ok(100))
+            .unwrap()
+            .is_empty());
+
+        // a lower cap splits stacks up again
+        let changed = player.consolidate_inventory(|_| Ok(50)).unwrap();
+        assert_eq!(changed, vec![1, 2, 3, 4, 5]);
+        let main: Vec<Option<Item>> = (0..6)
+            .map(|slot_num| *player.get_item(ItemLocation::Inven, slot_num).unwrap())
+            .collect();
+        assert_eq!(
+            main,
+            vec![
+                Some(stack(6, 15)),
+                Some(stack(5, 50)),
+                Some(stack(5, 50)),
+                Some(expiring),
+                Some(stack(5, 50)),
+                Some(stack(5, 40)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_consolidate_inventory_blocked() {
+        let stack = |quantity| {
+            let mut item = Item::new(ItemType::General, 5);
+            item.quantity = quantity;
+            item
+        };
+        let mut player = PlayerBuilder::new(1)
+            .item(ItemLocation::Inven, 0, stack(10))
+            .item(ItemLocation::Inven, 3, stack(20))
+            .build();
+
+        player.trade_id = Some(Uuid::new_v4());
+        let before = PlayerSnapshot::take(&player);
+        assert!(player.consolidate_inventory(|_| Ok(100)).is_err());
+        assert!(before.diff(&PlayerSnapshot::take(&player)).is_empty());
+
+        // a failed lookup leaves everything alone too
+        player.trade_id = None;
+        let before = PlayerSnapshot::take(&player);
+        assert!(player
+            .consolidate_inventory(|_| Err(FFError::build(Severity::Warning, String::new())))
+            .is_err());
+        assert!(before.diff(&PlayerSnapshot::take(&player)).is_empty());
+
+        assert_eq!(
+            player.consolidate_inventory(|_| Ok(100)).unwrap(),
+            vec![0, 3]
+        );
+        assert_eq!(
+            *player.get_item(ItemLocation::Inven, 0).unwrap(),
+            Some(stack(30))
+        );
+    }
+
+    #[test]
+    fn test_mission_task_flow() {
+        let mut player = PlayerBuilder::new(1).build();
+        let task = Task::new_mock(10, 1, MissionType::Normal);
+
+        let before = PlayerSnapshot::take(&player);
+        player.mission_journal.start_task(task).unwrap();
+        let started = PlayerSnapshot::take(&player);
+        let changes = before.diff(&started);
+        assert_eq!(changed_fields(&changes), vec!["current_task_ids"]);
+        assert_eq!(changes[0].to_string(), "current_task_ids: [] -> [10]");
+
+        player.mission_journal.complete_task(10).unwrap();
+        player.mission_journal.remove_task(10).unwrap();
+        player.set_mission_completed(1).unwrap();
+        let changes = started.diff(&PlayerSnapshot::take(&player));
+        assert_eq!(
+            changed_fields(&changes),
+            vec!["current_task_ids", "completed_mission_ids"]
+        );
+        assert_eq!(changes[1].to_string(), "completed_mission_ids: [] -> [1]");
+    }
+
+    fn turn_in_qitems(player: &mut Player, task_def: &TaskDefinition, others: &[&TaskDefinition]) {
+        for (qitem_id, count) in player.plan_qitem_turn_in(task_def, others) {
+            player.set_quest_item_count(qitem_id, count).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_qitem_turn_in_shared() {
+        const QITEM_ID: i16 = 7;
+        // A needs 3 and deletes the rest on turn-in, B needs 2 and uses them up
+        let mut task_a = TaskDefinition::new_mock(10, 1);
+        task_a.obj_qitems.insert(QITEM_ID, 3);
+        task_a.delete_qitems.insert(QITEM_ID);
+        let mut task_b = TaskDefinition::new_mock(20, 2);
+        task_b.obj_qitems.insert(QITEM_ID, 2);
+        task_b.succ_qitems.insert(QITEM_ID, -2);
+
+        let mut player = get_test_player();
+        player.set_quest_item_count(QITEM_ID, 5).unwrap();
+        assert_eq!(
+            player.plan_qitem_turn_in(&task_a, &[&task_b]),
+            vec![(QITEM_ID, 2)]
+        );
+        turn_in_qitems(&mut player, &task_a, &[&task_b]);
+        assert_eq!(player.plan_qitem_turn_in(&task_b, &[]), vec![(QITEM_ID, 0)]);
+        turn_in_qitems(&mut player, &task_b, &[]);
+        assert_eq!(player.get_quest_item_count(QITEM_ID), 0);
+
+        let mut player = get_test_player();
+        player.set_quest_item_count(QITEM_ID, 5).unwrap();
+        assert_eq!(
+            player.plan_qitem_turn_in(&task_b, &[&task_a]),
+            vec![(QITEM_ID, 3)]
+        );
+        turn_in_qitems(&mut player, &task_b, &[&task_a]);
+        assert_eq!(player.plan_qitem_turn_in(&task_a, &[]), vec![(QITEM_ID, 0)]);
+        turn_in_qitems(&mut player, &task_a, &[]);
+        assert_eq!(player.get_quest_item_count(QITEM_ID), 0);
+    }
+
+    #[test]
+    fn test_qitem_turn_in_clamped() {
+        let mut task = TaskDefinition::new_mock(10, 1);
+        task.succ_qitems.insert(1, -3);
+        task.succ_qitems.insert(2, 4);
+        // nothing to delete
+        task.delete_qitems.insert(3);
+
+        let mut player = get_test_player();
+        player.set_quest_item_count(1, 1).unwrap();
+        assert_eq!(player.plan_qitem_turn_in(&task, &[]), vec![(1, 0), (2, 4)]);
+        turn_in_qitems(&mut player, &task, &[]);
+        assert_eq!(player.get_quest_item_count(1), 0);
+        assert_eq!(player.get_quest_item_count(2), 4);
+    }
+
+    #[test]
+    fn test_quest_item_slot_reuse() {
+        let mut player = get_test_player();
+        assert_eq!(player.set_quest_item_count(1, 1).unwrap(), 0);
+        assert_eq!(player.set_quest_item_count(2, 1).unwrap(), 1);
+        assert_eq!(player.set_quest_item_count(1, 0).unwrap(), 0);
+
+        // updates stay in the item's slot instead of taking the freed one
+        assert_eq!(player.set_quest_item_count(2, 5).unwrap(), 1);
+        assert_eq!(player.get_quest_item_count(2), 5);
+        assert_eq!(player.set_quest_item_count(3, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_avatar_state_cleared_on_move() {
+        let time = SystemTime::now();
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        player.set_emote(EMOTE_CODE_SIT, time);
+        assert_eq!(player.get_avatar_state(), AvatarState::Sitting);
+
+        // stopping in place isn't moving
+        player.set_position(player.get_position());
+        assert_eq!(player.get_avatar_state(), AvatarState::Sitting);
+        player.set_position(Position { x: 100, y: 0, z: 0 });
+        assert_eq!(player.get_avatar_state(), AvatarState::Idle);
+
+        player.set_emote(EMOTE_CODE_SIT, time);
+        player.take_damage(0, EntityID::NPC(1));
+        assert_eq!(player.get_avatar_state(), AvatarState::Sitting);
+        player.take_damage(10, EntityID::NPC(1));
+        assert_eq!(player.get_avatar_state(), AvatarState::Idle);
+    }
+
+    #[test]
+    fn test_avatar_state_visible_emote() {
+        let time = SystemTime::now();
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        assert_eq!(player.get_visible_emote(time), None);
+
+        player.set_emote(EMOTE_CODE_SIT, time);
+        let much_later = time + Duration::from_secs(3600);
+        assert_eq!(player.get_visible_emote(much_later), Some(EMOTE_CODE_SIT));
+
+        // one-off emotes are only worth showing while they're still playing
+        player.set_emote(EMOTE_CODE_SIT + 1, time);
+        assert_eq!(player.get_visible_emote(time), Some(EMOTE_CODE_SIT + 1));
+        assert_eq!(player.get_visible_emote(much_later), None);
+    }
+
+    #[test]
+    fn test_avatar_state_sent_on_enter() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(2));
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+
+        player.send_enter(fake_clients.get_client(key)).unwrap();
+        assert_eq!(fake_clients.take_sent(key), vec![P_FE2CL_PC_NEW]);
+
+        player.set_emote(EMOTE_CODE_SIT, SystemTime::now());
+        player.send_enter(fake_clients.get_client(key)).unwrap();
+        assert_eq!(
+            fake_clients.take_sent(key),
+            vec![P_FE2CL_PC_NEW, P_FE2CL_REP_PC_AVATAR_EMOTES_CHAT]
+        );
+    }
+
+    #[test]
+    fn test_vehicle_boost_drain() {
+        let time = SystemTime::now();
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let rider_key = fake_clients.add_game_client(Some(1));
+        let watcher_key = fake_clients.add_game_client(Some(2));
+        for (pc_id, key) in [(1, rider_key), (2, watcher_key)] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .build();
+            let chunk_pos = player.get_chunk_coords();
+            let id = state.entity_map.track(Box::new(player), TickMode::Never);
+            state.entity_map.update(id, Some(chunk_pos), None);
+        }
+
+        let mut rider = state.get_player(1).unwrap().clone();
+        assert!(rider.start_vehicle_boost(time).is_err());
+        rider.vehicle_speed = Some(1000);
+        assert!(rider.start_vehicle_boost(time).is_err());
+        rider.set_weapon_boosts(2);
+        rider.start_vehicle_boost(time).unwrap();
+        assert_eq!(rider.get_speed(), 1500);
+
+        let mut tick = |rider: &mut Player, ms: u64| {
+            let time = time + Duration::from_millis(ms);
+            rider.tick_vehicle_boost(&time, &mut fake_clients.get_map(rider_key));
+            (
+                fake_clients.take_sent(rider_key),
+                fake_clients.take_sent(watcher_key),
+            )
+        };
+
+        // nothing burns until a full interval has passed
+        assert_eq!(tick(&mut rider, 500), (vec![], vec![]));
+        assert_eq!(rider.get_weapon_boosts(), 2);
+
+        assert_eq!(
+            tick(&mut rider, 1000),
+            (vec![P_FE2CL_REP_PC_VENDOR_BATTERY_BUY_SUCC], vec![])
+        );
+        assert_eq!(rider.get_weapon_boosts(), 1);
+        assert!(rider.is_vehicle_boosting());
+
+        // running dry ends the boost
+        assert_eq!(
+            tick(&mut rider, 2000),
+            (vec![P_FE2CL_REP_PC_VENDOR_BATTERY_BUY_SUCC], vec![])
+        );
+        assert_eq!(rider.get_weapon_boosts(), 0);
+        assert!(!rider.is_vehicle_boosting());
+        assert_eq!(rider.get_speed(), 1000);
+        assert_eq!(tick(&mut rider, 3000), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_buddy_warp_cooldown() {
+        let time = SystemTime::now();
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        assert!(player.check_buddy_warp_cooldown(time).is_ok());
+
+        player.start_buddy_warp_cooldown(time, Duration::from_secs(60));
+        assert!(player.check_buddy_warp_cooldown(time).is_err());
+        assert!(player
+            .check_buddy_warp_cooldown(time + Duration::from_secs(59))
+            .is_err());
+        assert!(player
+            .check_buddy_warp_cooldown(time + Duration::from_secs(60))
+            .is_ok());
+        assert_eq!(
+            player.get_buddy_warp_time(),
+            Some(time + Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_style_validation() {
+        assert!(PlayerStyle::default().validate().is_ok());
+
+        let male = PlayerStyle {
+            gender: 2,
+            face_style: 6,
+            hair_style: 25,
+            ..Default::default()
+        };
+        assert!(male.validate().is_ok());
+
+        // face and hair styles have to match the gender
+        let mismatched = PlayerStyle {
+            face_style: 6,
+            ..Default::default()
+        };
+        assert!(mismatched.validate().is_err());
+        let bad_gender = PlayerStyle {
+            gender: 3,
+            ..Default::default()
+        };
+        assert!(bad_gender.validate().is_err());
+        let bad_height = PlayerStyle {
+            height: 5,
+            ..Default::default()
+        };
+        assert!(bad_height.validate().is_err());
+    }
+
+    #[test]
+    fn test_refresh_buddy_info() {
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        let mut buddy = PlayerBuilder::new(2).pc_id(2).build();
+        buddy.style = Some(PlayerStyle::default());
+        buddy.first_name = "Old".to_string();
+        player.add_buddy(BuddyListEntry::new(&buddy)).unwrap();
+
+        // unchanged, or not a buddy at all
+        assert!(!player.refresh_buddy_info(BuddyListEntry::new(&buddy)));
+        let mut stranger = PlayerBuilder::new(3).pc_id(3).build();
+        stranger.style = Some(PlayerStyle::default());
+        assert!(!player.refresh_buddy_info(BuddyListEntry::new(&stranger)));
+
+        // renamed buddies keep their chat and block settings
+        player.block_player(2).unwrap();
+        buddy.first_name = "New".to_string();
+        buddy.flags.name_check = PlayerNameStatus::Pending;
+        assert!(player.refresh_buddy_info(BuddyListEntry::new(&buddy)));
+        let info = &player.get_all_buddy_info()[0];
+        assert_eq!(info.first_name, "New");
+        assert_eq!(info.name_check, PlayerNameStatus::Pending);
+        assert!(info.blocked);
+    }
+
+    #[test]
+    fn test_ignore_player() {
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        assert!(player.ignore_player(1).is_err());
+        assert!(player.ignore_player(2).unwrap());
+        assert!(!player.ignore_player(2).unwrap());
+        assert!(player.is_ignoring(2));
+        assert!(!player.is_ignoring(3));
+
+        // doesn't need a buddy list slot
+        assert_eq!(player.get_num_buddies(), 0);
+
+        for pc_uid in 3..(MAX_IGNORED_PLAYERS as i64 + 2) {
+            player.ignore_player(pc_uid).unwrap();
+        }
+        assert!(player
+            .ignore_player(MAX_IGNORED_PLAYERS as i64 + 2)
+            .is_err());
+        assert!(player.unignore_player(2));
+        assert!(!player.unignore_player(2));
+        assert!(player
+            .ignore_player(MAX_IGNORED_PLAYERS as i64 + 2)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_exit_countdown() {
+        let time = SystemTime::now();
+        let default_countdown = Duration::from_secs(config_get().shard.exit_countdown.get());
+        let safe_zones = [MapZone {
+            map_num: 0,
+            bounds: Some([0, 0, 1000, 1000]),
+        }];
+        let mut player = PlayerBuilder::new(1)
+            .pc_id(1)
+            .position(Position {
+                x: 500,
+                y: 500,
+                z: 0,
+            })
+            .build();
+        assert_eq!(player.get_exit_countdown(time, &[]), default_countdown);
+        assert_eq!(player.get_exit_countdown(time, &safe_zones), Duration::ZERO);
+
+        player.in_combat = true;
+        assert_eq!(
+            player.get_exit_countdown(time, &safe_zones),
+            default_countdown
+        );
+        player.in_combat = false;
+
+        // fighting keeps the countdown on for a little while after
+        player.mark_combat(time);
+        assert_eq!(
+            player.get_exit_countdown(time, &safe_zones),
+            default_countdown
+        );
+        let later = time + Duration::from_secs(SAFE_EXIT_COMBAT_COOLDOWN_SECS);
+        assert_eq!(
+            player.get_exit_countdown(later, &safe_zones),
+            Duration::ZERO
+        );
+
+        player.set_position(Position {
+            x: 2000,
+            y: 500,
+            z: 0,
+        });
+        assert_eq!(
+            player.get_exit_countdown(later, &safe_zones),
+            default_countdown
+        );
+    }
+
+    #[test]
+    fn test_exit_cancel() {
+        let time = SystemTime::now();
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        assert!(!player.cancel_exit());
+        assert!(!player.take_exit_cancelled());
+
+        let deadline = time + Duration::from_secs(60);
+        assert_eq!(player.start_exit(deadline), deadline);
+        // asking again doesn't restart the countdown
+        assert_eq!(
+            player.start_exit(deadline + Duration::from_secs(5)),
+            deadline
+        );
+        assert_eq!(player.get_exit_deadline(), Some(deadline));
+
+        assert!(player.cancel_exit());
+        assert_eq!(player.get_exit_deadline(), None);
+        assert!(player.take_exit_cancelled());
+        assert!(!player.take_exit_cancelled());
+
+        // getting hit cancels too, even for no damage
+        player.start_exit(deadline);
+        player.take_damage(0, EntityID::NPC(1));
+        assert_eq!(player.get_exit_deadline(), None);
+        assert!(player.take_exit_cancelled());
+    }
+
+    #[test]
+    fn test_skill_cooldowns() {
+        const SKILL_ID: i16 = 5;
+        const COOLDOWN: Duration = Duration::from_secs(10);
+
+        let mut player = PlayerBuilder::new(1).build();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert!(player.is_skill_ready(SKILL_ID, now));
+        player.start_skill_cooldown(SKILL_ID, COOLDOWN, now);
+        assert_eq!(player.get_skill_ready_time(SKILL_ID), Some(now + COOLDOWN));
+
+        // ready a little before the boundary, not any earlier
+        let ready_time = now + COOLDOWN;
+        let grace = Duration::from_millis(SKILL_COOLDOWN_GRACE_MS);
+        assert!(!player.is_skill_ready(SKILL_ID, ready_time - grace - Duration::from_millis(1)));
+        assert!(player.is_skill_ready(SKILL_ID, ready_time - grace));
+        assert!(player.is_skill_ready(SKILL_ID, ready_time));
+        // an early use doesn't move the next cooldown up
+        player.start_skill_cooldown(SKILL_ID, COOLDOWN, ready_time - grace);
+        assert_eq!(
+            player.get_skill_ready_time(SKILL_ID),
+            Some(ready_time + COOLDOWN)
+        );
+        // other skills have their own cooldowns
+        assert!(player.is_skill_ready(SKILL_ID + 1, now));
+
+        // changing maps or channels keeps the same player around
+        player.instance_id = InstanceID {
+            channel_num: 2,
+            map_num: 7,
+            instance_num: Some(1),
+        };
+        assert!(!player.is_skill_ready(SKILL_ID, now));
+
+        // skills without a cooldown never wait
+        player.start_skill_cooldown(SKILL_ID, Duration::ZERO, now);
+        assert!(player.is_skill_ready(SKILL_ID, now));
+        assert_eq!(player.get_skill_ready_time(SKILL_ID), None);
+    }
+
+    #[test]
+    fn test_rare_sell_confirm() {
+        const WINDOW: Duration = Duration::from_secs(10);
+
+        let mut player = PlayerBuilder::new(1).build();
+        let item = Item::new(ItemType::General, 5);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        // the first request only asks; the second goes through and is used up
+        assert!(!player.confirm_rare_sell(3, item, now, WINDOW));
+        assert!(player.confirm_rare_sell(3, item, now + WINDOW, WINDOW));
+        assert!(!player.confirm_rare_sell(3, item, now + WINDOW, WINDOW));
+
+        // too late, so the late request starts a new wait
+        let later = now + Duration::from_secs(100);
+        assert!(!player.confirm_rare_sell(
+            3,
+            item,
+            later + WINDOW + Duration::from_millis(1),
+            WINDOW
+        ));
+        assert!(player.confirm_rare_sell(3, item, later + WINDOW * 2, WINDOW));
+
+        // a different slot or stack doesn't confirm it
+        assert!(!player.confirm_rare_sell(3, item, later, WINDOW));
+        assert!(!player.confirm_rare_sell(4, item, later, WINDOW));
+        let mut stack = item;
+        stack.quantity = 2;
+        assert!(!player.confirm_rare_sell(4, stack, later, WINDOW));
+        assert!(player.confirm_rare_sell(4, stack, later, WINDOW));
+    }
+
+    #[test]
+    fn test_death_fm_penalty() {
+        const LEVEL_UP_FM: u32 = 1000;
+
+        let mut player = Player::new_mock(1, 10, 0, 500);
+        assert_eq!(player.take_death_fm_penalty(100, LEVEL_UP_FM), 100);
+        assert_eq!(player.get_fusion_matter(), 400);
+        // can't go below zero
+        assert_eq!(player.take_death_fm_penalty(1000, LEVEL_UP_FM), 400);
+        assert_eq!(player.get_fusion_matter(), 0);
+        assert_eq!(player.take_death_fm_penalty(100, LEVEL_UP_FM), 0);
+
+        // enough to level up stays enough to level up
+        let mut player = Player::new_mock(1, 10, 0, 1050);
+        assert_eq!(player.take_death_fm_penalty(100, LEVEL_UP_FM), 50);
+        assert_eq!(player.get_fusion_matter(), LEVEL_UP_FM);
+        assert_eq!(player.take_death_fm_penalty(100, LEVEL_UP_FM), 0);
+        assert_eq!(player.get_fusion_matter(), LEVEL_UP_FM);
+    }
+
+    #[test]
+    fn test_death_fm_exemptions() {
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+        player.instance_id.map_num = 5;
+        assert!(!player.is_death_fm_exempt(false, &[]));
+        assert!(!player.is_death_fm_exempt(false, &[4, 6]));
+
+        assert!(player.is_death_fm_exempt(true, &[]));
+        assert!(player.is_death_fm_exempt(false, &[4, 5]));
+
+        player.invulnerable = true;
+        assert!(player.is_death_fm_exempt(false, &[]));
+    }
+
+    #[test]
+    fn test_change_guide() {
+        const FM_COST: u32 = 100;
+
+        let mut player = Player::new_mock(1, 10, 0, 150);
+        assert_eq!(player.get_guide(), PlayerGuide::Computress);
+        assert!(player
+            .change_guide(PlayerGuide::Computress, FM_COST)
+            .is_err());
+
+        // first one's free
+        assert_eq!(player.change_guide(PlayerGuide::Ben, FM_COST).unwrap(), 1);
+        assert_eq!(player.get_guide(), PlayerGuide::Ben);
+        assert_eq!(player.get_fusion_matter(), 150);
+
+        assert_eq!(player.change_guide(PlayerGuide::Mojo, FM_COST).unwrap(), 2);
+        assert_eq!(player.get_fusion_matter(), 50);
+
+        // can't afford another, and nothing changes
+        assert!(player.change_guide(PlayerGuide::Edd, FM_COST).is_err());
+        assert_eq!(player.get_guide(), PlayerGuide::Mojo);
+        assert_eq!(player.get_total_guides(), 2);
+        assert_eq!(player.get_fusion_matter(), 50);
     }
 }
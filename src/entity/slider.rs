@@ -1,7 +1,5 @@
 use std::{any::Any, time::SystemTime};
 
-use rand::rngs::ThreadRng;
-
 use crate::{
     chunk::{ChunkCoords, InstanceID},
     defines::TYPE_SLIDER,
@@ -16,6 +14,7 @@ use crate::{
         ClientMap, FFClient,
     },
     path::Path,
+    rng::GameRng,
     state::ShardServerState,
     Position,
 };
@@ -118,15 +117,17 @@ impl Entity for Slider {
         _time: &SystemTime,
         clients: &mut ClientMap,
         state: &mut ShardServerState,
-        _rng: &mut ThreadRng,
+        _rng: &mut GameRng,
     ) {
         if let Some(path) = self.path.as_mut() {
             let speed = path.get_speed();
             path.tick(&mut self.position);
-            let chunk_pos = self.get_chunk_coords();
-            state
-                .entity_map
-                .update(self.get_id(), Some(chunk_pos), Some(clients));
+            state.entity_map.update_moved(
+                self.get_id(),
+                self.position,
+                self.instance_id,
+                Some(clients),
+            );
 
             let pkt = sP_FE2CL_TRANSPORTATION_MOVE {
                 eTT: TransportationType::Bus as i32,
@@ -115,6 +115,37 @@ ffenum!(RewardCategory, usize, {
     Racing = 4,
 });
 
+ffenum!(CurrencySource, i32, {
+    Unknown = 0,
+    MobKill = 1,
+    MissionReward = 2,
+    VendorBuy = 3,
+    VendorSell = 4,
+    Trade = 5,
+    GM = 6,
+    Egg = 7,
+    // 8 is kept for racing rewards, which this server doesn't hand out yet
+    Transport = 9,
+    Warp = 10,
+    Nano = 11,
+    Combine = 12,
+    PvP = 13,
+    Redeem = 14,
+    Death = 15,
+    Guide = 16,
+});
+
+ffenum!(TransactionKind, i32, {
+    Trade = 0,
+    VendorBuy = 1,
+    VendorSell = 2,
+});
+
+// where a player report came from; /block in the client is the only way in so far
+ffenum!(ReportCategory, i32, {
+    Block = 0,
+});
+
 // eItemLocation.cs
 ffenum!(ItemLocation, i32, 4, {
     Equip = 0,  /*eIL_Equip*/
@@ -184,3 +215,39 @@ ffenum!(PCRegenType, i32, 7, {
     HereByPhoenixItem = 6,  /*ePCRegenType_HereByPhoenixItem*/
     /* ePCRegenType__End */
 });
+
+ffenum!(SkillType, i32, {
+    HealHP = 2,            /*EST_HEAL_HP*/
+    HealStamina = 6,       /*EST_HEAL_STAMINA*/
+    Jump = 10,             /*EST_JUMP*/
+    Run = 11,              /*EST_RUN*/
+    Stealth = 12,          /*EST_STEALTH*/
+    Swim = 13,             /*EST_SWIM*/
+    MinimapEnemy = 14,     /*EST_MINIMAPENEMY*/
+    MinimapTresure = 15,   /*EST_MINIMAPTRESURE*/
+    Phoenix = 16,          /*EST_PHOENIX*/
+    ProtectBattery = 17,   /*EST_PROTECTBATTERY*/
+    ProtectInfection = 18, /*EST_PROTECTINFECTION*/
+    RewardBlob = 19,       /*EST_REWARDBLOB*/
+    RewardCash = 20,       /*EST_REWARDCASH*/
+    Freedom = 25,          /*EST_FREEDOM*/
+    NanoStimpak = 33,      /*EST_NANOSTIMPAK*/
+    ReturnHomeHeal = 34,   /*EST_RETURNHOMEHEAL*/
+});
+
+ffenum!(TimeBuffUpdate, i32, {
+    None = 0,  /*ETBU_NONE*/
+    Add = 1,   /*ETBU_ADD*/
+    Del = 2,   /*ETBU_DEL*/
+    Clear = 3, /*ETBU_CLEAR*/
+});
+
+ffenum!(TimeBuffType, i32, {
+    None = 0,       /*ETBT_NONE*/
+    Nano = 1,       /*ETBT_NANO*/
+    GroupNano = 2,  /*ETBT_GROUPNANO*/
+    Shiny = 3,      /*ETBT_SHINY*/
+    LandEffect = 4, /*ETBT_LANDEFFECT*/
+    Item = 5,       /*ETBT_ITEM*/
+    CashItem = 6,   /*ETBT_CASHITEM*/
+});
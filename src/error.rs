@@ -286,4 +286,12 @@ pub mod codes {
         InstanceLeft = 12,
         InventoryFull = 13,
     }
+
+    #[repr(i32)]
+    #[derive(PartialEq, Eq, Hash, TryFromPrimitive, Clone, Copy, Debug)]
+    #[num_enum(error_type(name = FFError, constructor = FFError::from_enum_err))]
+    pub enum ConfiscateItemErr {
+        Generic = 0,
+        TargetOnline = 1,
+    }
 }
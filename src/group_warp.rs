@@ -0,0 +1,470 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use uuid::Uuid;
+
+use crate::{
+    defines::RANGE_GROUP_PARTICIPATE,
+    entity::{Combatant, Entity, EntityID, Player},
+    enums::ItemLocation,
+    error::{log_if_failed, FFError, FFResult, Severity},
+    helpers,
+    net::{
+        packet::{PacketID::*, *},
+        ClientMap,
+    },
+    state::ShardServerState,
+    tabledata::WarpData,
+    Position,
+};
+
+// Where a group warp takes everyone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupWarpDest {
+    pub pos: Position,
+    pub map_num: u32,
+    pub instance_num: Option<u32>,
+    pub to_past: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupWarpOutcome {
+    pub accepted: Vec<i32>,
+    pub declined: Vec<i32>,
+    pub timed_out: Vec<i32>,
+}
+
+// A group warp the initiator already went on, waiting for the rest of the group to agree
+// to come along. Members are only brought along once everyone has answered or time is up
+#[derive(Debug, Clone)]
+pub struct PendingGroupWarp {
+    group_id: Uuid,
+    initiator_pc_id: i32,
+    dest: GroupWarpDest,
+    responses: HashMap<i32, Option<bool>>,
+    deadline: SystemTime,
+}
+impl PendingGroupWarp {
+    pub fn new(
+        group_id: Uuid,
+        initiator_pc_id: i32,
+        member_pc_ids: &[i32],
+        dest: GroupWarpDest,
+        deadline: SystemTime,
+    ) -> Self {
+        Self {
+            group_id,
+            initiator_pc_id,
+            dest,
+            responses: member_pc_ids.iter().map(|pc_id| (*pc_id, None)).collect(),
+            deadline,
+        }
+    }
+
+    pub fn get_group_id(&self) -> Uuid {
+        self.group_id
+    }
+
+    pub fn get_initiator_id(&self) -> i32 {
+        self.initiator_pc_id
+    }
+
+    pub fn get_dest(&self) -> GroupWarpDest {
+        self.dest
+    }
+
+    pub fn is_waiting_on(&self, pc_id: i32) -> bool {
+        matches!(self.responses.get(&pc_id), Some(None))
+    }
+
+    pub fn respond(&mut self, pc_id: i32, accept: bool) -> FFResult<()> {
+        match self.responses.get_mut(&pc_id) {
+            Some(response @ None) => {
+                *response = Some(accept);
+                Ok(())
+            }
+            Some(Some(_)) => Err(FFError::build(
+                Severity::Warning,
+                format!("Player {} already answered the group warp", pc_id),
+            )),
+            None => Err(FFError::build(
+                Severity::Warning,
+                format!("Player {} wasn't asked to join the group warp", pc_id),
+            )),
+        }
+    }
+
+    pub fn is_settled(&self, time: SystemTime) -> bool {
+        time >= self.deadline || self.responses.values().all(|r| r.is_some())
+    }
+
+    pub fn get_outcome(&self) -> GroupWarpOutcome {
+        let mut outcome = GroupWarpOutcome::default();
+        for (pc_id, response) in &self.responses {
+            match response {
+                Some(true) => outcome.accepted.push(*pc_id),
+                Some(false) => outcome.declined.push(*pc_id),
+                None => outcome.timed_out.push(*pc_id),
+            }
+        }
+        outcome.accepted.sort();
+        outcome.declined.sort();
+        outcome.timed_out.sort();
+        outcome
+    }
+}
+
+// Reasons a member can't be brought along no matter what the warp is
+pub fn get_member_busy_reason(member: &Player) -> Option<&'static str> {
+    if member.is_dead() {
+        Some("dead")
+    } else if member.trade_id.is_some() {
+        Some("trading")
+    } else if member.is_riding_skyway() {
+        Some("riding the skyway")
+    } else {
+        None
+    }
+}
+
+pub fn get_group_warp_skip_reason(
+    member: &Player,
+    warp_data: &WarpData,
+    check_requirements: bool,
+) -> Option<&'static str> {
+    get_member_busy_reason(member).or_else(|| {
+        if check_requirements && member.get_level() < warp_data.min_level {
+            Some("level too low")
+        } else {
+            None
+        }
+    })
+}
+
+// Members that can't come along, with why
+pub type SkippedMembers = Vec<(i32, &'static str)>;
+
+// Group members near the initiator, split into who can come along and who can't (with why).
+// Nobody is told anything here since the initiator's own warp can still fail
+pub fn gather_group_warp_members(
+    pc_id: i32,
+    group_id: Uuid,
+    warp_data: &WarpData,
+    check_requirements: bool,
+    state: &ShardServerState,
+) -> FFResult<(Vec<i32>, SkippedMembers)> {
+    let player = state.get_player(pc_id)?;
+    let group = state.groups.get(&group_id).ok_or(FFError::build(
+        Severity::Warning,
+        format!("Group {} not found", group_id),
+    ))?;
+
+    let mut coming = Vec::new();
+    let mut skipped = Vec::new();
+    for member_id in group.get_member_ids() {
+        let EntityID::Player(member_pc_id) = *member_id else {
+            continue;
+        };
+        if member_pc_id == pc_id {
+            continue;
+        }
+
+        let member = state.get_player(member_pc_id)?;
+        if member.instance_id != player.instance_id
+            || member.get_position().distance_to(&player.get_position()) > RANGE_GROUP_PARTICIPATE
+        {
+            continue;
+        }
+
+        match get_group_warp_skip_reason(member, warp_data, check_requirements) {
+            None => coming.push(member_pc_id),
+            Some(reason) => skipped.push((member_pc_id, reason)),
+        }
+    }
+    Ok((coming, skipped))
+}
+
+// Once the initiator has warped, tells the members who couldn't come and the initiator
+pub fn notify_skipped_members(
+    pc_id: i32,
+    skipped: &[(i32, &'static str)],
+    clients: &mut ClientMap,
+    state: &ShardServerState,
+) {
+    for (member_pc_id, reason) in skipped {
+        let Ok(member) = state.get_player(*member_pc_id) else {
+            continue;
+        };
+        if let Some(client) = member.get_client(clients) {
+            log_if_failed(helpers::send_system_message(
+                client,
+                &format!("Your group warped without you ({})", reason),
+            ));
+        }
+        let msg = format!("{} was left behind ({})", member, reason);
+        if let Some(client) = state
+            .get_player(pc_id)
+            .ok()
+            .and_then(|player| player.get_client(clients))
+        {
+            log_if_failed(helpers::send_system_message(client, &msg));
+        }
+    }
+}
+
+// Brings one group member along to where the initiator went
+pub fn warp_group_member(
+    pc_id: i32,
+    dest: &GroupWarpDest,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    helpers::warp_player(
+        pc_id,
+        dest.pos,
+        dest.map_num,
+        dest.instance_num,
+        clients,
+        state,
+    )?;
+
+    let player = state.get_player_mut(pc_id)?;
+    if dest.to_past {
+        if let Some(client) = player.get_client(clients) {
+            helpers::do_past_transition(player, client);
+        }
+    }
+
+    // generic NPC warp packet for players that are warping along
+    let player = state.get_player(pc_id)?;
+    let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
+        iX: player.get_position().x,
+        iY: player.get_position().y,
+        iZ: player.get_position().z,
+        eIL: ItemLocation::end(),
+        iItemSlotNum: unused!(),
+        Item: None.into(),
+        iCandy: player.get_taros() as i32,
+    };
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp));
+    }
+    Ok(())
+}
+
+// Brings along the members who accepted and tells the initiator who stayed behind
+pub fn finish_group_warp(
+    warp: PendingGroupWarp,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) {
+    let outcome = warp.get_outcome();
+    let mut left_behind = Vec::new();
+    for pc_id in outcome.accepted {
+        let Ok(member) = state.get_player(pc_id) else {
+            continue;
+        };
+        let reason = if member.group_id != Some(warp.group_id) {
+            Some("left the group")
+        } else {
+            get_member_busy_reason(member)
+        };
+        if let Some(reason) = reason {
+            left_behind.push(format!("{} ({})", member, reason));
+            if let Some(client) = member.get_client(clients) {
+                log_if_failed(helpers::send_system_message(
+                    client,
+                    &format!("Your group warped without you ({})", reason),
+                ));
+            }
+            continue;
+        }
+        log_if_failed(warp_group_member(pc_id, &warp.dest, clients, state));
+    }
+
+    for pc_id in outcome.declined {
+        if let Ok(member) = state.get_player(pc_id) {
+            left_behind.push(format!("{} (declined)", member));
+        }
+    }
+    for pc_id in outcome.timed_out {
+        let Ok(member) = state.get_player(pc_id) else {
+            continue;
+        };
+        left_behind.push(format!("{} (didn't answer)", member));
+        if let Some(client) = member.get_client(clients) {
+            log_if_failed(helpers::send_system_message(
+                client,
+                "Your group warped without you (didn't answer in time)",
+            ));
+        }
+    }
+
+    if left_behind.is_empty() {
+        return;
+    }
+    if let Ok(initiator) = state.get_player(warp.initiator_pc_id) {
+        if let Some(client) = initiator.get_client(clients) {
+            log_if_failed(helpers::send_system_message(
+                client,
+                &format!("Left behind: {}", left_behind.join(", ")),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    use crate::{
+        chunk::TickMode,
+        entity::Group,
+        enums::ItemType,
+        test_utils::{FakeClients, PlayerBuilder},
+    };
+
+    fn pending_warp(deadline: SystemTime) -> PendingGroupWarp {
+        let dest = GroupWarpDest {
+            pos: Position::default(),
+            map_num: 0,
+            instance_num: Some(1),
+            to_past: false,
+        };
+        PendingGroupWarp::new(Uuid::new_v4(), 1, &[2, 3, 4], dest, deadline)
+    }
+
+    #[test]
+    fn test_group_warp_timeout() {
+        let now = SystemTime::now();
+        let deadline = now + Duration::from_secs(15);
+        let warp = pending_warp(deadline);
+        assert!(!warp.is_settled(now));
+        assert!(warp.is_waiting_on(2));
+        assert!(!warp.is_waiting_on(1));
+
+        // nobody answered, so nobody comes along
+        assert!(warp.is_settled(deadline));
+        assert_eq!(
+            warp.get_outcome(),
+            GroupWarpOutcome {
+                accepted: vec![],
+                declined: vec![],
+                timed_out: vec![2, 3, 4],
+            }
+        );
+    }
+
+    #[test]
+    fn test_group_warp_partial_consent() {
+        let now = SystemTime::now();
+        let deadline = now + Duration::from_secs(15);
+        let mut warp = pending_warp(deadline);
+        warp.respond(2, true).unwrap();
+        warp.respond(4, false).unwrap();
+        // answers can't be changed, and only asked members can answer
+        assert!(warp.respond(2, false).is_err());
+        assert!(warp.respond(5, true).is_err());
+        assert!(!warp.is_waiting_on(2));
+        assert!(!warp.is_settled(now));
+
+        assert!(warp.is_settled(deadline));
+        assert_eq!(
+            warp.get_outcome(),
+            GroupWarpOutcome {
+                accepted: vec![2],
+                declined: vec![4],
+                timed_out: vec![3],
+            }
+        );
+
+        // no need to wait once everyone has answered
+        warp.respond(3, true).unwrap();
+        assert!(warp.is_settled(now));
+        assert_eq!(warp.get_outcome().accepted, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_group_warp_left_behind() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let near = Position { x: 500, y: 0, z: 0 };
+        let far_away = Position {
+            x: 50000,
+            y: 0,
+            z: 0,
+        };
+
+        let group_id = Uuid::new_v4();
+        let mut group = Group::new(EntityID::Player(1));
+        // (pc ID, position, level, hp)
+        let players = [
+            (1, Position::default(), 10, 1000),
+            (2, near, 10, 1000),
+            (3, far_away, 10, 1000),
+            (4, near, 1, 1000),
+        ];
+        let mut keys = HashMap::new();
+        for (pc_id, position, level, hp) in players {
+            let key = fake_clients.add_game_client(Some(pc_id));
+            keys.insert(pc_id, key);
+            let mut player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .position(position)
+                .level(level)
+                .hp(hp)
+                .build();
+            player.group_id = Some(group_id);
+            if pc_id != 1 {
+                group.add_member(EntityID::Player(pc_id)).unwrap();
+            }
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        state.groups.insert(group_id, group);
+
+        let warp_data = WarpData {
+            pos: Position::default(),
+            npc_type: 1,
+            is_instance: true,
+            is_group_warp: true,
+            map_num: 0,
+            min_level: 5,
+            req_task: None,
+            req_item: Some((ItemType::General, 1)),
+            req_item_consumed: None,
+            cost: 0,
+        };
+        let (coming, skipped) =
+            gather_group_warp_members(1, group_id, &warp_data, true, &state).unwrap();
+        // far away members aren't part of it
+        assert_eq!(coming, vec![2]);
+        assert_eq!(skipped, vec![(4, "level too low")]);
+        // nobody hears about it until the initiator's warp goes through
+        for key in keys.values() {
+            assert!(fake_clients.take_sent(*key).is_empty());
+        }
+
+        // only the initiator's own requirements matter unless configured otherwise
+        let (mut coming, skipped_unchecked) =
+            gather_group_warp_members(1, group_id, &warp_data, false, &state).unwrap();
+        // group members come in no particular order
+        coming.sort();
+        assert_eq!(coming, vec![2, 4]);
+        assert!(skipped_unchecked.is_empty());
+
+        notify_skipped_members(1, &skipped, &mut fake_clients.get_map(keys[&1]), &state);
+        assert_eq!(
+            fake_clients.take_sent(keys[&1]),
+            vec![P_FE2CL_PC_MOTD_LOGIN]
+        );
+        assert_eq!(
+            fake_clients.take_sent(keys[&4]),
+            vec![P_FE2CL_PC_MOTD_LOGIN]
+        );
+        for pc_id in [2, 3] {
+            assert!(fake_clients.take_sent(keys[&pc_id]).is_empty());
+        }
+    }
+}
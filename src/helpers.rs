@@ -1,17 +1,28 @@
-use rand::{rngs::ThreadRng, Rng};
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+
+use rand::Rng;
 use uuid::Uuid;
 
 use crate::{
-    entity::{Combatant, Entity, EntityID, Player},
+    chunk::InstanceID,
+    config::config_get,
+    defines::*,
+    entity::{CancelledOffers, Combatant, Entity, EntityID, Player, PlayerSearchQuery},
     enums::*,
     error::*,
+    item::{Item, ItemOrigin, RedeemCode},
     net::{
         packet::{PacketID::*, *},
         ClientMap, FFClient,
     },
+    perms::{check_gm_perms, check_gm_perms_on_other},
+    rng::GameRng,
     state::ShardServerState,
     tabledata::tdata_get,
-    util,
+    util, Position,
 };
 
 pub fn broadcast_state(
@@ -31,6 +42,194 @@ pub fn broadcast_state(
         });
 }
 
+// Tells a combatant (if it's a player) and everyone around it which conditions it has now.
+// Takes the combatant separately from the state since ticks run on a copy
+pub fn broadcast_conditions(
+    combatant: &dyn Combatant,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) {
+    let bcast = send_own_conditions(combatant, clients);
+    state
+        .entity_map
+        .for_each_around(combatant.get_id(), clients, |client| {
+            client.send_packet(P_FE2CL_CHAR_TIME_BUFF_TIME_OUT, &bcast)
+        });
+}
+
+// Returns the packet for everyone around
+fn send_own_conditions(
+    combatant: &dyn Combatant,
+    clients: &mut ClientMap,
+) -> sP_FE2CL_CHAR_TIME_BUFF_TIME_OUT {
+    let bcast = sP_FE2CL_CHAR_TIME_BUFF_TIME_OUT {
+        eCT: combatant.get_char_type() as i32,
+        iID: match combatant.get_id() {
+            EntityID::Player(id) | EntityID::NPC(id) => id,
+            _ => unreachable!(),
+        },
+        iConditionBitFlag: combatant.get_condition_bit_flag(),
+    };
+    if let Some(client) = combatant.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_CHAR_TIME_BUFF_TIME_OUT, &bcast));
+    }
+    bcast
+}
+
+// Puts a timed condition on a combatant, telling clients if it's new.
+// Returns whether the bit just turned on
+pub fn apply_condition(
+    target_id: EntityID,
+    bit: u32,
+    duration: Duration,
+    source: EntityID,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<bool> {
+    update_conditions(target_id, clients, state, |target| {
+        target.apply_condition(bit, duration, source, SystemTime::now())
+    })
+}
+
+// Returns whether the bit turned off
+pub fn clear_condition(
+    target_id: EntityID,
+    bit: u32,
+    source: Option<EntityID>,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<bool> {
+    update_conditions(target_id, clients, state, |target| {
+        target.clear_condition(bit, source)
+    })
+}
+
+fn update_conditions(
+    target_id: EntityID,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+    f: impl FnOnce(&mut dyn Combatant) -> bool,
+) -> FFResult<bool> {
+    let target = state.get_combatant_mut(target_id)?;
+    if !f(target) {
+        return Ok(false);
+    }
+    let bcast = send_own_conditions(target, clients);
+    state
+        .entity_map
+        .for_each_around(target_id, clients, |client| {
+            client.send_packet(P_FE2CL_CHAR_TIME_BUFF_TIME_OUT, &bcast)
+        });
+    Ok(true)
+}
+
+// Runs out expired conditions as part of the combatant's own tick
+pub fn tick_conditions(
+    combatant: &mut dyn Combatant,
+    time: &SystemTime,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) {
+    if !combatant
+        .get_conditions_mut()
+        .take_expired(*time)
+        .is_empty()
+    {
+        broadcast_conditions(combatant, clients, state);
+    }
+}
+
+// Takes the player off their vehicle if they're riding one, telling them and everyone around.
+// Returns whether they were riding
+pub fn force_dismount(
+    pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<bool> {
+    let player = state.get_player_mut(pc_id)?;
+    player.stop_vehicle_boost();
+    if player.vehicle_speed.take().is_none() {
+        return Ok(false);
+    }
+
+    let player_sbf = player.get_state_bit_flag();
+    if let Some(client) = player.get_client(clients) {
+        let pkt = sP_FE2CL_PC_VEHICLE_OFF_SUCC { UNUSED: unused!() };
+        log_if_failed(client.send_packet(P_FE2CL_PC_VEHICLE_OFF_SUCC, &pkt));
+    }
+    broadcast_state(pc_id, player_sbf, clients, state);
+    Ok(true)
+}
+
+// Re-sends the player to everyone around them so name and appearance changes show up
+pub fn broadcast_appearance(
+    pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let player = state.get_player(pc_id)?.clone();
+    state
+        .entity_map
+        .for_each_around(EntityID::Player(pc_id), clients, |client| {
+            player.send_exit(client)?;
+            player.send_enter(client)
+        });
+    Ok(())
+}
+
+// Uses up one of a general item from the player's main inventory, updating their client
+pub fn consume_item(player: &mut Player, item_id: i16, clients: &mut ClientMap) -> FFResult<()> {
+    let slot_num = player
+        .find_items(ItemLocation::Inven, |item| {
+            item.ty == ItemType::General && item.id == item_id
+        })
+        .first()
+        .copied()
+        .ok_or(FFError::build(
+            Severity::Warning,
+            format!("{} doesn't have item {}", player, item_id),
+        ))?;
+    let mut remaining = *player.get_item(ItemLocation::Inven, slot_num)?;
+    Item::split_items(&mut remaining, 1);
+    player.set_item(ItemLocation::Inven, slot_num, remaining)?;
+
+    if let Some(client) = player.get_client(clients) {
+        let pkt = sP_FE2CL_REP_PC_GIVE_ITEM_SUCC {
+            eIL: ItemLocation::Inven as i32,
+            iSlotNum: slot_num as i32,
+            Item: remaining.into(),
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_GIVE_ITEM_SUCC, &pkt));
+    }
+    Ok(())
+}
+
+pub fn broadcast_nano_active(pc_id: i32, clients: &mut ClientMap, state: &mut ShardServerState) {
+    let player = state.get_player(pc_id).unwrap();
+    let eid = player.get_id();
+
+    // make sure the entity map reflects the player's current position before broadcasting
+    // so that nobody who can see the player misses the change. players in transit aren't
+    // in a chunk; they get the correct nano in their enter packet once they're placed.
+    if !player.is_riding_skyway() && state.entity_map.get_entity_chunk(eid).is_some() {
+        let (pos, instance_id) = (player.get_position(), player.instance_id);
+        state
+            .entity_map
+            .update_moved(eid, pos, instance_id, Some(clients));
+    }
+
+    let player = state.get_player(pc_id).unwrap();
+    let bcast = sP_FE2CL_NANO_ACTIVE {
+        iPC_ID: pc_id,
+        Nano: player.get_active_nano().cloned().into(),
+        iConditionBitFlag: player.get_condition_bit_flag(),
+        eCSTB___Add: placeholder!(0),
+    };
+    state
+        .entity_map
+        .for_each_around(eid, clients, |c| c.send_packet(P_FE2CL_NANO_ACTIVE, &bcast));
+}
+
 pub fn broadcast_monkey(
     pc_id: i32,
     ride_type: RideType,
@@ -91,7 +290,7 @@ pub fn remove_group_member(
                     state.get_player_mut(*pc_id).unwrap().group_id = None;
                 }
                 EntityID::NPC(npc_id) => {
-                    state.get_npc_mut(*npc_id).unwrap().group_id = None;
+                    state.get_npc_mut(*npc_id).unwrap().leave_group();
                 }
                 _ => unreachable!(),
             }
@@ -102,6 +301,23 @@ pub fn remove_group_member(
         return Ok(());
     }
 
+    // escorts following a player who left stick with the rest of the group
+    if let EntityID::Player(_) = leaver_id {
+        let new_leader = group
+            .get_member_ids()
+            .iter()
+            .find(|eid| matches!(eid, EntityID::Player(_)))
+            .copied();
+        for eid in group.get_member_ids() {
+            if let EntityID::NPC(npc_id) = eid {
+                let npc = state.get_npc_mut(*npc_id).unwrap();
+                if npc.loose_follow == Some(leaver_id) {
+                    npc.loose_follow = new_leader;
+                }
+            }
+        }
+    }
+
     // notify clients of the group member removal
     let (pc_group_data, npc_group_data) = group.get_member_data(state);
     match leaver_id {
@@ -154,6 +370,322 @@ pub fn remove_group_member(
     Ok(())
 }
 
+// Takes an escort NPC out of its group, after which it walks back to where it was invited from
+pub fn kick_npc_from_group(
+    npc_id: i32,
+    state: &mut ShardServerState,
+    clients: &mut ClientMap,
+) -> FFResult<()> {
+    let npc = state.get_npc_mut(npc_id)?;
+    let group_id = npc
+        .group_id
+        .ok_or_else(|| FFError::build(Severity::Warning, format!("{} is not in a group", npc)))?;
+    npc.leave_group();
+    remove_group_member(EntityID::NPC(npc_id), group_id, state, clients)
+}
+
+// Moves the player to a new position and map. The player is taken out of the chunk map
+// until the client sends PC_LOADING_COMPLETE; the caller should send the warp response.
+pub fn warp_player(
+    pc_id: i32,
+    pos: Position,
+    map_num: u32,
+    instance_num: Option<u32>,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let player = state.get_player_mut(pc_id)?;
+    player.set_pre_warp();
+    player.set_position(pos);
+    player.instance_id = InstanceID {
+        channel_num: player.instance_id.channel_num,
+        map_num,
+        instance_num,
+    };
+
+    force_dismount(pc_id, clients, state)?;
+
+    // it needs to be done this way or the client will miss the PC/NPC_ENTER packets.
+    state
+        .entity_map
+        .update(EntityID::Player(pc_id), None, Some(clients));
+    Ok(())
+}
+
+fn broadcast_special_state(pc_id: i32, clients: &mut ClientMap, state: &mut ShardServerState) {
+    let player = state.get_player(pc_id).unwrap();
+    let bcast = sP_FE2CL_PC_SPECIAL_STATE_CHANGE {
+        iPC_ID: pc_id,
+        iReqSpecialStateFlag: unused!(),
+        iSpecialState: player.get_special_state_bit_flag(),
+    };
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_PC_SPECIAL_STATE_CHANGE, &bcast));
+    }
+    state
+        .entity_map
+        .for_each_around(EntityID::Player(pc_id), clients, |c| {
+            c.send_packet(P_FE2CL_PC_SPECIAL_STATE_CHANGE, &bcast)
+        });
+}
+
+// Attaches a GM to another player. The GM turns invisible and invulnerable,
+// the target stops receiving any packets about them, and they follow the target around.
+pub fn start_spectating(
+    pc_id: i32,
+    target_pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    if pc_id == target_pc_id {
+        return Err(FFError::build(
+            Severity::Warning,
+            "Can't spectate yourself".to_string(),
+        ));
+    }
+
+    let target_channel_num = state.get_player(target_pc_id)?.instance_id.channel_num;
+    let player = state.get_player_mut(pc_id)?;
+    if player.get_spectate_target().is_some() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{} is already spectating someone", player),
+        ));
+    }
+    if player.instance_id.channel_num != target_channel_num {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Target is on channel {}", target_channel_num),
+        ));
+    }
+    player.start_spectating(target_pc_id);
+
+    // the target might already be able to see us
+    let id = EntityID::Player(pc_id);
+    let target_id = EntityID::Player(target_pc_id);
+    if state.entity_map.get_around_entity(id).contains(&target_id) {
+        let target = state.get_player(target_pc_id).unwrap();
+        if let Some(target_client) = target.get_client(clients) {
+            let player = state.get_player(pc_id).unwrap();
+            log_if_failed(player.send_exit(target_client));
+        }
+    }
+    state.entity_map.set_hidden_from(id, Some(target_id));
+    broadcast_special_state(pc_id, clients, state);
+
+    follow_spectate_target(pc_id, target_pc_id, 0, clients, state)
+}
+
+// Detaches a spectating GM, restores their state, and sends them back where they started.
+// Returns the ID of the player that was being spectated, if any.
+pub fn stop_spectating(
+    pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<Option<i32>> {
+    let player = state.get_player_mut(pc_id)?;
+    let Some(spectate_state) = player.stop_spectating() else {
+        return Ok(None);
+    };
+
+    // move while still hidden so the target doesn't see us leave
+    let return_to = spectate_state.return_to;
+    warp_player(
+        pc_id,
+        return_to.position,
+        return_to.instance_id.map_num,
+        return_to.instance_id.instance_num,
+        clients,
+        state,
+    )?;
+    let player = state.get_player(pc_id).unwrap();
+    let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
+        iX: return_to.position.x,
+        iY: return_to.position.y,
+        iZ: return_to.position.z,
+        eIL: ItemLocation::end(),
+        iItemSlotNum: unused!(),
+        Item: unused!(),
+        iCandy: player.get_taros() as i32,
+    };
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp));
+    }
+
+    state
+        .entity_map
+        .set_hidden_from(EntityID::Player(pc_id), None);
+    broadcast_special_state(pc_id, clients, state);
+    Ok(Some(spectate_state.target_pc_id))
+}
+
+// Brings a spectating GM to their target if they've drifted further than max_distance away
+pub fn follow_spectate_target(
+    pc_id: i32,
+    target_pc_id: i32,
+    max_distance: u32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    if state
+        .entity_map
+        .get_entity_chunk(EntityID::Player(pc_id))
+        .is_none()
+    {
+        // still loading in from the last move
+        return Ok(());
+    }
+
+    let target = state.get_player(target_pc_id)?;
+    let target_pos = target.get_position();
+    let target_instance_id = target.instance_id;
+    let player = state.get_player_mut(pc_id)?;
+    if player.instance_id == target_instance_id
+        && player.get_position().distance_to(&target_pos) <= max_distance
+    {
+        return Ok(());
+    }
+
+    if player.instance_id != target_instance_id {
+        // different map or instance; needs a full warp
+        warp_player(
+            pc_id,
+            target_pos,
+            target_instance_id.map_num,
+            target_instance_id.instance_num,
+            clients,
+            state,
+        )?;
+        let player = state.get_player(pc_id).unwrap();
+        let resp = sP_FE2CL_REP_PC_WARP_USE_NPC_SUCC {
+            iX: target_pos.x,
+            iY: target_pos.y,
+            iZ: target_pos.z,
+            eIL: ItemLocation::end(),
+            iItemSlotNum: unused!(),
+            Item: unused!(),
+            iCandy: player.get_taros() as i32,
+        };
+        let client = player.get_client(clients).unwrap();
+        return client.send_packet(P_FE2CL_REP_PC_WARP_USE_NPC_SUCC, &resp);
+    }
+
+    player.set_position(target_pos);
+    let chunk_coords = player.get_chunk_coords();
+    let resp = sP_FE2CL_REP_PC_GOTO_SUCC {
+        iX: target_pos.x,
+        iY: target_pos.y,
+        iZ: target_pos.z,
+    };
+    let client = player.get_client(clients).unwrap();
+    log_if_failed(client.send_packet(P_FE2CL_REP_PC_GOTO_SUCC, &resp));
+    state
+        .entity_map
+        .update(EntityID::Player(pc_id), Some(chunk_coords), Some(clients));
+    Ok(())
+}
+
+const PAST_ARRIVAL_MSG: &str = "Welcome to the Past!";
+
+// Sends the player to the Past: sets the payzone flag, unlocks the configured
+// transport locations, drops any Future tasks, and announces the arrival.
+// Returns false if the player was already in the Past, in which case nothing happens.
+pub fn do_past_transition(player: &mut Player, client: &mut FFClient) -> bool {
+    if !player.set_future_done() {
+        return false;
+    }
+
+    // remove all active tasks
+    for task_id in player.mission_journal.get_current_task_ids() {
+        let task = player.mission_journal.remove_task(task_id).unwrap();
+        for item_id in &task.get_task_def().delete_qitems {
+            let qitem_slot = player.set_quest_item_count(*item_id, 0).unwrap();
+            // client doesn't automatically delete qitems clientside
+            let pkt = sP_FE2CL_REP_PC_ITEM_DELETE_SUCC {
+                eIL: ItemLocation::QInven as i32,
+                iSlotNum: qitem_slot as i32,
+            };
+            log_if_failed(client.send_packet(P_FE2CL_REP_PC_ITEM_DELETE_SUCC, &pkt));
+        }
+
+        let resp = sP_FE2CL_REP_PC_TASK_STOP_SUCC { iTaskNum: task_id };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_TASK_STOP_SUCC, &resp));
+    }
+
+    // refresh the client's transport location flags
+    let config = &config_get().shard;
+    let unlocks = config
+        .past_scamper_unlocks
+        .get()
+        .into_iter()
+        .map(|location_id| (TransportationType::Warp, location_id))
+        .chain(
+            config
+                .past_skyway_unlocks
+                .get()
+                .into_iter()
+                .map(|location_id| (TransportationType::Wyvern, location_id)),
+        );
+    for (transport_type, location_id) in unlocks {
+        let pkt = sP_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC {
+            eTT: transport_type as i32,
+            iLocationID: location_id,
+            iWarpLocationFlag: player.flags.scamper_flags.to_array()[0],
+            aWyvernLocationFlag: player.flags.skyway_flags.to_array(),
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC, &pkt));
+    }
+
+    send_announcement(client, PAST_ARRIVAL_MSG);
+    log(Severity::Info, &format!("{} traveled to the Past", player));
+    true
+}
+
+// Tells a player that offers they made were dropped without an answer
+pub fn send_cancelled_offers(
+    pc_id: i32,
+    cancelled: &CancelledOffers,
+    clients: &mut ClientMap,
+    state: &ShardServerState,
+) {
+    if cancelled.is_empty() {
+        return;
+    }
+    let Ok(player) = state.get_player(pc_id) else {
+        return;
+    };
+    let Some(client) = player.get_client(clients) else {
+        return;
+    };
+
+    if let Some(target_pc_id) = cancelled.group {
+        let pkt = sP_FE2CL_PC_GROUP_INVITE_REFUSE {
+            iID_To: target_pc_id,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_PC_GROUP_INVITE_REFUSE, &pkt));
+    }
+    if let Some(target_pc_id) = cancelled.trade {
+        let pkt = sP_FE2CL_REP_PC_TRADE_OFFER_REFUSAL {
+            iID_Request: target_pc_id,
+            iID_From: pc_id,
+            iID_To: target_pc_id,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_TRADE_OFFER_REFUSAL, &pkt));
+    }
+    if let Some(target_pc_uid) = cancelled.buddy {
+        // the target may have left the shard already
+        let target_pc_id = PlayerSearchQuery::ByUID(target_pc_uid)
+            .execute(state)
+            .unwrap_or(0);
+        let pkt = sP_FE2CL_REP_ACCEPT_MAKE_BUDDY_FAIL {
+            iBuddyID: target_pc_id,
+            iBuddyPCUID: target_pc_uid,
+            iErrorCode: ERROR_CODE_BUDDY_DENY,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_ACCEPT_MAKE_BUDDY_FAIL, &pkt));
+    }
+}
+
 pub fn send_system_message(client: &mut FFClient, msg: &str) -> FFResult<()> {
     let resp = sP_FE2CL_PC_MOTD_LOGIN {
         iType: unused!(),
@@ -162,24 +694,442 @@ pub fn send_system_message(client: &mut FFClient, msg: &str) -> FFResult<()> {
     client.send_packet(P_FE2CL_PC_MOTD_LOGIN, &resp)
 }
 
-pub fn give_defeat_rewards(
+// Tells a GM how laggy a player's connection is, as measured by live checks.
+// Negative round-trip times mean none were measured
+pub fn send_latency_info(
+    client: &mut FFClient,
+    target_name: &str,
+    avg_rtt: i32,
+    max_rtt: i32,
+) -> FFResult<()> {
+    let msg = if avg_rtt < 0 {
+        format!("No latency measured for {} yet", target_name)
+    } else {
+        format!(
+            "Latency for {}: {}ms average, {}ms max",
+            target_name, avg_rtt, max_rtt
+        )
+    };
+    send_system_message(client, &msg)
+}
+
+// Disconnects every player on the account, telling them why first.
+// Returns how many players were kicked.
+pub fn kick_banned_account(
+    acc_id: i64,
+    banned_until: SystemTime,
+    ban_reason: &str,
+    clients: &mut ClientMap,
+) -> usize {
+    // the ban may have been lifted in the meantime
+    let Ok(ban_duration) = banned_until.duration_since(SystemTime::now()) else {
+        return 0;
+    };
+    let ban_msg = format!(
+        "You have been banned for {}.\nReason: {}",
+        util::format_duration(ban_duration),
+        ban_reason
+    );
+
+    let mut kicked = 0;
+    for client in clients.get_all_gameclient() {
+        if client.get_account_id().ok() != Some(acc_id) {
+            continue;
+        }
+        let Ok(pc_id) = client.get_player_id() else {
+            continue;
+        };
+
+        let announce_pkt = sP_FE2CL_ANNOUNCE_MSG {
+            iAnnounceType: unused!(),
+            iDuringTime: MSG_BOX_DURATION_DEFAULT,
+            szAnnounceMsg: util::encode_utf16(&ban_msg),
+        };
+        log_if_failed(client.send_packet(P_FE2CL_ANNOUNCE_MSG, &announce_pkt));
+        let exit_pkt = sP_FE2CL_REP_PC_EXIT_SUCC {
+            iID: pc_id,
+            iExitCode: EXIT_CODE_REQ_BY_GM as i32,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_EXIT_SUCC, &exit_pkt));
+        client.disconnect();
+        kicked += 1;
+    }
+    kicked
+}
+
+// Kicks a freshly banned account here, then has the login server kick it everywhere else.
+// Returns how many players were kicked here
+pub fn kick_and_report_banned_account(
+    acc_id: i64,
+    banned_until: SystemTime,
+    ban_reason: &str,
+    clients: &mut ClientMap,
+) -> usize {
+    let kicked = kick_banned_account(acc_id, banned_until, ban_reason, clients);
+    if let Some(login_server) = clients.get_login_server() {
+        let pkt = sP_FE2LS_ACCOUNT_BANNED {
+            iAccountID: acc_id,
+            iBannedUntil: util::get_timestamp_sec(banned_until) as i64,
+            szBanReason: util::encode_utf16(ban_reason),
+        };
+        log_if_failed(login_server.send_packet(P_FE2LS_ACCOUNT_BANNED, &pkt));
+    }
+    kicked
+}
+
+// The login server passing along a ban made on another shard
+pub fn kick_account_for_login_server(
+    pkt: &sP_LS2FE_REQ_KICK_ACCOUNT,
+    clients: &mut ClientMap,
+) -> FFResult<()> {
+    let acc_id = pkt.iAccountID;
+    let banned_until = util::get_systime_from_sec(pkt.iBannedUntil as u64);
+    let ban_reason = util::parse_utf16(&pkt.szBanReason)?;
+    let kicked = kick_banned_account(acc_id, banned_until, &ban_reason, clients);
+    if kicked > 0 {
+        log(
+            Severity::Info,
+            &format!("Kicked {} player(s) on banned account {}", kicked, acc_id),
+        );
+    }
+    Ok(())
+}
+
+// Gives the player whatever the code is worth, telling them how it went.
+// `find_code` and `use_code` go to the database; `use_code` returns false if
+// the code can't be used (again) by this player
+pub fn redeem_code(
+    pc_id: i32,
+    code: &str,
+    find_code: impl FnOnce(&str) -> FFResult<Option<RedeemCode>>,
+    use_code: impl FnOnce(i64, &str) -> FFResult<bool>,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let player = state.get_player(pc_id)?;
+    let pc_uid = player.get_uid();
+    let client = player.get_client(clients).ok_or(FFError::build(
+        Severity::Warning,
+        format!("Player {} has no client", pc_id),
+    ))?;
+
+    let redeem_code = match find_code(code) {
+        Ok(Some(redeem_code)) => redeem_code,
+        Ok(None) => return send_system_message(client, "Invalid code"),
+        Err(e) => {
+            return send_system_message(client, &format!("Failed to redeem code: {}", e.get_msg()))
+        }
+    };
+    if redeem_code.is_expired() {
+        return send_system_message(client, "This code has expired");
+    }
+    if redeem_code.get_uses_left() == 0 {
+        return send_system_message(client, "This code has been fully redeemed");
+    }
+
+    // make sure the items fit before consuming a use
+    if player.get_free_slots(ItemLocation::Inven) < redeem_code.items.len() {
+        return send_system_message(
+            client,
+            &format!(
+                "You need {} free inventory slots to redeem this code",
+                redeem_code.items.len()
+            ),
+        );
+    }
+
+    match use_code(pc_uid, code) {
+        Ok(true) => {}
+        Ok(false) => return send_system_message(client, "This code can no longer be redeemed"),
+        Err(e) => {
+            return send_system_message(client, &format!("Failed to redeem code: {}", e.get_msg()))
+        }
+    }
+
+    let player = state.get_player_mut(pc_id)?;
+    let mut item_rewards = Vec::with_capacity(redeem_code.items.len());
+    for item in &redeem_code.items {
+        let slot = player.find_free_slot(ItemLocation::Inven)?;
+        player.set_item(ItemLocation::Inven, slot, Some(*item))?;
+        item_rewards.push(sItemReward {
+            sItem: Some(*item).into(),
+            eIL: ItemLocation::Inven as i32,
+            iSlotNum: slot as i32,
+        });
+    }
+
+    let taros = player.add_taros(redeem_code.taros as i64, CurrencySource::Redeem);
+    // skips the level up checks for codes without any
+    let fusion_matter = if redeem_code.fusion_matter > 0 {
+        player.add_fusion_matter(
+            redeem_code.fusion_matter as i64,
+            CurrencySource::Redeem,
+            Some(clients),
+        )
+    } else {
+        player.get_fusion_matter()
+    };
+    let reward_pkt = sP_FE2CL_REP_REWARD_ITEM {
+        m_iCandy: taros as i32,
+        m_iFusionMatter: fusion_matter as i32,
+        m_iBatteryN: player.get_nano_potions() as i32,
+        m_iBatteryW: player.get_weapon_boosts() as i32,
+        iItemCnt: item_rewards.len() as i8,
+        iFatigue: 100,
+        iFatigue_Level: 1,
+        iNPC_TypeID: unused!(),
+        iTaskID: unused!(),
+    };
+    log(
+        Severity::Info,
+        &format!("{} redeemed code {}", player, redeem_code.code),
+    );
+
+    let client = player.get_client(clients).unwrap();
+    client.queue_packet(P_FE2CL_REP_REWARD_ITEM, &reward_pkt);
+    for item in &item_rewards {
+        client.queue_struct(item);
+    }
+    client.flush()?;
+    send_system_message(client, &format!("Code {} redeemed!", redeem_code.code))
+}
+
+// Lets the login server tell the player's buddies they came online or left.
+// Sending it again while they're online just syncs their buddy list.
+pub fn send_buddy_presence(player: &Player, online: bool, clients: &mut ClientMap) {
+    let Some(login_server) = clients.get_login_server() else {
+        return;
+    };
+    let mut pkt = sP_FE2LS_BUDDY_PRESENCE {
+        iPC_UID: player.get_uid(),
+        iOnline: if online { 1 } else { 0 },
+        aBuddyUID: [0; SIZEOF_BUDDYLIST_SLOT as usize],
+        aBuddyBlocked: [0; SIZEOF_BUDDYLIST_SLOT as usize],
+    };
+    for (i, entry) in player.get_all_buddy_info().iter().enumerate() {
+        pkt.aBuddyUID[i] = entry.pc_uid;
+        pkt.aBuddyBlocked[i] = if entry.blocked { 1 } else { 0 };
+    }
+    log_if_failed(login_server.send_packet(P_FE2LS_BUDDY_PRESENCE, &pkt));
+}
+
+pub fn send_announcement(client: &mut FFClient, msg: &str) {
+    let pkt = sP_FE2CL_ANNOUNCE_MSG {
+        iAnnounceType: unused!(),
+        iDuringTime: MSG_BOX_DURATION_DEFAULT,
+        szAnnounceMsg: util::encode_utf16(msg),
+    };
+    log_if_failed(client.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt));
+}
+
+pub fn send_shard_announcement(clients: &mut ClientMap, msg: &str) {
+    let pkt = sP_FE2CL_ANNOUNCE_MSG {
+        iAnnounceType: unused!(),
+        iDuringTime: MSG_BOX_DURATION_DEFAULT,
+        szAnnounceMsg: util::encode_utf16(msg),
+    };
+    clients
+        .get_all_gameclient()
+        .for_each(|c| log_if_failed(c.send_packet(P_FE2CL_ANNOUNCE_MSG, &pkt)));
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RewardRateChange {
+    Set {
+        reward_type: RewardType,
+        category: RewardCategory,
+        percent: f32,
+    },
+    Reset,
+}
+
+// Applies a GM's reward rate change to a player, who keeps the rates until they log out.
+// Changing someone else's rates needs more privilege than changing your own.
+// Returns the target's rates afterward, for confirming to the GM and the target.
+pub fn change_reward_rates(
+    state: &mut ShardServerState,
+    gm_pc_id: i32,
+    target_pc_id: i32,
+    change: Option<RewardRateChange>,
+) -> FFResult<sP_FE2CL_GM_REP_REWARD_RATE_SUCC> {
+    let gm = state.get_player(gm_pc_id)?;
+    let gm_name = gm.to_string();
+    if target_pc_id != gm_pc_id && change.is_some() {
+        check_gm_perms_on_other(gm.perms, P_CL2FE_GM_REQ_REWARD_RATE)?;
+    } else {
+        check_gm_perms(gm.perms, P_CL2FE_GM_REQ_REWARD_RATE)?;
+    }
+
+    let target = state.get_player_mut(target_pc_id)?;
+    if let Some(change) = change {
+        let desc = match change {
+            RewardRateChange::Set {
+                reward_type,
+                category,
+                percent,
+            } => {
+                target
+                    .reward_data
+                    .set_reward_rate(reward_type, category, percent);
+                format!(
+                    "set {:?} reward rate for {:?} to {}%",
+                    reward_type, category, percent
+                )
+            }
+            RewardRateChange::Reset => {
+                target.reward_data.reset();
+                "reset all reward rates".to_string()
+            }
+        };
+        log(
+            Severity::Info,
+            &format!("[GM] {} {} for {}", gm_name, desc, target),
+        );
+    }
+
+    Ok(sP_FE2CL_GM_REP_REWARD_RATE_SUCC {
+        afRewardRate_Taros: target.reward_data.get_rates_as_array(RewardType::Taros),
+        afRewardRate_FusionMatter: target
+            .reward_data
+            .get_rates_as_array(RewardType::FusionMatter),
+    })
+}
+
+// Ends a trade without exchanging anything, telling whoever's still around
+pub fn cancel_trade(trade_id: Uuid, clients: &mut ClientMap, state: &mut ShardServerState) {
+    let Some(trade) = state.ongoing_trades.remove(&trade_id) else {
+        return;
+    };
+    for pc_id in [trade.get_id_from(), trade.get_id_to()] {
+        let Ok(player) = state.get_player_mut(pc_id) else {
+            continue;
+        };
+        player.trade_id = None;
+        let resp = sP_FE2CL_REP_PC_TRADE_CONFIRM_CANCEL {
+            iID_Request: trade.get_other_id(pc_id),
+            iID_From: trade.get_id_from(),
+            iID_To: trade.get_id_to(),
+        };
+        if let Some(client) = player.get_client(clients) {
+            log_if_failed(client.send_packet(P_FE2CL_REP_PC_TRADE_CONFIRM_CANCEL, &resp));
+        }
+    }
+}
+
+pub fn send_transport_location_unlocked(
+    client: &mut FFClient,
+    player: &Player,
+    transport_type: TransportationType,
+    location_id: i32,
+) -> FFResult<()> {
+    let pkt = sP_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC {
+        eTT: transport_type as i32,
+        iLocationID: location_id,
+        iWarpLocationFlag: player.flags.scamper_flags.to_array()[0],
+        aWyvernLocationFlag: player.flags.skyway_flags.to_array(),
+    };
+    client.send_packet(P_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC, &pkt)
+}
+
+// Registers any transporters the player has walked up to
+// so they show up on the map without having to talk to them first
+pub fn auto_unlock_transport_locations(
+    pc_id: i32,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let range = config_get().shard.transport_unlock_range.get();
+    if range == 0 {
+        return Ok(());
+    }
+
+    let pos = state.get_player(pc_id)?.get_position();
+    let mut nearby_npc_types = Vec::new();
+    for id in state.entity_map.get_around_entity(EntityID::Player(pc_id)) {
+        if let EntityID::NPC(npc_id) = id {
+            let npc = state.get_npc(npc_id)?;
+            if npc.get_position().distance_to(&pos) <= range {
+                nearby_npc_types.push(npc.ty);
+            }
+        }
+    }
+
+    let player = state.get_player_mut(pc_id)?;
+    for npc_type in nearby_npc_types {
+        for (transport_type, location_id) in tdata_get().get_transporter_locations(npc_type) {
+            if player.unlock_transport_location(transport_type, location_id)? {
+                if let Some(client) = player.get_client(clients) {
+                    send_transport_location_unlocked(client, player, transport_type, location_id)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Counts a kill towards the player's tasks. Returns the tasks that have the enemy as a target
+pub fn give_kill_credit(
     player: &mut Player,
     defeated_type: i32,
     clients: &mut ClientMap,
-    rng: &mut ThreadRng,
-) {
-    let active_task_id = player.mission_journal.get_active_task_id().unwrap_or(0);
-    let client = player.get_client(clients).unwrap();
-    let mut item_rewards = Vec::new();
+) -> HashSet<i32> {
     let (enemy_in_tasks, count_updated) = player.mission_journal.mark_enemy_defeated(defeated_type);
 
     // if this kill reduced the remaining enemy count in any tasks, notify the client
     if count_updated {
-        let kill_pkt = sP_FE2CL_REP_PC_KILL_QUEST_NPCs_SUCC {
-            iNPCID: defeated_type,
+        if let Some(client) = player.get_client(clients) {
+            let kill_pkt = sP_FE2CL_REP_PC_KILL_QUEST_NPCs_SUCC {
+                iNPCID: defeated_type,
+            };
+            log_if_failed(client.send_packet(P_FE2CL_REP_PC_KILL_QUEST_NPCs_SUCC, &kill_pkt));
+        }
+    }
+    enemy_in_tasks
+}
+
+// Everyone who damaged the mob gets mission credit for it, even without the final blow,
+// as long as they're still alive and nearby. Players in `credited` were already rewarded
+pub fn give_contributor_kill_credit(
+    npc_id: i32,
+    credited: &HashSet<i32>,
+    state: &mut ShardServerState,
+    clients: &mut ClientMap,
+) {
+    let Ok(defeated) = state.get_npc(npc_id) else {
+        return;
+    };
+    let defeated_type = defeated.ty;
+    let instance_id = defeated.instance_id;
+    let position = defeated.get_position();
+    let contributors: Vec<i32> = defeated.get_contributors().iter().copied().collect();
+    for pc_id in contributors {
+        if credited.contains(&pc_id) {
+            continue;
+        }
+        let Ok(player) = state.get_player_mut(pc_id) else {
+            continue;
         };
-        log_if_failed(client.send_packet(P_FE2CL_REP_PC_KILL_QUEST_NPCs_SUCC, &kill_pkt));
+        if player.is_dead()
+            || player.instance_id != instance_id
+            || player.get_position().distance_to(&position) >= RANGE_GROUP_PARTICIPATE
+        {
+            continue;
+        }
+        give_kill_credit(player, defeated_type, clients);
     }
+}
+
+pub fn give_defeat_rewards(
+    player: &mut Player,
+    defeated_type: i32,
+    mob_drop_id: Option<i32>,
+    event_id: Option<i32>,
+    clients: &mut ClientMap,
+    rng: &mut GameRng,
+) {
+    let active_task_id = player.mission_journal.get_active_task_id().unwrap_or(0);
+    let mut item_rewards = Vec::new();
+    let enemy_in_tasks = give_kill_credit(player, defeated_type, clients);
 
     // go through each task that has this enemy as a target and drop quest items
     for task_id in &enemy_in_tasks {
@@ -251,9 +1201,15 @@ pub fn give_defeat_rewards(
     let mut gained_potions = 0;
     let mut gained_boosts = 0;
     match tdata_get()
-        .get_mob_reward(defeated_type)
-        .map(|r| r.with_rates(&player.reward_data))
-    {
+        .get_mob_reward_for(rng, defeated_type, mob_drop_id, event_id)
+        .map(|r| {
+            r.with_rates(&player.reward_data).with_origin(
+                ItemOrigin::MobDrop {
+                    mob_type: defeated_type,
+                },
+                SystemTime::now(),
+            )
+        }) {
         Ok(reward) => {
             gained_taros = reward.taros;
             gained_fm = reward.fusion_matter;
@@ -277,10 +1233,12 @@ pub fn give_defeat_rewards(
     }
 
     let reward_pkt = sP_FE2CL_REP_REWARD_ITEM {
-        m_iCandy: player.set_taros(player.get_taros() + gained_taros) as i32,
-        m_iFusionMatter: player
-            .set_fusion_matter(player.get_fusion_matter() + gained_fm, Some(clients))
-            as i32,
+        m_iCandy: player.add_taros(gained_taros as i64, CurrencySource::MobKill) as i32,
+        m_iFusionMatter: player.add_fusion_matter(
+            gained_fm as i64,
+            CurrencySource::MobKill,
+            Some(clients),
+        ) as i32,
         m_iBatteryN: player.set_nano_potions(player.get_nano_potions() + gained_potions) as i32,
         m_iBatteryW: player.set_weapon_boosts(player.get_weapon_boosts() + gained_boosts) as i32,
         iItemCnt: item_rewards.len() as i8,
@@ -296,3 +1254,740 @@ pub fn give_defeat_rewards(
     }
     log_if_failed(client.flush());
 }
+
+// Sends a chat packet to everyone in chat range of the speaker, except anyone ignoring them
+pub fn send_in_chat_range<T: FFPacket>(
+    pc_id: i32,
+    pkt_id: PacketID,
+    pkt: &T,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) {
+    let Ok(speaker_uid) = state.get_player(pc_id).map(|speaker| speaker.get_uid()) else {
+        return;
+    };
+    let config = &config_get().shard;
+    let recipients = state.entity_map.get_chat_recipients(
+        pc_id,
+        config.chat_range_mode.get(),
+        config.chat_radius.get(),
+    );
+    for recipient_pc_id in recipients {
+        let Ok(recipient) = state.get_player(recipient_pc_id) else {
+            continue;
+        };
+        if recipient.is_ignoring(speaker_uid) {
+            continue;
+        }
+        if let Some(client) = recipient.get_client(clients) {
+            log_if_failed(client.send_packet(pkt_id, pkt));
+        }
+    }
+}
+
+// Sends a chat packet to everyone in the speaker's group, except anyone ignoring them
+pub fn send_in_group<T: FFPacket>(
+    pc_id: i32,
+    pkt_id: PacketID,
+    pkt: &T,
+    clients: &mut ClientMap,
+    state: &mut ShardServerState,
+) -> FFResult<()> {
+    let speaker = state.get_player(pc_id)?;
+    let speaker_uid = speaker.get_uid();
+    let Some(group_id) = speaker.group_id else {
+        return Ok(());
+    };
+    let group = state.groups.get(&group_id).unwrap();
+    for eid in group.get_member_ids() {
+        let entity = state.entity_map.get_entity_raw(*eid).unwrap();
+        if let EntityID::Player(member_pc_id) = *eid {
+            if state.get_player(member_pc_id)?.is_ignoring(speaker_uid) {
+                continue;
+            }
+        }
+        if let Some(client) = entity.get_client(clients) {
+            log_if_failed(client.send_packet(pkt_id, pkt));
+        }
+    }
+    Ok(())
+}
+
+// Turns down a group invite for a target ignoring the host, the same way the target would,
+// so the host can't tell. Returns whether it was turned down
+pub fn refuse_group_invite_if_ignored(
+    host_pc_id: i32,
+    target_pc_id: i32,
+    clients: &mut ClientMap,
+    state: &ShardServerState,
+) -> FFResult<bool> {
+    let host = state.get_player(host_pc_id)?;
+    if !state.get_player(target_pc_id)?.is_ignoring(host.get_uid()) {
+        return Ok(false);
+    }
+
+    let pkt = sP_FE2CL_PC_GROUP_INVITE_REFUSE {
+        iID_To: target_pc_id,
+    };
+    if let Some(client) = host.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_PC_GROUP_INVITE_REFUSE, &pkt));
+    }
+    Ok(true)
+}
+
+// Same as above, for trade offers
+pub fn refuse_trade_offer_if_ignored(
+    from_pc_id: i32,
+    to_pc_id: i32,
+    clients: &mut ClientMap,
+    state: &ShardServerState,
+) -> FFResult<bool> {
+    let from = state.get_player(from_pc_id)?;
+    if !state.get_player(to_pc_id)?.is_ignoring(from.get_uid()) {
+        return Ok(false);
+    }
+
+    let pkt = sP_FE2CL_REP_PC_TRADE_OFFER_REFUSAL {
+        iID_Request: to_pc_id,
+        iID_From: from_pc_id,
+        iID_To: to_pc_id,
+    };
+    if let Some(client) = from.get_client(clients) {
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_TRADE_OFFER_REFUSAL, &pkt));
+    }
+    Ok(true)
+}
+
+// Moving, fighting, or trading; any of these calls off a pending clean exit
+const EXIT_CANCELLING_PACKETS: [PacketID; 8] = [
+    P_CL2FE_REQ_PC_MOVE,
+    P_CL2FE_REQ_PC_JUMP,
+    P_CL2FE_REQ_PC_MOVETRANSPORTATION,
+    P_CL2FE_REQ_PC_ATTACK_NPCs,
+    P_CL2FE_REQ_PC_ATTACK_CHARs,
+    P_CL2FE_REQ_NANO_SKILL_USE,
+    P_CL2FE_REQ_PC_TRADE_OFFER,
+    P_CL2FE_REQ_PC_TRADE_OFFER_ACCEPT,
+];
+
+// Returns whether a pending exit was called off
+pub fn cancel_exit_on_packet(pc_id: i32, pkt_id: PacketID, state: &mut ShardServerState) -> bool {
+    if !EXIT_CANCELLING_PACKETS.contains(&pkt_id) {
+        return false;
+    }
+    state
+        .get_player_mut(pc_id)
+        .is_ok_and(|player| player.cancel_exit())
+}
+
+pub fn send_exit_countdown(client: &mut FFClient, countdown: Duration) -> FFResult<()> {
+    let msg = format!(
+        "Logging out in {}. Moving, fighting, or trading will cancel.",
+        util::format_duration(countdown)
+    );
+    send_system_message(client, &msg)
+}
+
+// The client has no packet for this, but an exit failure puts it back to normal
+pub fn send_exit_cancelled(player: &Player, clients: &mut ClientMap) {
+    let Some(client) = player.get_client(clients) else {
+        return;
+    };
+    let pkt = sP_FE2CL_REP_PC_EXIT_FAIL {
+        iID: player.get_player_id(),
+        iErrorCode: unused!(),
+    };
+    log_if_failed(client.send_packet(P_FE2CL_REP_PC_EXIT_FAIL, &pkt));
+    log_if_failed(send_system_message(client, "Logout cancelled."));
+}
+
+// Saves and sends off a player whose clean exit went through
+pub fn finish_exit(pc_id: i32, clients: &mut ClientMap, state: &mut ShardServerState) {
+    let exit_code = if clients.get_login_server().is_some() {
+        EXIT_CODE_REQ_BY_PC
+    } else {
+        EXIT_CODE_SERVER_ERROR
+    };
+    let Ok(player) = state.get_player(pc_id) else {
+        return;
+    };
+    if let Some(client) = player.get_client(clients) {
+        log_if_failed(client.clear_player_id().map(|_| ()));
+        let pkt = sP_FE2CL_REP_PC_EXIT_SUCC {
+            iID: pc_id,
+            iExitCode: exit_code as i32,
+        };
+        log_if_failed(client.send_packet(P_FE2CL_REP_PC_EXIT_SUCC, &pkt));
+    }
+    Player::disconnect(pc_id, state, clients);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::{
+        chunk::{ChunkCoords, TickMode, MAP_SQUARE_SIZE},
+        database::{Database, MockDatabase},
+        mission::Task,
+        net::ClientType,
+        test_utils::{FakeClients, NpcBuilder, PlayerBuilder},
+    };
+
+    #[test]
+    fn test_transport_location_unlock_sent() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let mut player = PlayerBuilder::new(1).pc_id(1).build();
+
+        assert!(player
+            .unlock_transport_location(TransportationType::Wyvern, 3)
+            .unwrap());
+        send_transport_location_unlocked(
+            fake_clients.get_client(key),
+            &player,
+            TransportationType::Wyvern,
+            3,
+        )
+        .unwrap();
+        assert_eq!(
+            fake_clients.take_sent(key),
+            vec![P_FE2CL_REP_PC_REGIST_TRANSPORTATION_LOCATION_SUCC]
+        );
+        assert!(player
+            .is_transport_location_unlocked(TransportationType::Wyvern, 3)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_warp_while_mounted() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let rider_key = fake_clients.add_game_client(Some(1));
+        let watcher_key = fake_clients.add_game_client(Some(2));
+        for (pc_id, key) in [(1, rider_key), (2, watcher_key)] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .build();
+            let chunk_pos = player.get_chunk_coords();
+            let id = state.entity_map.track(Box::new(player), TickMode::Never);
+            state.entity_map.update(id, Some(chunk_pos), None);
+        }
+        state.get_player_mut(1).unwrap().vehicle_speed = Some(1000);
+
+        let dest = Position {
+            x: 100000,
+            y: 100000,
+            z: 0,
+        };
+        warp_player(
+            1,
+            dest,
+            ID_OVERWORLD,
+            None,
+            &mut fake_clients.get_map(rider_key),
+            &mut state,
+        )
+        .unwrap();
+
+        let count = |sent: &[PacketID], pkt_id| sent.iter().filter(|&&id| id == pkt_id).count();
+        let rider_sent = fake_clients.take_sent(rider_key);
+        assert_eq!(count(&rider_sent, P_FE2CL_PC_VEHICLE_OFF_SUCC), 1);
+        assert_eq!(count(&rider_sent, P_FE2CL_PC_STATE_CHANGE), 1);
+        let watcher_sent = fake_clients.take_sent(watcher_key);
+        assert_eq!(count(&watcher_sent, P_FE2CL_PC_VEHICLE_OFF_SUCC), 0);
+        assert_eq!(count(&watcher_sent, P_FE2CL_PC_STATE_CHANGE), 1);
+
+        let rider = state.get_player(1).unwrap();
+        assert_eq!(rider.vehicle_speed, None);
+        assert_eq!(rider.get_state_bit_flag() & FLAG_PC_STATE_VEHICLE, 0);
+
+        // already off the vehicle, so there's nothing more to send
+        assert!(!force_dismount(1, &mut fake_clients.get_map(rider_key), &mut state).unwrap());
+        assert!(fake_clients.take_sent(rider_key).is_empty());
+    }
+
+    #[test]
+    fn test_broadcast_nano_active() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let caster_key = fake_clients.add_game_client(Some(1));
+        let watcher_key = fake_clients.add_game_client(Some(2));
+        let far_away = Position {
+            x: MAP_SQUARE_SIZE,
+            y: 0,
+            z: 0,
+        };
+        for (pc_id, key, position) in [
+            (1, caster_key, Position::default()),
+            (2, watcher_key, far_away),
+        ] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .position(position)
+                .build();
+            let chunk_pos = player.get_chunk_coords();
+            let id = state.entity_map.track(Box::new(player), TickMode::Never);
+            state.entity_map.update(id, Some(chunk_pos), None);
+        }
+        let caster_id = EntityID::Player(1);
+        let nano_active_sent = |fake_clients: &mut FakeClients, key| {
+            fake_clients.take_sent(key).contains(&P_FE2CL_NANO_ACTIVE)
+        };
+
+        // the caster moved next to the watcher, but the entity map hasn't caught up yet
+        state.get_player_mut(1).unwrap().set_position(far_away);
+        broadcast_nano_active(1, &mut fake_clients.get_map(caster_key), &mut state);
+        assert_eq!(
+            state.entity_map.get_entity_chunk(caster_id),
+            Some(ChunkCoords::from_pos_inst(far_away, InstanceID::default()))
+        );
+        assert!(nano_active_sent(&mut fake_clients, caster_key));
+        assert!(nano_active_sent(&mut fake_clients, watcher_key));
+
+        // players in transit aren't in a chunk, so there's nobody to tell
+        state.entity_map.update(caster_id, None, None);
+        broadcast_nano_active(1, &mut fake_clients.get_map(caster_key), &mut state);
+        assert_eq!(state.entity_map.get_entity_chunk(caster_id), None);
+        assert!(!nano_active_sent(&mut fake_clients, caster_key));
+        assert!(!nano_active_sent(&mut fake_clients, watcher_key));
+    }
+
+    #[test]
+    fn test_dismount_ends_vehicle_boost() {
+        let time = SystemTime::now();
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let player = PlayerBuilder::new(1).pc_id(1).client_id(key).build();
+        let chunk_pos = player.get_chunk_coords();
+        let id = state.entity_map.track(Box::new(player), TickMode::Never);
+        state.entity_map.update(id, Some(chunk_pos), None);
+
+        let rider = state.get_player_mut(1).unwrap();
+        rider.set_weapon_boosts(5);
+        rider.vehicle_speed = Some(1000);
+        rider.start_vehicle_boost(time).unwrap();
+        assert!(rider.is_vehicle_boosting());
+
+        assert!(force_dismount(1, &mut fake_clients.get_map(key), &mut state).unwrap());
+        let rider = state.get_player_mut(1).unwrap();
+        assert!(!rider.is_vehicle_boosting());
+        // getting back on doesn't pick the old boost back up
+        rider.vehicle_speed = Some(1000);
+        assert!(!rider.is_vehicle_boosting());
+    }
+
+    fn redeem(
+        code: &str,
+        db: &RefCell<MockDatabase>,
+        fake_clients: &mut FakeClients,
+        key: usize,
+        state: &mut ShardServerState,
+    ) {
+        redeem_code(
+            1,
+            code,
+            |code| db.borrow_mut().find_redeem_code(&code.to_string()),
+            |pc_uid, code| db.borrow_mut().use_redeem_code(pc_uid, &code.to_string()),
+            &mut fake_clients.get_map(key),
+            state,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_redeem_code() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let player = PlayerBuilder::new(1)
+            .pc_id(1)
+            .client_id(key)
+            .taros(100)
+            .build();
+        state.entity_map.track(Box::new(player), TickMode::Never);
+
+        let db = RefCell::new(MockDatabase::default());
+        let mut gift = RedeemCode::new("gift");
+        gift.max_uses = 2;
+        gift.taros = 50;
+        gift.items.push(Item::new(ItemType::General, 5));
+        let mut expired = RedeemCode::new("old");
+        expired.expiry_time = Some(SystemTime::now() - Duration::from_secs(1));
+        let mut used_up = RedeemCode::new("gone");
+        used_up.uses = 1;
+        let mut more = RedeemCode::new("more");
+        more.items.push(Item::new(ItemType::General, 6));
+        for code in [&gift, &expired, &used_up, &more] {
+            db.borrow_mut().create_redeem_code(code).unwrap();
+        }
+        let uses = |code: &str| {
+            let code = db.borrow_mut().find_redeem_code(&code.to_string());
+            code.unwrap().unwrap().uses
+        };
+
+        for (code, msg) in [
+            ("NOPE", "Invalid code"),
+            ("OLD", "This code has expired"),
+            ("GONE", "This code has been fully redeemed"),
+        ] {
+            redeem(code, &db, &mut fake_clients, key, &mut state);
+            assert_eq!(fake_clients.take_messages(key), vec![msg]);
+        }
+
+        db.borrow_mut().fail_next(1);
+        redeem("GIFT", &db, &mut fake_clients, key, &mut state);
+        let msgs = fake_clients.take_messages(key);
+        assert!(msgs[0].starts_with("Failed to redeem code"));
+        assert_eq!(uses("GIFT"), 0);
+
+        redeem("GIFT", &db, &mut fake_clients, key, &mut state);
+        assert_eq!(
+            fake_clients.take_sent(key),
+            vec![P_FE2CL_REP_REWARD_ITEM, P_FE2CL_PC_MOTD_LOGIN]
+        );
+        let player = state.get_player(1).unwrap();
+        assert_eq!(player.get_taros(), 150);
+        assert_eq!(
+            player.get_item(ItemLocation::Inven, 0).unwrap(),
+            &Some(Item::new(ItemType::General, 5))
+        );
+        assert_eq!(uses("GIFT"), 1);
+
+        // uses are left, but not for this player
+        redeem("GIFT", &db, &mut fake_clients, key, &mut state);
+        assert_eq!(
+            fake_clients.take_messages(key),
+            vec!["This code can no longer be redeemed"]
+        );
+        assert_eq!(state.get_player(1).unwrap().get_taros(), 150);
+
+        // a full inventory doesn't use up the code
+        let player = state.get_player_mut(1).unwrap();
+        while let Ok(slot) = player.find_free_slot(ItemLocation::Inven) {
+            player
+                .set_item(
+                    ItemLocation::Inven,
+                    slot,
+                    Some(Item::new(ItemType::General, 7)),
+                )
+                .unwrap();
+        }
+        redeem("MORE", &db, &mut fake_clients, key, &mut state);
+        assert_eq!(
+            fake_clients.take_messages(key),
+            vec!["You need 1 free inventory slots to redeem this code"]
+        );
+        assert_eq!(uses("MORE"), 0);
+    }
+
+    #[test]
+    fn test_contributor_kill_credit() {
+        const ENEMY_TYPE: i32 = 5;
+
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let far_away = Position {
+            x: RANGE_GROUP_PARTICIPATE as i32,
+            y: 0,
+            z: 0,
+        };
+        let mut keys = Vec::new();
+        for (pc_id, position, hp) in [
+            (1, Position::default(), 1000),
+            (2, Position::default(), 1000),
+            (3, far_away, 1000),
+            (4, Position::default(), 0),
+        ] {
+            let key = fake_clients.add_game_client(Some(pc_id));
+            let mut player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .position(position)
+                .hp(hp)
+                .build();
+            let mut task = Task::new_mock(10, 1, MissionType::Normal);
+            task.remaining_enemy_defeats.insert(ENEMY_TYPE, 3);
+            player.mission_journal.start_task(task).unwrap();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+            keys.push(key);
+        }
+
+        // players 1 and 2 split the damage; 3 wandered off and 4 died
+        let mut npc = NpcBuilder::new(1, ENEMY_TYPE).hp(400).build();
+        for pc_id in 1..=4 {
+            npc.take_damage(100, EntityID::Player(pc_id));
+        }
+        assert!(npc.is_dead());
+        assert_eq!(npc.get_contributors().len(), 4);
+        state.entity_map.track(Box::new(npc), TickMode::Never);
+
+        give_contributor_kill_credit(
+            1,
+            &HashSet::new(),
+            &mut state,
+            &mut fake_clients.get_map(keys[0]),
+        );
+        let remaining = |state: &ShardServerState, pc_id| {
+            let player = state.get_player(pc_id).unwrap();
+            player.mission_journal.get_current_tasks()[0].remaining_enemy_defeats[&ENEMY_TYPE]
+        };
+        for (pc_id, key) in (1..=4).zip(keys.iter()) {
+            let expected = if pc_id <= 2 { 2 } else { 3 };
+            assert_eq!(remaining(&state, pc_id), expected);
+            let sent = fake_clients.take_sent(*key);
+            assert_eq!(
+                sent.contains(&P_FE2CL_REP_PC_KILL_QUEST_NPCs_SUCC),
+                pc_id <= 2
+            );
+        }
+
+        // players who already got credit from the defeat itself aren't counted twice
+        give_contributor_kill_credit(
+            1,
+            &HashSet::from([1, 2]),
+            &mut state,
+            &mut fake_clients.get_map(keys[0]),
+        );
+        assert_eq!(remaining(&state, 1), 2);
+        assert_eq!(remaining(&state, 2), 2);
+
+        let npc = state.get_npc_mut(1).unwrap();
+        npc.reset();
+        assert!(npc.get_contributors().is_empty());
+    }
+
+    fn track_players(state: &mut ShardServerState, players: &[(i32, i16)]) {
+        for &(pc_id, perms) in players {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .perms(perms)
+                .build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+    }
+
+    #[test]
+    fn test_reward_rate_targeting() {
+        let mut state = ShardServerState::new_mock(1);
+        let gm_perms = CN_ACCOUNT_LEVEL__GM as i16;
+        let dev_perms = CN_ACCOUNT_LEVEL__DEVELOPER as i16;
+        track_players(&mut state, &[(1, gm_perms), (2, dev_perms), (3, 99)]);
+        let double_fm = Some(RewardRateChange::Set {
+            reward_type: RewardType::FusionMatter,
+            category: RewardCategory::Combat,
+            percent: 200.0,
+        });
+        let rate = |state: &ShardServerState, pc_id| {
+            state
+                .get_player(pc_id)
+                .unwrap()
+                .reward_data
+                .get_reward_rate(RewardType::FusionMatter, RewardCategory::Combat as usize)
+                .unwrap()
+        };
+
+        // a GM can change someone else's rates
+        let resp = change_reward_rates(&mut state, 1, 3, double_fm).unwrap();
+        assert_eq!({ resp.afRewardRate_FusionMatter }[1], 2.0);
+        assert_eq!(rate(&state, 3), 2.0);
+        assert_eq!(rate(&state, 1), 1.0);
+
+        // a developer can only change their own, but can still look
+        assert!(change_reward_rates(&mut state, 2, 3, Some(RewardRateChange::Reset)).is_err());
+        assert_eq!(rate(&state, 3), 2.0);
+        assert!(change_reward_rates(&mut state, 2, 3, None).is_ok());
+        change_reward_rates(&mut state, 2, 2, double_fm).unwrap();
+        assert_eq!(rate(&state, 2), 2.0);
+
+        // regular players can't do either
+        assert!(change_reward_rates(&mut state, 3, 3, double_fm).is_err());
+        assert!(change_reward_rates(&mut state, 3, 3, None).is_err());
+    }
+
+    #[test]
+    fn test_reward_rate_reset() {
+        let mut state = ShardServerState::new_mock(1);
+        track_players(&mut state, &[(1, CN_ACCOUNT_LEVEL__GM as i16), (2, 99)]);
+        for reward_type in [RewardType::Taros, RewardType::FusionMatter] {
+            let change = RewardRateChange::Set {
+                reward_type,
+                category: RewardCategory::All,
+                percent: 50.0,
+            };
+            change_reward_rates(&mut state, 1, 2, Some(change)).unwrap();
+        }
+        let resp = change_reward_rates(&mut state, 1, 2, None).unwrap();
+        assert_eq!({ resp.afRewardRate_Taros }[1..], [0.5; 4]);
+
+        let resp = change_reward_rates(&mut state, 1, 2, Some(RewardRateChange::Reset)).unwrap();
+        assert_eq!({ resp.afRewardRate_Taros }[1..], [1.0; 4]);
+        assert_eq!({ resp.afRewardRate_FusionMatter }[1..], [1.0; 4]);
+    }
+
+    fn add_players(count: i32, fake_clients: &mut FakeClients, state: &mut ShardServerState) {
+        for pc_id in 1..=count {
+            let key = fake_clients.add_game_client(Some(pc_id));
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(key)
+                .build();
+            let chunk_pos = player.get_chunk_coords();
+            let id = state.entity_map.track(Box::new(player), TickMode::Never);
+            state.entity_map.update(id, Some(chunk_pos), None);
+        }
+    }
+
+    #[test]
+    fn test_ignored_chat_dropped() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        add_players(3, &mut fake_clients, &mut state);
+        state.get_player_mut(3).unwrap().ignore_player(1).unwrap();
+
+        let pkt = sP_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC {
+            iPC_ID: 1,
+            szFreeChat: [0; 128],
+            iEmoteCode: 0,
+        };
+        send_in_chat_range(
+            1,
+            P_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC,
+            &pkt,
+            &mut fake_clients.get_map(0),
+            &mut state,
+        );
+        for key in [0, 1] {
+            assert_eq!(
+                fake_clients.take_sent(key),
+                vec![P_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC]
+            );
+        }
+        assert!(fake_clients.take_sent(2).is_empty());
+
+        // it only goes one way
+        let pkt = sP_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC { iPC_ID: 3, ..pkt };
+        send_in_chat_range(
+            3,
+            P_FE2CL_REP_SEND_MENUCHAT_MESSAGE_SUCC,
+            &pkt,
+            &mut fake_clients.get_map(2),
+            &mut state,
+        );
+        assert_eq!(fake_clients.take_sent(0).len(), 1);
+    }
+
+    #[test]
+    fn test_ignored_invites_refused() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        add_players(2, &mut fake_clients, &mut state);
+        let clients = &mut fake_clients.get_map(0);
+        assert!(!refuse_group_invite_if_ignored(1, 2, clients, &state).unwrap());
+        assert!(!refuse_trade_offer_if_ignored(1, 2, clients, &state).unwrap());
+        assert!(fake_clients.take_sent(0).is_empty());
+
+        // turned down just like a normal refusal, and the target never hears of it
+        state.get_player_mut(2).unwrap().ignore_player(1).unwrap();
+        let clients = &mut fake_clients.get_map(0);
+        assert!(refuse_group_invite_if_ignored(1, 2, clients, &state).unwrap());
+        assert!(refuse_trade_offer_if_ignored(1, 2, clients, &state).unwrap());
+        assert!(!refuse_group_invite_if_ignored(2, 1, clients, &state).unwrap());
+        assert_eq!(
+            fake_clients.take_sent(0),
+            vec![
+                P_FE2CL_PC_GROUP_INVITE_REFUSE,
+                P_FE2CL_REP_PC_TRADE_OFFER_REFUSAL
+            ]
+        );
+        assert!(fake_clients.take_sent(1).is_empty());
+    }
+
+    #[test]
+    fn test_kick_banned_account() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let other_key = fake_clients.add_game_client(Some(2));
+        let login_key = fake_clients.add_client(ClientType::LoginServer);
+        fake_clients.get_client(other_key).client_type = ClientType::GameClient {
+            account_id: 2,
+            serial_key: 0,
+            pc_id: Some(2),
+        };
+
+        // a lifted ban kicks nobody but still gets passed on
+        let lifted = SystemTime::now() - Duration::from_secs(60);
+        let mut clients = fake_clients.get_map(other_key);
+        assert_eq!(
+            kick_and_report_banned_account(1, lifted, "spam", &mut clients),
+            0
+        );
+        assert!(fake_clients.take_sent(key).is_empty());
+        assert!(!fake_clients.get_client(key).should_dc());
+        assert_eq!(
+            fake_clients.take_sent(login_key),
+            vec![P_FE2LS_ACCOUNT_BANNED]
+        );
+
+        let banned_until = SystemTime::now() + Duration::from_secs(3600);
+        let mut clients = fake_clients.get_map(other_key);
+        assert_eq!(
+            kick_and_report_banned_account(1, banned_until, "spam", &mut clients),
+            1
+        );
+        assert_eq!(
+            fake_clients.take_sent(key),
+            vec![P_FE2CL_ANNOUNCE_MSG, P_FE2CL_REP_PC_EXIT_SUCC]
+        );
+        assert!(fake_clients.get_client(key).should_dc());
+        assert!(fake_clients.take_sent(other_key).is_empty());
+        assert!(!fake_clients.get_client(other_key).should_dc());
+
+        let mut sent = fake_clients.take_sent_packets(login_key);
+        assert_eq!(sent.len(), 1);
+        let pkt: &sP_FE2LS_ACCOUNT_BANNED = sent[0].get_packet(P_FE2LS_ACCOUNT_BANNED).unwrap();
+        let (acc_id, secs) = (pkt.iAccountID, pkt.iBannedUntil);
+        assert_eq!(acc_id, 1);
+        assert_eq!(secs, util::get_timestamp_sec(banned_until) as i64);
+        assert_eq!(util::parse_utf16(&pkt.szBanReason).unwrap(), "spam");
+    }
+
+    #[test]
+    fn test_kick_account_for_login_server() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let loading_key = fake_clients.add_game_client(None);
+        let login_key = fake_clients.add_client(ClientType::LoginServer);
+
+        let banned_until = SystemTime::now() + Duration::from_secs(3600);
+        let pkt = sP_LS2FE_REQ_KICK_ACCOUNT {
+            iAccountID: 1,
+            iBannedUntil: util::get_timestamp_sec(banned_until) as i64,
+            szBanReason: util::encode_utf16("spam"),
+        };
+        let mut clients = fake_clients.get_map(login_key);
+        kick_account_for_login_server(&pkt, &mut clients).unwrap();
+
+        let mut sent = fake_clients.take_sent_packets(key);
+        assert_eq!(sent.len(), 2);
+        let announce: &sP_FE2CL_ANNOUNCE_MSG = sent[0].get_packet(P_FE2CL_ANNOUNCE_MSG).unwrap();
+        let msg = util::parse_utf16(&announce.szAnnounceMsg).unwrap();
+        assert!(msg.starts_with("You have been banned for "));
+        assert!(msg.ends_with("\nReason: spam"));
+        let exit: &sP_FE2CL_REP_PC_EXIT_SUCC =
+            sent[1].get_packet(P_FE2CL_REP_PC_EXIT_SUCC).unwrap();
+        let (id, code) = (exit.iID, exit.iExitCode);
+        assert_eq!((id, code), (1, EXIT_CODE_REQ_BY_GM as i32));
+        assert!(fake_clients.get_client(key).should_dc());
+
+        // nobody to kick before a player is loaded
+        assert!(fake_clients.take_sent(loading_key).is_empty());
+        // the login server already knows
+        assert!(fake_clients.take_sent(login_key).is_empty());
+    }
+}
@@ -1,22 +1,126 @@
-use std::{cmp::min, time::SystemTime};
+use std::{
+    cmp::min,
+    fmt::{Display, Formatter},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
     defines::*,
     entity::RewardData,
-    enums::{ItemType, RewardCategory, RewardType},
-    error::{panic_log, FFError, FFResult},
+    enums::{CombatStyle, ItemType, RewardCategory, RewardType, SkillType},
+    error::{panic_log, FFError, FFResult, Severity},
     net::packet::*,
     tabledata::tdata_get,
     util,
 };
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+// Where an item came from, for tracking down dupes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemOrigin {
+    MobDrop { mob_type: i32 },
+    Crate { crate_id: i16 },
+    Vendor,
+    Mission { mission_id: i32 },
+    // first seen in a trade, with no earlier history
+    Trade { from_uid: i64 },
+    GM { actor: i64 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeHop {
+    pub from_uid: i64,
+    pub to_uid: i64,
+    pub time: SystemTime,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemProvenance {
+    pub origin: ItemOrigin,
+    pub created_at: SystemTime,
+    // oldest first, and only the latest MAX_ITEM_TRADE_HOPS
+    trade_hops: [Option<TradeHop>; MAX_ITEM_TRADE_HOPS],
+}
+impl ItemProvenance {
+    pub fn new(origin: ItemOrigin, created_at: SystemTime) -> Self {
+        Self {
+            origin,
+            created_at,
+            trade_hops: [None; MAX_ITEM_TRADE_HOPS],
+        }
+    }
+
+    // As stored in the database
+    pub fn encode(&self) -> FFResult<String> {
+        serde_json::to_string(self).map_err(|e| {
+            FFError::build(
+                Severity::Warning,
+                format!("Couldn't encode item provenance: {}", e),
+            )
+        })
+    }
+
+    pub fn decode(text: &str) -> FFResult<Self> {
+        serde_json::from_str(text).map_err(|e| {
+            FFError::build(
+                Severity::Warning,
+                format!("Couldn't decode item provenance: {}", e),
+            )
+        })
+    }
+
+    pub fn get_trade_hops(&self) -> impl Iterator<Item = &TradeHop> {
+        self.trade_hops.iter().flatten()
+    }
+
+    fn add_trade_hop(&mut self, hop: TradeHop) {
+        match self.trade_hops.iter().position(Option::is_none) {
+            Some(idx) => self.trade_hops[idx] = Some(hop),
+            None => {
+                self.trade_hops.rotate_left(1);
+                self.trade_hops[MAX_ITEM_TRADE_HOPS - 1] = Some(hop);
+            }
+        }
+    }
+}
+impl Display for ItemProvenance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} at {}",
+            self.origin,
+            util::get_timestamp_sec(self.created_at)
+        )?;
+        for hop in self.get_trade_hops() {
+            write!(
+                f,
+                " -> traded {} to {} at {}",
+                hop.from_uid,
+                hop.to_uid,
+                util::get_timestamp_sec(hop.time)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, Serialize, Deserialize)]
 pub struct Item {
     pub ty: ItemType,
     pub id: i16,
     appearance_id: Option<i16>,
     pub quantity: u16,
     expiry_time: Option<SystemTime>,
+    // missing from items saved before provenance was tracked
+    #[serde(default)]
+    provenance: Option<ItemProvenance>,
+}
+// provenance is left out so it never gets in the way of stacking or matching items
+impl PartialEq for Item {
+    fn eq(&self, other: &Self) -> bool {
+        self.stacks_with(other) && self.quantity == other.quantity
+    }
 }
 impl Item {
     pub fn new(ty: ItemType, id: i16) -> Self {
@@ -30,9 +134,35 @@ impl Item {
             appearance_id: None,
             quantity: 1,
             expiry_time: None,
+            provenance: None,
         }
     }
 
+    pub fn with_origin(mut self, origin: ItemOrigin, time: SystemTime) -> Self {
+        self.provenance = Some(ItemProvenance::new(origin, time));
+        self
+    }
+
+    pub fn get_provenance(&self) -> Option<&ItemProvenance> {
+        self.provenance.as_ref()
+    }
+
+    pub fn set_provenance(&mut self, provenance: Option<ItemProvenance>) {
+        self.provenance = provenance;
+    }
+
+    // Items with no history start theirs here
+    pub fn add_trade_hop(&mut self, from_uid: i64, to_uid: i64, time: SystemTime) {
+        let provenance = self
+            .provenance
+            .get_or_insert_with(|| ItemProvenance::new(ItemOrigin::Trade { from_uid }, time));
+        provenance.add_trade_hop(TradeHop {
+            from_uid,
+            to_uid,
+            time,
+        });
+    }
+
     pub fn get_stats(&self) -> FFResult<&ItemStats> {
         tdata_get().get_item_stats(self.id, self.ty)
     }
@@ -45,6 +175,14 @@ impl Item {
         self.expiry_time = Some(time);
     }
 
+    // Whether the two could share a stack, ignoring how many are in each
+    pub fn stacks_with(&self, other: &Item) -> bool {
+        self.ty == other.ty
+            && self.id == other.id
+            && self.appearance_id == other.appearance_id
+            && self.expiry_time == other.expiry_time
+    }
+
     pub fn set_appearance(&mut self, looks_item: &Item) {
         self.appearance_id = Some(looks_item.id);
     }
@@ -127,6 +265,7 @@ impl TryFrom<sItemBase> for Option<Item> {
                 } else {
                     Some(util::get_systime_from_sec(value.iTimeLimit as u64))
                 },
+                provenance: None,
             }))
         }
     }
@@ -162,6 +301,27 @@ pub struct ItemStats {
     pub multi_power: Option<i32>,
     pub defense: Option<i32>,
     pub speed: Option<i32>,
+    pub style_mod_trump: Option<f32>,
+    pub style_mod_trumped: Option<f32>,
+    pub attack_range: Option<u32>,
+    pub effect_area: Option<u32>,
+    pub target_number: Option<usize>,
+    pub projectile_time: Option<Duration>,
+    pub use_effect: Option<ItemUseEffect>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemUseEffect {
+    // refills the stamina of an equipped nano of the given style
+    NanoStamina {
+        style: CombatStyle,
+    },
+    // grants a timed buff to the player
+    Buff {
+        skill_type: SkillType,
+        condition_bit: u32,
+        duration: Duration,
+    },
 }
 
 pub struct VendorItem {
@@ -213,6 +373,10 @@ impl VendorData {
         Ok(vendor_item_structs.try_into().unwrap())
     }
 
+    pub fn get_items(&self) -> &[VendorItem] {
+        &self.items
+    }
+
     pub fn has_item(&self, item_id: i16, item_type: ItemType) -> bool {
         self.items
             .iter()
@@ -260,4 +424,253 @@ impl Reward {
         }
         self
     }
+
+    pub fn with_origin(mut self, origin: ItemOrigin, time: SystemTime) -> Self {
+        for item in &mut self.items {
+            *item = item.with_origin(origin, time);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedeemCode {
+    pub code: String,
+    pub taros: u32,
+    pub fusion_matter: u32,
+    pub items: Vec<Item>,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub once_per_account: bool,
+    pub expiry_time: Option<SystemTime>,
+}
+impl RedeemCode {
+    pub fn new(code: &str) -> Self {
+        Self {
+            code: code.to_uppercase(),
+            taros: 0,
+            fusion_matter: 0,
+            items: Vec::new(),
+            max_uses: 1,
+            uses: 0,
+            once_per_account: false,
+            expiry_time: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiry_time
+            .is_some_and(|expiry_time| expiry_time <= SystemTime::now())
+    }
+
+    pub fn get_uses_left(&self) -> u32 {
+        self.max_uses.saturating_sub(self.uses)
+    }
+
+    // Builds a code from the addcode command's arguments:
+    // <code> <max_uses> <duration> <once_per> <taros> <fm> [items...].
+    // `get_max_stack` looks up how many of an item fit in one slot
+    pub fn parse(
+        args: &[&str],
+        time: SystemTime,
+        get_max_stack: impl Fn(i16, ItemType) -> FFResult<u16>,
+    ) -> Result<Self, String> {
+        if args.len() < 6 {
+            return Err("Missing arguments".to_string());
+        }
+        let mut redeem_code = Self::new(args[0]);
+
+        let Ok(max_uses) = args[1].parse::<u32>() else {
+            return Err("Invalid max uses".to_string());
+        };
+        if max_uses == 0 {
+            return Err("Max uses must be non-zero".to_string());
+        }
+        redeem_code.max_uses = max_uses;
+
+        if args[2] != "never" {
+            let Ok(duration) = util::get_duration_from_shorthand(args[2]) else {
+                return Err("Invalid duration".to_string());
+            };
+            if duration.is_zero() {
+                return Err("Duration must be non-zero".to_string());
+            }
+            redeem_code.expiry_time = Some(time + duration);
+        }
+
+        redeem_code.once_per_account = match args[3] {
+            "player" => false,
+            "account" => true,
+            _ => return Err("once_per must be \"player\" or \"account\"".to_string()),
+        };
+
+        let (Ok(taros), Ok(fusion_matter)) = (args[4].parse::<u32>(), args[5].parse::<u32>())
+        else {
+            return Err("Invalid taros or fusion matter amount".to_string());
+        };
+        redeem_code.taros = taros;
+        redeem_code.fusion_matter = fusion_matter;
+
+        if args.len() - 6 > SIZEOF_INVEN_SLOT as usize {
+            return Err("Too many items".to_string());
+        }
+        for item_arg in &args[6..] {
+            let parts: Vec<&str> = item_arg.split(':').collect();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(format!("Invalid item {}", item_arg));
+            }
+            let Some(ty) = parts[0]
+                .parse::<i16>()
+                .ok()
+                .and_then(|ty| ItemType::try_from(ty).ok())
+            else {
+                return Err(format!("Invalid item type {}", parts[0]));
+            };
+            let Ok(id) = parts[1].parse::<i16>() else {
+                return Err(format!("Invalid item ID {}", parts[1]));
+            };
+            let Ok(max_stack_size) = get_max_stack(id, ty) else {
+                return Err(format!("Item {:?} #{} not found", ty, id));
+            };
+            let quantity = match parts.get(2) {
+                Some(q) => match q.parse::<u16>() {
+                    Ok(q) if (1..=max_stack_size).contains(&q) => q,
+                    _ => {
+                        return Err(format!(
+                            "Invalid quantity for item {:?} #{} (max {})",
+                            ty, id, max_stack_size
+                        ))
+                    }
+                },
+                None => 1,
+            };
+
+            let mut item = Item::new(ty, id);
+            item.quantity = quantity;
+            redeem_code.items.push(item);
+        }
+        Ok(redeem_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_round_trip() {
+        let time = util::get_systime_from_sec(1_700_000_000);
+        let mut item =
+            Item::new(ItemType::Hand, 10).with_origin(ItemOrigin::Crate { crate_id: 5 }, time);
+        item.add_trade_hop(1, 2, time + Duration::from_secs(60));
+        let provenance = *item.get_provenance().unwrap();
+        let decoded = ItemProvenance::decode(&provenance.encode().unwrap()).unwrap();
+        assert_eq!(decoded, provenance);
+        assert!(ItemProvenance::decode("not json").is_err());
+
+        let json = serde_json::to_string(&item).unwrap();
+        let loaded: Item = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.get_provenance(), Some(&provenance));
+
+        // items saved before provenance was tracked still load
+        let mut old = serde_json::to_value(Item::new(ItemType::Hand, 10)).unwrap();
+        old.as_object_mut().unwrap().remove("provenance");
+        let loaded: Item = serde_json::from_value(old).unwrap();
+        assert!(loaded.get_provenance().is_none());
+    }
+
+    #[test]
+    fn test_trade_hops_capped() {
+        let time = SystemTime::UNIX_EPOCH;
+        let mut item = Item::new(ItemType::General, 7);
+        for uid in 0..(MAX_ITEM_TRADE_HOPS as i64 + 2) {
+            item.add_trade_hop(uid, uid + 1, time);
+        }
+        let provenance = item.get_provenance().unwrap();
+        // the first trade is where its history starts, even once that hop is gone
+        assert_eq!(provenance.origin, ItemOrigin::Trade { from_uid: 0 });
+        let from_uids: Vec<i64> = provenance
+            .get_trade_hops()
+            .map(|hop| hop.from_uid)
+            .collect();
+        let expected: Vec<i64> = (2..(MAX_ITEM_TRADE_HOPS as i64 + 2)).collect();
+        assert_eq!(from_uids, expected);
+    }
+
+    #[test]
+    fn test_provenance_ignored_by_eq() {
+        let time = SystemTime::UNIX_EPOCH;
+        let plain = Item::new(ItemType::General, 7);
+        let dropped = plain.with_origin(ItemOrigin::MobDrop { mob_type: 3 }, time);
+        let mut bought = plain.with_origin(ItemOrigin::Vendor, time);
+        bought.add_trade_hop(1, 2, time);
+        assert_eq!(dropped, plain);
+        assert_eq!(dropped, bought);
+        assert!(dropped.stacks_with(&bought));
+
+        bought.quantity = 2;
+        assert_ne!(dropped, bought);
+        assert!(dropped.stacks_with(&bought));
+    }
+
+    #[test]
+    fn test_redeem_code_parse() {
+        let time = SystemTime::now();
+        let get_max_stack = |id: i16, _ty: ItemType| match id {
+            1 => Ok(1),
+            2 => Ok(10),
+            _ => Err(FFError::build(
+                Severity::Warning,
+                "No such item".to_string(),
+            )),
+        };
+        let parse = |args: &str| {
+            let args: Vec<&str> = args.split_whitespace().collect();
+            RedeemCode::parse(&args, time, get_max_stack)
+        };
+
+        let code = parse("gift 5 1h account 100 200 7:1 7:2:7").unwrap();
+        assert_eq!(code.code, "GIFT");
+        assert_eq!(code.max_uses, 5);
+        assert_eq!(code.expiry_time, Some(time + Duration::from_secs(3600)));
+        assert!(code.once_per_account);
+        assert_eq!((code.taros, code.fusion_matter), (100, 200));
+        let items: Vec<(i16, u16)> = code.items.iter().map(|i| (i.id, i.quantity)).collect();
+        assert_eq!(items, vec![(1, 1), (2, 7)]);
+        assert!(code.items.iter().all(|i| i.ty == ItemType::General));
+
+        let code = parse("gift 1 never player 0 0").unwrap();
+        assert_eq!(code.expiry_time, None);
+        assert!(!code.once_per_account);
+        assert!(code.items.is_empty());
+
+        for (args, err) in [
+            ("gift 1 never player 0", "Missing arguments"),
+            ("gift 0 never player 0 0", "Max uses must be non-zero"),
+            ("gift x never player 0 0", "Invalid max uses"),
+            ("gift 1 0s player 0 0", "Duration must be non-zero"),
+            ("gift 1 soon player 0 0", "Invalid duration"),
+            (
+                "gift 1 never group 0 0",
+                "once_per must be \"player\" or \"account\"",
+            ),
+            (
+                "gift 1 never player -1 0",
+                "Invalid taros or fusion matter amount",
+            ),
+            ("gift 1 never player 0 0 7", "Invalid item 7"),
+            ("gift 1 never player 0 0 99:1", "Invalid item type 99"),
+            ("gift 1 never player 0 0 7:x", "Invalid item ID x"),
+            ("gift 1 never player 0 0 7:3", "Item General #3 not found"),
+            (
+                "gift 1 never player 0 0 7:2:11",
+                "Invalid quantity for item General #2 (max 10)",
+            ),
+        ] {
+            assert_eq!(parse(args).unwrap_err(), err, "{}", args);
+        }
+
+        let too_many = format!("gift 1 never player 0 0{}", " 7:1".repeat(51));
+        assert_eq!(parse(&too_many).unwrap_err(), "Too many items");
+    }
 }
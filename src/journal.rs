@@ -0,0 +1,496 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{db_run_sync, edit_offline_player},
+    entity::Player,
+    enums::ItemLocation,
+    error::{log, FFError, FFResult, Severity},
+    item::Item,
+    util,
+};
+
+// bump this whenever the record format changes
+const JOURNAL_VERSION: u32 = 1;
+
+// Every op sets a value outright instead of adjusting it,
+// so replaying a journal more than once gives the same result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalOp {
+    Taros {
+        taros: u32,
+    },
+    FusionMatter {
+        fusion_matter: u32,
+    },
+    Level {
+        level: i16,
+    },
+    Item {
+        location: ItemLocation,
+        slot_num: usize,
+        item: Option<Item>,
+    },
+    MissionCompleted {
+        mission_id: i32,
+    },
+}
+impl JournalOp {
+    fn apply(&self, player: &mut Player) -> FFResult<()> {
+        match self {
+            JournalOp::Taros { taros } => {
+                player.set_taros(*taros);
+            }
+            JournalOp::FusionMatter { fusion_matter } => {
+                player.set_fusion_matter(*fusion_matter, None);
+            }
+            JournalOp::Level { level } => {
+                player.set_level(*level)?;
+            }
+            JournalOp::Item {
+                location,
+                slot_num,
+                item,
+            } => {
+                player.set_item(*location, *slot_num, *item)?;
+            }
+            JournalOp::MissionCompleted { mission_id } => {
+                player.set_mission_completed(*mission_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct JournalHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct JournalRecord {
+    seq: u64,
+    pc_uid: i64,
+    #[serde(flatten)]
+    op: JournalOp,
+}
+
+struct Journal {
+    path: String,
+    writer: BufWriter<File>,
+    next_seq: u64,
+    // everything since the last commit, kept to rewrite the file when a save lands
+    records: Vec<JournalRecord>,
+}
+impl Journal {
+    fn create(path: &str, records: Vec<JournalRecord>) -> FFResult<BufWriter<File>> {
+        // write the new file off to the side so a crash mid-rewrite leaves the old one intact
+        let tmp_path = format!("{}.tmp", path);
+        let file = File::create(&tmp_path).map_err(|e| {
+            FFError::build(
+                Severity::Warning,
+                format!("Couldn't create journal file {}: {}", tmp_path, e),
+            )
+        })?;
+        let mut writer = BufWriter::new(file);
+        let header = JournalHeader {
+            version: JOURNAL_VERSION,
+        };
+        write_line(&mut writer, &header)?;
+        for record in &records {
+            write_line(&mut writer, record)?;
+        }
+        sync(&mut writer)?;
+        fs::rename(&tmp_path, path).map_err(|e| {
+            FFError::build(
+                Severity::Warning,
+                format!("Couldn't replace journal file {}: {}", path, e),
+            )
+        })?;
+        Ok(writer)
+    }
+
+    fn record(&mut self, pc_uid: i64, op: JournalOp) -> FFResult<()> {
+        let record = JournalRecord {
+            seq: self.next_seq,
+            pc_uid,
+            op,
+        };
+        self.next_seq += 1;
+        write_line(&mut self.writer, &record)?;
+        self.records.push(record);
+        Ok(())
+    }
+
+    fn commit(&mut self, mark: u64, pc_uids: Option<&[i64]>) -> FFResult<()> {
+        let is_committed = |record: &JournalRecord| {
+            record.seq < mark && pc_uids.map_or(true, |uids| uids.contains(&record.pc_uid))
+        };
+        if !self.records.iter().any(is_committed) {
+            return Ok(());
+        }
+
+        self.records.retain(|record| !is_committed(record));
+        self.writer = Self::create(&self.path, self.records.clone())?;
+        Ok(())
+    }
+}
+
+static JOURNAL: OnceLock<Mutex<Journal>> = OnceLock::new();
+
+fn write_line(writer: &mut BufWriter<File>, value: &impl Serialize) -> FFResult<()> {
+    let line = serde_json::to_string(value).map_err(|e| {
+        FFError::build(
+            Severity::Warning,
+            format!("Couldn't serialize journal record: {}", e),
+        )
+    })?;
+    writeln!(writer, "{}", line).map_err(|e| {
+        FFError::build(
+            Severity::Warning,
+            format!("Couldn't write to journal: {}", e),
+        )
+    })
+}
+
+fn sync(writer: &mut BufWriter<File>) -> FFResult<()> {
+    writer
+        .flush()
+        .and_then(|_| writer.get_ref().sync_data())
+        .map_err(|e| FFError::build(Severity::Warning, format!("Couldn't sync journal: {}", e)))
+}
+
+// Starts a fresh journal. Any leftover journal should be replayed first.
+pub fn journal_init(path: &str) -> FFResult<()> {
+    assert!(JOURNAL.get().is_none());
+    let writer = Journal::create(path, Vec::new())?;
+    let journal = Journal {
+        path: path.to_string(),
+        writer,
+        next_seq: 1,
+        records: Vec::new(),
+    };
+    let _ = JOURNAL.set(Mutex::new(journal));
+    log(
+        Severity::Info,
+        &format!("Writing player progress journal to {}", path),
+    );
+    Ok(())
+}
+
+// Records a change to a player. Does nothing if the journal is disabled.
+pub fn journal_record(pc_uid: i64, op: JournalOp) {
+    let Some(journal) = JOURNAL.get() else {
+        return;
+    };
+    let mut journal = journal.lock().unwrap();
+    if let Err(e) = journal.record(pc_uid, op) {
+        log(Severity::Warning, e.get_msg());
+    }
+}
+
+pub fn journal_flush() -> FFResult<()> {
+    let Some(journal) = JOURNAL.get() else {
+        return Ok(());
+    };
+    let mut journal = journal.lock().unwrap();
+    sync(&mut journal.writer)
+}
+
+// Marks the current end of the journal, to be passed to journal_commit once a save lands
+pub fn journal_mark() -> u64 {
+    match JOURNAL.get() {
+        Some(journal) => journal.lock().unwrap().next_seq,
+        None => 0,
+    }
+}
+
+// Drops records from before the mark, now that they're in the database.
+// If pc_uids is given, only those players' records are dropped.
+pub fn journal_commit(mark: u64, pc_uids: Option<&[i64]>) -> FFResult<()> {
+    let Some(journal) = JOURNAL.get() else {
+        return Ok(());
+    };
+    let mut journal = journal.lock().unwrap();
+    journal.commit(mark, pc_uids)
+}
+
+fn read_journal(path: &str) -> FFResult<Option<Vec<JournalRecord>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Couldn't open journal file {}: {}", path, e),
+            ))
+        }
+    };
+    parse_journal(BufReader::new(file)).map(Some)
+}
+
+fn parse_journal(reader: impl BufRead) -> FFResult<Vec<JournalRecord>> {
+    let mut lines = reader.lines().map_while(Result::ok);
+    let header: JournalHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line)
+            .map_err(|e| FFError::build(Severity::Warning, format!("Bad journal header: {}", e)))?,
+        None => return Ok(Vec::new()),
+    };
+    if header.version != JOURNAL_VERSION {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "Journal version {} doesn't match expected version {}",
+                header.version, JOURNAL_VERSION
+            ),
+        ));
+    }
+
+    let mut records = Vec::new();
+    for line in lines {
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                // a crash mid-write can only cut off the last line
+                log(
+                    Severity::Warning,
+                    &format!("Stopped reading journal at a bad record: {}", e),
+                );
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn group_by_player(records: Vec<JournalRecord>) -> Vec<(i64, Vec<JournalOp>)> {
+    let mut order = Vec::new();
+    let mut ops: HashMap<i64, Vec<JournalOp>> = HashMap::new();
+    for record in records {
+        if !ops.contains_key(&record.pc_uid) {
+            order.push(record.pc_uid);
+        }
+        ops.entry(record.pc_uid).or_default().push(record.op);
+    }
+    order
+        .into_iter()
+        .map(|pc_uid| (pc_uid, ops.remove(&pc_uid).unwrap()))
+        .collect()
+}
+
+// Applies a journal left behind by a crash to the database.
+// The journal is only removed once every player has been saved, so a crash
+// partway through just means it gets replayed again next time.
+pub fn journal_replay(path: &str) -> FFResult<()> {
+    let records = match read_journal(path) {
+        Ok(Some(records)) => records,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            set_aside(path);
+            return Err(e);
+        }
+    };
+
+    let players = group_by_player(records);
+    if !players.is_empty() {
+        log(
+            Severity::Info,
+            &format!(
+                "Replaying unsaved progress for {} player(s) from journal {}",
+                players.len(),
+                path
+            ),
+        );
+    }
+
+    let mut failed = HashSet::new();
+    for (pc_uid, ops) in players {
+        let res = db_run_sync(move |db| {
+            edit_offline_player(db, pc_uid, |player| {
+                for op in &ops {
+                    op.apply(player)?;
+                }
+                Ok(())
+            })
+        });
+        if let Err(e) = res {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Couldn't replay journal for player with UID {}: {}",
+                    pc_uid,
+                    e.get_msg()
+                ),
+            );
+            failed.insert(pc_uid);
+        }
+    }
+
+    if !failed.is_empty() {
+        set_aside(path);
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Journal replay failed for {} player(s)", failed.len()),
+        ));
+    }
+
+    if let Err(e) = fs::remove_file(path) {
+        log(
+            Severity::Warning,
+            &format!("Couldn't remove replayed journal {}: {}", path, e),
+        );
+    }
+    Ok(())
+}
+
+// keeps a journal that couldn't be replayed around for manual recovery
+fn set_aside(path: &str) {
+    let kept_path = format!(
+        "{}.{}.failed",
+        path,
+        util::get_timestamp_sec(SystemTime::now())
+    );
+    match fs::rename(path, &kept_path) {
+        Ok(()) => log(
+            Severity::Warning,
+            &format!("Kept unreplayed journal as {}", kept_path),
+        ),
+        Err(e) => log(
+            Severity::Warning,
+            &format!("Couldn't set aside journal {}: {}", path, e),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{enums::ItemType, test_utils::PlayerBuilder};
+
+    fn get_test_records() -> Vec<JournalRecord> {
+        let ops = [
+            (1, JournalOp::Taros { taros: 500 }),
+            (2, JournalOp::Level { level: 7 }),
+            (
+                1,
+                JournalOp::Item {
+                    location: ItemLocation::Inven,
+                    slot_num: 3,
+                    item: Some(Item::new(ItemType::General, 119)),
+                },
+            ),
+            (1, JournalOp::MissionCompleted { mission_id: 12 }),
+            (
+                2,
+                JournalOp::Item {
+                    location: ItemLocation::Equip,
+                    slot_num: 0,
+                    item: None,
+                },
+            ),
+        ];
+        ops.into_iter()
+            .enumerate()
+            .map(|(idx, (pc_uid, op))| JournalRecord {
+                seq: idx as u64 + 1,
+                pc_uid,
+                op,
+            })
+            .collect()
+    }
+
+    fn to_journal_text(version: u32, records: &[JournalRecord]) -> String {
+        let mut text = serde_json::to_string(&JournalHeader { version }).unwrap();
+        for record in records {
+            text.push('\n');
+            text.push_str(&serde_json::to_string(record).unwrap());
+        }
+        text
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let records = get_test_records();
+        let text = to_journal_text(JOURNAL_VERSION, &records);
+        assert_eq!(parse_journal(text.as_bytes()).unwrap(), records);
+
+        // a torn final write is dropped, everything before it is kept
+        let torn = format!("{}\n{{\"seq\":6,\"pc_uid\":1,\"op\":\"ta", text);
+        assert_eq!(parse_journal(torn.as_bytes()).unwrap(), records);
+
+        let old = to_journal_text(JOURNAL_VERSION + 1, &records);
+        assert!(parse_journal(old.as_bytes()).is_err());
+        assert!(parse_journal("".as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_journal_grouping() {
+        let grouped = group_by_player(get_test_records());
+        let uids: Vec<i64> = grouped.iter().map(|(pc_uid, _)| *pc_uid).collect();
+        assert_eq!(uids, vec![1, 2]);
+        assert_eq!(grouped[0].1.len(), 3);
+        assert_eq!(grouped[0].1[0], JournalOp::Taros { taros: 500 });
+        assert_eq!(grouped[1].1[0], JournalOp::Level { level: 7 });
+    }
+
+    #[test]
+    fn test_journal_commit() {
+        let path = std::env::temp_dir()
+            .join(format!("rf_journal_test_{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        let mut journal = Journal {
+            path: path.clone(),
+            writer: Journal::create(&path, Vec::new()).unwrap(),
+            next_seq: 1,
+            records: Vec::new(),
+        };
+        for (pc_uid, taros) in [(1, 100), (2, 200), (1, 300)] {
+            journal.record(pc_uid, JournalOp::Taros { taros }).unwrap();
+        }
+        let mark = journal.next_seq;
+        journal.record(1, JournalOp::Taros { taros: 400 }).unwrap();
+        sync(&mut journal.writer).unwrap();
+        assert_eq!(read_journal(&path).unwrap().unwrap().len(), 4);
+
+        // saving player 2 drops only their records from before the mark
+        journal.commit(mark, Some(&[2])).unwrap();
+        let seqs: Vec<u64> = read_journal(&path)
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|record| record.seq)
+            .collect();
+        assert_eq!(seqs, vec![1, 3, 4]);
+
+        // a full save keeps what came in after the mark, and replaying it
+        // brings a player from the save up to date
+        journal.commit(mark, None).unwrap();
+        let records = read_journal(&path).unwrap().unwrap();
+        assert_eq!(records.len(), 1);
+        let mut player = PlayerBuilder::new(1).taros(300).build();
+        for (pc_uid, ops) in group_by_player(records) {
+            assert_eq!(pc_uid, 1);
+            for op in ops {
+                op.apply(&mut player).unwrap();
+            }
+        }
+        assert_eq!(player.get_taros(), 400);
+
+        // records written after a commit still land in the rewritten file
+        journal.record(2, JournalOp::Taros { taros: 500 }).unwrap();
+        sync(&mut journal.writer).unwrap();
+        assert_eq!(read_journal(&path).unwrap().unwrap().len(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
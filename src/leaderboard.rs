@@ -0,0 +1,144 @@
+use std::{collections::HashMap, sync::Mutex, time::SystemTime};
+
+use crate::{
+    database::db_run_async,
+    error::{log, FFResult, Severity},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaceResult {
+    pub ep_id: i32,
+    pub score: i32,
+    pub ring_count: i32,
+    pub time_sec: i32,
+    pub timestamp: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leaderboard {
+    // best score per player on one infected zone
+    Race { ep_id: i32 },
+    // total mobs defeated per player
+    Kills,
+}
+impl Leaderboard {
+    pub fn get_title(&self) -> String {
+        match self {
+            Leaderboard::Race { ep_id } => format!("Race leaderboard (EP {})", ep_id),
+            Leaderboard::Kills => "Kill leaderboard".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderboardEntry {
+    pub pc_uid: i64,
+    pub name: String,
+    pub score: i64,
+}
+
+// results waiting to be written out to the DB, tagged with the player's UID
+static PENDING_RACE_RESULTS: Mutex<Vec<(i64, RaceResult)>> = Mutex::new(Vec::new());
+// kills are aggregated per player between flushes since there can be a lot of them
+static PENDING_KILLS: Mutex<Option<HashMap<i64, i32>>> = Mutex::new(None);
+
+pub fn leaderboard_queue_race_result(pc_uid: i64, result: RaceResult) {
+    PENDING_RACE_RESULTS.lock().unwrap().push((pc_uid, result));
+}
+
+pub fn leaderboard_queue_kill(pc_uid: i64) {
+    let mut pending = PENDING_KILLS.lock().unwrap();
+    *pending
+        .get_or_insert_with(HashMap::new)
+        .entry(pc_uid)
+        .or_default() += 1;
+}
+
+pub fn leaderboard_flush() -> FFResult<()> {
+    let results = std::mem::take(&mut *PENDING_RACE_RESULTS.lock().unwrap());
+    let kills: Vec<(i64, i32)> = PENDING_KILLS
+        .lock()
+        .unwrap()
+        .take()
+        .map(|kills| kills.into_iter().collect())
+        .unwrap_or_default();
+
+    if !results.is_empty() {
+        let num_results = results.len();
+        let _ = db_run_async(move |db| db.save_race_results(&results));
+        log(
+            Severity::Debug,
+            &format!("Queued {} race results for the DB", num_results),
+        );
+    }
+
+    if !kills.is_empty() {
+        let num_players = kills.len();
+        let _ = db_run_async(move |db| db.add_kill_counts(&kills));
+        log(
+            Severity::Debug,
+            &format!("Queued kill counts for {} players for the DB", num_players),
+        );
+    }
+    Ok(())
+}
+
+pub fn format_leaderboard(
+    board: Leaderboard,
+    entries: &[LeaderboardEntry],
+    personal_best: Option<i64>,
+) -> Vec<String> {
+    let mut lines = vec![board.get_title()];
+    if entries.is_empty() {
+        lines.push("No entries yet".to_string());
+    }
+    for (rank, entry) in entries.iter().enumerate() {
+        lines.push(format!("#{} {}: {}", rank + 1, entry.name, entry.score));
+    }
+    match personal_best {
+        Some(score) => lines.push(format!("Your best: {}", score)),
+        None => lines.push("You have no entry".to_string()),
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_leaderboard() {
+        let entries = vec![
+            LeaderboardEntry {
+                pc_uid: 1,
+                name: "Alpha Bravo".to_string(),
+                score: 300,
+            },
+            LeaderboardEntry {
+                pc_uid: 2,
+                name: "Charlie Delta".to_string(),
+                score: 250,
+            },
+        ];
+        let lines = format_leaderboard(Leaderboard::Kills, &entries, Some(12));
+        assert_eq!(
+            lines,
+            vec![
+                "Kill leaderboard",
+                "#1 Alpha Bravo: 300",
+                "#2 Charlie Delta: 250",
+                "Your best: 12",
+            ]
+        );
+
+        let lines = format_leaderboard(Leaderboard::Race { ep_id: 5 }, &[], None);
+        assert_eq!(
+            lines,
+            vec![
+                "Race leaderboard (EP 5)",
+                "No entries yet",
+                "You have no entry"
+            ]
+        );
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::ops::Add;
 
+use rand::Rng;
 use vecmath::{vec3_add, vec3_len, vec3_scale, vec3_sub, Vector3};
 
 #[macro_export]
@@ -25,18 +26,30 @@ pub mod enums;
 pub mod error;
 pub mod helpers;
 pub mod net;
+pub mod perms;
+pub mod rng;
 pub mod state;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 pub mod timer;
 pub mod util;
 
 pub mod config;
+pub mod currency;
 pub mod database;
+pub mod journal;
+pub mod leaderboard;
 pub mod monitor;
+pub mod report;
 pub mod tabledata;
+pub mod transaction;
+pub mod webhook;
+pub mod wordfilter;
 
 pub mod ai;
 pub mod chunk;
 pub mod entity;
+pub mod group_warp;
 pub mod item;
 pub mod mission;
 pub mod nano;
@@ -83,21 +96,32 @@ impl Position {
         }
     }
 
-    pub fn get_random_around(&self, x_radius: u32, y_radius: u32, z_radius: u32) -> Position {
+    pub fn get_random_around<R: Rng>(
+        &self,
+        rng: &mut R,
+        x_radius: u32,
+        y_radius: u32,
+        z_radius: u32,
+    ) -> Position {
         let x_radius = x_radius as i32;
         let y_radius = y_radius as i32;
         let z_radius = z_radius as i32;
         Position {
-            x: self.x + util::rand_range_inclusive(-x_radius, x_radius),
-            y: self.y + util::rand_range_inclusive(-y_radius, y_radius),
-            z: self.z + util::rand_range_inclusive(-z_radius, z_radius),
+            x: self.x + rng.gen_range(-x_radius..=x_radius),
+            y: self.y + rng.gen_range(-y_radius..=y_radius),
+            z: self.z + rng.gen_range(-z_radius..=z_radius),
         }
     }
 
     pub fn get_unstuck(&self) -> Position {
         const UNSTICK_XY_RANGE: u32 = 200;
         const UNSTICK_Z_BUMP: i32 = 80;
-        let mut nudged = self.get_random_around(UNSTICK_XY_RANGE, UNSTICK_XY_RANGE, 0);
+        let mut nudged = self.get_random_around(
+            &mut rand::thread_rng(),
+            UNSTICK_XY_RANGE,
+            UNSTICK_XY_RANGE,
+            0,
+        );
         nudged.z += UNSTICK_Z_BUMP;
         nudged
     }
@@ -4,9 +4,9 @@ use std::{
 };
 
 use crate::{
-    defines::{SIZEOF_QUESTFLAG_NUMBER, SIZEOF_RQUEST_SLOT},
+    defines::{NANO_QUEST_INDEX, SIZEOF_QUESTFLAG_NUMBER, SIZEOF_RQUEST_SLOT},
     enums::*,
-    error::{panic_log, FFError, FFResult, Severity},
+    error::{FFError, FFResult, Severity},
     net::packet::sRunningQuest,
     tabledata::tdata_get,
     util::Bitfield,
@@ -58,10 +58,52 @@ pub struct TaskDefinition {
     pub delete_qitems: HashSet<i16>,       // m_iDelItemID
     pub barks: Vec<i32>,                   // m_iHBarkerTextID
 }
+impl TaskDefinition {
+    // An empty task with no prerequisites or objectives, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_mock(task_id: i32, mission_id: i32) -> Self {
+        Self {
+            task_id,
+            mission_id,
+            task_type: TaskType::Talk,
+            prereq_npc_type: None,
+            prereq_completed_mission_ids: HashSet::new(),
+            prereq_nano_ids: HashSet::new(),
+            prereq_level: None,
+            prereq_guide: None,
+            prereq_map_num: None,
+            obj_npc_type: None,
+            obj_qitems: HashMap::new(),
+            obj_enemies: HashMap::new(),
+            obj_enemy_id_ordering: Vec::new(),
+            obj_escort_npc_type: None,
+            obj_time_limit: None,
+            fail_task_id: None,
+            fail_qitems: HashMap::new(),
+            succ_task_id: None,
+            succ_qitems: HashMap::new(),
+            succ_reward: None,
+            succ_nano_id: None,
+            given_qitems: HashMap::new(),
+            dropped_qitems: HashMap::new(),
+            delete_qitems: HashSet::new(),
+            barks: Vec::new(),
+        }
+    }
+}
+
+// the load data array has more running quest slots than the journal can display,
+// so only the first few are used
+const NANO_MISSION_SLOT: usize = NANO_QUEST_INDEX as usize;
+const GUIDE_MISSION_SLOT: usize = NANO_MISSION_SLOT + 1;
+const FIRST_WORLD_MISSION_SLOT: usize = GUIDE_MISSION_SLOT + 1;
+const JOURNAL_SLOTS: usize = FIRST_WORLD_MISSION_SLOT + 4;
 
 #[derive(Debug, Clone)]
 pub struct Task {
     task_id: i32,
+    mission_id: i32,
+    mission_type: MissionType,
     pub remaining_enemy_defeats: HashMap<i32, usize>,
     pub fail_time: Option<SystemTime>,
     pub escort_npc_id: Option<i32>,
@@ -70,6 +112,22 @@ pub struct Task {
     pub pending_repair: bool,
 }
 impl Task {
+    // Skips the task definition lookup, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_mock(task_id: i32, mission_id: i32, mission_type: MissionType) -> Self {
+        Self {
+            task_id,
+            mission_id,
+            mission_type,
+            remaining_enemy_defeats: HashMap::new(),
+            fail_time: None,
+            escort_npc_id: None,
+            completed: false,
+            failed: false,
+            pending_repair: false,
+        }
+    }
+
     pub fn get_task_id(&self) -> i32 {
         self.task_id
     }
@@ -85,6 +143,28 @@ impl Task {
             .unwrap()
     }
 
+    pub fn get_mission_id(&self) -> i32 {
+        self.mission_id
+    }
+
+    pub fn get_running_quest(&self) -> sRunningQuest {
+        let task_def = self.get_task_def();
+        let mut quest = sRunningQuest {
+            m_aCurrTaskID: self.task_id,
+            ..Default::default()
+        };
+        let remaining_counts = self.get_remaining_enemy_defeats();
+        for (j, npc_id) in task_def.obj_enemy_id_ordering.iter().enumerate() {
+            quest.m_aKillNPCID[j] = *npc_id;
+            quest.m_aKillNPCCount[j] = remaining_counts[j] as i32;
+        }
+        for (j, (item_id, count)) in task_def.obj_qitems.iter().enumerate() {
+            quest.m_aNeededItemID[j] = *item_id as i32;
+            quest.m_aNeededItemCount[j] = *count as i32;
+        }
+        quest
+    }
+
     pub fn get_remaining_enemy_defeats(&self) -> [usize; 3] {
         let task_def = self.get_task_def();
         let enemy_types = task_def.obj_enemy_id_ordering.as_slice();
@@ -108,8 +188,14 @@ impl Task {
 }
 impl From<&TaskDefinition> for Task {
     fn from(task_def: &TaskDefinition) -> Self {
+        let mission_type = tdata_get()
+            .get_mission_definition(task_def.mission_id)
+            .map(|mission_def| mission_def.mission_type)
+            .unwrap_or(MissionType::Unknown);
         Task {
             task_id: task_def.task_id,
+            mission_id: task_def.mission_id,
+            mission_type,
             remaining_enemy_defeats: task_def.obj_enemies.clone(),
             fail_time: task_def.obj_time_limit.map(|d| SystemTime::now() + d),
             escort_npc_id: None,
@@ -122,76 +208,72 @@ impl From<&TaskDefinition> for Task {
 
 #[derive(Debug, Clone)]
 pub struct MissionJournal {
-    current_nano_mission: Option<Task>,
-    current_guide_mission: Option<Task>,
-    current_world_missions: Vec<Task>,
+    tasks: [Option<Task>; JOURNAL_SLOTS],
     active_mission_slot: Option<usize>,
-    pub completed_mission_flags: Bitfield<i64>,
+    pub completed_mission_flags: Bitfield<i64, { SIZEOF_QUESTFLAG_NUMBER as usize }>,
 }
 impl Default for MissionJournal {
     fn default() -> Self {
         MissionJournal {
-            current_nano_mission: None,
-            current_guide_mission: None,
-            current_world_missions: Vec::new(),
+            tasks: Default::default(),
             active_mission_slot: None,
-            completed_mission_flags: Bitfield::new(SIZEOF_QUESTFLAG_NUMBER as usize),
+            completed_mission_flags: Bitfield::new(),
         }
     }
 }
 impl MissionJournal {
     fn get_task_iter(&self) -> impl Iterator<Item = &Task> {
-        let mut tasks = Vec::new();
-        if let Some(task) = &self.current_nano_mission {
-            tasks.push(task);
-        }
-        if let Some(task) = &self.current_guide_mission {
-            tasks.push(task);
-        }
-        tasks.extend(self.current_world_missions.iter());
-        tasks.into_iter()
+        self.tasks.iter().flatten()
     }
 
     fn get_task_iter_mut(&mut self) -> impl Iterator<Item = &mut Task> {
-        let mut tasks = Vec::new();
-        if let Some(task) = &mut self.current_nano_mission {
-            tasks.push(task);
-        }
-        if let Some(task) = &mut self.current_guide_mission {
-            tasks.push(task);
-        }
-        tasks.extend(self.current_world_missions.iter_mut());
-        tasks.into_iter()
+        self.tasks.iter_mut().flatten()
     }
 
     fn get_current_task_by_idx(&self, idx: usize) -> Option<&Task> {
-        match idx {
-            0 => self.current_nano_mission.as_ref(),
-            1 => self.current_guide_mission.as_ref(),
-            2..=5 => self.current_world_missions.get(idx - 2),
-            _ => panic_log("Invalid mission slot index"),
+        self.tasks.get(idx)?.as_ref()
+    }
+
+    fn get_slot_range(mission_type: MissionType) -> FFResult<std::ops::Range<usize>> {
+        match mission_type {
+            MissionType::Nano => Ok(NANO_MISSION_SLOT..GUIDE_MISSION_SLOT),
+            MissionType::Guide => Ok(GUIDE_MISSION_SLOT..FIRST_WORLD_MISSION_SLOT),
+            MissionType::Normal => Ok(FIRST_WORLD_MISSION_SLOT..JOURNAL_SLOTS),
+            MissionType::Unknown => Err(FFError::build(
+                Severity::Warning,
+                "No journal slots for unknown mission type".to_string(),
+            )),
         }
     }
 
+    fn find_free_slot(&self, mission_type: MissionType) -> FFResult<usize> {
+        Self::get_slot_range(mission_type)?
+            .find(|idx| self.tasks[*idx].is_none())
+            .ok_or_else(|| {
+                FFError::build(
+                    Severity::Warning,
+                    format!("No free journal slots for {:?} missions", mission_type),
+                )
+            })
+    }
+
+    pub fn has_free_slot(&self, mission_type: MissionType) -> bool {
+        self.find_free_slot(mission_type).is_ok()
+    }
+
+    pub fn has_mission(&self, mission_id: i32) -> bool {
+        self.get_task_iter().any(|t| t.mission_id == mission_id)
+    }
+
     pub fn get_current_tasks(&self) -> Vec<Task> {
         self.get_task_iter().cloned().collect()
     }
 
     pub fn get_running_quests(&self) -> [sRunningQuest; SIZEOF_RQUEST_SLOT as usize] {
         let mut running_quests = [sRunningQuest::default(); SIZEOF_RQUEST_SLOT as usize];
-        for (i, quest) in running_quests.iter_mut().enumerate().take(6) {
-            let task = self.get_current_task_by_idx(i);
+        for (idx, task) in self.tasks.iter().enumerate() {
             if let Some(task) = task {
-                let task_def = task.get_task_def();
-                quest.m_aCurrTaskID = task_def.task_id;
-                for (j, (npc_id, count)) in task.remaining_enemy_defeats.iter().enumerate() {
-                    quest.m_aKillNPCID[j] = *npc_id;
-                    quest.m_aKillNPCCount[j] = *count as i32;
-                }
-                for (j, (item_id, count)) in task_def.obj_qitems.iter().enumerate() {
-                    quest.m_aNeededItemID[j] = *item_id as i32;
-                    quest.m_aNeededItemCount[j] = *count as i32;
-                }
+                running_quests[idx] = task.get_running_quest();
             }
         }
         running_quests
@@ -206,25 +288,21 @@ impl MissionJournal {
     pub fn get_active_mission_id(&self) -> Option<i32> {
         let idx = self.active_mission_slot?;
         let active_task = self.get_current_task_by_idx(idx)?;
-        let task_def = active_task.get_task_def();
-        Some(task_def.mission_id)
+        Some(active_task.mission_id)
     }
 
     pub fn get_current_task_ids(&self) -> Vec<i32> {
-        let mut task_ids = Vec::new();
-        for task in self.get_task_iter() {
-            let task_def = task.get_task_def();
-            task_ids.push(task_def.task_id);
-        }
-        task_ids
+        self.get_task_iter().map(|t| t.task_id).collect()
     }
 
     pub fn has_nano_mission(&self) -> bool {
-        self.current_nano_mission.is_some()
+        self.tasks[NANO_MISSION_SLOT].is_some()
     }
 
     pub fn is_mission_completed(&self, mission_id: i32) -> FFResult<bool> {
-        self.completed_mission_flags.get((mission_id - 1) as usize)
+        self.completed_mission_flags
+            .get((mission_id - 1) as usize)
+            .map_err(FFError::from)
     }
 
     pub fn set_mission_completed(&mut self, mission_id: i32) -> FFResult<()> {
@@ -234,16 +312,10 @@ impl MissionJournal {
     }
 
     pub fn set_active_mission_id(&mut self, mission_id: i32) -> FFResult<usize> {
-        let mut current_mission_slot = None;
-        for idx in 0..6 {
-            if let Some(task) = self.get_current_task_by_idx(idx) {
-                let task_def = task.get_task_def();
-                if task_def.mission_id == mission_id {
-                    current_mission_slot = Some(idx);
-                    break;
-                }
-            }
-        }
+        let current_mission_slot = self
+            .tasks
+            .iter()
+            .position(|t| t.as_ref().is_some_and(|t| t.mission_id == mission_id));
 
         match current_mission_slot {
             Some(idx) => {
@@ -286,64 +358,62 @@ impl MissionJournal {
     }
 
     pub fn start_task(&mut self, task: Task) -> FFResult<bool> {
-        let mission_def = task.get_mission_def();
+        let mission_id = task.mission_id;
         let mission_existing_task = self
             .get_task_iter_mut()
-            .find(|t| t.get_task_def().mission_id == mission_def.mission_id);
-        let new_mission = if let Some(existing_task) = mission_existing_task {
+            .find(|t| t.mission_id == mission_id);
+        if let Some(existing_task) = mission_existing_task {
             if !existing_task.completed && !existing_task.failed {
                 return Err(FFError::build(
                     Severity::Warning,
                     format!(
                         "Tried to start task {} while task {} for mission {} is in progress",
-                        task.task_id, existing_task.task_id, mission_def.mission_id
+                        task.task_id, existing_task.task_id, mission_id
                     ),
                 ));
             }
-            *existing_task = task; // replace existing task
-            false
-        } else {
-            match mission_def.mission_type {
-                MissionType::Unknown => {
-                    return Err(FFError::build(
-                        Severity::Warning,
-                        format!(
-                            "Tried to start task {} for unknown mission type",
-                            task.task_id
-                        ),
-                    ))
-                }
-                MissionType::Guide => {
-                    if self.current_guide_mission.is_some() {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            "Guide mission already in progress".to_string(),
-                        ));
-                    }
-                    self.current_guide_mission = Some(task);
-                }
-                MissionType::Nano => {
-                    if self.current_nano_mission.is_some() {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            "Nano mission already in progress".to_string(),
-                        ));
-                    }
-                    self.current_nano_mission = Some(task);
-                }
-                MissionType::Normal => {
-                    if self.current_world_missions.len() >= 4 {
-                        return Err(FFError::build(
-                            Severity::Warning,
-                            "No empty world mission slots".to_string(),
-                        ));
-                    }
-                    self.current_world_missions.push(task);
-                }
-            };
-            true
-        };
-        Ok(new_mission)
+            *existing_task = task; // replace existing task, keeping the slot
+            return Ok(false);
+        }
+
+        let slot = self.find_free_slot(task.mission_type).map_err(|e| {
+            FFError::build(
+                e.get_severity(),
+                format!("Tried to start task {}: {}", task.task_id, e.get_msg()),
+            )
+        })?;
+        self.tasks[slot] = Some(task);
+        Ok(true)
+    }
+
+    // Puts a task back into the slot it was saved from
+    pub fn restore_task(&mut self, task: Task, slot: usize) -> FFResult<()> {
+        if !Self::get_slot_range(task.mission_type)?.contains(&slot) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Journal slot {} can't hold task {} ({:?} mission)",
+                    slot, task.task_id, task.mission_type
+                ),
+            ));
+        }
+        if self.has_mission(task.mission_id) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Mission {} is already in the journal", task.mission_id),
+            ));
+        }
+        if let Some(existing_task) = &self.tasks[slot] {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Journal slot {} already holds task {}",
+                    slot, existing_task.task_id
+                ),
+            ));
+        }
+        self.tasks[slot] = Some(task);
+        Ok(())
     }
 
     pub fn complete_task(&mut self, task_id: i32) -> FFResult<()> {
@@ -389,36 +459,20 @@ impl MissionJournal {
     }
 
     pub fn remove_task(&mut self, task_id: i32) -> FFResult<Task> {
-        for idx in 0..6 {
-            match idx {
-                0 => {
-                    if let Some(task) = &self.current_nano_mission {
-                        if task.get_task_id() == task_id {
-                            return Ok(self.current_nano_mission.take().unwrap());
-                        }
-                    }
-                }
-                1 => {
-                    if let Some(task) = &self.current_guide_mission {
-                        if task.get_task_id() == task_id {
-                            return Ok(self.current_guide_mission.take().unwrap());
-                        }
-                    }
-                }
-                2..=5 => {
-                    if let Some(task) = self.current_world_missions.get(idx - 2) {
-                        if task.get_task_id() == task_id {
-                            return Ok(self.current_world_missions.remove(idx - 2));
-                        }
-                    }
-                }
-                _ => unreachable!(),
-            }
+        let slot = self
+            .tasks
+            .iter()
+            .position(|t| t.as_ref().is_some_and(|t| t.task_id == task_id))
+            .ok_or_else(|| {
+                FFError::build(
+                    Severity::Warning,
+                    format!("Tried to remove task {} that is not in progress", task_id),
+                )
+            })?;
+        if self.active_mission_slot == Some(slot) {
+            self.active_mission_slot = None;
         }
-        Err(FFError::build(
-            Severity::Warning,
-            format!("Tried to remove task {} that is not in progress", task_id),
-        ))
+        Ok(self.tasks[slot].take().unwrap())
     }
 
     pub fn mark_enemy_defeated(&mut self, enemy_type: i32) -> (HashSet<i32>, bool) {
@@ -450,3 +504,113 @@ impl Default for sRunningQuest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_task(task_id: i32, mission_id: i32, mission_type: MissionType) -> Task {
+        Task::new_mock(task_id, mission_id, mission_type)
+    }
+
+    fn get_slot_task_ids(journal: &MissionJournal) -> Vec<i32> {
+        (0..JOURNAL_SLOTS)
+            .map(|idx| {
+                journal
+                    .get_current_task_by_idx(idx)
+                    .map(|t| t.get_task_id())
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_journal_capacity() {
+        let mut journal = MissionJournal::default();
+        for mission_id in 1..=4 {
+            let task = make_task(mission_id * 10, mission_id, MissionType::Normal);
+            assert!(journal.start_task(task).unwrap());
+        }
+        assert!(!journal.has_free_slot(MissionType::Normal));
+        assert!(journal
+            .start_task(make_task(50, 5, MissionType::Normal))
+            .is_err());
+        assert_eq!(journal.get_current_task_ids(), vec![10, 20, 30, 40]);
+
+        // nano and guide missions always have room
+        assert!(journal
+            .start_task(make_task(60, 6, MissionType::Nano))
+            .unwrap());
+        assert!(journal
+            .start_task(make_task(70, 7, MissionType::Guide))
+            .unwrap());
+        assert!(journal
+            .start_task(make_task(80, 8, MissionType::Nano))
+            .is_err());
+        assert!(journal
+            .start_task(make_task(90, 9, MissionType::Guide))
+            .is_err());
+
+        // next task in a running mission replaces it in place
+        journal.complete_task(20).unwrap();
+        assert!(!journal
+            .start_task(make_task(21, 2, MissionType::Normal))
+            .unwrap());
+        assert_eq!(get_slot_task_ids(&journal), vec![60, 70, 10, 21, 30, 40]);
+    }
+
+    #[test]
+    fn test_journal_slots() {
+        let mut journal = MissionJournal::default();
+        for mission_id in 1..=3 {
+            let task = make_task(mission_id * 10, mission_id, MissionType::Normal);
+            journal.start_task(task).unwrap();
+        }
+        journal.set_active_mission_id(3).unwrap();
+
+        // removing a mission leaves the others where they are
+        journal.remove_task(20).unwrap();
+        assert_eq!(get_slot_task_ids(&journal), vec![0, 0, 10, 0, 30, 0]);
+        assert_eq!(journal.get_active_mission_id(), Some(3));
+
+        // new missions fill the first free slot
+        journal
+            .start_task(make_task(40, 4, MissionType::Normal))
+            .unwrap();
+        assert_eq!(get_slot_task_ids(&journal), vec![0, 0, 10, 40, 30, 0]);
+
+        // removing the active mission clears it
+        journal.remove_task(30).unwrap();
+        assert_eq!(journal.get_active_mission_id(), None);
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let mut journal = MissionJournal::default();
+        journal
+            .start_task(make_task(10, 1, MissionType::Nano))
+            .unwrap();
+        for mission_id in 2..=5 {
+            let task = make_task(mission_id * 10, mission_id, MissionType::Normal);
+            journal.start_task(task).unwrap();
+        }
+        journal.remove_task(30).unwrap();
+        let saved = get_slot_task_ids(&journal);
+
+        // reload each task into the slot it was saved from
+        let mut loaded = MissionJournal::default();
+        for (slot, task) in journal.tasks.iter().enumerate() {
+            if let Some(task) = task {
+                loaded.restore_task(task.clone(), slot).unwrap();
+            }
+        }
+        assert_eq!(get_slot_task_ids(&loaded), saved);
+
+        // tasks can't be restored into slots for a different mission type
+        let mut loaded = MissionJournal::default();
+        let nano_task = make_task(10, 1, MissionType::Nano);
+        assert!(loaded.restore_task(nano_task.clone(), 2).is_err());
+        loaded.restore_task(nano_task.clone(), 0).unwrap();
+        assert!(loaded.restore_task(nano_task, 0).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
+    fmt::Display,
     io::Write as _,
     net::TcpListener,
     sync::{
@@ -15,7 +16,25 @@ use crate::error::{log, FFError, FFResult, Severity};
 
 pub type MonitorEvent = Event;
 
-static FEED: OnceLock<Sender<MonitorEvent>> = OnceLock::new();
+// A key/value line for state the ffmonitor protocol has no event type for,
+// written after the regular events of an update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorStat {
+    pub key: String,
+    pub value: String,
+}
+impl Display for MonitorStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "stat {} {}", self.key, self.value)
+    }
+}
+
+enum FeedItem {
+    Event(MonitorEvent),
+    Stat(MonitorStat),
+}
+
+static FEED: OnceLock<Sender<FeedItem>> = OnceLock::new();
 static FLUSH_SIGNAL: OnceLock<Sender<()>> = OnceLock::new();
 
 pub fn monitor_init(addr: String) {
@@ -28,9 +47,20 @@ pub fn monitor_init(addr: String) {
 }
 
 pub fn monitor_queue(event: MonitorEvent) {
+    feed_send(FeedItem::Event(event));
+}
+
+pub fn monitor_queue_stat(key: String, value: impl Display) {
+    feed_send(FeedItem::Stat(MonitorStat {
+        key,
+        value: value.to_string(),
+    }));
+}
+
+fn feed_send(item: FeedItem) {
     // for ease of use, it's okay to call this function if the monitor is not initialized
     if let Some(feed) = FEED.get() {
-        if feed.send(event).is_err() {
+        if feed.send(item).is_err() {
             log(Severity::Warning, "Failed to queue monitor event");
         }
     }
@@ -52,7 +82,7 @@ pub fn monitor_flush() -> FFResult<()> {
     Ok(())
 }
 
-fn monitor_thread(frx: Receiver<MonitorEvent>, srx: Receiver<()>, addr: String) {
+fn monitor_thread(frx: Receiver<FeedItem>, srx: Receiver<()>, addr: String) {
     let listener = match TcpListener::bind(&addr) {
         Ok(listener) => listener,
         Err(e) => {
@@ -95,12 +125,20 @@ fn monitor_thread(frx: Receiver<MonitorEvent>, srx: Receiver<()>, addr: String)
         }
 
         let mut update = MonitorUpdate::default();
-        while let Ok(event) = frx.try_recv() {
-            update.add_event(event);
+        let mut stats = Vec::new();
+        while let Ok(item) = frx.try_recv() {
+            match item {
+                FeedItem::Event(event) => update.add_event(event),
+                FeedItem::Stat(stat) => stats.push(stat),
+            }
+        }
+        let mut payload = update.to_string();
+        for stat in stats {
+            payload.push_str(&stat.to_string());
         }
 
         for (client_addr, client) in clients.iter_mut() {
-            if client.write_all(&update.to_string().into_bytes()).is_err() {
+            if client.write_all(payload.as_bytes()).is_err() {
                 to_disconnect.insert(*client_addr);
             }
         }
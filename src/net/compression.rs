@@ -0,0 +1,225 @@
+use std::io::{Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::error::{FFError, FFResult, Severity};
+
+// Bit in the connect handshake's iCapabilities
+pub const CAPABILITY_COMPRESSION: i32 = 1 << 0;
+
+// Every frame on a compressed link starts with one of these
+const FRAME_RAW: u8 = 0;
+const FRAME_COMPRESSED: u8 = 1;
+// flag, original length
+const COMPRESSED_HEADER_SIZE: usize = 1 + 4;
+pub const FRAME_OVERHEAD: usize = 1;
+
+// Bytes in and out of a compressed link, in one direction
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompressionStats {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub frames: u64,
+    pub frames_compressed: u64,
+}
+impl CompressionStats {
+    pub fn record(&mut self, payload_len: usize, frame_len: usize, compressed: bool) {
+        self.bytes_before += payload_len as u64;
+        self.bytes_after += frame_len as u64;
+        self.frames += 1;
+        if compressed {
+            self.frames_compressed += 1;
+        }
+    }
+
+    // Wire bytes as a percentage of payload bytes
+    pub fn get_ratio(&self) -> f64 {
+        if self.bytes_before == 0 {
+            return 100.0;
+        }
+        self.bytes_after as f64 / self.bytes_before as f64 * 100.0
+    }
+}
+
+// Wraps a payload for a compressed link.
+// Payloads under the threshold, or that don't shrink, go out raw.
+// Returns the frame and whether it was compressed.
+pub fn encode_frame(payload: &[u8], threshold: usize) -> (Vec<u8>, bool) {
+    if payload.len() >= threshold {
+        let compressed = compress(payload);
+        if COMPRESSED_HEADER_SIZE + compressed.len() < FRAME_OVERHEAD + payload.len() {
+            let mut frame = Vec::with_capacity(COMPRESSED_HEADER_SIZE + compressed.len());
+            frame.push(FRAME_COMPRESSED);
+            frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            frame.extend_from_slice(&compressed);
+            return (frame, true);
+        }
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_OVERHEAD + payload.len());
+    frame.push(FRAME_RAW);
+    frame.extend_from_slice(payload);
+    (frame, false)
+}
+
+// Unwraps a frame from a compressed link.
+// Anything malformed means the link can't be trusted anymore, so errors disconnect.
+// Returns the payload and whether it was compressed.
+pub fn decode_frame(frame: &[u8], max_len: usize) -> FFResult<(Vec<u8>, bool)> {
+    let Some((&flag, body)) = frame.split_first() else {
+        return Err(bad_frame("empty frame".to_string()));
+    };
+
+    match flag {
+        FRAME_RAW => {
+            if body.len() > max_len {
+                return Err(bad_frame(format!(
+                    "raw payload too big ({} > {})",
+                    body.len(),
+                    max_len
+                )));
+            }
+            Ok((body.to_vec(), false))
+        }
+        FRAME_COMPRESSED => {
+            if frame.len() < COMPRESSED_HEADER_SIZE {
+                return Err(bad_frame(format!(
+                    "header truncated ({} bytes)",
+                    frame.len()
+                )));
+            }
+            let raw_len = u32::from_le_bytes(frame[1..5].try_into().unwrap()) as usize;
+            if raw_len > max_len {
+                return Err(bad_frame(format!(
+                    "decompressed size too big ({} > {})",
+                    raw_len, max_len
+                )));
+            }
+
+            let payload = decompress(&frame[COMPRESSED_HEADER_SIZE..], raw_len)?;
+            Ok((payload, true))
+        }
+        _ => Err(bad_frame(format!("unknown flag {}", flag))),
+    }
+}
+
+fn bad_frame(reason: String) -> FFError {
+    FFError::build_dc(
+        Severity::Warning,
+        format!("Bad compressed frame: {}", reason),
+    )
+}
+
+fn compress(payload: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    // writing to a Vec can't fail
+    encoder.write_all(payload).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decompress(data: &[u8], raw_len: usize) -> FFResult<Vec<u8>> {
+    let mut payload = Vec::with_capacity(raw_len);
+    // one byte more than expected is enough to tell it's too big
+    DeflateDecoder::new(data)
+        .take(raw_len as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|e| bad_frame(e.to_string()))?;
+    if payload.len() != raw_len {
+        return Err(bad_frame(format!(
+            "decompressed to {} bytes, expected {}",
+            payload.len(),
+            raw_len
+        )));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    fn round_trip(payload: &[u8], threshold: usize) -> bool {
+        let (frame, compressed) = encode_frame(payload, threshold);
+        let (decoded, was_compressed) = decode_frame(&frame, payload.len()).unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(was_compressed, compressed);
+        compressed
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        // mostly-empty structs are what the inter-server packets look like
+        let mut payload = vec![0_u8; 3000];
+        payload[..4].copy_from_slice(&0x32000016_u32.to_le_bytes());
+        payload[100..108].copy_from_slice(&1234_i64.to_le_bytes());
+        payload[2000..2050].fill(7);
+        assert!(round_trip(&payload, 128));
+        assert!(encode_frame(&payload, 128).0.len() < payload.len() / 10);
+
+        let pattern: Vec<u8> = (0..2000).map(|i| (i % 37) as u8).collect();
+        assert!(round_trip(&pattern, 128));
+
+        // random bytes don't shrink, so they go out raw
+        let noise: Vec<u8> = (0..2000).map(|_| thread_rng().gen()).collect();
+        assert!(!round_trip(&noise, 128));
+
+        assert!(!round_trip(&[], 0));
+        assert!(!round_trip(&[1, 2, 3], 0));
+    }
+
+    #[test]
+    fn test_frame_threshold() {
+        let payload = vec![0_u8; 100];
+        let (frame, compressed) = encode_frame(&payload, 128);
+        assert!(!compressed);
+        assert_eq!(frame.len(), payload.len() + FRAME_OVERHEAD);
+        assert!(round_trip(&payload, 100));
+
+        let mut stats = CompressionStats::default();
+        stats.record(1000, 100, true);
+        stats.record(50, 51, false);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.frames_compressed, 1);
+        assert_eq!(stats.bytes_before, 1050);
+        assert_eq!(stats.bytes_after, 151);
+    }
+
+    #[test]
+    fn test_frame_corrupted() {
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 10) as u8).collect();
+        let (frame, compressed) = encode_frame(&payload, 0);
+        assert!(compressed);
+
+        // flipped byte in the compressed data
+        let mut bad = frame.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+        assert!(decode_frame(&bad, 4096).unwrap_err().should_dc());
+
+        // wrong original length
+        let mut bad = frame.clone();
+        bad[1] ^= 1;
+        assert!(decode_frame(&bad, 4096).is_err());
+
+        // truncated
+        assert!(decode_frame(&frame[..frame.len() / 2], 4096).is_err());
+        assert!(decode_frame(&frame[..4], 4096).is_err());
+        assert!(decode_frame(&[], 4096).is_err());
+
+        // unknown flag
+        let mut bad = frame.clone();
+        bad[0] = 2;
+        assert!(decode_frame(&bad, 4096).is_err());
+
+        // claims to be bigger than the input buffer
+        assert!(decode_frame(&frame, payload.len() - 1).is_err());
+        let (raw, _) = encode_frame(&payload, usize::MAX);
+        assert!(decode_frame(&raw, payload.len() - 1).is_err());
+
+        // not deflate data at all
+        let bad = [FRAME_COMPRESSED, 8, 0, 0, 0, 0xFF, 0xFF, 0xFF];
+        assert!(decode_frame(&bad, 4096).is_err());
+    }
+}
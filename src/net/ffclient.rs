@@ -1,17 +1,21 @@
 use std::{
-    io::{Read, Write},
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
     mem::size_of,
     net::{SocketAddr, TcpStream},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
+    config::config_get,
+    defines::LATENCY_SAMPLE_COUNT,
     error::{log, panic_log, FFError, FFResult, Severity},
     net::{struct_to_bytes, PACKET_BUFFER_SIZE, SILENCED_PACKETS},
 };
 
 use super::{
     bytes_to_struct,
+    compression::{decode_frame, encode_frame, CompressionStats, FRAME_OVERHEAD},
     crypto::{
         decrypt_payload, encrypt_payload, AuthChallenge, EncryptionMode, CRYPTO_KEY_SIZE,
         DEFAULT_KEY,
@@ -58,6 +62,15 @@ impl Default for PacketBuffer {
     }
 }
 impl PacketBuffer {
+    // A buffer holding one already-decrypted payload, ready to be read back
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_payload(payload: &[u8]) -> Self {
+        let mut buf = Self::default();
+        buf.buf[..payload.len()].copy_from_slice(payload);
+        buf.len = payload.len();
+        buf
+    }
+
     pub fn reset(&mut self) {
         self.buf.fill(0);
         self.ptr = 0;
@@ -96,6 +109,33 @@ impl PacketBuffer {
                 ),
             ));
         }
+        self.fetch_packet(pkt_id)
+    }
+
+    // Reads whatever packet is buffered as T without checking its ID.
+    // Only for handlers shared by packets with the same layout; get_packet catches wrong reads.
+    pub fn get_packet_unchecked<T: FFPacket>(&mut self) -> FFResult<&T> {
+        self.fetched_packet_id = None;
+        let buffered_pkt_id = self.peek_packet_id()?;
+        self.fetch_packet(buffered_pkt_id)
+    }
+
+    fn fetch_packet<T: FFPacket>(&mut self, pkt_id: PacketID) -> FFResult<&T> {
+        debug_assert_eq!(self.ptr, 0, "{:?} fetched more than once", pkt_id);
+
+        // catch truncated packets here rather than as garbage fields in the handler
+        let expected_len = 4 + size_of::<T>();
+        let received_len = self.len - self.ptr;
+        if received_len < expected_len {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "{:?} is truncated: {} bytes came in, expected at least {}",
+                    pkt_id, received_len, expected_len
+                ),
+            ));
+        }
+
         self.fetched_packet_id = Some(pkt_id);
         self.ptr += 4;
         self.get_struct()
@@ -166,38 +206,277 @@ impl PacketBuffer {
     }
 }
 
+pub struct OutboundQueue {
+    buf: VecDeque<u8>,
+    max_len: usize,
+}
+impl OutboundQueue {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            max_len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn push(&mut self, dat: &[u8]) -> FFResult<()> {
+        if self.buf.len() + dat.len() > self.max_len {
+            return Err(FFError::build_dc(
+                Severity::Warning,
+                format!(
+                    "Outbound queue full ({} + {} > {} bytes)",
+                    self.buf.len(),
+                    dat.len(),
+                    self.max_len
+                ),
+            ));
+        }
+        self.buf.extend(dat);
+        Ok(())
+    }
+
+    // Writes as much as the stream will take without blocking.
+    // Returns whether the queue was fully drained.
+    pub fn drain_to(&mut self, stream: &mut impl Write) -> FFResult<bool> {
+        while !self.buf.is_empty() {
+            let (front, _) = self.buf.as_slices();
+            match stream.write(front) {
+                Ok(0) => {
+                    return Err(FFError::from_io_err(ErrorKind::WriteZero.into()));
+                }
+                Ok(written) => {
+                    self.buf.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(FFError::from_io_err(e)),
+            }
+        }
+        Ok(true)
+    }
+}
+
+// Round-trip times measured with live checks, over the last few checks
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    sent_time: Option<SystemTime>,
+    samples: VecDeque<Duration>,
+}
+impl LatencyStats {
+    fn on_live_check_sent(&mut self, time: SystemTime) {
+        self.sent_time = Some(time);
+    }
+
+    // Returns the round-trip time, if a live check was waiting on this reply
+    fn on_live_check_reply(&mut self, time: SystemTime) -> Option<Duration> {
+        let sent_time = self.sent_time.take()?;
+        let rtt = time.duration_since(sent_time).unwrap_or_default();
+        if self.samples.len() == LATENCY_SAMPLE_COUNT {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        Some(rtt)
+    }
+
+    pub fn get_average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+
+    pub fn get_max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    // Average and max in milliseconds, or -1 for both if nothing was measured yet
+    pub fn get_millis(&self) -> (i32, i32) {
+        let to_millis = |rtt: Option<Duration>| rtt.map_or(-1, |rtt| rtt.as_millis() as i32);
+        (to_millis(self.get_average()), to_millis(self.get_max()))
+    }
+}
+
+// Requests a legit client would never send, over the last strike window
+#[derive(Debug, Default)]
+struct StrikeCounter {
+    strikes: VecDeque<SystemTime>,
+    exempt: bool,
+}
+impl StrikeCounter {
+    // Returns how many strikes are still within the window, counting the new one
+    fn add(&mut self, time: SystemTime, window: Duration) -> usize {
+        while let Some(&oldest) = self.strikes.front() {
+            if time.duration_since(oldest).unwrap_or_default() < window {
+                break;
+            }
+            self.strikes.pop_front();
+        }
+        self.strikes.push_back(time);
+        self.strikes.len()
+    }
+}
+
+// Packets on an inter-server connection that negotiated compression go in envelopes
+#[derive(Debug)]
+pub struct CompressionLink {
+    threshold: usize,
+    pub sent: CompressionStats,
+    pub received: CompressionStats,
+}
+
+// Reads into buf until it's full, picking up where the last call left off.
+// Returns whether buf was filled.
+fn read_partial(stream: &mut impl Read, buf: &mut [u8], progress: &mut usize) -> FFResult<bool> {
+    while *progress < buf.len() {
+        match stream.read(&mut buf[*progress..]) {
+            Ok(0) => {
+                return Err(FFError::from_io_err(ErrorKind::UnexpectedEof.into()));
+            }
+            Ok(read) => *progress += read,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FFError::from_io_err(e)),
+        }
+    }
+    Ok(true)
+}
+
+// The connection a client's packets go over
+pub enum ClientStream {
+    Tcp(TcpStream),
+    #[cfg(any(test, feature = "test-utils"))]
+    Memory(MemoryStream),
+}
+impl ClientStream {
+    // The socket the poller watches
+    pub fn get_tcp_stream(&self) -> &TcpStream {
+        match self {
+            ClientStream::Tcp(stream) => stream,
+            #[cfg(any(test, feature = "test-utils"))]
+            ClientStream::Memory(_) => panic_log("In-memory streams can't be polled"),
+        }
+    }
+}
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => stream.read(buf),
+            #[cfg(any(test, feature = "test-utils"))]
+            ClientStream::Memory(stream) => stream.read(buf),
+        }
+    }
+}
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => stream.write(buf),
+            #[cfg(any(test, feature = "test-utils"))]
+            ClientStream::Memory(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.flush(),
+            #[cfg(any(test, feature = "test-utils"))]
+            ClientStream::Memory(stream) => stream.flush(),
+        }
+    }
+}
+
+// Stands in for a socket in tests.
+// Reads come out of incoming like a non-blocking socket; writes pile up in written.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug, Default)]
+pub struct MemoryStream {
+    pub incoming: VecDeque<u8>,
+    pub written: Vec<u8>,
+}
+#[cfg(any(test, feature = "test-utils"))]
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.incoming.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.incoming.read(buf)
+    }
+}
+#[cfg(any(test, feature = "test-utils"))]
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct FFClient {
-    pub sock: TcpStream,
+    pub sock: ClientStream,
     addr: SocketAddr,
+    sz_buf: [u8; 4],
+    read_progress: usize,
     waiting_data_len: Option<usize>,
     in_buf: PacketBuffer,
+    in_frame: Vec<u8>,
     out_buf: PacketBuffer,
+    out_queue: OutboundQueue,
+    compression: Option<CompressionLink>,
     pub e_key: [u8; CRYPTO_KEY_SIZE],
     pub fe_key: [u8; CRYPTO_KEY_SIZE],
     pub enc_mode: EncryptionMode,
     pub client_type: ClientType,
     pub last_heartbeat: SystemTime,
     pub live_check_time: Option<SystemTime>,
+    latency: LatencyStats,
+    strikes: StrikeCounter,
     should_dc: bool,
+    handler_panicked: bool,
     ignore_packets: bool,
 }
 
 impl FFClient {
     pub fn new(conn_data: (TcpStream, SocketAddr)) -> Self {
+        Self::with_stream(ClientStream::Tcp(conn_data.0), conn_data.1)
+    }
+
+    // A client with no socket behind it, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_in_memory(addr: SocketAddr) -> Self {
+        Self::with_stream(ClientStream::Memory(MemoryStream::default()), addr)
+    }
+
+    fn with_stream(sock: ClientStream, addr: SocketAddr) -> Self {
         let default_key: [u8; CRYPTO_KEY_SIZE] = DEFAULT_KEY.try_into().unwrap();
         Self {
-            sock: conn_data.0,
-            addr: conn_data.1,
+            sock,
+            addr,
+            sz_buf: [0; 4],
+            read_progress: 0,
             waiting_data_len: None,
             in_buf: PacketBuffer::default(),
+            in_frame: Vec::new(),
             out_buf: PacketBuffer::default(),
+            out_queue: OutboundQueue::new(config_get().general.max_outbound_bytes.get()),
+            compression: None,
             e_key: default_key,
             fe_key: default_key,
             enc_mode: EncryptionMode::EKey,
             client_type: ClientType::Unknown,
             last_heartbeat: SystemTime::now(),
             live_check_time: None,
+            latency: LatencyStats::default(),
+            strikes: StrikeCounter::default(),
             should_dc: false,
+            handler_panicked: false,
             ignore_packets: false,
         }
     }
@@ -210,10 +489,148 @@ impl FFClient {
         self.should_dc = true;
     }
 
+    // A handler panicked partway through one of this client's packets,
+    // so whatever it was in the middle of changing can't be trusted
+    pub fn on_handler_panic(&mut self) {
+        self.handler_panicked = true;
+        self.disconnect();
+    }
+
+    pub fn has_handler_panicked(&self) -> bool {
+        self.handler_panicked
+    }
+
     pub fn get_addr(&self) -> String {
         self.addr.to_string()
     }
 
+    pub fn get_latency(&self) -> &LatencyStats {
+        &self.latency
+    }
+
+    pub fn on_live_check_sent(&mut self, time: SystemTime) {
+        self.latency.on_live_check_sent(time);
+    }
+
+    pub fn on_live_check_reply(&mut self, time: SystemTime) {
+        let Some(rtt) = self.latency.on_live_check_reply(time) else {
+            return;
+        };
+        log(
+            Severity::Debug,
+            &format!(
+                "Live check RTT for {}: {}ms",
+                self.get_addr(),
+                rtt.as_millis()
+            ),
+        );
+
+        let threshold = config_get().general.latency_warn_threshold.get();
+        let (avg_rtt, _) = self.latency.get_millis();
+        if threshold > 0 && avg_rtt as u64 > threshold {
+            log(
+                Severity::Warning,
+                &format!(
+                    "High latency for {}: {}ms average over the last {} live check(s)",
+                    self.get_addr(),
+                    avg_rtt,
+                    self.latency.samples.len()
+                ),
+            );
+        }
+    }
+
+    // Only for login server <-> shard server connections, once both ends agreed to it.
+    // Everything sent or received after this is framed, so both ends have to switch
+    // at the same point in the conversation.
+    pub fn enable_compression(&mut self, threshold: usize) -> FFResult<()> {
+        if !matches!(
+            self.client_type,
+            ClientType::LoginServer | ClientType::ShardServer(_)
+        ) {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Tried to enable compression for {:?}", self.client_type),
+            ));
+        }
+
+        self.compression = Some(CompressionLink {
+            threshold,
+            sent: CompressionStats::default(),
+            received: CompressionStats::default(),
+        });
+        Ok(())
+    }
+
+    pub fn get_compression(&self) -> Option<&CompressionLink> {
+        self.compression.as_ref()
+    }
+
+    pub fn log_compression_stats(&self) {
+        let Some(link) = &self.compression else {
+            return;
+        };
+        log(
+            Severity::Info,
+            &format!(
+                "Compression for {}: sent {} -> {} bytes ({:.1}%, {}/{} frames compressed), received {} -> {} bytes ({:.1}%, {}/{} frames compressed)",
+                self.get_addr(),
+                link.sent.bytes_before,
+                link.sent.bytes_after,
+                link.sent.get_ratio(),
+                link.sent.frames_compressed,
+                link.sent.frames,
+                link.received.bytes_after,
+                link.received.bytes_before,
+                link.received.get_ratio(),
+                link.received.frames_compressed,
+                link.received.frames,
+            ),
+        );
+    }
+
+    pub fn set_strike_exempt(&mut self, exempt: bool) {
+        self.strikes.exempt = exempt;
+    }
+
+    // Handlers call this when they reject a request no legit client would send.
+    // Ordinary failures, like a full inventory, shouldn't count.
+    pub fn strike(&mut self, reason: &str) {
+        let limit = config_get().general.strike_limit.get();
+        let window = Duration::from_secs(config_get().general.strike_window.get());
+        self.add_strike(reason, SystemTime::now(), limit, window);
+    }
+
+    fn add_strike(&mut self, reason: &str, time: SystemTime, limit: usize, window: Duration) {
+        if limit == 0 || self.strikes.exempt {
+            return;
+        }
+
+        let count = self.strikes.add(time, window);
+        log(
+            Severity::Info,
+            &format!(
+                "Strike {}/{} for {}: {}",
+                count,
+                limit,
+                self.get_addr(),
+                reason
+            ),
+        );
+        if count > limit {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Disconnecting {} for {} strikes within {}s",
+                    self.get_addr(),
+                    count,
+                    window.as_secs()
+                ),
+            );
+            self.disconnect();
+        }
+    }
+
     pub fn get_fe_key_uint(&self) -> u64 {
         u64::from_le_bytes(self.fe_key)
     }
@@ -309,45 +726,77 @@ impl FFClient {
         self.in_buf.get_packet(pkt_id)
     }
 
+    pub fn get_packet_unchecked<T: FFPacket>(&mut self) -> FFResult<&T> {
+        self.in_buf.get_packet_unchecked()
+    }
+
     pub fn get_struct<T: FFPacket>(&mut self) -> FFResult<&T> {
         self.in_buf.get_struct()
     }
 
-    pub fn read_payload(&mut self) -> FFResult<()> {
+    // Returns whether a full packet is ready to be handled
+    pub fn read_payload(&mut self) -> FFResult<bool> {
         self.last_heartbeat = SystemTime::now();
         self.live_check_time = None;
 
         if self.waiting_data_len.is_none() {
             // read the size
-            let mut sz_buf: [u8; 4] = [0; 4];
-            self.sock
-                .read_exact(&mut sz_buf)
-                .map_err(FFError::from_io_err)?;
-            let sz: usize = u32::from_le_bytes(sz_buf) as usize;
+            if !read_partial(&mut self.sock, &mut self.sz_buf, &mut self.read_progress)? {
+                return Ok(false);
+            }
+            let sz: usize = u32::from_le_bytes(self.sz_buf) as usize;
+            self.read_progress = 0;
             self.waiting_data_len = Some(sz);
         }
 
         let sz = self.waiting_data_len.unwrap();
-        if sz > PACKET_BUFFER_SIZE {
+        let max_sz = match self.compression {
+            Some(_) => PACKET_BUFFER_SIZE + FRAME_OVERHEAD,
+            None => PACKET_BUFFER_SIZE,
+        };
+        if sz > max_sz {
             return Err(FFError::build_dc(
                 Severity::Warning,
                 format!(
                     "Payload bigger than input buffer ({} > {}); disconnecting client",
-                    sz, PACKET_BUFFER_SIZE
+                    sz, max_sz
                 ),
             ));
         }
 
         // read the packet
-        let buf: &mut [u8] = &mut self.in_buf.buf[..sz];
-        self.sock.read_exact(buf).map_err(FFError::from_io_err)?;
-        self.waiting_data_len = None;
+        let sz = match &mut self.compression {
+            None => {
+                let buf: &mut [u8] = &mut self.in_buf.buf[..sz];
+                if !read_partial(&mut self.sock, buf, &mut self.read_progress)? {
+                    return Ok(false);
+                }
+                self.read_progress = 0;
+                self.waiting_data_len = None;
+
+                // decrypt the packet (client always encrypts with E key)
+                decrypt_payload(buf, &self.e_key);
+                sz
+            }
+            Some(link) => {
+                self.in_frame.resize(sz, 0);
+                if !read_partial(&mut self.sock, &mut self.in_frame, &mut self.read_progress)? {
+                    return Ok(false);
+                }
+                self.read_progress = 0;
+                self.waiting_data_len = None;
+
+                // the envelope is encrypted as a whole
+                decrypt_payload(&mut self.in_frame, &self.e_key);
+                let (payload, compressed) = decode_frame(&self.in_frame, PACKET_BUFFER_SIZE)?;
+                link.received.record(payload.len(), sz, compressed);
+                self.in_buf.buf[..payload.len()].copy_from_slice(&payload);
+                payload.len()
+            }
+        };
         self.in_buf.ptr = 0;
         self.in_buf.len = sz;
 
-        // decrypt the packet (client always encrypts with E key)
-        decrypt_payload(buf, &self.e_key);
-
         let id = self.peek_packet_id()?;
 
         // discard packet if we're ignoring them for this client,
@@ -368,7 +817,12 @@ impl FFClient {
                 &format!("{} sent {:?}", self.get_addr(), id),
             );
         }
-        Ok(())
+        Ok(true)
+    }
+
+    // Drops a packet that was being built but never sent
+    pub fn discard_unflushed(&mut self) {
+        self.out_buf.reset();
     }
 
     pub fn flush(&mut self) -> FFResult<()> {
@@ -377,30 +831,58 @@ impl FFClient {
     }
 
     pub fn flush_exact(&mut self, sz: usize) -> FFResult<()> {
-        // send the size
         assert!(sz <= PACKET_BUFFER_SIZE);
 
-        // send the size unencrypted
-        let sz_buf: [u8; 4] = u32::to_le_bytes(sz as u32);
-        self.sock.write_all(&sz_buf).map_err(FFError::from_io_err)?;
+        let mut envelope = None;
+        if let Some(link) = &mut self.compression {
+            let (env, compressed) = encode_frame(&self.out_buf.buf[..sz], link.threshold);
+            link.sent.record(sz, env.len(), compressed);
+            envelope = Some(env);
+        }
+        let send_buf: &mut [u8] = match &mut envelope {
+            Some(env) => env.as_mut_slice(),
+            None => &mut self.out_buf.buf[..sz],
+        };
 
-        let send_buf = &mut self.out_buf.buf[..sz];
+        // size goes unencrypted
+        let mut frame = Vec::with_capacity(4 + send_buf.len());
+        frame.extend_from_slice(&u32::to_le_bytes(send_buf.len() as u32));
 
         // encrypt the payload (client decrypts with either E or FE key)
         match self.enc_mode {
             EncryptionMode::EKey => encrypt_payload(send_buf, &self.e_key),
             EncryptionMode::FEKey => encrypt_payload(send_buf, &self.fe_key),
         }
+        frame.extend_from_slice(send_buf);
+        self.out_buf.reset();
 
-        // send the payload
-        self.sock
-            .write_all(send_buf)
-            .map_err(FFError::from_io_err)?;
+        if let Err(e) = self.out_queue.push(&frame) {
+            if !self.should_dc {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Client {} isn't keeping up with outbound data; disconnecting",
+                        self.get_addr()
+                    ),
+                );
+                self.disconnect();
+            }
+            return Err(e);
+        }
 
-        self.out_buf.reset();
+        // the poll loop will pick up anything that doesn't go out now
+        self.send_queued()?;
         Ok(())
     }
 
+    pub fn has_queued_output(&self) -> bool {
+        !self.out_queue.is_empty()
+    }
+
+    pub fn send_queued(&mut self) -> FFResult<bool> {
+        self.out_queue.drain_to(&mut self.sock)
+    }
+
     pub fn send_payload(&mut self, payload: PacketBuffer) -> FFResult<()> {
         self.out_buf = payload;
         self.flush()
@@ -419,3 +901,276 @@ impl FFClient {
         self.out_buf.queue_struct(s);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{mem::size_of_val, net::TcpListener};
+
+    use super::*;
+    use crate::{
+        net::packet::{PacketID::*, *},
+        test_utils::FakeClients,
+    };
+
+    struct MockStream {
+        written: Vec<u8>,
+        budget: usize,
+        max_chunk: usize,
+    }
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.budget == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let len = buf.len().min(self.budget).min(self.max_chunk);
+            self.written.extend_from_slice(&buf[..len]);
+            self.budget -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_outbound_queue_cap() {
+        let mut queue = OutboundQueue::new(10);
+        assert!(queue.push(&[1; 6]).is_ok());
+        assert!(queue.push(&[2; 4]).is_ok());
+
+        let err = queue.push(&[3; 1]).unwrap_err();
+        assert!(err.should_dc());
+        assert_eq!(queue.len(), 10); // rejected data isn't partially queued
+    }
+
+    #[test]
+    fn test_outbound_queue_partial_write() {
+        let mut queue = OutboundQueue::new(64);
+        queue.push(b"hello ").unwrap();
+        queue.push(b"world").unwrap();
+
+        let mut stream = MockStream {
+            written: Vec::new(),
+            budget: 4,
+            max_chunk: 3,
+        };
+        assert!(!queue.drain_to(&mut stream).unwrap());
+        assert_eq!(stream.written, b"hell");
+        assert_eq!(queue.len(), 7);
+
+        // socket becomes writable again
+        stream.budget = 100;
+        assert!(queue.drain_to(&mut stream).unwrap());
+        assert_eq!(stream.written, b"hello world");
+        assert!(queue.is_empty());
+    }
+
+    fn received<T: FFPacket>(pkt_id: PacketID, pkt: &T, missing_bytes: usize) -> PacketBuffer {
+        let mut buf = PacketBuffer::default();
+        buf.queue_packet(pkt_id, pkt);
+        buf.len = buf.ptr - missing_bytes;
+        buf.ptr = 0;
+        buf
+    }
+
+    #[test]
+    fn test_get_packet_wrong_id() {
+        let pkt = sP_CL2FE_REQ_PC_TRADE_OFFER {
+            iID_Request: 1,
+            iID_From: 2,
+            iID_To: 3,
+        };
+        let mut buf = received(P_CL2FE_REQ_PC_TRADE_OFFER, &pkt, 0);
+        assert!(buf
+            .get_packet::<sP_CL2FE_REQ_PC_TRADE_OFFER_ACCEPT>(P_CL2FE_REQ_PC_TRADE_OFFER_ACCEPT)
+            .is_err());
+        assert!(buf.get_struct::<sP_CL2FE_REQ_PC_TRADE_OFFER>().is_err());
+
+        let fetched: &sP_CL2FE_REQ_PC_TRADE_OFFER =
+            buf.get_packet(P_CL2FE_REQ_PC_TRADE_OFFER).unwrap();
+        assert_eq!({ fetched.iID_To }, 3);
+
+        let mut buf = received(P_CL2FE_REQ_PC_TRADE_OFFER, &pkt, 0);
+        let fetched: &sP_CL2FE_REQ_PC_TRADE_OFFER_ACCEPT = buf.get_packet_unchecked().unwrap();
+        assert_eq!({ fetched.iID_From }, 2);
+    }
+
+    #[test]
+    fn test_get_packet_truncated() {
+        let pkt = sP_CL2FE_REQ_PC_TRADE_OFFER {
+            iID_Request: 1,
+            iID_From: 2,
+            iID_To: 3,
+        };
+        let mut buf = received(P_CL2FE_REQ_PC_TRADE_OFFER, &pkt, 2);
+        let err = buf
+            .get_packet::<sP_CL2FE_REQ_PC_TRADE_OFFER>(P_CL2FE_REQ_PC_TRADE_OFFER)
+            .unwrap_err();
+        assert!(err.get_msg().contains("truncated"));
+        assert!(buf
+            .get_packet_unchecked::<sP_CL2FE_REQ_PC_TRADE_OFFER>()
+            .is_err());
+
+        // the ID alone doesn't make a packet
+        let mut buf = received(P_CL2FE_REQ_PC_TRADE_OFFER, &pkt, size_of_val(&pkt));
+        assert!(buf
+            .get_packet::<sP_CL2FE_REQ_PC_TRADE_OFFER>(P_CL2FE_REQ_PC_TRADE_OFFER)
+            .is_err());
+    }
+
+    #[test]
+    fn test_strikes() {
+        const LIMIT: usize = 3;
+        const WINDOW: Duration = Duration::from_secs(60);
+
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let client = fake_clients.get_client(key);
+        let start = SystemTime::now();
+
+        // spread out, old strikes fall off before the limit is reached
+        for i in 0..(LIMIT * 3) as u64 {
+            client.add_strike("test", start + WINDOW / 2 * i as u32, LIMIT, WINDOW);
+        }
+        assert!(!client.should_dc());
+
+        // in quick succession, the client gets kicked once it goes over
+        let later = start + WINDOW * 10;
+        for i in 0..LIMIT {
+            client.add_strike("test", later + Duration::from_secs(i as u64), LIMIT, WINDOW);
+        }
+        assert!(!client.should_dc());
+        client.add_strike(
+            "test",
+            later + Duration::from_secs(LIMIT as u64),
+            LIMIT,
+            WINDOW,
+        );
+        assert!(client.should_dc());
+
+        // exempt clients and a limit of 0 never get kicked
+        let key = fake_clients.add_game_client(Some(2));
+        let client = fake_clients.get_client(key);
+        client.set_strike_exempt(true);
+        for _ in 0..=LIMIT {
+            client.add_strike("test", start, LIMIT, WINDOW);
+        }
+        client.set_strike_exempt(false);
+        for _ in 0..=LIMIT {
+            client.add_strike("test", start, 0, WINDOW);
+        }
+        assert!(!client.should_dc());
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let mut latency = LatencyStats::default();
+        let start = SystemTime::now();
+        assert_eq!(latency.get_millis(), (-1, -1));
+
+        // replies without a live check out don't count
+        assert!(latency.on_live_check_reply(start).is_none());
+
+        latency.on_live_check_sent(start);
+        assert_eq!(
+            latency.on_live_check_reply(start + Duration::from_millis(100)),
+            Some(Duration::from_millis(100))
+        );
+        latency.on_live_check_sent(start);
+        latency.on_live_check_reply(start + Duration::from_millis(300));
+        assert_eq!(latency.get_millis(), (200, 300));
+
+        // only the newest samples are kept
+        for _ in 0..LATENCY_SAMPLE_COUNT {
+            latency.on_live_check_sent(start);
+            latency.on_live_check_reply(start + Duration::from_millis(50));
+        }
+        assert_eq!(latency.get_millis(), (50, 50));
+    }
+
+    // Both ends of a shard <-> login server connection
+    fn server_link() -> (FFClient, FFClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut shard_end = FFClient::new((TcpStream::connect(addr).unwrap(), addr));
+        let mut login_end = FFClient::new(listener.accept().unwrap());
+        shard_end.client_type = ClientType::LoginServer;
+        login_end.client_type = ClientType::ShardServer(1);
+        (shard_end, login_end)
+    }
+
+    #[test]
+    fn test_compressed_link() {
+        let (mut shard_end, mut login_end) = server_link();
+        shard_end.enable_compression(128).unwrap();
+        login_end.enable_compression(128).unwrap();
+
+        let mut pkt = sP_FE2LS_BUDDY_PRESENCE {
+            iPC_UID: 1,
+            iOnline: 1,
+            aBuddyUID: [0; 50],
+            aBuddyBlocked: [0; 50],
+        };
+        pkt.aBuddyUID[0] = 2;
+        pkt.aBuddyUID[1] = 3;
+        pkt.aBuddyUID[2] = 4;
+        pkt.aBuddyBlocked[1] = 1;
+        shard_end.send_packet(P_FE2LS_BUDDY_PRESENCE, &pkt).unwrap();
+        shard_end
+            .send_packet(
+                P_FE2LS_REP_LIVE_CHECK,
+                &sP_FE2LS_REP_LIVE_CHECK { iTempValue: 9 },
+            )
+            .unwrap();
+
+        assert!(login_end.read_payload().unwrap());
+        let received: &sP_FE2LS_BUDDY_PRESENCE =
+            login_end.get_packet(P_FE2LS_BUDDY_PRESENCE).unwrap();
+        assert_eq!({ received.aBuddyUID }[..4], [2, 3, 4, 0]);
+        assert_eq!({ received.aBuddyBlocked }[1], 1);
+
+        // small packets skip compression
+        assert!(login_end.read_payload().unwrap());
+        let received: &sP_FE2LS_REP_LIVE_CHECK =
+            login_end.get_packet(P_FE2LS_REP_LIVE_CHECK).unwrap();
+        assert_eq!({ received.iTempValue }, 9);
+
+        let sent = shard_end.get_compression().unwrap().sent;
+        let received = login_end.get_compression().unwrap().received;
+        assert_eq!(sent.frames, 2);
+        assert_eq!(sent.frames_compressed, 1);
+        assert!(sent.bytes_after < sent.bytes_before);
+        assert_eq!(sent.bytes_before, received.bytes_before);
+        assert_eq!(sent.bytes_after, received.bytes_after);
+    }
+
+    #[test]
+    fn test_compressed_link_corrupted() {
+        let (mut shard_end, mut login_end) = server_link();
+        login_end.enable_compression(0).unwrap();
+
+        let (mut frame, compressed) = encode_frame(&[0; 200], 0);
+        assert!(compressed);
+        let last = frame.len() - 1;
+        frame[last] ^= 0x55;
+        encrypt_payload(&mut frame, &shard_end.e_key);
+        shard_end
+            .sock
+            .write_all(&(frame.len() as u32).to_le_bytes())
+            .unwrap();
+        shard_end.sock.write_all(&frame).unwrap();
+
+        let err = login_end.read_payload().unwrap_err();
+        assert!(err.should_dc());
+    }
+
+    #[test]
+    fn test_compression_server_links_only() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let client = fake_clients.get_client(key);
+        assert!(client.enable_compression(0).is_err());
+        assert!(client.get_compression().is_none());
+    }
+}
@@ -1,22 +1,71 @@
 use polling::{Event, PollMode, Poller};
 
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     io::{ErrorKind, Result},
     net::{SocketAddr, TcpListener, TcpStream},
-    time::{Duration, SystemTime},
+    panic::{catch_unwind, AssertUnwindSafe},
+    time::{Duration, Instant, SystemTime},
 };
 
 use crate::{
+    config::config_get,
     error::{log, log_if_failed, FFError, Severity},
     state::ServerState,
 };
 
 use super::{
-    ClientMap, ClientType, DisconnectCallback, FFClient, LiveCheckCallback, PacketCallback,
+    packet::PacketID, ClientMap, ClientType, DisconnectCallback, FFClient, LiveCheckCallback,
+    PacketCallback,
 };
 
 const EPOLL_KEY_SELF: usize = 0;
+const HANDLER_STATS_DUMP_COUNT: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerStat {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+// How long each packet handler has taken since the stats were last dumped
+#[derive(Debug, Default)]
+pub struct HandlerStats {
+    stats: HashMap<PacketID, HandlerStat>,
+}
+impl HandlerStats {
+    pub fn record(&mut self, pkt_id: PacketID, elapsed: Duration) {
+        let stat = self.stats.entry(pkt_id).or_default();
+        stat.count += 1;
+        stat.total += elapsed;
+        stat.max = stat.max.max(elapsed);
+    }
+
+    // Returns the handlers that took the most time overall, worst first, and starts over
+    pub fn take_top(&mut self, count: usize) -> Vec<(PacketID, HandlerStat)> {
+        let mut stats: Vec<(PacketID, HandlerStat)> = self.stats.drain().collect();
+        stats.sort_by_key(|(_, stat)| Reverse(stat.total));
+        stats.truncate(count);
+        stats
+    }
+
+    pub fn log_top(&mut self) {
+        for (pkt_id, stat) in self.take_top(HANDLER_STATS_DUMP_COUNT) {
+            log(
+                Severity::Info,
+                &format!(
+                    "Handler for {:?}: {} calls, {}ms total, {}ms max",
+                    pkt_id,
+                    stat.count,
+                    stat.total.as_millis(),
+                    stat.max.as_millis()
+                ),
+            );
+        }
+    }
+}
 
 pub struct FFServer {
     poll_timeout: Option<Duration>,
@@ -27,6 +76,8 @@ pub struct FFServer {
     dc_handler: Option<DisconnectCallback>,
     live_check_handler: Option<LiveCheckCallback>,
     clients: HashMap<usize, FFClient>,
+    write_interest: HashSet<usize>,
+    handler_panics: usize,
 }
 
 impl FFServer {
@@ -46,6 +97,8 @@ impl FFServer {
             dc_handler,
             live_check_handler,
             clients: HashMap::new(),
+            write_interest: HashSet::new(),
+            handler_panics: 0,
         };
         server.sock.set_nonblocking(true)?;
         server.poller.add_with_mode(
@@ -99,6 +152,7 @@ impl FFServer {
                             );
                             log_if_failed(lc_callback(client));
                             client.live_check_time = Some(time_now + live_check_interval);
+                            client.on_live_check_sent(time_now);
                         }
                     }
                 }
@@ -109,6 +163,14 @@ impl FFServer {
             }
         }
 
+        self.update_write_interest()?;
+
+        // 0 turns the warning off
+        let slow_threshold = match config_get().general.slow_handler_threshold.get() {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        };
+
         let mut events: Vec<Event> = Vec::new();
         if let Err(e) = self.poller.wait(&mut events, self.poll_timeout) {
             match e.kind() {
@@ -136,12 +198,83 @@ impl FFServer {
                 };
                 let addr = client.get_addr();
 
+                if ev.writable {
+                    if let Err(e) = client.send_queued() {
+                        log(e.get_severity(), &format!("{} ({})", e.get_msg(), addr));
+                        if e.should_dc() {
+                            self.disconnect_client(ev.key, state)?;
+                            continue;
+                        }
+                    }
+                }
+
+                if !ev.readable {
+                    continue;
+                }
+
                 let res = (|clients: &mut HashMap<usize, FFClient>| {
                     let client = clients.get_mut(&ev.key).unwrap();
-                    client.read_payload()?;
+                    if !client.read_payload()? {
+                        // rest of the packet hasn't come in yet
+                        return Ok(());
+                    }
                     let pkt_id = client.peek_packet_id()?;
-                    (self.pkt_handler)(ev.key, clients, pkt_id, state, time_now).map_err(|e| {
-                        FFError::build(e.get_severity(), format!("<{:?}> {}", pkt_id, e.get_msg()))
+                    let start = Instant::now();
+                    // The handler gets &mut access to the client map and state, which aren't
+                    // UnwindSafe, so this has to be asserted. What a panic can leave behind:
+                    // - The panicking client's player may be half-updated. The client is
+                    //   marked, and Player::disconnect drops the player's unsaved progress
+                    //   instead of saving it.
+                    // - Other entities the handler touched (a trade partner, group members)
+                    //   may be half-updated too and still get saved later. Nothing here can
+                    //   tell which ones those are, so the panic limit shuts the server down
+                    //   before enough of these pile up to matter.
+                    // - A packet the handler was still building for any client would be
+                    //   sent unfinished ahead of that client's next one, so those are
+                    //   dropped. Packets already flushed are whole.
+                    // - A global lock held during the panic stays poisoned, so anything that
+                    //   needs it next panics too and counts towards the limit.
+                    let res = catch_unwind(AssertUnwindSafe(|| {
+                        (self.pkt_handler)(ev.key, clients, pkt_id, state, time_now)
+                    }))
+                    .unwrap_or_else(|payload| {
+                        self.handler_panics += 1;
+                        for client in clients.values_mut() {
+                            client.discard_unflushed();
+                        }
+                        if let Some(client) = clients.get_mut(&ev.key) {
+                            client.on_handler_panic();
+                        }
+                        let msg = payload
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or(payload.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown panic");
+                        Err(FFError::build_dc(
+                            Severity::Fatal,
+                            format!("Handler panicked ({}); disconnecting client", msg),
+                        ))
+                    });
+                    let elapsed = start.elapsed();
+                    state.get_handler_stats_mut().record(pkt_id, elapsed);
+                    if slow_threshold.is_some_and(|threshold| elapsed > threshold) {
+                        log(
+                            Severity::Warning,
+                            &format!(
+                                "Handler for {:?} from {} took {}ms",
+                                pkt_id,
+                                addr,
+                                elapsed.as_millis()
+                            ),
+                        );
+                    }
+                    res.map_err(|e| {
+                        let msg = format!("<{:?}> {}", pkt_id, e.get_msg());
+                        if e.should_dc() {
+                            FFError::build_dc(e.get_severity(), msg)
+                        } else {
+                            FFError::build(e.get_severity(), msg)
+                        }
                     })
                 })(&mut self.clients);
 
@@ -157,6 +290,12 @@ impl FFServer {
         Ok(())
     }
 
+    // Whether enough handlers have panicked that the state can't be trusted anymore
+    pub fn should_shut_down(&self) -> bool {
+        let limit = config_get().general.handler_panic_limit.get();
+        limit > 0 && self.handler_panics >= limit
+    }
+
     pub fn get_endpoint(&self) -> String {
         self.sock.local_addr().unwrap().to_string()
     }
@@ -173,9 +312,37 @@ impl FFServer {
         if let Some(callback) = self.dc_handler {
             callback(client_key, &mut self.clients, state);
         };
+
+        // best effort to get any last packets (e.g. exit notices) out
+        if let Some(client) = self.clients.get_mut(&client_key) {
+            let _ = client.send_queued();
+        }
         self.unregister_client(client_key)
     }
 
+    fn update_write_interest(&mut self) -> Result<()> {
+        for (key, client) in &self.clients {
+            let wants_write = client.has_queued_output();
+            if wants_write == self.write_interest.contains(key) {
+                continue;
+            }
+
+            let interest = if wants_write {
+                self.write_interest.insert(*key);
+                Event::all(*key)
+            } else {
+                self.write_interest.remove(key);
+                Event::readable(*key)
+            };
+            self.poller.modify_with_mode(
+                client.sock.get_tcp_stream(),
+                interest,
+                PollMode::Level,
+            )?;
+        }
+        Ok(())
+    }
+
     fn get_next_epoll_key(&mut self) -> usize {
         let key: usize = self.next_epoll_key;
         self.next_epoll_key += 1;
@@ -184,6 +351,7 @@ impl FFServer {
 
     fn register_client(&mut self, conn_data: (TcpStream, SocketAddr)) -> Result<usize> {
         let key: usize = self.get_next_epoll_key();
+        conn_data.0.set_nonblocking(true)?;
         self.poller
             .add_with_mode(&conn_data.0, Event::readable(key), PollMode::Level)?;
         self.clients.insert(key, FFClient::new(conn_data));
@@ -192,7 +360,153 @@ impl FFServer {
 
     fn unregister_client(&mut self, key: usize) -> Result<()> {
         let client = self.clients.remove(&key).unwrap();
-        self.poller.delete(&client.sock)?;
+        self.write_interest.remove(&key);
+        self.poller.delete(client.sock.get_tcp_stream())?;
         Ok(()) // client is dropped
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::Write,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use crate::{
+        error::FFResult,
+        net::{
+            crypto::{encrypt_payload, DEFAULT_KEY},
+            packet::PacketID::*,
+        },
+    };
+
+    fn panicking_handler(
+        _key: usize,
+        _clients: &mut HashMap<usize, FFClient>,
+        _pkt_id: PacketID,
+        _state: &mut ServerState,
+        _time: SystemTime,
+    ) -> FFResult<()> {
+        panic!("malformed request");
+    }
+
+    static PANICKED_CLIENT_DROPPED: AtomicBool = AtomicBool::new(false);
+
+    fn recording_dc_handler(
+        key: usize,
+        clients: &mut HashMap<usize, FFClient>,
+        _state: &mut ServerState,
+    ) {
+        if clients[&key].has_handler_panicked() {
+            PANICKED_CLIENT_DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Polls until the server has the expected number of clients, or gives up
+    fn poll_for_clients(server: &mut FFServer, state: &mut ServerState, count: usize) {
+        for _ in 0..50 {
+            if server.get_clients().len() == count {
+                return;
+            }
+            server.poll(state, Duration::from_secs(60)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_handler_stats() {
+        let mut stats = HandlerStats::default();
+        stats.record(P_CL2FE_REQ_PC_MOVE, Duration::from_millis(1));
+        stats.record(P_CL2FE_REQ_PC_MOVE, Duration::from_millis(3));
+        stats.record(P_CL2FE_REQ_PC_ENTER, Duration::from_millis(50));
+        stats.record(P_CL2FE_REQ_PC_JUMP, Duration::from_millis(2));
+
+        let top = stats.take_top(2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, P_CL2FE_REQ_PC_ENTER);
+        assert_eq!(top[1].0, P_CL2FE_REQ_PC_MOVE);
+        assert_eq!(
+            top[1].1,
+            HandlerStat {
+                count: 2,
+                total: Duration::from_millis(4),
+                max: Duration::from_millis(3),
+            }
+        );
+
+        // dumping starts over
+        assert!(stats.take_top(2).is_empty());
+    }
+
+    #[test]
+    fn test_handler_stats_overhead() {
+        const ITERATIONS: u32 = 100_000;
+        const PKT_IDS: [PacketID; 4] = [
+            P_CL2FE_REQ_PC_MOVE,
+            P_CL2FE_REQ_PC_JUMP,
+            P_CL2FE_REQ_PC_STOP,
+            P_CL2FE_REQ_PC_ATTACK_NPCs,
+        ];
+
+        // what the dispatcher does around every handler, minus the handler
+        let mut stats = HandlerStats::default();
+        let bench_start = Instant::now();
+        for i in 0..ITERATIONS {
+            let start = Instant::now();
+            let elapsed = start.elapsed();
+            stats.record(PKT_IDS[i as usize % PKT_IDS.len()], elapsed);
+        }
+        let per_call = bench_start.elapsed() / ITERATIONS;
+        // generous enough for unoptimized builds on slow machines
+        assert!(
+            per_call < Duration::from_micros(5),
+            "{:?} per call",
+            per_call
+        );
+        let total_count: u64 = stats
+            .take_top(PKT_IDS.len())
+            .iter()
+            .map(|s| s.1.count)
+            .sum();
+        assert_eq!(total_count, ITERATIONS as u64);
+    }
+
+    #[test]
+    fn test_handler_panic_disconnects_client() {
+        let mut server = FFServer::new(
+            "127.0.0.1:0",
+            panicking_handler,
+            Some(recording_dc_handler),
+            None,
+            Some(Duration::from_millis(10)),
+        )
+        .unwrap();
+        let mut state = ServerState::new_login();
+        let mut sock = TcpStream::connect(server.get_endpoint()).unwrap();
+        poll_for_clients(&mut server, &mut state, 1);
+        assert_eq!(server.get_clients().len(), 1);
+        assert!(!server
+            .get_clients()
+            .values()
+            .any(FFClient::has_handler_panicked));
+
+        let mut payload = (P_CL2LS_REQ_LOGIN as u32).to_le_bytes();
+        encrypt_payload(&mut payload, DEFAULT_KEY);
+        sock.write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        sock.write_all(&payload).unwrap();
+        poll_for_clients(&mut server, &mut state, 0);
+        assert!(server.get_clients().is_empty());
+        assert_eq!(server.handler_panics, 1);
+        assert!(!server.should_shut_down());
+        // marked before the disconnect handler ran, so it knows not to save anything
+        assert!(PANICKED_CLIENT_DROPPED.load(Ordering::SeqCst));
+
+        // still serving everyone else
+        let _sock = TcpStream::connect(server.get_endpoint()).unwrap();
+        poll_for_clients(&mut server, &mut state, 1);
+        assert_eq!(server.get_clients().len(), 1);
+    }
+}
@@ -1,10 +1,16 @@
-use std::{collections::HashMap, mem::size_of, slice::from_raw_parts, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    slice::from_raw_parts,
+    time::SystemTime,
+};
 
 use self::packet::{
     FFPacket,
     PacketID::{self, *},
 };
 use crate::{
+    chunk::{EntityMap, InstanceID},
     error::{log, FFResult, Severity},
     state::ServerState,
 };
@@ -40,6 +46,7 @@ pub use ffclient::*;
 mod ffserver;
 pub use ffserver::*;
 
+pub mod compression;
 pub mod crypto;
 pub mod packet;
 
@@ -98,6 +105,30 @@ impl<'a> ClientMap<'a> {
             .filter(|c| matches!(c.client_type, ClientType::GameClient { .. }))
     }
 
+    // Where each player is lives in the entity map, so it has to be passed in for these
+    pub fn get_gameclients_on_channel(
+        &mut self,
+        channel_num: u8,
+        entity_map: &EntityMap,
+    ) -> impl Iterator<Item = &mut FFClient> {
+        let pc_ids = entity_map.find_players(|p| p.instance_id.channel_num == channel_num);
+        self.get_gameclients_for(pc_ids.into_iter().collect())
+    }
+
+    pub fn get_gameclients_in_instance(
+        &mut self,
+        instance_id: InstanceID,
+        entity_map: &EntityMap,
+    ) -> impl Iterator<Item = &mut FFClient> {
+        let pc_ids = entity_map.find_players(|p| p.instance_id == instance_id);
+        self.get_gameclients_for(pc_ids.into_iter().collect())
+    }
+
+    fn get_gameclients_for(&mut self, pc_ids: HashSet<i32>) -> impl Iterator<Item = &mut FFClient> {
+        self.get_all_gameclient()
+            .filter(move |c| c.get_player_id().is_ok_and(|pc_id| pc_ids.contains(&pc_id)))
+    }
+
     pub fn get_login_server(&mut self) -> Option<&mut FFClient> {
         let login_server = self
             .clients
@@ -109,3 +140,62 @@ impl<'a> ClientMap<'a> {
         login_server
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        chunk::TickMode,
+        test_utils::{FakeClients, PlayerBuilder},
+    };
+
+    fn get_scoped_ids<'a>(clients: impl Iterator<Item = &'a mut FFClient>) -> HashSet<i32> {
+        clients.map(|c| c.get_player_id().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_gameclient_scoping() {
+        let mut entity_map = EntityMap::default();
+        let mut fake_clients = FakeClients::default();
+        let overworld_1 = InstanceID::default();
+        let overworld_2 = InstanceID {
+            channel_num: 2,
+            ..overworld_1
+        };
+        let instance_1 = InstanceID {
+            instance_num: Some(1),
+            ..overworld_1
+        };
+        for (pc_id, instance_id) in [(1, overworld_1), (2, overworld_2), (3, instance_1)] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .instance_id(instance_id)
+                .build();
+            entity_map.track(Box::new(player), TickMode::Never);
+            fake_clients.add_game_client(Some(pc_id));
+        }
+        // still loading in; not tracked yet
+        let key = fake_clients.add_game_client(Some(4));
+
+        let mut clients = fake_clients.get_map(key);
+        assert_eq!(get_scoped_ids(clients.get_all_gameclient()).len(), 4);
+        assert_eq!(
+            get_scoped_ids(clients.get_gameclients_on_channel(1, &entity_map)),
+            HashSet::from([1, 3])
+        );
+        assert_eq!(
+            get_scoped_ids(clients.get_gameclients_on_channel(2, &entity_map)),
+            HashSet::from([2])
+        );
+        assert_eq!(
+            get_scoped_ids(clients.get_gameclients_in_instance(overworld_1, &entity_map)),
+            HashSet::from([1])
+        );
+        assert_eq!(
+            get_scoped_ids(clients.get_gameclients_in_instance(instance_1, &entity_map)),
+            HashSet::from([3])
+        );
+        assert!(get_scoped_ids(clients.get_gameclients_on_channel(3, &entity_map)).is_empty());
+    }
+}
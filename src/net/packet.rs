@@ -16,7 +16,7 @@ pub const PACKET_MASK_LS2FE: u32 = 0x23000000;
 pub const PACKET_MASK_FE2LS: u32 = 0x32000000;
 
 #[repr(u32)]
-#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq)]
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
 #[num_enum(error_type(name = FFError, constructor = FFError::from_enum_err))]
 pub enum PacketID {
     P_NULL = 0,
@@ -531,19 +531,25 @@ pub enum PacketID {
     P_LS2CL_REP_SHARD_LIST_INFO_SUCC = 0x21000019,  // 553648153
 
     // custom packets
-    P_LS2FE_REP_CONNECT_SUCC = 0x23000001,      // 587202561
-    P_LS2FE_REP_CONNECT_FAIL = 0x23000002,      // 587202562
-    P_LS2FE_REQ_LIVE_CHECK = 0x23000003,        // 587202563
-    P_LS2FE_REQ_UPDATE_LOGIN_INFO = 0x23000004, // 587202564
-    P_LS2FE_REP_MOTD = 0x23000005,              // 587202565
-    P_LS2FE_ANNOUNCE_MSG = 0x23000006,          // 587202566
-    P_LS2FE_REP_PC_LOCATION_SUCC = 0x23000007,  // 587202567
-    P_LS2FE_REP_PC_LOCATION_FAIL = 0x23000008,  // 587202568
-    P_LS2FE_REQ_PC_LOCATION = 0x23000009,       // 587202569
-    P_LS2FE_REQ_PC_EXIT_DUPLICATE = 0x2300000a, // 587202570
-    P_LS2FE_REP_LIVE_CHECK = 0x2300000b,        // 587202571
-    P_LS2FE_REP_AUTH_CHALLENGE = 0x2300000c,    // 587202572
-    P_LS2FE_REP_GET_BUDDY_STATE = 0x2300000d,   // 587202573
+    P_LS2FE_REP_CONNECT_SUCC = 0x23000001,         // 587202561
+    P_LS2FE_REP_CONNECT_FAIL = 0x23000002,         // 587202562
+    P_LS2FE_REQ_LIVE_CHECK = 0x23000003,           // 587202563
+    P_LS2FE_REQ_UPDATE_LOGIN_INFO = 0x23000004,    // 587202564
+    P_LS2FE_REP_MOTD = 0x23000005,                 // 587202565
+    P_LS2FE_ANNOUNCE_MSG = 0x23000006,             // 587202566
+    P_LS2FE_REP_PC_LOCATION_SUCC = 0x23000007,     // 587202567
+    P_LS2FE_REP_PC_LOCATION_FAIL = 0x23000008,     // 587202568
+    P_LS2FE_REQ_PC_LOCATION = 0x23000009,          // 587202569
+    P_LS2FE_REQ_PC_EXIT_DUPLICATE = 0x2300000a,    // 587202570
+    P_LS2FE_REP_LIVE_CHECK = 0x2300000b,           // 587202571
+    P_LS2FE_REP_AUTH_CHALLENGE = 0x2300000c,       // 587202572
+    P_LS2FE_REP_GET_BUDDY_STATE = 0x2300000d,      // 587202573
+    P_LS2FE_REQ_KICK_ACCOUNT = 0x2300000e,         // 587202574
+    P_LS2FE_REP_CHANGE_NAME_SUCC = 0x2300000f,     // 587202575
+    P_LS2FE_REP_CHANGE_NAME_FAIL = 0x23000010,     // 587202576
+    P_LS2FE_REP_CONFISCATE_ITEM_SUCC = 0x23000011, // 587202577
+    P_LS2FE_REP_CONFISCATE_ITEM_FAIL = 0x23000012, // 587202578
+    P_LS2FE_REQ_VERIFY_PLAYER = 0x23000013,        // 587202579
 
     P_FE2LS_REQ_CONNECT = 0x32000001,                // 838860801
     P_FE2LS_REP_LIVE_CHECK = 0x32000002,             // 838860802
@@ -561,6 +567,13 @@ pub enum PacketID {
     P_FE2LS_REQ_AUTH_CHALLENGE = 0x3200000e,   // 838860814
     P_FE2LS_REQ_GET_BUDDY_STATE = 0x3200000f,  // 838860815
     P_FE2LS_DISCONNECTING = 0x32000010,        // 838860816
+    P_FE2LS_ACCOUNT_BANNED = 0x32000011,       // 838860817
+    P_FE2LS_REQ_CHANGE_NAME = 0x32000012,      // 838860818
+    P_FE2LS_REQ_CONFISCATE_ITEM = 0x32000013,  // 838860819
+    P_FE2LS_LOGIN_DATA_EXPIRED = 0x32000014,   // 838860820
+    P_FE2LS_REP_VERIFY_PLAYER = 0x32000015,    // 838860821
+    P_FE2LS_BUDDY_PRESENCE = 0x32000016,       // 838860822
+    P_FE2LS_WORLD_SNAPSHOT = 0x32000017,       // 838860823
 }
 
 pub trait FFPacket: std::fmt::Debug {}
@@ -6539,6 +6552,7 @@ impl FFPacket for sP_LS2CL_REP_SHARD_LIST_INFO_SUCC {}
 pub struct sP_LS2FE_REP_CONNECT_SUCC {
     pub uiSvrTime: u64,
     pub aLS_UID: [u8; 16],
+    pub iCapabilities: i32, // compression::CAPABILITY_* bits
 }
 impl FFPacket for sP_LS2FE_REP_CONNECT_SUCC {}
 
@@ -6595,6 +6609,8 @@ impl FFPacket for sP_LS2FE_ANNOUNCE_MSG {}
 pub struct sP_LS2FE_REP_PC_LOCATION_SUCC {
     pub iPC_ID: i32,
     pub sResp: sP_FE2CL_GM_REP_PC_LOCATION,
+    pub iAvgRTT: i32, // milliseconds; -1 if unknown
+    pub iMaxRTT: i32,
 }
 impl FFPacket for sP_LS2FE_REP_PC_LOCATION_SUCC {}
 
@@ -6652,6 +6668,68 @@ pub struct sP_LS2FE_REP_GET_BUDDY_STATE {
 }
 impl FFPacket for sP_LS2FE_REP_GET_BUDDY_STATE {}
 
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REQ_KICK_ACCOUNT {
+    pub iAccountID: i64,
+    pub iBannedUntil: i64,
+    pub szBanReason: [u16; 512],
+}
+impl FFPacket for sP_LS2FE_REQ_KICK_ACCOUNT {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REP_CHANGE_NAME_SUCC {
+    pub iPC_UID: i64,
+    pub iItemID: i16,
+    pub iNameCheck: i8,
+    pub szFirstName: [u16; 9],
+    pub szLastName: [u16; 17],
+}
+impl FFPacket for sP_LS2FE_REP_CHANGE_NAME_SUCC {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REP_CHANGE_NAME_FAIL {
+    pub iPC_UID: i64,
+    pub iErrorCode: i32,
+}
+impl FFPacket for sP_LS2FE_REP_CHANGE_NAME_FAIL {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REP_CONFISCATE_ITEM_SUCC {
+    pub iGM_PC_UID: i64,
+    pub iTargetPC_UID: i64,
+    pub iItemType: i16,
+    pub iItemID: i16,
+    pub iRemoved: i32,
+}
+impl FFPacket for sP_LS2FE_REP_CONFISCATE_ITEM_SUCC {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REP_CONFISCATE_ITEM_FAIL {
+    pub iGM_PC_UID: i64,
+    pub iTargetPC_UID: i64,
+    pub iErrorCode: i32,
+}
+impl FFPacket for sP_LS2FE_REP_CONFISCATE_ITEM_FAIL {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_LS2FE_REQ_VERIFY_PLAYER {
+    pub iAccountID: i64,
+    pub iPC_UID: i64,
+}
+impl FFPacket for sP_LS2FE_REQ_VERIFY_PLAYER {}
+
 #[repr(packed(4))]
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -6660,6 +6738,9 @@ pub struct sP_FE2LS_REQ_CONNECT {
     pub iShardID: i32,
     pub iMaxChannelPop: i32,
     pub iNumChannels: i8,
+    pub g_FE_ServerIP: [u8; 16],
+    pub g_FE_ServerPort: i32,
+    pub iCapabilities: i32, // compression::CAPABILITY_* bits
 }
 impl FFPacket for sP_FE2LS_REQ_CONNECT {}
 
@@ -6695,6 +6776,7 @@ impl FFPacket for sP_FE2LS_REP_UPDATE_LOGIN_INFO_FAIL {}
 #[derive(Debug, Copy, Clone)]
 pub struct sP_FE2LS_UPDATE_PC_STATUSES {
     pub iCnt: u32,
+    pub bJoinable: i32, // whether the shard is taking new players
 }
 impl FFPacket for sP_FE2LS_UPDATE_PC_STATUSES {}
 
@@ -6754,6 +6836,8 @@ pub struct sP_FE2LS_REP_PC_LOCATION_SUCC {
     pub iReqShard_ID: i32,
     pub iPC_ID: i32,
     pub sResp: sP_FE2CL_GM_REP_PC_LOCATION,
+    pub iAvgRTT: i32, // milliseconds; -1 if unknown
+    pub iMaxRTT: i32,
 }
 impl FFPacket for sP_FE2LS_REP_PC_LOCATION_SUCC {}
 
@@ -6800,3 +6884,91 @@ pub struct sP_FE2LS_DISCONNECTING {
     pub iTempValue: i32,
 }
 impl FFPacket for sP_FE2LS_DISCONNECTING {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_ACCOUNT_BANNED {
+    pub iAccountID: i64,
+    pub iBannedUntil: i64,
+    pub szBanReason: [u16; 512],
+}
+impl FFPacket for sP_FE2LS_ACCOUNT_BANNED {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_REQ_CHANGE_NAME {
+    pub iPC_UID: i64,
+    pub iItemID: i16, // consumed once the change goes through; 0 if none
+    pub szFirstName: [u16; 9],
+    pub szLastName: [u16; 17],
+}
+impl FFPacket for sP_FE2LS_REQ_CHANGE_NAME {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_REQ_CONFISCATE_ITEM {
+    pub iGM_PC_UID: i64,
+    pub iTargetPC_UID: i64,
+    pub iItemType: i16,
+    pub iItemID: i16,
+    pub iCount: i32,
+}
+impl FFPacket for sP_FE2LS_REQ_CONFISCATE_ITEM {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_LOGIN_DATA_EXPIRED {
+    pub iEnterSerialKey: i64,
+    pub iAccountID: i64,
+    pub iPC_UID: i64,
+}
+impl FFPacket for sP_FE2LS_LOGIN_DATA_EXPIRED {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_REP_VERIFY_PLAYER {
+    pub iAccountID: i64,
+    pub iPC_UID: i64,
+    pub iPresent: i32,
+}
+impl FFPacket for sP_FE2LS_REP_VERIFY_PLAYER {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_BUDDY_PRESENCE {
+    pub iPC_UID: i64,
+    pub iOnline: i32,
+    pub aBuddyUID: [i64; 50],
+    pub aBuddyBlocked: [u8; 50],
+}
+impl FFPacket for sP_FE2LS_BUDDY_PRESENCE {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sTrackedNPCStatus {
+    pub iNPC_ID: i32,
+    pub iNPCType: i32,
+    pub iChannelNum: i8,
+    pub bAlive: i8,
+}
+impl FFPacket for sTrackedNPCStatus {}
+
+#[repr(packed(4))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sP_FE2LS_WORLD_SNAPSHOT {
+    pub iAvgTickUs: u32,
+    pub iChannelCnt: i32,
+    pub aChannelPop: [u32; 25],
+    pub iTrackedNPCCnt: i32,
+    pub bTrackedNPCsTruncated: i32,
+    pub aTrackedNPCs: [sTrackedNPCStatus; 32],
+}
+impl FFPacket for sP_FE2LS_WORLD_SNAPSHOT {}
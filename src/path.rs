@@ -1,4 +1,14 @@
-use crate::{defines::SHARD_TICKS_PER_SECOND, Position};
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::{
+    defines::{
+        PATH_RECORD_INTERVAL_MS, PATH_RECORD_MAX_POINTS, PATH_RECORD_MIN_DISTANCE,
+        SHARD_TICKS_PER_SECOND,
+    },
+    Position,
+};
 
 #[derive(Debug, Copy, Clone)]
 pub struct PathPoint {
@@ -144,3 +154,213 @@ impl Path {
         false
     }
 }
+
+// Builds up an NPC path from a GM walking it out in-game
+#[derive(Debug, Clone, Default)]
+pub struct PathRecorder {
+    points: Vec<PathPoint>,
+    last_sample: Option<(Position, SystemTime)>,
+    idle_ms: u64,
+    full: bool,
+    stopped: bool,
+}
+impl PathRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_points(&self) -> &[PathPoint] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.stopped && !self.full
+    }
+
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    // Returns false once the recorder has run out of room
+    pub fn sample(&mut self, pos: Position, time: SystemTime) -> bool {
+        if !self.is_recording() {
+            return false;
+        }
+
+        let Some((last_pos, last_time)) = self.last_sample else {
+            self.points.push(PathPoint {
+                pos,
+                speed: 0,
+                stop_ticks: 0,
+            });
+            self.last_sample = Some((pos, time));
+            return true;
+        };
+
+        let elapsed_ms = time
+            .duration_since(last_time)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        if elapsed_ms < PATH_RECORD_INTERVAL_MS {
+            return true;
+        }
+
+        let dist = last_pos.distance_to(&pos);
+        if dist < PATH_RECORD_MIN_DISTANCE {
+            // path stop ticks are in whole seconds, see Path::tick
+            self.idle_ms += elapsed_ms;
+            self.points.last_mut().unwrap().stop_ticks = (self.idle_ms / 1000) as usize;
+            self.last_sample = Some((last_pos, time));
+            return true;
+        }
+
+        if self.points.len() >= PATH_RECORD_MAX_POINTS {
+            self.full = true;
+            return false;
+        }
+
+        self.points.push(PathPoint {
+            pos,
+            speed: (dist as u64 * 1000 / elapsed_ms) as i32,
+            stop_ticks: 0,
+        });
+        self.idle_ms = 0;
+        self.last_sample = Some((pos, time));
+        true
+    }
+
+    pub fn get_base_speed(&self) -> i32 {
+        let speeds: Vec<i32> = self
+            .points
+            .iter()
+            .map(|point| point.speed)
+            .filter(|speed| *speed > 0)
+            .collect();
+        if speeds.is_empty() {
+            return 0;
+        }
+        speeds.iter().sum::<i32>() / speeds.len() as i32
+    }
+
+    pub fn to_path(&self, cycle: bool) -> Path {
+        let base_speed = self.get_base_speed();
+        let points = self
+            .points
+            .iter()
+            .map(|point| PathPoint {
+                speed: if point.speed > 0 {
+                    point.speed
+                } else {
+                    base_speed
+                },
+                ..*point
+            })
+            .collect();
+        Path::new(points, cycle)
+    }
+
+    // Matches the NPC path entries in paths.json
+    pub fn to_json(&self, npc_types: &[i32], cycle: bool) -> Value {
+        let mut points: Vec<Value> = self
+            .points
+            .iter()
+            .map(|point| {
+                json!({
+                    "iX": point.pos.x,
+                    "iY": point.pos.y,
+                    "iZ": point.pos.z,
+                    "iStopTicks": point.stop_ticks,
+                })
+            })
+            .collect();
+        if cycle && self.points.len() > 1 {
+            // tdata marks cyclic paths by repeating the start point
+            let mut end = points[0].clone();
+            end["iStopTicks"] = json!(0);
+            points.push(end);
+        }
+        json!({
+            "aNPCTypes": npc_types,
+            "aNPCIDs": [],
+            "iBaseSpeed": self.get_base_speed(),
+            "aPoints": points,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn pos(x: i32) -> Position {
+        Position { x, y: 0, z: 0 }
+    }
+
+    #[test]
+    fn test_path_recorder() {
+        let start = SystemTime::UNIX_EPOCH;
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut recorder = PathRecorder::new();
+        assert!(recorder.sample(pos(0), at(0)));
+
+        // too soon to take another sample
+        recorder.sample(pos(1000), at(100));
+        assert_eq!(recorder.get_points().len(), 1);
+
+        recorder.sample(pos(500), at(1000));
+        recorder.sample(pos(1500), at(2000));
+        let points = recorder.get_points();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[1].speed, 500);
+        assert_eq!(points[2].speed, 1000);
+        assert_eq!(recorder.get_base_speed(), 750);
+
+        // standing still turns into a stop on the last point
+        recorder.sample(pos(1510), at(3000));
+        recorder.sample(pos(1500), at(4500));
+        assert_eq!(recorder.get_points().len(), 3);
+        assert_eq!(recorder.get_points()[2].stop_ticks, 2);
+
+        let path = recorder.to_path(true);
+        assert_eq!(path.get_points()[0].speed, 750);
+        assert_eq!(path.get_points()[2].speed, 1000);
+
+        let entry = recorder.to_json(&[42], true);
+        assert_eq!(entry["aNPCTypes"], json!([42]));
+        assert_eq!(entry["iBaseSpeed"], json!(750));
+        let json_points = entry["aPoints"].as_array().unwrap();
+        assert_eq!(json_points.len(), 4);
+        assert_eq!(json_points[0], json_points[3]);
+        assert_eq!(json_points[2]["iStopTicks"], json!(2));
+    }
+
+    #[test]
+    fn test_path_recorder_cap() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut recorder = PathRecorder::new();
+        let mut ms = 0;
+        for i in 0..PATH_RECORD_MAX_POINTS {
+            assert!(recorder.sample(pos(i as i32 * 1000), start + Duration::from_millis(ms)));
+            ms += PATH_RECORD_INTERVAL_MS;
+        }
+        assert!(!recorder.sample(pos(-1000), start + Duration::from_millis(ms)));
+        assert!(recorder.is_full());
+        assert_eq!(recorder.get_points().len(), PATH_RECORD_MAX_POINTS);
+
+        let mut recorder = PathRecorder::new();
+        recorder.sample(pos(0), start);
+        recorder.stop();
+        assert!(!recorder.sample(pos(1000), start + Duration::from_secs(1)));
+        assert_eq!(recorder.get_points().len(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    config::config_get,
+    defines::*,
+    error::{FFError, FFResult, Severity},
+    net::packet::PacketID::{self, *},
+};
+
+// Minimum account level needed to send each GM packet.
+// Lower levels are more privileged. Packets not listed here are refused outright.
+const GM_PACKET_PERMS: [(PacketID, u32); 18] = [
+    (P_CL2FE_GM_REQ_PC_SET_VALUE, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_PC_GIVE_ITEM, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_PC_GIVE_NANO, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_PC_GOTO, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_GM_REQ_PC_SPECIAL_STATE_SWITCH, CN_ACCOUNT_LEVEL__GM),
+    (P_CL2FE_GM_REQ_PC_MOTD_REGISTER, CN_ACCOUNT_LEVEL__CS),
+    (P_CL2FE_GM_REQ_PC_ANNOUNCE, CN_ACCOUNT_LEVEL__CS),
+    (P_CL2FE_GM_REQ_PC_LOCATION, CN_ACCOUNT_LEVEL__CS),
+    (
+        P_CL2FE_GM_REQ_TARGET_PC_SPECIAL_STATE_ONOFF,
+        CN_ACCOUNT_LEVEL__CS,
+    ),
+    (P_CL2FE_GM_REQ_TARGET_PC_TELEPORT, CN_ACCOUNT_LEVEL__CS),
+    (P_CL2FE_GM_REQ_KICK_PLAYER, CN_ACCOUNT_LEVEL__CS),
+    (P_CL2FE_GM_REQ_REWARD_RATE, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_PC_TASK_COMPLETE, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_PC_MISSION_COMPLETE, CN_ACCOUNT_LEVEL__DEVELOPER),
+    (P_CL2FE_REQ_SHINY_SUMMON, CN_ACCOUNT_LEVEL__GM),
+    (P_CL2FE_REQ_NPC_SUMMON, CN_ACCOUNT_LEVEL__GM),
+    (P_CL2FE_REQ_NPC_GROUP_SUMMON, CN_ACCOUNT_LEVEL__GM),
+    (P_CL2FE_REQ_NPC_UNSUMMON, CN_ACCOUNT_LEVEL__GM),
+];
+
+// Stricter levels for GM packets when they're used on another player.
+// Packets not listed here need the same level either way.
+const GM_PACKET_PERMS_ON_OTHER: [(PacketID, u32); 1] =
+    [(P_CL2FE_GM_REQ_REWARD_RATE, CN_ACCOUNT_LEVEL__GM)];
+
+pub fn get_gm_packet_ids() -> impl Iterator<Item = PacketID> {
+    GM_PACKET_PERMS.into_iter().map(|(pkt_id, _)| pkt_id)
+}
+
+// Config overrides are keyed by packet name, e.g. P_CL2FE_REQ_PC_GOTO
+pub fn get_required_perms(pkt_id: PacketID) -> Option<i16> {
+    let (_, default_perms) = GM_PACKET_PERMS.iter().find(|(id, _)| *id == pkt_id)?;
+    let overrides = config_get().shard.gm_perms.get();
+    let perms = overrides
+        .get(&format!("{:?}", pkt_id))
+        .copied()
+        .unwrap_or(*default_perms as i16);
+    Some(perms)
+}
+
+// Config overrides are keyed by packet name with _OTHER on the end, e.g. P_CL2FE_GM_REQ_REWARD_RATE_OTHER.
+// Never looser than using the packet on yourself.
+pub fn get_required_perms_on_other(pkt_id: PacketID) -> Option<i16> {
+    let self_perms = get_required_perms(pkt_id)?;
+    let Some((_, default_perms)) = GM_PACKET_PERMS_ON_OTHER
+        .iter()
+        .find(|(id, _)| *id == pkt_id)
+    else {
+        return Some(self_perms);
+    };
+    let overrides = config_get().shard.gm_perms.get();
+    let perms = overrides
+        .get(&format!("{:?}_OTHER", pkt_id))
+        .copied()
+        .unwrap_or(*default_perms as i16);
+    Some(perms.min(self_perms))
+}
+
+pub fn check_gm_perms(perms: i16, pkt_id: PacketID) -> FFResult<()> {
+    check_perms(perms, pkt_id, get_required_perms(pkt_id))
+}
+
+pub fn check_gm_perms_on_other(perms: i16, pkt_id: PacketID) -> FFResult<()> {
+    check_perms(perms, pkt_id, get_required_perms_on_other(pkt_id))
+}
+
+fn check_perms(perms: i16, pkt_id: PacketID, req_perms: Option<i16>) -> FFResult<()> {
+    let Some(req_perms) = req_perms else {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{:?} is not a GM packet", pkt_id),
+        ));
+    };
+    if perms > req_perms {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{:?} needs perms {} but had {}", pkt_id, req_perms, perms),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::entity::Player;
+
+    #[test]
+    fn test_gm_perms_reject_users() {
+        let mut player = Player::default();
+        player.perms = CN_ACCOUNT_LEVEL__USER as i16;
+        for pkt_id in get_gm_packet_ids() {
+            assert!(
+                check_gm_perms(player.perms, pkt_id).is_err(),
+                "{:?} accepted from a regular user",
+                pkt_id
+            );
+        }
+    }
+
+    #[test]
+    fn test_gm_perms_table() {
+        for (pkt_id, req_perms) in GM_PACKET_PERMS {
+            assert_eq!(get_required_perms(pkt_id), Some(req_perms as i16));
+            assert!(check_gm_perms(req_perms as i16, pkt_id).is_ok());
+            assert!(check_gm_perms(req_perms as i16 + 1, pkt_id).is_err());
+            assert!(check_gm_perms(CN_ACCOUNT_LEVEL__MASTER as i16, pkt_id).is_ok());
+        }
+
+        // non-GM packets are never accepted, even from the highest level
+        assert!(check_gm_perms(CN_ACCOUNT_LEVEL__MASTER as i16, P_CL2FE_REQ_PC_MOVE).is_err());
+    }
+
+    #[test]
+    fn test_gm_perms_on_other() {
+        let pkt_id = P_CL2FE_GM_REQ_REWARD_RATE;
+        let self_perms = get_required_perms(pkt_id).unwrap();
+        let other_perms = get_required_perms_on_other(pkt_id).unwrap();
+        assert!(other_perms < self_perms);
+
+        // enough to use it on yourself isn't enough to use it on someone else
+        assert!(check_gm_perms(self_perms, pkt_id).is_ok());
+        assert!(check_gm_perms_on_other(self_perms, pkt_id).is_err());
+        assert!(check_gm_perms_on_other(other_perms, pkt_id).is_ok());
+
+        // unlisted packets need the same level either way
+        assert_eq!(
+            get_required_perms_on_other(P_CL2FE_REQ_PC_GOTO),
+            get_required_perms(P_CL2FE_REQ_PC_GOTO)
+        );
+        assert!(get_required_perms_on_other(P_CL2FE_REQ_PC_MOVE).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::{enums::ReportCategory, util, Position};
+
+const SECS_PER_HOUR: u64 = 60 * 60;
+
+// A player reporting another player, queued up for moderators
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    // assigned by the DB when the report is filed
+    pub id: i64,
+    pub reporter_uid: i64,
+    pub reported_uid: i64,
+    pub category: ReportCategory,
+    pub reason: Option<String>,
+    pub time: SystemTime,
+    pub shard_id: i32,
+    pub channel_num: u8,
+    pub reporter_pos: Position,
+    // only known if the reported player was on the same shard
+    pub reported_pos: Option<Position>,
+    // set when the reported player outranks the reporter, e.g. a GM
+    pub flagged: bool,
+    // who marked it handled, if anyone
+    pub handled_by: Option<i64>,
+}
+impl Report {
+    pub fn describe(&self) -> String {
+        let fmt_pos = |pos: &Position| format!("({}, {}, {})", pos.x, pos.y, pos.z);
+        let mut desc = format!(
+            "#{} [{}] uid:{} reported uid:{} ({:?}) on shard {} channel {} at {}",
+            self.id,
+            util::get_timestamp_str(self.time),
+            self.reporter_uid,
+            self.reported_uid,
+            self.category,
+            self.shard_id,
+            self.channel_num,
+            fmt_pos(&self.reporter_pos)
+        );
+        if let Some(pos) = &self.reported_pos {
+            desc.push_str(&format!(", target at {}", fmt_pos(pos)));
+        }
+        if let Some(reason) = &self.reason {
+            desc.push_str(&format!(": \"{}\"", reason));
+        }
+        if self.flagged {
+            desc.push_str(" [FLAGGED: target outranks reporter]");
+        }
+        desc
+    }
+}
+
+// Keeps players from flooding the report queue
+#[derive(Debug, Default)]
+pub struct ReportLimiter {
+    // reporter UID -> when they filed their reports in the last hour
+    recent: HashMap<i64, Vec<SystemTime>>,
+}
+impl ReportLimiter {
+    // Returns false, without counting it, if the player is out of reports for the hour
+    pub fn try_report(&mut self, pc_uid: i64, time: SystemTime, per_hour: usize) -> bool {
+        let window_start = time - Duration::from_secs(SECS_PER_HOUR);
+        let recent = self.recent.entry(pc_uid).or_default();
+        recent.retain(|t| *t > window_start);
+        if recent.len() >= per_hour {
+            return false;
+        }
+        recent.push(time);
+        true
+    }
+
+    // Drops players whose reports have all aged out
+    pub fn prune(&mut self, time: SystemTime) {
+        let window_start = time - Duration::from_secs(SECS_PER_HOUR);
+        self.recent.retain(|_, recent| {
+            recent.retain(|t| *t > window_start);
+            !recent.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_limiter() {
+        let mut limiter = ReportLimiter::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(SECS_PER_HOUR);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.try_report(1, at(0), 2));
+        assert!(limiter.try_report(1, at(10), 2));
+        assert!(!limiter.try_report(1, at(20), 2));
+        // other players have their own allowance
+        assert!(limiter.try_report(2, at(20), 2));
+
+        // the refused report didn't count, so one frees up an hour after the first
+        assert!(!limiter.try_report(1, at(SECS_PER_HOUR - 1), 2));
+        assert!(limiter.try_report(1, at(SECS_PER_HOUR), 2));
+        assert!(!limiter.try_report(1, at(SECS_PER_HOUR + 5), 2));
+
+        limiter.prune(at(SECS_PER_HOUR + 20));
+        assert_eq!(limiter.recent.len(), 1);
+        limiter.prune(at(3 * SECS_PER_HOUR));
+        assert!(limiter.recent.is_empty());
+    }
+
+    #[test]
+    fn test_describe_report() {
+        let mut report = Report {
+            id: 3,
+            reporter_uid: 1,
+            reported_uid: 2,
+            category: ReportCategory::Block,
+            reason: None,
+            time: SystemTime::UNIX_EPOCH,
+            shard_id: 1,
+            channel_num: 2,
+            reporter_pos: Position { x: 1, y: 2, z: 3 },
+            reported_pos: None,
+            flagged: false,
+            handled_by: None,
+        };
+        let time_str = util::get_timestamp_str(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            report.describe(),
+            format!(
+                "#3 [{}] uid:1 reported uid:2 (Block) on shard 1 channel 2 at (1, 2, 3)",
+                time_str
+            )
+        );
+
+        report.reported_pos = Some(Position { x: 4, y: 5, z: 6 });
+        report.reason = Some("spamming".to_string());
+        report.flagged = true;
+        assert_eq!(
+            report.describe(),
+            format!(
+                "#3 [{}] uid:1 reported uid:2 (Block) on shard 1 channel 2 at (1, 2, 3), \
+                 target at (4, 5, 6): \"spamming\" [FLAGGED: target outranks reporter]",
+                time_str
+            )
+        );
+    }
+}
@@ -1,36 +1,152 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
 use rand::Rng;
 
 use crate::{
+    config::config_get,
     defines::*,
-    entity::{Combatant, EntityID},
-    enums::CombatStyle,
+    entity::{Combatant, Entity, EntityID},
+    enums::{CharType, CombatStyle, SkillType, TimeBuffType, TimeBuffUpdate},
     error::*,
+    helpers,
     net::{
         packet::{PacketID::*, *},
         ClientMap, PacketBuffer,
     },
     state::ShardServerState,
+    tabledata::tdata_get,
 };
 
 struct BasicAttack {
     power: i32,
     crit_chance: Option<f32>,
     attack_style: Option<CombatStyle>,
+    style_modifiers: (Option<f32>, Option<f32>),
     charged: bool,
 }
 
+// A basic attack whose damage lands some time after it's fired
+#[derive(Debug, Clone, Copy)]
+pub struct Projectile {
+    pub travel_time: Duration,
+    // targets further than this from the attacker when it lands are missed
+    pub max_range: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingHit {
+    land_time: SystemTime,
+    attacker_id: EntityID,
+    target_id: EntityID,
+    damage: i32,
+    max_range: Option<u32>,
+}
+
+// Projectile hits still in flight, kept in the order they land
+#[derive(Debug, Default)]
+pub struct PendingHits {
+    hits: VecDeque<PendingHit>,
+}
+impl PendingHits {
+    pub fn push(&mut self, hit: PendingHit) {
+        // hits landing at the same time resolve in the order they were fired
+        let idx = self.hits.partition_point(|h| h.land_time <= hit.land_time);
+        self.hits.insert(idx, hit);
+    }
+
+    pub fn take_landed(&mut self, time: SystemTime) -> Vec<PendingHit> {
+        let count = self.hits.partition_point(|h| h.land_time <= time);
+        self.hits.drain(..count).collect()
+    }
+}
+
+// Applies a hit that just landed, returning the damage dealt.
+// Fails if the target died, left, or got out of range while the hit was in flight.
+pub fn land_hit(hit: &PendingHit, state: &mut ShardServerState) -> FFResult<i32> {
+    let attacker = state.get_combatant(hit.attacker_id)?;
+    let attacker_chunk = attacker.get_chunk_coords();
+    let attacker_pos = attacker.get_position();
+
+    let target = state.get_combatant_mut(hit.target_id)?;
+    if target.is_dead() {
+        return Err(FFError::build(
+            Severity::Debug,
+            format!("{:?} was already dead", hit.target_id),
+        ));
+    }
+    if target.get_chunk_coords().i != attacker_chunk.i {
+        return Err(FFError::build(
+            Severity::Debug,
+            format!("{:?} left {:?}'s instance", hit.target_id, hit.attacker_id),
+        ));
+    }
+    if let Some(max_range) = hit.max_range {
+        let dist = attacker_pos.distance_to(&target.get_position());
+        if dist > max_range {
+            return Err(FFError::build(
+                Severity::Debug,
+                format!(
+                    "{:?} moved out of {:?}'s range ({} > {})",
+                    hit.target_id, hit.attacker_id, dist, max_range
+                ),
+            ));
+        }
+    }
+
+    Ok(target.take_damage(hit.damage, hit.attacker_id))
+}
+
 pub fn do_basic_attack(
     attacker_id: EntityID,
     target_ids: &[EntityID],
     charged: bool,
+    projectile: Option<Projectile>,
     state: &mut ShardServerState,
     clients: &mut ClientMap,
 ) -> FFResult<()> {
     const CRIT_CHANCE: f32 = 0.05;
 
     let attacker = state.get_combatant(attacker_id)?;
+    if !attacker.can_act() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{:?} tried to attack while incapacitated", attacker_id),
+        ));
+    }
     let mut attacker_client = attacker.get_client(clients);
 
+    // weed out bad targets first so the power matches the number actually hit
+    let mut valid_target_ids: Vec<EntityID> = Vec::with_capacity(target_ids.len());
+    for target_id in target_ids {
+        if valid_target_ids.contains(target_id) {
+            log(
+                Severity::Warning,
+                &format!(
+                    "{:?} tried to attack target {:?} more than once",
+                    attacker_id, target_id
+                ),
+            );
+            continue;
+        }
+        match state.get_combatant(*target_id) {
+            Ok(target) if target.is_dead() => {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "{:?} tried to attack dead target {:?}",
+                        attacker_id, target_id
+                    ),
+                );
+            }
+            Ok(_) => valid_target_ids.push(*target_id),
+            Err(e) => log_error(&e),
+        }
+    }
+    let target_ids = valid_target_ids;
+
     let power = if target_ids.len() == 1 {
         attacker.get_single_power()
     } else {
@@ -40,12 +156,14 @@ pub fn do_basic_attack(
         power,
         crit_chance: Some(CRIT_CHANCE),
         attack_style: attacker.get_style(),
+        style_modifiers: attacker.get_style_modifiers(),
         charged,
     };
 
+    let land_time = projectile.map(|projectile| SystemTime::now() + projectile.travel_time);
     let mut pc_attack_results = Vec::new();
     let mut npc_attack_results = Vec::new();
-    for target_id in target_ids {
+    for target_id in &target_ids {
         let target = match state.get_combatant_mut(*target_id) {
             Ok(target) => target,
             Err(e) => {
@@ -53,17 +171,17 @@ pub fn do_basic_attack(
                 continue;
             }
         };
-        if target.is_dead() {
-            log(
-                Severity::Warning,
-                &format!(
-                    "{:?} tried to attack dead target {:?}",
-                    attacker_id, target_id
-                ),
-            );
-            continue;
+        let (result, damage) =
+            handle_basic_attack(attacker_id, target, &basic_attack, land_time.is_some());
+        if let (Some(land_time), Some(projectile)) = (land_time, projectile) {
+            state.pending_hits.push(PendingHit {
+                land_time,
+                attacker_id,
+                target_id: *target_id,
+                damage,
+                max_range: projectile.max_range,
+            });
         }
-        let result = handle_basic_attack(attacker_id, target, &basic_attack);
         match target_id {
             EntityID::Player(_) => pc_attack_results.push(result),
             EntityID::NPC(_) => npc_attack_results.push(result),
@@ -184,25 +302,298 @@ pub fn do_basic_attack(
     Ok(())
 }
 
+// A nano skill's entry in the skill table, at the base power level
+#[derive(Debug, Clone)]
+pub struct SkillData {
+    pub skill_type: i32,
+    pub target_type: i32,
+    pub effect_area: u32,
+    pub stamina_cost: i16,
+    pub duration: Duration,
+    pub cooldown: Duration,
+    pub value: i32,
+}
+
+// skill table target type for skills that land on the caster's whole group
+const SKILL_TARGET_GROUP: i32 = 3;
+
+// What a nano skill that helps players does to each of its targets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SupportEffect {
+    // restores this many thousandths of the target's max HP
+    Heal {
+        permille: i32,
+    },
+    Buff {
+        condition_bit: u32,
+        duration: Duration,
+    },
+}
+impl SupportEffect {
+    // None for skills that hurt enemies or that aren't implemented yet
+    pub fn from_skill(skill: &SkillData) -> Option<Self> {
+        let skill_type: SkillType = skill.skill_type.try_into().ok()?;
+        let condition_bit = match skill_type {
+            SkillType::HealHP => {
+                return Some(Self::Heal {
+                    permille: skill.value,
+                })
+            }
+            SkillType::Run => CSB_BIT_UP_MOVE_SPEED,
+            SkillType::Swim => CSB_BIT_UP_SWIM_SPEED,
+            SkillType::Jump => CSB_BIT_UP_JUMP_HEIGHT,
+            SkillType::Stealth => CSB_BIT_UP_STEALTH,
+            SkillType::Phoenix => CSB_BIT_PHOENIX,
+            SkillType::ProtectBattery => CSB_BIT_PROTECT_BATTERY,
+            SkillType::ProtectInfection => CSB_BIT_PROTECT_INFECTION,
+            SkillType::MinimapEnemy => CSB_BIT_MINIMAP_ENEMY,
+            SkillType::MinimapTresure => CSB_BIT_MINIMAP_TRESURE,
+            SkillType::RewardBlob => CSB_BIT_REWARD_BLOB,
+            SkillType::RewardCash => CSB_BIT_REWARD_CASH,
+            SkillType::Freedom => CSB_BIT_FREEDOM,
+            _ => return None,
+        };
+        Some(Self::Buff {
+            condition_bit,
+            duration: skill.duration,
+        })
+    }
+}
+
+// Picks the players a support skill lands on: the caster alone,
+// or every living player in the caster's group within range of them.
+pub fn get_support_targets(
+    caster_pc_id: i32,
+    skill: &SkillData,
+    state: &ShardServerState,
+) -> FFResult<Vec<i32>> {
+    let caster = state.get_player(caster_pc_id)?;
+    let group = caster.get_group_id().and_then(|id| state.groups.get(&id));
+    let group = match group {
+        Some(group) if skill.target_type == SKILL_TARGET_GROUP => group,
+        _ => return Ok(vec![caster_pc_id]),
+    };
+
+    let range = match skill.effect_area {
+        0 => RANGE_GROUP_PARTICIPATE,
+        area => area,
+    };
+    let mut target_ids: Vec<i32> = group
+        .get_member_ids()
+        .iter()
+        .filter_map(|id| match id {
+            EntityID::Player(pc_id) => Some(*pc_id),
+            _ => None,
+        })
+        .filter(|pc_id| {
+            let Ok(member) = state.get_player(*pc_id) else {
+                return false;
+            };
+            !member.is_dead()
+                && member.instance_id == caster.instance_id
+                && member.get_position().distance_to(&caster.get_position()) <= range
+        })
+        .collect();
+    target_ids.sort();
+    Ok(target_ids)
+}
+
+// Uses the caster's active nano's skill on their group.
+// Skills that damage enemies go through the attack path instead.
+pub fn do_support_skill(
+    caster_pc_id: i32,
+    req: &sP_CL2FE_REQ_NANO_SKILL_USE,
+    state: &mut ShardServerState,
+    clients: &mut ClientMap,
+    time: SystemTime,
+) -> FFResult<()> {
+    let caster = state.get_player(caster_pc_id)?;
+    let nano = caster.get_active_nano().ok_or(FFError::build(
+        Severity::Warning,
+        format!("{} tried to use a nano skill with no active nano", caster),
+    ))?;
+    let nano_id = nano.get_id();
+    let skill_id = nano.selected_skill.ok_or(FFError::build(
+        Severity::Warning,
+        format!("{} tried to use nano {} with no skill", caster, nano_id),
+    ))?;
+    if nano.stamina <= 0 {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{} tried to use nano {} with no stamina", caster, nano_id),
+        ));
+    }
+
+    let skill = tdata_get().get_skill_data(skill_id)?;
+    if !caster.is_skill_ready(skill_id, time) {
+        // the client keeps its own cooldowns, so only a modified one gets here
+        clients
+            .get_self()
+            .strike("used a nano skill during its cooldown");
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "{} tried to use nano skill {} {}ms before its cooldown ended",
+                caster,
+                skill_id,
+                caster
+                    .get_skill_ready_time(skill_id)
+                    .unwrap()
+                    .duration_since(time)
+                    .unwrap_or_default()
+                    .as_millis()
+            ),
+        ));
+    }
+    let Some(effect) = SupportEffect::from_skill(skill) else {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "Nano skill {} (type {}) isn't implemented",
+                skill_id, skill.skill_type
+            ),
+        ));
+    };
+    let target_ids = get_support_targets(caster_pc_id, skill, state)?;
+
+    // stamina and cooldown
+    let caster = state.get_player_mut(caster_pc_id)?;
+    caster.start_skill_cooldown(skill_id, skill.cooldown, time);
+    let nano = caster.get_active_nano_mut().unwrap();
+    nano.stamina -= skill.stamina_cost;
+    let deactivate = nano.stamina <= 0;
+    if deactivate {
+        nano.stamina = 0;
+    }
+    let nano_stamina = nano.stamina;
+    if deactivate {
+        caster.set_active_nano_slot(None).unwrap();
+    }
+
+    let mut heal_results = Vec::new();
+    let mut buff_results = Vec::new();
+    for target_id in &target_ids {
+        let target = state.get_player_mut(*target_id)?;
+        match effect {
+            SupportEffect::Heal { permille } => {
+                let max_hp = target.get_max_hp();
+                let old_hp = target.get_hp();
+                // never knocks down players who are already over the cap
+                let new_hp = (old_hp + max_hp * permille / 1000).min(max_hp).max(old_hp);
+                target.set_hp(new_hp);
+                heal_results.push(sSkillResult_Heal_HP {
+                    eCT: CharType::Player as i32,
+                    iID: *target_id,
+                    iHealHP: new_hp - old_hp,
+                    iHP: new_hp,
+                });
+            }
+            SupportEffect::Buff {
+                condition_bit,
+                duration,
+            } => {
+                let buff_type = if *target_id == caster_pc_id {
+                    TimeBuffType::Nano
+                } else {
+                    TimeBuffType::GroupNano
+                };
+                target.add_timed_buff(condition_bit, buff_type, duration, time);
+                buff_results.push(sSkillResult_Buff {
+                    eCT: CharType::Player as i32,
+                    iID: *target_id,
+                    bProtected: 0,
+                    iConditionBitFlag: target.get_condition_bit_flag(),
+                });
+                let buff_update =
+                    target.get_buff_update(condition_bit, buff_type, TimeBuffUpdate::Add);
+                if let Some(client) = target.get_client(clients) {
+                    log_if_failed(client.send_packet(P_FE2CL_PC_BUFF_UPDATE, &buff_update));
+                }
+            }
+        }
+    }
+
+    // response packet
+    let resp = sP_FE2CL_NANO_SKILL_USE_SUCC {
+        iPC_ID: caster_pc_id,
+        iBulletID: req.iBulletID,
+        iSkillID: skill_id,
+        iArg1: req.iArg1,
+        iArg2: req.iArg2,
+        iArg3: req.iArg3,
+        bNanoDeactive: if deactivate { 1 } else { 0 },
+        iNanoID: nano_id,
+        iNanoStamina: nano_stamina,
+        eST: skill.skill_type,
+        iTargetCnt: target_ids.len() as i32,
+    };
+    let caster = state.get_player(caster_pc_id)?;
+    if let Some(client) = caster.get_client(clients) {
+        client.queue_packet(P_FE2CL_NANO_SKILL_USE_SUCC, &resp);
+        for result in &heal_results {
+            client.queue_struct(result);
+        }
+        for result in &buff_results {
+            client.queue_struct(result);
+        }
+        log_if_failed(client.flush());
+    }
+
+    // broadcast packet
+    let pkt = sP_FE2CL_NANO_SKILL_USE {
+        iPC_ID: resp.iPC_ID,
+        iBulletID: resp.iBulletID,
+        iSkillID: resp.iSkillID,
+        iArg1: resp.iArg1,
+        iArg2: resp.iArg2,
+        iArg3: resp.iArg3,
+        bNanoDeactive: resp.bNanoDeactive,
+        iNanoID: resp.iNanoID,
+        iNanoStamina: resp.iNanoStamina,
+        eST: resp.eST,
+        iTargetCnt: resp.iTargetCnt,
+    };
+    let mut payload = PacketBuffer::default();
+    payload.queue_packet(P_FE2CL_NANO_SKILL_USE, &pkt);
+    for result in &heal_results {
+        payload.queue_struct(result);
+    }
+    for result in &buff_results {
+        payload.queue_struct(result);
+    }
+    state
+        .entity_map
+        .for_each_around(EntityID::Player(caster_pc_id), clients, |c| {
+            c.send_payload(payload.clone())
+        });
+
+    if deactivate {
+        helpers::broadcast_nano_active(caster_pc_id, clients, state);
+    }
+
+    Ok(())
+}
+
 fn calculate_damage(
     attack: &BasicAttack,
     defense: i32,
     defense_style: Option<CombatStyle>,
     defense_level: i16,
-) -> (i32, bool) {
+) -> (i32, bool, RpsResult) {
     // this formula is taken basically 1:1 from OpenFusion
     let mut rng = rand::thread_rng();
     let BasicAttack {
         power: attack,
         crit_chance,
         attack_style,
+        style_modifiers,
         charged,
     } = attack;
 
     // base damage + variability
     if attack + defense == 0 {
         // divide-by-0 check
-        return (0, false);
+        return (0, false, RpsResult::Draw);
     }
     let mut damage = attack * attack / (attack + defense);
     damage = std::cmp::max(
@@ -213,15 +604,8 @@ fn calculate_damage(
 
     // rock-paper-scissors
     let rps = do_rps(attack_style, &defense_style);
-    match rps {
-        RpsResult::Win => {
-            damage = damage * 5 / 4;
-        }
-        RpsResult::Lose => {
-            damage = damage * 4 / 5;
-        }
-        RpsResult::Draw => {}
-    };
+    let style_bonus = config_get().shard.style_advantage_bonus.get();
+    damage = (damage as f32 * get_style_multiplier(rps, style_bonus, *style_modifiers)) as i32;
 
     // boost
     if *charged {
@@ -237,26 +621,40 @@ fn calculate_damage(
         damage *= 2;
     }
 
-    (damage, crit)
+    (damage, crit, rps)
 }
 
+// Rolls the damage against one target, returning the result to show and the rolled damage.
+// Delayed hits aren't applied yet, so the result shows what the target is expected to have left.
 fn handle_basic_attack(
     from: EntityID,
     to: &mut dyn Combatant,
     attack: &BasicAttack,
-) -> sAttackResult {
+    delayed: bool,
+) -> (sAttackResult, i32) {
     let defense = to.get_defense();
     let defense_style = to.get_style();
     let defense_level = to.get_level();
-    let (damage, crit) = calculate_damage(attack, defense, defense_style, defense_level);
-    let dealt = to.take_damage(damage, from);
+    let (damage, crit, rps) = calculate_damage(attack, defense, defense_style, defense_level);
+    let (dealt, hp) = if delayed {
+        let dealt = damage.clamp(0, to.get_hp());
+        (dealt, to.get_hp() - dealt)
+    } else {
+        let dealt = to.take_damage(damage, from);
+        (dealt, to.get_hp())
+    };
 
     let mut hit_flag = HF_BIT_NORMAL as i8;
     if crit {
         hit_flag |= HF_BIT_CRITICAL as i8;
     }
+    hit_flag |= match rps {
+        RpsResult::Win => HF_BIT_STYLE_WIN as i8,
+        RpsResult::Lose => HF_BIT_STYLE_LOSE as i8,
+        RpsResult::Draw => HF_BIT_STYLE_TIE as i8,
+    };
 
-    sAttackResult {
+    let result = sAttackResult {
         eCT: to.get_char_type() as i32,
         iID: match to.get_id() {
             EntityID::Player(id) => id,
@@ -265,16 +663,33 @@ fn handle_basic_attack(
         },
         bProtected: unused!(),
         iDamage: dealt,
-        iHP: to.get_hp(),
+        iHP: hp,
         iHitFlag: hit_flag,
-    }
+    };
+    (result, damage)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum RpsResult {
     Win,
     Lose,
     Draw,
 }
+
+fn get_style_multiplier(
+    rps: RpsResult,
+    style_bonus: f32,
+    style_modifiers: (Option<f32>, Option<f32>),
+) -> f32 {
+    // weapons can override the default multipliers
+    let (trump_mod, trumped_mod) = style_modifiers;
+    match rps {
+        RpsResult::Win => trump_mod.unwrap_or(1.0 + style_bonus),
+        RpsResult::Lose => trumped_mod.unwrap_or(1.0 / (1.0 + style_bonus)),
+        RpsResult::Draw => 1.0,
+    }
+}
+
 fn do_rps(us: &Option<CombatStyle>, them: &Option<CombatStyle>) -> RpsResult {
     if us.is_none() || them.is_none() {
         return RpsResult::Draw;
@@ -302,3 +717,300 @@ fn do_rps(us: &Option<CombatStyle>, them: &Option<CombatStyle>) -> RpsResult {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use uuid::Uuid;
+
+    use crate::{
+        chunk::{InstanceID, TickMode},
+        entity::Group,
+        test_utils::{FakeClients, PlayerBuilder},
+        Position,
+    };
+
+    fn pending_hit(land_time: SystemTime, attacker_id: i32, target_id: i32) -> PendingHit {
+        PendingHit {
+            land_time,
+            attacker_id: EntityID::Player(attacker_id),
+            target_id: EntityID::Player(target_id),
+            damage: 100,
+            max_range: Some(1000),
+        }
+    }
+
+    #[test]
+    fn test_pending_hit_order() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_millis(500);
+        let mut hits = PendingHits::default();
+        hits.push(pending_hit(later, 1, 2));
+        hits.push(pending_hit(now, 1, 3));
+        hits.push(pending_hit(later, 1, 4));
+        hits.push(pending_hit(now, 1, 5));
+
+        let target_ids = |hits: Vec<PendingHit>| {
+            hits.iter()
+                .map(|hit| hit.target_id)
+                .collect::<Vec<EntityID>>()
+        };
+        assert!(hits.take_landed(now - Duration::from_millis(1)).is_empty());
+        // ties keep the order they were fired in
+        assert_eq!(
+            target_ids(hits.take_landed(now)),
+            vec![EntityID::Player(3), EntityID::Player(5)]
+        );
+        assert_eq!(
+            target_ids(hits.take_landed(later)),
+            vec![EntityID::Player(2), EntityID::Player(4)]
+        );
+        assert!(hits.take_landed(later).is_empty());
+    }
+
+    #[test]
+    fn test_land_hit_target_gone() {
+        let mut state = ShardServerState::new_mock(1);
+        let far_away = Position {
+            x: 5000,
+            y: 0,
+            z: 0,
+        };
+        for (pc_id, position, hp) in [
+            (1, Position::default(), 1000),
+            (2, Position::default(), 1000),
+            (3, far_away, 1000),
+            (4, Position::default(), 100),
+        ] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .position(position)
+                .hp(hp)
+                .build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        let now = SystemTime::now();
+        let hp = |state: &ShardServerState, pc_id| state.get_player(pc_id).unwrap().get_hp();
+
+        // still in range
+        assert_eq!(land_hit(&pending_hit(now, 1, 2), &mut state).unwrap(), 100);
+        assert_eq!(hp(&state, 2), 900);
+
+        // moved out of range while the hit was in flight
+        assert!(land_hit(&pending_hit(now, 1, 3), &mut state).is_err());
+        assert_eq!(hp(&state, 3), 1000);
+
+        // died before it landed
+        assert_eq!(land_hit(&pending_hit(now, 1, 4), &mut state).unwrap(), 100);
+        assert!(land_hit(&pending_hit(now, 1, 4), &mut state).is_err());
+
+        // left the shard entirely
+        assert!(land_hit(&pending_hit(now, 1, 5), &mut state).is_err());
+    }
+
+    #[test]
+    fn test_basic_attack_bad_targets() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        for (pc_id, hp) in [(1, 1000), (2, 1000), (3, 0)] {
+            let mut builder = PlayerBuilder::new(pc_id as i64).pc_id(pc_id).hp(hp);
+            if pc_id == 1 {
+                builder = builder.client_id(key);
+            }
+            state
+                .entity_map
+                .track(Box::new(builder.build()), TickMode::Never);
+        }
+
+        // how many targets the attacker was told they hit
+        let mut attack = |target_ids: &[i32], state: &mut ShardServerState| {
+            let target_ids: Vec<EntityID> =
+                target_ids.iter().map(|id| EntityID::Player(*id)).collect();
+            let mut clients = fake_clients.get_map(key);
+            do_basic_attack(
+                EntityID::Player(1),
+                &target_ids,
+                false,
+                None,
+                state,
+                &mut clients,
+            )
+            .unwrap();
+            let mut sent = fake_clients.take_sent_packets(key);
+            let resp: &sP_FE2CL_PC_ATTACK_CHARs_SUCC =
+                sent[0].get_packet(P_FE2CL_PC_ATTACK_CHARs_SUCC).unwrap();
+            resp.iTargetCnt
+        };
+        let hp = |state: &ShardServerState, pc_id| state.get_player(pc_id).unwrap().get_hp();
+
+        // dead targets are skipped
+        assert_eq!(attack(&[2, 3], &mut state), 1);
+        let hp_after_one_hit = hp(&state, 2);
+        assert!(hp_after_one_hit < 1000);
+        assert_eq!(hp(&state, 3), 0);
+
+        // so are ones that aren't there
+        assert_eq!(attack(&[9, 2], &mut state), 1);
+        assert!(hp(&state, 2) < hp_after_one_hit);
+
+        // and each target is only hit once
+        let hp_before = hp(&state, 2);
+        assert_eq!(attack(&[2, 2, 2], &mut state), 1);
+        assert!(hp(&state, 2) < hp_before);
+
+        // nothing left to hit sends nothing
+        let mut clients = fake_clients.get_map(key);
+        let target_ids = [EntityID::Player(3), EntityID::Player(9)];
+        do_basic_attack(
+            EntityID::Player(1),
+            &target_ids,
+            false,
+            None,
+            &mut state,
+            &mut clients,
+        )
+        .unwrap();
+        assert!(fake_clients.take_sent(key).is_empty());
+    }
+
+    fn support_skill(target_type: i32, effect_area: u32) -> SkillData {
+        SkillData {
+            skill_type: SkillType::HealHP as i32,
+            target_type,
+            effect_area,
+            stamina_cost: 30,
+            duration: Duration::ZERO,
+            cooldown: Duration::ZERO,
+            value: 200,
+        }
+    }
+
+    #[test]
+    fn test_support_targets() {
+        let mut state = ShardServerState::new_mock(1);
+        let near = Position { x: 500, y: 0, z: 0 };
+        let far_away = Position {
+            x: 5000,
+            y: 0,
+            z: 0,
+        };
+        let other_instance = InstanceID {
+            instance_num: Some(1),
+            ..Default::default()
+        };
+
+        let group_id = Uuid::new_v4();
+        let mut group = Group::new(EntityID::Player(1));
+        // (pc ID, in group, position, instance, hp)
+        let players = [
+            (1, true, Position::default(), InstanceID::default(), 1000),
+            (2, true, near, InstanceID::default(), 1000),
+            (3, true, far_away, InstanceID::default(), 1000),
+            (4, true, near, InstanceID::default(), 0),
+            (5, false, near, InstanceID::default(), 1000),
+        ];
+        for (pc_id, in_group, position, instance_id, hp) in players {
+            let mut player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .position(position)
+                .instance_id(instance_id)
+                .hp(hp)
+                .build();
+            if in_group {
+                player.group_id = Some(group_id);
+                if pc_id != 1 {
+                    group.add_member(EntityID::Player(pc_id)).unwrap();
+                }
+            }
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        state.groups.insert(group_id, group);
+
+        // out of range and dead members are left out
+        let targets = get_support_targets(1, &support_skill(SKILL_TARGET_GROUP, 1000), &state);
+        assert_eq!(targets.unwrap(), vec![1, 2]);
+
+        // no effect area falls back to the group participation range
+        let targets = get_support_targets(1, &support_skill(SKILL_TARGET_GROUP, 0), &state);
+        assert_eq!(targets.unwrap(), vec![1, 2, 3]);
+
+        // so are members in another instance
+        state.get_player_mut(2).unwrap().instance_id = other_instance;
+        let targets = get_support_targets(1, &support_skill(SKILL_TARGET_GROUP, 1000), &state);
+        assert_eq!(targets.unwrap(), vec![1]);
+
+        // self-targeted skills skip the group
+        let targets = get_support_targets(2, &support_skill(2, 1000), &state);
+        assert_eq!(targets.unwrap(), vec![2]);
+
+        // so do group skills used outside of a group
+        let targets = get_support_targets(5, &support_skill(SKILL_TARGET_GROUP, 1000), &state);
+        assert_eq!(targets.unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn test_support_effect() {
+        let mut skill = support_skill(SKILL_TARGET_GROUP, 0);
+        assert_eq!(
+            SupportEffect::from_skill(&skill),
+            Some(SupportEffect::Heal { permille: 200 })
+        );
+
+        skill.skill_type = SkillType::Run as i32;
+        skill.duration = Duration::from_secs(15);
+        assert_eq!(
+            SupportEffect::from_skill(&skill),
+            Some(SupportEffect::Buff {
+                condition_bit: CSB_BIT_UP_MOVE_SPEED,
+                duration: Duration::from_secs(15),
+            })
+        );
+
+        // damage skills aren't support skills
+        skill.skill_type = 1;
+        assert_eq!(SupportEffect::from_skill(&skill), None);
+    }
+
+    #[test]
+    fn test_rps_matrix() {
+        use CombatStyle::*;
+        use RpsResult::*;
+
+        let styles = [Adaptium, Blastons, Cosmix];
+        // rows are attacker styles, columns are defender styles
+        let expected = [
+            [Draw, Win, Lose], // Adaptium
+            [Lose, Draw, Win], // Blastons
+            [Win, Lose, Draw], // Cosmix
+        ];
+        for (i, us) in styles.iter().enumerate() {
+            for (j, them) in styles.iter().enumerate() {
+                assert_eq!(do_rps(&Some(*us), &Some(*them)), expected[i][j]);
+            }
+            assert_eq!(do_rps(&Some(*us), &None), Draw);
+            assert_eq!(do_rps(&None, &Some(*us)), Draw);
+        }
+        assert_eq!(do_rps(&None, &None), Draw);
+    }
+
+    #[test]
+    fn test_style_multiplier() {
+        let no_mods = (None, None);
+        assert_eq!(get_style_multiplier(RpsResult::Win, 0.25, no_mods), 1.25);
+        assert_eq!(get_style_multiplier(RpsResult::Lose, 0.25, no_mods), 0.8);
+        assert_eq!(get_style_multiplier(RpsResult::Draw, 0.25, no_mods), 1.0);
+
+        // weapon modifiers take precedence
+        let mods = (Some(1.5), Some(0.5));
+        assert_eq!(get_style_multiplier(RpsResult::Win, 0.25, mods), 1.5);
+        assert_eq!(get_style_multiplier(RpsResult::Lose, 0.25, mods), 0.5);
+        assert_eq!(get_style_multiplier(RpsResult::Draw, 0.25, mods), 1.0);
+
+        // partial weapon modifiers
+        let mods = (Some(2.0), None);
+        assert_eq!(get_style_multiplier(RpsResult::Win, 0.25, mods), 2.0);
+        assert_eq!(get_style_multiplier(RpsResult::Lose, 0.25, mods), 0.8);
+    }
+}
@@ -1,22 +1,29 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
     time::{Duration, SystemTime},
 };
 
 use uuid::Uuid;
 
 use crate::{
+    config::config_get,
     defines::*,
     entity::{Player, PlayerMetadata},
     enums::ShardChannelStatus,
-    error::{log_if_failed, FFError, FFResult, Severity},
+    error::{log, log_if_failed, FFError, FFResult, Severity},
     net::{
         packet::{PacketID::*, *},
-        ClientType, FFClient,
+        ClientType, FFClient, HandlerStats,
     },
     util,
 };
 
+use super::{BuddyPresenceIndex, WorldSnapshot};
+
+const SHARD_CONN_TIMEOUT_SEC: u64 = 20;
+
+#[derive(Debug, Clone)]
 pub struct Account {
     pub id: i64,
     pub username: String,
@@ -25,16 +32,22 @@ pub struct Account {
     pub account_level: i16,
     pub banned_until: SystemTime,
     pub ban_reason: String,
+    pub slot_bonus: u8,
+    pub free_chat_allowed: bool,
 }
 
 struct ShardConnectionRequest {
     pub shard_id: Option<i32>,
     pub expire_time: SystemTime,
+    // last queue position sent to the client while waiting on a full shard
+    pub queue_position: Option<usize>,
 }
 
 struct LoginSession {
     account: Account,
     players: HashMap<i64, Player>,
+    // slots held by characters that failed to load
+    unavailable_slots: HashSet<usize>,
     selected_player_uid: Option<i64>,
     shard_connection_request: Option<ShardConnectionRequest>,
 }
@@ -42,9 +55,31 @@ struct LoginSession {
 struct ShardServerInfo {
     num_channels: u8,
     max_channel_pop: usize,
+    // where the shard says clients can reach it
+    public_addr: SocketAddr,
     players: HashMap<i64, PlayerMetadata>,
+    // players handed off to the shard that it hasn't reported back yet
+    reservations: HashMap<i64, SystemTime>,
+    // cleared while the shard says it can't take new players
+    joinable: bool,
+    // latest world state the shard reported, for the monitor
+    world_snapshot: Option<WorldSnapshot>,
 }
 impl ShardServerInfo {
+    fn get_free_capacity(&self) -> usize {
+        let capacity = self.num_channels as usize * self.max_channel_pop;
+        capacity.saturating_sub(self.players.len() + self.reservations.len())
+    }
+
+    fn prune_reservations(&mut self, time: SystemTime) {
+        self.reservations
+            .retain(|_, expire_time| *expire_time > time);
+    }
+
+    fn cancel_reservation(&mut self, pc_uid: i64) -> bool {
+        self.reservations.remove(&pc_uid).is_some()
+    }
+
     fn get_channel_population(&self, channel_num: u8) -> usize {
         self.players
             .values()
@@ -71,11 +106,23 @@ impl ShardServerInfo {
 
     fn get_channel_statuses(&self) -> [ShardChannelStatus; MAX_NUM_CHANNELS] {
         let mut channels = [ShardChannelStatus::Closed; MAX_NUM_CHANNELS];
-        for i in 0..self.num_channels {
-            channels[i as usize] = self.get_channel_status(i);
+        if !self.joinable {
+            return channels;
+        }
+        for channel_num in 1..=self.num_channels {
+            channels[channel_num as usize - 1] = self.get_channel_status(channel_num);
         }
         channels
     }
+
+    fn get_list_info(&self) -> sP_LS2CL_REP_SHARD_LIST_INFO_SUCC {
+        let mut statuses = [0; MAX_NUM_CHANNELS + 1];
+        statuses[0] = unused!();
+        statuses[1..].copy_from_slice(&self.get_channel_statuses().map(|s| s as u8));
+        sP_LS2CL_REP_SHARD_LIST_INFO_SUCC {
+            aShardConnectFlag: statuses,
+        }
+    }
 }
 
 pub struct PlayerSearchRequest {
@@ -86,7 +133,14 @@ pub struct LoginServerState {
     pub server_id: Uuid,
     sessions: HashMap<i64, LoginSession>,
     shards: HashMap<i32, ShardServerInfo>,
+    // accounts waiting for a spot on a full shard, in order of arrival
+    shard_queues: HashMap<i32, VecDeque<i64>>,
+    // (account ID, player UID) pairs to ask shards about, for when a login runs into a player
+    // that might not actually be on the shard anymore
+    player_checks: HashSet<(i64, i64)>,
     pub player_search_reqeusts: HashMap<(i32, i32), PlayerSearchRequest>,
+    pub buddy_presence: BuddyPresenceIndex,
+    pub handler_stats: HandlerStats,
 }
 impl Default for LoginServerState {
     fn default() -> Self {
@@ -94,7 +148,11 @@ impl Default for LoginServerState {
             server_id: Uuid::new_v4(),
             sessions: HashMap::new(),
             shards: HashMap::new(),
+            shard_queues: HashMap::new(),
+            player_checks: HashSet::new(),
             player_search_reqeusts: HashMap::new(),
+            buddy_presence: BuddyPresenceIndex::default(),
+            handler_stats: HandlerStats::default(),
         }
     }
 }
@@ -117,7 +175,12 @@ impl LoginServerState {
         self.sessions.contains_key(&acc_id)
     }
 
-    pub fn start_session(&mut self, account: Account, player_it: impl Iterator<Item = Player>) {
+    pub fn start_session(
+        &mut self,
+        account: Account,
+        player_it: impl Iterator<Item = Player>,
+        unavailable_slots: HashSet<usize>,
+    ) {
         let mut players = HashMap::new();
         for player in player_it {
             players.insert(player.get_uid(), player);
@@ -127,6 +190,7 @@ impl LoginServerState {
             LoginSession {
                 account,
                 players,
+                unavailable_slots,
                 selected_player_uid: None,
                 shard_connection_request: None,
             },
@@ -140,9 +204,17 @@ impl LoginServerState {
                 format!("Account {} not logged in", acc_id),
             ));
         }
+
+        self.leave_shard_queues(acc_id);
         Ok(())
     }
 
+    fn leave_shard_queues(&mut self, acc_id: i64) {
+        for queue in self.shard_queues.values_mut() {
+            queue.retain(|id| *id != acc_id);
+        }
+    }
+
     pub fn set_selected_player_id(&mut self, acc_id: i64, player_uid: i64) -> FFResult<()> {
         let session = self.get_session_mut(acc_id)?;
         session.selected_player_uid = Some(player_uid);
@@ -164,9 +236,35 @@ impl LoginServerState {
         Ok(&mut acc.players)
     }
 
+    pub fn get_max_player_slots(&self, acc_id: i64) -> FFResult<usize> {
+        let session = self.get_session(acc_id)?;
+        Ok(config_get().login.character_slots.get() + session.account.slot_bonus as usize)
+    }
+
+    pub fn get_free_player_slot(&self, acc_id: i64, requested_slot: usize) -> FFResult<usize> {
+        let session = self.get_session(acc_id)?;
+        let max_slots = self.get_max_player_slots(acc_id)?;
+        let used_slots: HashSet<usize> = session
+            .players
+            .values()
+            .map(|player| player.get_slot_num())
+            .chain(session.unavailable_slots.iter().copied())
+            .collect();
+        find_free_slot(&used_slots, requested_slot, max_slots).ok_or(FFError::build(
+            Severity::Warning,
+            format!(
+                "Account {} has no free character slots ({}/{})",
+                acc_id,
+                used_slots.len(),
+                max_slots
+            ),
+        ))
+    }
+
     pub fn get_lowest_pop_shard_id(&mut self) -> Option<i32> {
         self.shards
             .iter()
+            .filter(|(_, shard)| shard.joinable)
             .min_by_key(|(_, shard)| shard.players.len())
             .map(|(shard_id, _)| *shard_id)
     }
@@ -176,6 +274,7 @@ impl LoginServerState {
         shard_id: i32,
         num_channels: u8,
         max_channel_pop: usize,
+        public_addr: SocketAddr,
     ) -> FFResult<()> {
         if self.shards.contains_key(&shard_id) {
             return Err(FFError::build(
@@ -196,7 +295,11 @@ impl LoginServerState {
             ShardServerInfo {
                 num_channels,
                 max_channel_pop,
+                public_addr,
                 players: HashMap::new(),
+                reservations: HashMap::new(),
+                joinable: true,
+                world_snapshot: None,
             },
         );
         Ok(())
@@ -204,12 +307,58 @@ impl LoginServerState {
 
     pub fn unregister_shard(&mut self, shard_id: i32) {
         self.shards.remove(&shard_id);
+        self.buddy_presence.clear_shard(shard_id);
+        // queued requests fall back to timing out like any other request for a missing shard
+        if let Some(queue) = self.shard_queues.remove(&shard_id) {
+            for acc_id in queue {
+                if let Some(request) = self
+                    .sessions
+                    .get_mut(&acc_id)
+                    .and_then(|session| session.shard_connection_request.as_mut())
+                {
+                    request.queue_position = None;
+                }
+            }
+        }
+    }
+
+    // Where clients should connect to reach the shard
+    pub fn get_shard_public_addr(&self, shard_id: i32) -> FFResult<SocketAddr> {
+        let shard = self.shards.get(&shard_id).ok_or(FFError::build(
+            Severity::Warning,
+            format!("Shard {} not registered", shard_id),
+        ))?;
+        let overrides = config_get().login.shard_addr_overrides.get();
+        Ok(resolve_shard_addr(shard_id, shard.public_addr, &overrides))
     }
 
     pub fn get_shard_ids(&self) -> Vec<i32> {
         self.shards.keys().copied().collect()
     }
 
+    // Returns whether it changed
+    pub fn set_shard_joinable(&mut self, shard_id: i32, joinable: bool) -> bool {
+        let shard = self.shards.get_mut(&shard_id).unwrap();
+        let changed = shard.joinable != joinable;
+        shard.joinable = joinable;
+        changed
+    }
+
+    pub fn set_shard_world_snapshot(&mut self, shard_id: i32, snapshot: WorldSnapshot) {
+        let shard = self.shards.get_mut(&shard_id).unwrap();
+        shard.world_snapshot = Some(snapshot);
+    }
+
+    pub fn get_shard_world_snapshots(&self) -> Vec<(i32, &WorldSnapshot)> {
+        let mut snapshots: Vec<(i32, &WorldSnapshot)> = self
+            .shards
+            .iter()
+            .filter_map(|(shard_id, shard)| Some((*shard_id, shard.world_snapshot.as_ref()?)))
+            .collect();
+        snapshots.sort_by_key(|(shard_id, _)| *shard_id);
+        snapshots
+    }
+
     pub fn clear_shard_players(&mut self, shard_id: i32) {
         let shard = self.shards.get_mut(&shard_id).unwrap();
         shard.players.clear();
@@ -228,10 +377,83 @@ impl LoginServerState {
         }
 
         let shard = self.shards.get_mut(&shard_id).unwrap();
+        shard.reservations.remove(&player_uid);
         shard.players.insert(player_uid, player_data);
         old_shard_id
     }
 
+    // For when the shard gives up waiting on a player it was handed off
+    pub fn cancel_reservation(&mut self, shard_id: i32, player_uid: i64) -> bool {
+        self.shards
+            .get_mut(&shard_id)
+            .is_some_and(|shard| shard.cancel_reservation(player_uid))
+    }
+
+    // Asks the shard the player is supposedly on whether they're really there
+    pub fn request_player_check(&mut self, acc_id: i64, player_uid: i64) {
+        self.player_checks.insert((acc_id, player_uid));
+    }
+
+    // Returns true if the shard said the player was gone and they were dropped
+    pub fn resolve_player_check(&mut self, shard_id: i32, player_uid: i64, present: bool) -> bool {
+        if present {
+            return false;
+        }
+        self.shards
+            .get_mut(&shard_id)
+            .is_some_and(|shard| shard.players.remove(&player_uid).is_some())
+    }
+
+    pub fn process_player_checks(&mut self, clients: &mut HashMap<usize, FFClient>) {
+        for (acc_id, pc_uid) in std::mem::take(&mut self.player_checks) {
+            let Some(shard_id) = self.get_player_shard(pc_uid) else {
+                continue;
+            };
+            let Some(shard) = clients
+                .values_mut()
+                .find(|c| matches!(c.client_type, ClientType::ShardServer(sid) if sid == shard_id))
+            else {
+                continue;
+            };
+            let pkt = sP_LS2FE_REQ_VERIFY_PLAYER {
+                iAccountID: acc_id,
+                iPC_UID: pc_uid,
+            };
+            log_if_failed(shard.send_packet(P_LS2FE_REQ_VERIFY_PLAYER, &pkt));
+        }
+    }
+
+    // Sends fresh buddy states to each player's shard, given players by shard ID
+    pub fn push_buddy_states(
+        &self,
+        watchers: HashMap<i32, Vec<i64>>,
+        clients: &mut HashMap<usize, FFClient>,
+    ) {
+        for (shard_id, watcher_uids) in watchers {
+            let Some(shard) = clients
+                .values_mut()
+                .find(|c| matches!(c.client_type, ClientType::ShardServer(sid) if sid == shard_id))
+            else {
+                continue;
+            };
+            for watcher_uid in watcher_uids {
+                let Some(buddy_states) = self.buddy_presence.get_buddy_states(watcher_uid) else {
+                    continue;
+                };
+                let mut pkt = sP_LS2FE_REP_GET_BUDDY_STATE {
+                    iPC_UID: watcher_uid,
+                    aBuddyUID: [0; SIZEOF_BUDDYLIST_SLOT as usize],
+                    aBuddyState: [0; SIZEOF_BUDDYLIST_SLOT as usize],
+                };
+                for (i, (buddy_uid, online)) in buddy_states.into_iter().enumerate() {
+                    pkt.aBuddyUID[i] = buddy_uid;
+                    pkt.aBuddyState[i] = if online { 1 } else { 0 };
+                }
+                log_if_failed(shard.send_packet(P_LS2FE_REP_GET_BUDDY_STATE, &pkt));
+            }
+        }
+    }
+
     pub fn get_player_shard(&self, player_uid: i64) -> Option<i32> {
         for (shard_id, shard) in self.shards.iter() {
             if shard.players.contains_key(&player_uid) {
@@ -259,12 +481,18 @@ impl LoginServerState {
         shard.get_channel_statuses()
     }
 
+    pub fn get_shard_list_info(&self, shard_id: i32) -> sP_LS2CL_REP_SHARD_LIST_INFO_SUCC {
+        let shard = self.shards.get(&shard_id).unwrap();
+        shard.get_list_info()
+    }
+
     pub fn request_shard_connection(&mut self, acc_id: i64, shard_id: Option<i32>) -> FFResult<()> {
-        const SHARD_CONN_TIMEOUT_SEC: u64 = 20;
+        self.leave_shard_queues(acc_id);
         let session = self.get_session_mut(acc_id)?;
         session.shard_connection_request = Some(ShardConnectionRequest {
             shard_id,
             expire_time: SystemTime::now() + Duration::from_secs(SHARD_CONN_TIMEOUT_SEC),
+            queue_position: None,
         });
         Ok(())
     }
@@ -275,6 +503,10 @@ impl LoginServerState {
         time: SystemTime,
     ) {
         let lowest_pop_shard_id = self.get_lowest_pop_shard_id();
+        for shard in self.shards.values_mut() {
+            shard.prune_reservations(time);
+        }
+
         let client_keys = clients.keys().copied().collect::<Vec<_>>();
         for client_key in client_keys {
             let client = clients.get_mut(&client_key).unwrap();
@@ -291,11 +523,12 @@ impl LoginServerState {
             let Some(pc_uid) = session.selected_player_uid else {
                 continue;
             };
-            let Some(request) = &session.shard_connection_request else {
+            let Some(request) = session.shard_connection_request.as_mut() else {
                 continue;
             };
 
-            if request.expire_time < time {
+            // queued requests wait as long as it takes
+            if request.queue_position.is_none() && request.expire_time < time {
                 let resp = sP_LS2CL_REP_SHARD_SELECT_FAIL {
                     iErrorCode: 1, // "Shard connection error"
                 };
@@ -316,6 +549,63 @@ impl LoginServerState {
                 }
             };
 
+            // a shard that isn't ready yet may be soon, so wait it out like a missing one
+            if self
+                .shards
+                .get(&shard_id)
+                .is_some_and(|shard| !shard.joinable)
+            {
+                continue;
+            }
+
+            let can_bypass_queue = config_get().login.queue_bypass_gm.get()
+                && session.account.account_level <= CN_ACCOUNT_LEVEL__GM as i16;
+            if let Some(shard_info) = self.shards.get(&shard_id).filter(|_| !can_bypass_queue) {
+                let shard_full = shard_info.get_free_capacity() == 0;
+                let queue = self.shard_queues.entry(shard_id).or_default();
+                // nobody cuts in line
+                if !queue.contains(&acc_id) && (shard_full || !queue.is_empty()) {
+                    queue.push_back(acc_id);
+                    log(
+                        Severity::Info,
+                        &format!(
+                            "Account {} queued for full shard {} ({} waiting)",
+                            acc_id,
+                            shard_id,
+                            queue.len()
+                        ),
+                    );
+                }
+
+                if let Some(idx) = queue.iter().position(|id| *id == acc_id) {
+                    if idx > 0 || shard_full {
+                        let position = idx + 1;
+                        if request.queue_position != Some(position) {
+                            log(
+                                Severity::Debug,
+                                &format!(
+                                    "Account {} is number {} in line for shard {}",
+                                    acc_id, position, shard_id
+                                ),
+                            );
+                            // the client can't show a place in line,
+                            // but a fresh channel list at least shows the shard is full
+                            let resp = shard_info.get_list_info();
+                            log_if_failed(
+                                client.send_packet(P_LS2CL_REP_SHARD_LIST_INFO_SUCC, &resp),
+                            );
+                            request.queue_position = Some(position);
+                        }
+                        continue;
+                    }
+
+                    // front of the line and there's room; the usual timeout applies from here
+                    queue.pop_front();
+                    request.queue_position = None;
+                    request.expire_time = time + Duration::from_secs(SHARD_CONN_TIMEOUT_SEC);
+                }
+            }
+
             let Some(shard) = clients
                 .values_mut()
                 .find(|c| matches!(c.client_type, ClientType::ShardServer(sid) if sid == shard_id))
@@ -340,8 +630,440 @@ impl LoginServerState {
                 };
                 let client = clients.get_mut(&client_key).unwrap();
                 log_if_failed(client.send_packet(P_LS2CL_REP_SHARD_SELECT_FAIL, &resp));
+            } else if let Some(shard_info) = self.shards.get_mut(&shard_id) {
+                // hold the spot until the shard reports the player
+                let expire_time = time + Duration::from_secs(SHARD_CONN_TIMEOUT_SEC);
+                shard_info.reservations.insert(pc_uid, expire_time);
             }
             session.shard_connection_request = None;
         }
     }
 }
+
+// Has every other shard kick an account banned on one of them,
+// and drops the account's own connection here
+pub fn relay_account_ban(
+    from_shard_key: usize,
+    pkt: &sP_FE2LS_ACCOUNT_BANNED,
+    clients: &mut HashMap<usize, FFClient>,
+) {
+    let acc_id = pkt.iAccountID;
+    let kick_pkt = sP_LS2FE_REQ_KICK_ACCOUNT {
+        iAccountID: acc_id,
+        iBannedUntil: pkt.iBannedUntil,
+        szBanReason: pkt.szBanReason,
+    };
+    for (key, client) in clients.iter_mut() {
+        match client.client_type {
+            // the banning shard already kicked the account
+            ClientType::ShardServer(_) if *key != from_shard_key => {
+                log_if_failed(client.send_packet(P_LS2FE_REQ_KICK_ACCOUNT, &kick_pkt));
+            }
+            ClientType::GameClient { account_id, .. } if account_id == acc_id => {
+                client.disconnect();
+            }
+            _ => (),
+        }
+    }
+}
+
+fn find_free_slot(
+    used_slots: &HashSet<usize>,
+    requested_slot: usize,
+    max_slots: usize,
+) -> Option<usize> {
+    if used_slots.len() >= max_slots {
+        return None;
+    }
+
+    // slot numbers are 1-indexed
+    if (1..=max_slots).contains(&requested_slot) && !used_slots.contains(&requested_slot) {
+        return Some(requested_slot);
+    }
+    (1..=max_slots).find(|slot| !used_slots.contains(slot))
+}
+
+// A login-side override beats what the shard advertised, since the shard might not know its public address
+fn resolve_shard_addr(
+    shard_id: i32,
+    advertised: SocketAddr,
+    overrides: &HashMap<String, String>,
+) -> SocketAddr {
+    let Some(addr) = overrides.get(&shard_id.to_string()) else {
+        return advertised;
+    };
+    match addr.parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            log(
+                Severity::Warning,
+                &format!("Bad address override for shard {}: {}", shard_id, addr),
+            );
+            advertised
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        database::{Database, MockDatabase},
+        helpers,
+        test_utils::{FakeClients, PlayerBuilder},
+    };
+
+    #[test]
+    fn test_shard_free_capacity() {
+        let now = SystemTime::now();
+        let mut shard = ShardServerInfo {
+            num_channels: 2,
+            max_channel_pop: 2,
+            public_addr: "127.0.0.1:23001".parse().unwrap(),
+            players: HashMap::new(),
+            reservations: HashMap::new(),
+            joinable: true,
+            world_snapshot: None,
+        };
+        assert_eq!(shard.get_free_capacity(), 4);
+
+        // pending handoffs count against capacity until they expire
+        for pc_uid in 1..=5 {
+            shard
+                .reservations
+                .insert(pc_uid, now + Duration::from_secs(pc_uid as u64));
+        }
+        assert_eq!(shard.get_free_capacity(), 0);
+        shard.prune_reservations(now + Duration::from_secs(2));
+        assert_eq!(shard.get_free_capacity(), 1);
+        shard.prune_reservations(now + Duration::from_secs(5));
+        assert_eq!(shard.get_free_capacity(), 4);
+    }
+
+    #[test]
+    fn test_unjoinable_shard_hidden() {
+        let mut state = LoginServerState::default();
+        let addr: SocketAddr = "127.0.0.1:23001".parse().unwrap();
+        state.register_shard(1, 2, 10, addr).unwrap();
+        state.register_shard(2, 2, 10, addr).unwrap();
+        let metadata = PlayerMetadata {
+            first_name: "Alpha".to_string(),
+            last_name: "Bravo".to_string(),
+            x_coord: 0,
+            y_coord: 0,
+            z_coord: 0,
+            channel: 1,
+        };
+        state.set_player_shard(10, metadata, 2);
+        assert_eq!(state.get_lowest_pop_shard_id(), Some(1));
+
+        assert!(state.set_shard_joinable(1, false));
+        assert!(!state.set_shard_joinable(1, false));
+        assert_eq!(state.get_lowest_pop_shard_id(), Some(2));
+        assert!(state
+            .get_shard_channel_statuses(1)
+            .iter()
+            .all(|status| *status == ShardChannelStatus::Closed));
+
+        assert!(state.set_shard_joinable(2, false));
+        assert_eq!(state.get_lowest_pop_shard_id(), None);
+
+        // the shard getting its login server link back reopens it
+        assert!(state.set_shard_joinable(1, true));
+        assert_eq!(state.get_lowest_pop_shard_id(), Some(1));
+        assert_eq!(
+            state.get_shard_channel_statuses(1)[0],
+            ShardChannelStatus::Empty
+        );
+    }
+
+    #[test]
+    fn test_shard_world_snapshots() {
+        let mut state = LoginServerState::default();
+        let addr: SocketAddr = "127.0.0.1:23001".parse().unwrap();
+        for shard_id in [3, 1, 2] {
+            state.register_shard(shard_id, 1, 10, addr).unwrap();
+        }
+        let snapshot = |pop| WorldSnapshot {
+            channel_pops: vec![pop],
+            ..Default::default()
+        };
+        state.set_shard_world_snapshot(3, snapshot(30));
+        state.set_shard_world_snapshot(1, snapshot(10));
+        state.set_shard_world_snapshot(1, snapshot(15));
+
+        // shard 2 hasn't reported yet; newer reports replace older ones
+        let snapshots = state.get_shard_world_snapshots();
+        assert_eq!(snapshots, vec![(1, &snapshot(15)), (3, &snapshot(30))]);
+
+        // gone with the shard
+        state.unregister_shard(3);
+        assert_eq!(state.get_shard_world_snapshots(), vec![(1, &snapshot(15))]);
+    }
+
+    #[test]
+    fn test_cancel_reservation() {
+        let now = SystemTime::now();
+        let mut state = LoginServerState::default();
+        state
+            .register_shard(1, 1, 2, "127.0.0.1:23001".parse().unwrap())
+            .unwrap();
+        let shard = state.shards.get_mut(&1).unwrap();
+        shard
+            .reservations
+            .insert(1, now + Duration::from_secs(SHARD_CONN_TIMEOUT_SEC));
+        shard
+            .reservations
+            .insert(2, now + Duration::from_secs(SHARD_CONN_TIMEOUT_SEC));
+        assert_eq!(shard.get_free_capacity(), 0);
+
+        // the shard dropped the player's login info, so the spot frees up right away
+        assert!(state.cancel_reservation(1, 1));
+        assert!(!state.cancel_reservation(1, 1));
+        assert!(!state.cancel_reservation(2, 2));
+        assert_eq!(state.shards[&1].get_free_capacity(), 1);
+    }
+
+    #[test]
+    fn test_shard_queue() {
+        let now = SystemTime::now();
+        let mut state = LoginServerState::default();
+        state
+            .register_shard(1, 1, 1, "127.0.0.1:23001".parse().unwrap())
+            .unwrap();
+        let metadata = PlayerMetadata {
+            first_name: "Alpha".to_string(),
+            last_name: "Bravo".to_string(),
+            x_coord: 0,
+            y_coord: 0,
+            z_coord: 0,
+            channel: 1,
+        };
+        state.set_player_shard(99, metadata, 1);
+
+        let account = Account {
+            id: 1,
+            username: "test".to_string(),
+            password_hashed: String::new(),
+            selected_slot: 1,
+            account_level: CN_ACCOUNT_LEVEL__USER as i16,
+            banned_until: SystemTime::UNIX_EPOCH,
+            ban_reason: String::new(),
+            slot_bonus: 0,
+            free_chat_allowed: true,
+        };
+        let player = PlayerBuilder::new(10).build();
+        state.start_session(account, [player].into_iter(), HashSet::new());
+        state.set_selected_player_id(1, 10).unwrap();
+        state.request_shard_connection(1, Some(1)).unwrap();
+
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(None);
+
+        // the client is only told about the full shard once per place in line
+        state.process_shard_connection_requests(fake_clients.get_clients(), now);
+        let mut sent = fake_clients.take_sent_packets(key);
+        assert_eq!(sent.len(), 1);
+        let resp: &sP_LS2CL_REP_SHARD_LIST_INFO_SUCC = sent[0]
+            .get_packet(P_LS2CL_REP_SHARD_LIST_INFO_SUCC)
+            .unwrap();
+        assert_eq!(resp.aShardConnectFlag[1], ShardChannelStatus::Closed as u8);
+        assert_eq!(state.shard_queues[&1], [1]);
+
+        state.process_shard_connection_requests(fake_clients.get_clients(), now);
+        assert!(fake_clients.take_sent(key).is_empty());
+
+        // a spot opening up moves the account out of line
+        state.shards.get_mut(&1).unwrap().players.remove(&99);
+        state.process_shard_connection_requests(fake_clients.get_clients(), now);
+        assert!(state.shard_queues[&1].is_empty());
+    }
+
+    #[test]
+    fn test_stale_player_recovery() {
+        let mut state = LoginServerState::default();
+        let addr: SocketAddr = "127.0.0.1:23001".parse().unwrap();
+        state.register_shard(1, 1, 10, addr).unwrap();
+        state.register_shard(2, 1, 10, addr).unwrap();
+        let metadata = PlayerMetadata {
+            first_name: "Alpha".to_string(),
+            last_name: "Bravo".to_string(),
+            x_coord: 0,
+            y_coord: 0,
+            z_coord: 0,
+            channel: 1,
+        };
+        state.set_player_shard(10, metadata, 1);
+
+        // checks queue up until the timer sends them out
+        state.request_player_check(5, 10);
+        state.request_player_check(5, 10);
+        assert_eq!(state.player_checks.len(), 1);
+        state.process_player_checks(&mut HashMap::new());
+        assert!(state.player_checks.is_empty());
+
+        // the shard still has them, or another shard answered
+        assert!(!state.resolve_player_check(1, 10, true));
+        assert!(!state.resolve_player_check(2, 10, false));
+        assert_eq!(state.get_player_shard(10), Some(1));
+
+        // the shard doesn't have them, so they can log in again
+        assert!(state.resolve_player_check(1, 10, false));
+        assert_eq!(state.get_player_shard(10), None);
+        assert!(!state.resolve_player_check(1, 10, false));
+    }
+
+    #[test]
+    fn test_shard_public_addr() {
+        let first_addr: SocketAddr = "10.0.0.5:23001".parse().unwrap();
+        let second_addr: SocketAddr = "10.0.0.6:23001".parse().unwrap();
+        let mut state = LoginServerState::default();
+        state.register_shard(1, 1, 100, first_addr).unwrap();
+        assert_eq!(state.get_shard_public_addr(1).unwrap(), first_addr);
+
+        // the cached address goes away with the shard, and a reconnect can change it
+        state.unregister_shard(1);
+        assert!(state.get_shard_public_addr(1).is_err());
+        state.register_shard(1, 1, 100, second_addr).unwrap();
+        assert_eq!(state.get_shard_public_addr(1).unwrap(), second_addr);
+
+        // overrides apply per shard, and bad ones are ignored
+        let overrides = HashMap::from([
+            ("1".to_string(), "203.0.113.5:23001".to_string()),
+            ("2".to_string(), "not an address".to_string()),
+        ]);
+        assert_eq!(
+            resolve_shard_addr(1, first_addr, &overrides),
+            "203.0.113.5:23001".parse().unwrap()
+        );
+        assert_eq!(resolve_shard_addr(2, first_addr, &overrides), first_addr);
+        assert_eq!(resolve_shard_addr(3, first_addr, &overrides), first_addr);
+    }
+
+    fn log_in(db: &mut MockDatabase, state: &mut LoginServerState, username: &str) -> i64 {
+        let account = db
+            .find_account_from_username(&username.to_string())
+            .unwrap()
+            .unwrap();
+        let acc_id = account.id;
+        let players = db.load_players(acc_id).unwrap();
+        state.start_session(
+            account,
+            players.into_iter().map(Result::unwrap),
+            HashSet::new(),
+        );
+        acc_id
+    }
+
+    #[test]
+    fn test_slot_bonus_grant() {
+        let mut db = MockDatabase::default();
+        let acc = db
+            .create_account(&"player".to_string(), &"hash".to_string())
+            .unwrap();
+        let base_slots = config_get().login.character_slots.get();
+        for slot in 1..=base_slots {
+            let player = PlayerBuilder::new(slot as i64).slot_num(slot).build();
+            db.init_player(acc.id, &player).unwrap();
+        }
+
+        let mut state = LoginServerState::default();
+        log_in(&mut db, &mut state, "player");
+        assert!(state.get_free_player_slot(acc.id, 1).is_err());
+
+        // what the slots command saves
+        let granted_acc = db.find_account_from_player(1).unwrap();
+        db.set_account_slot_bonus(granted_acc.id, 1).unwrap();
+
+        // sessions keep the account they started with
+        assert!(state.get_free_player_slot(acc.id, 1).is_err());
+        state.end_session(acc.id).unwrap();
+        log_in(&mut db, &mut state, "player");
+        assert_eq!(state.get_max_player_slots(acc.id).unwrap(), base_slots + 1);
+        let slot = state.get_free_player_slot(acc.id, 1).unwrap();
+        assert_eq!(slot, base_slots + 1);
+
+        let player = PlayerBuilder::new(100).slot_num(slot).build();
+        db.init_player(acc.id, &player).unwrap();
+    }
+
+    #[test]
+    fn test_find_free_slot() {
+        const MAX_SLOTS: usize = 4;
+        let mut used_slots = HashSet::new();
+
+        // create up to the cap
+        for slot in 1..=MAX_SLOTS {
+            let assigned = find_free_slot(&used_slots, slot, MAX_SLOTS).unwrap();
+            assert_eq!(assigned, slot);
+            used_slots.insert(assigned);
+        }
+        assert!(find_free_slot(&used_slots, 1, MAX_SLOTS).is_none());
+        assert!(find_free_slot(&used_slots, MAX_SLOTS + 1, MAX_SLOTS).is_none());
+
+        // delete then create again; freed slot is reused
+        used_slots.remove(&2);
+        assert_eq!(find_free_slot(&used_slots, 2, MAX_SLOTS), Some(2));
+        // colliding or out-of-range requests get the freed slot
+        assert_eq!(find_free_slot(&used_slots, 3, MAX_SLOTS), Some(2));
+        assert_eq!(find_free_slot(&used_slots, 0, MAX_SLOTS), Some(2));
+        assert_eq!(find_free_slot(&used_slots, 9, MAX_SLOTS), Some(2));
+        used_slots.insert(2);
+        assert!(find_free_slot(&used_slots, 2, MAX_SLOTS).is_none());
+
+        // bonus slots extend the cap
+        assert_eq!(find_free_slot(&used_slots, 1, MAX_SLOTS + 1), Some(5));
+        assert_eq!(find_free_slot(&used_slots, 5, MAX_SLOTS + 1), Some(5));
+    }
+
+    #[test]
+    fn test_relay_account_ban() {
+        let mut fake_clients = FakeClients::default();
+        let origin_key = fake_clients.add_client(ClientType::ShardServer(1));
+        let shard_key = fake_clients.add_client(ClientType::ShardServer(2));
+        let banned_key = fake_clients.add_client(ClientType::GameClient {
+            account_id: 1,
+            serial_key: 0,
+            pc_id: None,
+        });
+        let other_key = fake_clients.add_client(ClientType::GameClient {
+            account_id: 2,
+            serial_key: 0,
+            pc_id: None,
+        });
+
+        let banned_until = SystemTime::now() + Duration::from_secs(3600);
+        let pkt = sP_FE2LS_ACCOUNT_BANNED {
+            iAccountID: 1,
+            iBannedUntil: util::get_timestamp_sec(banned_until) as i64,
+            szBanReason: util::encode_utf16("spam"),
+        };
+        relay_account_ban(origin_key, &pkt, fake_clients.get_clients());
+
+        assert!(fake_clients.take_sent(origin_key).is_empty());
+        assert!(fake_clients.get_client(banned_key).should_dc());
+        assert!(!fake_clients.get_client(other_key).should_dc());
+        assert!(!fake_clients.get_client(shard_key).should_dc());
+
+        let mut sent = fake_clients.take_sent_packets(shard_key);
+        assert_eq!(sent.len(), 1);
+        let kick_pkt: sP_LS2FE_REQ_KICK_ACCOUNT =
+            *sent[0].get_packet(P_LS2FE_REQ_KICK_ACCOUNT).unwrap();
+        let (acc_id, secs) = (kick_pkt.iAccountID, kick_pkt.iBannedUntil);
+        let sent_secs = pkt.iBannedUntil;
+        assert_eq!((acc_id, secs), (1, sent_secs));
+
+        // and the other shard kicks its player on that account
+        let mut shard_clients = FakeClients::default();
+        let player_key = shard_clients.add_game_client(Some(1));
+        let login_key = shard_clients.add_client(ClientType::LoginServer);
+        let mut clients = shard_clients.get_map(login_key);
+        helpers::kick_account_for_login_server(&kick_pkt, &mut clients).unwrap();
+        assert_eq!(
+            shard_clients.take_sent(player_key),
+            vec![P_FE2CL_ANNOUNCE_MSG, P_FE2CL_REP_PC_EXIT_SUCC]
+        );
+        assert!(shard_clients.get_client(player_key).should_dc());
+    }
+}
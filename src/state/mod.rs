@@ -3,14 +3,26 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use crate::error::{FFError, FFResult, Severity};
+use crate::{
+    error::{FFError, FFResult, Severity},
+    net::HandlerStats,
+};
 
 mod login;
 pub use login::*;
 
+mod pending;
+pub use pending::*;
+
+mod presence;
+pub use presence::*;
+
 mod shard;
 pub use shard::*;
 
+mod snapshot;
+pub use snapshot::*;
+
 #[derive(Debug)]
 pub struct FFReceiver<T> {
     start_time: SystemTime,
@@ -96,6 +108,13 @@ impl ServerState {
         }
     }
 
+    pub fn get_handler_stats_mut(&mut self) -> &mut HandlerStats {
+        match self {
+            Self::Login(state) => &mut state.handler_stats,
+            Self::Shard(state) => &mut state.handler_stats,
+        }
+    }
+
     pub fn as_shard(&mut self) -> &mut ShardServerState {
         if let Self::Shard(state) = self {
             state
@@ -0,0 +1,152 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    database::DbResult,
+    error::{log, FFError, FFResult, Severity},
+    net::ClientMap,
+};
+
+use super::FFReceiver;
+
+pub type ReceiverCallback<S> = fn(&mut S, &mut ClientMap, FFResult<DbResult>);
+
+struct PendingOp<S> {
+    label: String,
+    rx: FFReceiver<DbResult>,
+    deadline: SystemTime,
+    on_complete: ReceiverCallback<S>,
+}
+
+// Async DB operations whose results the owning state still cares about.
+// Each op's callback fires exactly once: with its result, or with an error if it times out.
+pub struct PendingOps<S> {
+    ops: Vec<PendingOp<S>>,
+}
+impl<S> Default for PendingOps<S> {
+    fn default() -> Self {
+        Self { ops: Vec::new() }
+    }
+}
+impl<S> PendingOps<S> {
+    pub fn register(
+        &mut self,
+        label: &str,
+        rx: FFReceiver<DbResult>,
+        timeout: Duration,
+        on_complete: ReceiverCallback<S>,
+    ) {
+        self.ops.push(PendingOp {
+            label: label.to_string(),
+            rx,
+            deadline: SystemTime::now() + timeout,
+            on_complete,
+        });
+    }
+
+    // Takes every op that finished or expired, paired with its callback.
+    // The callbacks need the state that owns this, so the caller runs them.
+    pub fn poll(&mut self, time: SystemTime) -> Vec<(ReceiverCallback<S>, FFResult<DbResult>)> {
+        let mut finished = Vec::new();
+        self.ops.retain(|op| {
+            let result = match op.rx.try_recv() {
+                Some(result) => result,
+                None if time > op.deadline => {
+                    let msg = format!("Gave up waiting on {}", op.label);
+                    log(Severity::Warning, &msg);
+                    Err(FFError::build(Severity::Warning, msg))
+                }
+                None => return true, // in progress
+            };
+            finished.push((op.on_complete, result));
+            false
+        });
+        finished
+    }
+
+    // Drops everything still pending, returning what was abandoned
+    pub fn drain(&mut self) -> Vec<String> {
+        self.ops.drain(..).map(|op| op.label).collect()
+    }
+
+    pub fn get_labels(&self) -> impl Iterator<Item = &str> {
+        self.ops.iter().map(|op| op.label.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::mpsc};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct TestState {
+        completed: Vec<bool>,
+    }
+
+    fn on_test_complete(state: &mut TestState, _clients: &mut ClientMap, res: FFResult<DbResult>) {
+        state.completed.push(res.is_ok());
+    }
+
+    fn run_callbacks(pending: &mut PendingOps<TestState>, state: &mut TestState, time: SystemTime) {
+        let mut clients = HashMap::new();
+        let mut clients = ClientMap::new(0, &mut clients);
+        for (on_complete, res) in pending.poll(time) {
+            on_complete(state, &mut clients, res);
+        }
+    }
+
+    #[test]
+    fn test_pending_ops_complete_once() {
+        let mut pending = PendingOps::default();
+        let mut state = TestState::default();
+        let (tx, rx) = mpsc::channel();
+        pending.register(
+            "test op",
+            FFReceiver::new(SystemTime::now(), rx),
+            Duration::from_secs(60),
+            on_test_complete,
+        );
+
+        run_callbacks(&mut pending, &mut state, SystemTime::now());
+        assert!(state.completed.is_empty());
+        assert_eq!(pending.get_labels().collect::<Vec<_>>(), vec!["test op"]);
+
+        tx.send(DbResult::new(Ok(()))).unwrap();
+        run_callbacks(&mut pending, &mut state, SystemTime::now());
+        run_callbacks(&mut pending, &mut state, SystemTime::now());
+        assert_eq!(state.completed, vec![true]);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_pending_ops_timeout() {
+        let mut pending = PendingOps::default();
+        let mut state = TestState::default();
+        let (_tx, rx) = mpsc::channel();
+        pending.register(
+            "slow op",
+            FFReceiver::new(SystemTime::now(), rx),
+            Duration::from_secs(5),
+            on_test_complete,
+        );
+        let (_tx2, rx2) = mpsc::channel();
+        pending.register(
+            "slower op",
+            FFReceiver::new(SystemTime::now(), rx2),
+            Duration::from_secs(60),
+            on_test_complete,
+        );
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        run_callbacks(&mut pending, &mut state, later);
+        run_callbacks(&mut pending, &mut state, later);
+        assert_eq!(state.completed, vec![false]);
+        assert_eq!(pending.drain(), vec!["slower op".to_string()]);
+        assert!(pending.is_empty());
+    }
+}
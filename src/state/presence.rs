@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+
+struct PresenceEntry {
+    shard_id: i32,
+    buddies: HashSet<i64>,
+    blocked: HashSet<i64>,
+}
+
+// Which online players want to hear about each other coming and going.
+// Kept by the login server from the buddy lists shards send when players load in.
+#[derive(Default)]
+pub struct BuddyPresenceIndex {
+    online: HashMap<i64, PresenceEntry>,
+    // player UID -> online players with them on their buddy list
+    watchers: HashMap<i64, HashSet<i64>>,
+}
+impl BuddyPresenceIndex {
+    // Records a player as online along with their latest buddy list.
+    // Returns whether they just came online, as opposed to syncing a changed buddy list.
+    pub fn set_online(
+        &mut self,
+        pc_uid: i64,
+        shard_id: i32,
+        buddies: &[i64],
+        blocked: &[i64],
+    ) -> bool {
+        let came_online = self.remove(pc_uid).is_none();
+        for buddy_uid in buddies {
+            self.watchers.entry(*buddy_uid).or_default().insert(pc_uid);
+        }
+        self.online.insert(
+            pc_uid,
+            PresenceEntry {
+                shard_id,
+                buddies: buddies.iter().copied().collect(),
+                blocked: blocked.iter().copied().collect(),
+            },
+        );
+        came_online
+    }
+
+    // Returns whether they were online
+    pub fn set_offline(&mut self, pc_uid: i64) -> bool {
+        self.remove(pc_uid).is_some()
+    }
+
+    // Drops everyone on a shard that went away
+    pub fn clear_shard(&mut self, shard_id: i32) {
+        let pc_uids: Vec<i64> = self
+            .online
+            .iter()
+            .filter(|(_, entry)| entry.shard_id == shard_id)
+            .map(|(pc_uid, _)| *pc_uid)
+            .collect();
+        for pc_uid in pc_uids {
+            self.remove(pc_uid);
+        }
+    }
+
+    pub fn is_online(&self, pc_uid: i64) -> bool {
+        self.online.contains_key(&pc_uid)
+    }
+
+    // Whether `viewer` gets to see that `pc_uid` is online
+    pub fn is_visible_to(&self, pc_uid: i64, viewer_uid: i64) -> bool {
+        self.online
+            .get(&pc_uid)
+            .is_some_and(|entry| !entry.blocked.contains(&viewer_uid))
+    }
+
+    // Who should hear about the player coming or going, grouped by the shard they're on.
+    // Anyone the player blocked is left out.
+    pub fn get_watchers(&self, pc_uid: i64) -> HashMap<i32, Vec<i64>> {
+        let blocked = self.online.get(&pc_uid).map(|entry| &entry.blocked);
+        let mut by_shard: HashMap<i32, Vec<i64>> = HashMap::new();
+        for watcher_uid in self.watchers.get(&pc_uid).into_iter().flatten() {
+            if blocked.is_some_and(|blocked| blocked.contains(watcher_uid)) {
+                continue;
+            }
+            if let Some(watcher) = self.online.get(watcher_uid) {
+                by_shard
+                    .entry(watcher.shard_id)
+                    .or_default()
+                    .push(*watcher_uid);
+            }
+        }
+        by_shard
+    }
+
+    // The online player's buddy list, paired with whether each buddy shows as online to them
+    pub fn get_buddy_states(&self, pc_uid: i64) -> Option<Vec<(i64, bool)>> {
+        let entry = self.online.get(&pc_uid)?;
+        Some(
+            entry
+                .buddies
+                .iter()
+                .map(|buddy_uid| (*buddy_uid, self.is_visible_to(*buddy_uid, pc_uid)))
+                .collect(),
+        )
+    }
+
+    fn remove(&mut self, pc_uid: i64) -> Option<PresenceEntry> {
+        let entry = self.online.remove(&pc_uid)?;
+        for buddy_uid in &entry.buddies {
+            if let Some(watchers) = self.watchers.get_mut(buddy_uid) {
+                watchers.remove(&pc_uid);
+                if watchers.is_empty() {
+                    self.watchers.remove(buddy_uid);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presence_index_login_logout() {
+        let mut index = BuddyPresenceIndex::default();
+        assert!(index.set_online(1, 1, &[2, 3], &[]));
+        // player 1 watches their buddies, but no one is watching player 1 yet
+        assert_eq!(index.get_watchers(2)[&1], vec![1]);
+        assert!(index.get_watchers(1).is_empty());
+
+        // 2 and 3 have player 1 on their lists, so they hear about them
+        assert!(index.set_online(2, 1, &[1], &[]));
+        assert!(index.set_online(3, 2, &[1], &[]));
+        let watchers = index.get_watchers(1);
+        assert_eq!(watchers.len(), 2);
+        assert_eq!(watchers[&1], vec![2]);
+        assert_eq!(watchers[&2], vec![3]);
+        assert_eq!(index.get_watchers(2)[&1], vec![1]);
+
+        let mut states = index.get_buddy_states(1).unwrap();
+        states.sort();
+        assert_eq!(states, vec![(2, true), (3, true)]);
+
+        // player 3 leaves
+        assert!(index.set_offline(3));
+        assert!(!index.set_offline(3));
+        assert!(!index.is_online(3));
+        assert_eq!(index.get_watchers(1).len(), 1);
+        let mut states = index.get_buddy_states(1).unwrap();
+        states.sort();
+        assert_eq!(states, vec![(2, true), (3, false)]);
+        assert!(index.get_buddy_states(3).is_none());
+
+        // syncing a changed list isn't coming online again
+        assert!(!index.set_online(1, 1, &[2], &[]));
+        assert!(index.get_watchers(3).is_empty());
+
+        // everyone on a shard that goes away is offline
+        index.clear_shard(1);
+        assert!(!index.is_online(1) && !index.is_online(2));
+        assert!(index.watchers.is_empty());
+    }
+
+    #[test]
+    fn test_presence_index_blocked() {
+        let mut index = BuddyPresenceIndex::default();
+        // player 1 blocked player 2
+        index.set_online(1, 1, &[2, 3], &[2]);
+        index.set_online(2, 1, &[1], &[]);
+        index.set_online(3, 1, &[1], &[]);
+
+        assert_eq!(index.get_watchers(1)[&1], vec![3]);
+        assert!(!index.is_visible_to(1, 2));
+        assert!(index.is_visible_to(1, 3));
+        assert_eq!(index.get_buddy_states(2).unwrap(), vec![(1, false)]);
+        assert_eq!(index.get_buddy_states(3).unwrap(), vec![(1, true)]);
+        // blocking only goes one way
+        assert!(index.get_watchers(2)[&1].contains(&1));
+    }
+}
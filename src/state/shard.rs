@@ -1,58 +1,108 @@
-use std::{collections::HashMap, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime},
+};
 
-use rand::thread_rng;
 use uuid::Uuid;
 
 use crate::{
     ai::AI,
-    chunk::{EntityMap, InstanceID, TickMode},
+    chunk::{EntityMap, InstanceID, TickMode, TickSlicer},
     config::config_get,
-    database::DbResult,
+    database::{db_run_async, DbResult},
     defines::*,
-    entity::{Combatant, Egg, Entity, EntityID, Group, Player, Slider, NPC},
+    entity::{Combatant, Egg, EggSummon, Entity, EntityID, Group, Player, Slider, NPC},
     enums::ItemType,
     error::{log, log_if_failed, panic_log, FFError, FFResult, Severity},
+    group_warp::{self, PendingGroupWarp},
     helpers,
     item::Item,
+    journal::{journal_commit, journal_mark},
     net::{
         packet::{PacketID::*, *},
-        ClientMap, LoginData,
+        ClientMap, HandlerStats, LoginData,
     },
+    report::ReportLimiter,
+    rng::GameRng,
+    skills::{self, PendingHits},
     tabledata::tdata_get,
-    trade::TradeContext,
+    trade::{validate_trade_parties, TradeContext},
+    webhook::{webhook_queue, WebhookEventKind},
+    Position,
+};
+
+use super::{
+    Account, FFReceiver, PendingOps, ReceiverCallback, TickStats, TrackedNPCStatus, WorldSnapshot,
 };
 
-use super::FFReceiver;
+// Limits on how many NPCs can be summoned and how many entities the shard will hold.
+// 0 means no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct SpawnCaps {
+    pub summons_per_channel: usize,
+    pub summons_per_map: usize,
+    pub entities: usize,
+}
+impl SpawnCaps {
+    pub fn from_config() -> Self {
+        let config = &config_get().shard;
+        Self {
+            summons_per_channel: config.summon_cap_per_channel.get(),
+            summons_per_map: config.summon_cap_per_map.get(),
+            entities: config.entity_hard_cap.get(),
+        }
+    }
+}
 
 pub struct ShardServerState {
     pub shard_id: i32,
     pub login_server_conn_id: Option<Uuid>,
-    pub login_data: HashMap<i64, LoginData>,
-    pub save_rx: Option<FFReceiver<DbResult>>,
+    // set once the channels are populated, which needs tabledata loaded
+    world_loaded: bool,
+    // login info from the login server for players that haven't connected yet, by serial key,
+    // along with when it arrived
+    login_data: HashMap<i64, (LoginData, SystemTime)>,
     pub entity_map: EntityMap,
     pub buyback_lists: HashMap<i32, Vec<Item>>,
     pub ongoing_trades: HashMap<Uuid, TradeContext>,
     pub groups: HashMap<Uuid, Group>,
+    // group warps waiting on members to agree to come along, by group ID
+    pub pending_group_warps: HashMap<Uuid, PendingGroupWarp>,
+    // instances marked PvP by a GM, on top of the configured zones
+    pub pvp_instances: HashSet<InstanceID>,
+    // event whose crates mobs also drop, set by a GM
+    pub active_event_id: Option<i32>,
+    pub pending_hits: PendingHits,
+    pub report_limiter: ReportLimiter,
+    pending_ops: PendingOps<ShardServerState>,
+    tick_slicer: TickSlicer,
+    // set while there are more tickable entities than the hard cap
+    tick_brake_engaged: bool,
+    pub handler_stats: HandlerStats,
+    pub tick_stats: TickStats,
+    pub rng: GameRng,
 }
 
 impl ShardServerState {
     pub fn new(shard_id: i32) -> Self {
-        let mut state = Self {
-            login_server_conn_id: None,
-            shard_id,
-            login_data: HashMap::new(),
-            save_rx: None,
-            entity_map: EntityMap::default(),
-            buyback_lists: HashMap::new(),
-            ongoing_trades: HashMap::new(),
-            groups: HashMap::new(),
+        let mut state = Self::new_empty(shard_id);
+        state.rng = match config_get().shard.rng_seed.get() {
+            0 => GameRng::from_entropy(),
+            seed => GameRng::new(seed),
         };
+        log(
+            Severity::Info,
+            &format!("Gameplay RNG seed: {}", state.rng.get_seed()),
+        );
         let num_channels = config_get().shard.num_channels.get();
         if num_channels == 0 || num_channels > MAX_NUM_CHANNELS as u8 {
             panic_log("Invalid number of channels");
         }
         for channel_num in 1..=num_channels {
-            for mut npc in tdata_get().make_all_npcs(&mut state.entity_map, channel_num) {
+            let template = config_get().shard.get_channel_template(channel_num);
+            for mut npc in
+                tdata_get().make_all_npcs(&mut state.entity_map, channel_num, template.as_ref())
+            {
                 if let Some(path) = tdata_get().get_npc_path(npc.ty) {
                     npc.path = Some(path);
                 }
@@ -128,9 +178,49 @@ impl ShardServerState {
                 &format!("Spawned {} sliders", sliders_spawned),
             );
         }
+        state.world_loaded = true;
         state
     }
 
+    fn new_empty(shard_id: i32) -> Self {
+        Self {
+            login_server_conn_id: None,
+            world_loaded: false,
+            shard_id,
+            login_data: HashMap::new(),
+            entity_map: EntityMap::default(),
+            buyback_lists: HashMap::new(),
+            ongoing_trades: HashMap::new(),
+            groups: HashMap::new(),
+            pending_group_warps: HashMap::new(),
+            pvp_instances: HashSet::new(),
+            active_event_id: None,
+            pending_hits: PendingHits::default(),
+            report_limiter: ReportLimiter::default(),
+            pending_ops: PendingOps::default(),
+            tick_slicer: TickSlicer::default(),
+            tick_brake_engaged: false,
+            handler_stats: HandlerStats::default(),
+            tick_stats: TickStats::default(),
+            rng: GameRng::default(),
+        }
+    }
+
+    // Skips spawning NPCs, eggs, and sliders, for tests
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_mock(shard_id: i32) -> Self {
+        Self {
+            world_loaded: true,
+            ..Self::new_empty(shard_id)
+        }
+    }
+
+    // New players are only let in once the world is loaded and there's a login server
+    // to validate their handoff against. Players already here stay if the link drops.
+    pub fn is_ready(&self) -> bool {
+        self.world_loaded && self.login_server_conn_id.is_some()
+    }
+
     pub fn get_npc(&self, npc_id: i32) -> FFResult<&NPC> {
         let id = EntityID::NPC(npc_id);
         self.entity_map.get_entity(id).ok_or(FFError::build(
@@ -187,6 +277,131 @@ impl ShardServerState {
         ))
     }
 
+    // Spawns a summoned egg that despawns once its lifetime is up. Returns the egg's ID.
+    pub fn summon_egg(
+        &mut self,
+        summon: &EggSummon,
+        pos: Position,
+        clients: &mut ClientMap,
+    ) -> i32 {
+        let entity_map = &mut self.entity_map;
+        let egg_id = entity_map.gen_next_egg_id();
+        let mut egg = Egg::new(egg_id, summon.ty, pos, summon.instance_id, true);
+        egg.set_despawn_time(SystemTime::now() + summon.lifetime);
+        if let Some(reward) = summon.reward {
+            egg.set_reward(reward);
+        }
+        let chunk_coords = egg.get_chunk_coords();
+        let eid = entity_map.track(Box::new(egg), TickMode::Always);
+        entity_map.update(eid, Some(chunk_coords), Some(clients));
+        egg_id
+    }
+
+    // Fails if summoning `count` more NPCs into `instance_id` would go over `caps`.
+    // Only summoned NPCs count toward the summon caps, but everything counts toward the hard cap.
+    pub fn check_npc_summon(
+        &self,
+        instance_id: InstanceID,
+        count: usize,
+        caps: SpawnCaps,
+    ) -> FFResult<()> {
+        let num_entities = self.entity_map.count();
+        if caps.entities > 0 && num_entities + count > caps.entities {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Can't summon {} NPCs: the shard has {} entities and the hard cap is {}",
+                    count, num_entities, caps.entities
+                ),
+            ));
+        }
+
+        let (mut in_channel, mut in_map) = (0, 0);
+        for npc_id in self.entity_map.get_npc_ids() {
+            let npc = self.get_npc(npc_id).unwrap();
+            if !npc.summoned || npc.instance_id.channel_num != instance_id.channel_num {
+                continue;
+            }
+            in_channel += 1;
+            if npc.instance_id == instance_id {
+                in_map += 1;
+            }
+        }
+        if caps.summons_per_channel > 0 && in_channel + count > caps.summons_per_channel {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Can't summon {} NPCs: channel {} has {} summoned and the cap is {}",
+                    count, instance_id.channel_num, in_channel, caps.summons_per_channel
+                ),
+            ));
+        }
+        if caps.summons_per_map > 0 && in_map + count > caps.summons_per_map {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!(
+                    "Can't summon {} NPCs: {} has {} summoned and the cap is {}",
+                    count, instance_id, in_map, caps.summons_per_map
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // Spawns an NPC that isn't from tabledata, within the configured caps. Returns the NPC's ID.
+    pub fn summon_npc(&mut self, mut npc: NPC, clients: &mut ClientMap) -> FFResult<i32> {
+        self.check_npc_summon(npc.instance_id, 1, SpawnCaps::from_config())?;
+        npc.summoned = true;
+        let (ai, tick_mode) = AI::make_for_npc(&npc, true);
+        npc.ai = ai;
+        let npc_id = npc.id;
+        let chunk_coords = npc.get_chunk_coords();
+        let eid = self.entity_map.track(Box::new(npc), tick_mode);
+        self.entity_map
+            .update(eid, Some(chunk_coords), Some(clients));
+        Ok(npc_id)
+    }
+
+    // Despawns a summoned NPC. Tabledata NPCs are refused.
+    pub fn unsummon_npc(&mut self, npc_id: i32, clients: &mut ClientMap) -> FFResult<()> {
+        if !self.get_npc(npc_id)?.summoned {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("NPC {} wasn't summoned", npc_id),
+            ));
+        }
+        let eid = EntityID::NPC(npc_id);
+        self.entity_map.update(eid, None, Some(clients));
+        let mut npc = self.entity_map.untrack(eid);
+        npc.cleanup(clients, self);
+        Ok(())
+    }
+
+    pub fn get_summoned_npc_ids(&self, instance_id: InstanceID) -> Vec<i32> {
+        let mut npc_ids = self
+            .entity_map
+            .find_npcs(|npc| npc.summoned && npc.instance_id == instance_id);
+        npc_ids.sort();
+        npc_ids
+    }
+
+    // Summons eggs at random spots within `radius` of `center`, e.g. for an egg hunt
+    pub fn scatter_eggs(
+        &mut self,
+        summon: &EggSummon,
+        center: Position,
+        radius: u32,
+        count: usize,
+        clients: &mut ClientMap,
+    ) -> Vec<i32> {
+        (0..count)
+            .map(|_| {
+                let pos = center.get_random_around(&mut self.rng, radius, radius, 0);
+                self.summon_egg(summon, pos, clients)
+            })
+            .collect()
+    }
+
     pub fn get_egg_mut(&mut self, egg_id: i32) -> FFResult<&mut Egg> {
         let id = EntityID::Egg(egg_id);
         self.entity_map.get_entity_mut(id).ok_or(FFError::build(
@@ -222,6 +437,47 @@ impl ShardServerState {
         ))
     }
 
+    pub fn is_pvp_zone(&self, instance_id: InstanceID, pos: Position) -> bool {
+        self.pvp_instances.contains(&instance_id)
+            || config_get()
+                .shard
+                .pvp_zones
+                .get()
+                .iter()
+                .any(|zone| zone.contains(instance_id.map_num, pos))
+    }
+
+    pub fn validate_pvp(&self, attacker_pc_id: i32, target_pc_id: i32) -> FFResult<()> {
+        let attacker = self.get_player(attacker_pc_id)?;
+        let target = self.get_player(target_pc_id)?;
+        let deny = |reason: &str| {
+            Err(FFError::build(
+                Severity::Warning,
+                format!("{} can't attack {}: {}", attacker, target, reason),
+            ))
+        };
+
+        if attacker_pc_id == target_pc_id {
+            return deny("same player");
+        }
+        // hitting a corpse again mustn't count as another defeat
+        if target.is_dead() {
+            return deny("already defeated");
+        }
+        if attacker.instance_id != target.instance_id {
+            return deny("in another instance");
+        }
+        if !self.is_pvp_zone(attacker.instance_id, attacker.get_position())
+            || !self.is_pvp_zone(target.instance_id, target.get_position())
+        {
+            return deny("not in a PvP zone");
+        }
+        if attacker.group_id.is_some() && attacker.group_id == target.group_id {
+            return deny("in the same group");
+        }
+        Ok(())
+    }
+
     pub fn check_for_expired_vehicles(&mut self, time: SystemTime, clients: &mut ClientMap) {
         log(Severity::Debug, "Checking for expired vehicles");
         let pc_ids: Vec<i32> = self.entity_map.get_player_ids().collect();
@@ -234,12 +490,9 @@ impl ShardServerState {
                     if time > expiry_time {
                         vehicle_slot.take();
 
-                        // dismount
+                        // dismount after the loop
                         let client = player.get_client(clients).unwrap();
                         if player.vehicle_speed.is_some() {
-                            player.vehicle_speed = None;
-                            let pkt = sP_FE2CL_PC_VEHICLE_OFF_SUCC { UNUSED: unused!() };
-                            log_if_failed(client.send_packet(P_FE2CL_PC_VEHICLE_OFF_SUCC, &pkt));
                             pc_ids_dismounted.push(pc_id);
                         }
 
@@ -258,8 +511,67 @@ impl ShardServerState {
         }
 
         for pc_id in pc_ids_dismounted {
-            let player = self.get_player(pc_id).unwrap();
-            helpers::broadcast_state(pc_id, player.get_state_bit_flag(), clients, self);
+            log_if_failed(helpers::force_dismount(pc_id, clients, self));
+        }
+    }
+
+    // Returns false if the serial key is already taken
+    pub fn add_login_data(&mut self, serial_key: i64, data: LoginData, time: SystemTime) -> bool {
+        if self.login_data.contains_key(&serial_key) {
+            return false;
+        }
+        self.login_data.insert(serial_key, (data, time));
+        true
+    }
+
+    pub fn take_login_data(&mut self, serial_key: i64) -> FFResult<LoginData> {
+        self.login_data
+            .remove(&serial_key)
+            .map(|(data, _)| data)
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("No login data for serial key {}", serial_key),
+            ))
+    }
+
+    // Removes login info for players that never showed up
+    pub fn take_expired_login_data(&mut self, time: SystemTime) -> Vec<(i64, LoginData)> {
+        let timeout = Duration::from_secs(config_get().shard.login_data_timeout.get());
+        let expired: Vec<i64> = self
+            .login_data
+            .iter()
+            .filter(|(_, (_, received))| {
+                time.duration_since(*received)
+                    .is_ok_and(|elapsed| elapsed >= timeout)
+            })
+            .map(|(serial_key, _)| *serial_key)
+            .collect();
+        let mut expired: Vec<(i64, LoginData)> = expired
+            .into_iter()
+            .map(|serial_key| (serial_key, self.login_data.remove(&serial_key).unwrap().0))
+            .collect();
+        expired.sort_by_key(|(serial_key, _)| *serial_key);
+        expired
+    }
+
+    pub fn tick_login_data(&mut self, time: SystemTime, clients: &mut ClientMap) {
+        for (serial_key, data) in self.take_expired_login_data(time) {
+            log(
+                Severity::Info,
+                &format!(
+                    "Player with UID {} never connected; dropping their login info",
+                    data.iPC_UID
+                ),
+            );
+            // let the login server know so it doesn't keep holding a spot for them
+            let pkt = sP_FE2LS_LOGIN_DATA_EXPIRED {
+                iEnterSerialKey: serial_key,
+                iAccountID: data.iAccountID,
+                iPC_UID: data.iPC_UID,
+            };
+            if let Some(login_server) = clients.get_login_server() {
+                log_if_failed(login_server.send_packet(P_FE2LS_LOGIN_DATA_EXPIRED, &pkt));
+            }
         }
     }
 
@@ -278,7 +590,44 @@ impl ShardServerState {
         }
     }
 
-    pub fn tick_groups(&mut self, clients: &mut ClientMap) {
+    pub fn tick_groups(&mut self, time: SystemTime, clients: &mut ClientMap) {
+        // escorts give up on players who leave them behind for too long
+        let escort_ids: Vec<i32> = self
+            .groups
+            .values()
+            .flat_map(|group| group.get_member_ids())
+            .filter_map(|eid| match eid {
+                EntityID::NPC(npc_id) => Some(*npc_id),
+                _ => None,
+            })
+            .collect();
+        for npc_id in escort_ids {
+            let Ok(npc) = self.get_npc(npc_id) else {
+                continue;
+            };
+            if npc.group_id.is_none() {
+                // group was disbanded earlier in this loop
+                continue;
+            }
+
+            let player_pos = match npc.loose_follow {
+                Some(EntityID::Player(pc_id)) => self
+                    .get_player(pc_id)
+                    .ok()
+                    .filter(|player| player.instance_id == npc.instance_id)
+                    .map(|player| player.get_position()),
+                _ => None,
+            };
+            let npc = self.get_npc_mut(npc_id).unwrap();
+            if npc.check_escort_leash(player_pos, time) {
+                log(
+                    Severity::Debug,
+                    &format!("{} was left behind by its group", npc),
+                );
+                log_if_failed(helpers::kick_npc_from_group(npc_id, self, clients));
+            }
+        }
+
         for group in self.groups.values() {
             let (pc_group_data, npc_group_data) = group.get_member_data(self);
             let pkt = sP_FE2CL_PC_GROUP_MEMBER_INFO {
@@ -302,9 +651,121 @@ impl ShardServerState {
         }
     }
 
+    pub fn tick_group_warps(&mut self, time: SystemTime, clients: &mut ClientMap) {
+        let settled: Vec<Uuid> = self
+            .pending_group_warps
+            .iter()
+            .filter(|(_, warp)| warp.is_settled(time))
+            .map(|(group_id, _)| *group_id)
+            .collect();
+        for group_id in settled {
+            let warp = self.pending_group_warps.remove(&group_id).unwrap();
+            group_warp::finish_group_warp(warp, clients, self);
+        }
+    }
+
+    // Tells players their clean exit was called off.
+    // Returns the players whose countdown is up, for the caller to send off.
+    pub fn tick_exits(&mut self, time: SystemTime, clients: &mut ClientMap) -> Vec<i32> {
+        let pc_ids: Vec<i32> = self.entity_map.get_player_ids().collect();
+        let mut due = Vec::new();
+        for pc_id in pc_ids {
+            let player = self.get_player_mut(pc_id).unwrap();
+            if player.take_exit_cancelled() {
+                helpers::send_exit_cancelled(player, clients);
+            }
+            if player
+                .get_exit_deadline()
+                .is_some_and(|deadline| deadline <= time)
+            {
+                due.push(pc_id);
+            }
+        }
+        due
+    }
+
+    pub fn tick_offers(&mut self, time: SystemTime, clients: &mut ClientMap) {
+        let pc_ids: Vec<i32> = self.entity_map.get_player_ids().collect();
+        for pc_id in pc_ids {
+            let player = self.get_player_mut(pc_id).unwrap();
+            let expired = player.take_expired_offers(time);
+            helpers::send_cancelled_offers(pc_id, &expired, clients, self);
+        }
+    }
+
+    // Cancels trades whose players have wandered apart or become unable to trade
+    pub fn tick_trades(&mut self, clients: &mut ClientMap) {
+        let range = config_get().shard.trade_range.get();
+        let mut broken_trades = Vec::new();
+        for (trade_id, trade) in &self.ongoing_trades {
+            let res = self.get_player(trade.get_id_from()).and_then(|from| {
+                let to = self.get_player(trade.get_id_to())?;
+                validate_trade_parties(from, to, range)
+            });
+            if let Err(e) = res {
+                log(
+                    Severity::Info,
+                    &format!("Cancelling trade: {}", e.get_msg()),
+                );
+                broken_trades.push(*trade_id);
+            }
+        }
+
+        for trade_id in broken_trades {
+            helpers::cancel_trade(trade_id, clients, self);
+        }
+    }
+
+    pub fn tick_spectators(&mut self, clients: &mut ClientMap) {
+        let max_distance = config_get().shard.spectate_distance.get();
+        let pc_ids: Vec<i32> = self.entity_map.get_player_ids().collect();
+        for pc_id in pc_ids {
+            let player = self.get_player(pc_id).unwrap();
+            let Some(target_pc_id) = player.get_spectate_target() else {
+                continue;
+            };
+
+            // spectating ends if the target leaves or either side changes channels
+            let channel_num = player.instance_id.channel_num;
+            let target_gone = !self
+                .get_player(target_pc_id)
+                .is_ok_and(|target| target.instance_id.channel_num == channel_num);
+            let res = if target_gone {
+                helpers::stop_spectating(pc_id, clients, self).map(|_| {
+                    let player = self.get_player(pc_id).unwrap();
+                    if let Some(client) = player.get_client(clients) {
+                        log_if_failed(helpers::send_system_message(
+                            client,
+                            "Spectate target is gone; spectating ended",
+                        ));
+                    }
+                })
+            } else {
+                helpers::follow_spectate_target(pc_id, target_pc_id, max_distance, clients, self)
+            };
+            log_if_failed(res);
+        }
+    }
+
+    // Lands projectile hits that have arrived. Anything that got away in the meantime is missed.
+    pub fn tick_pending_hits(&mut self, time: SystemTime) {
+        for hit in self.pending_hits.take_landed(time) {
+            if let Err(e) = skills::land_hit(&hit, self) {
+                log(
+                    Severity::Debug,
+                    &format!("Projectile missed: {}", e.get_msg()),
+                );
+            }
+        }
+    }
+
     pub fn tick_entities(&mut self, time: SystemTime, clients: &mut ClientMap) {
-        let mut rng = thread_rng();
-        let eids: Vec<EntityID> = self.entity_map.get_tickable_ids().collect();
+        let start = Instant::now();
+        // lent out to the entities, which get the state too
+        let mut rng = std::mem::take(&mut self.rng);
+        let mut eids: Vec<EntityID> = self.entity_map.get_tickable_ids().collect();
+        self.apply_tick_brake(&mut eids, config_get().shard.entity_hard_cap.get());
+        let mut deferrable = Vec::new();
         for eid in eids {
             match eid {
                 // we copy the entity here so we can mutably borrow the state.
@@ -315,9 +776,15 @@ impl ShardServerState {
                     *self.get_player_mut(pc_id).unwrap() = player;
                 }
                 EntityID::NPC(npc_id) => {
-                    let mut npc = self.get_npc_mut(npc_id).unwrap().clone();
-                    npc.tick(&time, clients, self, &mut rng);
-                    *self.get_npc_mut(npc_id).unwrap() = npc;
+                    // NPCs that only tick when loaded can be put off if we're over budget
+                    if matches!(
+                        self.entity_map.get_tick_mode(eid),
+                        Some(TickMode::WhenLoaded)
+                    ) {
+                        deferrable.push(npc_id);
+                    } else {
+                        self.tick_npc(npc_id, &time, clients, &mut rng);
+                    }
                 }
                 EntityID::Slider(slider_id) => {
                     let mut slider = self.get_slider_mut(slider_id).unwrap().clone();
@@ -331,27 +798,826 @@ impl ShardServerState {
                 }
             }
         }
+
+        let budget = match config_get().shard.tick_budget.get() {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        let mut tick_slicer = std::mem::take(&mut self.tick_slicer);
+        tick_slicer.run(
+            deferrable,
+            budget,
+            || start.elapsed(),
+            |npc_id| {
+                self.tick_npc(npc_id, &time, clients, &mut rng);
+            },
+        );
+        self.tick_slicer = tick_slicer;
+        self.rng = rng;
+        self.tick_stats.record(start.elapsed());
+    }
+
+    // Past the hard cap, only that many entities tick, players first,
+    // so a flood of NPCs slows the world down instead of freezing it
+    fn apply_tick_brake(&mut self, eids: &mut Vec<EntityID>, hard_cap: usize) {
+        let over = hard_cap > 0 && eids.len() > hard_cap;
+        if over != self.tick_brake_engaged {
+            self.tick_brake_engaged = over;
+            if over {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "{} entities to tick, over the hard cap of {}; skipping the rest",
+                        eids.len(),
+                        hard_cap
+                    ),
+                );
+            } else {
+                log(Severity::Info, "Back under the entity hard cap");
+            }
+        }
+        if over {
+            eids.sort_by_key(|eid| !matches!(eid, EntityID::Player(_)));
+            eids.truncate(hard_cap);
+        }
     }
 
-    pub fn check_receivers(&mut self) -> bool {
-        if let Some(receiver) = &self.save_rx {
-            match receiver.try_recv() {
-                None => (), // in progress
-                Some(Ok(res)) => {
-                    let elapsed = res.completed.elapsed().unwrap_or_default();
+    // Channel populations, tracked NPCs, and tick health for the monitor.
+    // Lists at most MAX_TRACKED_NPCS of the NPCs of `tracked_npc_types`.
+    pub fn get_world_snapshot(&mut self, tracked_npc_types: &[i32]) -> WorldSnapshot {
+        let num_channels = config_get().shard.num_channels.get() as usize;
+        let mut channel_pops = vec![0; num_channels];
+        for pc_id in self.entity_map.get_player_ids() {
+            let player = self.get_player(pc_id).unwrap();
+            let channel_idx = (player.instance_id.channel_num as usize).wrapping_sub(1);
+            if let Some(pop) = channel_pops.get_mut(channel_idx) {
+                *pop += 1;
+            }
+        }
+
+        let mut tracked_npcs: Vec<TrackedNPCStatus> = self
+            .entity_map
+            .get_npc_ids()
+            .filter_map(|npc_id| {
+                let npc = self.get_npc(npc_id).unwrap();
+                if !tracked_npc_types.contains(&npc.ty) {
+                    return None;
+                }
+                Some(TrackedNPCStatus {
+                    npc_id,
+                    npc_type: npc.ty,
+                    channel_num: npc.instance_id.channel_num,
+                    alive: !npc.is_dead(),
+                })
+            })
+            .collect();
+        // so the same ones get left out every time
+        tracked_npcs.sort_by_key(|status| status.npc_id);
+        let tracked_npcs_truncated = tracked_npcs.len() > MAX_TRACKED_NPCS;
+        tracked_npcs.truncate(MAX_TRACKED_NPCS);
+
+        WorldSnapshot {
+            channel_pops,
+            tracked_npcs,
+            tracked_npcs_truncated,
+            avg_tick: self.tick_stats.take_average(),
+        }
+    }
+
+    fn tick_npc(
+        &mut self,
+        npc_id: i32,
+        time: &SystemTime,
+        clients: &mut ClientMap,
+        rng: &mut GameRng,
+    ) {
+        let mut npc = self.get_npc_mut(npc_id).unwrap().clone();
+        npc.tick(time, clients, self, rng);
+        *self.get_npc_mut(npc_id).unwrap() = npc;
+    }
+
+    pub fn register_receiver(
+        &mut self,
+        label: &str,
+        rx: FFReceiver<DbResult>,
+        timeout: Duration,
+        on_complete: ReceiverCallback<Self>,
+    ) {
+        self.pending_ops.register(label, rx, timeout, on_complete);
+    }
+
+    // Runs the callbacks of any finished async ops.
+    // Returns whether any are still pending.
+    pub fn check_receivers(&mut self, clients: &mut ClientMap) -> bool {
+        for (on_complete, result) in self.pending_ops.poll(SystemTime::now()) {
+            on_complete(self, clients, result);
+        }
+        !self.pending_ops.is_empty()
+    }
+
+    // Gives up on every pending async op, returning the labels of the abandoned ones
+    pub fn abandon_receivers(&mut self) -> Vec<String> {
+        self.pending_ops.drain()
+    }
+
+    // Saves the given players right away instead of waiting for the next autosave.
+    // Anything that moves items between players, or destroys items to make new ones, calls this.
+    // Database ops run one at a time in the order they're queued, so this save always lands
+    // after any older autosave snapshot of the same players and can't be undone by it.
+    pub fn save_players_now(&mut self, pc_ids: &[i32], label: &str) {
+        const IMMEDIATE_SAVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+        let players: Vec<Player> = pc_ids
+            .iter()
+            .filter_map(|pc_id| self.get_player(*pc_id).ok().cloned())
+            .collect();
+        if players.is_empty() {
+            return;
+        }
+
+        let pc_uids: Vec<i64> = players.iter().map(|player| player.get_uid()).collect();
+        let journal_mark = journal_mark();
+        let rx = db_run_async(move |db| {
+            let player_refs: Vec<&Player> = players.iter().collect();
+            db.save_players(&player_refs)?;
+            journal_commit(journal_mark, Some(&pc_uids))
+        });
+        self.register_receiver(
+            label,
+            rx,
+            IMMEDIATE_SAVE_TIMEOUT,
+            Self::on_immediate_save_complete,
+        );
+    }
+
+    fn on_immediate_save_complete(
+        _state: &mut ShardServerState,
+        _clients: &mut ClientMap,
+        result: FFResult<DbResult>,
+    ) {
+        if let Err(e) = result.and_then(|res| res.get::<()>()) {
+            Self::report_save_failure(e);
+        }
+    }
+
+    pub fn on_save_complete(
+        _state: &mut ShardServerState,
+        _clients: &mut ClientMap,
+        result: FFResult<DbResult>,
+    ) {
+        match result.and_then(|res| {
+            let elapsed = res.completed.elapsed().unwrap_or_default();
+            res.get::<()>().map(|_| elapsed)
+        }) {
+            Ok(elapsed) => log(
+                Severity::Info,
+                &format!("Save complete ({:.2}s)", elapsed.as_secs_f32()),
+            ),
+            Err(e) => Self::report_save_failure(e),
+        }
+    }
+
+    pub fn on_ban_check_complete(
+        _state: &mut ShardServerState,
+        clients: &mut ClientMap,
+        result: FFResult<DbResult>,
+    ) {
+        match result.and_then(|res| res.get::<Vec<Account>>()) {
+            Ok(banned_accounts) => {
+                for account in banned_accounts {
+                    let kicked = helpers::kick_banned_account(
+                        account.id,
+                        account.banned_until,
+                        &account.ban_reason,
+                        clients,
+                    );
+                    if kicked > 0 {
+                        log(
+                            Severity::Info,
+                            &format!(
+                                "Kicked {} player(s) on banned account {}",
+                                kicked, account.id
+                            ),
+                        );
+                    }
+                }
+            }
+            Err(e) => log(
+                Severity::Warning,
+                &format!("Ban check failed: {}", e.get_msg()),
+            ),
+        }
+    }
+
+    // Saves every player one last time before the server goes down, waiting up to `timeout` for
+    // those saves and any other pending ops to land. Players whose save isn't confirmed by then
+    // are saved again one at a time through `save_sync`.
+    // The save functions are passed in so this doesn't need a live database.
+    pub fn save_for_shutdown(
+        &mut self,
+        clients: &mut ClientMap,
+        timeout: Duration,
+        save_async: impl Fn(Player) -> FFReceiver<DbResult>,
+        save_sync: impl Fn(&Player) -> FFResult<()>,
+    ) -> ShutdownSaveReport {
+        const POLL_INTERVAL: Duration = Duration::from_millis(100);
+        const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+        let players: Vec<Player> = self
+            .entity_map
+            .get_player_ids()
+            .map(|pc_id| self.get_player(pc_id).unwrap().clone())
+            .collect();
+        let mut report = ShutdownSaveReport {
+            total: players.len(),
+            ..Default::default()
+        };
+        log(
+            Severity::Info,
+            &format!("Saving {} player(s) before shutdown...", report.total),
+        );
+
+        let mut unconfirmed: Vec<(Player, FFReceiver<DbResult>)> = players
+            .into_iter()
+            .map(|player| {
+                let rx = save_async(player.clone());
+                (player, rx)
+            })
+            .collect();
+        let mut failed = Vec::new();
+        let start = Instant::now();
+        let mut last_progress = start;
+        loop {
+            unconfirmed.retain(|(player, rx)| {
+                let Some(result) = rx.try_recv() else {
+                    return true;
+                };
+                match result.and_then(|res| res.get::<()>()) {
+                    Ok(()) => report.saved += 1,
+                    Err(e) => {
+                        log(
+                            Severity::Warning,
+                            &format!(
+                                "Shutdown save for player {} failed: {}",
+                                player.get_uid(),
+                                e.get_msg()
+                            ),
+                        );
+                        failed.push(player.clone());
+                    }
+                }
+                false
+            });
+
+            let ops_pending = self.check_receivers(clients);
+            if unconfirmed.is_empty() && !ops_pending {
+                break;
+            }
+
+            if start.elapsed() >= timeout {
+                let abandoned = self.abandon_receivers();
+                if !abandoned.is_empty() {
                     log(
-                        Severity::Info,
-                        &format!("Save complete ({:.2}s)", elapsed.as_secs_f32()),
+                        Severity::Warning,
+                        &format!("Abandoned unfinished operations: {}", abandoned.join(", ")),
                     );
-                    self.save_rx = None;
                 }
-                Some(Err(e)) => {
-                    log(Severity::Warning, &format!("Save failed: {}", e.get_msg()));
-                    self.save_rx = None;
+                break;
+            }
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                log(
+                    Severity::Info,
+                    &format!(
+                        "{}/{} player(s) saved, {} database operation(s) pending",
+                        report.saved,
+                        report.total,
+                        unconfirmed.len() + self.pending_ops.get_labels().count()
+                    ),
+                );
+                last_progress = Instant::now();
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        failed.extend(unconfirmed.into_iter().map(|(player, _)| player));
+        if !failed.is_empty() {
+            log(
+                Severity::Warning,
+                &format!("Saving {} player(s) one at a time...", failed.len()),
+            );
+        }
+        for player in failed {
+            match save_sync(&player) {
+                Ok(()) => report.saved_sync += 1,
+                Err(e) => {
+                    log(
+                        Severity::Warning,
+                        &format!("Couldn't save player {}: {}", player.get_uid(), e.get_msg()),
+                    );
+                    report.unsaved.push(player.get_uid());
                 }
             }
         }
 
-        self.save_rx.is_some()
+        if report.all_saved() {
+            log(
+                Severity::Info,
+                &format!(
+                    "All {} player(s) saved ({} retried)",
+                    report.total, report.saved_sync
+                ),
+            );
+        } else {
+            let msg = format!(
+                "{} of {} player(s) weren't saved: {:?}",
+                report.unsaved.len(),
+                report.total,
+                report.unsaved
+            );
+            log(Severity::Warning, &msg);
+            webhook_queue(WebhookEventKind::AutosaveFailed, &msg);
+        }
+        report
+    }
+
+    fn report_save_failure(e: FFError) {
+        let msg = format!("Save failed: {}", e.get_msg());
+        log(Severity::Warning, &msg);
+        webhook_queue(WebhookEventKind::AutosaveFailed, &msg);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ShutdownSaveReport {
+    pub total: usize,
+    pub saved: usize,
+    // saved through the one-at-a-time fallback
+    pub saved_sync: usize,
+    pub unsaved: Vec<i64>,
+}
+impl ShutdownSaveReport {
+    pub fn all_saved(&self) -> bool {
+        self.unsaved.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, sync::mpsc};
+
+    use crate::{
+        database::{Database, MockDatabase},
+        test_utils::{FakeClients, NpcBuilder, PlayerBuilder},
+    };
+
+    fn login_data(pc_uid: i64) -> LoginData {
+        LoginData {
+            iAccountID: 1,
+            iPC_UID: pc_uid,
+            uiFEKey: 0,
+            uiSvrTime: 0,
+        }
+    }
+
+    #[test]
+    fn test_validate_pvp_dead_target() {
+        let mut state = ShardServerState::new_mock(1);
+        for (pc_id, hp) in [(1, 1000), (2, 0)] {
+            let player = PlayerBuilder::new(pc_id as i64).pc_id(pc_id).hp(hp).build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        let err = state.validate_pvp(1, 2).unwrap_err();
+        assert!(err.get_msg().contains("already defeated"));
+        assert!(!state
+            .validate_pvp(2, 1)
+            .unwrap_err()
+            .get_msg()
+            .contains("already defeated"));
+    }
+
+    #[test]
+    fn test_login_data_expiry() {
+        let mut state = ShardServerState::new_mock(1);
+        let timeout = Duration::from_secs(config_get().shard.login_data_timeout.get());
+        let now = SystemTime::now();
+        assert!(state.add_login_data(100, login_data(1), now));
+        assert!(state.add_login_data(200, login_data(2), now + Duration::from_secs(10)));
+        assert!(!state.add_login_data(100, login_data(3), now));
+
+        // nothing is stale yet
+        assert!(state.take_expired_login_data(now).is_empty());
+
+        // only the first entry has been waiting long enough
+        let expired = state.take_expired_login_data(now + timeout);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, 100);
+        assert_eq!(expired[0].1.iPC_UID, 1);
+
+        // a player connecting late is turned away instead of using stale data
+        assert!(state.take_login_data(100).is_err());
+        assert_eq!(state.take_login_data(200).unwrap().iPC_UID, 2);
+        assert!(state.take_login_data(200).is_err());
+        assert!(state.take_expired_login_data(now + timeout * 2).is_empty());
+    }
+
+    #[test]
+    fn test_ready_around_login_server_reconnect() {
+        let mut state = ShardServerState::new_empty(1);
+        state.login_server_conn_id = Some(Uuid::new_v4());
+        assert!(!state.is_ready());
+
+        let mut state = ShardServerState::new_mock(1);
+        assert!(!state.is_ready());
+        state.login_server_conn_id = Some(Uuid::new_v4());
+        assert!(state.is_ready());
+
+        // the link dropping closes the gate, but players already here stay
+        let player = PlayerBuilder::new(1).pc_id(1).build();
+        state.entity_map.track(Box::new(player), TickMode::Never);
+        state.login_server_conn_id = None;
+        assert!(!state.is_ready());
+        assert!(state.get_player(1).is_ok());
+
+        // and reconnecting, possibly to a different login server, opens it back up
+        state.login_server_conn_id = Some(Uuid::new_v4());
+        assert!(state.is_ready());
+    }
+
+    #[test]
+    fn test_world_snapshot() {
+        const BOSS_TYPE: i32 = 2467;
+
+        let mut state = ShardServerState::new_mock(1);
+        let num_channels = config_get().shard.num_channels.get() as usize;
+        for pc_id in 1..=3 {
+            let player = PlayerBuilder::new(pc_id as i64).pc_id(pc_id).build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        // a channel the shard doesn't have isn't counted anywhere
+        let stray = PlayerBuilder::new(4)
+            .pc_id(4)
+            .instance_id(InstanceID {
+                channel_num: num_channels as u8 + 1,
+                ..Default::default()
+            })
+            .build();
+        state.entity_map.track(Box::new(stray), TickMode::Never);
+
+        let boss = NpcBuilder::new(20, BOSS_TYPE).build();
+        let dead_boss = NpcBuilder::new(10, BOSS_TYPE).hp(0).build();
+        let mob = NpcBuilder::new(30, 1).build();
+        for npc in [boss, dead_boss, mob] {
+            state.entity_map.track(Box::new(npc), TickMode::Never);
+        }
+
+        state.tick_stats.record(Duration::from_millis(3));
+        let snapshot = state.get_world_snapshot(&[BOSS_TYPE]);
+        let mut expected_pops = vec![0; num_channels];
+        expected_pops[0] = 3;
+        assert_eq!(snapshot.channel_pops, expected_pops);
+        assert_eq!(
+            snapshot.tracked_npcs,
+            vec![
+                TrackedNPCStatus {
+                    npc_id: 10,
+                    npc_type: BOSS_TYPE,
+                    channel_num: 1,
+                    alive: false,
+                },
+                TrackedNPCStatus {
+                    npc_id: 20,
+                    npc_type: BOSS_TYPE,
+                    channel_num: 1,
+                    alive: true,
+                },
+            ]
+        );
+        assert!(!snapshot.tracked_npcs_truncated);
+        assert_eq!(snapshot.avg_tick, Duration::from_millis(3));
+
+        // too many to send; the lowest IDs make the cut
+        for npc_id in 100..(100 + MAX_TRACKED_NPCS as i32) {
+            let npc = NpcBuilder::new(npc_id, BOSS_TYPE).build();
+            state.entity_map.track(Box::new(npc), TickMode::Never);
+        }
+        let snapshot = state.get_world_snapshot(&[BOSS_TYPE]);
+        assert_eq!(snapshot.tracked_npcs.len(), MAX_TRACKED_NPCS);
+        assert!(snapshot.tracked_npcs_truncated);
+        assert_eq!(snapshot.tracked_npcs[0].npc_id, 10);
+        assert_eq!(snapshot.avg_tick, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_scattered_eggs_despawn() {
+        const EGG_COUNT: usize = 5;
+        const RADIUS: u32 = 500;
+
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let center = Position {
+            x: 1000,
+            y: 1000,
+            z: 50,
+        };
+        let player = PlayerBuilder::new(1)
+            .pc_id(1)
+            .client_id(key)
+            .position(center)
+            .build();
+        let instance_id = player.instance_id;
+        let chunk_pos = player.get_chunk_coords();
+        let id = state.entity_map.track(Box::new(player), TickMode::Never);
+        state.entity_map.update(id, Some(chunk_pos), None);
+
+        let summon = EggSummon {
+            ty: 1,
+            instance_id,
+            lifetime: Duration::from_secs(60),
+            reward: None,
+        };
+        let egg_ids = state.scatter_eggs(
+            &summon,
+            center,
+            RADIUS,
+            EGG_COUNT,
+            &mut fake_clients.get_map(key),
+        );
+        assert_eq!(egg_ids.len(), EGG_COUNT);
+        for egg_id in &egg_ids {
+            let egg = state.get_egg(*egg_id).unwrap();
+            let pos = egg.get_position();
+            assert!(egg.is_summoned());
+            assert!(pos.x.abs_diff(center.x) <= RADIUS && pos.y.abs_diff(center.y) <= RADIUS);
+            assert_eq!(pos.z, center.z);
+        }
+        let sent = fake_clients.take_sent(key);
+        assert_eq!(sent, vec![P_FE2CL_SHINY_ENTER; EGG_COUNT]);
+
+        // they disappear for everyone once their lifetime is up, and don't come back
+        let despawn_time = SystemTime::now() + summon.lifetime;
+        state.tick_entities(despawn_time, &mut fake_clients.get_map(key));
+        state.tick_garbage_collection(&mut fake_clients.get_map(key));
+        for egg_id in &egg_ids {
+            assert!(state.get_egg(*egg_id).is_err());
+        }
+        let sent = fake_clients.take_sent(key);
+        assert_eq!(sent, vec![P_FE2CL_SHINY_EXIT; EGG_COUNT]);
+    }
+
+    #[test]
+    fn test_summon_caps() {
+        const CAPS: SpawnCaps = SpawnCaps {
+            summons_per_channel: 5,
+            summons_per_map: 3,
+            entities: 10,
+        };
+
+        let mut state = ShardServerState::new_mock(1);
+        let map = InstanceID::default();
+        let other_map = InstanceID {
+            map_num: 1,
+            ..Default::default()
+        };
+        let other_channel = InstanceID {
+            channel_num: 2,
+            ..Default::default()
+        };
+        let mut next_npc_id = 1;
+        let mut spawn = |state: &mut ShardServerState, instance_id, summoned| {
+            let mut npc = NpcBuilder::new(next_npc_id, 1)
+                .instance_id(instance_id)
+                .build();
+            npc.summoned = summoned;
+            state.entity_map.track(Box::new(npc), TickMode::Never);
+            next_npc_id += 1;
+        };
+
+        // NPCs from tabledata don't count toward the summon caps
+        for _ in 0..4 {
+            spawn(&mut state, map, false);
+        }
+        assert!(state.check_npc_summon(map, 3, CAPS).is_ok());
+        assert!(state.check_npc_summon(map, 4, CAPS).is_err());
+
+        for _ in 0..3 {
+            spawn(&mut state, map, true);
+        }
+        assert!(state.check_npc_summon(map, 1, CAPS).is_err());
+        assert!(state.check_npc_summon(other_map, 2, CAPS).is_ok());
+        // but the channel only has room for two more
+        assert!(state.check_npc_summon(other_map, 3, CAPS).is_err());
+        assert!(state.check_npc_summon(other_channel, 3, CAPS).is_ok());
+        assert_eq!(state.get_summoned_npc_ids(map), vec![5, 6, 7]);
+        assert!(state.get_summoned_npc_ids(other_map).is_empty());
+
+        // they do count toward the hard cap
+        for _ in 0..2 {
+            spawn(&mut state, other_channel, false);
+        }
+        assert!(state.check_npc_summon(other_channel, 1, CAPS).is_ok());
+        assert!(state.check_npc_summon(other_channel, 2, CAPS).is_err());
+
+        let no_caps = SpawnCaps {
+            summons_per_channel: 0,
+            summons_per_map: 0,
+            entities: 0,
+        };
+        assert!(state.check_npc_summon(map, 1000, no_caps).is_ok());
+    }
+
+    #[test]
+    fn test_tick_brake() {
+        let mut state = ShardServerState::new_mock(1);
+        for npc_id in 1..=3 {
+            let npc = NpcBuilder::new(npc_id, 1).build();
+            state.entity_map.track(Box::new(npc), TickMode::Always);
+        }
+        for pc_id in 1..=2 {
+            let player = PlayerBuilder::new(pc_id as i64).pc_id(pc_id).build();
+            state.entity_map.track(Box::new(player), TickMode::Always);
+        }
+        let mut eids: Vec<EntityID> = state.entity_map.get_tickable_ids().collect();
+        state.apply_tick_brake(&mut eids, 5);
+        assert_eq!(eids.len(), 5);
+        assert!(!state.tick_brake_engaged);
+
+        // players make the cut before NPCs do
+        state.apply_tick_brake(&mut eids, 3);
+        assert!(state.tick_brake_engaged);
+        assert_eq!(eids.len(), 3);
+        assert!(eids[..2]
+            .iter()
+            .all(|eid| matches!(eid, EntityID::Player(_))));
+
+        state.apply_tick_brake(&mut eids, 0);
+        assert_eq!(eids.len(), 3);
+        assert!(!state.tick_brake_engaged);
+    }
+
+    fn shutdown_save_fixture(pc_count: i32) -> (ShardServerState, RefCell<MockDatabase>) {
+        let mut state = ShardServerState::new_mock(1);
+        let mut db = MockDatabase::default();
+        for pc_id in 1..=pc_count {
+            let account = db
+                .create_account(&format!("player{}", pc_id), &"hash".to_string())
+                .unwrap();
+            let player = PlayerBuilder::new(pc_id as i64).pc_id(pc_id).build();
+            db.init_player(account.id, &player).unwrap();
+            // picked up some taros since they were last saved
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .taros(1000)
+                .build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+        (state, RefCell::new(db))
+    }
+
+    fn saved_taros(db: &RefCell<MockDatabase>, pc_uid: i64) -> u32 {
+        let mut db = db.borrow_mut();
+        let account = db.find_account_from_player(pc_uid).unwrap();
+        db.load_player(account.id, pc_uid).unwrap().get_taros()
+    }
+
+    #[test]
+    fn test_shutdown_save() {
+        let (mut state, db) = shutdown_save_fixture(3);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(None);
+
+        let report = state.save_for_shutdown(
+            &mut fake_clients.get_map(key),
+            Duration::from_secs(5),
+            |player| {
+                let (tx, rx) = mpsc::channel();
+                let result = db.borrow_mut().save_player(&player);
+                tx.send(DbResult::new(result)).unwrap();
+                FFReceiver::new(SystemTime::now(), rx)
+            },
+            |_| panic!("nothing should need saving twice"),
+        );
+        assert_eq!(report.total, 3);
+        assert_eq!(report.saved, 3);
+        assert_eq!(report.saved_sync, 0);
+        assert!(report.all_saved());
+        for pc_uid in 1..=3 {
+            assert_eq!(saved_taros(&db, pc_uid), 1000);
+        }
+    }
+
+    #[test]
+    fn test_shutdown_save_fallback() {
+        const TIMEOUT: Duration = Duration::from_millis(300);
+
+        let (mut state, db) = shutdown_save_fixture(3);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(None);
+        // the async saves don't finish until well after the timeout
+        let delayed = RefCell::new(Vec::new());
+        let sync_saves = RefCell::new(0);
+        // one of the fallback saves fails too
+        db.borrow_mut().fail_next(1);
+
+        let start = Instant::now();
+        let report = state.save_for_shutdown(
+            &mut fake_clients.get_map(key),
+            TIMEOUT,
+            |_player| {
+                let (tx, rx) = mpsc::channel();
+                delayed.borrow_mut().push(tx);
+                FFReceiver::new(SystemTime::now(), rx)
+            },
+            |player| {
+                *sync_saves.borrow_mut() += 1;
+                db.borrow_mut().save_player(player)
+            },
+        );
+        assert!(start.elapsed() >= TIMEOUT);
+        assert_eq!(*sync_saves.borrow(), 3);
+        assert_eq!(report.total, 3);
+        assert_eq!(report.saved, 0);
+        assert_eq!(report.saved_sync, 2);
+        assert_eq!(report.unsaved.len(), 1);
+        assert!(!report.all_saved());
+
+        let unsaved = report.unsaved[0];
+        for pc_uid in 1..=3 {
+            let expected = if pc_uid == unsaved { 0 } else { 1000 };
+            assert_eq!(saved_taros(&db, pc_uid), expected);
+        }
+
+        // the late results have nowhere to go
+        for tx in delayed.into_inner() {
+            assert!(tx.send(DbResult::new(Ok(()))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tick_exits() {
+        let mut state = ShardServerState::new_mock(1);
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let other_key = fake_clients.add_game_client(Some(2));
+        for (pc_id, client_key) in [(1, key), (2, other_key)] {
+            let player = PlayerBuilder::new(pc_id as i64)
+                .pc_id(pc_id)
+                .client_id(client_key)
+                .build();
+            state.entity_map.track(Box::new(player), TickMode::Never);
+        }
+
+        let time = SystemTime::now();
+        let deadline = time + Duration::from_secs(60);
+        state.get_player_mut(1).unwrap().start_exit(deadline);
+        state.get_player_mut(2).unwrap().start_exit(deadline);
+        assert!(state
+            .tick_exits(time, &mut fake_clients.get_map(key))
+            .is_empty());
+
+        // chatting or stopping in place doesn't count as moving
+        for pkt_id in [
+            PacketID::P_CL2FE_REQ_SEND_FREECHAT_MESSAGE,
+            PacketID::P_CL2FE_REQ_PC_STOP,
+        ] {
+            assert!(!helpers::cancel_exit_on_packet(1, pkt_id, &mut state));
+        }
+        assert!(helpers::cancel_exit_on_packet(
+            1,
+            PacketID::P_CL2FE_REQ_PC_MOVE,
+            &mut state
+        ));
+        assert!(!helpers::cancel_exit_on_packet(
+            1,
+            PacketID::P_CL2FE_REQ_PC_ATTACK_NPCs,
+            &mut state
+        ));
+
+        // only the cancelled player hears about it, once
+        let due = state.tick_exits(deadline, &mut fake_clients.get_map(key));
+        assert_eq!(due, vec![2]);
+        assert_eq!(
+            fake_clients.take_sent(key),
+            vec![
+                PacketID::P_FE2CL_REP_PC_EXIT_FAIL,
+                PacketID::P_FE2CL_PC_MOTD_LOGIN
+            ]
+        );
+        assert!(fake_clients.take_sent(other_key).is_empty());
+        state.tick_exits(deadline, &mut fake_clients.get_map(key));
+        assert!(fake_clients.take_sent(key).is_empty());
+
+        // trading cancels as well
+        assert!(helpers::cancel_exit_on_packet(
+            2,
+            PacketID::P_CL2FE_REQ_PC_TRADE_OFFER,
+            &mut state
+        ));
+        assert!(state
+            .tick_exits(deadline, &mut fake_clients.get_map(key))
+            .is_empty());
     }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+// How long entity ticks have taken since the last world snapshot
+#[derive(Debug, Default)]
+pub struct TickStats {
+    count: u32,
+    total: Duration,
+}
+impl TickStats {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+    }
+
+    // Returns the average tick time and starts over
+    pub fn take_average(&mut self) -> Duration {
+        let avg = if self.count > 0 {
+            self.total / self.count
+        } else {
+            Duration::ZERO
+        };
+        *self = Self::default();
+        avg
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackedNPCStatus {
+    pub npc_id: i32,
+    pub npc_type: i32,
+    pub channel_num: u8,
+    pub alive: bool,
+}
+
+// World state a shard periodically reports to the login server for the monitor
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorldSnapshot {
+    // channel 1 first
+    pub channel_pops: Vec<usize>,
+    pub tracked_npcs: Vec<TrackedNPCStatus>,
+    // there were more tracked NPCs than fit
+    pub tracked_npcs_truncated: bool,
+    pub avg_tick: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_stats() {
+        let mut stats = TickStats::default();
+        assert_eq!(stats.take_average(), Duration::ZERO);
+
+        stats.record(Duration::from_millis(2));
+        stats.record(Duration::from_millis(4));
+        stats.record(Duration::from_millis(9));
+        assert_eq!(stats.take_average(), Duration::from_millis(5));
+        // starts over
+        assert_eq!(stats.take_average(), Duration::ZERO);
+    }
+}
@@ -1,26 +1,27 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 
-use rand::{rngs::ThreadRng, thread_rng, Rng};
+use rand::Rng;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{Map, Value};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     sync::OnceLock,
     time::{Duration, SystemTime},
 };
 
 use crate::{
     chunk::{EntityMap, InstanceID},
-    config::config_get,
+    config::{config_get, ChannelTemplate},
     defines::*,
     entity::{Egg, EntityID, NPC},
     enums::*,
     error::{log, log_error, log_if_failed, panic_log, FFError, FFResult, Severity},
-    item::{CrocPotData, Item, ItemStats, Reward, VendorData, VendorItem},
+    item::{CrocPotData, Item, ItemStats, ItemUseEffect, Reward, VendorData, VendorItem},
     mission::{MissionDefinition, TaskDefinition},
     nano::{NanoStats, NanoTuning},
     path::{Path, PathPoint},
+    skills::SkillData,
     util, Position,
 };
 
@@ -33,36 +34,171 @@ struct XDTData {
     transportation_data: TransportationData,
     instance_data: InstanceData,
     nano_data: NanoData,
+    skill_data: HashMap<i16, SkillData>,
     mission_data: MissionData,
     respawn_data: Vec<RespawnPoint>,
     player_data: HashMap<i16, PlayerStats>,
     npc_data: HashMap<i32, NPCStats>,
+    version: Option<String>,
+    disabled_features: Vec<&'static str>,
 }
 impl XDTData {
     fn load() -> Result<Self, String> {
-        let root = load_json("xdt.json")?;
-        Ok(Self {
-            vendor_data: load_vendor_data(&root)
+        let root = &load_json("xdt.json")?;
+        let mut disabled_features = Vec::new();
+        let crocpot_data = load_optional_table(
+            root,
+            "crocpot data",
+            "Croc-Pot combining",
+            load_crocpot_data,
+            &mut disabled_features,
+        );
+        let respawn_data = load_optional_table(
+            root,
+            "respawn data",
+            "respawning at the nearest X-COM",
+            load_respawn_data,
+            &mut disabled_features,
+        );
+        let skill_data = load_optional_table(
+            root,
+            "skill data",
+            "nano skills",
+            load_skill_data,
+            &mut disabled_features,
+        );
+
+        let mut xdt_data = Self {
+            vendor_data: load_vendor_data(root)
                 .map_err(|e| format!("Error loading vendor data: {}", e))?,
-            item_data: load_item_data(&root)
+            item_data: load_item_data(root)
                 .map_err(|e| format!("Error loading item data: {}", e))?,
-            crocpot_data: load_crocpot_data(&root)
-                .map_err(|e| format!("Error loading crocpot data: {}", e))?,
-            transportation_data: load_transportation_data(&root)
+            crocpot_data,
+            transportation_data: load_transportation_data(root)
                 .map_err(|e| format!("Error loading transportation data: {}", e))?,
-            instance_data: load_instance_data(&root)
+            instance_data: load_instance_data(root)
                 .map_err(|e| format!("Error loading instance data: {}", e))?,
-            nano_data: load_nano_data(&root)
+            nano_data: load_nano_data(root)
                 .map_err(|e| format!("Error loading nano data: {}", e))?,
-            mission_data: load_mission_data(&root)
+            skill_data,
+            mission_data: load_mission_data(root)
                 .map_err(|e| format!("Error loading mission data: {}", e))?,
-            respawn_data: load_respawn_data(&root)
-                .map_err(|e| format!("Error loading respawn data: {}", e))?,
-            player_data: load_player_data(&root)
+            respawn_data,
+            player_data: load_player_data(root)
                 .map_err(|e| format!("Error loading player data: {}", e))?,
-            npc_data: load_npc_data(&root).map_err(|e| format!("Error loading NPC data: {}", e))?,
-        })
+            npc_data: load_npc_data(root).map_err(|e| format!("Error loading NPC data: {}", e))?,
+            version: get_xdt_version(root),
+            disabled_features,
+        };
+
+        // barker fields are absent in some builds rather than being a table of their own
+        if xdt_data
+            .npc_data
+            .values()
+            .all(|stats| stats.bark_type.is_none())
+        {
+            log(
+                Severity::Warning,
+                "No NPC barker data in the XDT; NPC barks will be disabled",
+            );
+            xdt_data.disabled_features.push("NPC barks");
+        }
+        Ok(xdt_data)
+    }
+}
+
+fn build_skyway_routes(
+    transportation_data: &TransportationData,
+    path_data: &PathData,
+) -> HashMap<i32, Vec<(i32, i32)>> {
+    let mut routes: HashMap<i32, Vec<(i32, i32)>> = HashMap::new();
+    for trip in transportation_data.trip_data.values() {
+        if trip.transportation_type != TransportationType::Wyvern
+            || !path_data.skyway_paths.contains_key(&trip.route_number)
+            || !transportation_data
+                .monkey_skyway_data
+                .contains_key(&trip.start_location)
+            || !transportation_data
+                .monkey_skyway_data
+                .contains_key(&trip.end_location)
+        {
+            continue;
+        }
+        routes
+            .entry(trip.start_location)
+            .or_default()
+            .push((trip.end_location, trip.route_number));
+    }
+    routes
+}
+
+// Breadth-first, so the route with the fewest legs wins.
+// Returns each leg as (end location, route number).
+fn find_skyway_route(
+    routes: &HashMap<i32, Vec<(i32, i32)>>,
+    start_location: i32,
+    end_location: i32,
+) -> Option<Vec<(i32, i32)>> {
+    let mut came_from: HashMap<i32, (i32, i32)> = HashMap::new();
+    let mut visited = HashSet::from([start_location]);
+    let mut queue = VecDeque::from([start_location]);
+    while let Some(location) = queue.pop_front() {
+        if location == end_location {
+            let mut hops = Vec::new();
+            let mut current = end_location;
+            while current != start_location {
+                let (prev, route_number) = came_from[&current];
+                hops.push((current, route_number));
+                current = prev;
+            }
+            hops.reverse();
+            return Some(hops);
+        }
+
+        for &(next, route_number) in routes.get(&location).into_iter().flatten() {
+            if visited.insert(next) {
+                came_from.insert(next, (location, route_number));
+                queue.push_back(next);
+            }
+        }
     }
+    None
+}
+
+// Loads a table that not every client build ships with.
+// If it's missing or malformed, it's left empty and the features that need it are disabled.
+fn load_optional_table<T: Default>(
+    root: &Map<std::string::String, Value>,
+    table_name: &str,
+    feature: &'static str,
+    loader: fn(&Map<std::string::String, Value>) -> Result<T, String>,
+    disabled_features: &mut Vec<&'static str>,
+) -> T {
+    match loader(root) {
+        Ok(table) => table,
+        Err(e) => {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Couldn't load {} ({}); {} will be disabled",
+                    table_name, e, feature
+                ),
+            );
+            disabled_features.push(feature);
+            T::default()
+        }
+    }
+}
+
+fn get_xdt_version(root: &Map<std::string::String, Value>) -> Option<String> {
+    const XDT_VERSION_KEYS: [&str; 2] = ["version", "m_strVersion"];
+    XDT_VERSION_KEYS
+        .iter()
+        .find_map(|key| match root.get(*key)? {
+            Value::String(version) => Some(version.clone()),
+            Value::Number(version) => Some(version.to_string()),
+            _ => None,
+        })
 }
 
 #[derive(Debug)]
@@ -72,6 +208,7 @@ struct NPCSpawnData {
     pos: Position,
     angle: i32,
     map_num: Option<u32>,
+    mob_drop_id: Option<i32>,
     followers: Vec<FollowerData>,
 }
 
@@ -86,6 +223,7 @@ struct EggSpawnData {
 struct FollowerData {
     npc_type: i32,
     offset: Position,
+    mob_drop_id: Option<i32>,
 }
 
 #[derive(Debug)]
@@ -99,6 +237,12 @@ pub struct TripData {
     pub route_number: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct SkywayLeg {
+    pub end_location: i32,
+    pub path: Path,
+}
+
 #[derive(Debug)]
 pub struct TransporterData {
     pub npc_type: i32,
@@ -157,6 +301,7 @@ pub struct PlayerStats {
     pub req_fm_nano_create: u32,
     pub req_fm_nano_tune: u32,
     pub fm_limit: u32,
+    pub death_fm: u32,
     pub nano_quest_task_id: Option<i32>,
     pub nano_id: i16,
 }
@@ -181,6 +326,226 @@ pub struct NPCStats {
     pub bark_type: Option<usize>,
 }
 
+// Tuning for the NPC behavior tree. Anything not set in ai.json keeps these defaults
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AIParams {
+    pub scan_radius_mult: f32,
+    pub aggro_rate_up: f32,
+    pub aggro_rate_down: f32, // applied as a decay, so it's given as a positive number
+    pub aggro_threshold: f32,
+    pub level_factor: f32,
+    pub distance_factor: f32,
+    pub retreat_speed_mult: f32,
+    pub roam_radius_mult: f32,
+    pub roam_delay_mult: f32,
+    pub attack_cooldown_mult: f32,
+}
+impl Default for AIParams {
+    fn default() -> Self {
+        Self {
+            scan_radius_mult: 1.0,
+            aggro_rate_up: 1.5,
+            aggro_rate_down: 1.0,
+            aggro_threshold: 1.0,
+            level_factor: 0.1,
+            distance_factor: 0.5,
+            retreat_speed_mult: 2.0,
+            roam_radius_mult: 1.0,
+            roam_delay_mult: 1.0,
+            attack_cooldown_mult: 1.0,
+        }
+    }
+}
+impl AIParams {
+    fn validate(&self) -> Result<(), String> {
+        let values = [
+            ("scan_radius_mult", self.scan_radius_mult),
+            ("aggro_rate_up", self.aggro_rate_up),
+            ("aggro_rate_down", self.aggro_rate_down),
+            ("aggro_threshold", self.aggro_threshold),
+            ("level_factor", self.level_factor),
+            ("distance_factor", self.distance_factor),
+            ("retreat_speed_mult", self.retreat_speed_mult),
+            ("roam_radius_mult", self.roam_radius_mult),
+            ("roam_delay_mult", self.roam_delay_mult),
+            ("attack_cooldown_mult", self.attack_cooldown_mult),
+        ];
+        for (name, value) in values {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!(
+                    "{} must be a non-negative number, got {}",
+                    name, value
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AIParamsData {
+    global: AIParams,
+    by_ai_type: HashMap<u8, AIParams>,
+}
+impl AIParamsData {
+    // Expects { "Global": { ... }, "AITypes": { "<ai type>": { ... } } }, both optional.
+    // Per-type entries are layered on top of the global ones.
+    pub fn load(root: &Map<std::string::String, Value>) -> Result<Self, String> {
+        const GLOBAL_KEY: &str = "Global";
+        const AI_TYPES_KEY: &str = "AITypes";
+
+        fn parse_params(table: Map<std::string::String, Value>) -> Result<AIParams, String> {
+            let params: AIParams = serde_json::from_value(Value::Object(table))
+                .map_err(|e| format!("Malformed AI params: {}", e))?;
+            params.validate()?;
+            Ok(params)
+        }
+
+        let global_table = match root.get(GLOBAL_KEY) {
+            Some(_) => get_object(root, GLOBAL_KEY)?.clone(),
+            None => Map::new(),
+        };
+        let global = parse_params(global_table.clone())?;
+
+        let mut by_ai_type = HashMap::new();
+        if root.contains_key(AI_TYPES_KEY) {
+            for (key, overrides) in get_object(root, AI_TYPES_KEY)? {
+                let ai_type: u8 = key
+                    .parse()
+                    .map_err(|_| format!("Invalid AI type: {}", key))?;
+                let Value::Object(overrides) = overrides else {
+                    return Err(format!(
+                        "AI params for AI type {} are not an object",
+                        ai_type
+                    ));
+                };
+                let mut table = global_table.clone();
+                table.extend(overrides.clone());
+                let params =
+                    parse_params(table).map_err(|e| format!("AI type {}: {}", ai_type, e))?;
+                by_ai_type.insert(ai_type, params);
+            }
+        }
+        Ok(Self { global, by_ai_type })
+    }
+
+    pub fn get(&self, ai_type: u8) -> &AIParams {
+        self.by_ai_type.get(&ai_type).unwrap_or(&self.global)
+    }
+}
+
+// Old NPC type -> new NPC type, for custom content that renumbered or merged mobs.
+// Everything that refers to NPCs by type is translated once, at load time.
+#[derive(Debug)]
+struct NpcRemap {
+    types: HashMap<i32, i32>,
+}
+impl NpcRemap {
+    // Expects { "<old type>": <new type>, ... }
+    fn load(root: &Map<std::string::String, Value>) -> Result<Self, String> {
+        let mut types = HashMap::new();
+        for (key, value) in root {
+            let old_type: i32 = key
+                .parse()
+                .map_err(|_| format!("Invalid NPC type: {}", key))?;
+            let new_type = value
+                .as_i64()
+                .and_then(|new_type| i32::try_from(new_type).ok())
+                .ok_or(format!(
+                    "NPC type {} remaps to invalid type {}",
+                    old_type, value
+                ))?;
+            types.insert(old_type, new_type);
+        }
+
+        // each reference is only translated once, so a chain would stop partway through
+        for (old_type, new_type) in &types {
+            if types.contains_key(new_type) {
+                return Err(format!(
+                    "NPC type {} remaps to {}, which is remapped itself",
+                    old_type, new_type
+                ));
+            }
+        }
+        Ok(Self { types })
+    }
+
+    fn get(&self, npc_type: i32) -> i32 {
+        self.types.get(&npc_type).copied().unwrap_or(npc_type)
+    }
+
+    // Moves entries for old types to their new type. When types were merged, the new type
+    // keeps whatever it already had, or else the entry of the lowest old type
+    fn remap_keys<T>(&self, table: &mut HashMap<i32, T>) {
+        let mut old_types: Vec<&i32> = self.types.keys().collect();
+        old_types.sort();
+        for old_type in old_types {
+            if let Some(value) = table.remove(old_type) {
+                table.entry(self.types[old_type]).or_insert(value);
+            }
+        }
+    }
+
+    // Kill progress is saved by objective slot rather than by type, so objectives keep their
+    // order and saved mission journals line up with the new types on their own.
+    // Two objectives of one task merging into a single type can't be lined up like that.
+    fn remap_task(&self, task_def: &mut TaskDefinition) -> Result<(), String> {
+        let ordering: Vec<i32> = task_def
+            .obj_enemy_id_ordering
+            .iter()
+            .map(|npc_type| self.get(*npc_type))
+            .collect();
+        if ordering.iter().collect::<HashSet<_>>().len() != ordering.len() {
+            return Err(format!(
+                "Task {} has kill objectives for NPC types that were merged",
+                task_def.task_id
+            ));
+        }
+        task_def.obj_enemies = task_def
+            .obj_enemies
+            .iter()
+            .map(|(npc_type, count)| (self.get(*npc_type), *count))
+            .collect();
+        task_def.obj_enemy_id_ordering = ordering;
+
+        let remap = |npc_type: Option<i32>| npc_type.map(|npc_type| self.get(npc_type));
+        task_def.prereq_npc_type = remap(task_def.prereq_npc_type);
+        task_def.obj_npc_type = remap(task_def.obj_npc_type);
+        task_def.obj_escort_npc_type = remap(task_def.obj_escort_npc_type);
+        Ok(())
+    }
+
+    fn apply(&self, tdata: &mut TableData) -> Result<(), String> {
+        for spawn in &mut tdata.npcs {
+            spawn.npc_type = self.get(spawn.npc_type);
+            for follower in &mut spawn.followers {
+                follower.npc_type = self.get(follower.npc_type);
+            }
+        }
+
+        let xdt_data = &mut tdata.xdt_data;
+        for task_def in xdt_data.mission_data.task_definitions.values_mut() {
+            self.remap_task(task_def)?;
+        }
+        let transportation_data = &mut xdt_data.transportation_data;
+        for transporter in transportation_data
+            .scamper_data
+            .values_mut()
+            .chain(transportation_data.monkey_skyway_data.values_mut())
+        {
+            transporter.npc_type = self.get(transporter.npc_type);
+        }
+        for warp in xdt_data.instance_data.warp_data.values_mut() {
+            warp.npc_type = self.get(warp.npc_type);
+        }
+
+        self.remap_keys(&mut tdata.drop_data.mob_drop_data);
+        self.remap_keys(&mut tdata.path_data.npc_paths);
+        Ok(())
+    }
+}
+
 pub struct EggStats {
     pub crate_id: Option<i16>,
     pub effect_id: Option<i32>,
@@ -200,6 +565,17 @@ struct CrateDropType {
     CrateIDs: Vec<i32>,
 }
 
+#[derive(Debug, Deserialize)]
+struct EventCrate {
+    DropChance: i32,
+    DropChanceTotal: i32,
+    CrateIDs: Vec<i32>,
+    #[serde(default)]
+    MinMobLevel: Option<i16>,
+    #[serde(default)]
+    MaxMobLevel: Option<i16>,
+}
+
 #[derive(Debug, Deserialize)]
 struct CrateData {
     ItemSetID: i32,
@@ -269,6 +645,7 @@ struct EggData {
 struct DropData {
     crate_drop_chances: HashMap<i32, CrateDropChance>,
     crate_drop_types: HashMap<i32, CrateDropType>,
+    event_crates: HashMap<i32, EventCrate>,
     crate_data: HashMap<i32, CrateData>,
     misc_drop_chances: HashMap<i32, MiscDropChance>,
     misc_drop_types: HashMap<i32, MiscDropType>,
@@ -285,12 +662,48 @@ struct PathData {
     npc_paths: HashMap<i32, Path>,
 }
 
+// Picks which spawn entries a channel gets, repeating mob entries when the multiplier calls for it
+fn get_spawns_for_channel<'a>(
+    spawns: &'a [NPCSpawnData],
+    template: Option<&ChannelTemplate>,
+    is_mob: impl Fn(i32) -> bool,
+) -> Vec<&'a NPCSpawnData> {
+    let Some(template) = template else {
+        return spawns.iter().collect();
+    };
+
+    let multiplier = template.spawn_multiplier.max(0.0);
+    let mut mobs_seen = 0;
+    let mut chosen = Vec::new();
+    for dat in spawns {
+        let dat_is_mob = is_mob(dat.npc_type);
+        if !template.allows_spawn(dat.group_id, dat_is_mob) {
+            continue;
+        }
+
+        if !dat_is_mob {
+            chosen.push(dat);
+            continue;
+        }
+
+        // spread fractional multipliers evenly across the spawn list
+        let copies = ((mobs_seen + 1) as f32 * multiplier) as usize
+            - (mobs_seen as f32 * multiplier) as usize;
+        mobs_seen += 1;
+        chosen.extend(std::iter::repeat(dat).take(copies));
+    }
+    chosen
+}
+
 pub struct TableData {
     xdt_data: XDTData,
     npcs: Vec<NPCSpawnData>,
     drop_data: DropData,
     path_data: PathData,
     egg_data: EggData,
+    ai_params: AIParamsData,
+    // skyway location -> (connected location, route number) for every route with a path
+    skyway_routes: HashMap<i32, Vec<(i32, i32)>>,
 }
 impl TableData {
     fn new() -> Self {
@@ -300,13 +713,30 @@ impl TableData {
     }
 
     fn load() -> Result<Self, String> {
-        Ok(Self {
-            xdt_data: XDTData::load().map_err(|e| format!("Error loading XDT: {}", e))?,
+        let xdt_data = XDTData::load().map_err(|e| format!("Error loading XDT: {}", e))?;
+        let path_data = load_path_data().map_err(|e| format!("Error loading path data: {}", e))?;
+        let skyway_routes = build_skyway_routes(&xdt_data.transportation_data, &path_data);
+        let mut tdata = Self {
+            xdt_data,
             npcs: load_npcs().map_err(|e| format!("Error loading NPC data: {}", e))?,
             drop_data: load_drop_data().map_err(|e| format!("Error loading drop data: {}", e))?,
-            path_data: load_path_data().map_err(|e| format!("Error loading path data: {}", e))?,
+            path_data,
             egg_data: load_egg_data().map_err(|e| format!("Error loading egg data: {}", e))?,
-        })
+            ai_params: load_ai_params().map_err(|e| format!("Error loading AI params: {}", e))?,
+            skyway_routes,
+        };
+        if let Some(remap) =
+            load_npc_remap().map_err(|e| format!("Error loading NPC remap: {}", e))?
+        {
+            remap
+                .apply(&mut tdata)
+                .map_err(|e| format!("Error applying NPC remap: {}", e))?;
+            log(
+                Severity::Info,
+                &format!("Remapped {} NPC type(s)", remap.types.len()),
+            );
+        }
+        Ok(tdata)
     }
 
     pub fn get_item_stats(&self, item_id: i16, item_type: ItemType) -> FFResult<&ItemStats> {
@@ -329,6 +759,10 @@ impl TableData {
         ))
     }
 
+    pub fn get_ai_params(&self, ai_type: u8) -> &AIParams {
+        self.ai_params.get(ai_type)
+    }
+
     pub fn get_vendor_data(&self, vendor_id: i32) -> FFResult<&VendorData> {
         self.xdt_data
             .vendor_data
@@ -349,6 +783,16 @@ impl TableData {
             ))
     }
 
+    pub fn get_skill_data(&self, skill_id: i16) -> FFResult<&SkillData> {
+        self.xdt_data
+            .skill_data
+            .get(&skill_id)
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Skill with ID {} doesn't exist", skill_id),
+            ))
+    }
+
     pub fn get_trip_data(&self, trip_id: i32) -> FFResult<&TripData> {
         self.xdt_data
             .transportation_data
@@ -385,6 +829,22 @@ impl TableData {
             ))
     }
 
+    // Every S.C.A.M.P.E.R. and Skyway location served by this NPC type
+    pub fn get_transporter_locations(&self, npc_type: i32) -> Vec<(TransportationType, i32)> {
+        let transportation_data = &self.xdt_data.transportation_data;
+        let scamper_locations = transportation_data
+            .scamper_data
+            .iter()
+            .filter(|(_, dat)| dat.npc_type == npc_type)
+            .map(|(id, _)| (TransportationType::Warp, *id));
+        let skyway_locations = transportation_data
+            .monkey_skyway_data
+            .iter()
+            .filter(|(_, dat)| dat.npc_type == npc_type)
+            .map(|(id, _)| (TransportationType::Wyvern, *id));
+        scamper_locations.chain(skyway_locations).collect()
+    }
+
     pub fn get_warp_data(&self, warp_id: i32) -> FFResult<&WarpData> {
         self.xdt_data
             .instance_data
@@ -436,7 +896,7 @@ impl TableData {
     ) -> Vec<NPC> {
         let dat = spawn_data;
         let mut npcs = Vec::new();
-        let npc = match NPC::new(
+        let mut npc = match NPC::new(
             entity_map.gen_next_npc_id(),
             dat.npc_type,
             Position {
@@ -460,6 +920,7 @@ impl TableData {
                 return npcs;
             }
         };
+        npc.mob_drop_id = dat.mob_drop_id;
         for follower_data in &dat.followers {
             let id = entity_map.gen_next_npc_id();
             let mut follower = match NPC::new(
@@ -483,15 +944,25 @@ impl TableData {
                 }
             };
             follower.tight_follow = Some((EntityID::NPC(npc.id), follower_data.offset));
+            follower.mob_drop_id = follower_data.mob_drop_id;
             npcs.push(follower);
         }
         npcs.push(npc);
         npcs
     }
 
-    pub fn make_all_npcs(&self, entity_map: &mut EntityMap, channel_num: u8) -> Vec<NPC> {
+    pub fn make_all_npcs(
+        &self,
+        entity_map: &mut EntityMap,
+        channel_num: u8,
+        template: Option<&ChannelTemplate>,
+    ) -> Vec<NPC> {
+        let is_mob = |npc_type| {
+            self.get_npc_stats(npc_type)
+                .is_ok_and(|stats| stats.team == CombatantTeam::Mob)
+        };
         let mut npcs = Vec::new();
-        for dat in &self.npcs {
+        for dat in get_spawns_for_channel(&self.npcs, template, is_mob) {
             npcs.extend(Self::make_npcs_from_spawn_data(
                 dat,
                 entity_map,
@@ -545,15 +1016,70 @@ impl TableData {
         eggs
     }
 
-    pub fn get_item_from_crate(&self, crate_id: i16, gender: i32) -> FFResult<Item> {
-        let crate_data =
-            self.drop_data
-                .crate_data
-                .get(&(crate_id as i32))
-                .ok_or(FFError::build(
-                    Severity::Warning,
-                    format!("No C.R.A.T.E. data for id {}", crate_id),
-                ))?;
+    pub fn get_item_from_crate<R: Rng>(
+        &self,
+        rng: &mut R,
+        crate_id: i16,
+        gender: i32,
+    ) -> FFResult<Item> {
+        self.get_item_from_crate_with_pity(rng, crate_id, gender, 0, 0.0)
+            .map(|(item, _)| item)
+    }
+
+    // Rolls a C.R.A.T.E. for a player who has gone `pity` rolls without its rarest rarity.
+    // Each of those adds `pity_rate` of the total rarity weight to the rarest rarity.
+    // Also returns whether the rarest rarity came up.
+    pub fn get_item_from_crate_with_pity<R: Rng>(
+        &self,
+        rng: &mut R,
+        crate_id: i16,
+        gender: i32,
+        pity: u32,
+        pity_rate: f32,
+    ) -> FFResult<(Item, bool)> {
+        let (item_set, rarity_weights) = self.get_crate_tables(crate_id as i32)?;
+        let (rarity, rarest) = roll_crate_rarity(rng, &rarity_weights.Weights, pity, pity_rate);
+
+        let (item_pool, errors) = self.get_crate_item_pool(item_set, rarity, gender);
+        for e in errors {
+            log(e.get_severity(), e.get_msg());
+        }
+        if item_pool.is_empty() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Item pool was empty: id {}, gender {}", crate_id, gender),
+            ));
+        }
+
+        // get the weights for each item
+        let mut item_weights = vec![item_set.DefaultItemWeight; item_pool.len()];
+        for (idx, item_ref_id) in item_pool.iter().enumerate() {
+            let override_weight = item_set.AlterItemWeightMap.get(&item_ref_id.to_string());
+            if let Some(weight) = override_weight {
+                item_weights[idx] = *weight;
+            }
+        }
+
+        // select an item
+        let rolled_item_ref_id = item_pool[util::weighted_rand_with(rng, &item_weights)];
+        let rolled_item_ref = self.drop_data.item_refs.get(&rolled_item_ref_id).unwrap();
+
+        let item = Item::new(
+            (rolled_item_ref.Type as i16).try_into()?,
+            rolled_item_ref.ItemID as i16,
+        );
+        Ok((item, rarest))
+    }
+
+    fn get_crate_tables(&self, crate_id: i32) -> FFResult<(&ItemSet, &RarityWeights)> {
+        let crate_data = self
+            .drop_data
+            .crate_data
+            .get(&crate_id)
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("No C.R.A.T.E. data for id {}", crate_id),
+            ))?;
 
         let item_set =
             self.drop_data
@@ -573,11 +1099,19 @@ impl TableData {
                 format!("No rarity data for id {}", crate_data.RarityWeightID),
             ))?;
 
-        // generate a rarity from the rarity weights. rarities start at 1
-        let rarity = (util::weighted_rand(&rarity_weights.Weights) + 1) as i32;
+        Ok((item_set, rarity_weights))
+    }
 
-        // build a pool of eligible items
+    // Item references in the set that a roll of the given rarity can give to the given gender,
+    // along with whatever went wrong looking up the rest
+    fn get_crate_item_pool(
+        &self,
+        item_set: &ItemSet,
+        rarity: i32,
+        gender: i32,
+    ) -> (Vec<i32>, Vec<FFError>) {
         let mut item_pool = Vec::new();
+        let mut errors = Vec::new();
         for item_ref_id in &item_set.ItemReferenceIDs {
             let eligible: FFResult<bool> = (|| {
                 let item_ref = self
@@ -617,40 +1151,13 @@ impl TableData {
                         item_pool.push(*item_ref_id);
                     }
                 }
-                Err(e) => log(e.get_severity(), e.get_msg()),
+                Err(e) => errors.push(e),
             }
         }
-
-        if item_pool.is_empty() {
-            return Err(FFError::build(
-                Severity::Warning,
-                format!("Item pool was empty: id {}, gender {}", crate_id, gender),
-            ));
-        }
-
-        // get the weights for each item
-        let mut item_weights = vec![item_set.DefaultItemWeight; item_pool.len()];
-        for (idx, item_ref_id) in item_pool.iter().enumerate() {
-            let override_weight = item_set.AlterItemWeightMap.get(&item_ref_id.to_string());
-            if let Some(weight) = override_weight {
-                item_weights[idx] = *weight;
-            }
-        }
-
-        // select an item
-        let rolled_item_ref_id = item_pool[util::weighted_rand(&item_weights)];
-        let rolled_item_ref = self.drop_data.item_refs.get(&rolled_item_ref_id).unwrap();
-
-        Ok(Item::new(
-            (rolled_item_ref.Type as i16).try_into()?,
-            rolled_item_ref.ItemID as i16,
-        ))
+        (item_pool, errors)
     }
 
-    pub fn get_mob_reward(&self, mob_type: i32) -> FFResult<Reward> {
-        let mut rng = thread_rng();
-        let mut reward = Reward::new(RewardCategory::Combat);
-
+    pub fn get_mob_reward<R: Rng>(&self, rng: &mut R, mob_type: i32) -> FFResult<Reward> {
         let mapping = self
             .drop_data
             .mob_drop_data
@@ -659,17 +1166,80 @@ impl TableData {
                 Severity::Warning,
                 format!("No mob drop data for mob type {}", mob_type),
             ))?;
+        self.get_mob_drop_reward(rng, mapping.MobDropID)
+    }
+
+    pub fn get_mob_reward_for<R: Rng>(
+        &self,
+        rng: &mut R,
+        mob_type: i32,
+        mob_drop_id: Option<i32>,
+        event_id: Option<i32>,
+    ) -> FFResult<Reward> {
+        let mut reward = match mob_drop_id {
+            Some(mob_drop_id) if self.validate_mob_drop_id(mob_drop_id).is_ok() => {
+                self.get_mob_drop_reward(rng, mob_drop_id)?
+            }
+            Some(mob_drop_id) => {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Mob type {} has nonexistent mob drop override {}; using type default",
+                        mob_type, mob_drop_id
+                    ),
+                );
+                self.get_mob_reward(rng, mob_type)?
+            }
+            None => self.get_mob_reward(rng, mob_type)?,
+        };
+
+        // event crates drop on top of the regular crate
+        if event_id.is_some() {
+            let mob_level = self.get_npc_stats(mob_type)?.level;
+            if let Some(crate_item) =
+                roll_event_crate(rng, &self.drop_data.event_crates, event_id, mob_level)
+            {
+                reward.items.push(crate_item);
+            }
+        }
+        Ok(reward)
+    }
+
+    pub fn validate_event_id(&self, event_id: i32) -> FFResult<()> {
+        if self.drop_data.event_crates.contains_key(&event_id) {
+            Ok(())
+        } else {
+            Err(FFError::build(
+                Severity::Warning,
+                format!("No event crates for event id {}", event_id),
+            ))
+        }
+    }
+
+    pub fn validate_mob_drop_id(&self, mob_drop_id: i32) -> FFResult<()> {
+        if self.drop_data.mob_drops.contains_key(&mob_drop_id) {
+            Ok(())
+        } else {
+            Err(FFError::build(
+                Severity::Warning,
+                format!("No mob drop for mob drop id {}", mob_drop_id),
+            ))
+        }
+    }
+
+    fn get_mob_drop_reward<R: Rng>(&self, rng: &mut R, mob_drop_id: i32) -> FFResult<Reward> {
+        let mut reward = Reward::new(RewardCategory::Combat);
 
         let mob_drop = self
             .drop_data
             .mob_drops
-            .get(&mapping.MobDropID)
+            .get(&mob_drop_id)
             .ok_or(FFError::build(
                 Severity::Warning,
-                format!("No mob drop for mob drop id {}", mapping.MobDropID),
+                format!("No mob drop for mob drop id {}", mob_drop_id),
             ))?;
 
-        let apply_misc_drop = |rng: &mut ThreadRng, reward: &mut Reward| {
+        let apply_misc_drop = |rng: &mut R, reward: &mut Reward| {
             let misc_drop_type = self
                 .drop_data
                 .misc_drop_types
@@ -708,8 +1278,7 @@ impl TableData {
             Ok(())
         };
 
-        let apply_crate_drop = |rng: &mut ThreadRng, reward: &mut Reward| {
-            // TODO event crate drops
+        let apply_crate_drop = |rng: &mut R, reward: &mut Reward| {
             let crate_drop_type = self
                 .drop_data
                 .crate_drop_types
@@ -728,17 +1297,17 @@ impl TableData {
                 ))?;
             if rng.gen_range(0..crate_drop_chance.DropChanceTotal) < crate_drop_chance.DropChance {
                 let crate_id = crate_drop_type.CrateIDs
-                    [util::weighted_rand(&crate_drop_chance.CrateTypeDropWeights)];
+                    [util::weighted_rand_with(rng, &crate_drop_chance.CrateTypeDropWeights)];
                 let crate_item = Item::new(ItemType::Chest, crate_id as i16);
                 reward.items.push(crate_item);
             }
             Ok(())
         };
 
-        log_if_failed(apply_misc_drop(&mut rng, &mut reward));
-        if let Err(e) = apply_crate_drop(&mut rng, &mut reward) {
+        log_if_failed(apply_misc_drop(rng, &mut reward));
+        if let Err(e) = apply_crate_drop(rng, &mut reward) {
             log_error(&e);
-            reward.items.push(util::get_random_gumball());
+            reward.items.push(util::get_random_gumball(rng));
         }
 
         Ok(reward)
@@ -763,6 +1332,40 @@ impl TableData {
             ))
     }
 
+    // The legs a Skyway trip flies through, in order.
+    // Trips with their own path fly it directly; otherwise the shortest chain of routes is used.
+    pub fn get_skyway_route(&self, trip: &TripData) -> FFResult<Vec<SkywayLeg>> {
+        if let Ok(path) = self.get_skyway_path(trip.route_number) {
+            return Ok(vec![SkywayLeg {
+                end_location: trip.end_location,
+                path,
+            }]);
+        }
+
+        let hops = find_skyway_route(&self.skyway_routes, trip.start_location, trip.end_location)
+            .ok_or(FFError::build(
+            Severity::Warning,
+            format!(
+                "No Skyway route from location {} to {}",
+                trip.start_location, trip.end_location
+            ),
+        ))?;
+        if hops.is_empty() {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("Skyway trip {} goes nowhere", trip.start_location),
+            ));
+        }
+        hops.into_iter()
+            .map(|(end_location, route_number)| {
+                Ok(SkywayLeg {
+                    end_location,
+                    path: self.get_skyway_path(route_number)?,
+                })
+            })
+            .collect()
+    }
+
     pub fn get_task_definition(&self, task_id: i32) -> FFResult<&TaskDefinition> {
         self.xdt_data
             .mission_data
@@ -792,23 +1395,245 @@ impl TableData {
         ))
     }
 
-    pub fn get_npc_stats(&self, npc_type: i32) -> FFResult<&NPCStats> {
-        self.xdt_data.npc_data.get(&npc_type).ok_or(FFError::build(
-            Severity::Warning,
-            format!("NPC stats for type {} don't exist", npc_type),
-        ))
+    pub fn get_npc_stats(&self, npc_type: i32) -> FFResult<&NPCStats> {
+        self.xdt_data.npc_data.get(&npc_type).ok_or(FFError::build(
+            Severity::Warning,
+            format!("NPC stats for type {} don't exist", npc_type),
+        ))
+    }
+
+    pub fn get_mission_reward(&self, reward_id: i32) -> FFResult<Reward> {
+        self.xdt_data
+            .mission_data
+            .rewards
+            .get(&reward_id)
+            .cloned()
+            .ok_or(FFError::build(
+                Severity::Warning,
+                format!("Reward with id {} doesn't exist", reward_id),
+            ))
+    }
+
+    fn log_summary(&self) {
+        let xdt_data = &self.xdt_data;
+        log(
+            Severity::Info,
+            &format!(
+                "XDT version: {}",
+                xdt_data.version.as_deref().unwrap_or("unknown")
+            ),
+        );
+        log(
+            Severity::Info,
+            &format!(
+                "Table data has {} items, {} NPC types, {} NPC spawns, {} missions, {} warps",
+                xdt_data.item_data.len(),
+                xdt_data.npc_data.len(),
+                self.npcs.len(),
+                xdt_data.mission_data.mission_definitions.len(),
+                xdt_data.instance_data.warp_data.len(),
+            ),
+        );
+        if !xdt_data.disabled_features.is_empty() {
+            log(
+                Severity::Warning,
+                &format!(
+                    "Disabled due to missing table data: {}",
+                    xdt_data.disabled_features.join(", ")
+                ),
+            );
+        }
+    }
+
+    // Walks every cross-reference between tables and collects the ones that point nowhere,
+    // grouped by kind. Groups with nothing dangling are left out.
+    pub fn find_dangling_refs(&self) -> Vec<(&'static str, Vec<String>)> {
+        let xdt_data = &self.xdt_data;
+        let mission_data = &xdt_data.mission_data;
+        let has_item = |id: i16, ty: ItemType| xdt_data.item_data.contains_key(&(id, ty));
+
+        let mut vendor_items = Vec::new();
+        for (vendor_id, vendor) in &xdt_data.vendor_data {
+            for item in vendor.get_items() {
+                if !has_item(item.id, item.ty) {
+                    vendor_items.push(format!(
+                        "Vendor {} sells item {:?} #{}",
+                        vendor_id, item.ty, item.id
+                    ));
+                }
+            }
+        }
+
+        let mut reward_items = Vec::new();
+        for (reward_id, reward) in &mission_data.rewards {
+            for item in &reward.items {
+                if !has_item(item.id, item.ty) {
+                    reward_items.push(format!(
+                        "Reward {} gives item {:?} #{}",
+                        reward_id, item.ty, item.id
+                    ));
+                }
+            }
+        }
+
+        let mut rewards = Vec::new();
+        let mut tasks = Vec::new();
+        for (task_id, task_def) in &mission_data.task_definitions {
+            if let Some(reward_id) = task_def.succ_reward {
+                if !mission_data.rewards.contains_key(&reward_id) {
+                    rewards.push(format!("Task {} gives reward {}", task_id, reward_id));
+                }
+            }
+            for (outcome, next_task_id) in [
+                ("success", task_def.succ_task_id),
+                ("failure", task_def.fail_task_id),
+            ] {
+                let Some(next_task_id) = next_task_id else {
+                    continue;
+                };
+                if !mission_data.task_definitions.contains_key(&next_task_id) {
+                    tasks.push(format!(
+                        "Task {} moves to task {} on {}",
+                        task_id, next_task_id, outcome
+                    ));
+                }
+            }
+        }
+
+        let instance_data = &xdt_data.instance_data;
+        let mut maps = Vec::new();
+        for (warp_id, warp) in &instance_data.warp_data {
+            if warp.map_num != ID_OVERWORLD && !instance_data.map_data.contains_key(&warp.map_num) {
+                maps.push(format!("Warp {} goes to map {}", warp_id, warp.map_num));
+            }
+        }
+
+        let mut npc_types = Vec::new();
+        for spawn in &self.npcs {
+            let followers = spawn.followers.iter().map(|f| f.npc_type);
+            for npc_type in std::iter::once(spawn.npc_type).chain(followers) {
+                if !xdt_data.npc_data.contains_key(&npc_type) {
+                    npc_types.push(format!(
+                        "NPC spawn at ({}, {}, {}) uses NPC type {}",
+                        spawn.pos.x, spawn.pos.y, spawn.pos.z, npc_type
+                    ));
+                }
+            }
+        }
+        for npc_type in self.path_data.npc_paths.keys() {
+            if !xdt_data.npc_data.contains_key(npc_type) {
+                npc_types.push(format!("NPC path is for NPC type {}", npc_type));
+            }
+        }
+        for (task_id, task_def) in &mission_data.task_definitions {
+            for npc_type in &task_def.obj_enemy_id_ordering {
+                if !xdt_data.npc_data.contains_key(npc_type) {
+                    npc_types.push(format!(
+                        "Task {} needs NPC type {} defeated",
+                        task_id, npc_type
+                    ));
+                }
+            }
+        }
+        for npc_type in self.drop_data.mob_drop_data.keys() {
+            if !xdt_data.npc_data.contains_key(npc_type) {
+                npc_types.push(format!("Mob drop is for NPC type {}", npc_type));
+            }
+        }
+
+        let mut egg_types = Vec::new();
+        for egg in &self.egg_data.eggs {
+            if !self.egg_data.egg_stats.contains_key(&egg.egg_type) {
+                egg_types.push(format!(
+                    "Egg spawn at ({}, {}, {}) uses egg type {}",
+                    egg.pos.x, egg.pos.y, egg.pos.z, egg.egg_type
+                ));
+            }
+        }
+
+        let mut skyway_routes = Vec::new();
+        for (trip_id, trip) in &xdt_data.transportation_data.trip_data {
+            if trip.transportation_type != TransportationType::Wyvern
+                || self.path_data.skyway_paths.contains_key(&trip.route_number)
+            {
+                continue;
+            }
+            let hops =
+                find_skyway_route(&self.skyway_routes, trip.start_location, trip.end_location);
+            if hops.map_or(true, |hops| hops.is_empty()) {
+                skyway_routes.push(format!(
+                    "Skyway trip {} ({} -> {}) has no path for route {}",
+                    trip_id, trip.start_location, trip.end_location, trip.route_number
+                ));
+            }
+        }
+
+        // every rarity a crate can roll should have something for both genders
+        let mut crate_pools = Vec::new();
+        for crate_id in self.drop_data.crate_data.keys() {
+            let (item_set, rarity_weights) = match self.get_crate_tables(*crate_id) {
+                Ok(tables) => tables,
+                Err(e) => {
+                    crate_pools.push(format!("C.R.A.T.E. {}: {}", crate_id, e.get_msg()));
+                    continue;
+                }
+            };
+            for (idx, weight) in rarity_weights.Weights.iter().enumerate() {
+                if *weight <= 0 {
+                    continue;
+                }
+                let rarity = idx as i32 + 1;
+                for gender in [1, 2] {
+                    if self
+                        .get_crate_item_pool(item_set, rarity, gender)
+                        .0
+                        .is_empty()
+                    {
+                        crate_pools.push(format!(
+                            "C.R.A.T.E. {} has no rarity {} items for gender {}",
+                            crate_id, rarity, gender
+                        ));
+                    }
+                }
+            }
+        }
+
+        [
+            ("vendor items", vendor_items),
+            ("mission reward items", reward_items),
+            ("mission rewards", rewards),
+            ("follow-up tasks", tasks),
+            ("warp maps", maps),
+            ("NPC types", npc_types),
+            ("egg types", egg_types),
+            ("Skyway routes", skyway_routes),
+            ("C.R.A.T.E. item pools", crate_pools),
+        ]
+        .into_iter()
+        .filter(|(_, refs)| !refs.is_empty())
+        .map(|(kind, mut refs)| {
+            refs.sort();
+            (kind, refs)
+        })
+        .collect()
     }
 
-    pub fn get_mission_reward(&self, reward_id: i32) -> FFResult<Reward> {
-        self.xdt_data
-            .mission_data
-            .rewards
-            .get(&reward_id)
-            .cloned()
-            .ok_or(FFError::build(
-                Severity::Warning,
-                format!("Reward with id {} doesn't exist", reward_id),
-            ))
+    fn log_dangling_refs(&self) {
+        let dangling_refs = self.find_dangling_refs();
+        if dangling_refs.is_empty() {
+            return;
+        }
+        let summary: Vec<String> = dangling_refs
+            .iter()
+            .map(|(kind, refs)| format!("{} {}", refs.len(), kind))
+            .collect();
+        log(
+            Severity::Warning,
+            &format!(
+                "Table data has dangling references: {} (run with --validate-tdata for details)",
+                summary.join(", ")
+            ),
+        );
     }
 
     pub fn get_nearest_respawn_point(&self, pos: Position, map_num: u32) -> Option<Position> {
@@ -841,6 +1666,8 @@ pub fn tdata_init() -> &'static TableData {
         Severity::Info,
         &format!("Loaded TableData ({:.2}s)", load_time.as_secs_f32()),
     );
+    tdata_get().log_summary();
+    tdata_get().log_dangling_refs();
     tdata_get()
 }
 
@@ -858,13 +1685,122 @@ fn load_json(filename: &str) -> Result<Map<std::string::String, Value>, String>
     let json = serde_json::from_str(&file)
         .map_err(|e| format!("Couldn't parse {:?} as JSON: {}", path, e))?;
 
-    let Value::Object(root) = json else {
+    let Value::Object(mut root) = json else {
         return Err(format!("Malformed {:?}", path));
     };
-    // TODO patching
+
+    for patch_name in config_get().general.table_data_patches.get() {
+        let patch_path = std::path::Path::new(&tdata_path)
+            .join("patch")
+            .join(&patch_name)
+            .join(filename);
+        if !patch_path.exists() {
+            continue;
+        }
+
+        let patch_file = std::fs::read_to_string(patch_path.clone())
+            .map_err(|e| format!("Couldn't read patch file {:?}: {}", patch_path, e))?;
+        let patch: Value = serde_json::from_str(&patch_file)
+            .map_err(|e| format!("Couldn't parse patch file {:?} as JSON: {}", patch_path, e))?;
+        let Value::Object(patch) = patch else {
+            return Err(format!("Malformed patch file {:?}", patch_path));
+        };
+        patch_object(&mut root, patch, "")
+            .map_err(|e| format!("Couldn't apply patch file {:?}: {}", patch_path, e))?;
+        log(
+            Severity::Debug,
+            &format!("Applied patch {} to {}", patch_name, filename),
+        );
+    }
     Ok(root)
 }
 
+// OpenFusion-style table data patching.
+// - objects are merged recursively and scalars are replaced.
+// - a key prefixed with ! deletes that key from the base before anything else.
+//   if the value isn't null, it's then inserted as-is without merging.
+// - arrays are appended to the base array.
+// - objects with index keys (e.g. "5", "!5") patch into base arrays by index.
+fn patch_json(base: &mut Value, patch: Value, path: &str) -> Result<(), String> {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => patch_object(base, patch, path),
+        (Value::Array(base), Value::Array(patch)) => {
+            base.extend(patch);
+            Ok(())
+        }
+        (Value::Array(base), Value::Object(patch)) => patch_array(base, patch, path),
+        (base, patch) => {
+            *base = patch;
+            Ok(())
+        }
+    }
+}
+
+fn patch_object(
+    base: &mut Map<std::string::String, Value>,
+    patch: Map<std::string::String, Value>,
+    path: &str,
+) -> Result<(), String> {
+    for (key, value) in patch {
+        let key_path = format!("{}/{}", path, key);
+        if let Some(key) = key.strip_prefix('!') {
+            if base.remove(key).is_none() {
+                return Err(format!("{}: can't delete missing key", key_path));
+            }
+            if !value.is_null() {
+                base.insert(key.to_string(), value);
+            }
+        } else if let Some(base_value) = base.get_mut(&key) {
+            patch_json(base_value, value, &key_path)?;
+        } else {
+            base.insert(key, value);
+        }
+    }
+    Ok(())
+}
+
+fn patch_array(
+    base: &mut Vec<Value>,
+    patch: Map<std::string::String, Value>,
+    path: &str,
+) -> Result<(), String> {
+    let mut to_delete = Vec::new();
+    for (key, value) in patch {
+        let key_path = format!("{}/{}", path, key);
+        let (delete, idx_str) = match key.strip_prefix('!') {
+            Some(idx_str) => (true, idx_str),
+            None => (false, key.as_str()),
+        };
+        let idx: usize = idx_str
+            .parse()
+            .map_err(|_| format!("{}: array patch keys must be indices", key_path))?;
+        let Some(base_value) = base.get_mut(idx) else {
+            return Err(format!(
+                "{}: index out of bounds (length {})",
+                key_path,
+                base.len()
+            ));
+        };
+
+        if delete {
+            if value.is_null() {
+                to_delete.push(idx);
+            } else {
+                *base_value = value;
+            }
+        } else {
+            patch_json(base_value, value, &key_path)?;
+        }
+    }
+
+    // delete back-to-front so earlier indices stay valid
+    to_delete.sort_unstable();
+    for idx in to_delete.into_iter().rev() {
+        base.remove(idx);
+    }
+    Ok(())
+}
+
 fn get_object<'a>(
     root: &'a Map<std::string::String, Value>,
     key: &'static str,
@@ -962,6 +1898,43 @@ fn load_item_data(
             m_iCashAble: Option<i32>,
         }
 
+        fn get_item_use_effect(item_id: i16, data: &ItemDataEntry) -> Option<ItemUseEffect> {
+            // one gumball per combat style, in style order
+            if (ID_GUMBALL..ID_GUMBALL + SIZEOF_NANO_STYLE as i16).contains(&item_id) {
+                let style = ((item_id - ID_GUMBALL) as i32).try_into().ok()?;
+                return Some(ItemUseEffect::NanoStamina { style });
+            }
+
+            let duration = data.m_iDurationTime.filter(|d| *d > 0)?;
+            let boosts = [
+                (data.m_iUp_runSpeed, SkillType::Run, CSB_BIT_UP_MOVE_SPEED),
+                (data.m_iUp_swimSpeed, SkillType::Swim, CSB_BIT_UP_SWIM_SPEED),
+                (
+                    data.m_iUp_jumpHeight,
+                    SkillType::Jump,
+                    CSB_BIT_UP_JUMP_HEIGHT,
+                ),
+                (
+                    data.m_iUp_addFusionMatter,
+                    SkillType::RewardBlob,
+                    CSB_BIT_REWARD_BLOB,
+                ),
+                (
+                    data.m_iUp_addCandy,
+                    SkillType::RewardCash,
+                    CSB_BIT_REWARD_CASH,
+                ),
+            ];
+            let (_, skill_type, condition_bit) = boosts
+                .into_iter()
+                .find(|(amount, _, _)| amount.is_some_and(|a| a > 0))?;
+            Some(ItemUseEffect::Buff {
+                skill_type,
+                condition_bit,
+                duration: Duration::from_secs(duration as u64),
+            })
+        }
+
         let table_key = match item_type {
             ItemType::Hand => "m_pWeaponItemTable",
             ItemType::UpperBody => "m_pShirtsItemTable",
@@ -983,6 +1956,10 @@ fn load_item_data(
             let data: ItemDataEntry = serde_json::from_value(i.clone())
                 .map_err(|e| format!("Malformed item data entry ({:?}): {} {}", item_type, e, i))?;
             let key = (data.m_iItemNumber as i16, item_type);
+            let use_effect = match item_type {
+                ItemType::General => get_item_use_effect(key.0, &data),
+                _ => None,
+            };
             let data = ItemStats {
                 buy_price: data.m_iItemPrice as u32,
                 sell_price: data.m_iItemSellPrice as u32,
@@ -996,6 +1973,17 @@ fn load_item_data(
                 multi_power: data.m_iGroupRat,
                 defense: data.m_iDefenseRat,
                 speed: data.m_iUp_runSpeed,
+                style_mod_trump: data.m_fStyleMod_TrumpMonster.filter(|m| *m > 0.0),
+                style_mod_trumped: data.m_fStyleMod_Trumped.filter(|m| *m > 0.0),
+                attack_range: data.m_iAtkRange.filter(|r| *r > 0).map(|r| r as u32),
+                effect_area: data.m_iEffectArea.filter(|a| *a > 0).map(|a| a as u32),
+                target_number: data.m_iTargetNumber.filter(|n| *n > 0).map(|n| n as usize),
+                // tenths of a second, like the NPC timings
+                projectile_time: data
+                    .m_iDeliverTime
+                    .filter(|t| *t > 0)
+                    .map(|t| Duration::from_millis(t as u64 * 100)),
+                use_effect,
             };
             map.insert(key, data);
         }
@@ -1403,6 +2391,49 @@ fn load_nano_data(root: &Map<std::string::String, Value>) -> Result<NanoData, St
     })
 }
 
+fn load_skill_data(
+    root: &Map<std::string::String, Value>,
+) -> Result<HashMap<i16, SkillData>, String> {
+    const SKILL_TABLE_KEY: &str = "m_pSkillTable";
+    const SKILL_TABLE_SKILL_DATA_KEY: &str = "m_pSkillData";
+
+    // each array has one value per nano power level
+    #[derive(Deserialize)]
+    struct SkillDataEntry {
+        m_iSkillNumber: i32,
+        m_iSkillType: i32,
+        m_iTargetType: i32,
+        m_iEffectArea: i32,
+        m_iBatteryDrainUse: [i32; 4],
+        m_iDurationTime: [i32; 4],
+        // not in every table dump; no cooldown then
+        #[serde(default)]
+        m_iCoolTime: [i32; 4],
+        m_iValueA: [i32; 4],
+    }
+
+    let table = get_object(root, SKILL_TABLE_KEY)?;
+    let skill_data = get_array(table, SKILL_TABLE_SKILL_DATA_KEY)?;
+    let mut skill_table = HashMap::new();
+    for v in skill_data {
+        let entry: SkillDataEntry = serde_json::from_value(v.clone())
+            .map_err(|e| format!("Malformed skill data entry: {} {}", e, v))?;
+        // only the base power level is supported for now
+        let skill = SkillData {
+            skill_type: entry.m_iSkillType,
+            target_type: entry.m_iTargetType,
+            effect_area: entry.m_iEffectArea.max(0) as u32,
+            stamina_cost: entry.m_iBatteryDrainUse[0].max(0) as i16,
+            // durations are in tenths of a second
+            duration: Duration::from_millis(entry.m_iDurationTime[0].max(0) as u64 * 100),
+            cooldown: Duration::from_millis(entry.m_iCoolTime[0].max(0) as u64 * 100),
+            value: entry.m_iValueA[0],
+        };
+        skill_table.insert(entry.m_iSkillNumber as i16, skill);
+    }
+    Ok(skill_table)
+}
+
 fn load_mission_data(root: &Map<std::string::String, Value>) -> Result<MissionData, String> {
     const MISSION_TABLE_KEY: &str = "m_pMissionTable";
     const MISSION_TABLE_MISSION_DATA_KEY: &str = "m_pMissionData";
@@ -1441,6 +2472,7 @@ fn load_mission_data(root: &Map<std::string::String, Value>) -> Result<MissionDa
         m_iSTItemDropRate: [i16; MAX_NEED_SORT_OF_ITEM as usize],
         m_iSTNanoID: i16,
         m_iDelItemID: [i16; 4],
+        #[serde(default)]
         m_iHBarkerTextID: [i32; 4],
     }
 
@@ -1750,6 +2782,7 @@ fn load_player_data(
             req_fm_nano_create: entry.m_iReqBlob_NanoCreate,
             req_fm_nano_tune: entry.m_iReqBlob_NanoTune,
             fm_limit: entry.m_iFMLimit,
+            death_fm: entry.m_iDeathFM,
             nano_quest_task_id: match entry.m_iNanoQuestTaskID {
                 0 => None,
                 tid => Some(tid),
@@ -1784,6 +2817,7 @@ fn load_npc_data(root: &Map<std::string::String, Value>) -> Result<HashMap<i32,
         m_iRegenTime: u64,
         m_iDelayTime: u64,
         m_iAiType: u8,
+        #[serde(default)]
         m_iBarkerType: usize,
     }
 
@@ -1842,6 +2876,7 @@ fn load_npcs() -> Result<Vec<NPCSpawnData>, String> {
             iNPCType: i32,
             iOffsetX: i32,
             iOffsetY: i32,
+            iMobDropID: Option<i32>,
         }
 
         #[derive(Deserialize)]
@@ -1849,6 +2884,7 @@ fn load_npcs() -> Result<Vec<NPCSpawnData>, String> {
             aFollowers: Option<Vec<FollowerDataEntry>>,
             iAngle: i32,
             iMapNum: Option<u32>,
+            iMobDropID: Option<i32>,
             iNPCType: i32,
             iX: i32,
             iY: i32,
@@ -1870,6 +2906,7 @@ fn load_npcs() -> Result<Vec<NPCSpawnData>, String> {
                 },
                 angle: npc_data_entry.iAngle,
                 map_num: npc_data_entry.iMapNum,
+                mob_drop_id: npc_data_entry.iMobDropID,
                 followers: if let Some(followers) = npc_data_entry.aFollowers {
                     followers
                         .into_iter()
@@ -1880,6 +2917,7 @@ fn load_npcs() -> Result<Vec<NPCSpawnData>, String> {
                                 y: f.iOffsetY,
                                 z: 0,
                             },
+                            mob_drop_id: f.iMobDropID,
                         })
                         .collect()
                 } else {
@@ -1906,10 +2944,52 @@ fn load_npcs() -> Result<Vec<NPCSpawnData>, String> {
     Ok(npc_data)
 }
 
+fn roll_event_crate<R: Rng>(
+    rng: &mut R,
+    event_crates: &HashMap<i32, EventCrate>,
+    event_id: Option<i32>,
+    mob_level: i16,
+) -> Option<Item> {
+    let event_crate = event_crates.get(&event_id?)?;
+    if event_crate.MinMobLevel.is_some_and(|min| mob_level < min)
+        || event_crate.MaxMobLevel.is_some_and(|max| mob_level > max)
+    {
+        return None;
+    }
+
+    if rng.gen_range(0..event_crate.DropChanceTotal) >= event_crate.DropChance {
+        return None;
+    }
+    let crate_id = event_crate.CrateIDs[rng.gen_range(0..event_crate.CrateIDs.len())];
+    Some(Item::new(ItemType::Chest, crate_id as i16))
+}
+
+// Rolls a rarity (starting at 1) from a C.R.A.T.E.'s rarity weights, returning it and whether
+// it was the rarest one the crate can give. Each point of pity adds `pity_rate` of the
+// total weight to that rarest rarity.
+fn roll_crate_rarity<R: Rng>(
+    rng: &mut R,
+    weights: &[i32],
+    pity: u32,
+    pity_rate: f32,
+) -> (i32, bool) {
+    let Some(rarest_idx) = weights.iter().rposition(|weight| *weight > 0) else {
+        return (util::weighted_rand_with(rng, weights) as i32 + 1, false);
+    };
+
+    let mut weights = weights.to_vec();
+    let total: i32 = weights.iter().sum();
+    let bonus = (total as f32 * pity_rate.max(0.0) * pity as f32) as i32;
+    weights[rarest_idx] += bonus.min(i32::MAX - total);
+    let idx = util::weighted_rand_with(rng, &weights);
+    (idx as i32 + 1, idx == rarest_idx)
+}
+
 fn load_drop_data() -> Result<DropData, String> {
     const CRATE_DROP_CHANCES_TABLE_KEY: &str = "CrateDropChances";
     const CRATE_DROP_TYPES_TABLE_KEY: &str = "CrateDropTypes";
     const CRATE_DATA_TABLE_KEY: &str = "Crates";
+    const EVENT_CRATES_TABLE_KEY: &str = "EventCrates";
     const MISC_DROP_CHANCES_TABLE_KEY: &str = "MiscDropChances";
     const MISC_DROP_TYPES_TABLE_KEY: &str = "MiscDropTypes";
     const MOB_DROPS_TABLE_KEY: &str = "MobDrops";
@@ -1921,6 +3001,7 @@ fn load_drop_data() -> Result<DropData, String> {
     const CRATE_DROP_CHANCES_ID_KEY: &str = "CrateDropChanceID";
     const CRATE_DROP_TYPES_ID_KEY: &str = "CrateDropTypeID";
     const CRATE_DATA_ID_KEY: &str = "CrateID";
+    const EVENT_CRATES_ID_KEY: &str = "EventID";
     const MISC_DROP_CHANCES_ID_KEY: &str = "MiscDropChanceID";
     const MISC_DROP_TYPES_ID_KEY: &str = "MiscDropTypeID";
     const MOB_DROP_ID_KEY: &str = "MobDropID";
@@ -1952,6 +3033,19 @@ fn load_drop_data() -> Result<DropData, String> {
     let crate_drop_chances_table = get_object(&drop_root, CRATE_DROP_CHANCES_TABLE_KEY)?;
     let crate_drop_types_table = get_object(&drop_root, CRATE_DROP_TYPES_TABLE_KEY)?;
     let crate_data_table = get_object(&drop_root, CRATE_DATA_TABLE_KEY)?;
+    // no events unless configured
+    let event_crates: HashMap<i32, EventCrate> = match drop_root.get(EVENT_CRATES_TABLE_KEY) {
+        Some(_) => load_drop_table(
+            get_object(&drop_root, EVENT_CRATES_TABLE_KEY)?,
+            EVENT_CRATES_ID_KEY,
+        )?,
+        None => HashMap::new(),
+    };
+    for (event_id, event_crate) in &event_crates {
+        if event_crate.DropChanceTotal <= 0 || event_crate.CrateIDs.is_empty() {
+            return Err(format!("Event {} has no crates to drop", event_id));
+        }
+    }
 
     let misc_drop_chances_table = get_object(&drop_root, MISC_DROP_CHANCES_TABLE_KEY)?;
     let misc_drop_types_table = get_object(&drop_root, MISC_DROP_TYPES_TABLE_KEY)?;
@@ -1966,6 +3060,7 @@ fn load_drop_data() -> Result<DropData, String> {
     Ok(DropData {
         crate_drop_chances: load_drop_table(crate_drop_chances_table, CRATE_DROP_CHANCES_ID_KEY)?,
         crate_drop_types: load_drop_table(crate_drop_types_table, CRATE_DROP_TYPES_ID_KEY)?,
+        event_crates,
         crate_data: load_drop_table(crate_data_table, CRATE_DATA_ID_KEY)?,
 
         misc_drop_chances: load_drop_table(misc_drop_chances_table, MISC_DROP_CHANCES_ID_KEY)?,
@@ -2057,6 +3152,33 @@ fn load_egg_data() -> Result<EggData, String> {
     })
 }
 
+// ai.json is our own addition rather than part of the client's data, so it's fine for it to be missing
+fn load_ai_params() -> Result<AIParamsData, String> {
+    const AI_PARAMS_FILENAME: &str = "ai.json";
+
+    let tdata_path = config_get().general.table_data_path.get();
+    if !std::path::Path::new(&tdata_path)
+        .join(AI_PARAMS_FILENAME)
+        .exists()
+    {
+        return Ok(AIParamsData::default());
+    }
+    AIParamsData::load(&load_json(AI_PARAMS_FILENAME)?)
+}
+
+fn load_npc_remap() -> Result<Option<NpcRemap>, String> {
+    const NPC_REMAP_FILENAME: &str = "npc_remap.json";
+
+    let tdata_path = config_get().general.table_data_path.get();
+    if !std::path::Path::new(&tdata_path)
+        .join(NPC_REMAP_FILENAME)
+        .exists()
+    {
+        return Ok(None);
+    }
+    NpcRemap::load(&load_json(NPC_REMAP_FILENAME)?).map(Some)
+}
+
 fn load_path_data() -> Result<PathData, String> {
     #[derive(Deserialize)]
     struct PathPointEntry {
@@ -2197,3 +3319,1132 @@ fn load_path_data() -> Result<PathData, String> {
         npc_paths: load_npc_paths(&paths_root)?,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+    use crate::rng::GameRng;
+
+    fn patched(base: Value, patch: Value) -> Result<Value, String> {
+        let mut base = base;
+        patch_json(&mut base, patch, "").map(|_| base)
+    }
+
+    #[test]
+    fn test_patch_scalars() {
+        let base = json!({ "a": 1, "b": "two", "c": true });
+        let patch = json!({ "a": 5, "c": null, "d": 1.5 });
+        assert_eq!(
+            patched(base, patch).unwrap(),
+            json!({ "a": 5, "b": "two", "c": null, "d": 1.5 })
+        );
+    }
+
+    #[test]
+    fn test_patch_nested_objects() {
+        let base = json!({
+            "npcs": {
+                "1": { "type": 10, "pos": { "x": 1, "y": 2, "z": 3 } },
+                "2": { "type": 20, "pos": { "x": 4, "y": 5, "z": 6 } },
+            }
+        });
+        let patch = json!({
+            "npcs": {
+                "1": { "pos": { "z": 300 } },
+                "3": { "type": 30 },
+            }
+        });
+        assert_eq!(
+            patched(base, patch).unwrap(),
+            json!({
+                "npcs": {
+                    "1": { "type": 10, "pos": { "x": 1, "y": 2, "z": 300 } },
+                    "2": { "type": 20, "pos": { "x": 4, "y": 5, "z": 6 } },
+                    "3": { "type": 30 },
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_patch_deletions() {
+        let base = json!({
+            "npcs": {
+                "1": { "type": 10, "pos": { "x": 1, "y": 2 } },
+                "2": { "type": 20 },
+            }
+        });
+        let patch = json!({
+            "npcs": {
+                "!2": null,
+                "!1": { "type": 11 },
+            }
+        });
+        assert_eq!(
+            patched(base, patch).unwrap(),
+            json!({ "npcs": { "1": { "type": 11 } } })
+        );
+
+        // deleting a missing key is an error naming the key path
+        let base = json!({ "npcs": { "1": {} } });
+        let patch = json!({ "npcs": { "!7": null } });
+        let err = patched(base, patch).unwrap_err();
+        assert!(err.contains("/npcs/!7"), "{}", err);
+    }
+
+    #[test]
+    fn test_patch_arrays() {
+        // plain arrays are appended to
+        let base = json!({ "points": [1, 2] });
+        let patch = json!({ "points": [3] });
+        assert_eq!(
+            patched(base, patch).unwrap(),
+            json!({ "points": [1, 2, 3] })
+        );
+
+        // replacing a scalar with an array just replaces
+        let base = json!({ "points": 0 });
+        let patch = json!({ "points": [3] });
+        assert_eq!(patched(base, patch).unwrap(), json!({ "points": [3] }));
+    }
+
+    #[test]
+    fn test_patch_keyed_arrays() {
+        let base = json!({
+            "items": [
+                { "id": 0, "price": 100, "stats": { "power": 1 } },
+                { "id": 1, "price": 200, "stats": { "power": 2 } },
+                { "id": 2, "price": 300, "stats": { "power": 3 } },
+                { "id": 3, "price": 400, "stats": { "power": 4 } },
+            ]
+        });
+        let patch = json!({
+            "items": {
+                "1": { "price": 250, "stats": { "range": 5 } },
+                "!2": { "id": 2, "price": 1 },
+                "!0": null,
+                "!3": null,
+            }
+        });
+        assert_eq!(
+            patched(base, patch).unwrap(),
+            json!({
+                "items": [
+                    { "id": 1, "price": 250, "stats": { "power": 2, "range": 5 } },
+                    { "id": 2, "price": 1 },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_patch_keyed_array_errors() {
+        let base = json!({ "items": [{ "id": 0 }] });
+        let patch = json!({ "items": { "4": { "id": 4 } } });
+        let err = patched(base, patch).unwrap_err();
+        assert!(err.contains("/items/4"), "{}", err);
+
+        let base = json!({ "items": [{ "id": 0 }] });
+        let patch = json!({ "items": { "first": { "id": 4 } } });
+        let err = patched(base, patch).unwrap_err();
+        assert!(err.contains("/items/first"), "{}", err);
+    }
+
+    fn as_root(value: Value) -> Map<std::string::String, Value> {
+        let Value::Object(root) = value else {
+            panic!("Fixture is not an object");
+        };
+        root
+    }
+
+    fn load_test_table<T: Default>(
+        fixture: Value,
+        loader: fn(&Map<std::string::String, Value>) -> Result<T, String>,
+    ) -> (T, Vec<&'static str>) {
+        let mut disabled_features = Vec::new();
+        let table = load_optional_table(
+            &as_root(fixture),
+            "test table",
+            "testing",
+            loader,
+            &mut disabled_features,
+        );
+        (table, disabled_features)
+    }
+
+    #[test]
+    fn test_optional_crocpot_data() {
+        let entry = json!({
+            "m_iLevelGap": 1,
+            "m_fSameGrade": 100.0,
+            "m_fOneGrade": 50.0,
+            "m_fTwoGrade": 25.0,
+            "m_fThreeGrade": 10.0,
+            "m_fLevelGapStandard": 80.0,
+            "m_iLookConstant": 2,
+            "m_iStatConstant": 3,
+        });
+        let (crocpot_data, disabled) = load_test_table(
+            json!({ "m_pCombiningTable": { "m_pCombiningData": [entry] } }),
+            load_crocpot_data,
+        );
+        assert_eq!(crocpot_data.len(), 1);
+        assert!(disabled.is_empty());
+
+        for fixture in [
+            json!({}),
+            json!({ "m_pCombiningTable": {} }),
+            json!({ "m_pCombiningTable": { "m_pCombiningData": [{ "m_iLevelGap": 1 }] } }),
+        ] {
+            let (crocpot_data, disabled) = load_test_table(fixture, load_crocpot_data);
+            assert!(crocpot_data.is_empty());
+            assert_eq!(disabled, vec!["testing"]);
+        }
+    }
+
+    #[test]
+    fn test_optional_skill_data() {
+        let entry = json!({
+            "m_iSkillNumber": 5,
+            "m_iSkillType": 11,
+            "m_iTargetType": 3,
+            "m_iEffectArea": 800,
+            "m_iBatteryDrainUse": [30, 25, 20, 15],
+            "m_iDurationTime": [150, 200, 250, 300],
+            "m_iValueA": [0, 0, 0, 0],
+        });
+        let (skill_data, disabled) = load_test_table(
+            json!({ "m_pSkillTable": { "m_pSkillData": [entry] } }),
+            load_skill_data,
+        );
+        assert!(disabled.is_empty());
+        let skill = &skill_data[&5];
+        assert_eq!(skill.stamina_cost, 30);
+        assert_eq!(skill.duration, Duration::from_secs(15));
+
+        for fixture in [
+            json!({}),
+            json!({ "m_pSkillTable": {} }),
+            json!({ "m_pSkillTable": { "m_pSkillData": [{ "m_iSkillNumber": 5 }] } }),
+        ] {
+            let (skill_data, disabled) = load_test_table(fixture, load_skill_data);
+            assert!(skill_data.is_empty());
+            assert_eq!(disabled, vec!["testing"]);
+        }
+    }
+
+    #[test]
+    fn test_optional_respawn_data() {
+        let entry = json!({ "m_iXpos": 1, "m_iYpos": 2, "m_iZpos": 3, "m_iZone": 0 });
+        let (respawn_data, disabled) = load_test_table(
+            json!({ "m_pXComTable": { "m_pXComData": [entry] } }),
+            load_respawn_data,
+        );
+        assert_eq!(respawn_data.len(), 1);
+        assert!(disabled.is_empty());
+
+        for fixture in [
+            json!({}),
+            json!({ "m_pXComTable": {} }),
+            json!({ "m_pXComTable": { "m_pXComData": [{ "m_iXpos": 1 }] } }),
+        ] {
+            let (respawn_data, disabled) = load_test_table(fixture, load_respawn_data);
+            assert!(respawn_data.is_empty());
+            assert_eq!(disabled, vec!["testing"]);
+        }
+    }
+
+    #[test]
+    fn test_optional_barker_data() {
+        let mut entry = json!({
+            "m_iNpcNumber": 7,
+            "m_iTeam": CombatantTeam::Mob as i32,
+            "m_iNpcLevel": 1,
+            "m_iHP": 100,
+            "m_iPower": 1,
+            "m_iProtection": 1,
+            "m_iNpcStyle": CombatStyle::Adaptium as i32,
+            "m_iRadius": 1,
+            "m_iWalkSpeed": 1,
+            "m_iRunSpeed": 1,
+            "m_iSightRange": 1,
+            "m_iIdleRange": 1,
+            "m_iCombatRange": 1,
+            "m_iAtkRange": 1,
+            "m_iRegenTime": 1,
+            "m_iDelayTime": 1,
+            "m_iAiType": 1,
+        });
+        let root = as_root(json!({ "m_pNpcTable": { "m_pNpcData": [entry.clone()] } }));
+        let npc_data = load_npc_data(&root).unwrap();
+        assert_eq!(npc_data[&7].bark_type, None);
+
+        entry["m_iBarkerType"] = json!(2);
+        let root = as_root(json!({ "m_pNpcTable": { "m_pNpcData": [entry] } }));
+        let npc_data = load_npc_data(&root).unwrap();
+        assert_eq!(npc_data[&7].bark_type, Some(2));
+
+        // required tables still hard-fail
+        assert!(load_npc_data(&as_root(json!({}))).is_err());
+    }
+
+    #[test]
+    fn test_ai_params() {
+        let ai_params = AIParamsData::load(&as_root(json!({}))).unwrap();
+        assert_eq!(*ai_params.get(1), AIParams::default());
+
+        let ai_params = AIParamsData::load(&as_root(json!({
+            "Global": { "aggro_rate_up": 3.0, "level_factor": 0.2 },
+            "AITypes": { "2": { "aggro_rate_up": 0.5 } },
+        })))
+        .unwrap();
+        assert_eq!(ai_params.get(1).aggro_rate_up, 3.0);
+        // overridden per type, but still layered on the global values
+        assert_eq!(ai_params.get(2).aggro_rate_up, 0.5);
+        assert_eq!(ai_params.get(2).level_factor, 0.2);
+        assert_eq!(ai_params.get(2).scan_radius_mult, 1.0);
+
+        for fixture in [
+            json!({ "Global": { "aggro_rate_down": -1.0 } }),
+            json!({ "AITypes": { "2": { "scan_radius_mult": -0.5 } } }),
+            json!({ "Global": { "aggro_rate": 1.0 } }),
+            json!({ "AITypes": { "two": {} } }),
+            json!({ "AITypes": { "2": 1.0 } }),
+        ] {
+            assert!(AIParamsData::load(&as_root(fixture)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_xdt_version() {
+        assert_eq!(get_xdt_version(&as_root(json!({}))), None);
+        assert_eq!(
+            get_xdt_version(&as_root(json!({ "version": "beta-20100104" }))),
+            Some("beta-20100104".to_string())
+        );
+        assert_eq!(
+            get_xdt_version(&as_root(json!({ "m_strVersion": 1013 }))),
+            Some("1013".to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_skyway_route() {
+        let routes = HashMap::from([
+            (1, vec![(2, 12), (3, 13)]),
+            (2, vec![(4, 24)]),
+            (3, vec![(4, 34), (5, 35)]),
+            (5, vec![(1, 51)]),
+        ]);
+        assert_eq!(find_skyway_route(&routes, 1, 2), Some(vec![(2, 12)]));
+        assert_eq!(
+            find_skyway_route(&routes, 1, 5),
+            Some(vec![(3, 13), (5, 35)])
+        );
+        assert_eq!(
+            find_skyway_route(&routes, 5, 4).map(|hops| hops.len()),
+            Some(3)
+        );
+        assert_eq!(find_skyway_route(&routes, 4, 1), None);
+        assert_eq!(find_skyway_route(&routes, 1, 6), None);
+    }
+
+    #[test]
+    fn test_event_crate_roll() {
+        let event_crates = HashMap::from([(
+            2,
+            EventCrate {
+                DropChance: 1,
+                DropChanceTotal: 2,
+                CrateIDs: vec![100, 101],
+                MinMobLevel: None,
+                MaxMobLevel: None,
+            },
+        )]);
+        let mut rng = StdRng::seed_from_u64(3);
+        let drops: Vec<Item> = (0..1000)
+            .filter_map(|_| roll_event_crate(&mut rng, &event_crates, Some(2), 10))
+            .collect();
+        assert!(drops.len() > 400 && drops.len() < 600);
+        assert!(drops
+            .iter()
+            .all(|item| item.ty == ItemType::Chest && (item.id == 100 || item.id == 101)));
+        assert!(drops.iter().any(|item| item.id == 100));
+        assert!(drops.iter().any(|item| item.id == 101));
+
+        // same seed, same drops
+        let mut rng = StdRng::seed_from_u64(3);
+        let replayed: Vec<Item> = (0..1000)
+            .filter_map(|_| roll_event_crate(&mut rng, &event_crates, Some(2), 10))
+            .collect();
+        assert_eq!(drops, replayed);
+
+        // nothing extra drops outside of a known event
+        for _ in 0..100 {
+            assert_eq!(roll_event_crate(&mut rng, &event_crates, None, 10), None);
+            assert_eq!(roll_event_crate(&mut rng, &event_crates, Some(3), 10), None);
+        }
+    }
+
+    #[test]
+    fn test_event_crate_level_gating() {
+        let event_crates = HashMap::from([(
+            2,
+            EventCrate {
+                DropChance: 1,
+                DropChanceTotal: 1,
+                CrateIDs: vec![100],
+                MinMobLevel: Some(5),
+                MaxMobLevel: Some(10),
+            },
+        )]);
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(roll_event_crate(&mut rng, &event_crates, Some(2), 4), None);
+        assert!(roll_event_crate(&mut rng, &event_crates, Some(2), 5).is_some());
+        assert!(roll_event_crate(&mut rng, &event_crates, Some(2), 10).is_some());
+        assert_eq!(roll_event_crate(&mut rng, &event_crates, Some(2), 11), None);
+    }
+
+    #[test]
+    fn test_event_crate_entry() {
+        let entry = json!({
+            "EventID": 5,
+            "DropChance": 1,
+            "DropChanceTotal": 1,
+            "CrateIDs": [101],
+            "MinMobLevel": 20
+        });
+        let event_crate: EventCrate = serde_json::from_value(entry).unwrap();
+        assert_eq!(event_crate.MinMobLevel, Some(20));
+        assert_eq!(event_crate.MaxMobLevel, None);
+    }
+
+    fn spawn(npc_type: i32, group_id: Option<i32>) -> NPCSpawnData {
+        NPCSpawnData {
+            group_id,
+            npc_type,
+            pos: Position::default(),
+            angle: 0,
+            map_num: None,
+            mob_drop_id: None,
+            followers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_spawns_for_channel() {
+        // types 1xx are mobs, the rest are friendly
+        let spawns = vec![
+            spawn(1, None),
+            spawn(101, None),
+            spawn(102, Some(1)),
+            spawn(2, Some(2)),
+            spawn(103, Some(2)),
+        ];
+        let is_mob = |npc_type: i32| npc_type > 100;
+        let spawned_types = |template: Option<&ChannelTemplate>| {
+            get_spawns_for_channel(&spawns, template, is_mob)
+                .iter()
+                .map(|dat| dat.npc_type)
+                .collect::<Vec<_>>()
+        };
+        let template = |no_mobs, npc_group_whitelist, spawn_multiplier| ChannelTemplate {
+            channel_num: 2,
+            no_mobs,
+            npc_group_whitelist,
+            spawn_multiplier,
+        };
+
+        assert_eq!(spawned_types(None), vec![1, 101, 102, 2, 103]);
+        assert_eq!(
+            spawned_types(Some(&template(false, None, 1.0))),
+            vec![1, 101, 102, 2, 103]
+        );
+        assert_eq!(spawned_types(Some(&template(true, None, 1.0))), vec![1, 2]);
+        assert_eq!(
+            spawned_types(Some(&template(false, Some(vec![2]), 1.0))),
+            vec![1, 101, 2, 103]
+        );
+        assert_eq!(
+            spawned_types(Some(&template(false, None, 2.0))),
+            vec![1, 101, 101, 102, 102, 2, 103, 103]
+        );
+        // every other mob spawn is dropped
+        assert_eq!(
+            spawned_types(Some(&template(false, None, 0.5))),
+            vec![1, 102, 2]
+        );
+    }
+
+    fn empty_table_data() -> TableData {
+        TableData {
+            xdt_data: XDTData {
+                vendor_data: HashMap::new(),
+                item_data: HashMap::new(),
+                crocpot_data: HashMap::new(),
+                transportation_data: TransportationData {
+                    trip_data: HashMap::new(),
+                    scamper_data: HashMap::new(),
+                    monkey_skyway_data: HashMap::new(),
+                },
+                instance_data: InstanceData {
+                    warp_data: HashMap::new(),
+                    map_data: HashMap::new(),
+                },
+                nano_data: NanoData {
+                    nano_stats: HashMap::new(),
+                    nano_tunings: HashMap::new(),
+                },
+                skill_data: HashMap::new(),
+                mission_data: MissionData {
+                    mission_definitions: HashMap::new(),
+                    task_definitions: HashMap::new(),
+                    rewards: HashMap::new(),
+                },
+                respawn_data: Vec::new(),
+                player_data: HashMap::new(),
+                npc_data: HashMap::new(),
+                version: None,
+                disabled_features: Vec::new(),
+            },
+            npcs: Vec::new(),
+            drop_data: DropData {
+                crate_drop_chances: HashMap::new(),
+                crate_drop_types: HashMap::new(),
+                event_crates: HashMap::new(),
+                crate_data: HashMap::new(),
+                misc_drop_chances: HashMap::new(),
+                misc_drop_types: HashMap::new(),
+                mob_drops: HashMap::new(),
+                mob_drop_data: HashMap::new(),
+                rarity_weights: HashMap::new(),
+                item_sets: HashMap::new(),
+                item_refs: HashMap::new(),
+            },
+            path_data: PathData {
+                skyway_paths: HashMap::new(),
+                slider_path: Path::new(Vec::new(), true),
+                npc_paths: HashMap::new(),
+            },
+            egg_data: EggData {
+                egg_stats: HashMap::new(),
+                eggs: Vec::new(),
+            },
+            ai_params: AIParamsData::default(),
+            skyway_routes: HashMap::new(),
+        }
+    }
+
+    fn item_stats() -> ItemStats {
+        ItemStats {
+            buy_price: 0,
+            sell_price: 0,
+            sellable: true,
+            tradeable: true,
+            max_stack_size: 1,
+            required_level: 1,
+            rarity: None,
+            gender: None,
+            single_power: None,
+            multi_power: None,
+            defense: None,
+            speed: None,
+            style_mod_trump: None,
+            style_mod_trumped: None,
+            attack_range: None,
+            effect_area: None,
+            target_number: None,
+            projectile_time: None,
+            use_effect: None,
+        }
+    }
+
+    fn npc_stats() -> NPCStats {
+        NPCStats {
+            team: CombatantTeam::Friendly,
+            style: CombatStyle::Adaptium,
+            level: 1,
+            max_hp: 100,
+            power: 1,
+            defense: 1,
+            radius: 1,
+            walk_speed: 1,
+            run_speed: 1,
+            sight_range: 1,
+            idle_range: 1,
+            combat_range: 1,
+            attack_range: 1,
+            regen_time: 1,
+            delay_time: 1,
+            ai_type: 1,
+            bark_type: None,
+        }
+    }
+
+    fn trip(start_location: i32, end_location: i32, route_number: i32) -> TripData {
+        TripData {
+            npc_id: 1,
+            transportation_type: TransportationType::Wyvern,
+            start_location,
+            end_location,
+            cost: 0,
+            speed: 1,
+            route_number,
+        }
+    }
+
+    fn warp(map_num: u32) -> WarpData {
+        WarpData {
+            pos: Position::default(),
+            npc_type: 1,
+            is_instance: true,
+            is_group_warp: false,
+            map_num,
+            min_level: 1,
+            req_task: None,
+            req_item: None,
+            req_item_consumed: None,
+            cost: 0,
+        }
+    }
+
+    #[test]
+    fn test_dangling_refs_clean() {
+        let mut tdata = empty_table_data();
+        assert!(tdata.find_dangling_refs().is_empty());
+
+        let xdt_data = &mut tdata.xdt_data;
+        xdt_data
+            .item_data
+            .insert((10, ItemType::Hand), item_stats());
+        let mut vendor = VendorData::new(1);
+        vendor.insert(VendorItem {
+            sort_number: 0,
+            ty: ItemType::Hand,
+            id: 10,
+        });
+        xdt_data.vendor_data.insert(1, vendor);
+        let mut reward = Reward::new(RewardCategory::Missions);
+        reward.items.push(Item::new(ItemType::Hand, 10));
+        xdt_data.mission_data.rewards.insert(5, reward);
+        let mut first = TaskDefinition::new_mock(100, 1);
+        first.succ_task_id = Some(101);
+        first.succ_reward = Some(5);
+        let mut second = TaskDefinition::new_mock(101, 1);
+        second.fail_task_id = Some(100);
+        xdt_data.mission_data.task_definitions.insert(100, first);
+        xdt_data.mission_data.task_definitions.insert(101, second);
+        xdt_data.instance_data.map_data.insert(
+            7,
+            MapData {
+                ep_id: None,
+                map_square: (0, 0),
+            },
+        );
+        xdt_data.instance_data.warp_data.insert(1, warp(7));
+        xdt_data
+            .instance_data
+            .warp_data
+            .insert(2, warp(ID_OVERWORLD));
+        xdt_data.npc_data.insert(3, npc_stats());
+        xdt_data
+            .transportation_data
+            .trip_data
+            .insert(1, trip(1, 2, 12));
+        xdt_data
+            .transportation_data
+            .trip_data
+            .insert(2, trip(1, 3, 99));
+        tdata.npcs.push(NPCSpawnData {
+            group_id: None,
+            npc_type: 3,
+            pos: Position::default(),
+            angle: 0,
+            map_num: None,
+            mob_drop_id: None,
+            followers: vec![FollowerData {
+                npc_type: 3,
+                offset: Position::default(),
+                mob_drop_id: None,
+            }],
+        });
+        tdata
+            .path_data
+            .npc_paths
+            .insert(3, Path::new(Vec::new(), true));
+        tdata
+            .path_data
+            .skyway_paths
+            .insert(12, Path::new(Vec::new(), false));
+        // trip 2 has no path of its own, but can chain through other routes
+        tdata.skyway_routes = HashMap::from([(1, vec![(2, 12)]), (2, vec![(3, 23)])]);
+        tdata.egg_data.egg_stats.insert(
+            4,
+            EggStats {
+                crate_id: None,
+                effect_id: None,
+                effect_duration: Duration::ZERO,
+                respawn_time: Duration::ZERO,
+            },
+        );
+        tdata.egg_data.eggs.push(EggSpawnData {
+            egg_type: 4,
+            pos: Position::default(),
+            map_num: None,
+        });
+        assert!(tdata.find_dangling_refs().is_empty());
+    }
+
+    #[test]
+    fn test_dangling_refs_reported() {
+        let mut tdata = empty_table_data();
+        let xdt_data = &mut tdata.xdt_data;
+        let mut vendor = VendorData::new(1);
+        vendor.insert(VendorItem {
+            sort_number: 0,
+            ty: ItemType::Hand,
+            id: 10,
+        });
+        xdt_data.vendor_data.insert(1, vendor);
+        let mut reward = Reward::new(RewardCategory::Missions);
+        reward.items.push(Item::new(ItemType::Head, 11));
+        xdt_data.mission_data.rewards.insert(5, reward);
+        let mut task_def = TaskDefinition::new_mock(100, 1);
+        task_def.succ_task_id = Some(101);
+        task_def.fail_task_id = Some(102);
+        task_def.succ_reward = Some(6);
+        task_def.obj_enemies.insert(9, 1);
+        task_def.obj_enemy_id_ordering.push(9);
+        xdt_data.mission_data.task_definitions.insert(100, task_def);
+        xdt_data.instance_data.warp_data.insert(1, warp(7));
+        xdt_data
+            .transportation_data
+            .trip_data
+            .insert(1, trip(1, 2, 12));
+        tdata.npcs.push(NPCSpawnData {
+            group_id: None,
+            npc_type: 3,
+            pos: Position::default(),
+            angle: 0,
+            map_num: None,
+            mob_drop_id: None,
+            followers: Vec::new(),
+        });
+        tdata
+            .path_data
+            .npc_paths
+            .insert(8, Path::new(Vec::new(), true));
+        tdata.egg_data.eggs.push(EggSpawnData {
+            egg_type: 4,
+            pos: Position::default(),
+            map_num: None,
+        });
+
+        let dangling_refs = tdata.find_dangling_refs();
+        let kinds: Vec<&str> = dangling_refs.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "vendor items",
+                "mission reward items",
+                "mission rewards",
+                "follow-up tasks",
+                "warp maps",
+                "NPC types",
+                "egg types",
+                "Skyway routes",
+            ]
+        );
+        let counts: Vec<usize> = dangling_refs.iter().map(|(_, refs)| refs.len()).collect();
+        assert_eq!(counts, vec![1, 1, 1, 2, 1, 3, 1, 1]);
+        assert_eq!(
+            dangling_refs[3].1,
+            vec![
+                "Task 100 moves to task 101 on success".to_string(),
+                "Task 100 moves to task 102 on failure".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crate_item_pools_checked() {
+        let mut tdata = empty_table_data();
+        // (item ID, rarity, gender)
+        for (id, rarity, gender) in [(10, 1, 1), (11, 1, 2), (12, 2, 0)] {
+            let mut stats = item_stats();
+            stats.rarity = Some(rarity);
+            stats.gender = Some(gender);
+            tdata.xdt_data.item_data.insert((id, ItemType::Hand), stats);
+            tdata.drop_data.item_refs.insert(
+                id as i32,
+                ItemReference {
+                    ItemID: id as i32,
+                    Type: ItemType::Hand as i32,
+                },
+            );
+        }
+        let item_set = |ref_ids: Vec<i32>| ItemSet {
+            IgnoreRarity: false,
+            IgnoreGender: false,
+            DefaultItemWeight: 1,
+            AlterRarityMap: HashMap::new(),
+            AlterGenderMap: HashMap::new(),
+            AlterItemWeightMap: HashMap::new(),
+            ItemReferenceIDs: ref_ids,
+        };
+        let drop_data = &mut tdata.drop_data;
+        drop_data.item_sets.insert(1, item_set(vec![10, 11, 12]));
+        drop_data.item_sets.insert(2, item_set(vec![10, 12]));
+        drop_data.rarity_weights.insert(
+            1,
+            RarityWeights {
+                Weights: vec![5, 1],
+            },
+        );
+        // rarity 2 can't come out of this one
+        drop_data.rarity_weights.insert(
+            2,
+            RarityWeights {
+                Weights: vec![5, 0],
+            },
+        );
+        let crate_data = |item_set_id, rarity_weight_id| CrateData {
+            ItemSetID: item_set_id,
+            RarityWeightID: rarity_weight_id,
+        };
+        drop_data.crate_data.insert(100, crate_data(1, 1));
+        drop_data.crate_data.insert(101, crate_data(1, 2));
+        assert!(tdata.find_dangling_refs().is_empty());
+
+        tdata.drop_data.crate_data.insert(102, crate_data(2, 1));
+        tdata.drop_data.crate_data.insert(103, crate_data(3, 1));
+        let dangling_refs = tdata.find_dangling_refs();
+        assert_eq!(dangling_refs.len(), 1);
+        assert_eq!(dangling_refs[0].0, "C.R.A.T.E. item pools");
+        assert_eq!(
+            dangling_refs[0].1,
+            vec![
+                "C.R.A.T.E. 102 has no rarity 1 items for gender 2".to_string(),
+                "C.R.A.T.E. 103: No item set with id 3".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crate_pity() {
+        const ROLLS: usize = 20_000;
+        const WEIGHTS: [i32; 3] = [90, 9, 1];
+
+        // longest run without the rarest rarity, and how often it came up
+        let run = |pity_rate: f32| {
+            let mut rng = StdRng::seed_from_u64(11);
+            let mut pity = 0;
+            let mut longest_drought = 0;
+            let mut rare_count = 0;
+            for _ in 0..ROLLS {
+                let (rarity, rarest) = roll_crate_rarity(&mut rng, &WEIGHTS, pity, pity_rate);
+                assert!((1..=3).contains(&rarity));
+                assert_eq!(rarest, rarity == 3);
+                if rarest {
+                    rare_count += 1;
+                    pity = 0;
+                } else {
+                    pity += 1;
+                    longest_drought = longest_drought.max(pity);
+                }
+            }
+            (longest_drought, rare_count)
+        };
+
+        let (no_pity_drought, no_pity_count) = run(0.0);
+        let (pity_drought, pity_count) = run(0.05);
+        assert!(no_pity_count > ROLLS / 100 && no_pity_count < ROLLS / 25);
+        assert!(pity_count > no_pity_count * 2);
+        assert!(pity_drought < no_pity_drought);
+        // by 20 misses the rarest rarity already outweighs everything else
+        assert!(pity_drought < 40);
+
+        // a crate with only one possible rarity always rolls its rarest
+        let mut rng = StdRng::seed_from_u64(11);
+        assert_eq!(roll_crate_rarity(&mut rng, &[0, 5], 100, 1.0), (2, true));
+        assert!(!roll_crate_rarity(&mut rng, &[0, 0, 0], 0, 0.0).1);
+    }
+
+    #[test]
+    fn test_seeded_rewards() {
+        const ROLLS: usize = 200;
+
+        let mut tdata = empty_table_data();
+        // (item ID, rarity)
+        for (id, rarity) in [(10, 1), (11, 1), (12, 2)] {
+            let mut stats = item_stats();
+            stats.rarity = Some(rarity);
+            stats.gender = Some(0);
+            tdata.xdt_data.item_data.insert((id, ItemType::Hand), stats);
+            tdata.drop_data.item_refs.insert(
+                id as i32,
+                ItemReference {
+                    ItemID: id as i32,
+                    Type: ItemType::Hand as i32,
+                },
+            );
+        }
+        let drop_data = &mut tdata.drop_data;
+        drop_data.item_sets.insert(
+            1,
+            ItemSet {
+                IgnoreRarity: false,
+                IgnoreGender: false,
+                DefaultItemWeight: 1,
+                AlterRarityMap: HashMap::new(),
+                AlterGenderMap: HashMap::new(),
+                AlterItemWeightMap: HashMap::new(),
+                ItemReferenceIDs: vec![10, 11, 12],
+            },
+        );
+        drop_data.rarity_weights.insert(
+            1,
+            RarityWeights {
+                Weights: vec![3, 1],
+            },
+        );
+        drop_data.crate_data.insert(
+            100,
+            CrateData {
+                ItemSetID: 1,
+                RarityWeightID: 1,
+            },
+        );
+        drop_data.crate_drop_chances.insert(
+            1,
+            CrateDropChance {
+                DropChance: 1,
+                DropChanceTotal: 2,
+                CrateTypeDropWeights: vec![1, 1],
+            },
+        );
+        drop_data.crate_drop_types.insert(
+            1,
+            CrateDropType {
+                CrateIDs: vec![100, 101],
+            },
+        );
+        drop_data.misc_drop_chances.insert(
+            1,
+            MiscDropChance {
+                PotionDropChance: 1,
+                PotionDropChanceTotal: 2,
+                BoostDropChance: 1,
+                BoostDropChanceTotal: 2,
+                TaroDropChance: 1,
+                TaroDropChanceTotal: 2,
+                FMDropChance: 1,
+                FMDropChanceTotal: 2,
+            },
+        );
+        drop_data.misc_drop_types.insert(
+            1,
+            MiscDropType {
+                PotionAmount: 1,
+                BoostAmount: 2,
+                TaroAmount: 30,
+                FMAmount: 40,
+            },
+        );
+        drop_data.mob_drops.insert(
+            5,
+            MobDrop {
+                CrateDropChanceID: 1,
+                CrateDropTypeID: 1,
+                MiscDropChanceID: 1,
+                MiscDropTypeID: 1,
+            },
+        );
+        drop_data
+            .mob_drop_data
+            .insert(1, MobDropData { MobDropID: 5 });
+
+        // a mob kill and a crate opening per roll
+        let run = |seed: u64| {
+            let mut rng = GameRng::new(seed);
+            (0..ROLLS)
+                .map(|_| {
+                    let reward = tdata.get_mob_reward(&mut rng, 1).unwrap();
+                    let crate_item = tdata.get_item_from_crate(&mut rng, 100, 1).unwrap();
+                    (
+                        reward.taros,
+                        reward.fusion_matter,
+                        reward.nano_potions,
+                        reward.weapon_boosts,
+                        reward.items,
+                        crate_item,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let outcomes = run(42);
+        assert_eq!(outcomes, run(42));
+        assert_ne!(outcomes, run(43));
+
+        // the same seed isn't just replaying a single outcome
+        let crate_ids: HashSet<i16> = outcomes.iter().map(|o| o.5.id).collect();
+        assert_eq!(crate_ids, HashSet::from([10, 11, 12]));
+        let crate_drops = outcomes.iter().filter(|o| !o.4.is_empty()).count();
+        assert!(crate_drops > 0 && crate_drops < ROLLS);
+        assert!(outcomes.iter().any(|o| o.0 == 30) && outcomes.iter().any(|o| o.0 == 0));
+    }
+
+    #[test]
+    fn test_mob_drop_override() {
+        let mut tdata = empty_table_data();
+        let drop_data = &mut tdata.drop_data;
+        // always taros and nothing else; the missing crate tables just mean a gumball
+        drop_data.misc_drop_chances.insert(
+            1,
+            MiscDropChance {
+                PotionDropChance: 0,
+                PotionDropChanceTotal: 1,
+                BoostDropChance: 0,
+                BoostDropChanceTotal: 1,
+                TaroDropChance: 1,
+                TaroDropChanceTotal: 1,
+                FMDropChance: 0,
+                FMDropChanceTotal: 1,
+            },
+        );
+        for (mob_drop_id, taros) in [(5, 30), (6, 50)] {
+            drop_data.misc_drop_types.insert(
+                mob_drop_id,
+                MiscDropType {
+                    PotionAmount: 0,
+                    BoostAmount: 0,
+                    TaroAmount: taros,
+                    FMAmount: 0,
+                },
+            );
+            drop_data.mob_drops.insert(
+                mob_drop_id,
+                MobDrop {
+                    CrateDropChanceID: 1,
+                    CrateDropTypeID: 1,
+                    MiscDropChanceID: 1,
+                    MiscDropTypeID: mob_drop_id,
+                },
+            );
+        }
+        drop_data
+            .mob_drop_data
+            .insert(1, MobDropData { MobDropID: 5 });
+
+        let mut rng = GameRng::new(1);
+        let mut taros_for = |mob_type, mob_drop_id| {
+            tdata
+                .get_mob_reward_for(&mut rng, mob_type, mob_drop_id, None)
+                .map(|reward| reward.taros)
+                .ok()
+        };
+        assert_eq!(taros_for(1, None), Some(30));
+        assert_eq!(taros_for(1, Some(6)), Some(50));
+        // an override that doesn't exist falls back to the type's drops
+        assert_eq!(taros_for(1, Some(999)), Some(30));
+        // unless the type doesn't have any either
+        assert_eq!(taros_for(2, Some(999)), None);
+    }
+
+    fn remap_json(json: &str) -> Map<std::string::String, Value> {
+        match serde_json::from_str(json).unwrap() {
+            Value::Object(root) => root,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn test_npc_remap_load() {
+        let remap = NpcRemap::load(&remap_json(r#"{ "1": 10, "2": 10, "3": 30 }"#)).unwrap();
+        assert_eq!(remap.get(1), 10);
+        assert_eq!(remap.get(2), 10);
+        assert_eq!(remap.get(3), 30);
+        assert_eq!(remap.get(4), 4);
+
+        // a -> b and b -> c isn't applied as a -> c
+        let err = NpcRemap::load(&remap_json(r#"{ "1": 2, "2": 3 }"#)).unwrap_err();
+        assert_eq!(err, "NPC type 1 remaps to 2, which is remapped itself");
+        assert!(NpcRemap::load(&remap_json(r#"{ "5": 5 }"#)).is_err());
+        assert!(NpcRemap::load(&remap_json(r#"{ "mob": 5 }"#)).is_err());
+        assert!(NpcRemap::load(&remap_json(r#"{ "1": "5" }"#)).is_err());
+    }
+
+    #[test]
+    fn test_npc_remap_apply() {
+        let remap = NpcRemap::load(&remap_json(r#"{ "1": 10, "2": 20, "3": 20 }"#)).unwrap();
+        let mut tdata = empty_table_data();
+        tdata.npcs.push(NPCSpawnData {
+            group_id: Some(1),
+            npc_type: 1,
+            pos: Position::default(),
+            angle: 0,
+            map_num: None,
+            mob_drop_id: None,
+            followers: vec![FollowerData {
+                npc_type: 2,
+                offset: Position::default(),
+                mob_drop_id: None,
+            }],
+        });
+        let mut task_def = TaskDefinition::new_mock(100, 1);
+        task_def.obj_enemies = HashMap::from([(1, 5), (4, 3)]);
+        task_def.obj_enemy_id_ordering = vec![4, 1];
+        task_def.obj_npc_type = Some(2);
+        tdata
+            .xdt_data
+            .mission_data
+            .task_definitions
+            .insert(100, task_def);
+        let drop_data = &mut tdata.drop_data;
+        drop_data
+            .mob_drop_data
+            .insert(1, MobDropData { MobDropID: 7 });
+        // merged into a type that already has its own drops
+        drop_data
+            .mob_drop_data
+            .insert(3, MobDropData { MobDropID: 8 });
+        drop_data
+            .mob_drop_data
+            .insert(20, MobDropData { MobDropID: 9 });
+        tdata
+            .path_data
+            .npc_paths
+            .insert(2, Path::new(Vec::new(), true));
+
+        remap.apply(&mut tdata).unwrap();
+        assert_eq!(tdata.npcs[0].npc_type, 10);
+        assert_eq!(tdata.npcs[0].followers[0].npc_type, 20);
+        let task_def = tdata.get_task_definition(100).unwrap();
+        assert_eq!(task_def.obj_enemies, HashMap::from([(10, 5), (4, 3)]));
+        // same order, so saved kill counts still line up
+        assert_eq!(task_def.obj_enemy_id_ordering, vec![4, 10]);
+        assert_eq!(task_def.obj_npc_type, Some(20));
+        let mob_drops = &tdata.drop_data.mob_drop_data;
+        assert_eq!(mob_drops.len(), 2);
+        assert_eq!(mob_drops[&10].MobDropID, 7);
+        assert_eq!(mob_drops[&20].MobDropID, 9);
+        assert!(tdata.get_npc_path(20).is_some());
+        assert!(tdata.get_npc_path(2).is_none());
+
+        // two kill objectives can't become one
+        let mut tdata = empty_table_data();
+        let mut task_def = TaskDefinition::new_mock(200, 2);
+        task_def.obj_enemies = HashMap::from([(2, 5), (3, 5)]);
+        task_def.obj_enemy_id_ordering = vec![2, 3];
+        tdata
+            .xdt_data
+            .mission_data
+            .task_definitions
+            .insert(200, task_def);
+        assert!(remap.apply(&mut tdata).is_err());
+    }
+}
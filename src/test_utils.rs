@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    net::SocketAddr,
+};
+
+use crate::{
+    chunk::InstanceID,
+    defines::*,
+    entity::{Combatant, Entity, Player, NPC},
+    enums::{ItemLocation, PlayerGuide, PlayerNameStatus},
+    item::Item,
+    net::{
+        crypto::{decrypt_payload, EncryptionMode},
+        packet::{sP_FE2CL_PC_MOTD_LOGIN, PacketID},
+        ClientMap, ClientStream, ClientType, FFClient, PacketBuffer,
+    },
+    util, Position,
+};
+
+// The parts of a player a client could observe, for comparing before and after a handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSnapshot {
+    pub position: Position,
+    pub instance_id: InstanceID,
+    pub level: i16,
+    pub hp: i32,
+    pub taros: u32,
+    pub fusion_matter: u32,
+    pub nano_potions: u32,
+    pub weapon_boosts: u32,
+    // keyed by absolute slot number; see util::slot_num_to_loc_and_slot_num
+    pub items: BTreeMap<usize, Item>,
+    pub quest_items: BTreeMap<i16, usize>,
+    pub equipped_nanos: [u16; SIZEOF_NANO_CARRY_SLOT as usize],
+    pub name_check: PlayerNameStatus,
+    pub tutorial_flag: bool,
+    pub payzone_flag: bool,
+    // set bit indices
+    pub tip_flags: Vec<usize>,
+    pub scamper_flags: Vec<usize>,
+    pub skyway_flags: Vec<usize>,
+    pub guide: PlayerGuide,
+    pub total_guides: usize,
+    pub state_flags: i8,
+    pub special_state_flags: i8,
+    pub current_task_ids: Vec<i32>,
+    pub active_mission_id: Option<i32>,
+    pub completed_mission_ids: Vec<i32>,
+    pub crate_pity: BTreeMap<i16, u32>,
+}
+impl PlayerSnapshot {
+    pub fn take(player: &Player) -> Self {
+        let mut current_task_ids = player.mission_journal.get_current_task_ids();
+        current_task_ids.sort();
+        let completed_mission_ids = player
+            .mission_journal
+            .completed_mission_flags
+            .iter_set()
+            .map(|idx| idx as i32 + 1)
+            .collect();
+
+        Self {
+            position: player.get_position(),
+            instance_id: player.instance_id,
+            level: player.get_level(),
+            hp: player.get_hp(),
+            taros: player.get_taros(),
+            fusion_matter: player.get_fusion_matter(),
+            nano_potions: player.get_nano_potions(),
+            weapon_boosts: player.get_weapon_boosts(),
+            items: player
+                .get_item_iter()
+                .map(|(slot_num, item)| (slot_num, *item))
+                .collect(),
+            quest_items: player.get_quest_item_iter().collect(),
+            equipped_nanos: player.get_equipped_nano_ids(),
+            name_check: player.flags.name_check,
+            tutorial_flag: player.flags.tutorial_flag,
+            payzone_flag: player.flags.payzone_flag,
+            tip_flags: player.flags.tip_flags.iter_set().collect(),
+            scamper_flags: player.flags.scamper_flags.iter_set().collect(),
+            skyway_flags: player.flags.skyway_flags.iter_set().collect(),
+            guide: player.get_guide(),
+            total_guides: player.get_total_guides(),
+            state_flags: player.get_state_bit_flag(),
+            special_state_flags: player.get_special_state_bit_flag(),
+            current_task_ids,
+            active_mission_id: player.mission_journal.get_active_mission_id(),
+            completed_mission_ids,
+            crate_pity: player.get_crate_pity_iter().collect(),
+        }
+    }
+
+    // Lists every field that differs, going from self to after
+    pub fn diff(&self, after: &PlayerSnapshot) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+        macro_rules! diff_fields {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != after.$field {
+                        changes.push(FieldChange::new(
+                            stringify!($field),
+                            &self.$field,
+                            &after.$field,
+                        ));
+                    }
+                )*
+            };
+        }
+        diff_fields!(
+            position,
+            instance_id,
+            level,
+            hp,
+            taros,
+            fusion_matter,
+            nano_potions,
+            weapon_boosts
+        );
+
+        let mut slot_nums: Vec<usize> = self
+            .items
+            .keys()
+            .chain(after.items.keys())
+            .copied()
+            .collect();
+        slot_nums.sort();
+        slot_nums.dedup();
+        for slot_num in slot_nums {
+            let (before_item, after_item) = (self.items.get(&slot_num), after.items.get(&slot_num));
+            if before_item != after_item {
+                let (location, slot_num_loc) =
+                    util::slot_num_to_loc_and_slot_num(slot_num).unwrap();
+                changes.push(FieldChange::new(
+                    &format!("items[{:?} {}]", location, slot_num_loc),
+                    &before_item,
+                    &after_item,
+                ));
+            }
+        }
+
+        diff_fields!(
+            quest_items,
+            equipped_nanos,
+            name_check,
+            tutorial_flag,
+            payzone_flag,
+            tip_flags,
+            scamper_flags,
+            skyway_flags,
+            guide,
+            total_guides,
+            state_flags,
+            special_state_flags,
+            current_task_ids,
+            active_mission_id,
+            completed_mission_ids,
+            crate_pity
+        );
+        changes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+impl FieldChange {
+    fn new(field: &str, before: &impl Debug, after: &impl Debug) -> Self {
+        Self {
+            field: field.to_string(),
+            before: format!("{:?}", before),
+            after: format!("{:?}", after),
+        }
+    }
+}
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.before, self.after)
+    }
+}
+
+// Returns the names of the changed fields, for terse assertions
+pub fn changed_fields(changes: &[FieldChange]) -> Vec<&str> {
+    changes.iter().map(|change| change.field.as_str()).collect()
+}
+
+pub struct PlayerBuilder {
+    uid: i64,
+    slot_num: usize,
+    pc_id: Option<i32>,
+    client_id: Option<usize>,
+    level: i16,
+    hp: i32,
+    taros: u32,
+    fusion_matter: u32,
+    perms: i16,
+    position: Position,
+    instance_id: InstanceID,
+    items: Vec<(ItemLocation, usize, Item)>,
+}
+impl PlayerBuilder {
+    pub fn new(uid: i64) -> Self {
+        Self {
+            uid,
+            slot_num: 1,
+            pc_id: None,
+            client_id: None,
+            level: 1,
+            hp: 1000,
+            taros: 0,
+            fusion_matter: 0,
+            perms: CN_ACCOUNT_LEVEL__USER as i16,
+            position: Position::default(),
+            instance_id: InstanceID::default(),
+            items: Vec::new(),
+        }
+    }
+
+    pub fn slot_num(mut self, slot_num: usize) -> Self {
+        self.slot_num = slot_num;
+        self
+    }
+
+    pub fn pc_id(mut self, pc_id: i32) -> Self {
+        self.pc_id = Some(pc_id);
+        self
+    }
+
+    pub fn client_id(mut self, client_id: usize) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
+
+    pub fn level(mut self, level: i16) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn hp(mut self, hp: i32) -> Self {
+        self.hp = hp;
+        self
+    }
+
+    pub fn taros(mut self, taros: u32) -> Self {
+        self.taros = taros;
+        self
+    }
+
+    pub fn fusion_matter(mut self, fusion_matter: u32) -> Self {
+        self.fusion_matter = fusion_matter;
+        self
+    }
+
+    pub fn perms(mut self, perms: i16) -> Self {
+        self.perms = perms;
+        self
+    }
+
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn instance_id(mut self, instance_id: InstanceID) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    pub fn item(mut self, location: ItemLocation, slot_num: usize, item: Item) -> Self {
+        self.items.push((location, slot_num, item));
+        self
+    }
+
+    pub fn build(self) -> Player {
+        let mut player = Player::new_mock(self.uid, self.level, self.hp, self.fusion_matter);
+        player.set_slot_num_mock(self.slot_num);
+        player.perms = self.perms;
+        player.instance_id = self.instance_id;
+        player.set_position(self.position);
+        player.set_taros(self.taros);
+        for (location, slot_num, item) in self.items {
+            player.set_item(location, slot_num, Some(item)).unwrap();
+        }
+        // set last so none of the above gets journaled
+        if let Some(pc_id) = self.pc_id {
+            player.set_player_id(pc_id);
+        }
+        if let Some(client_id) = self.client_id {
+            player.set_client_id(client_id);
+        }
+        player
+    }
+}
+
+pub struct NpcBuilder {
+    id: i32,
+    ty: i32,
+    position: Position,
+    instance_id: InstanceID,
+    hp: i32,
+}
+impl NpcBuilder {
+    pub fn new(id: i32, ty: i32) -> Self {
+        Self {
+            id,
+            ty,
+            position: Position::default(),
+            instance_id: InstanceID::default(),
+            hp: 1000,
+        }
+    }
+
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn instance_id(mut self, instance_id: InstanceID) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    pub fn hp(mut self, hp: i32) -> Self {
+        self.hp = hp;
+        self
+    }
+
+    pub fn build(self) -> NPC {
+        NPC::new_mock(self.id, self.ty, self.position, self.instance_id, self.hp)
+    }
+}
+
+// Clients backed by in-memory streams, so handlers can run unmodified.
+// Whatever the server sends can be read back and decoded.
+#[derive(Default)]
+pub struct FakeClients {
+    clients: HashMap<usize, FFClient>,
+    next_key: usize,
+}
+impl FakeClients {
+    pub fn add_game_client(&mut self, pc_id: Option<i32>) -> usize {
+        self.add_client(ClientType::GameClient {
+            account_id: 1,
+            serial_key: 0,
+            pc_id,
+        })
+    }
+
+    // For servers and anything else that isn't a game client
+    pub fn add_client(&mut self, client_type: ClientType) -> usize {
+        let key = self.next_key;
+        self.next_key += 1;
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], key as u16));
+        let mut client = FFClient::new_in_memory(addr);
+        client.client_type = client_type;
+        self.clients.insert(key, client);
+        key
+    }
+
+    // ClientMap with the given client as "self"
+    pub fn get_map(&mut self, key: usize) -> ClientMap<'_> {
+        ClientMap::new(key, &mut self.clients)
+    }
+
+    pub fn get_client(&mut self, key: usize) -> &mut FFClient {
+        self.clients.get_mut(&key).unwrap()
+    }
+
+    // For code that goes through every client at once
+    pub fn get_clients(&mut self) -> &mut HashMap<usize, FFClient> {
+        &mut self.clients
+    }
+
+    // Packets sent to a client since the last call, decrypted and in order.
+    // Each frame carries one packet, followed by any trailing structs.
+    pub fn take_sent_packets(&mut self, key: usize) -> Vec<PacketBuffer> {
+        let client = self.clients.get_mut(&key).unwrap();
+        let crypto_key = match client.enc_mode {
+            EncryptionMode::EKey => client.e_key,
+            EncryptionMode::FEKey => client.fe_key,
+        };
+        let ClientStream::Memory(stream) = &mut client.sock else {
+            panic!("Fake client {} isn't in memory", key);
+        };
+
+        let mut packets = Vec::new();
+        let mut sent = stream.written.as_slice();
+        while !sent.is_empty() {
+            let sz = u32::from_le_bytes(sent[..4].try_into().unwrap()) as usize;
+            let mut payload = sent[4..4 + sz].to_vec();
+            decrypt_payload(&mut payload, &crypto_key);
+            packets.push(PacketBuffer::from_payload(&payload));
+            sent = &sent[4 + sz..];
+        }
+        stream.written.clear();
+        packets
+    }
+
+    // IDs of the packets sent to a client since the last call
+    pub fn take_sent(&mut self, key: usize) -> Vec<PacketID> {
+        self.take_sent_packets(key)
+            .iter()
+            .map(|pkt| pkt.peek_packet_id().unwrap())
+            .collect()
+    }
+
+    // Text of the system messages sent to a client since the last call.
+    // Other packets are dropped
+    pub fn take_messages(&mut self, key: usize) -> Vec<String> {
+        self.take_sent_packets(key)
+            .iter_mut()
+            .filter(|pkt| pkt.peek_packet_id().unwrap() == PacketID::P_FE2CL_PC_MOTD_LOGIN)
+            .map(|pkt| {
+                let pkt: &sP_FE2CL_PC_MOTD_LOGIN =
+                    pkt.get_packet(PacketID::P_FE2CL_PC_MOTD_LOGIN).unwrap();
+                util::parse_utf16(&{ pkt.szSystemMsg }).unwrap()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{enums::ItemType, helpers::send_system_message};
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut player = PlayerBuilder::new(1)
+            .taros(100)
+            .item(ItemLocation::Inven, 0, Item::new(ItemType::General, 5))
+            .build();
+        let before = PlayerSnapshot::take(&player);
+        assert!(before.diff(&PlayerSnapshot::take(&player)).is_empty());
+
+        player.set_taros(50);
+        player.set_item(ItemLocation::Inven, 0, None).unwrap();
+        player
+            .set_item(ItemLocation::Bank, 2, Some(Item::new(ItemType::General, 6)))
+            .unwrap();
+        let changes = before.diff(&PlayerSnapshot::take(&player));
+        assert_eq!(
+            changed_fields(&changes),
+            vec!["taros", "items[Inven 0]", "items[Bank 2]"]
+        );
+        assert_eq!(changes[0].to_string(), "taros: 100 -> 50");
+    }
+
+    #[test]
+    fn test_fake_clients() {
+        let mut fake_clients = FakeClients::default();
+        let key = fake_clients.add_game_client(Some(1));
+        let other_key = fake_clients.add_game_client(Some(2));
+
+        let mut clients = fake_clients.get_map(key);
+        assert_eq!(clients.get_self().get_player_id().unwrap(), 1);
+        send_system_message(clients.get_self(), "hello").unwrap();
+        send_system_message(clients.get(other_key), "hi").unwrap();
+        send_system_message(clients.get_self(), "again").unwrap();
+
+        let msgs = fake_clients.take_messages(key);
+        assert_eq!(msgs, vec!["hello", "again"]);
+        assert_eq!(
+            fake_clients.take_sent(other_key),
+            vec![PacketID::P_FE2CL_PC_MOTD_LOGIN]
+        );
+        assert!(fake_clients.take_sent(key).is_empty());
+    }
+}
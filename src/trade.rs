@@ -1,6 +1,8 @@
+use std::time::SystemTime;
+
 use crate::{
     defines::*,
-    entity::Player,
+    entity::{Combatant, Entity, Player},
     enums::*,
     error::{panic_log, FFError, FFResult, Severity},
     item::Item,
@@ -72,6 +74,47 @@ impl TradeOffer {
         ))
     }
 }
+
+// Both sides of a trade need to stay near each other and free to trade until it's done
+pub fn validate_trade_parties(player: &Player, other: &Player, range: u32) -> FFResult<()> {
+    for p in [player, other] {
+        let busy_with = if p.is_dead() {
+            Some("being dead")
+        } else if p.is_riding_skyway() {
+            Some("riding the Skyway")
+        } else if p.in_menu {
+            Some("being in a menu")
+        } else {
+            None
+        };
+        if let Some(busy_with) = busy_with {
+            return Err(FFError::build(
+                Severity::Warning,
+                format!("{} can't trade while {}", p, busy_with),
+            ));
+        }
+    }
+
+    if player.instance_id != other.instance_id {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("{} and {} aren't in the same instance", player, other),
+        ));
+    }
+
+    let distance = player.get_position().distance_to(&other.get_position());
+    if distance > range {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!(
+                "{} and {} are too far apart to trade ({} > {})",
+                player, other, distance, range
+            ),
+        ));
+    }
+    Ok(())
+}
+
 pub struct TradeContext {
     from_pc_id: i32,
     from_offer: TradeOffer,
@@ -108,6 +151,21 @@ impl TradeContext {
         panic_log("Bad trade state");
     }
 
+    fn get_offer(&self, pc_id: i32) -> FFResult<&TradeOffer> {
+        if pc_id == self.from_pc_id {
+            return Ok(&self.from_offer);
+        }
+
+        if pc_id == self.to_pc_id {
+            return Ok(&self.to_offer);
+        }
+
+        Err(FFError::build(
+            Severity::Warning,
+            format!("Player {} not in trade", pc_id),
+        ))
+    }
+
     fn get_offer_mut(&mut self, pc_id: i32) -> FFResult<&mut TradeOffer> {
         if pc_id == self.from_pc_id {
             return Ok(&mut self.from_offer);
@@ -148,6 +206,21 @@ impl TradeContext {
         offer.remove_item(trade_slot_num)
     }
 
+    // The taros and items the player has put up, as they are in their inventory right now
+    pub fn get_offered_goods(&self, player: &Player) -> FFResult<(u32, Vec<Item>)> {
+        let offer = self.get_offer(player.get_player_id())?;
+        let mut items = Vec::new();
+        for trade_item in offer.items.iter().flatten() {
+            if let Some(mut item) =
+                *player.get_item(ItemLocation::Inven, trade_item.inven_slot_num)?
+            {
+                item.quantity = trade_item.quantity;
+                items.push(item);
+            }
+        }
+        Ok((offer.taros, items))
+    }
+
     fn is_ready(&self) -> bool {
         self.from_offer.confirmed && self.to_offer.confirmed
     }
@@ -171,8 +244,8 @@ impl TradeContext {
             to: &mut Player,
         ) -> FFResult<Vec<sItemTrade>> {
             // taros
-            from.set_taros(from.get_taros() - offer.taros);
-            to.set_taros(to.get_taros() + offer.taros);
+            from.add_taros(-(offer.taros as i64), CurrencySource::Trade);
+            to.add_taros(offer.taros as i64, CurrencySource::Trade);
 
             // items
             let mut items = Vec::new();
@@ -180,7 +253,8 @@ impl TradeContext {
                 let slot = from
                     .get_item_mut(ItemLocation::Inven, item.inven_slot_num)
                     .unwrap();
-                let item_traded = Item::split_items(slot, item.quantity).unwrap();
+                let mut item_traded = Item::split_items(slot, item.quantity).unwrap();
+                item_traded.add_trade_hop(from.get_uid(), to.get_uid(), SystemTime::now());
                 let free_slot = to.find_free_slot(ItemLocation::Inven)?;
                 to.set_item(ItemLocation::Inven, free_slot, Some(item_traded))
                     .unwrap();
@@ -196,6 +270,22 @@ impl TradeContext {
             Ok(items)
         }
 
+        // taros might have been spent since they were registered
+        for player in [&*players.0, &*players.1] {
+            let offer = self.get_offer(player.get_player_id())?;
+            if player.get_taros() < offer.taros {
+                return Err(FFError::build(
+                    Severity::Warning,
+                    format!(
+                        "{} no longer has the taros they offered ({} < {})",
+                        player,
+                        player.get_taros(),
+                        offer.taros
+                    ),
+                ));
+            }
+        }
+
         let blank_item = sItemTrade {
             iType: 0,
             iID: 0,
@@ -220,3 +310,124 @@ impl TradeContext {
         Ok((items.1.try_into().unwrap(), items.0.try_into().unwrap()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        chunk::InstanceID,
+        item::ItemOrigin,
+        test_utils::{changed_fields, PlayerBuilder, PlayerSnapshot},
+        Position,
+    };
+
+    #[test]
+    fn test_trade_resolve() {
+        let mut stack = Item::new(ItemType::General, 5);
+        stack.quantity = 3;
+        let mut from = PlayerBuilder::new(1)
+            .pc_id(1)
+            .taros(500)
+            .item(ItemLocation::Inven, 0, stack)
+            .build();
+        let mut to = PlayerBuilder::new(2).pc_id(2).taros(100).build();
+        let from_before = PlayerSnapshot::take(&from);
+        let to_before = PlayerSnapshot::take(&to);
+
+        let mut trade = TradeContext::new(1, 2);
+        trade.set_taros(1, 200).unwrap();
+        trade.add_item(1, 0, 0, 2).unwrap();
+        assert!(!trade.lock_in(1).unwrap());
+        assert!(trade.lock_in(2).unwrap());
+        let mut offered = stack;
+        offered.quantity = 2;
+        assert_eq!(
+            trade.get_offered_goods(&from).unwrap(),
+            (200, vec![offered])
+        );
+        assert_eq!(trade.get_offered_goods(&to).unwrap(), (0, vec![]));
+        trade.resolve((&mut from, &mut to)).unwrap();
+
+        let from_changes = from_before.diff(&PlayerSnapshot::take(&from));
+        assert_eq!(
+            changed_fields(&from_changes),
+            vec!["taros", "items[Inven 0]"]
+        );
+        let to_changes = to_before.diff(&PlayerSnapshot::take(&to));
+        assert_eq!(changed_fields(&to_changes), vec!["taros", "items[Inven 0]"]);
+
+        assert_eq!(from.get_taros(), 300);
+        assert_eq!(to.get_taros(), 300);
+        let quantity = |player: &Player| {
+            player
+                .get_item(ItemLocation::Inven, 0)
+                .unwrap()
+                .map(|item| item.quantity)
+        };
+        assert_eq!(quantity(&from), Some(1));
+        assert_eq!(quantity(&to), Some(2));
+
+        // the trade goes on the received item's record, not the one left behind
+        let received = to.get_item(ItemLocation::Inven, 0).unwrap().unwrap();
+        let provenance = received.get_provenance().unwrap();
+        assert_eq!(provenance.origin, ItemOrigin::Trade { from_uid: 1 });
+        let hops: Vec<(i64, i64)> = provenance
+            .get_trade_hops()
+            .map(|hop| (hop.from_uid, hop.to_uid))
+            .collect();
+        assert_eq!(hops, vec![(1, 2)]);
+        let kept = from.get_item(ItemLocation::Inven, 0).unwrap().unwrap();
+        assert!(kept.get_provenance().is_none());
+    }
+
+    #[test]
+    fn test_trade_distance() {
+        let from = PlayerBuilder::new(1).pc_id(1).build();
+        let near = PlayerBuilder::new(2)
+            .pc_id(2)
+            .position(Position { x: 500, y: 0, z: 0 })
+            .build();
+        let far = PlayerBuilder::new(3)
+            .pc_id(3)
+            .position(Position { x: 900, y: 0, z: 0 })
+            .build();
+        assert!(validate_trade_parties(&from, &near, 800).is_ok());
+        assert!(validate_trade_parties(&from, &far, 800).is_err());
+        assert!(validate_trade_parties(&from, &near, 400).is_err());
+
+        let other_instance = PlayerBuilder::new(4)
+            .pc_id(4)
+            .instance_id(InstanceID {
+                channel_num: 2,
+                ..from.instance_id
+            })
+            .build();
+        assert!(validate_trade_parties(&from, &other_instance, 800).is_err());
+
+        let mut busy = PlayerBuilder::new(5).pc_id(5).build();
+        busy.in_menu = true;
+        assert!(validate_trade_parties(&from, &busy, 800).is_err());
+        let dead = PlayerBuilder::new(6).pc_id(6).hp(0).build();
+        assert!(validate_trade_parties(&dead, &near, 800).is_err());
+    }
+
+    #[test]
+    fn test_trade_stale_taros() {
+        let mut from = PlayerBuilder::new(1).pc_id(1).taros(500).build();
+        let mut to = PlayerBuilder::new(2).pc_id(2).taros(100).build();
+
+        let mut trade = TradeContext::new(1, 2);
+        trade.set_taros(1, 400).unwrap();
+        trade.lock_in(1).unwrap();
+        trade.lock_in(2).unwrap();
+
+        // spent after registering them
+        from.add_taros(-200, CurrencySource::VendorBuy);
+        let from_before = PlayerSnapshot::take(&from);
+        let to_before = PlayerSnapshot::take(&to);
+        assert!(trade.resolve((&mut from, &mut to)).is_err());
+        assert!(from_before.diff(&PlayerSnapshot::take(&from)).is_empty());
+        assert!(to_before.diff(&PlayerSnapshot::take(&to)).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::config_get,
+    database::db_run_async,
+    enums::TransactionKind,
+    error::{log, FFResult, Severity},
+    item::Item,
+    util,
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// A completed trade or vendor deal, as seen by `pc_uid`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub kind: TransactionKind,
+    pub pc_uid: i64,
+    // the other side of a trade; vendor deals don't have one
+    pub other_pc_uid: Option<i64>,
+    pub taros_given: u32,
+    pub items_given: Vec<Item>,
+    pub taros_received: u32,
+    pub items_received: Vec<Item>,
+    pub time: SystemTime,
+}
+impl Transaction {
+    // Describes the transaction from the point of view of either player involved
+    pub fn describe(&self, pc_uid: i64) -> String {
+        let flipped = self.other_pc_uid == Some(pc_uid) && self.pc_uid != pc_uid;
+        let (taros_given, items_given, taros_received, items_received) = if flipped {
+            (
+                self.taros_received,
+                &self.items_received,
+                self.taros_given,
+                &self.items_given,
+            )
+        } else {
+            (
+                self.taros_given,
+                &self.items_given,
+                self.taros_received,
+                &self.items_received,
+            )
+        };
+        let what = match self.kind {
+            TransactionKind::Trade => {
+                let other_uid = if flipped {
+                    Some(self.pc_uid)
+                } else {
+                    self.other_pc_uid
+                };
+                match other_uid {
+                    Some(other_uid) => format!("Trade with uid:{}", other_uid),
+                    None => "Trade".to_string(),
+                }
+            }
+            TransactionKind::VendorBuy => "Vendor purchase".to_string(),
+            TransactionKind::VendorSell => "Vendor sale".to_string(),
+        };
+        format!(
+            "[{}] {}: gave {}; got {}",
+            util::get_timestamp_str(self.time),
+            what,
+            describe_goods(taros_given, items_given),
+            describe_goods(taros_received, items_received)
+        )
+    }
+}
+
+fn describe_goods(taros: u32, items: &[Item]) -> String {
+    let mut parts = Vec::new();
+    if taros > 0 {
+        parts.push(format!("{} taros", taros));
+    }
+    for item in items {
+        parts.push(format!(
+            "{}x item ({}, {:?})",
+            item.quantity, item.id, item.ty
+        ));
+    }
+    if parts.is_empty() {
+        return "nothing".to_string();
+    }
+    parts.join(", ")
+}
+
+// transactions waiting to be written out to the DB
+static PENDING_TRANSACTIONS: Mutex<Vec<Transaction>> = Mutex::new(Vec::new());
+
+pub fn transaction_log_enabled() -> bool {
+    config_get().shard.transaction_log_enabled.get()
+}
+
+// Vendor deals only get logged past a certain price, since most are small
+pub fn is_loggable_vendor_deal(price: u64) -> bool {
+    price >= config_get().shard.transaction_log_min_price.get()
+}
+
+pub fn transaction_log_queue(transaction: Transaction) {
+    if !transaction_log_enabled() {
+        return;
+    }
+    PENDING_TRANSACTIONS.lock().unwrap().push(transaction);
+}
+
+pub fn transaction_log_flush() -> FFResult<()> {
+    let transactions = std::mem::take(&mut *PENDING_TRANSACTIONS.lock().unwrap());
+    if transactions.is_empty() {
+        return Ok(());
+    }
+
+    let num_transactions = transactions.len();
+    let _ = db_run_async(move |db| db.log_trade(&transactions));
+    log(
+        Severity::Debug,
+        &format!("Queued {} transactions for the DB", num_transactions),
+    );
+    Ok(())
+}
+
+// Drops transactions older than the retention window
+pub fn transaction_log_prune(time: SystemTime) -> FFResult<()> {
+    let retention_days = config_get().shard.transaction_log_retention.get();
+    if !transaction_log_enabled() || retention_days == 0 {
+        return Ok(());
+    }
+
+    let cutoff = time - Duration::from_secs(retention_days * SECS_PER_DAY);
+    let _ = db_run_async(move |db| db.prune_transactions(cutoff));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::enums::ItemType;
+
+    #[test]
+    fn test_describe_transaction() {
+        let mut stack = Item::new(ItemType::General, 5);
+        stack.quantity = 3;
+        let trade = Transaction {
+            kind: TransactionKind::Trade,
+            pc_uid: 1,
+            other_pc_uid: Some(2),
+            taros_given: 0,
+            items_given: vec![stack],
+            taros_received: 500,
+            items_received: vec![],
+            time: SystemTime::UNIX_EPOCH,
+        };
+        let time_str = util::get_timestamp_str(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            trade.describe(1),
+            format!(
+                "[{}] Trade with uid:2: gave 3x item (5, General); got 500 taros",
+                time_str
+            )
+        );
+        // the other side sees it the other way around
+        assert_eq!(
+            trade.describe(2),
+            format!(
+                "[{}] Trade with uid:1: gave 500 taros; got 3x item (5, General)",
+                time_str
+            )
+        );
+
+        let sale = Transaction {
+            kind: TransactionKind::VendorSell,
+            other_pc_uid: None,
+            taros_given: 0,
+            items_given: vec![],
+            ..trade
+        };
+        assert_eq!(
+            sale.describe(1),
+            format!("[{}] Vendor sale: gave nothing; got 500 taros", time_str)
+        );
+    }
+}
@@ -1,10 +1,16 @@
 use std::{
-    ops::{BitAnd, BitAndAssign, BitOrAssign, Not, Shl, Shr},
+    fmt::{Display, Formatter},
+    net::{IpAddr, SocketAddr},
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Shl},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use chrono::{DateTime, Local};
-use rand::{distributions::uniform::SampleUniform, Rng};
+use rand::{
+    distributions::{uniform::SampleUniform, Alphanumeric},
+    Rng,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
     defines::*,
@@ -40,32 +46,83 @@ impl AsBytes for i64 {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Bitfield<T> {
-    chunks: Vec<T>,
+// Integer types a Bitfield can be made of
+pub trait BitChunk:
+    Copy
+    + Default
+    + PartialEq
+    + From<bool>
+    + AsBytes
+    + Shl<usize, Output = Self>
+    + Not<Output = Self>
+    + BitOr<Output = Self>
+    + BitAnd<Output = Self>
+    + BitOrAssign
+    + BitAndAssign
+{
 }
 impl<
-        T: Clone
-            + Copy
+        T: Copy
+            + Default
+            + PartialEq
             + From<bool>
             + AsBytes
             + Shl<usize, Output = T>
-            + Shr<usize, Output = T>
             + Not<Output = T>
-            + PartialEq
+            + BitOr<Output = T>
+            + BitAnd<Output = T>
             + BitOrAssign
-            + BitAndAssign
-            + BitAnd<Output = T>,
-    > Bitfield<T>
+            + BitAndAssign,
+    > BitChunk for T
 {
-    pub fn new(num_chunks: usize) -> Self {
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitfieldIndexError {
+    pub idx: usize,
+    pub capacity: usize,
+}
+impl Display for BitfieldIndexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Bitfield index {} out of bounds, capacity {}",
+            self.idx, self.capacity
+        )
+    }
+}
+impl From<BitfieldIndexError> for FFError {
+    fn from(e: BitfieldIndexError) -> Self {
+        FFError::build(Severity::Warning, e.to_string())
+    }
+}
+
+// Flags packed into N chunks of T, laid out the way the client sends them.
+// Bit i lives in chunk i / bits(T), counting from each chunk's low bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitfield<T, const N: usize> {
+    chunks: [T; N],
+}
+impl<T: BitChunk, const N: usize> Default for Bitfield<T, N> {
+    fn default() -> Self {
         Self {
-            chunks: vec![Self::false_value(); num_chunks],
+            chunks: [T::default(); N],
         }
     }
+}
+impl<T: BitChunk, const N: usize> Bitfield<T, N> {
+    pub const CAPACITY: usize = N * Self::chunk_size_bits();
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_array(chunks: [T; N]) -> Self {
+        Self { chunks }
+    }
 
-    pub fn from_single(val: T) -> Self {
-        Self { chunks: vec![val] }
+    pub fn to_array(&self) -> [T; N] {
+        self.chunks
     }
 
     const fn chunk_size_bytes() -> usize {
@@ -76,26 +133,25 @@ impl<
         Self::chunk_size_bytes() * 8
     }
 
-    fn false_value() -> T {
-        T::from(false)
+    fn check_idx(idx: usize) -> Result<(), BitfieldIndexError> {
+        if idx >= Self::CAPACITY {
+            return Err(BitfieldIndexError {
+                idx,
+                capacity: Self::CAPACITY,
+            });
+        }
+        Ok(())
     }
 
-    fn true_value() -> T {
-        T::from(true)
+    fn locate(idx: usize) -> (usize, T) {
+        let mask = T::from(true) << (idx % Self::chunk_size_bits());
+        (idx / Self::chunk_size_bits(), mask)
     }
 
-    pub fn set(&mut self, idx: usize, val: bool) -> FFResult<bool> {
-        let chunk_idx = idx / Self::chunk_size_bits();
-        if chunk_idx >= self.chunks.len() {
-            let max_idx = self.chunks.len() * Self::chunk_size_bits() - 1;
-            return Err(FFError::build(
-                Severity::Warning,
-                format!("Bitfield index {} out of bounds, max {}", idx, max_idx),
-            ));
-        }
-
-        let bit_idx = idx % Self::chunk_size_bits();
-        let mask = Self::true_value() << bit_idx;
+    // Returns whether the bit changed
+    pub fn set(&mut self, idx: usize, val: bool) -> Result<bool, BitfieldIndexError> {
+        Self::check_idx(idx)?;
+        let (chunk_idx, mask) = Self::locate(idx);
         let old_val = self.chunks[chunk_idx];
         if val {
             self.chunks[chunk_idx] |= mask;
@@ -105,93 +161,64 @@ impl<
         Ok(old_val != self.chunks[chunk_idx])
     }
 
-    pub fn get(&self, idx: usize) -> FFResult<bool> {
-        let chunk_idx = idx / Self::chunk_size_bits();
-        if chunk_idx >= self.chunks.len() {
-            let max_idx = self.chunks.len() * Self::chunk_size_bits() - 1;
-            return Err(FFError::build(
-                Severity::Warning,
-                format!("Bitfield index {} out of bounds, max {}", idx, max_idx),
-            ));
-        }
+    pub fn get(&self, idx: usize) -> Result<bool, BitfieldIndexError> {
+        Self::check_idx(idx)?;
+        let (chunk_idx, mask) = Self::locate(idx);
+        Ok((self.chunks[chunk_idx] & mask) != T::default())
+    }
 
-        let bit_idx = idx % Self::chunk_size_bits();
-        let mask = Self::true_value() << bit_idx;
-        Ok((self.chunks[chunk_idx] & mask) != Self::false_value())
+    // Indices of the set bits, in order
+    pub fn iter_set(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..Self::CAPACITY).filter(|idx| {
+            let (chunk_idx, mask) = Self::locate(*idx);
+            (self.chunks[chunk_idx] & mask) != T::default()
+        })
     }
 
-    pub fn get_chunk(&self, idx: usize) -> FFResult<T> {
-        if idx >= self.chunks.len() {
-            return Err(FFError::build(
-                Severity::Warning,
-                format!(
-                    "Bitfield chunk index {} out of bounds, max {}",
-                    idx,
-                    self.chunks.len() - 1
-                ),
-            ));
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            chunks: std::array::from_fn(|i| self.chunks[i] | other.chunks[i]),
         }
-        Ok(self.chunks[idx])
     }
 
-    pub fn set_chunk(&mut self, idx: usize, val: T) -> FFResult<()> {
-        if idx >= self.chunks.len() {
-            return Err(FFError::build(
-                Severity::Warning,
-                format!(
-                    "Bitfield chunk index {} out of bounds, max {}",
-                    idx,
-                    self.chunks.len() - 1
-                ),
-            ));
+    pub fn intersect(&self, other: &Self) -> Self {
+        Self {
+            chunks: std::array::from_fn(|i| self.chunks[i] & other.chunks[i]),
         }
-        self.chunks[idx] = val;
-        Ok(())
     }
 
+    // Little-endian chunks, back to back. This is what goes in the DB
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        for chunk in &self.chunks {
-            let chunk_bytes = chunk.to_bytes();
-            for byte in &chunk_bytes {
-                bytes.push(*byte);
-            }
-        }
-        bytes
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.to_bytes())
+            .collect()
     }
 
-    pub fn from_bytes(bytes: &[u8], num_chunks: usize) -> FFResult<Self> {
-        let mut chunks = Vec::new();
+    // Missing chunks are left clear and extra bytes are ignored,
+    // so flags saved with a different size still load
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut bitfield = Self::default();
         let chunk_size_bytes = Self::chunk_size_bytes();
-        for i in 0..num_chunks {
-            let start = i * chunk_size_bytes;
-            let end = start + chunk_size_bytes;
-            if start >= bytes.len() || end > bytes.len() {
-                chunks.push(Self::false_value());
-            } else {
-                let chunk_bytes = &bytes[start..end];
-                chunks.push(T::from_bytes(chunk_bytes));
-            }
+        for (chunk, chunk_bytes) in bitfield
+            .chunks
+            .iter_mut()
+            .zip(bytes.chunks_exact(chunk_size_bytes))
+        {
+            *chunk = T::from_bytes(chunk_bytes);
         }
-        Ok(Self { chunks })
+        bitfield
     }
-
-    pub fn to_array<const SIZE: usize>(&self) -> FFResult<[T; SIZE]> {
-        if SIZE > self.chunks.len() {
-            return Err(FFError::build(
-                Severity::Warning,
-                format!(
-                    "Bitfield has {} chunks, but array size is {}",
-                    self.chunks.len(),
-                    SIZE
-                ),
-            ));
-        }
-        let mut array = [Self::false_value(); SIZE];
-        for (i, chunk) in self.chunks.iter().enumerate() {
-            array[i] = *chunk;
-        }
-        Ok(array)
+}
+impl<T: BitChunk, const N: usize> Serialize for Bitfield<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+impl<'de, T: BitChunk, const N: usize> Deserialize<'de> for Bitfield<T, N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        Ok(Self::from_bytes(&bytes))
     }
 }
 
@@ -251,6 +278,35 @@ pub fn parse_utf8(chars: &[u8]) -> FFResult<String> {
         .map(|s| s.to_string())
 }
 
+// Packs a server address into the IP string buffer and port that clients get told to connect to
+pub fn encode_server_addr(addr: &SocketAddr) -> FFResult<([u8; 16], i32)> {
+    let mut ip_buf = [0; 16];
+    let ip_str = addr.ip().to_string();
+    let ip_bytes = ip_str.as_bytes();
+    if ip_bytes.len() > ip_buf.len() {
+        return Err(FFError::build(
+            Severity::Warning,
+            format!("Address {} is too long to send to clients", ip_str),
+        ));
+    }
+    ip_buf[..ip_bytes.len()].copy_from_slice(ip_bytes);
+    Ok((ip_buf, addr.port() as i32))
+}
+
+pub fn parse_server_addr(ip_buf: &[u8], port: i32) -> FFResult<SocketAddr> {
+    let ip_str = parse_utf8(ip_buf)?;
+    let ip: IpAddr = ip_str.parse().map_err(|_| {
+        FFError::build(
+            Severity::Warning,
+            format!("Bad server IP address {}", ip_str),
+        )
+    })?;
+    let port: u16 = port
+        .try_into()
+        .map_err(|_| FFError::build(Severity::Warning, format!("Bad server port {}", port)))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
 pub fn encode_utf16<const SIZE: usize>(chars: &str) -> [u16; SIZE] {
     let mut str_vec: Vec<u16> = chars.encode_utf16().collect();
     str_vec.push(0);
@@ -294,6 +350,50 @@ pub fn check_password(password: &str, hash: &str) -> FFResult<bool> {
     bcrypt::verify(password, hash).map_err(FFError::from_bcrypt_err)
 }
 
+// One-time login tokens handed out by launchers look like <prefix><ID>.<secret>.
+// Only a hash of the secret is stored, so the ID is what tokens are looked up by.
+// Kept short enough to fit in the client's password field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthToken {
+    pub id: String,
+    pub secret: String,
+}
+impl AuthToken {
+    const ID_LEN: usize = 8;
+    const SECRET_LEN: usize = 16;
+
+    pub fn generate() -> Self {
+        let gen_string = |len| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect()
+        };
+        Self {
+            id: gen_string(Self::ID_LEN),
+            secret: gen_string(Self::SECRET_LEN),
+        }
+    }
+
+    pub fn parse(token: &str, prefix: &str) -> Option<Self> {
+        let (id, secret) = token.strip_prefix(prefix)?.split_once('.')?;
+        let is_valid =
+            |part: &str, len| part.len() == len && part.chars().all(|c| c.is_ascii_alphanumeric());
+        if !is_valid(id, Self::ID_LEN) || !is_valid(secret, Self::SECRET_LEN) {
+            return None;
+        }
+        Some(Self {
+            id: id.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+
+    pub fn format(&self, prefix: &str) -> String {
+        format!("{}{}.{}", prefix, self.id, self.secret)
+    }
+}
+
 pub fn make_duration(days: u64, hours: u64, mins: u64, secs: u64) -> Duration {
     let mut duration = Duration::from_secs(secs);
     duration += Duration::from_secs(mins * 60);
@@ -373,8 +473,12 @@ pub fn slot_num_to_loc_and_slot_num(mut slot_num: usize) -> FFResult<(ItemLocati
 }
 
 pub fn weighted_rand(weights: &[i32]) -> usize {
+    weighted_rand_with(&mut rand::thread_rng(), weights)
+}
+
+pub fn weighted_rand_with<R: Rng>(rng: &mut R, weights: &[i32]) -> usize {
     let sum: i32 = weights.iter().sum();
-    let mut roll = rand::thread_rng().gen_range(0..=sum);
+    let mut roll = rng.gen_range(0..=sum);
     for (idx, limit) in weights.iter().enumerate() {
         if roll < *limit {
             return idx;
@@ -402,13 +506,13 @@ pub fn get_text_file_contents(path: &str) -> FFResult<String> {
     })
 }
 
-pub fn get_random_gumball() -> Item {
+pub fn get_random_gumball<R: Rng>(rng: &mut R) -> Item {
     let gumballs = [
         Item::new(ItemType::General, ID_GUMBALL),
         Item::new(ItemType::General, ID_GUMBALL + 1),
         Item::new(ItemType::General, ID_GUMBALL + 2),
     ];
-    let choice = rand_range_exclusive(0, gumballs.len());
+    let choice = rng.gen_range(0..gumballs.len());
     gumballs[choice]
 }
 
@@ -416,6 +520,41 @@ pub fn get_random_gumball() -> Item {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_server_addr_encoding() {
+        let addr: SocketAddr = "203.0.113.5:23001".parse().unwrap();
+        let (ip_buf, port) = encode_server_addr(&addr).unwrap();
+        assert_eq!(parse_server_addr(&ip_buf, port).unwrap(), addr);
+
+        let addr: SocketAddr = "[::1]:23001".parse().unwrap();
+        let (ip_buf, port) = encode_server_addr(&addr).unwrap();
+        assert_eq!(parse_server_addr(&ip_buf, port).unwrap(), addr);
+
+        // doesn't fit in the client's buffer
+        let addr: SocketAddr = "[2001:db8:85a3::8a2e:370:7334]:23001".parse().unwrap();
+        assert!(encode_server_addr(&addr).is_err());
+
+        assert!(parse_server_addr(b"not an ip", 23001).is_err());
+        assert!(parse_server_addr(b"127.0.0.1", -1).is_err());
+    }
+
+    #[test]
+    fn test_auth_token_parsing() {
+        let token = AuthToken::generate();
+        assert_eq!(
+            AuthToken::parse(&token.format("tok:"), "tok:"),
+            Some(token.clone())
+        );
+        assert!(AuthToken::parse(&token.format("tok:"), "sso:").is_none());
+        assert!(AuthToken::parse(&token.format(""), "tok:").is_none());
+        assert!(token.format("tok:").len() <= 32);
+
+        assert!(AuthToken::parse("tok:abcdefgh", "tok:").is_none());
+        assert!(AuthToken::parse("tok:abcdefgh.0123456789abcde", "tok:").is_none());
+        assert!(AuthToken::parse("tok:abcdefg!.0123456789abcdef", "tok:").is_none());
+        assert!(AuthToken::parse("tok:abcdefgh.0123456789abcdef", "tok:").is_some());
+    }
+
     #[test]
     fn test_duration_parsing() {
         assert_eq!(
@@ -487,4 +626,106 @@ mod tests {
             make_duration(1, 2, 0, 0)
         );
     }
+
+    #[test]
+    fn test_bitfield_bounds() {
+        type Flags = Bitfield<i64, 2>;
+        assert_eq!(Flags::CAPACITY, 128);
+        let mut flags = Flags::new();
+
+        // first and last bits of each chunk, including the sign bit
+        for idx in [0, 63, 64, 127] {
+            assert!(!flags.get(idx).unwrap());
+            assert!(flags.set(idx, true).unwrap());
+            assert!(flags.get(idx).unwrap());
+            // setting it again doesn't change anything
+            assert!(!flags.set(idx, true).unwrap());
+        }
+        assert_eq!(flags.to_array(), [i64::MIN | 1, i64::MIN | 1]);
+
+        let err = BitfieldIndexError {
+            idx: 128,
+            capacity: 128,
+        };
+        assert_eq!(flags.get(128), Err(err));
+        assert_eq!(flags.set(128, true), Err(err));
+        assert_eq!(flags.set(usize::MAX, false).unwrap_err().idx, usize::MAX);
+        assert_eq!(
+            FFError::from(err).get_msg(),
+            "Bitfield index 128 out of bounds, capacity 128"
+        );
+
+        assert!(flags.set(63, false).unwrap());
+        assert!(!flags.get(63).unwrap());
+        assert!(!flags.set(63, false).unwrap());
+        assert_eq!(flags.iter_set().collect::<Vec<_>>(), vec![0, 64, 127]);
+    }
+
+    #[test]
+    fn test_bitfield_set_ops() {
+        let mut a = Bitfield::<i32, 2>::new();
+        let mut b = Bitfield::<i32, 2>::new();
+        for idx in [1, 31, 40] {
+            a.set(idx, true).unwrap();
+        }
+        for idx in [1, 32, 40, 63] {
+            b.set(idx, true).unwrap();
+        }
+        assert_eq!(
+            a.union(&b).iter_set().collect::<Vec<_>>(),
+            vec![1, 31, 32, 40, 63]
+        );
+        assert_eq!(a.intersect(&b).iter_set().collect::<Vec<_>>(), vec![1, 40]);
+        assert_eq!(a.intersect(&Bitfield::new()), Bitfield::new());
+        assert_eq!(a.union(&a), a);
+    }
+
+    #[test]
+    fn test_bitfield_round_trips() {
+        // the same layout the client uses for aQuestFlag and friends
+        let mut quests = Bitfield::<i64, { SIZEOF_QUESTFLAG_NUMBER as usize }>::new();
+        for idx in [0, 64, 700, 2047] {
+            quests.set(idx, true).unwrap();
+        }
+        let array = quests.to_array();
+        assert_eq!(array[0], 1);
+        assert_eq!(array[1], 1);
+        assert_eq!(array[10], 1 << 60);
+        assert_eq!(array[31], i64::MIN);
+        assert_eq!(Bitfield::from_array(array), quests);
+
+        let bytes = quests.to_bytes();
+        assert_eq!(bytes.len(), SIZEOF_QUESTFLAG_NUMBER as usize * 8);
+        assert_eq!(bytes[0], 1);
+        assert_eq!(bytes[bytes.len() - 1], 0x80);
+        assert_eq!(Bitfield::from_bytes(&bytes), quests);
+
+        // serde stores the same bytes the DB does
+        let json = serde_json::to_string(&quests).unwrap();
+        assert_eq!(json, serde_json::to_string(&bytes).unwrap());
+        let decoded: Bitfield<i64, { SIZEOF_QUESTFLAG_NUMBER as usize }> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, quests);
+
+        let scamper = Bitfield::<i32, SIZEOF_SCAMPER_FLAGS>::from_array([0x1234]);
+        assert_eq!(scamper.to_bytes(), vec![0x34, 0x12, 0, 0]);
+        assert_eq!(scamper.iter_set().collect::<Vec<_>>(), vec![2, 4, 5, 9, 12]);
+    }
+
+    #[test]
+    fn test_bitfield_from_bytes_size_mismatch() {
+        type Skyway = Bitfield<i64, { WYVERN_LOCATION_FLAG_SIZE as usize }>;
+
+        // short or partial data leaves the rest clear
+        assert_eq!(Skyway::from_bytes(&[]), Skyway::new());
+        assert_eq!(Skyway::from_bytes(&[0xff; 12]).to_array(), [-1, 0]);
+
+        // extra data past the end is dropped
+        let mut bytes = vec![0; 24];
+        bytes[8] = 2;
+        bytes[16] = 0xff;
+        let skyway = Skyway::from_bytes(&bytes);
+        assert_eq!(skyway.to_array(), [0, 2]);
+        assert_eq!(skyway.to_bytes(), bytes[..16].to_vec());
+    }
 }
@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::{log, FFError, FFResult, Severity};
+
+const MAX_QUEUED_EVENTS: usize = 100;
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    Ban,
+    Kick,
+    Announce,
+    ServerUp,
+    ServerDown,
+    AutosaveFailed,
+}
+impl WebhookEventKind {
+    pub fn all() -> Vec<WebhookEventKind> {
+        vec![
+            WebhookEventKind::Ban,
+            WebhookEventKind::Kick,
+            WebhookEventKind::Announce,
+            WebhookEventKind::ServerUp,
+            WebhookEventKind::ServerDown,
+            WebhookEventKind::AutosaveFailed,
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WebhookEvent {
+    kind: WebhookEventKind,
+    msg: String,
+    time: SystemTime,
+}
+impl WebhookEvent {
+    fn to_payload(&self, source: &str) -> String {
+        let timestamp = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        json!({
+            "content": format!("[{}] {}", source, self.msg),
+            "event": self.kind,
+            "source": source,
+            "timestamp": timestamp,
+        })
+        .to_string()
+    }
+}
+
+struct PendingEvent {
+    event: WebhookEvent,
+    attempts: u32,
+    next_attempt: SystemTime,
+}
+
+enum WebhookSignal {
+    Flush,
+    Shutdown,
+}
+
+struct WebhookFeed {
+    tx: Sender<WebhookEvent>,
+    signal_tx: Sender<WebhookSignal>,
+    filter: HashSet<WebhookEventKind>,
+}
+
+static FEED: OnceLock<WebhookFeed> = OnceLock::new();
+
+pub fn webhook_init(
+    url: String,
+    events: Vec<WebhookEventKind>,
+    source: String,
+) -> Option<JoinHandle<()>> {
+    assert!(FEED.get().is_none());
+    if url.is_empty() {
+        // disabled
+        return None;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let (signal_tx, signal_rx) = mpsc::channel();
+    let filter = events.into_iter().collect();
+    let _ = FEED.set(WebhookFeed {
+        tx,
+        signal_tx,
+        filter,
+    });
+    log(Severity::Info, "Webhook notifications enabled");
+    Some(std::thread::spawn(move || {
+        webhook_thread(rx, signal_rx, url, source)
+    }))
+}
+
+pub fn webhook_queue(kind: WebhookEventKind, msg: &str) {
+    // silently drop events if the webhook is disabled or filtered
+    let Some(feed) = FEED.get() else {
+        return;
+    };
+    if !feed.filter.contains(&kind) {
+        return;
+    }
+
+    let event = WebhookEvent {
+        kind,
+        msg: msg.to_string(),
+        time: SystemTime::now(),
+    };
+    if feed.tx.send(event).is_err() {
+        log(Severity::Warning, "Failed to queue webhook event");
+    }
+}
+
+pub fn webhook_flush() -> FFResult<()> {
+    webhook_signal(WebhookSignal::Flush)
+}
+
+pub fn webhook_shutdown() -> FFResult<()> {
+    webhook_signal(WebhookSignal::Shutdown)
+}
+
+fn webhook_signal(signal: WebhookSignal) -> FFResult<()> {
+    let Some(feed) = FEED.get() else {
+        // disabled
+        return Ok(());
+    };
+    if feed.signal_tx.send(signal).is_err() {
+        return Err(FFError::build(
+            Severity::Warning,
+            "Failed to signal webhook sender".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn webhook_thread(
+    rx: Receiver<WebhookEvent>,
+    signal_rx: Receiver<WebhookSignal>,
+    url: String,
+    source: String,
+) {
+    let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+    let mut pending: VecDeque<PendingEvent> = VecDeque::new();
+    loop {
+        let shutdown = match signal_rx.recv() {
+            Ok(WebhookSignal::Flush) => false,
+            Ok(WebhookSignal::Shutdown) | Err(_) => true,
+        };
+
+        while let Ok(event) = rx.try_recv() {
+            if pending.len() >= MAX_QUEUED_EVENTS {
+                pending.pop_front();
+                log(
+                    Severity::Warning,
+                    "Webhook queue full; dropping oldest event",
+                );
+            }
+            pending.push_back(PendingEvent {
+                event,
+                attempts: 0,
+                next_attempt: SystemTime::UNIX_EPOCH,
+            });
+        }
+
+        let time_now = SystemTime::now();
+        let mut retry = VecDeque::new();
+        while let Some(mut entry) = pending.pop_front() {
+            if !shutdown && entry.next_attempt > time_now {
+                retry.push_back(entry);
+                continue;
+            }
+
+            let payload = entry.event.to_payload(&source);
+            let Err(e) = send_payload(&agent, &url, &payload) else {
+                continue;
+            };
+
+            entry.attempts += 1;
+            if shutdown || entry.attempts >= MAX_ATTEMPTS {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Dropping webhook event {:?} after {} attempt(s): {}",
+                        entry.event.kind,
+                        entry.attempts,
+                        e.get_msg()
+                    ),
+                );
+                continue;
+            }
+
+            // exponential backoff
+            entry.next_attempt = time_now + RETRY_BASE_DELAY * 2_u32.pow(entry.attempts - 1);
+            retry.push_back(entry);
+        }
+        pending = retry;
+
+        if shutdown {
+            break;
+        }
+    }
+}
+
+fn send_payload(agent: &ureq::Agent, url: &str, payload: &str) -> FFResult<()> {
+    agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(payload)
+        .map(|_| ())
+        .map_err(|e| FFError::build(Severity::Warning, format!("Webhook POST failed: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let event = WebhookEvent {
+            kind: WebhookEventKind::AutosaveFailed,
+            msg: "Save failed".to_string(),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1234),
+        };
+        let payload: serde_json::Value =
+            serde_json::from_str(&event.to_payload("Shard 1")).unwrap();
+        assert_eq!(payload["content"], "[Shard 1] Save failed");
+        assert_eq!(payload["event"], "autosave_failed");
+        assert_eq!(payload["source"], "Shard 1");
+        assert_eq!(payload["timestamp"], 1234);
+    }
+}
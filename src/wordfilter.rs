@@ -0,0 +1,212 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::{log, panic_log, FFError, FFResult, Severity};
+
+#[derive(Deserialize)]
+struct WordFilterFile {
+    #[serde(default)]
+    banned_words: Vec<String>,
+    #[serde(default)]
+    name_patterns: Vec<String>,
+}
+
+pub struct WordFilter {
+    banned_words: Vec<String>,
+    name_patterns: Vec<Regex>,
+}
+impl WordFilter {
+    pub fn parse(contents: &str) -> FFResult<Self> {
+        let file: WordFilterFile = toml::from_str(contents).map_err(|e| {
+            FFError::build(
+                Severity::Fatal,
+                format!("Couldn't parse word filter: {}", e),
+            )
+        })?;
+
+        let banned_words = file
+            .banned_words
+            .iter()
+            .map(|word| normalize(word))
+            .filter(|word| !word.is_empty())
+            .collect();
+        let mut name_patterns = Vec::with_capacity(file.name_patterns.len());
+        for pattern in &file.name_patterns {
+            let regex = Regex::new(pattern).map_err(|e| {
+                FFError::build(
+                    Severity::Fatal,
+                    format!("Bad word filter name pattern {}: {}", pattern, e),
+                )
+            })?;
+            name_patterns.push(regex);
+        }
+
+        Ok(Self {
+            banned_words,
+            name_patterns,
+        })
+    }
+
+    pub fn check_name(&self, name: &str) -> bool {
+        self.name_patterns.iter().all(|regex| regex.is_match(name))
+            && !self.contains_banned_word(name)
+    }
+
+    pub fn contains_banned_word(&self, text: &str) -> bool {
+        // check the whole string so banned words can't be split up with spaces or symbols
+        let normalized = normalize(text);
+        self.banned_words
+            .iter()
+            .any(|word| normalized.contains(word.as_str()))
+    }
+
+    pub fn censor(&self, msg: &str) -> String {
+        let mut censored = String::with_capacity(msg.len());
+        let mut word_start = None;
+        for (idx, c) in msg.char_indices() {
+            if c.is_whitespace() {
+                if let Some(start) = word_start.take() {
+                    censored.push_str(&self.censor_word(&msg[start..idx]));
+                }
+                censored.push(c);
+            } else if word_start.is_none() {
+                word_start = Some(idx);
+            }
+        }
+        if let Some(start) = word_start {
+            censored.push_str(&self.censor_word(&msg[start..]));
+        }
+        censored
+    }
+
+    fn censor_word(&self, word: &str) -> String {
+        if self.contains_banned_word(word) {
+            "*".repeat(word.chars().count())
+        } else {
+            word.to_string()
+        }
+    }
+}
+
+// Lowercases and undoes basic leet-speak, dropping anything that isn't a letter
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' | '|' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            _ => c,
+        })
+        .filter(|c| c.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+static WORD_FILTER: OnceLock<Option<WordFilter>> = OnceLock::new();
+
+pub fn wordfilter_init(path: &str) {
+    assert!(WORD_FILTER.get().is_none());
+    let filter = if path.is_empty() {
+        None
+    } else {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => match WordFilter::parse(&contents) {
+                Ok(filter) => {
+                    log(
+                        Severity::Info,
+                        &format!(
+                            "Loaded word filter with {} banned word(s) and {} name pattern(s)",
+                            filter.banned_words.len(),
+                            filter.name_patterns.len()
+                        ),
+                    );
+                    Some(filter)
+                }
+                Err(e) => panic_log(e.get_msg()),
+            },
+            Err(e) => {
+                log(
+                    Severity::Warning,
+                    &format!(
+                        "Couldn't read word filter {} ({}); filtering disabled",
+                        path, e
+                    ),
+                );
+                None
+            }
+        }
+    };
+    let _ = WORD_FILTER.set(filter);
+}
+
+pub fn wordfilter_get() -> Option<&'static WordFilter> {
+    WORD_FILTER.get()?.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_filter() -> WordFilter {
+        WordFilter::parse(
+            r#"
+            banned_words = ["Darn", "heck"]
+            name_patterns = ["^[A-Za-z][A-Za-z '-]*$"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("H3LL0 W0rld!"), "helloworldi");
+        assert_eq!(normalize("d@rn"), "darn");
+        assert_eq!(normalize("$p4c e"), "space");
+    }
+
+    #[test]
+    fn test_check_name() {
+        let filter = make_filter();
+        assert!(filter.check_name("Sam"));
+        assert!(filter.check_name("O'Brien"));
+        assert!(filter.check_name("Mary-Jane"));
+
+        assert!(!filter.check_name("Darnell")); // substring matches are rejected in names
+        assert!(!filter.check_name("HECK"));
+        assert!(!filter.check_name("x1"));
+        assert!(!filter.check_name("7ester"));
+        assert!(!filter.check_name(""));
+    }
+
+    #[test]
+    fn test_contains_banned_word() {
+        let filter = make_filter();
+        assert!(!filter.contains_banned_word("hello there"));
+        assert!(filter.contains_banned_word("oh darn"));
+        assert!(filter.contains_banned_word("D4RN"));
+        assert!(filter.contains_banned_word("what the h3ck"));
+        assert!(filter.contains_banned_word("d a r n"));
+    }
+
+    #[test]
+    fn test_censor() {
+        let filter = make_filter();
+        assert_eq!(filter.censor("hello there"), "hello there");
+        assert_eq!(filter.censor("oh d@rn it"), "oh **** it");
+        assert_eq!(filter.censor("HECK!  yes"), "*****  yes");
+    }
+
+    #[test]
+    fn test_bad_pattern() {
+        assert!(WordFilter::parse(r#"name_patterns = ["("]"#).is_err());
+        assert!(WordFilter::parse("banned_words = 3").is_err());
+        assert!(WordFilter::parse("").is_ok());
+    }
+}